@@ -0,0 +1,158 @@
+//! systemd socket activation: the sockets systemd opened for the unit, given
+//! from descriptor 3 on with `LISTEN_FDS`, are used in place of binding the
+//! DHCP, TFTP, DNS and HTTP ports, so the server can run without the rights
+//! to bind them. A socket is used for the address it's bound to, and for the
+//! interface of its `BindToDevice=`, one without being bound to the first
+//! interface asking for it. The sockets handed over by a running server to
+//! the one taking over are used the same way.
+use std::{
+    env,
+    net::{SocketAddr, SocketAddrV4, TcpListener, UdpSocket},
+    os::fd::{FromRawFd, RawFd},
+    process,
+    sync::Mutex,
+};
+
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use socket2::{Socket, Type};
+
+use crate::Result;
+
+/// The first descriptor passed, `SD_LISTEN_FDS_START`.
+const LISTEN_FDS_START: RawFd = 3;
+
+static SOCKETS: Lazy<Mutex<Vec<ActivatedSocket>>> = Lazy::new(Default::default);
+static LISTENERS: Lazy<Mutex<Vec<(SocketAddr, Socket)>>> = Lazy::new(Default::default);
+
+struct ActivatedSocket {
+    socket: Socket,
+    addr: SocketAddrV4,
+    /// The interface of `SO_BINDTODEVICE`.
+    device: Option<String>,
+}
+
+/// The descriptors passed to the process of PID `pid` by the values of
+/// `LISTEN_PID` and `LISTEN_FDS`, none when they're meant for another.
+pub fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Vec<RawFd> {
+    let is_ours = listen_pid
+        .and_then(|listen_pid| listen_pid.parse::<u32>().ok())
+        .is_some_and(|listen_pid| listen_pid == pid);
+    let count = listen_fds.and_then(|count| count.parse::<RawFd>().ok()).unwrap_or(0);
+    match is_ours {
+        true => (LISTEN_FDS_START..LISTEN_FDS_START + count.max(0)).collect(),
+        false => Vec::new(),
+    }
+}
+
+/// Takes the sockets systemd passed, the variables telling them being
+/// removed so the processes started don't take them as theirs. To be
+/// called once, before any thread is started.
+pub fn init() -> Result<()> {
+    let fds = listen_fds(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+        process::id(),
+    );
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+    // SAFETY: systemd passes these descriptors open, for the process to own
+    unsafe { add(&fds, "systemd") }
+}
+
+/// Adds the UDP sockets and TCP listeners of the descriptors `fds` to those
+/// used, taking ownership of them.
+///
+/// # Safety
+///
+/// The descriptors must be open and not owned by anything else.
+pub unsafe fn add_fds(fds: &[RawFd]) -> Result<()> {
+    add(fds, "systemd")
+}
+
+/// Adds the sockets of `fds`, as [`add_fds`] does, handed over by the server
+/// taken over.
+///
+/// # Safety
+///
+/// The descriptors must be open and not owned by anything else.
+pub unsafe fn add_handed_over(fds: &[RawFd]) -> Result<()> {
+    add(fds, "the server taken over")
+}
+
+unsafe fn add(fds: &[RawFd], from: &str) -> Result<()> {
+    let mut sockets = SOCKETS.lock().map_err(|_| anyhow!("Activated sockets poisoned"))?;
+    let mut listeners = LISTENERS.lock().map_err(|_| anyhow!("Activated sockets poisoned"))?;
+    for fd in fds {
+        let socket = Socket::from_raw_fd(*fd);
+        // The hooks started don't inherit them
+        socket.set_cloexec(true)?;
+        let addr = socket.local_addr()?.as_socket();
+        match (socket.r#type()?, addr) {
+            (Type::DGRAM, Some(SocketAddr::V4(addr))) => {
+                let device = socket
+                    .device()?
+                    .map(|device| String::from_utf8_lossy(&device).to_string());
+                info!(
+                    "Using the socket of {addr}{} passed by {from}.",
+                    device.as_ref().map(|device| format!(" on {device}")).unwrap_or_default()
+                );
+                sockets.push(ActivatedSocket { socket, addr, device });
+            }
+            (Type::STREAM, Some(addr)) => {
+                info!("Using the listener of {addr} passed by {from}.");
+                listeners.push((addr, socket));
+            }
+            _ => warn!(
+                "Ignoring the socket passed by {from} as descriptor {fd}, it's neither a UDP IPv4 one nor a TCP listener."
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a socket bound to `addr` was passed, on any interface.
+pub fn has(addr: SocketAddrV4) -> bool {
+    SOCKETS
+        .lock()
+        .map(|sockets| sockets.iter().any(|socket| socket.addr == addr))
+        .unwrap_or(false)
+}
+
+/// A socket bound to `addr` passed by systemd, for the interface `device`,
+/// or `None` for it to be bound as usual. It's a copy of the one passed, so
+/// it can be used again after a service restarts.
+pub fn socket(addr: SocketAddrV4, device: Option<&str>) -> Result<Option<UdpSocket>> {
+    let mut sockets = SOCKETS.lock().map_err(|_| anyhow!("Activated sockets poisoned"))?;
+    let same_device = sockets
+        .iter()
+        .position(|socket| socket.addr == addr && socket.device.as_deref() == device);
+    let index = match same_device {
+        Some(index) => index,
+        None => match sockets.iter().position(|socket| socket.addr == addr && socket.device.is_none()) {
+            // Used for this interface from now on
+            Some(index) if device.is_some() => {
+                let activated = &mut sockets[index];
+                activated.socket.bind_device(device.map(str::as_bytes))?;
+                activated.device = device.map(str::to_string);
+                index
+            }
+            _ => return Ok(None),
+        },
+    };
+
+    Ok(Some(sockets[index].socket.try_clone()?.into()))
+}
+
+/// A copy of the TCP listener of `addr` passed, or `None` for it to be bound
+/// as usual.
+pub fn tcp_listener(addr: SocketAddr) -> Result<Option<TcpListener>> {
+    let listeners = LISTENERS.lock().map_err(|_| anyhow!("Activated sockets poisoned"))?;
+    listeners
+        .iter()
+        .find(|(listening, _)| *listening == addr)
+        .map(|(_, listener)| Ok(listener.try_clone()?.into()))
+        .transpose()
+}
@@ -0,0 +1,355 @@
+//! Transfer analytics: the TFTP and HTTP transfers ended since the server
+//! started, added up by file, by subnet of the clients and by throughput,
+//! with the transfers that failed and the TFTP blocks sent again, to find
+//! out which images or network segments slow a mass provisioning down.
+//! Reported by the `transfers` command and the control API, and handed over
+//! to the server taking over, but not saved.
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{control, Result};
+
+/// Files and subnets added up, those past it counted as `OTHERS`.
+pub const MAX_KEYS: usize = 10_000;
+const OTHERS: &str = "others";
+/// Upper bounds of the throughput buckets, in bytes per second, the last
+/// bucket being unbounded.
+pub const THROUGHPUT_BUCKETS: [u64; 4] = [100_000, 1_000_000, 10_000_000, 100_000_000];
+
+/// The protocol a file was transferred over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tftp,
+    Http,
+}
+
+/// A transfer that ended, read whole or not.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransferRecord {
+    pub client: IpAddr,
+    pub path: String,
+    pub protocol: Protocol,
+    pub bytes: u64,
+    pub duration: Duration,
+    /// Whether it was read, or written, to its end.
+    pub completed: bool,
+    /// TFTP blocks sent again, as the client didn't acknowledge them in time.
+    pub retransmissions: u64,
+}
+
+/// Transfers added up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransferTotals {
+    pub transfers: u64,
+    /// Transfers not read, or written, to their end.
+    pub failed: u64,
+    pub bytes: u64,
+    /// Time the transfers took, in milliseconds.
+    pub millis: u64,
+    pub retransmissions: u64,
+}
+
+impl TransferTotals {
+    fn add(&mut self, record: &TransferRecord) {
+        self.transfers += 1;
+        self.failed += u64::from(!record.completed);
+        self.bytes += record.bytes;
+        self.millis += record.duration.as_millis() as u64;
+        self.retransmissions += record.retransmissions;
+    }
+
+    /// Bytes per second, over the time all the transfers took.
+    pub fn throughput(&self) -> Option<u64> {
+        (self.millis > 0).then(|| self.bytes * 1000 / self.millis)
+    }
+}
+
+/// The analytics, as answered on the control socket.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AnalyticsState {
+    /// When the analytics were started, in seconds since the Unix epoch.
+    pub since: u64,
+    pub totals: TransferTotals,
+    pub tftp: TransferTotals,
+    pub http: TransferTotals,
+    /// By path, as requested.
+    pub files: BTreeMap<String, TransferTotals>,
+    /// By subnet of the clients, `/24` for IPv4 and `/64` for IPv6.
+    pub subnets: BTreeMap<String, TransferTotals>,
+    /// Transfers read whole, by throughput, in the buckets of
+    /// `THROUGHPUT_BUCKETS`.
+    pub throughput: Vec<u64>,
+}
+
+/// The analytics of the running server.
+pub struct TransferAnalytics {
+    state: Mutex<AnalyticsState>,
+}
+
+impl Default for TransferAnalytics {
+    fn default() -> Self {
+        Self::new(AnalyticsState {
+            since: unix_secs(SystemTime::now()),
+            ..Default::default()
+        })
+    }
+}
+
+impl TransferAnalytics {
+    pub fn new(state: AnalyticsState) -> Self {
+        Self {
+            state: Mutex::new(state),
+        }
+    }
+
+    pub fn snapshot(&self) -> AnalyticsState {
+        self.lock().clone()
+    }
+
+    /// Carries on the analytics of the server taken over.
+    pub fn inherit(&self, state: AnalyticsState) {
+        *self.lock() = state;
+    }
+
+    /// Adds up the transfer that ended.
+    pub fn record(&self, record: &TransferRecord) {
+        let mut state = self.lock();
+        let state = &mut *state;
+        state.totals.add(record);
+        match record.protocol {
+            Protocol::Tftp => state.tftp.add(record),
+            Protocol::Http => state.http.add(record),
+        }
+        add_to(&mut state.files, &record.path, record);
+        add_to(&mut state.subnets, &subnet(record.client), record);
+
+        let millis = record.duration.as_millis() as u64;
+        if record.completed && millis > 0 {
+            let throughput = record.bytes * 1000 / millis;
+            let bucket = THROUGHPUT_BUCKETS
+                .iter()
+                .position(|bound| throughput < *bound)
+                .unwrap_or(THROUGHPUT_BUCKETS.len());
+            state.throughput.resize(THROUGHPUT_BUCKETS.len() + 1, 0);
+            state.throughput[bucket] += 1;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, AnalyticsState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn add_to(totals: &mut BTreeMap<String, TransferTotals>, key: &str, record: &TransferRecord) {
+    let key = match totals.contains_key(key) || totals.len() < MAX_KEYS {
+        true => key,
+        false => OTHERS,
+    };
+    totals.entry(key.to_string()).or_default().add(record);
+}
+
+/// The subnet of `client`, `/24` for IPv4 and `/64` for IPv6.
+pub fn subnet(client: IpAddr) -> String {
+    match client {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{a}.{b}.{c}.0/24")
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            let prefix = std::net::Ipv6Addr::new(
+                segments[0],
+                segments[1],
+                segments[2],
+                segments[3],
+                0,
+                0,
+                0,
+                0,
+            );
+            format!("{prefix}/64")
+        }
+    }
+}
+
+/// Asks the server listening on the control socket at `path` for its
+/// transfer analytics.
+pub fn ask(path: &Path) -> Result<AnalyticsState> {
+    Ok(serde_json::from_value(control::request(
+        path,
+        "transfers",
+    )?)?)
+}
+
+/// The analytics of `state` as a report: the totals, the `top` files most
+/// transferred, the throughput of the transfers and the subnets, those
+/// failing the most first.
+pub fn report(state: &AnalyticsState, top: usize) -> String {
+    let totals = &state.totals;
+    let mut lines = vec![format!(
+        "Transfers since {}: {}, failed: {}, retransmissions: {}",
+        format_time(state.since),
+        totals.transfers,
+        with_rate(totals.failed, totals.transfers),
+        totals.retransmissions
+    )];
+    if totals.transfers == 0 {
+        return lines.join("\n") + "\n";
+    }
+    for (name, totals) in [("TFTP", &state.tftp), ("HTTP", &state.http)] {
+        lines.push(format!(
+            "  {name}: {}, failed: {}, {} at {}",
+            totals.transfers,
+            totals.failed,
+            format_bytes(totals.bytes),
+            format_throughput(totals.throughput())
+        ));
+    }
+
+    let mut files: Vec<(&String, &TransferTotals)> = state.files.iter().collect();
+    files.sort_by_key(|(path, totals)| (std::cmp::Reverse(totals.transfers), *path));
+    lines.push(format!("Top files, of {}:", files.len()));
+    lines.extend(table(
+        "FILE",
+        files
+            .into_iter()
+            .take(top)
+            .map(|(path, totals)| (path.clone(), totals)),
+    ));
+
+    lines.push("Throughput of the transfers read whole:".to_string());
+    let mut throughput = state.throughput.clone();
+    throughput.resize(THROUGHPUT_BUCKETS.len() + 1, 0);
+    let most = throughput.iter().max().copied().unwrap_or_default();
+    let mut lower = 0;
+    for (bucket, count) in throughput.into_iter().enumerate() {
+        let range = match THROUGHPUT_BUCKETS.get(bucket) {
+            Some(upper) => format!("{} - {}", format_rate(lower), format_rate(*upper)),
+            None => format!("{} and more", format_rate(lower)),
+        };
+        lower = THROUGHPUT_BUCKETS.get(bucket).copied().unwrap_or_default();
+        let bar = "#".repeat((count * 40).div_ceil(most.max(1)) as usize);
+        lines.push(
+            format!("  {range:<22}  {count:>6}  {bar}")
+                .trim_end()
+                .to_string(),
+        );
+    }
+
+    let mut subnets: Vec<(&String, &TransferTotals)> = state.subnets.iter().collect();
+    subnets.sort_by(|(a, a_totals), (b, b_totals)| {
+        let rate = |totals: &TransferTotals| totals.failed as f64 / totals.transfers.max(1) as f64;
+        rate(b_totals)
+            .total_cmp(&rate(a_totals))
+            .then(b_totals.transfers.cmp(&a_totals.transfers))
+            .then(a.cmp(b))
+    });
+    lines.push("Subnets, failing the most first:".to_string());
+    lines.extend(table(
+        "SUBNET",
+        subnets
+            .into_iter()
+            .map(|(subnet, totals)| (subnet.clone(), totals)),
+    ));
+
+    lines.join("\n") + "\n"
+}
+
+/// The rows of `totals`, named in the first column headed `first`.
+fn table<'a>(
+    first: &str,
+    totals: impl Iterator<Item = (String, &'a TransferTotals)>,
+) -> Vec<String> {
+    let header = [
+        first,
+        "TRANSFERS",
+        "FAILED",
+        "BYTES",
+        "THROUGHPUT",
+        "RETRANSMISSIONS",
+    ];
+    let mut rows = vec![header.map(str::to_string)];
+    rows.extend(totals.map(|(name, totals)| {
+        [
+            name,
+            totals.transfers.to_string(),
+            with_rate(totals.failed, totals.transfers),
+            format_bytes(totals.bytes),
+            format_throughput(totals.throughput()),
+            totals.retransmissions.to_string(),
+        ]
+    }));
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].len())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    rows.iter()
+        .map(|row| {
+            let cells: Vec<String> = (0..header.len())
+                .map(|column| format!("{:width$}", row[column], width = widths[column]))
+                .collect();
+            format!("  {}", cells.join("  ").trim_end())
+        })
+        .collect()
+}
+
+/// `count` with its share of `total`, e.g. `3 (1.5%)`.
+fn with_rate(count: u64, total: u64) -> String {
+    match total {
+        0 => count.to_string(),
+        _ => format!("{count} ({:.1}%)", count as f64 * 100.0 / total as f64),
+    }
+}
+
+/// `bytes` in the largest unit of 1000 it has one of, e.g. `1.5 MB`.
+fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "kB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < units.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{value:.1} {}", units[unit]),
+    }
+}
+
+fn format_rate(bytes_per_sec: u64) -> String {
+    format_bytes(bytes_per_sec).replace(".0 ", " ") + "/s"
+}
+
+fn format_throughput(throughput: Option<u64>) -> String {
+    throughput.map_or("-".to_string(), |throughput| {
+        format_bytes(throughput) + "/s"
+    })
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn format_time(secs: u64) -> String {
+    OffsetDateTime::from_unix_timestamp(secs as i64)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or(secs.to_string())
+}
@@ -0,0 +1,207 @@
+//! Control API on `api_addr`, for provisioning orchestrators to drive the
+//! running server over HTTP: list and expire the DHCP sessions, look up the
+//! clients handed a boot file and the timeline of one, reload the
+//! configuration, toggle the dry run of DHCP and fetch the counters. The requests are authorized with
+//! `api_token` as a bearer token, the answers being JSON documents, but for
+//! the events of the clients, streamed as server-sent events.
+use std::sync::Arc;
+
+use async_std::{
+    io,
+    task,
+};
+use futures::{StreamExt, TryStreamExt};
+use log::{error, info};
+
+use crate::{
+    conf::Conf,
+    control::Control,
+    dashboard,
+    dhcp,
+    events,
+    http::{self, Body, Request, Response},
+    template::parse_mac,
+    timeline::{self, Entry},
+    Result,
+};
+
+/// Prefix of the paths of the API, bumped on incompatible changes.
+pub const PREFIX: &str = "/api/v1";
+/// Largest request body read, the API taking small JSON documents only.
+const BODY_LIMIT: u64 = 4096;
+
+/// Serves the API on `api_addr` once `api_token` is set as well, each
+/// connection on its own task. A bind failure is logged, not fatal.
+pub fn spawn_api_async(conf: &Conf, control: Arc<Control>) -> Result<()> {
+    let (Some(addr), Some(token)) = (conf.get_api_addr(), conf.get_api_token()) else {
+        return Ok(());
+    };
+    let listener = match http::bind_listener(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Control API on {addr} failed: {e:#}");
+            return Ok(());
+        }
+    };
+    info!("Control API listening on http://{addr}{PREFIX}/");
+
+    let token = Arc::new(token.clone());
+    http::serve(listener, "control API", BODY_LIMIT, move |request, body| {
+        let (control, token) = (Arc::clone(&control), Arc::clone(&token));
+        async move {
+            match is_authorized(&request, &token) {
+                true => respond(&request, &body, &control).await,
+                false => json(401, serde_json::json!({ "error": "Unauthorized" }))
+                    .header("WWW-Authenticate", "Bearer"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn is_authorized(request: &Request, token: &str) -> bool {
+    request
+        .header("Authorization")
+        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+        .is_some_and(|given| http::secret_eq(given.trim(), token))
+}
+
+async fn respond(request: &Request, body: &[u8], control: &Control) -> Response {
+    let Some(path) = request.path.strip_prefix(PREFIX) else {
+        return not_found();
+    };
+    let method = match request.method.as_str() {
+        "HEAD" => "GET",
+        method => method,
+    };
+
+    match (method, path) {
+        ("GET", "/sessions") => json(200, serde_json::json!(control.sessions().await)),
+        ("DELETE", path) if path.starts_with("/sessions/") => {
+            let Some(xid) = parse_xid(&path["/sessions/".len()..]) else {
+                return json(400, serde_json::json!({ "error": "Invalid XID" }));
+            };
+            match control.expire_session(xid).await {
+                true => {
+                    info!("Session for XID: {xid} expired by the control API.");
+                    Response::new(204)
+                }
+                false => not_found(),
+            }
+        }
+        ("GET", "/clients") => {
+            let mac = request.query_param("mac");
+            let clients: Vec<_> = dashboard::recent_boots(&control.tracker)
+                .into_iter()
+                .filter(|client| mac.as_ref().is_none_or(|mac| client.mac_address.eq_ignore_ascii_case(mac)))
+                .collect();
+            json(200, serde_json::json!(clients))
+        }
+        ("GET", "/assets") => {
+            let mac = request.query_param("mac");
+            let assets: Vec<_> = control
+                .tracker
+                .assets()
+                .list()
+                .into_iter()
+                .filter(|asset| mac.as_ref().is_none_or(|mac| asset.mac_address.eq_ignore_ascii_case(mac)))
+                .collect();
+            json(200, serde_json::json!(assets))
+        }
+        ("GET", "/transfers") => json(200, serde_json::json!(control.tracker.analytics().snapshot())),
+        ("GET", "/events") => event_stream(request.query_param("mac")),
+        ("GET", "/timeline") => {
+            let Some(mac) = request.query_param("mac").filter(|mac| parse_mac(mac).is_some()) else {
+                return json(400, serde_json::json!({ "error": "Expected ?mac=<MAC address>" }));
+            };
+            match task::spawn_blocking(move || timeline::recorded(&mac)).await {
+                Some(Ok(entries)) => {
+                    let entries: Vec<_> = entries.iter().map(Entry::to_json).collect();
+                    json(200, serde_json::json!(entries))
+                }
+                Some(Err(e)) => json(500, serde_json::json!({ "error": format!("{e:#}") })),
+                None => json(404, serde_json::json!({ "error": "No event_log, the events of the clients aren't recorded" })),
+            }
+        }
+        ("POST", "/reload") => match control.reloader.reload().await {
+            Ok(()) => json(200, serde_json::json!({ "reloaded": true })),
+            Err(e) => json(422, serde_json::json!({ "error": format!("{e:#}") })),
+        },
+        ("GET", "/dry_run") => json(200, serde_json::json!({ "dry_run": dhcp::is_dry_run() })),
+        ("PUT", "/dry_run") => {
+            let dry_run = serde_json::from_slice::<serde_json::Value>(body)
+                .ok()
+                .and_then(|doc| doc["dry_run"].as_bool());
+            let Some(dry_run) = dry_run else {
+                return json(400, serde_json::json!({ "error": "Expected {\"dry_run\": <bool>}" }));
+            };
+            if dry_run != dhcp::is_dry_run() {
+                match dry_run {
+                    true => info!("DHCP dry run started by the control API, the answers are only logged."),
+                    false => info!("DHCP dry run stopped by the control API."),
+                }
+            }
+            dhcp::set_dry_run(dry_run);
+            json(200, serde_json::json!({ "dry_run": dry_run }))
+        }
+        ("GET", "/stats") => {
+            let state = dashboard::state(control).await;
+            json(
+                200,
+                serde_json::json!({
+                    "version": state.version,
+                    "uptime_secs": state.uptime_secs,
+                    "counts": state.counts,
+                    "ifaces": state.ifaces,
+                    "sessions": state.sessions.len(),
+                    "active_transfers": state.transfers.len(),
+                    "rules": state.rules,
+                    "dry_run": dhcp::is_dry_run(),
+                }),
+            )
+        }
+        (_, "/sessions" | "/clients" | "/assets" | "/transfers" | "/events" | "/timeline" | "/reload" | "/dry_run" | "/stats") => {
+            json(405, serde_json::json!({ "error": "Method not allowed" }))
+        }
+        (_, path) if path.starts_with("/sessions/") => {
+            json(405, serde_json::json!({ "error": "Method not allowed" }))
+        }
+        _ => not_found(),
+    }
+}
+
+/// The events of the clients of `mac_address`, or all of them, as
+/// server-sent events named after the events, with comments as keep-alives,
+/// until the client is gone.
+fn event_stream(mac_address: Option<String>) -> Response {
+    let events = events::watch_json(mac_address).map(|event| {
+        let message = match event {
+            Some(event) => format!("event: {}\ndata: {event}\n\n", event["event"].as_str().unwrap_or_default()),
+            None => ": keep-alive\n\n".to_string(),
+        };
+        io::Result::Ok(message.into_bytes())
+    });
+    Response::new(200)
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .body(Body::Reader(Box::new(events.into_async_read()), None))
+}
+
+/// Parses an XID, in hexadecimal with `0x` or in decimal.
+fn parse_xid(xid: &str) -> Option<u32> {
+    match xid.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => xid.parse().ok(),
+    }
+}
+
+fn json(status: u16, doc: serde_json::Value) -> Response {
+    Response::new(status)
+        .header("Content-Type", "application/json")
+        .body(Body::Bytes(doc.to_string().into_bytes()))
+}
+
+fn not_found() -> Response {
+    json(404, serde_json::json!({ "error": "Not found" }))
+}
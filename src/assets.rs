@@ -0,0 +1,314 @@
+//! Asset inventory: every client that asked to boot, by MAC address, with
+//! the UUID, architecture and vendor class its DHCP messages told, when it
+//! was first and last seen and the boot file it was last given, for the
+//! sites to find out the machines on their networks. Unlike the boot
+//! tracker, the clients are never forgotten, and with `assets_file` they
+//! survive restarts, saved every minute. Listed by the `assets` command and
+//! the control APIs.
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use async_std::task;
+use dhcproto::v4::{DhcpOption, Message, OptionCode};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    conf::arch_name,
+    control,
+    tracker::BootTracker,
+    util::{bytes_to_mac_address, part_path},
+    Result,
+};
+
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A client, its times in seconds since the Unix epoch.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Asset {
+    pub mac_address: String,
+    /// The client machine identifier of option 97.
+    pub uuid: Option<String>,
+    /// The architecture of option 93, by the name of `match` rules.
+    pub arch: Option<String>,
+    /// The vendor class identifier of option 60.
+    pub vendor: Option<String>,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub last_boot_file: Option<String>,
+}
+
+impl Asset {
+    /// The client of `msg` seen at `now`, with what its options tell.
+    pub fn of(msg: &Message, now: u64) -> Self {
+        let opts = msg.opts();
+        let uuid = match opts.get(OptionCode::ClientMachineIdentifier) {
+            Some(DhcpOption::ClientMachineIdentifier(id)) => format_uuid(id),
+            _ => None,
+        };
+        let arch = match opts.get(OptionCode::ClientSystemArchitecture) {
+            Some(DhcpOption::ClientSystemArchitecture(arch)) => Some(arch_name((*arch).into())),
+            _ => None,
+        };
+        let vendor = match opts.get(OptionCode::ClassIdentifier) {
+            Some(DhcpOption::ClassIdentifier(class)) => Some(
+                String::from_utf8_lossy(class)
+                    .trim_end_matches('\0')
+                    .to_string(),
+            ),
+            _ => None,
+        };
+
+        Self {
+            mac_address: bytes_to_mac_address(msg.chaddr()),
+            uuid,
+            arch,
+            vendor,
+            first_seen: now,
+            last_seen: now,
+            last_boot_file: None,
+        }
+    }
+}
+
+/// The UUID of option 97, its type, 0, followed by 16 bytes, as sent.
+fn format_uuid(id: &[u8]) -> Option<String> {
+    let bytes = match id {
+        [0, bytes @ ..] if bytes.len() == 16 => bytes,
+        _ => return None,
+    };
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    Some(
+        [&hex[..4], &hex[4..6], &hex[6..8], &hex[8..10], &hex[10..]]
+            .map(|group| group.concat())
+            .join("-"),
+    )
+}
+
+/// The clients ever seen, by MAC address.
+#[derive(Default)]
+pub struct AssetDb {
+    assets: Mutex<BTreeMap<String, Asset>>,
+    /// Changed since saved.
+    changed: AtomicBool,
+    /// Handed over to the server taking over, no longer saved.
+    handed_over: AtomicBool,
+}
+
+impl AssetDb {
+    pub fn new(assets: Vec<Asset>) -> Self {
+        let assets = assets
+            .into_iter()
+            .map(|asset| (asset.mac_address.clone(), asset))
+            .collect();
+        Self {
+            assets: Mutex::new(assets),
+            ..Default::default()
+        }
+    }
+
+    /// The clients saved at `path`, none when there's no file yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        let assets = match fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .context(format!("Reading the assets {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).context(format!("Reading the assets {}", path.display())),
+        };
+        Ok(Self::new(assets))
+    }
+
+    /// Saves the clients to `path`, replacing it at once.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        self.changed.store(false, Ordering::Relaxed);
+        let content = serde_json::to_vec_pretty(&self.list())?;
+        let part = part_path(path);
+        fs::write(&part, content)?;
+        fs::rename(&part, path)?;
+        Ok(())
+    }
+
+    /// The clients, by MAC address.
+    pub fn list(&self) -> Vec<Asset> {
+        self.lock().values().cloned().collect()
+    }
+
+    /// The clients for the server taking over, which saves them from then on.
+    pub fn hand_over(&self) -> Vec<Asset> {
+        self.handed_over.store(true, Ordering::Relaxed);
+        self.list()
+    }
+
+    /// Carries on the clients of the server taken over.
+    pub fn inherit(&self, assets: Vec<Asset>) {
+        let mut known = self.lock();
+        for asset in assets {
+            known.insert(asset.mac_address.clone(), asset);
+        }
+    }
+
+    /// Records the client of `msg`, seen at `now`, what its options tell
+    /// replacing what was known.
+    pub fn seen(&self, msg: &Message, now: SystemTime) {
+        let seen = Asset::of(msg, unix_secs(now));
+        let mut assets = self.lock();
+        match assets.get_mut(&seen.mac_address) {
+            Some(asset) => {
+                asset.last_seen = seen.last_seen;
+                asset.uuid = seen.uuid.or(asset.uuid.take());
+                asset.arch = seen.arch.or(asset.arch.take());
+                asset.vendor = seen.vendor.or(asset.vendor.take());
+            }
+            None => {
+                assets.insert(seen.mac_address.clone(), seen);
+            }
+        }
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Records that `mac_address` was given `boot_file` at `now`.
+    pub fn boot_file_sent(&self, mac_address: &str, boot_file: &str, now: SystemTime) {
+        let now = unix_secs(now);
+        let mut assets = self.lock();
+        let asset = assets
+            .entry(mac_address.to_string())
+            .or_insert_with(|| Asset {
+                mac_address: mac_address.to_string(),
+                first_seen: now,
+                ..Default::default()
+            });
+        asset.last_seen = now;
+        asset.last_boot_file = Some(boot_file.to_string());
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Asset>> {
+        self.assets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Saves the clients of `tracker` to `path` every `SAVE_INTERVAL` they
+/// changed, until they're handed over.
+pub fn spawn_saving(tracker: Arc<BootTracker>, path: PathBuf) {
+    task::spawn(async move {
+        loop {
+            task::sleep(SAVE_INTERVAL).await;
+            let assets = tracker.assets();
+            if assets.handed_over.load(Ordering::Relaxed) {
+                break;
+            }
+            if !assets.changed.load(Ordering::Relaxed) {
+                continue;
+            }
+            match assets.save(&path) {
+                Ok(()) => debug!("Saved the assets to {}.", path.display()),
+                Err(e) => warn!("Saving the assets to {} failed: {e:#}", path.display()),
+            }
+        }
+    });
+}
+
+/// Asks the server listening on the control socket at `path` for the
+/// clients it has seen.
+pub fn ask(path: &Path) -> Result<Vec<Asset>> {
+    Ok(serde_json::from_value(control::request(path, "assets")?)?)
+}
+
+/// The clients of `assets` as a table, or as CSV with `csv`.
+pub fn report(assets: &[Asset], csv: bool) -> String {
+    let header = [
+        "MAC",
+        "UUID",
+        "ARCH",
+        "VENDOR",
+        "FIRST SEEN",
+        "LAST SEEN",
+        "LAST BOOT FILE",
+    ];
+    let rows: Vec<[String; 7]> = assets
+        .iter()
+        .map(|asset| {
+            let or_none = |value: &Option<String>| value.clone().unwrap_or_default();
+            [
+                asset.mac_address.clone(),
+                or_none(&asset.uuid),
+                or_none(&asset.arch),
+                or_none(&asset.vendor),
+                format_time(asset.first_seen),
+                format_time(asset.last_seen),
+                or_none(&asset.last_boot_file),
+            ]
+        })
+        .collect();
+
+    if csv {
+        let header = header.map(|name| name.to_ascii_lowercase().replace(' ', "_"));
+        let lines = std::iter::once(header.join(",")).chain(rows.iter().map(|row| {
+            row.iter()
+                .map(|cell| csv_field(cell))
+                .collect::<Vec<_>>()
+                .join(",")
+        }));
+        return lines.map(|line| line + "\n").collect();
+    }
+    if rows.is_empty() {
+        return "No clients seen yet.\n".to_string();
+    }
+    let rows: Vec<[String; 7]> = std::iter::once(header.map(str::to_string))
+        .chain(rows.into_iter().map(|row| {
+            row.map(|cell| match cell.is_empty() {
+                true => "-".to_string(),
+                false => cell,
+            })
+        }))
+        .collect();
+    let widths: Vec<usize> = (0..7)
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].len())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    rows.iter()
+        .map(|row| {
+            let cells: Vec<String> = (0..7)
+                .map(|column| format!("{:width$}", row[column], width = widths[column]))
+                .collect();
+            cells.join("  ").trim_end().to_string() + "\n"
+        })
+        .collect()
+}
+
+/// `field` quoted when it has commas, quotes or line breaks, RFC 4180.
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn format_time(secs: u64) -> String {
+    OffsetDateTime::from_unix_timestamp(secs as i64)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or(secs.to_string())
+}
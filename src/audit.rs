@@ -0,0 +1,108 @@
+//! Audit trail of the boot decisions, `audit_log`: a line of JSON for each
+//! reply telling a client what to boot, with who the client is, what
+//! decided its configuration and the boot file and server it was given,
+//! for the security reviews to tell who was told to boot what. Unlike the
+//! messages of the server, it's kept apart from the operational logs and
+//! their levels, and only appended to, the file being rotated once it
+//! reaches `audit_log_max_size`, `audit_log_files` of them being kept.
+use std::{
+    io,
+    net::Ipv4Addr,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::Context;
+use log::{info, warn};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{conf::Conf, logging, template::parse_mac, util::LineLog, Result};
+
+static AUDIT_LOG: OnceLock<Mutex<AuditLog>> = OnceLock::new();
+
+/// A boot decision, as told to a client.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Decision {
+    pub mac_address: String,
+    pub xid: u32,
+    pub client_ip: Option<Ipv4Addr>,
+    /// Relay agent the client is reached through.
+    pub relay_ip: Option<Ipv4Addr>,
+    pub iface: String,
+    /// Reply it was told in, `offer`, `ack` or `boot-server`.
+    pub reply: String,
+    /// What decided the configuration, as `sessions` tells it.
+    pub rule: String,
+    pub boot_file: Option<String>,
+    /// Server the boot file is downloaded from.
+    pub boot_server: Option<Ipv4Addr>,
+}
+
+impl Decision {
+    /// The line of the decision, made at `time`, those unknown being `null`.
+    pub fn to_json(&self, time: OffsetDateTime) -> serde_json::Value {
+        serde_json::json!({
+            "time": time.format(&Rfc3339).unwrap_or_default(),
+            "mac_address": self.mac_address,
+            "xid": format!("{:#010x}", self.xid),
+            "session": parse_mac(&self.mac_address).map(|mac| logging::session_id(&mac, self.xid)),
+            "client_ip": self.client_ip,
+            "relay_ip": self.relay_ip,
+            "iface": self.iface,
+            "reply": self.reply,
+            "rule": self.rule,
+            "boot_file": self.boot_file,
+            "boot_server": self.boot_server,
+        })
+    }
+}
+
+/// A file the decisions are appended to, rotated by size.
+pub struct AuditLog(LineLog);
+
+impl AuditLog {
+    /// Opens `path` as the audit log, one decision per line, rotated as [`LineLog::open`] does.
+    pub fn open(path: &Path, max_size: u64, files: u64) -> io::Result<Self> {
+        LineLog::open(path, max_size, files).map(Self)
+    }
+
+    /// Appends `decision`, made at `time`.
+    pub fn write(&mut self, decision: &Decision, time: OffsetDateTime) -> io::Result<()> {
+        self.0.write(&decision.to_json(time).to_string())
+    }
+}
+
+/// Starts appending to the `audit_log` of `conf`, when set. Only the first
+/// call applies, the audit log being read on start only.
+pub fn start(conf: &Conf) -> Result<()> {
+    let Some(path) = conf.get_audit_log() else {
+        return Ok(());
+    };
+    if AUDIT_LOG.get().is_some() {
+        return Ok(());
+    }
+    let audit_log = AuditLog::open(
+        path,
+        conf.get_audit_log_max_size(),
+        conf.get_audit_log_files(),
+    )
+    .context(format!("Opening the audit log {}", path.display()))?;
+    let _ = AUDIT_LOG.set(Mutex::new(audit_log));
+    info!("Recording the boot decisions to {}.", path.display());
+    Ok(())
+}
+
+/// Appends `decision` to the audit log, when recording.
+pub fn record(decision: &Decision) {
+    let Some(audit_log) = AUDIT_LOG.get() else {
+        return;
+    };
+    let mut audit_log = audit_log.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(e) = audit_log.write(decision, OffsetDateTime::now_utc()) {
+        warn!(
+            "Failed recording the boot decision for {} to {}: {e}",
+            decision.mac_address,
+            audit_log.0.path().display()
+        );
+    }
+}
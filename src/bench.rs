@@ -0,0 +1,297 @@
+//! `bench` subcommand: simulates PXE clients booting at once, each through
+//! the DHCP handshake then the TFTP download of its boot file, and reports
+//! the percentiles of their timings, to size the server for mass imaging.
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_std::{future::timeout, net::UdpSocket, task};
+use dhcproto::v4::{Decodable, Decoder, DhcpOption, Encodable, Encoder, Message, MessageType};
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    future, StreamExt,
+};
+use network_interface::{NetworkInterface, NetworkInterfaceConfig};
+
+use crate::{
+    conf::MacAddress,
+    dhcp,
+    probe::{self, ProbeAnswer},
+    tftp_get, upstream, Result,
+};
+
+/// What the simulated clients do.
+#[derive(Clone, Debug)]
+pub struct BenchSettings {
+    pub clients: usize,
+    /// Interface the DHCP handshakes are run on, none to only download.
+    pub iface: Option<String>,
+    /// File downloaded instead of the boot file given over DHCP.
+    pub file: Option<PathBuf>,
+    /// TFTP server downloaded from instead of the one given over DHCP.
+    pub server: Option<SocketAddr>,
+    /// Time each DHCP answer is waited for.
+    pub timeout: Duration,
+}
+
+/// Timings of a client booting.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientTimings {
+    /// From the DISCOVER to the ACK with the boot information.
+    pub dhcp: Option<Duration>,
+    pub tftp: Duration,
+    pub bytes: u64,
+}
+
+/// Where the DHCP answers are sent to, by transaction ID.
+type Dispatch = Arc<Mutex<HashMap<u32, UnboundedSender<Message>>>>;
+
+/// Runs the clients of `settings` at once, answering the timings of each.
+pub async fn run(settings: &BenchSettings) -> Result<Vec<Result<ClientTimings>>> {
+    let dhcp = match &settings.iface {
+        Some(iface) => Some(dhcp_socket(iface)?),
+        None if settings.file.is_none() => bail!("--file is needed without --iface"),
+        None => None,
+    };
+
+    let clients = (0..settings.clients).map(|_| {
+        let dhcp = dhcp.clone();
+        async move {
+            let (boot, dhcp_time) = match dhcp {
+                Some((socket, dispatch)) => {
+                    let (boot, time) = handshake(&socket, &dispatch, settings.timeout).await?;
+                    (Some(boot), Some(time))
+                }
+                None => (None, None),
+            };
+            let (tftp, bytes) = download(boot, settings).await?;
+
+            Ok(ClientTimings {
+                dhcp: dhcp_time,
+                tftp,
+                bytes,
+            })
+        }
+    });
+
+    Ok(future::join_all(clients).await)
+}
+
+/// The socket of port 68 on `iface_name` the DHCP answers of all the clients
+/// are received on, with where they're dispatched to.
+pub(crate) fn dhcp_socket(iface_name: &str) -> Result<(Arc<UdpSocket>, Dispatch)> {
+    let iface = NetworkInterface::show()
+        .context("Listing network interfaces")?
+        .into_iter()
+        .find(|iface| iface.name == iface_name)
+        .ok_or(anyhow!("No network interface {iface_name}"))?;
+    let socket = Arc::new(dhcp::socket_from_iface_ip(&iface, &"0.0.0.0:68")?);
+    let dispatch: Dispatch = Default::default();
+
+    let (receiving, senders) = (Arc::clone(&socket), Arc::clone(&dispatch));
+    task::spawn(async move {
+        let mut buf = [0u8; 1500];
+        while let Ok((len, _)) = receiving.recv_from(&mut buf).await {
+            let Ok(message) = Message::decode(&mut Decoder::new(&buf[..len])) else {
+                continue;
+            };
+            let sender = senders
+                .lock()
+                .ok()
+                .and_then(|senders| senders.get(&message.xid()).cloned());
+            if let Some(sender) = sender {
+                let _ = sender.unbounded_send(message);
+            }
+        }
+    });
+
+    Ok((socket, dispatch))
+}
+
+/// Runs the DHCP handshake of a new client, answering the ACK giving it its
+/// boot information and the time it took.
+pub(crate) async fn handshake(
+    socket: &UdpSocket,
+    dispatch: &Dispatch,
+    wait: Duration,
+) -> Result<(ProbeAnswer, Duration)> {
+    let mut mac: MacAddress = rand::random();
+    // Locally administered, not to be taken for a real client
+    mac[0] = (mac[0] & 0xfc) | 0x02;
+    let xid: u32 = rand::random();
+    let (sender, mut receiver) = mpsc::unbounded();
+    if let Ok(mut senders) = dispatch.lock() {
+        senders.insert(xid, sender);
+    }
+
+    let start = Instant::now();
+    let result = async {
+        send(socket, &probe::discover_message(&mac, xid)).await?;
+        let offer = wait_for(&mut receiver, MessageType::Offer, wait).await?;
+        send(socket, &request_message(&mac, xid, &offer)).await?;
+        let ack = wait_for(&mut receiver, MessageType::Ack, wait).await?;
+
+        Ok((ack, start.elapsed()))
+    }
+    .await;
+    if let Ok(mut senders) = dispatch.lock() {
+        senders.remove(&xid);
+    }
+
+    result
+}
+
+/// Waits for the answer of type `msg_type` giving a boot file, those without
+/// being the ones of the DHCP server of the network.
+async fn wait_for(
+    receiver: &mut UnboundedReceiver<Message>,
+    msg_type: MessageType,
+    wait: Duration,
+) -> Result<ProbeAnswer> {
+    let deadline = Instant::now() + wait;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let message = timeout(left, receiver.next())
+            .await
+            .ok()
+            .flatten()
+            .ok_or(anyhow!(
+                "No {msg_type:?} with a boot file within {}s",
+                wait.as_secs()
+            ))?;
+        if message.opts().msg_type() != Some(msg_type) {
+            continue;
+        }
+        let answer = ProbeAnswer::from_message(&message, Ipv4Addr::UNSPECIFIED);
+        if answer.boot_file.is_some() {
+            return Ok(answer);
+        }
+    }
+}
+
+/// The REQUEST of the address of `offer`, to the server offering it.
+pub fn request_message(mac: &MacAddress, xid: u32, offer: &ProbeAnswer) -> Message {
+    let mut message = probe::discover_message(mac, xid);
+    let opts = message.opts_mut();
+    opts.insert(DhcpOption::MessageType(MessageType::Request));
+    opts.insert(DhcpOption::ServerIdentifier(offer.server));
+    if let Some(ip) = offer.offered_ip {
+        opts.insert(DhcpOption::RequestedIpAddress(ip));
+    }
+
+    message
+}
+
+async fn send(socket: &UdpSocket, message: &Message) -> Result<()> {
+    let mut buf = Vec::new();
+    message.encode(&mut Encoder::new(&mut buf))?;
+    socket.send_to(&buf, "255.255.255.255:67").await?;
+
+    Ok(())
+}
+
+/// Downloads the file of `settings`, or the boot file of the ACK `boot`,
+/// answering the time it took and its size.
+async fn download(boot: Option<ProbeAnswer>, settings: &BenchSettings) -> Result<(Duration, u64)> {
+    let (boot_file, boot_server) =
+        boot.map_or((None, None), |ack| (ack.boot_file, ack.boot_server));
+    let file = match (&settings.file, boot_file) {
+        (Some(file), _) => file.clone(),
+        (None, Some(boot_file)) => PathBuf::from(boot_file),
+        (None, None) => bail!("No file to download"),
+    };
+    let server = match (settings.server, boot_server) {
+        (Some(server), _) => server,
+        (None, Some(server)) => {
+            let ip: IpAddr = server
+                .parse()
+                .context("Reading the TFTP server of the ACK")?;
+            SocketAddr::new(ip, upstream::DEFAULT_TFTP_PORT)
+        }
+        (None, None) => tftp_get::server_addr(None)?,
+    };
+
+    let download = tftp_get::download(server, &file, None).await?;
+    Ok((download.elapsed, download.size))
+}
+
+/// The value under which `percent` of the `sorted` values are.
+pub fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Percentiles of the timings of `results`, the clients having run for
+/// `elapsed`, and the errors of those failing.
+pub fn report(results: &[Result<ClientTimings>], elapsed: Duration) -> String {
+    let timings: Vec<&ClientTimings> = results.iter().filter_map(|r| r.as_ref().ok()).collect();
+    let mut lines = vec![format!(
+        "Clients: {}, succeeded: {}, failed: {}",
+        results.len(),
+        timings.len(),
+        results.len() - timings.len()
+    )];
+
+    let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    let dhcp: Vec<f64> = timings.iter().filter_map(|t| t.dhcp.map(millis)).collect();
+    let tftp: Vec<f64> = timings.iter().map(|t| millis(t.tftp)).collect();
+    let rates: Vec<f64> = timings
+        .iter()
+        .map(|t| t.bytes as f64 / 1024.0 / t.tftp.as_secs_f64().max(0.001))
+        .collect();
+    for (name, values, unit) in [
+        ("DHCP handshake", dhcp, "ms"),
+        ("TFTP download", tftp, "ms"),
+        ("Client throughput", rates, "KiB/s"),
+    ] {
+        if let Some(line) = percentiles_line(name, values, unit) {
+            lines.push(line);
+        }
+    }
+
+    let bytes: u64 = timings.iter().map(|t| t.bytes).sum();
+    let secs = elapsed.as_secs_f64().max(0.001);
+    lines.push(format!(
+        "Total: {bytes} bytes in {secs:.1} s, {:.1} KiB/s",
+        bytes as f64 / 1024.0 / secs
+    ));
+
+    let mut errors: BTreeMap<String, usize> = BTreeMap::new();
+    for error in results.iter().filter_map(|r| r.as_ref().err()) {
+        *errors.entry(format!("{error:#}")).or_default() += 1;
+    }
+    if !errors.is_empty() {
+        lines.push("Failures:".to_string());
+        lines.extend(
+            errors
+                .iter()
+                .map(|(error, count)| format!("  {count}x {error}")),
+        );
+    }
+
+    lines.join("\n") + "\n"
+}
+
+fn percentiles_line(name: &str, mut values: Vec<f64>, unit: &str) -> Option<String> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let at: Vec<String> = [50.0, 90.0, 99.0]
+        .map(|percent| format!("p{percent} {:.1} {unit}", percentile(&values, percent)))
+        .into();
+
+    Some(format!(
+        "{name}: {}, max {:.1} {unit}",
+        at.join(", "),
+        values[values.len() - 1]
+    ))
+}
@@ -0,0 +1,77 @@
+//! iPXE chainloading preset: the iPXE project's binaries are fetched into the
+//! image store and handed over TFTP to PXE clients without a `boot_file`.
+//! Once started, iPXE asks for a boot file again and is handed a script
+//! jumping to `/boot.ipxe` of the HTTP service, so the rest of the boot is
+//! transferred over HTTP.
+use std::{fs, path::Path};
+
+use once_cell::sync::Lazy;
+
+use crate::{conf::ImageSource, images::IMAGES_DIR, util::part_path, Result};
+
+/// Directory of the image store the binaries are kept in.
+pub const DIR: &str = "ipxe";
+/// Script of the TFTP root handed to iPXE, chaining the HTTP service.
+pub const SCRIPT: &str = "ipxe/http.ipxe";
+const RELEASE_URL: &str = "https://boot.ipxe.org";
+
+/// The BIOS, x64 UEFI and ARM64 UEFI binaries, by their path on the release
+/// server and in the image store.
+const BINARIES: [(&str, &str); 3] = [
+    ("undionly.kpxe", "undionly.kpxe"),
+    ("ipxe.efi", "ipxe.efi"),
+    ("arm64-efi/ipxe.efi", "ipxe-arm64.efi"),
+];
+static BOOT_FILES: Lazy<[String; 3]> =
+    Lazy::new(|| BINARIES.map(|(_, binary)| format!("{IMAGES_DIR}/{DIR}/{binary}")));
+static SCRIPT_BOOT_FILE: Lazy<String> = Lazy::new(|| SCRIPT.to_string());
+
+/// Boot file for a client of the given processor architecture, option 93:
+/// iPXE, or the script chaining the HTTP service when it's iPXE asking.
+pub fn boot_file(arch: Option<u16>, is_ipxe: bool) -> &'static String {
+    match arch {
+        _ if is_ipxe => &SCRIPT_BOOT_FILE,
+        Some(0x07 | 0x09) => &BOOT_FILES[1],
+        Some(0x0b) => &BOOT_FILES[2],
+        _ => &BOOT_FILES[0],
+    }
+}
+
+/// The iPXE binaries, for the image store to fetch.
+pub fn images() -> Vec<ImageSource> {
+    BINARIES
+        .iter()
+        .map(|(release_path, binary)| ImageSource {
+            path: Path::new(DIR).join(binary),
+            url: format!("{RELEASE_URL}/{release_path}"),
+            sha256: None,
+            sha256sums_url: None,
+            minisign_key: None,
+        })
+        .collect()
+}
+
+/// Writes the script handed to iPXE, chaining `/boot.ipxe` of the HTTP
+/// service on the server it was booted from.
+pub fn write_script(tftp_root: &Path, http_port: u16) -> Result<()> {
+    let script = format!(
+        "#!ipxe\n\
+         # Written by preboot-oxide, loads the boot script of the client\n\
+         chain http://${{next-server}}:{http_port}/boot.ipxe?mac=${{net0/mac}}\n"
+    );
+    let path = tftp_root.join(SCRIPT);
+    if fs::read_to_string(&path).is_ok_and(|current| current == script) {
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let part = part_path(&path);
+    fs::write(&part, &script)?;
+    fs::rename(&part, &path).inspect_err(|_| {
+        let _ = fs::remove_file(&part);
+    })?;
+
+    Ok(())
+}
@@ -1,4 +1,9 @@
-use clap::Parser;
+use std::{io::Write, path::PathBuf, time::Duration};
+
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+
+use crate::{conf::Conf, daemon::DaemonSettings, logging::LogFormat, oneshot::Target};
 
 #[derive(Parser)]
 #[command(name = crate_name!())]
@@ -6,15 +11,249 @@ use clap::Parser;
 #[command(about = "preboot-oxide: PXE Boot Server utility\nProject home: https://github.com/alexculea/Preboot-Oxide", long_about = None)]
 pub struct Cli {
     /// Sets the output verbosity level. Available levels: error, warn, info, debug, trace. Example: -v, -vv, -vvv
-    #[arg(short, action = clap::ArgAction::Count)]
+    #[arg(short, action = clap::ArgAction::Count, global = true)]
     verbosity: Option<u8>,
+    /// Configuration file, or URL, to load, in place of PO_CONF_PATH and the default file. Example: --config /etc/preboot-oxide/lab.yaml
+    #[arg(long, value_name = "PATH|URL", global = true)]
+    pub config: Option<String>,
+    /// Overrides a field of the configuration, repeatable. Example: --set default.boot_file=ipxe.efi
+    #[arg(long = "set", value_name = "KEY=VALUE", global = true)]
+    pub set: Vec<String>,
+    /// Detaches from the terminal, running in the background. Example: --daemon --pid-file /run/preboot-oxide.pid
+    #[arg(long, global = true)]
+    pub daemon: bool,
+    /// Writes the PID of the server to this file, removed when it's stopped
+    #[arg(long, value_name = "PATH", global = true)]
+    pub pid_file: Option<PathBuf>,
+    /// File the output of the server is appended to once detached, discarded without it
+    #[arg(long, value_name = "PATH", global = true)]
+    #[cfg_attr(unix, arg(requires = "daemon"))]
+    pub log_file: Option<PathBuf>,
+    /// Name of the server among those running on the host with their own configurations, for its lock and control socket. Example: --instance-name lab2
+    #[arg(long, value_name = "NAME", global = true, value_parser = parse_instance_name)]
+    pub instance_name: Option<String>,
+    /// Format of the messages: text on stderr, or a line of JSON a message on stdout with the client MAC address and DHCP transaction ID, for log collectors. Default: text, or PO_LOG_FORMAT
+    #[arg(long, value_name = "FORMAT", value_enum, global = true)]
+    pub log_format: Option<LogFormat>,
+    /// Runs as the process of a container: configured by the environment or PO_CONF_PATH only, logging JSON to stdout, without the instance lock
+    #[arg(long, global = true, conflicts_with = "daemon")]
+    pub container: bool,
+    /// Exits once boot files are served to N clients, 1 without N, or to each of the MAC addresses separated by commas. Example: --oneshot=52:54:00:12:34:56
+    #[arg(long, value_name = "N|MACS", global = true, num_args = 0..=1, require_equals = true, default_missing_value = "1")]
+    pub oneshot: Option<Target>,
+    /// Takes over the running server without dropping packets, its sockets, DHCP sessions and tracked clients handed over on the control socket, the server taken over exiting once its transfers end. Linux only.
+    #[arg(long, global = true)]
+    pub takeover: bool,
+    // `serve` when not given
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Runs the server, the default
+    Serve,
+    /// Runs the server as a Windows service, the command the service control manager starts
+    #[cfg(windows)]
+    Service,
+    /// Tells whether the server is running, and the services the configuration starts
+    Status,
+    /// Checks the configuration, printing its warnings, without starting the server
+    Test,
+    /// Checks the host for what keeps clients from booting, such as ports taken or missing rights
+    Doctor,
+    /// Boots a simulated PXE client against the running server, from a network namespace of its own, as root on Linux
+    SelfTest {
+        /// Seconds each step is waited for
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+    },
+    /// Lists the DHCP sessions in progress on the running server
+    Sessions,
+    /// Follows the events of the clients of the running server as they happen, a line each colored by client
+    Watch {
+        /// Only the events of this client
+        #[arg(long)]
+        mac: Option<String>,
+        /// Prints the events as lines of JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Lists the clients the running server has ever seen, with what they told of themselves
+    Assets {
+        /// Lists them as CSV, for spreadsheets and inventory tools
+        #[arg(long)]
+        csv: bool,
+    },
+    /// Tells how many clients of the running server booted, and those that didn't
+    Stats {
+        /// Only the clients seen within this time, in seconds or with a unit of s, m, h or d. Example: --since 12h
+        #[arg(long, value_parser = parse_since)]
+        since: Option<Duration>,
+    },
+    /// Reports the transfers of the running server: the files most transferred, their throughput, and the subnets they fail on
+    Transfers {
+        /// Number of files listed
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+        /// Prints the analytics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Tells what a client did, every DHCP message, transfer and timeout recorded to event_log in the order they happened
+    Timeline {
+        /// MAC address of the client. Example: 52:54:00:12:34:56
+        mac: String,
+        /// Prints the events as lines of JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Reloads the configuration of the running server, telling why it's rejected when invalid
+    Reload,
+    /// Broadcasts a DISCOVER and reports the DHCP servers answering
+    Probe {
+        /// Network interface to send it on
+        #[arg(long)]
+        iface: String,
+        /// Seconds the answers are waited for
+        #[arg(long, default_value_t = 3)]
+        timeout: u64,
+    },
+    /// Tells which match entry a client gets, and its configuration, without it booting
+    TestMatch {
+        /// MAC address of the client, or a JSON or pcap file of one of its DHCP messages
+        client: String,
+        /// Field added to the message, repeatable. Example: --field Interface=eth0
+        #[arg(long = "field", value_name = "NAME=VALUE")]
+        fields: Vec<String>,
+    },
+    /// Simulates PXE clients booting at once, reporting the percentiles of their timings
+    Bench {
+        /// Number of clients
+        #[arg(long, default_value_t = 10)]
+        clients: usize,
+        /// Network interface to run their DHCP handshakes on, they only download without it
+        #[arg(long)]
+        iface: Option<String>,
+        /// File downloaded, defaults to the boot file given over DHCP
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// TFTP server, defaults to the one given over DHCP, else 127.0.0.1
+        #[arg(long)]
+        server: Option<String>,
+        /// Seconds each DHCP answer is waited for
+        #[arg(long, default_value_t = 5)]
+        timeout: u64,
+    },
+    /// Downloads a file from a TFTP server, timing it
+    TftpGet {
+        /// Path of the file on the server
+        path: PathBuf,
+        /// IP address of the server, with an optional port, defaults to 127.0.0.1
+        #[arg(long)]
+        server: Option<String>,
+        /// File to write it to, the download is only timed and checksummed without it
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Asks a few questions and writes a starter configuration file
+    Init {
+        /// Where to write the configuration, defaults to where it's loaded from
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Prints the configuration in effect, the defaults included, as YAML
+    Config,
+    /// Tells where the configuration is looked for, in order, why each place was passed over and the one loaded
+    WhichConfig,
+    /// Rewrites the configuration file to the current version of its schema
+    Migrate {
+        /// The configuration file, defaults to where it's loaded from
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Prints the completions of a shell, or the man page, for installing them. Example: generate bash > /usr/share/bash-completion/completions/preboot-oxide
+    Generate {
+        #[arg(value_enum)]
+        what: Generated,
+    },
+}
+
+/// What `generate` prints.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Generated {
+    Bash,
+    Zsh,
+    Fish,
+    /// The man page, in roff
+    Man,
+}
+
+impl Cli {
+    pub fn command(&self) -> Command {
+        self.command.clone().unwrap_or(Command::Serve)
+    }
+
+    pub fn daemon_settings(&self) -> DaemonSettings {
+        DaemonSettings {
+            detach: self.daemon,
+            pid_file: self.pid_file.clone(),
+            log_file: self.log_file.clone(),
+        }
+    }
+
+    /// The level of `-v...`, `None` without it so the one of the environment
+    /// or the default of the command applies.
+    pub fn log_level(&self) -> Option<String> {
+        const LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+        let verbosity = self.verbosity.filter(|verbosity| *verbosity > 0)?;
+        Some(LEVELS[usize::from(verbosity).min(LEVELS.len() - 1)].to_string())
+    }
+}
+
+/// See [`Conf::is_valid_instance_name`].
+fn parse_instance_name(name: &str) -> Result<String, String> {
+    match Conf::is_valid_instance_name(name) {
+        true => Ok(name.to_string()),
+        false => Err("only ASCII letters, digits, '-' and '_' are allowed".to_string()),
+    }
 }
 
-pub fn parse() -> Option<String> {
-    let args = Cli::parse();
+/// A time such as `90`, `30m` or `12h`, in seconds without a unit.
+fn parse_since(since: &str) -> Result<Duration, String> {
+    let (value, unit) = match since.find(|c: char| !c.is_ascii_digit()) {
+        Some(at) => since.split_at(at),
+        None => (since, "s"),
+    };
+    let value: u64 = value.parse().map_err(|_| "expected a number, with a unit of s, m, h or d".to_string())?;
+    let secs = match unit {
+        "s" => value,
+        "m" => value * 60,
+        "h" => value * 60 * 60,
+        "d" => value * 24 * 60 * 60,
+        _ => return Err(format!("unknown unit {unit}, expected one of s, m, h, d")),
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+/// Writes `what` to `out`, from the definitions of the command line.
+pub fn generate(what: Generated, out: &mut dyn Write) -> crate::Result<()> {
+    let mut command = <Cli as CommandFactory>::command();
+    let shell = match what {
+        Generated::Bash => Shell::Bash,
+        Generated::Zsh => Shell::Zsh,
+        Generated::Fish => Shell::Fish,
+        Generated::Man => return Ok(clap_mangen::Man::new(command).render(out)?),
+    };
+    let name = command.get_name().to_string();
+    // Written at once, clap_complete panicking on write errors
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, name, &mut script);
+    out.write_all(&script)?;
+
+    Ok(())
+}
 
-    const LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
-    LEVELS
-        .get(args.verbosity.unwrap_or(0) as usize)
-        .map(|s| s.to_string())
+pub fn parse() -> Cli {
+    Cli::parse()
 }
@@ -1,17 +1,41 @@
 use anyhow::{Context, Result};
-use log::{info, trace};
+use log::{debug, info, trace, warn, LevelFilter};
 use once_cell::sync::Lazy;
 use regex::Regex;
+use sha2::{Digest, Sha256};
 use std::fmt;
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
     io::Read,
-    net::Ipv4Addr,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     path::{Path, PathBuf},
     str::FromStr,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
 };
+use dhcproto::v4::{Architecture, DhcpOption, Message, OptionCode};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, Time, UtcOffset, Weekday};
 use yaml_rust2::Yaml;
 
+use crate::{
+    dns::DEFAULT_DNS_PORT,
+    events::Event,
+    hosts::HostList,
+    images::IMAGES_DIR,
+    iso::ISO_EXTENSION,
+    migrate::{self, CONF_VERSION, MIGRATIONS},
+    mqtt::{MqttBroker, DEFAULT_MQTT_TOPIC},
+    notifications::{NotificationKind, SmtpServer},
+    overrides::Overrides,
+    remote::ConfSource,
+    schema,
+    syslog::{LogOutput, SyslogServer},
+    upstream::DEFAULT_TFTP_PORT,
+    util::{bytes_to_mac_address, mac_from_doc, redact_url},
+};
+
 pub type MacAddress = [u8; 6];
 type FieldConverter = for<'a> fn(&'a serde_json::Value) -> Result<String>;
 type FieldConverterMap = Lazy<HashMap<&'static str, FieldConverter>>;
@@ -19,25 +43,778 @@ type FieldConverterMap = Lazy<HashMap<&'static str, FieldConverter>>;
 #[derive(Clone, Debug)]
 pub struct Conf {
     default: Option<ConfEntry>,
+    /// `defaults` of the clients on each interface, by its name.
+    iface_defaults: BTreeMap<String, ConfEntry>,
+    /// Entries of `profiles`, by their name.
+    profiles: BTreeMap<String, ConfEntry>,
+    /// Configurations of the `tenants`, by their name, served on interfaces
+    /// of their own.
+    tenants: BTreeMap<String, Conf>,
+    /// `file:` and `env:` references of the secrets read from them, by the
+    /// path of their field, shown in their place.
+    secret_refs: BTreeMap<String, String>,
     ifaces: Option<Vec<String>>,
     match_map: Option<Vec<MatchEntry>>,
+    /// The `match` entries used for the clients lately.
+    decisions: DecisionCache,
+    match_policy: MatchPolicy,
+    /// Whether clients matching none of the `match` entries are ignored
+    /// rather than given the default.
+    require_match: bool,
     tftp_server_dir: Option<String>,
+    tftp_symlinks: SymlinkPolicy,
+    tftp_max_transfers: u64,
+    tftp_max_transfers_per_client: u64,
+    tftp_file_filter: FileFilter,
+    tftp_fallbacks: Vec<TftpFallback>,
+    tftp_block_size_limit: Option<u16>,
+    tftp_upstream: Option<SocketAddr>,
+    http_port: Option<u16>,
+    https_port: Option<u16>,
+    https_cert: Option<PathBuf>,
+    https_key: Option<PathBuf>,
+    autoinstall_dir: Option<PathBuf>,
+    netbootxyz: bool,
+    images: Vec<ImageSource>,
+    mirrors: Vec<MirrorSource>,
+    image_versions: u64,
+    secure_boot: bool,
+    upload_token: Option<String>,
+    signing_cert: Option<PathBuf>,
+    signing_key: Option<PathBuf>,
+    image_refresh_interval: Option<u64>,
+    upload_users: BTreeMap<String, String>,
+    upload_max_size: u64,
+    https_client_ca: Option<PathBuf>,
+    boot_hostname: Option<String>,
+    dns_port: Option<u16>,
+    /// Whether the hostnames of the clients are looked up in the reverse
+    /// DNS, for `ReverseHostname`.
+    reverse_dns: bool,
+    reverse_dns_server: Option<SocketAddr>,
+    ipxe_chainload: bool,
+    webhook_url: Option<String>,
+    webhook_timeout: Option<u64>,
+    boot_hook: Option<PathBuf>,
+    /// Programs run on the events of the clients.
+    event_hooks: BTreeMap<Event, PathBuf>,
+    /// URLs the events of the clients are posted to.
+    event_webhooks: Vec<EventWebhook>,
+    /// Chats and mailboxes the events of the clients are sent to.
+    notifications: Vec<Notification>,
+    /// Broker the events of the clients are published to.
+    mqtt_broker: Option<MqttBroker>,
+    mqtt_topic: Option<String>,
+    mqtt_username: Option<String>,
+    mqtt_password: Option<String>,
+    mqtt_retain: bool,
+    netbox_url: Option<String>,
+    netbox_token: Option<String>,
+    inventory_cache_ttl: Option<u64>,
+    /// Unix socket the `sessions` command asks the running server on.
+    control_socket: Option<PathBuf>,
+    /// Address the read-only web dashboard listens on, none without it.
+    dashboard_addr: Option<SocketAddr>,
+    /// Address the control API listens on, none without it.
+    api_addr: Option<SocketAddr>,
+    /// Address the gRPC control API listens on, none without it.
+    grpc_addr: Option<SocketAddr>,
+    /// Address the health and readiness checks are answered on, none
+    /// without it.
+    health_addr: Option<SocketAddr>,
+    /// Bearer token authorizing the requests of the control API.
+    api_token: Option<String>,
+    /// Address the SNMP agent listens on, none without it.
+    snmp_addr: Option<SocketAddr>,
+    /// Community the SNMP requests are answered for.
+    snmp_community: Option<String>,
+    /// Where the messages are written once the configuration is loaded.
+    log_output: LogOutput,
+    /// Remote server of `log_output: syslog`, the local socket without it.
+    syslog_server: Option<SyslogServer>,
+    /// File the DHCP packets are captured to, none without it.
+    pcap_file: Option<PathBuf>,
+    /// Size in MB of the capture file before it's rotated.
+    pcap_max_size: u64,
+    /// Capture files kept, the one written included.
+    pcap_files: u64,
+    /// Whether the TFTP requests are captured along the DHCP packets.
+    pcap_tftp: bool,
+    /// File the boot decisions are appended to, none without it.
+    audit_log: Option<PathBuf>,
+    /// Size in MB of the audit log before it's rotated.
+    audit_log_max_size: u64,
+    /// Audit logs kept, the one written included.
+    audit_log_files: u64,
+    /// File the decoded DHCP messages are appended to, none without it.
+    traffic_log: Option<PathBuf>,
+    /// Size in MB of the traffic log before it's rotated.
+    traffic_log_max_size: u64,
+    /// Traffic logs kept, the one written included.
+    traffic_log_files: u64,
+    /// File the events of the clients are appended to, none without it.
+    event_log: Option<PathBuf>,
+    /// Size in MB of the event log before it's rotated.
+    event_log_max_size: u64,
+    /// Event logs kept, the one written included.
+    event_log_files: u64,
+    /// File the boot statistics are saved to, kept in memory only without it.
+    stats_file: Option<PathBuf>,
+    /// File the clients seen are saved to, kept in memory only without it.
+    assets_file: Option<PathBuf>,
+    /// Account the server runs as once its sockets are bound.
+    user: Option<String>,
+    group: Option<String>,
+    /// Whether the process is confined by Landlock and seccomp once started.
+    sandbox: bool,
+    /// `--instance-name`, telling the server apart from the others of the
+    /// host.
+    instance_name: Option<String>,
+    strict: bool,
     max_sessions: u64,
+    /// Quotas of DHCP sessions of the clients on each interface, by its name.
+    max_sessions_per_iface: BTreeMap<String, u64>,
+    /// Quotas of DHCP sessions of the clients in each network.
+    max_sessions_per_subnet: Vec<(Network, u64)>,
+    /// DHCP messages answered at once, those received over it dropped.
+    dhcp_max_handlers: u64,
+    /// Whether the PXE boot server requests of port 4011 are answered.
+    pxe_boot_server: bool,
+    /// Sockets of port 4011 on each interface, the kernel spreading the
+    /// requests among them.
+    pxe_boot_server_workers: u64,
+    /// `dhcp.enabled`: whether the DHCP messages of the clients are answered.
+    dhcp_enabled: bool,
+    /// `tftp.enabled`: whether the TFTP servers of port 69 are started.
+    tftp_enabled: bool,
+}
+
+/// Parses an `<ip>[:<port>]` TFTP server address, the port defaults to 69.
+fn parse_tftp_upstream(s: &str) -> Result<SocketAddr> {
+    s.parse::<SocketAddr>()
+        .or_else(|_| s.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, DEFAULT_TFTP_PORT)))
+        .map_err(|_| anyhow!("Invalid TFTP server address: {s}, expected <ip>[:<port>]"))
+}
+
+/// Parses an `<ip>[:<port>]` DNS server address, the port defaults to 53.
+fn parse_dns_server(s: &str) -> Result<SocketAddr> {
+    s.parse::<SocketAddr>()
+        .or_else(|_| s.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, DEFAULT_DNS_PORT)))
+        .map_err(|_| anyhow!("Invalid DNS server address: {s}, expected <ip>[:<port>]"))
+}
+
+/// Parses the `<ip>:<port>` address of the dashboard or of the API.
+fn parse_listen_addr(s: &str) -> Result<SocketAddr> {
+    s.parse::<SocketAddr>()
+        .map_err(|_| anyhow!("Invalid address: {s}, expected <ip>:<port>"))
+}
+
+/// Parses an `<event>=<program>` event hook, as in `PO_EVENT_HOOKS`.
+fn parse_event_hook(s: &str) -> Result<(Event, PathBuf)> {
+    let (event, hook) = s
+        .split_once('=')
+        .ok_or(anyhow!("Invalid event hook {s}, expected <event>=<program>"))?;
+    Ok((event.trim().parse()?, PathBuf::from(hook.trim())))
+}
+
+/// URL the events of the clients are posted to, of `event_webhooks`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventWebhook {
+    pub url: String,
+    /// The events posted, all of them when empty.
+    pub events: Vec<Event>,
+}
+
+impl EventWebhook {
+    /// Whether `event` is posted to it.
+    pub fn takes(&self, event: Event) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+/// Chat or mailbox the events of the clients are sent to as messages, of
+/// `notifications`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    /// Webhook of the chat, or `smtp://<host>[:<port>]` of the relay the
+    /// mails are sent through.
+    pub url: String,
+    /// Sender of the mails.
+    pub from: Option<String>,
+    /// Recipients of the mails.
+    pub to: Vec<String>,
+    /// The events sent, all of them when empty.
+    pub events: Vec<Event>,
+}
+
+impl Notification {
+    /// Whether `event` is sent to it.
+    pub fn takes(&self, event: Event) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+/// File served instead of a missing one requested from under `prefix`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TftpFallback {
+    pub prefix: PathBuf,
+    pub file: PathBuf,
+}
+
+impl FromStr for TftpFallback {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (prefix, file) = s
+            .split_once('=')
+            .ok_or(anyhow!("Invalid TFTP fallback: {s}, expected <directory>=<file>"))?;
+
+        Ok(Self {
+            prefix: PathBuf::from(prefix.trim()),
+            file: PathBuf::from(file.trim()),
+        })
+    }
+}
+
+/// Boot asset of the image store, fetched from `url` and served at
+/// `images/<path>` under the TFTP root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageSource {
+    pub path: PathBuf,
+    pub url: String,
+    /// Expected SHA-256 checksum, hex encoded.
+    pub sha256: Option<String>,
+    /// Checksum list in the `sha256sum` format, listing the file of `url`.
+    pub sha256sums_url: Option<String>,
+    /// Minisign public key verifying the `<url>.minisig` signature.
+    pub minisign_key: Option<String>,
+}
+
+impl ImageSource {
+    fn from_yaml(path: &Yaml, source: &Yaml) -> Result<Self> {
+        let path = PathBuf::from(path.as_str().ok_or(anyhow!("Expected a string key in images"))?);
+        let field = |name: &str| source[name].as_str().map(|s| s.to_string());
+        let image = Self {
+            url: field("url").ok_or(anyhow!("No url given for image {}", path.display()))?,
+            sha256: field("sha256").map(|sha256| sha256.to_ascii_lowercase()),
+            sha256sums_url: field("sha256sums_url"),
+            minisign_key: field("minisign_key"),
+            path,
+        };
+
+        if image.path.as_os_str().is_empty()
+            || !image
+                .path
+                .components()
+                .all(|component| matches!(component, std::path::Component::Normal(_)))
+        {
+            bail!("Invalid image path {}, expected a relative path", image.path.display());
+        }
+        if let Some(sha256) = &image.sha256 {
+            if !is_sha256(sha256) {
+                bail!("Invalid sha256 for image {}", image.path.display());
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+/// Upstream directory proxied by the HTTP service, its files being cached at
+/// `<path>` under the TFTP root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MirrorSource {
+    pub path: PathBuf,
+    /// URL of the upstream directory the files under `path` are fetched from.
+    pub url: String,
+}
+
+impl MirrorSource {
+    fn from_yaml(path: &Yaml, url: &Yaml) -> Result<Self> {
+        let path = PathBuf::from(path.as_str().ok_or(anyhow!("Expected a string key in mirrors"))?);
+        let url = url
+            .as_str()
+            .ok_or(anyhow!("No url given for mirror {}", path.display()))?;
+        if !is_relative_path(&path) {
+            bail!("Invalid mirror path {}, expected a relative path", path.display());
+        }
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            bail!("Invalid url for mirror {}, expected an HTTP(S) URL", path.display());
+        }
+
+        Ok(Self {
+            path,
+            url: url.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+/// Format of the configuration file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfFormat {
+    /// The format told by the extension of `path`, YAML when unknown.
+    pub fn of(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("toml") => Self::Toml,
+            Some("json") => Self::Json,
+            _ => Self::Yaml,
+        }
+    }
+
+    /// Parses `content` into the YAML document the fields are read from.
+    fn load(self, content: &str) -> Result<Yaml> {
+        let document = match self {
+            Self::Yaml => yaml_rust2::YamlLoader::load_from_str(content)?
+                .into_iter()
+                .next()
+                .unwrap_or(Yaml::Null),
+            Self::Toml => toml_to_yaml(content.parse::<toml::Table>()?.into()),
+            Self::Json => json_to_yaml(serde_json::from_str(content)?),
+        };
+
+        Ok(document)
+    }
+}
+
+impl fmt::Display for ConfFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Yaml => write!(f, "YAML"),
+            Self::Toml => write!(f, "TOML"),
+            Self::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+fn toml_to_yaml(value: toml::Value) -> Yaml {
+    match value {
+        toml::Value::String(s) => Yaml::String(s),
+        toml::Value::Integer(i) => Yaml::Integer(i),
+        toml::Value::Float(f) => Yaml::Real(f.to_string()),
+        toml::Value::Boolean(b) => Yaml::Boolean(b),
+        toml::Value::Datetime(datetime) => Yaml::String(datetime.to_string()),
+        toml::Value::Array(values) => Yaml::Array(values.into_iter().map(toml_to_yaml).collect()),
+        toml::Value::Table(table) => Yaml::Hash(
+            table
+                .into_iter()
+                .map(|(key, value)| (Yaml::String(key), toml_to_yaml(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn json_to_yaml(value: serde_json::Value) -> Yaml {
+    match value {
+        serde_json::Value::Null => Yaml::Null,
+        serde_json::Value::Bool(b) => Yaml::Boolean(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Yaml::Integer(i),
+            None => Yaml::Real(n.to_string()),
+        },
+        serde_json::Value::String(s) => Yaml::String(s),
+        serde_json::Value::Array(values) => {
+            Yaml::Array(values.into_iter().map(json_to_yaml).collect())
+        }
+        serde_json::Value::Object(object) => Yaml::Hash(
+            object
+                .into_iter()
+                .map(|(key, value)| (Yaml::String(key), json_to_yaml(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Whether `path` is a configuration file of an include directory, going by
+/// its extension. Hidden files, like editor backups, are skipped.
+fn is_include_file(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    !hidden && matches!(extension.as_deref(), Some("yaml" | "yml" | "toml" | "json"))
+}
+
+/// Whether `s` is a valid DNS name, e.g. `boot.lab`.
+fn is_hostname(s: &str) -> bool {
+    let s = s.strip_suffix('.').unwrap_or(s);
+    s.len() <= 253
+        && s.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// The entry of `profiles` named `name`.
+fn profile<'a>(profiles: &'a BTreeMap<String, ConfEntry>, name: &str) -> Result<&'a ConfEntry> {
+    profiles.get(name).ok_or(anyhow!("Unknown profile {name}"))
+}
+
+/// `value` as a YAML string, `null` when there's none.
+fn yaml_str(value: Option<impl ToString>) -> Yaml {
+    value.map_or(Yaml::Null, |value| Yaml::String(value.to_string()))
+}
+
+/// Mapping of the `fields` with a value, `null` when none has one.
+fn yaml_mapping<K: ToString>(fields: impl IntoIterator<Item = (K, Yaml)>) -> Yaml {
+    let mapping: yaml_rust2::yaml::Hash = fields
+        .into_iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(key, value)| (Yaml::String(key.to_string()), value))
+        .collect();
+    match mapping.is_empty() {
+        true => Yaml::Null,
+        false => Yaml::Hash(mapping),
+    }
+}
+
+/// `true`, or `null` for false flags, left out as they default to false.
+fn yaml_flag(value: bool) -> Yaml {
+    match value {
+        true => Yaml::Boolean(true),
+        false => Yaml::Null,
+    }
+}
+
+/// List of the `items`, `null` when empty.
+fn yaml_list(items: impl IntoIterator<Item = Yaml>) -> Yaml {
+    let items: Vec<Yaml> = items.into_iter().collect();
+    match items.is_empty() {
+        true => Yaml::Null,
+        false => Yaml::Array(items),
+    }
+}
+
+/// Whether `s` is a hex encoded SHA-256.
+fn is_sha256(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Whether `path` is relative to the image store and names an ISO image.
+fn is_boot_iso_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case(ISO_EXTENSION))
+        && is_relative_path(path)
+}
+
+/// Whether `path` is relative and stays within the directory it's joined to.
+fn is_relative_path(path: &Path) -> bool {
+    !path.as_os_str().is_empty()
+        && path
+            .components()
+        .all(|component| matches!(component, std::path::Component::Normal(_)))
+}
+
+/// Restricts which files the TFTP service serves based on their names.
+#[derive(Clone, Debug)]
+pub struct FileFilter {
+    /// Serve files and directories whose name starts with a dot.
+    pub serve_hidden: bool,
+    /// When given, only files with one of these extensions are served.
+    pub allowed_extensions: Option<Vec<String>>,
+    /// Files with any of these extensions are never served.
+    pub denied_extensions: Vec<String>,
+}
+
+impl Default for FileFilter {
+    fn default() -> Self {
+        Self {
+            serve_hidden: false,
+            allowed_extensions: None,
+            denied_extensions: DEFAULT_TFTP_DENIED_EXTENSIONS
+                .iter()
+                .map(|ext| ext.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl FileFilter {
+    pub fn allows(&self, path: &Path) -> bool {
+        let is_hidden = path.components().any(|component| {
+            component
+                .as_os_str()
+                .to_str()
+                .map(|name| name.starts_with('.') && name != "." && name != "..")
+                .unwrap_or(false)
+        });
+        if is_hidden && !self.serve_hidden {
+            return false;
+        }
+
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        let has_extension = |list: &Vec<String>| {
+            list.iter()
+                .any(|item| item.trim_start_matches('.').eq_ignore_ascii_case(extension))
+        };
+        if has_extension(&self.denied_extensions) {
+            return false;
+        }
+
+        self.allowed_extensions
+            .as_ref()
+            .map(has_extension)
+            .unwrap_or(true)
+    }
+}
+
+/// How the TFTP service treats symbolic links found under its root directory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Refuse any path that goes through a symbolic link.
+    Deny,
+    /// Follow symbolic links as long as the target stays inside the root directory.
+    #[default]
+    DenyEscaping,
+    /// Follow all symbolic links, even those pointing outside the root directory.
+    Allow,
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "deny" => Ok(Self::Deny),
+            "deny-escaping" => Ok(Self::DenyEscaping),
+            "allow" => Ok(Self::Allow),
+            _ => Err(anyhow!(
+                "Invalid symlink policy: {s}, expected one of: deny, deny-escaping, allow"
+            )),
+        }
+    }
+}
+
+/// Which entry of `match` is used when several match a client, among those
+/// of the highest priority.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MatchPolicy {
+    /// The first in the order of definition.
+    #[default]
+    First,
+    /// The one with the most `select` fields matching the client, values
+    /// taking precedence over patterns, then the first.
+    MostSpecific,
+}
+
+impl FromStr for MatchPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "first" => Ok(Self::First),
+            "most-specific" => Ok(Self::MostSpecific),
+            _ => Err(anyhow!(
+                "Invalid match policy: {s}, expected one of: first, most-specific"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MatchPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::First => write!(f, "first"),
+            Self::MostSpecific => write!(f, "most-specific"),
+        }
+    }
 }
 
 #[derive(Default, Clone, Debug)]
 pub struct ConfEntry {
     pub boot_file: Option<String>,
     pub boot_server_ipv4: Option<Ipv4Addr>,
+    pub ipxe_script: Option<String>,
+    pub grub_cfg: Option<String>,
+    /// Ignition or Butane config template of `autoinstall_dir`.
+    pub ignition: Option<String>,
+    /// ISO of the image store booted through the iPXE script.
+    pub boot_iso: Option<PathBuf>,
+    /// Windows installation media, a directory or ISO of the TFTP root,
+    /// booted with wimboot through the iPXE script.
+    pub windows: Option<PathBuf>,
+    /// Label of the entry in the generated iPXE menu.
+    pub menu_label: Option<String>,
+    /// Subnet mask of the DHCP acknowledgement, when the offer of the DHCP
+    /// server didn't give one.
+    pub subnet_mask: Option<Ipv4Addr>,
+    /// Lease time in seconds of the DHCP acknowledgement, when the offer of
+    /// the DHCP server didn't give one.
+    pub lease_time: Option<u32>,
+    /// Milliseconds the DHCP offer and acknowledgement are delayed by, e.g.
+    /// for firmware expecting them after those of the DHCP server.
+    pub reply_delay_ms: Option<u64>,
+    /// Level the messages logged about the client are shown from, when
+    /// more detailed than that of the service.
+    pub log_level: Option<LevelFilter>,
+    /// Values for the placeholders of the templates rendered for the client.
+    pub vars: BTreeMap<String, String>,
+    /// Versions of the image store served to the client instead of the
+    /// current ones, by SHA-256.
+    pub pinned_images: BTreeMap<PathBuf, String>,
+    /// Name of the entry of `profiles` completing this one, merged into it
+    /// once the configuration is loaded.
+    pub profile: Option<String>,
 }
 
 #[derive(Default, Clone, Debug)]
 pub struct ConfEntryRef<'a> {
     pub boot_file: Option<&'a String>,
     pub boot_server_ipv4: Option<&'a Ipv4Addr>,
+    pub ipxe_script: Option<&'a String>,
+    pub grub_cfg: Option<&'a String>,
+    pub ignition: Option<&'a String>,
+    pub boot_iso: Option<&'a PathBuf>,
+    pub windows: Option<&'a PathBuf>,
+    pub menu_label: Option<&'a String>,
+    pub subnet_mask: Option<&'a Ipv4Addr>,
+    pub lease_time: Option<&'a u32>,
+    pub reply_delay_ms: Option<&'a u64>,
+    pub log_level: Option<&'a LevelFilter>,
+    pub vars: BTreeMap<&'a str, &'a str>,
+    pub pinned_images: BTreeMap<&'a Path, &'a str>,
+}
+
+impl ConfEntryRef<'_> {
+    /// The fields of the entry, owned.
+    pub fn to_entry(&self) -> ConfEntry {
+        ConfEntry {
+            boot_file: self.boot_file.cloned(),
+            boot_server_ipv4: self.boot_server_ipv4.copied(),
+            ipxe_script: self.ipxe_script.cloned(),
+            grub_cfg: self.grub_cfg.cloned(),
+            ignition: self.ignition.cloned(),
+            boot_iso: self.boot_iso.cloned(),
+            windows: self.windows.cloned(),
+            menu_label: self.menu_label.cloned(),
+            subnet_mask: self.subnet_mask.copied(),
+            lease_time: self.lease_time.copied(),
+            reply_delay_ms: self.reply_delay_ms.copied(),
+            log_level: self.log_level.copied(),
+            vars: self
+                .vars
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            pinned_images: self
+                .pinned_images
+                .iter()
+                .map(|(image, version)| (image.to_path_buf(), version.to_string()))
+                .collect(),
+            profile: None,
+        }
+    }
+
+    /// Whether both boot the same way, showing as the same iPXE menu entry.
+    pub fn boots_same_as(&self, other: &ConfEntryRef) -> bool {
+        self.boot_iso == other.boot_iso
+            && self.windows == other.windows
+            && self.ipxe_script == other.ipxe_script
+            && self.menu_label == other.menu_label
+    }
 }
 
 impl ConfEntry {
+    /// Parses an entry given in JSON, with the fields of `default`.
+    pub fn from_json(value: serde_json::Value) -> Result<ConfEntry> {
+        Conf::base_conf_from_yaml(&json_to_yaml(value))?
+            .ok_or(anyhow!("Expected a JSON object of configuration fields"))
+    }
+
+    /// This entry with the fields it doesn't set taken from `other`.
+    pub fn merged_with(&self, other: &ConfEntry) -> ConfEntry {
+        ConfEntry {
+            boot_file: self.boot_file.clone().or(other.boot_file.clone()),
+            boot_server_ipv4: self.boot_server_ipv4.or(other.boot_server_ipv4),
+            ipxe_script: self.ipxe_script.clone().or(other.ipxe_script.clone()),
+            grub_cfg: self.grub_cfg.clone().or(other.grub_cfg.clone()),
+            ignition: self.ignition.clone().or(other.ignition.clone()),
+            boot_iso: self.boot_iso.clone().or(other.boot_iso.clone()),
+            windows: self.windows.clone().or(other.windows.clone()),
+            menu_label: self.menu_label.clone().or(other.menu_label.clone()),
+            subnet_mask: self.subnet_mask.or(other.subnet_mask),
+            lease_time: self.lease_time.or(other.lease_time),
+            reply_delay_ms: self.reply_delay_ms.or(other.reply_delay_ms),
+            log_level: self.log_level.or(other.log_level),
+            vars: other
+                .vars
+                .clone()
+                .into_iter()
+                .chain(self.vars.clone())
+                .collect(),
+            pinned_images: other
+                .pinned_images
+                .clone()
+                .into_iter()
+                .chain(self.pinned_images.clone())
+                .collect(),
+            profile: self.profile.clone().or(other.profile.clone()),
+        }
+    }
+
+    /// The fields of the entry set, as YAML of the configuration file.
+    pub fn to_yaml_string(&self) -> Result<String> {
+        let mut yaml = String::new();
+        yaml_rust2::YamlEmitter::new(&mut yaml).dump(&self.to_yaml())?;
+        yaml.push('\n');
+
+        Ok(yaml)
+    }
+
+    /// The fields of the entry set, as written in the configuration file.
+    fn to_yaml(&self) -> Yaml {
+        let path = |path: &Option<PathBuf>| yaml_str(path.as_ref().map(|path| path.display()));
+        yaml_mapping([
+            ("boot_file", yaml_str(self.boot_file.as_ref())),
+            ("boot_server_ipv4", yaml_str(self.boot_server_ipv4)),
+            ("ipxe_script", yaml_str(self.ipxe_script.as_ref())),
+            ("grub_cfg", yaml_str(self.grub_cfg.as_ref())),
+            ("ignition", yaml_str(self.ignition.as_ref())),
+            ("boot_iso", path(&self.boot_iso)),
+            ("windows", path(&self.windows)),
+            ("menu_label", yaml_str(self.menu_label.as_ref())),
+            ("subnet_mask", yaml_str(self.subnet_mask)),
+            ("lease_time", self.lease_time.map_or(Yaml::Null, |secs| Yaml::Integer(secs.into()))),
+            (
+                "reply_delay_ms",
+                self.reply_delay_ms.map_or(Yaml::Null, |ms| Yaml::Integer(ms as i64)),
+            ),
+            (
+                "log_level",
+                yaml_str(self.log_level.map(|level| level.as_str().to_lowercase())),
+            ),
+            ("profile", yaml_str(self.profile.as_ref())),
+            (
+                "vars",
+                yaml_mapping(self.vars.iter().map(|(name, value)| (name, yaml_str(Some(value))))),
+            ),
+            (
+                "pinned_images",
+                yaml_mapping(
+                    self.pinned_images
+                        .iter()
+                        .map(|(path, sha256)| (path.display(), yaml_str(Some(sha256)))),
+                ),
+            ),
+        ])
+    }
+
     pub fn merge_refs<'a>(&'a self, other: Option<&'a ConfEntry>) -> ConfEntryRef<'a> {
         let boot_file = self
             .boot_file
@@ -47,37 +824,155 @@ impl ConfEntry {
             .boot_server_ipv4
             .as_ref()
             .or(other.and_then(|o| o.boot_server_ipv4.as_ref()));
+        let ipxe_script = self
+            .ipxe_script
+            .as_ref()
+            .or(other.and_then(|o| o.ipxe_script.as_ref()));
+        let grub_cfg = self
+            .grub_cfg
+            .as_ref()
+            .or(other.and_then(|o| o.grub_cfg.as_ref()));
+        let ignition = self
+            .ignition
+            .as_ref()
+            .or(other.and_then(|o| o.ignition.as_ref()));
+        let boot_iso = self
+            .boot_iso
+            .as_ref()
+            .or(other.and_then(|o| o.boot_iso.as_ref()));
+        let windows = self
+            .windows
+            .as_ref()
+            .or(other.and_then(|o| o.windows.as_ref()));
+        let menu_label = self
+            .menu_label
+            .as_ref()
+            .or(other.and_then(|o| o.menu_label.as_ref()));
+        let subnet_mask = self
+            .subnet_mask
+            .as_ref()
+            .or(other.and_then(|o| o.subnet_mask.as_ref()));
+        let lease_time = self
+            .lease_time
+            .as_ref()
+            .or(other.and_then(|o| o.lease_time.as_ref()));
+        let reply_delay_ms = self
+            .reply_delay_ms
+            .as_ref()
+            .or(other.and_then(|o| o.reply_delay_ms.as_ref()));
+        let log_level = self
+            .log_level
+            .as_ref()
+            .or(other.and_then(|o| o.log_level.as_ref()));
+        let vars = other
+            .iter()
+            .flat_map(|o| o.vars.iter())
+            .chain(self.vars.iter())
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        let pinned_images = other
+            .iter()
+            .flat_map(|o| o.pinned_images.iter())
+            .chain(self.pinned_images.iter())
+            .map(|(image, version)| (image.as_path(), version.as_str()))
+            .collect();
 
         ConfEntryRef {
             boot_file,
             boot_server_ipv4,
+            ipxe_script,
+            grub_cfg,
+            ignition,
+            boot_iso,
+            windows,
+            menu_label,
+            subnet_mask,
+            lease_time,
+            reply_delay_ms,
+            log_level,
+            vars,
+            pinned_images,
         }
     }
 }
 
+/// IPv4 network in CIDR notation (`10.0.1.0/24`), or a single address
+/// without a prefix length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Network {
+    ip: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl Network {
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len)).unwrap_or(0);
+        !ip.is_unspecified() && u32::from(ip) & mask == u32::from(self.ip) & mask
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+}
+
+impl FromStr for Network {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (ip, prefix_len) = value.split_once('/').unwrap_or((value, "32"));
+        ip.trim()
+            .parse::<Ipv4Addr>()
+            .ok()
+            .zip(prefix_len.trim().parse::<u8>().ok().filter(|len| *len <= 32))
+            .map(|(ip, prefix_len)| Self { ip, prefix_len })
+            .ok_or(anyhow!("Invalid network {value}, expected <ip>[/<prefix length>]"))
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.ip, self.prefix_len)
+    }
+}
+
 #[derive(Debug, Clone)]
 struct FieldValue {
     value: String,
     regex: Option<Regex>,
+    /// Network of `Subnet` and `Giaddr`, matching the addresses in it.
+    network: Option<Network>,
 }
 
 impl FieldValue {
-    pub fn from_string(value: String, regex: bool) -> Result<Self> {
+    pub fn from_string(value: String, mode: MatchMode) -> Result<Self> {
         Ok(Self {
-            regex: if regex {
-                Some(Regex::new(&value)?)
-            } else {
-                None
+            regex: match mode {
+                MatchMode::Exact => None,
+                MatchMode::Regex => Some(Regex::new(&value)?),
+                MatchMode::Glob => Some(Regex::new(&glob_to_regex(&value))?),
             },
+            network: None,
+            value,
+        })
+    }
+
+    /// Value matching the addresses of the network `value`, in CIDR notation
+    /// (`10.0.1.0/24`), or a single address without a prefix length.
+    pub fn from_network(value: String) -> Result<Self> {
+        Ok(Self {
+            regex: None,
+            network: Some(value.parse()?),
             value,
         })
     }
 
-    pub fn matches(&self, other: &String) -> bool {
-        if let Some(re) = self.regex.as_ref() {
+    pub fn matches(&self, other: &str) -> bool {
+        if let Some(network) = self.network {
+            other.parse::<Ipv4Addr>().is_ok_and(|ip| network.contains(ip))
+        } else if let Some(re) = self.regex.as_ref() {
             re.is_match(other)
         } else {
-            other.eq_ignore_ascii_case(other)
+            self.value.eq_ignore_ascii_case(other)
         }
     }
 }
@@ -88,414 +983,3725 @@ impl fmt::Display for FieldValue {
     }
 }
 
+/// Translates a glob pattern, where `*` stands for any characters and `?`
+/// for any one, to a regular expression matching whole values, case
+/// insensitively like the exact matching.
+fn glob_to_regex(pattern: &str) -> String {
+    let mut regex = String::from("(?i)^");
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            _ => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+
+    regex
+}
+
+/// How the values of `select` are compared to those of the clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MatchMode {
+    Exact,
+    Regex,
+    Glob,
+}
+
+impl fmt::Display for MatchMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self {
+            MatchMode::Exact => "exact",
+            MatchMode::Regex => "regex",
+            MatchMode::Glob => "glob",
+        };
+        write!(f, "{mode}")
+    }
+}
+
 #[derive(Clone, Debug)]
 enum MatchType {
     Any,
     All,
 }
+/// What a client has to match for a match entry to be used, or for a group
+/// of its `any` or `all`.
 #[derive(Clone, Debug)]
-struct MatchEntry {
+struct MatchCondition {
     fields_values: HashMap<String, FieldValue>,
-    conf: ConfEntry,
+    /// Values of `not`, the condition isn't met by clients with any of them.
+    excluded_values: HashMap<String, Vec<FieldValue>>,
     match_type: MatchType,
-    regex: bool,
+    mode: MatchMode,
+    /// Groups of which at least one has to match.
+    any_groups: Vec<MatchCondition>,
+    /// Groups that all have to match.
+    all_groups: Vec<MatchCondition>,
+    /// Times the condition may be met at, `active`.
+    active: Option<TimeWindow>,
 }
 
-pub const DEFAULT_MAX_SESSIONS: u64 = 500;
-pub const CONFIG_FOLDER: &str = "preboot-oxide";
-pub const YAML_FILENAME: &str = "preboot-oxide.yaml";
-pub const ENV_VAR_PREFIX: &str = "PO_";
-// Unused for now, until we add support for architecture based configuration
-pub const _DHCP_ARCHES: phf::Map<&'static str, u16> = phf_map! {
-    "x86" => 0x0,
-    "itanium" => 0x2,
-    "x86-uefi" => 0x6,
-    "x64-uefi" => 0x7,
-    "arm32-uefi" => 0x0a,
-    "arm64-uefi" => 0x0b,
-    "arm32-uboot" => 0x15,
-    "arm64-uboot" => 0x16,
-    "arm32-rpiboot" => 0x29,
-    "riscv32-uefi" => 0x19,
-    "riscv64-uefi" => 0x1b,
-    "riscv128-uefi" => 0x1d
-};
-// source: https://www.iana.org/assignments/dhcpv6-parameters/dhcpv6-parameters.xhtml#processor-architecture
+/// Times of `active` a match entry or group applies at: on `days` of the
+/// week, between the `hours` of the day, and from or until a date.
+#[derive(Clone, Debug)]
+struct TimeWindow {
+    /// Days of the week the window opens on, every day when empty.
+    days: Vec<Weekday>,
+    /// Start and end of the window each day, past midnight when the end is
+    /// before the start.
+    hours: Option<(Time, Time)>,
+    from: Option<OffsetDateTime>,
+    until: Option<OffsetDateTime>,
+    /// Offset of the days and hours from UTC.
+    utc_offset: UtcOffset,
+}
 
-pub const FIELD_MAP: phf::Map<&'static str, &'static str> = phf_map! {
-    "ClientMacAddress" => "chaddr",
-    "HardwareType" => "htype",
-};
-static FIELD_CONVERTERS: FieldConverterMap = Lazy::new(|| {
-    HashMap::from([
-        (
-            "ClientMacAddress",
-            (|input: &serde_json::Value| Conf::get_mac_from_doc_string(input)) as FieldConverter,
-        ),
-        (
-            "ClassIdentifier",
-            |input: &serde_json::Value| -> Result<String> {
-                input
-                    .as_array()
-                    .map(|arr| {
-                        Ok(arr
-                            .iter()
-                            .map(|item| Ok(char::try_from(item.as_u64().unwrap_or(0) as u32)?))
-                            .collect::<Result<Vec<char>>>()?
-                            .iter()
-                            .collect::<String>())
-                    })
-                    .unwrap_or(Ok(String::default()))
-            },
-        ),
-        (
-            "ClientMachineIdentifier",
-            |input: &serde_json::Value| -> Result<String> {
-                input
-                    .as_object()
-                    .map(|dict| dict.get("ClientMachineIdentifier"))
-                    .flatten()
-                    .map(|value| value.as_array())
-                    .flatten()
-                    .map(|arr| {
-                        Ok(arr
-                            .iter()
-                            .map(|item| Ok(char::try_from(item.as_u64().unwrap_or(0) as u32)?.to_string()))
-                            .collect::<Result<Vec<String>>>()?
-                            .join("."))
-                    })
-                    .unwrap_or(Ok(String::default()))
-            },
-        ),
-    ])
+impl TimeWindow {
+    fn from_yaml(yaml: &Yaml) -> Result<Self> {
+        let timestamp = |name: &str| {
+            yaml[name]
+                .as_str()
+                .map(|s| {
+                    OffsetDateTime::parse(s, &Rfc3339)
+                        .map_err(|_| anyhow!("Invalid {name}: {s}, expected a RFC 3339 date"))
+                })
+                .transpose()
+        };
+        let days = yaml["days"]
+            .as_vec()
+            .into_iter()
+            .flatten()
+            .map(|day| day.as_str().and_then(parse_weekday).ok_or(anyhow!("Invalid day: {day:?}")))
+            .collect::<Result<Vec<Weekday>>>()?;
+        let hours = yaml["hours"]
+            .as_str()
+            .map(|hours| {
+                hours
+                    .split_once('-')
+                    .and_then(|(start, end)| Some((parse_time(start)?, parse_time(end)?)))
+                    .ok_or(anyhow!("Invalid hours: {hours}, expected <HH:MM>-<HH:MM>"))
+            })
+            .transpose()?;
+        let utc_offset = yaml["utc_offset"]
+            .as_str()
+            .map(|offset| {
+                parse_utc_offset(offset)
+                    .ok_or(anyhow!("Invalid utc_offset: {offset}, expected +<HH:MM> or -<HH:MM>"))
+            })
+            .transpose()?
+            .unwrap_or(UtcOffset::UTC);
+
+        Ok(Self {
+            days,
+            hours,
+            from: timestamp("from")?,
+            until: timestamp("until")?,
+            utc_offset,
+        })
+    }
+
+    fn to_yaml(&self) -> Yaml {
+        let time = |time: Time| format!("{:02}:{:02}", time.hour(), time.minute());
+        let timestamp = |timestamp: Option<OffsetDateTime>| {
+            yaml_str(timestamp.and_then(|timestamp| timestamp.format(&Rfc3339).ok()))
+        };
+        let (hours, minutes, _) = self.utc_offset.as_hms();
+        let sign = if self.utc_offset.is_negative() { '-' } else { '+' };
+        let utc_offset = format!("{sign}{:02}:{:02}", hours.abs(), minutes.abs());
+        let days = self
+            .days
+            .iter()
+            .map(|day| Yaml::String(day.to_string().to_ascii_lowercase()));
+
+        let hours = self
+            .hours
+            .map(|(start, end)| format!("{}-{}", time(start), time(end)));
+
+        yaml_mapping([
+            ("days", yaml_list(days)),
+            ("hours", yaml_str(hours)),
+            ("from", timestamp(self.from)),
+            ("until", timestamp(self.until)),
+            ("utc_offset", yaml_str((!self.utc_offset.is_utc()).then_some(utc_offset))),
+        ])
+    }
+
+    fn contains(&self, now: OffsetDateTime) -> bool {
+        let has_started = self.from.is_none_or(|from| from <= now);
+        let has_ended = self.until.is_some_and(|until| until <= now);
+        if !has_started || has_ended {
+            return false;
+        }
+
+        let now = now.to_offset(self.utc_offset);
+        let is_open_on = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+        match self.hours {
+            None => is_open_on(now.weekday()),
+            Some((start, end)) if start <= end => {
+                is_open_on(now.weekday()) && start <= now.time() && now.time() < end
+            }
+            // Past midnight, the window opened the day before
+            Some((start, end)) => {
+                (is_open_on(now.weekday()) && now.time() >= start)
+                    || (is_open_on(now.weekday().previous()) && now.time() < end)
+            }
+        }
+    }
+}
+
+fn parse_weekday(day: &str) -> Option<Weekday> {
+    let day = day.to_ascii_lowercase();
+    [
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+        Weekday::Sunday,
+    ]
+    .into_iter()
+    .find(|weekday| {
+        let name = weekday.to_string().to_ascii_lowercase();
+        day == name || day == name[..3]
+    })
+}
+
+/// Parses a `HH:MM` time of day.
+fn parse_time(time: &str) -> Option<Time> {
+    let (hour, minute) = time.trim().split_once(':')?;
+    Time::from_hms(hour.parse().ok()?, minute.parse().ok()?, 0).ok()
+}
+
+/// Parses a `+HH:MM` or `-HH:MM` offset from UTC.
+fn parse_utc_offset(offset: &str) -> Option<UtcOffset> {
+    let (sign, offset) = match offset.trim().split_at_checked(1)? {
+        ("+", offset) => (1, offset),
+        ("-", offset) => (-1, offset),
+        _ => return None,
+    };
+    let (hours, minutes) = offset.split_once(':')?;
+    let (hours, minutes): (i8, i8) = (hours.parse().ok()?, minutes.parse().ok()?);
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+}
+
+impl MatchCondition {
+    /// Condition met by all the clients.
+    fn everyone() -> Self {
+        Self {
+            fields_values: HashMap::new(),
+            excluded_values: HashMap::new(),
+            match_type: MatchType::All,
+            mode: MatchMode::Exact,
+            any_groups: Vec::new(),
+            all_groups: Vec::new(),
+            active: None,
+        }
+    }
+
+    /// The fields of the condition, as written in the configuration file,
+    /// the mode of the groups included.
+    fn yaml_fields(&self) -> Vec<(&'static str, Yaml)> {
+        let value = |value: &FieldValue| Yaml::String(value.value.clone());
+        let values = |values: &Vec<FieldValue>| Yaml::Array(values.iter().map(value).collect());
+        let mut select: Vec<(&String, &FieldValue)> = self.fields_values.iter().collect();
+        select.sort_by_key(|(key, _)| *key);
+        let mut not: Vec<(&String, &Vec<FieldValue>)> = self.excluded_values.iter().collect();
+        not.sort_by_key(|(key, _)| *key);
+        let groups = |groups: &[MatchCondition]| {
+            yaml_list(groups.iter().map(|group| yaml_mapping(group.yaml_fields())))
+        };
+        let match_type = match self.match_type {
+            MatchType::Any => "any",
+            MatchType::All => "all",
+        };
+
+        vec![
+            ("select", yaml_mapping(select.into_iter().map(|(key, v)| (key, value(v))))),
+            ("not", yaml_mapping(not.into_iter().map(|(key, v)| (key, values(v))))),
+            ("any", groups(&self.any_groups)),
+            ("all", groups(&self.all_groups)),
+            ("active", self.active.as_ref().map_or(Yaml::Null, TimeWindow::to_yaml)),
+            ("match_type", Yaml::String(match_type.to_string())),
+            ("regex", yaml_flag(self.mode == MatchMode::Regex)),
+            ("glob", yaml_flag(self.mode == MatchMode::Glob)),
+        ]
+    }
+
+    fn matches(&self, doc: &dyn ClientFields, now: OffsetDateTime) -> bool {
+        let mut fields_values = self.fields_values.iter();
+        let is_field_match = |(key, config_value): (&String, &FieldValue)| {
+            self.is_field_match(doc, key, config_value)
+        };
+        let is_selected = match self.match_type {
+            // Conditions with only `not` or groups match the other clients
+            _ if self.fields_values.is_empty() => true,
+            MatchType::Any => fields_values.any(is_field_match),
+            MatchType::All => fields_values.all(is_field_match),
+        };
+        let is_excluded = || {
+            self.excluded_values.iter().any(|(key, values)| {
+                values.iter().any(|value| self.is_field_match(doc, key, value))
+            })
+        };
+
+        let group_matches = |group: &MatchCondition| group.matches(doc, now);
+        let any_group_matches =
+            || self.any_groups.is_empty() || self.any_groups.iter().any(group_matches);
+
+        self.active.as_ref().is_none_or(|window| window.contains(now))
+            && is_selected
+            && !is_excluded()
+            && any_group_matches()
+            && self.all_groups.iter().all(group_matches)
+    }
+
+    fn is_field_match(
+        &self,
+        doc: &dyn ClientFields,
+        cfg_key: &str,
+        cfg_value: &FieldValue,
+    ) -> bool {
+        let matcher = |converted_value: Cow<str>| {
+            let match_result = cfg_value.matches(&converted_value);
+            let match_type = self.mode;
+
+            trace!("Matching {match_type} field {cfg_key}=\"{converted_value}\" to \"{cfg_value}\", matching = {match_result}");
+            match_result
+        };
+
+        doc.field(cfg_key).map(matcher).unwrap_or(false)
+    }
+
+    /// Adds the names of the fields the condition reads to `fields`, telling
+    /// whether it depends on them only, not on the time with `active`.
+    fn read_fields<'a>(&'a self, fields: &mut BTreeSet<&'a str>) -> bool {
+        fields.extend(self.fields_values.keys().map(String::as_str));
+        fields.extend(self.excluded_values.keys().map(String::as_str));
+        let groups = self.any_groups.iter().chain(&self.all_groups);
+        let untimed_groups = groups.fold(true, |untimed, group| group.read_fields(fields) & untimed);
+        self.active.is_none() && untimed_groups
+    }
+
+    /// The values the clients are required to match, those of `select` when
+    /// all of them have to and of the `all` groups.
+    fn required_values(&self) -> Vec<(&str, &FieldValue)> {
+        let mut required = Vec::new();
+        if matches!(self.match_type, MatchType::All) || self.fields_values.len() == 1 {
+            required.extend(self.fields_values.iter().map(|(key, v)| (key.as_str(), v)));
+        }
+        for group in &self.all_groups {
+            required.extend(group.required_values());
+        }
+
+        required
+    }
+
+    /// Why no client can meet the condition, if told by its fields: a field
+    /// required to have two different values, or a value it's required to
+    /// have excluded by `not`, or an `active` window never open.
+    fn contradiction(&self) -> Option<String> {
+        let required = self.required_values();
+        let is_value = |value: &FieldValue| value.regex.is_none() && value.network.is_none();
+        for (index, (key, value)) in required.iter().enumerate() {
+            if !is_value(value) {
+                continue;
+            }
+            let conflicting = required[index + 1..].iter().find(|(other_key, other)| {
+                other_key == key
+                    && is_value(other)
+                    && !other.value.eq_ignore_ascii_case(&value.value)
+            });
+            if let Some((_, other)) = conflicting {
+                return Some(format!("{key} is required to be both {value} and {other}"));
+            }
+            let excluded = self.excluded_values.get(*key).into_iter().flatten();
+            if excluded.into_iter().any(|excluded| excluded.matches(&value.value)) {
+                return Some(format!("{key} {value} is excluded by not"));
+            }
+        }
+
+        let window = self.active.as_ref();
+        let is_closed = window.is_some_and(|window| {
+            window.from.zip(window.until).is_some_and(|(from, until)| from >= until)
+                || window.hours.is_some_and(|(start, end)| start == end)
+        });
+        if is_closed {
+            return Some("the active window never opens".to_string());
+        }
+
+        if let Some(reason) = self.all_groups.iter().find_map(MatchCondition::contradiction) {
+            return Some(reason);
+        }
+        let any_reasons: Vec<String> =
+            self.any_groups.iter().filter_map(MatchCondition::contradiction).collect();
+        match any_reasons.len() {
+            0 => None,
+            len if len == self.any_groups.len() => Some(any_reasons.join(", ")),
+            _ => None,
+        }
+    }
+
+    /// Whether every client meeting `other` meets the condition too, as far
+    /// as can be told from the values `other` requires.
+    fn covers(&self, other: &MatchCondition) -> bool {
+        let is_restricted = !self.excluded_values.is_empty()
+            || !self.any_groups.is_empty()
+            || !self.all_groups.is_empty()
+            || self.active.is_some();
+        if is_restricted {
+            return false;
+        }
+
+        let required = other.required_values();
+        let is_accepted = |(key, value): (&String, &FieldValue)| {
+            required.iter().any(|(other_key, other_value)| {
+                other_key == key
+                    && other_value.regex.is_none()
+                    && other_value.network.is_none()
+                    && value.matches(&other_value.value)
+            })
+        };
+        let mut fields_values = self.fields_values.iter();
+        match self.match_type {
+            _ if self.fields_values.is_empty() => true,
+            MatchType::Any => fields_values.any(is_accepted),
+            MatchType::All => fields_values.all(is_accepted),
+        }
+    }
+
+    /// How specific the match is to the client: the number of fields of
+    /// `select` matching it, those of the groups included.
+    fn specificity(&self, doc: &dyn ClientFields) -> usize {
+        let matched = self
+            .fields_values
+            .iter()
+            .filter(|(key, config_value)| self.is_field_match(doc, key, config_value))
+            .count();
+        let groups = self.any_groups.iter().chain(&self.all_groups);
+
+        matched + groups.map(|group| group.specificity(doc)).sum::<usize>()
+    }
+}
+
+/// The fields of a client the `match` rules select, by their names in
+/// `select`.
+pub trait ClientFields {
+    /// The value of the field `name` as it's matched, `None` when the client
+    /// doesn't tell it.
+    fn field(&self, name: &str) -> Option<Cow<'_, str>>;
+
+    /// MAC address of the client, from its `chaddr`.
+    fn mac(&self) -> Option<MacAddress>;
+}
+
+impl<T: ClientFields + ?Sized> ClientFields for &T {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        (**self).field(name)
+    }
+
+    fn mac(&self) -> Option<MacAddress> {
+        (**self).mac()
+    }
+}
+
+/// A client known by the JSON document of its DHCP message, as posted to
+/// `webhook_url`, or of what it told over HTTP or TFTP.
+impl ClientFields for serde_json::Value {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        let value = self.get(Conf::get_remapped_key(name)).or(self
+            .get("opts")
+            .and_then(|opts| opts.get(name))
+            .and_then(|opts_key| opts_key.get(name)))?;
+        Some(converted(name, value).into())
+    }
+
+    fn mac(&self) -> Option<MacAddress> {
+        mac_from_doc(self)
+    }
+}
+
+/// The value of the field `name` of a JSON document as it's matched.
+fn converted(name: &str, value: &serde_json::Value) -> String {
+    // Strings as they are, numbers and the others as JSON
+    let default_converter: FieldConverter = |v: &serde_json::Value| -> Result<String> {
+        Ok(v.as_str().map(str::to_string).unwrap_or(v.to_string()))
+    };
+    let converter = FIELD_CONVERTERS.get(name).unwrap_or(&default_converter);
+    converter(value).unwrap_or(value.to_string())
+}
+
+/// Options by their names in the JSON documents of the DHCP messages.
+static OPTION_CODES: Lazy<HashMap<String, OptionCode>> = Lazy::new(|| {
+    (0..=u8::MAX)
+        .map(OptionCode::from)
+        .filter_map(|code| Some((serde_json::to_value(code).ok()?.as_str()?.to_string(), code)))
+        .collect()
+});
+
+/// A client known by its DHCP message as decoded, its fields read from it
+/// rather than from its JSON document, with those the server tells of it,
+/// such as the `Interface` it came in on.
+pub struct MessageFields<'a> {
+    message: &'a Message,
+    added: Vec<(&'static str, String)>,
+}
+
+impl<'a> MessageFields<'a> {
+    pub fn new(message: &'a Message) -> Self {
+        Self {
+            message,
+            added: Vec::new(),
+        }
+    }
+
+    /// Adds the field `name`, that the message doesn't tell.
+    pub fn with(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.added.retain(|(added, _)| *added != name);
+        self.added.push((name, value.into()));
+        self
+    }
+
+    /// The JSON document of the message with the fields added, as posted to
+    /// `webhook_url` and given to `boot_hook`.
+    pub fn to_doc(&self) -> serde_json::Value {
+        let mut doc = serde_json::to_value(self.message).unwrap_or_default();
+        if let Some(doc) = doc.as_object_mut() {
+            for (name, value) in &self.added {
+                doc.insert(name.to_string(), value.as_str().into());
+            }
+        }
+        doc
+    }
+
+    /// The field `name` of the header of the message.
+    fn header_field(&self, name: &str) -> Option<Cow<'_, str>> {
+        let message = self.message;
+        let value = match name {
+            "chaddr" => return self.mac().map(|mac| bytes_to_mac_address(&mac).into()),
+            "ciaddr" => message.ciaddr().to_string(),
+            "yiaddr" => message.yiaddr().to_string(),
+            "siaddr" => message.siaddr().to_string(),
+            "giaddr" => message.giaddr().to_string(),
+            "hlen" => message.hlen().to_string(),
+            "hops" => message.hops().to_string(),
+            "xid" => message.xid().to_string(),
+            "secs" => message.secs().to_string(),
+            // The rarely matched ones as in the JSON document
+            "htype" => converted(name, &serde_json::to_value(message.htype()).ok()?),
+            "opcode" => converted(name, &serde_json::to_value(message.opcode()).ok()?),
+            "flags" => converted(name, &serde_json::to_value(message.flags()).ok()?),
+            "sname" => converted(name, &serde_json::to_value(message.sname()).ok()?),
+            "fname" => converted(name, &serde_json::to_value(message.fname()).ok()?),
+            _ => return None,
+        };
+        Some(value.into())
+    }
+
+    /// The option `name` of the message.
+    fn option_field(&self, name: &str) -> Option<Cow<'_, str>> {
+        let option = self.message.opts().get(*OPTION_CODES.get(name)?)?;
+        let text = |bytes: &[u8]| bytes.iter().copied().map(char::from).collect::<String>();
+        let value = match option {
+            DhcpOption::ClassIdentifier(bytes) | DhcpOption::UserClass(bytes) => text(bytes),
+            DhcpOption::ClientSystemArchitecture(arch) => arch_name(u16::from(*arch)),
+            DhcpOption::ClientIdentifier(bytes) => {
+                bytes.iter().map(|byte| format!("{byte:02x}")).collect::<Vec<_>>().join(":")
+            }
+            DhcpOption::Hostname(name) => return Some(name.into()),
+            DhcpOption::RequestedIpAddress(ip) | DhcpOption::ServerIdentifier(ip) => ip.to_string(),
+            DhcpOption::MaxMessageSize(size) => size.to_string(),
+            // The rarely matched ones as in the JSON document
+            option => converted(name, serde_json::to_value(option).ok()?.get(name)?),
+        };
+        Some(value.into())
+    }
+}
+
+impl ClientFields for MessageFields<'_> {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        if let Some((_, value)) = self.added.iter().find(|(added, _)| *added == name) {
+            return Some(value.into());
+        }
+        self.header_field(Conf::get_remapped_key(name))
+            .or_else(|| self.option_field(name))
+    }
+
+    fn mac(&self) -> Option<MacAddress> {
+        self.message.chaddr().get(..6)?.try_into().ok()
+    }
+}
+
+/// How long the `match` entry used for a client is remembered.
+const DECISION_TTL: Duration = Duration::from_secs(60);
+/// Clients whose `match` entry is remembered at most.
+const MAX_DECISIONS: usize = 10_000;
+
+/// The `match` entries used for the clients lately, by the values of the
+/// fields the entries read, so the machines booting again and again aren't
+/// matched against all of them for each of their messages. Not used when
+/// an entry is `active` at some times only.
+#[derive(Debug, Default)]
+struct DecisionCache {
+    /// Fields the entries read, none when they depend on the time.
+    fields: OnceLock<Option<Vec<String>>>,
+    decisions: Mutex<HashMap<String, (Option<usize>, Instant)>>,
+}
+
+/// A copy of the configuration may have other entries, it starts afresh.
+impl Clone for DecisionCache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl DecisionCache {
+    /// What tells the decisions for the client of `doc` apart with the
+    /// `entries`, none when they can't be remembered.
+    fn key(&self, entries: &[MatchEntry], doc: &dyn ClientFields) -> Option<String> {
+        let fields = self.fields.get_or_init(|| {
+            let mut fields = BTreeSet::new();
+            let untimed = entries
+                .iter()
+                .fold(true, |untimed, entry| entry.condition.read_fields(&mut fields) & untimed);
+            // Those of `hosts` are told by their MAC address
+            if entries.iter().any(|entry| entry.hosts.is_some()) {
+                fields.insert("chaddr");
+            }
+            untimed.then(|| fields.into_iter().map(str::to_string).collect())
+        });
+        let values: Vec<Option<Cow<str>>> =
+            fields.as_ref()?.iter().map(|field| doc.field(field)).collect();
+        Some(serde_json::to_string(&values).unwrap_or_default())
+    }
+
+    fn get(&self, key: &str, now: Instant) -> Option<Option<usize>> {
+        let decisions = self.decisions.lock().unwrap_or_else(|e| e.into_inner());
+        let (decision, decided) = decisions.get(key)?;
+        (now.duration_since(*decided) < DECISION_TTL).then_some(*decision)
+    }
+
+    fn insert(&self, key: String, decision: Option<usize>, now: Instant) {
+        let mut decisions = self.decisions.lock().unwrap_or_else(|e| e.into_inner());
+        if decisions.len() >= MAX_DECISIONS {
+            decisions.retain(|_, (_, decided)| now.duration_since(*decided) < DECISION_TTL);
+            if decisions.len() >= MAX_DECISIONS {
+                decisions.clear();
+            }
+        }
+        decisions.insert(key, (decision, now));
+    }
+}
+
+#[derive(Clone, Debug)]
+struct MatchEntry {
+    condition: MatchCondition,
+    conf: ConfEntry,
+    /// Entries of higher priority are used over the others matching.
+    priority: i64,
+    /// Hosts of the `hosts` file, the only clients matched when given, with
+    /// their configuration completed by `conf`.
+    hosts: Option<HostList>,
+    canary: Option<Canary>,
+}
+
+/// Share of the clients of a match entry given another configuration, e.g.
+/// a new installer image rolled out to some of the machines first.
+#[derive(Clone, Debug)]
+struct Canary {
+    /// Percentage of the clients given `conf`, chosen by a hash of their MAC
+    /// address so a client always gets the same configuration.
+    percent: u8,
+    /// Mixed into the hash, for another rollout to pick other clients.
+    seed: String,
+    /// Configuration of the clients of the canary, completed by the one of
+    /// the entry.
+    conf: ConfEntry,
+}
+
+impl Canary {
+    fn includes(&self, doc: &dyn ClientFields) -> bool {
+        doc.mac().is_some_and(|mac| canary_bucket(&self.seed, &mac) < self.percent)
+    }
+}
+
+/// Bucket of the client with the MAC address `mac`, from 0 to 99, clients
+/// of buckets below the `percent` of a canary being in it. The same on every
+/// release and platform.
+pub fn canary_bucket(seed: &str, mac: &MacAddress) -> u8 {
+    let digest = Sha256::new().chain_update(seed).chain_update(mac).finalize();
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(value) % 100) as u8
+}
+
+impl MatchEntry {
+    fn matches(&self, doc: &dyn ClientFields, now: OffsetDateTime) -> bool {
+        self.condition.matches(doc, now)
+            && self.hosts.as_ref().is_none_or(|hosts| {
+                doc.mac().and_then(|mac| hosts.get(&mac)).is_some()
+            })
+    }
+
+    /// Configuration of the client of `doc`: the one of its row of `hosts`
+    /// when given, else of the `canary` when it's in it.
+    fn conf_for(&self, doc: &dyn ClientFields) -> &ConfEntry {
+        let canary = || {
+            let canary = self.canary.as_ref().filter(|canary| canary.includes(doc))?;
+            trace!("Client in the canary of {}%.", canary.percent);
+            Some(&canary.conf)
+        };
+
+        self.hosts
+            .as_ref()
+            .zip(doc.mac())
+            .and_then(|(hosts, mac)| hosts.get(&mac))
+            .or_else(canary)
+            .unwrap_or(&self.conf)
+    }
+
+    /// How specific the entry is to the client, its row of `hosts` counting
+    /// as a field.
+    fn specificity(&self, doc: &dyn ClientFields) -> usize {
+        self.condition.specificity(doc) + usize::from(self.hosts.is_some())
+    }
+
+    /// `conf` and those of the `hosts` and the `canary`.
+    fn confs(&self) -> impl Iterator<Item = &ConfEntry> {
+        std::iter::once(&self.conf)
+            .chain(self.hosts.iter().flat_map(HostList::entries))
+            .chain(self.canary.iter().map(|canary| &canary.conf))
+    }
+}
+
+pub const DEFAULT_MAX_SESSIONS: u64 = 500;
+pub const DEFAULT_DHCP_MAX_HANDLERS: u64 = 1024;
+/// Size in MB a capture file of `pcap_file` is rotated at.
+pub const DEFAULT_PCAP_MAX_SIZE: u64 = 10;
+pub const DEFAULT_PCAP_FILES: u64 = 5;
+/// Size in MB an upload is refused above.
+pub const DEFAULT_UPLOAD_MAX_SIZE: u64 = 4096;
+/// Size in MB the `audit_log` is rotated at.
+pub const DEFAULT_AUDIT_LOG_MAX_SIZE: u64 = 10;
+pub const DEFAULT_AUDIT_LOG_FILES: u64 = 5;
+/// Size in MB the `traffic_log` is rotated at.
+pub const DEFAULT_TRAFFIC_LOG_MAX_SIZE: u64 = 10;
+pub const DEFAULT_TRAFFIC_LOG_FILES: u64 = 5;
+/// Size in MB the `event_log` is rotated at.
+pub const DEFAULT_EVENT_LOG_MAX_SIZE: u64 = 10;
+pub const DEFAULT_EVENT_LOG_FILES: u64 = 5;
+pub const DEFAULT_TFTP_MAX_TRANSFERS: u64 = 500;
+pub const DEFAULT_TFTP_MAX_TRANSFERS_PER_CLIENT: u64 = 8;
+pub const DEFAULT_SNMP_COMMUNITY: &str = "public";
+/// Block sizes a client may negotiate, RFC 2348.
+pub const TFTP_BLOCK_SIZE_RANGE: std::ops::RangeInclusive<u16> = 8..=65464;
+/// Versions of each image kept, the one served included.
+pub const DEFAULT_IMAGE_VERSIONS: u64 = 3;
+/// Milliseconds the webhook is waited for, short enough for DHCP clients
+/// not to give up on the offer.
+pub const DEFAULT_WEBHOOK_TIMEOUT_MS: u64 = 2000;
+/// Longest `reply_delay_ms`, DHCP clients retransmitting their requests
+/// after about 4 seconds.
+pub const MAX_REPLY_DELAY_MS: u64 = 4000;
+/// Seconds the hosts looked up in the inventory are cached for.
+pub const DEFAULT_INVENTORY_CACHE_TTL_SECS: u64 = 300;
+pub const DEFAULT_CONTROL_SOCKET: &str = "/run/preboot-oxide.sock";
+pub const DEFAULT_TFTP_DENIED_EXTENSIONS: [&str; 5] = ["key", "pem", "p12", "pfx", "env"];
+pub const CONFIG_FOLDER: &str = "preboot-oxide";
+/// Names of the configuration file looked up in `CONFIG_FOLDER`, the first
+/// one found being loaded.
+pub const CONFIG_FILENAMES: [&str; 3] =
+    ["preboot-oxide.yaml", "preboot-oxide.toml", "preboot-oxide.json"];
+pub const ENV_VAR_PREFIX: &str = "PO_";
+/// Fields of the configuration `tenants` set for themselves.
+pub const TENANT_FIELDS: [&str; 8] = [
+    "default",
+    "defaults",
+    "profiles",
+    "match",
+    "match_policy",
+    "require_match",
+    "ifaces",
+    "tftp_server_dir",
+];
+/// Names of the processor architectures of option 93, usable as values of
+/// `ClientSystemArchitecture` in `select`.
+pub const DHCP_ARCHES: phf::Map<&'static str, u16> = phf_map! {
+    "x86" => 0x0,
+    "itanium" => 0x2,
+    "x86-uefi" => 0x6,
+    "x64-uefi" => 0x7,
+    "arm32-uefi" => 0x0a,
+    "arm64-uefi" => 0x0b,
+    "arm32-uboot" => 0x15,
+    "arm64-uboot" => 0x16,
+    "arm32-rpiboot" => 0x29,
+    "riscv32-uefi" => 0x19,
+    "riscv64-uefi" => 0x1b,
+    "riscv128-uefi" => 0x1d
+};
+// source: https://www.iana.org/assignments/dhcpv6-parameters/dhcpv6-parameters.xhtml#processor-architecture
+
+pub const FIELD_MAP: phf::Map<&'static str, &'static str> = phf_map! {
+    "ClientMacAddress" => "chaddr",
+    "HardwareType" => "htype",
+    "Giaddr" => "giaddr",
+};
+/// Readable names of the fields of `select` and `not`, for the names of the
+/// options as serialized by dhcproto.
+pub const FIELD_ALIASES: phf::Map<&'static str, &'static str> = phf_map! {
+    "MacAddress" => "ClientMacAddress",
+    "VendorClass" => "ClassIdentifier",
+    "VendorClassIdentifier" => "ClassIdentifier",
+    "ClientArch" => "ClientSystemArchitecture",
+    "Arch" => "ClientSystemArchitecture",
+    "RequestedIp" => "RequestedIpAddress",
+    "ServerId" => "ServerIdentifier",
+    "ClientId" => "ClientIdentifier",
+    "MaxDhcpMessageSize" => "MaxMessageSize",
+    "DhcpMessageType" => "MessageType",
+    "HostName" => "Hostname",
+    "RelayAgent" => "Giaddr",
+};
+/// Fields of `select` holding networks the addresses of the clients are
+/// matched to, unless matched by `regex` or `glob`.
+const NETWORK_FIELDS: [&str; 2] = ["Subnet", "Giaddr"];
+static FIELD_CONVERTERS: FieldConverterMap = Lazy::new(|| {
+    let string: FieldConverter = |input: &serde_json::Value| -> Result<String> {
+        input
+            .as_str()
+            .map(|s| s.to_string())
+            .ok_or(anyhow!("Expected a string."))
+    };
+    // Options carrying bytes, such as the text of the vendor and user classes
+    let text: FieldConverter = |input: &serde_json::Value| -> Result<String> {
+        input
+            .as_array()
+            .map(|arr| {
+                Ok(arr
+                    .iter()
+                    .map(|item| Ok(char::try_from(item.as_u64().unwrap_or(0) as u32)?))
+                    .collect::<Result<Vec<char>>>()?
+                    .iter()
+                    .collect::<String>())
+            })
+            .unwrap_or(Ok(String::default()))
+    };
+
+    HashMap::from([
+        ("Uuid", string),
+        ("Serial", string),
+        ("Subnet", string),
+        ("Interface", string),
+        ("Stage", string),
+        ("ReverseHostname", string),
+        ("Giaddr", string),
+        (
+            "ClientSystemArchitecture",
+            |input: &serde_json::Value| -> Result<String> {
+                let arch = serde_json::from_value::<Architecture>(input.clone())?;
+                Ok(arch_name(u16::from(arch)))
+            },
+        ),
+        (
+            "ClientMacAddress",
+            (|input: &serde_json::Value| Conf::get_mac_from_doc_string(input)) as FieldConverter,
+        ),
+        ("ClassIdentifier", text),
+        ("UserClass", text),
+        (
+            "ClientIdentifier",
+            |input: &serde_json::Value| -> Result<String> {
+                let bytes = input.as_array().ok_or(anyhow!("Expected an array."))?;
+                Ok(bytes
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte.as_u64().unwrap_or(0)))
+                    .collect::<Vec<String>>()
+                    .join(":"))
+            },
+        ),
+        (
+            "ClientMachineIdentifier",
+            |input: &serde_json::Value| -> Result<String> {
+                input
+                    .as_object()
+                    .and_then(|dict| dict.get("ClientMachineIdentifier"))
+                    .and_then(|value| value.as_array())
+                    .map(|arr| {
+                        Ok(arr
+                            .iter()
+                            .map(|item| Ok(char::try_from(item.as_u64().unwrap_or(0) as u32)?.to_string()))
+                            .collect::<Result<Vec<String>>>()?
+                            .join("."))
+                    })
+                    .unwrap_or(Ok(String::default()))
+            },
+        ),
+    ])
 });
 
-pub struct ProcessEnvConf {
-    conf: ConfEntry,
-    ifaces: Option<Vec<String>>,
-    tftp_server_dir: Option<String>,
-    max_sessions: Option<u64>,
-}
+/// Quotas of the mapping `yaml`, by key.
+fn quotas_from_yaml(yaml: &Yaml) -> Result<Vec<(String, u64)>> {
+    yaml.as_hash()
+        .into_iter()
+        .flatten()
+        .map(|(key, quota)| {
+            let key = key.as_str().ok_or(anyhow!("Expected string keys"))?;
+            let quota = quota
+                .as_i64()
+                .and_then(|quota| u64::try_from(quota).ok())
+                .ok_or(anyhow!("Expected a number of sessions for {key}"))?;
+            Ok((key.to_string(), quota))
+        })
+        .collect()
+}
+
+/// Whether `value` references a secret, as `file:<path>` or `env:<variable>`.
+fn is_secret_ref(value: &str) -> bool {
+    value.starts_with("file:") || value.starts_with("env:")
+}
+
+/// Value of a sensitive field, read from the file or environment variable
+/// it references, without the line break ending the file, or as given.
+fn resolve_secret(value: &str) -> Result<String> {
+    if let Some(path) = value.strip_prefix("file:") {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Reading the secret file {path}: {e}"))?;
+        Ok(content.trim_end_matches(['\r', '\n']).to_string())
+    } else if let Some(var) = value.strip_prefix("env:") {
+        std::env::var(var).map_err(|_| anyhow!("Secret environment variable {var} not set"))
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+/// Secret of the prefixed environment variable `name`, ignored with a
+/// warning when the one it references can't be read.
+fn secret_var(name: &str) -> Option<String> {
+    let value = std::env::var(format!("{ENV_VAR_PREFIX}{name}")).ok()?;
+    resolve_secret(&value)
+        .inspect_err(|e| warn!("Ignoring {ENV_VAR_PREFIX}{name}: {e}"))
+        .ok()
+}
+
+/// Whether `mask` is a subnet mask, its bits set followed by those unset.
+fn is_subnet_mask(mask: Ipv4Addr) -> bool {
+    u32::from(mask).leading_ones() + u32::from(mask).trailing_zeros() == 32
+}
+
+/// Name of the field `key` of `select` or `not`, given as is or by one of
+/// `FIELD_ALIASES`.
+fn field_name(key: &str) -> &str {
+    FIELD_ALIASES.get(key).copied().unwrap_or(key)
+}
+
+/// Name of the architecture `code` of option 93, or the code when unnamed.
+pub(crate) fn arch_name(code: u16) -> String {
+    DHCP_ARCHES
+        .entries()
+        .find(|(_, arch)| **arch == code)
+        .map(|(name, _)| name.to_string())
+        .unwrap_or_else(|| code.to_string())
+}
+
+/// Architecture of `select` as matched to the clients: one of `DHCP_ARCHES`,
+/// or the code of option 93 in decimal or hexadecimal (`0x7`).
+fn parse_arch(value: &str) -> Result<String> {
+    let value = value.trim().to_ascii_lowercase();
+    if DHCP_ARCHES.contains_key(value.as_str()) {
+        return Ok(value);
+    }
+
+    let code = match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    code.map(arch_name).map_err(|_| {
+        let names: Vec<&str> = DHCP_ARCHES.keys().copied().collect();
+        anyhow!("Unknown architecture {value}, expected a code or one of {}", names.join(", "))
+    })
+}
+
+pub struct ProcessEnvConf {
+    conf: ConfEntry,
+    ifaces: Option<Vec<String>>,
+    tftp_server_dir: Option<String>,
+    tftp_symlinks: Option<SymlinkPolicy>,
+    match_policy: Option<MatchPolicy>,
+    tftp_max_transfers: Option<u64>,
+    tftp_max_transfers_per_client: Option<u64>,
+    tftp_serve_hidden: Option<bool>,
+    tftp_allowed_extensions: Option<Vec<String>>,
+    tftp_denied_extensions: Option<Vec<String>>,
+    tftp_fallbacks: Option<Vec<TftpFallback>>,
+    tftp_block_size_limit: Option<u16>,
+    tftp_upstream: Option<SocketAddr>,
+    http_port: Option<u16>,
+    https_port: Option<u16>,
+    https_cert: Option<PathBuf>,
+    https_key: Option<PathBuf>,
+    autoinstall_dir: Option<PathBuf>,
+    netbootxyz: Option<bool>,
+    image_versions: Option<u64>,
+    secure_boot: Option<bool>,
+    upload_token: Option<String>,
+    signing_cert: Option<PathBuf>,
+    signing_key: Option<PathBuf>,
+    image_refresh_interval: Option<u64>,
+    upload_users: Option<BTreeMap<String, String>>,
+    upload_max_size: Option<u64>,
+    https_client_ca: Option<PathBuf>,
+    boot_hostname: Option<String>,
+    dns_port: Option<u16>,
+    reverse_dns: Option<bool>,
+    reverse_dns_server: Option<SocketAddr>,
+    ipxe_chainload: Option<bool>,
+    webhook_url: Option<String>,
+    webhook_timeout: Option<u64>,
+    boot_hook: Option<PathBuf>,
+    event_hooks: Option<BTreeMap<Event, PathBuf>>,
+    event_webhooks: Option<Vec<EventWebhook>>,
+    mqtt_broker: Option<MqttBroker>,
+    mqtt_topic: Option<String>,
+    mqtt_username: Option<String>,
+    mqtt_password: Option<String>,
+    mqtt_retain: Option<bool>,
+    netbox_url: Option<String>,
+    netbox_token: Option<String>,
+    inventory_cache_ttl: Option<u64>,
+    control_socket: Option<PathBuf>,
+    dashboard_addr: Option<SocketAddr>,
+    api_addr: Option<SocketAddr>,
+    grpc_addr: Option<SocketAddr>,
+    health_addr: Option<SocketAddr>,
+    api_token: Option<String>,
+    snmp_addr: Option<SocketAddr>,
+    snmp_community: Option<String>,
+    log_output: Option<LogOutput>,
+    syslog_server: Option<SyslogServer>,
+    pcap_file: Option<PathBuf>,
+    pcap_max_size: Option<u64>,
+    pcap_files: Option<u64>,
+    pcap_tftp: Option<bool>,
+    audit_log: Option<PathBuf>,
+    audit_log_max_size: Option<u64>,
+    audit_log_files: Option<u64>,
+    traffic_log: Option<PathBuf>,
+    traffic_log_max_size: Option<u64>,
+    traffic_log_files: Option<u64>,
+    event_log: Option<PathBuf>,
+    event_log_max_size: Option<u64>,
+    event_log_files: Option<u64>,
+    stats_file: Option<PathBuf>,
+    assets_file: Option<PathBuf>,
+    user: Option<String>,
+    group: Option<String>,
+    sandbox: Option<bool>,
+    strict: Option<bool>,
+    require_match: Option<bool>,
+    max_sessions: Option<u64>,
+    max_sessions_per_iface: Option<BTreeMap<String, u64>>,
+    max_sessions_per_subnet: Option<Vec<(Network, u64)>>,
+    dhcp_max_handlers: Option<u64>,
+    pxe_boot_server: Option<bool>,
+    pxe_boot_server_workers: Option<u64>,
+    dhcp_enabled: Option<bool>,
+    tftp_enabled: Option<bool>,
+}
+
+impl ProcessEnvConf {
+    pub fn from_process_env() -> ProcessEnvConf {
+        let boot_server_ipv4: Option<Ipv4Addr> =
+            std::env::var(format!("{ENV_VAR_PREFIX}TFTP_SERVER_IPV4"))
+                .unwrap_or_default()
+                .parse()
+                .ok();
+        let boot_file = std::env::var(format!("{ENV_VAR_PREFIX}BOOT_FILE")).ok();
+        let ipxe_script = std::env::var(format!("{ENV_VAR_PREFIX}IPXE_SCRIPT")).ok();
+        let grub_cfg = std::env::var(format!("{ENV_VAR_PREFIX}GRUB_CFG")).ok();
+        let menu_label = std::env::var(format!("{ENV_VAR_PREFIX}MENU_LABEL")).ok();
+        let subnet_mask = std::env::var(format!("{ENV_VAR_PREFIX}SUBNET_MASK"))
+            .ok()
+            .and_then(|mask| mask.parse().ok());
+        let lease_time = std::env::var(format!("{ENV_VAR_PREFIX}LEASE_TIME"))
+            .ok()
+            .and_then(|secs| secs.parse().ok());
+        let reply_delay_ms = std::env::var(format!("{ENV_VAR_PREFIX}REPLY_DELAY_MS"))
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .filter(|ms| *ms <= MAX_REPLY_DELAY_MS);
+        let ignition = std::env::var(format!("{ENV_VAR_PREFIX}IGNITION")).ok();
+        let boot_iso = std::env::var(format!("{ENV_VAR_PREFIX}BOOT_ISO"))
+            .ok()
+            .map(PathBuf::from)
+            .filter(|path| is_boot_iso_path(path));
+        let windows = std::env::var(format!("{ENV_VAR_PREFIX}WINDOWS"))
+            .ok()
+            .map(PathBuf::from)
+            .filter(|path| is_relative_path(path));
+        let tftp_server_dir = std::env::var(format!("{ENV_VAR_PREFIX}TFTP_SERVER_DIR_PATH")).ok();
+        let tftp_symlinks = std::env::var(format!("{ENV_VAR_PREFIX}TFTP_SYMLINKS"))
+            .map(|s| s.parse::<SymlinkPolicy>().ok())
+            .ok()
+            .flatten();
+        let match_policy = std::env::var(format!("{ENV_VAR_PREFIX}MATCH_POLICY"))
+            .map(|s| s.parse::<MatchPolicy>().ok())
+            .ok()
+            .flatten();
+        let tftp_max_transfers = std::env::var(format!("{ENV_VAR_PREFIX}TFTP_MAX_TRANSFERS"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        let tftp_max_transfers_per_client =
+            std::env::var(format!("{ENV_VAR_PREFIX}TFTP_MAX_TRANSFERS_PER_CLIENT"))
+                .map(|s| s.parse::<u64>().ok())
+                .ok()
+                .flatten();
+        let tftp_serve_hidden = std::env::var(format!("{ENV_VAR_PREFIX}TFTP_SERVE_HIDDEN"))
+            .map(|s| s.parse::<bool>().ok())
+            .ok()
+            .flatten();
+        let tftp_allowed_extensions =
+            std::env::var(format!("{ENV_VAR_PREFIX}TFTP_ALLOWED_EXTENSIONS"))
+                .ok()
+                .map(|csv| csv.split(",").map(|s| s.to_string()).collect());
+        let tftp_denied_extensions =
+            std::env::var(format!("{ENV_VAR_PREFIX}TFTP_DENIED_EXTENSIONS"))
+                .ok()
+                .map(|csv| csv.split(",").map(|s| s.to_string()).collect());
+        let tftp_fallbacks = std::env::var(format!("{ENV_VAR_PREFIX}TFTP_FALLBACKS"))
+            .ok()
+            .and_then(|csv| {
+                csv.split(",")
+                    .map(TftpFallback::from_str)
+                    .collect::<Result<Vec<TftpFallback>>>()
+                    .ok()
+            });
+        let tftp_block_size_limit =
+            std::env::var(format!("{ENV_VAR_PREFIX}TFTP_BLOCK_SIZE_LIMIT"))
+                .map(|s| s.parse::<u16>().ok())
+                .ok()
+                .flatten();
+        let tftp_upstream = std::env::var(format!("{ENV_VAR_PREFIX}TFTP_UPSTREAM"))
+            .map(|s| parse_tftp_upstream(&s).ok())
+            .ok()
+            .flatten();
+        let http_port = std::env::var(format!("{ENV_VAR_PREFIX}HTTP_PORT"))
+            .map(|s| s.parse::<u16>().ok())
+            .ok()
+            .flatten();
+        let https_port = std::env::var(format!("{ENV_VAR_PREFIX}HTTPS_PORT"))
+            .map(|s| s.parse::<u16>().ok())
+            .ok()
+            .flatten();
+        let https_cert = std::env::var(format!("{ENV_VAR_PREFIX}HTTPS_CERT"))
+            .ok()
+            .map(PathBuf::from);
+        let https_key = std::env::var(format!("{ENV_VAR_PREFIX}HTTPS_KEY"))
+            .ok()
+            .map(PathBuf::from);
+        let autoinstall_dir = std::env::var(format!("{ENV_VAR_PREFIX}AUTOINSTALL_DIR"))
+            .ok()
+            .map(PathBuf::from);
+        let netbootxyz = std::env::var(format!("{ENV_VAR_PREFIX}NETBOOTXYZ"))
+            .map(|s| s.parse::<bool>().ok())
+            .ok()
+            .flatten();
+        let image_versions = std::env::var(format!("{ENV_VAR_PREFIX}IMAGE_VERSIONS"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        let secure_boot = std::env::var(format!("{ENV_VAR_PREFIX}SECURE_BOOT"))
+            .map(|s| s.parse::<bool>().ok())
+            .ok()
+            .flatten();
+        let upload_token = secret_var("UPLOAD_TOKEN");
+        let signing_cert = std::env::var(format!("{ENV_VAR_PREFIX}SIGNING_CERT"))
+            .map(PathBuf::from)
+            .ok();
+        let signing_key = std::env::var(format!("{ENV_VAR_PREFIX}SIGNING_KEY"))
+            .map(PathBuf::from)
+            .ok();
+        let image_refresh_interval =
+            std::env::var(format!("{ENV_VAR_PREFIX}IMAGE_REFRESH_INTERVAL"))
+                .map(|s| s.parse::<u64>().ok())
+                .ok()
+                .flatten();
+        let upload_users = std::env::var(format!("{ENV_VAR_PREFIX}UPLOAD_USERS"))
+            .map(|csv| {
+                csv.split(',')
+                    .filter_map(|user| user.split_once(':'))
+                    .filter_map(|(user, password)| {
+                        let password = resolve_secret(password)
+                            .inspect_err(|e| warn!("Ignoring the password of {user}: {e}"))
+                            .ok()?;
+                        Some((user.trim().to_string(), password))
+                    })
+                    .collect()
+            })
+            .ok();
+        let upload_max_size = std::env::var(format!("{ENV_VAR_PREFIX}UPLOAD_MAX_SIZE"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        let https_client_ca = std::env::var(format!("{ENV_VAR_PREFIX}HTTPS_CLIENT_CA"))
+            .map(PathBuf::from)
+            .ok();
+        let boot_hostname = std::env::var(format!("{ENV_VAR_PREFIX}BOOT_HOSTNAME")).ok();
+        let dns_port = std::env::var(format!("{ENV_VAR_PREFIX}DNS_PORT"))
+            .map(|s| s.parse::<u16>().ok())
+            .ok()
+            .flatten();
+        let reverse_dns = std::env::var(format!("{ENV_VAR_PREFIX}REVERSE_DNS"))
+            .map(|s| s.parse::<bool>().ok())
+            .ok()
+            .flatten();
+        let reverse_dns_server = std::env::var(format!("{ENV_VAR_PREFIX}REVERSE_DNS_SERVER"))
+            .map(|s| parse_dns_server(&s).ok())
+            .ok()
+            .flatten();
+        let ipxe_chainload = std::env::var(format!("{ENV_VAR_PREFIX}IPXE_CHAINLOAD"))
+            .map(|s| s.parse::<bool>().ok())
+            .ok()
+            .flatten();
+        let webhook_url = secret_var("WEBHOOK_URL");
+        let webhook_timeout = std::env::var(format!("{ENV_VAR_PREFIX}WEBHOOK_TIMEOUT"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        let boot_hook = std::env::var(format!("{ENV_VAR_PREFIX}BOOT_HOOK"))
+            .map(PathBuf::from)
+            .ok();
+        let event_hooks = std::env::var(format!("{ENV_VAR_PREFIX}EVENT_HOOKS"))
+            .ok()
+            .and_then(|csv| csv.split(",").map(parse_event_hook).collect::<Result<_>>().ok());
+        let event_webhooks = secret_var("EVENT_WEBHOOKS").map(|csv| {
+            csv.split(',')
+                .map(|url| EventWebhook { url: url.trim().to_string(), events: Vec::new() })
+                .collect()
+        });
+        let mqtt_broker = std::env::var(format!("{ENV_VAR_PREFIX}MQTT_BROKER"))
+            .map(|s| s.parse::<MqttBroker>().ok())
+            .ok()
+            .flatten();
+        let mqtt_topic = std::env::var(format!("{ENV_VAR_PREFIX}MQTT_TOPIC")).ok();
+        let mqtt_username = std::env::var(format!("{ENV_VAR_PREFIX}MQTT_USERNAME")).ok();
+        let mqtt_password = secret_var("MQTT_PASSWORD");
+        let mqtt_retain = std::env::var(format!("{ENV_VAR_PREFIX}MQTT_RETAIN"))
+            .map(|s| s.parse::<bool>().ok())
+            .ok()
+            .flatten();
+        let netbox_url = std::env::var(format!("{ENV_VAR_PREFIX}NETBOX_URL")).ok();
+        let netbox_token = secret_var("NETBOX_TOKEN");
+        let inventory_cache_ttl = std::env::var(format!("{ENV_VAR_PREFIX}INVENTORY_CACHE_TTL"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        let control_socket = std::env::var(format!("{ENV_VAR_PREFIX}CONTROL_SOCKET"))
+            .map(PathBuf::from)
+            .ok();
+        let dashboard_addr = std::env::var(format!("{ENV_VAR_PREFIX}DASHBOARD_ADDR"))
+            .map(|s| parse_listen_addr(&s).ok())
+            .ok()
+            .flatten();
+        let api_addr = std::env::var(format!("{ENV_VAR_PREFIX}API_ADDR"))
+            .map(|s| parse_listen_addr(&s).ok())
+            .ok()
+            .flatten();
+        let grpc_addr = std::env::var(format!("{ENV_VAR_PREFIX}GRPC_ADDR"))
+            .map(|s| parse_listen_addr(&s).ok())
+            .ok()
+            .flatten();
+        let health_addr = std::env::var(format!("{ENV_VAR_PREFIX}HEALTH_ADDR"))
+            .map(|s| parse_listen_addr(&s).ok())
+            .ok()
+            .flatten();
+        let api_token = secret_var("API_TOKEN");
+        let snmp_addr = std::env::var(format!("{ENV_VAR_PREFIX}SNMP_ADDR"))
+            .map(|s| parse_listen_addr(&s).ok())
+            .ok()
+            .flatten();
+        let snmp_community = secret_var("SNMP_COMMUNITY");
+        let log_output = std::env::var(format!("{ENV_VAR_PREFIX}LOG_OUTPUT"))
+            .map(|s| s.parse::<LogOutput>().ok())
+            .ok()
+            .flatten();
+        let syslog_server = std::env::var(format!("{ENV_VAR_PREFIX}SYSLOG_SERVER"))
+            .map(|s| s.parse::<SyslogServer>().ok())
+            .ok()
+            .flatten();
+        let pcap_file = std::env::var(format!("{ENV_VAR_PREFIX}PCAP_FILE"))
+            .map(PathBuf::from)
+            .ok();
+        let pcap_max_size = std::env::var(format!("{ENV_VAR_PREFIX}PCAP_MAX_SIZE"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        let pcap_files = std::env::var(format!("{ENV_VAR_PREFIX}PCAP_FILES"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        let pcap_tftp = std::env::var(format!("{ENV_VAR_PREFIX}PCAP_TFTP"))
+            .map(|s| s.parse::<bool>().ok())
+            .ok()
+            .flatten();
+        let audit_log = std::env::var(format!("{ENV_VAR_PREFIX}AUDIT_LOG"))
+            .map(PathBuf::from)
+            .ok();
+        let audit_log_max_size = std::env::var(format!("{ENV_VAR_PREFIX}AUDIT_LOG_MAX_SIZE"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        let audit_log_files = std::env::var(format!("{ENV_VAR_PREFIX}AUDIT_LOG_FILES"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        let traffic_log = std::env::var(format!("{ENV_VAR_PREFIX}TRAFFIC_LOG"))
+            .map(PathBuf::from)
+            .ok();
+        let traffic_log_max_size = std::env::var(format!("{ENV_VAR_PREFIX}TRAFFIC_LOG_MAX_SIZE"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        let traffic_log_files = std::env::var(format!("{ENV_VAR_PREFIX}TRAFFIC_LOG_FILES"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        let event_log = std::env::var(format!("{ENV_VAR_PREFIX}EVENT_LOG"))
+            .map(PathBuf::from)
+            .ok();
+        let event_log_max_size = std::env::var(format!("{ENV_VAR_PREFIX}EVENT_LOG_MAX_SIZE"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        let event_log_files = std::env::var(format!("{ENV_VAR_PREFIX}EVENT_LOG_FILES"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        let stats_file = std::env::var(format!("{ENV_VAR_PREFIX}STATS_FILE"))
+            .map(PathBuf::from)
+            .ok();
+        let assets_file = std::env::var(format!("{ENV_VAR_PREFIX}ASSETS_FILE"))
+            .map(PathBuf::from)
+            .ok();
+        let user = std::env::var(format!("{ENV_VAR_PREFIX}USER")).ok();
+        let group = std::env::var(format!("{ENV_VAR_PREFIX}GROUP")).ok();
+        let sandbox = std::env::var(format!("{ENV_VAR_PREFIX}SANDBOX"))
+            .map(|s| s.parse::<bool>().ok())
+            .ok()
+            .flatten();
+        let strict = std::env::var(format!("{ENV_VAR_PREFIX}STRICT"))
+            .map(|s| s.parse::<bool>().ok())
+            .ok()
+            .flatten();
+        let require_match = std::env::var(format!("{ENV_VAR_PREFIX}REQUIRE_MATCH"))
+            .map(|s| s.parse::<bool>().ok())
+            .ok()
+            .flatten();
+        let pxe_boot_server = std::env::var(format!("{ENV_VAR_PREFIX}PXE_BOOT_SERVER"))
+            .map(|s| s.parse::<bool>().ok())
+            .ok()
+            .flatten();
+        let dhcp_enabled = std::env::var(format!("{ENV_VAR_PREFIX}DHCP_ENABLED"))
+            .map(|s| s.parse::<bool>().ok())
+            .ok()
+            .flatten();
+        let tftp_enabled = std::env::var(format!("{ENV_VAR_PREFIX}TFTP_ENABLED"))
+            .map(|s| s.parse::<bool>().ok())
+            .ok()
+            .flatten();
+        let ifaces_csv = std::env::var(format!("{ENV_VAR_PREFIX}IFACES")).ok();
+        let ifaces = ifaces_csv.map(|csv| csv.split(",").map(|s| s.to_string()).collect());
+        let max_sessions = std::env::var(format!("{ENV_VAR_PREFIX}MAX_SESSIONS"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        // Comma separated `<key>:<quota>` pairs
+        let quotas = |name: &str| -> Option<Vec<(String, u64)>> {
+            let csv = std::env::var(format!("{ENV_VAR_PREFIX}{name}")).ok()?;
+            let quotas = csv
+                .split(',')
+                .filter_map(|pair| pair.rsplit_once(':'))
+                .filter_map(|(key, quota)| {
+                    Some((key.trim().to_string(), quota.trim().parse().ok()?))
+                })
+                .collect();
+            Some(quotas)
+        };
+        let max_sessions_per_iface =
+            quotas("MAX_SESSIONS_PER_IFACE").map(|quotas| quotas.into_iter().collect());
+        let max_sessions_per_subnet = quotas("MAX_SESSIONS_PER_SUBNET").map(|quotas| {
+            quotas
+                .into_iter()
+                .filter_map(|(network, quota)| Some((network.parse().ok()?, quota)))
+                .collect()
+        });
+        let dhcp_max_handlers = std::env::var(format!("{ENV_VAR_PREFIX}DHCP_MAX_HANDLERS"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+        let pxe_boot_server_workers = std::env::var(format!("{ENV_VAR_PREFIX}PXE_BOOT_SERVER_WORKERS"))
+            .map(|s| s.parse::<u64>().ok())
+            .ok()
+            .flatten();
+
+        Self {
+            conf: ConfEntry {
+                boot_server_ipv4,
+                boot_file,
+                ipxe_script,
+                grub_cfg,
+                ignition,
+                boot_iso,
+                windows,
+                menu_label,
+                subnet_mask,
+                lease_time,
+                reply_delay_ms,
+                log_level: None,
+                vars: Default::default(),
+                pinned_images: Default::default(),
+                profile: None,
+            },
+            tftp_server_dir,
+            tftp_symlinks,
+            match_policy,
+            tftp_max_transfers,
+            tftp_max_transfers_per_client,
+            tftp_serve_hidden,
+            tftp_allowed_extensions,
+            tftp_denied_extensions,
+            tftp_fallbacks,
+            tftp_block_size_limit,
+            tftp_upstream,
+            http_port,
+            https_port,
+            https_cert,
+            https_key,
+            ifaces,
+            autoinstall_dir,
+            netbootxyz,
+            image_versions,
+            secure_boot,
+            upload_token,
+            signing_cert,
+            signing_key,
+            image_refresh_interval,
+            upload_users,
+            upload_max_size,
+            https_client_ca,
+            boot_hostname,
+            dns_port,
+            reverse_dns,
+            reverse_dns_server,
+            ipxe_chainload,
+            webhook_url,
+            webhook_timeout,
+            boot_hook,
+            event_hooks,
+            event_webhooks,
+            mqtt_broker,
+            mqtt_topic,
+            mqtt_username,
+            mqtt_password,
+            mqtt_retain,
+            netbox_url,
+            netbox_token,
+            inventory_cache_ttl,
+            control_socket,
+            dashboard_addr,
+            api_addr,
+            grpc_addr,
+            health_addr,
+            api_token,
+            snmp_addr,
+            snmp_community,
+            log_output,
+            syslog_server,
+            pcap_file,
+            pcap_max_size,
+            pcap_files,
+            pcap_tftp,
+            audit_log,
+            audit_log_max_size,
+            audit_log_files,
+            traffic_log,
+            traffic_log_max_size,
+            traffic_log_files,
+            event_log,
+            event_log_max_size,
+            event_log_files,
+            stats_file,
+            assets_file,
+            user,
+            group,
+            sandbox,
+            strict,
+            require_match,
+            max_sessions,
+            max_sessions_per_iface,
+            max_sessions_per_subnet,
+            dhcp_max_handlers,
+            pxe_boot_server,
+            pxe_boot_server_workers,
+            dhcp_enabled,
+            tftp_enabled,
+        }
+    }
+}
+
+impl From<ProcessEnvConf> for Conf {
+    fn from(env_conf: ProcessEnvConf) -> Self {
+        let mut conf = Self {
+            default: None,
+            ifaces: None,
+            max_sessions: env_conf.max_sessions.unwrap_or(DEFAULT_MAX_SESSIONS),
+            max_sessions_per_iface: env_conf.max_sessions_per_iface.unwrap_or_default(),
+            max_sessions_per_subnet: env_conf.max_sessions_per_subnet.unwrap_or_default(),
+            dhcp_max_handlers: env_conf.dhcp_max_handlers.unwrap_or(DEFAULT_DHCP_MAX_HANDLERS),
+            pxe_boot_server: env_conf.pxe_boot_server.unwrap_or_default(),
+            pxe_boot_server_workers: env_conf.pxe_boot_server_workers.unwrap_or(1),
+            dhcp_enabled: env_conf.dhcp_enabled.unwrap_or(true),
+            tftp_enabled: env_conf.tftp_enabled.unwrap_or(true),
+            match_map: None,
+            decisions: Default::default(),
+            iface_defaults: BTreeMap::new(),
+            profiles: BTreeMap::new(),
+            tenants: BTreeMap::new(),
+            secret_refs: BTreeMap::new(),
+            match_policy: env_conf.match_policy.unwrap_or_default(),
+            require_match: env_conf.require_match.unwrap_or_default(),
+            tftp_server_dir: None,
+            tftp_symlinks: env_conf.tftp_symlinks.unwrap_or_default(),
+            tftp_max_transfers: env_conf
+                .tftp_max_transfers
+                .unwrap_or(DEFAULT_TFTP_MAX_TRANSFERS),
+            tftp_max_transfers_per_client: env_conf
+                .tftp_max_transfers_per_client
+                .unwrap_or(DEFAULT_TFTP_MAX_TRANSFERS_PER_CLIENT),
+            tftp_file_filter: FileFilter::default(),
+            tftp_fallbacks: env_conf.tftp_fallbacks.unwrap_or_default(),
+            tftp_block_size_limit: env_conf.tftp_block_size_limit,
+            tftp_upstream: env_conf.tftp_upstream,
+            http_port: env_conf.http_port,
+            https_port: env_conf.https_port,
+            https_cert: env_conf.https_cert,
+            https_key: env_conf.https_key,
+            autoinstall_dir: env_conf.autoinstall_dir,
+            netbootxyz: env_conf.netbootxyz.unwrap_or_default(),
+            images: Vec::new(),
+            mirrors: Vec::new(),
+            image_versions: env_conf.image_versions.unwrap_or(DEFAULT_IMAGE_VERSIONS),
+            secure_boot: env_conf.secure_boot.unwrap_or_default(),
+            upload_token: env_conf.upload_token,
+            signing_cert: env_conf.signing_cert,
+            signing_key: env_conf.signing_key,
+            image_refresh_interval: env_conf.image_refresh_interval,
+            upload_users: env_conf.upload_users.unwrap_or_default(),
+            upload_max_size: env_conf.upload_max_size.unwrap_or(DEFAULT_UPLOAD_MAX_SIZE),
+            https_client_ca: env_conf.https_client_ca,
+            boot_hostname: env_conf.boot_hostname,
+            dns_port: env_conf.dns_port,
+            reverse_dns: env_conf.reverse_dns.unwrap_or_default(),
+            reverse_dns_server: env_conf.reverse_dns_server,
+            ipxe_chainload: env_conf.ipxe_chainload.unwrap_or_default(),
+            webhook_url: env_conf.webhook_url,
+            webhook_timeout: env_conf.webhook_timeout,
+            boot_hook: env_conf.boot_hook,
+            event_hooks: env_conf.event_hooks.unwrap_or_default(),
+            event_webhooks: env_conf.event_webhooks.unwrap_or_default(),
+            notifications: Vec::new(),
+            mqtt_broker: env_conf.mqtt_broker,
+            mqtt_topic: env_conf.mqtt_topic,
+            mqtt_username: env_conf.mqtt_username,
+            mqtt_password: env_conf.mqtt_password,
+            mqtt_retain: env_conf.mqtt_retain.unwrap_or_default(),
+            netbox_url: env_conf.netbox_url,
+            netbox_token: env_conf.netbox_token,
+            inventory_cache_ttl: env_conf.inventory_cache_ttl,
+            control_socket: env_conf.control_socket,
+            dashboard_addr: env_conf.dashboard_addr,
+            api_addr: env_conf.api_addr,
+            grpc_addr: env_conf.grpc_addr,
+            health_addr: env_conf.health_addr,
+            api_token: env_conf.api_token,
+            snmp_addr: env_conf.snmp_addr,
+            snmp_community: env_conf.snmp_community,
+            log_output: env_conf.log_output.unwrap_or_default(),
+            syslog_server: env_conf.syslog_server,
+            pcap_file: env_conf.pcap_file,
+            pcap_max_size: env_conf.pcap_max_size.unwrap_or(DEFAULT_PCAP_MAX_SIZE),
+            pcap_files: env_conf.pcap_files.unwrap_or(DEFAULT_PCAP_FILES),
+            pcap_tftp: env_conf.pcap_tftp.unwrap_or_default(),
+            audit_log: env_conf.audit_log,
+            audit_log_max_size: env_conf.audit_log_max_size.unwrap_or(DEFAULT_AUDIT_LOG_MAX_SIZE),
+            audit_log_files: env_conf.audit_log_files.unwrap_or(DEFAULT_AUDIT_LOG_FILES),
+            traffic_log: env_conf.traffic_log,
+            traffic_log_max_size: env_conf.traffic_log_max_size.unwrap_or(DEFAULT_TRAFFIC_LOG_MAX_SIZE),
+            traffic_log_files: env_conf.traffic_log_files.unwrap_or(DEFAULT_TRAFFIC_LOG_FILES),
+            event_log: env_conf.event_log,
+            event_log_max_size: env_conf.event_log_max_size.unwrap_or(DEFAULT_EVENT_LOG_MAX_SIZE),
+            event_log_files: env_conf.event_log_files.unwrap_or(DEFAULT_EVENT_LOG_FILES),
+            stats_file: env_conf.stats_file,
+            assets_file: env_conf.assets_file,
+            user: env_conf.user,
+            group: env_conf.group,
+            sandbox: env_conf.sandbox.unwrap_or_default(),
+            instance_name: None,
+            strict: env_conf.strict.unwrap_or_default(),
+        };
+
+        if let Some(serve_hidden) = env_conf.tftp_serve_hidden {
+            conf.tftp_file_filter.serve_hidden = serve_hidden;
+        }
+        if let Some(allowed_extensions) = env_conf.tftp_allowed_extensions {
+            conf.tftp_file_filter.allowed_extensions = Some(allowed_extensions);
+        }
+        if let Some(denied_extensions) = env_conf.tftp_denied_extensions {
+            conf.tftp_file_filter.denied_extensions = denied_extensions;
+        }
+
+        conf.merge_left_into_default(&env_conf.conf);
+        conf.ifaces = env_conf.ifaces;
+        conf.tftp_server_dir = env_conf.tftp_server_dir;
+
+        conf
+    }
+}
+
+impl Conf {
+    pub fn validate(&self) -> Result<()> {
+        // Root keeps the rights a group would take away
+        if self.group.is_some() && self.user.is_none() {
+            return Err(anyhow!("group needs user to be configured."));
+        }
+        if let Some(name) = self.instance_name.as_deref().filter(|name| !Self::is_valid_instance_name(name)) {
+            return Err(anyhow!("The instance name {name:?} can only have letters, digits, '-' and '_'."));
+        }
+        if self.api_addr.is_some() && self.api_token.is_none() {
+            return Err(anyhow!("api_addr needs api_token to be configured."));
+        }
+        if self.grpc_addr.is_some() && self.api_token.is_none() {
+            return Err(anyhow!("grpc_addr needs api_token to be configured."));
+        }
+        if cfg!(not(feature = "grpc")) && self.grpc_addr.is_some() {
+            return Err(anyhow!("grpc_addr needs preboot-oxide built with the grpc feature."));
+        }
+        if self.syslog_server.is_some() && self.log_output != LogOutput::Syslog {
+            return Err(anyhow!("syslog_server needs log_output to be syslog."));
+        }
+        if cfg!(not(unix)) && self.log_output == LogOutput::Syslog && self.syslog_server.is_none() {
+            return Err(anyhow!("log_output syslog needs syslog_server on this system."));
+        }
+        if cfg!(not(target_os = "linux")) && self.log_output == LogOutput::Journald {
+            return Err(anyhow!("log_output journald is only supported on Linux."));
+        }
+        if self.pcap_max_size == 0 || self.pcap_files == 0 {
+            return Err(anyhow!("pcap_max_size and pcap_files must be at least 1."));
+        }
+        if self.pcap_tftp && self.pcap_file.is_none() {
+            return Err(anyhow!("pcap_tftp needs pcap_file to be configured."));
+        }
+        if self.audit_log_max_size == 0 || self.audit_log_files == 0 {
+            return Err(anyhow!("audit_log_max_size and audit_log_files must be at least 1."));
+        }
+        if self.traffic_log_max_size == 0 || self.traffic_log_files == 0 {
+            return Err(anyhow!("traffic_log_max_size and traffic_log_files must be at least 1."));
+        }
+        if self.event_log_max_size == 0 || self.event_log_files == 0 {
+            return Err(anyhow!("event_log_max_size and event_log_files must be at least 1."));
+        }
+        if !self.tenants.is_empty() {
+            self.validate_tenants()?;
+            if self.ifaces.is_none() {
+                return Ok(());
+            }
+        }
+
+        let has_external_tftp_server = self.any_entry(|e| e.boot_server_ipv4.is_some());
+        let has_tftp_path = self.tftp_server_dir.is_some();
+        let has_boot_filename = self.any_entry(|e| e.boot_file.is_some());
+
+        if !self.dhcp_enabled && !has_tftp_path {
+            return Err(anyhow!("tftp_server_dir needs to be configured with dhcp disabled."));
+        }
+
+        // The clients would be sent to the TFTP servers that aren't started
+        if self.dhcp_enabled && !self.tftp_enabled {
+            let served_here = self.named_entries().into_iter().find(|(_, entry)| {
+                entry.boot_server_ipv4.is_none()
+                    && entry.boot_file.is_some_and(|file| !file.contains("://"))
+            });
+            if let Some((name, _)) = served_here {
+                return Err(anyhow!("{name} needs boot_server_ipv4 with tftp disabled."));
+            }
+        }
+
+        if self.dhcp_enabled && !has_external_tftp_server && !has_tftp_path {
+            return Err(anyhow!(
+                "No TFTP server path or external TFTP server configured."
+            ));
+        }
+
+        if !self.images.is_empty() && !has_tftp_path {
+            return Err(anyhow!("images need tftp_server_dir to be configured."));
+        }
+
+        let has_http = self.http_port.is_some() || self.https_port.is_some();
+        if !self.mirrors.is_empty() && (!has_tftp_path || !has_http) {
+            return Err(anyhow!(
+                "mirrors need tftp_server_dir and http_port or https_port to be configured."
+            ));
+        }
+
+        if self.any_entry(|e| e.boot_iso.is_some()) && !has_tftp_path {
+            return Err(anyhow!("boot_iso needs tftp_server_dir to be configured."));
+        }
+
+        if self.any_entry(|e| e.ignition.is_some()) && self.autoinstall_dir.is_none() {
+            return Err(anyhow!("ignition needs autoinstall_dir to be configured."));
+        }
+
+        if self.has_windows_profile() && !has_tftp_path {
+            return Err(anyhow!("windows needs tftp_server_dir to be configured."));
+        }
+
+        if self.image_versions == 0 {
+            return Err(anyhow!("image_versions needs to be at least 1."));
+        }
+        if self.image_refresh_interval == Some(0) {
+            return Err(anyhow!("image_refresh_interval needs to be at least 1 second."));
+        }
+        if self.dhcp_max_handlers == 0 {
+            return Err(anyhow!("dhcp_max_handlers needs to be at least 1."));
+        }
+        if self.pxe_boot_server_workers == 0 {
+            return Err(anyhow!("pxe_boot_server_workers needs to be at least 1."));
+        }
+
+        if self.netbootxyz && !has_tftp_path {
+            return Err(anyhow!("netbootxyz needs tftp_server_dir to be configured."));
+        }
+
+        if self.secure_boot && (!has_tftp_path || self.http_port.is_none()) {
+            return Err(anyhow!(
+                "secure_boot needs tftp_server_dir and http_port to be configured."
+            ));
+        }
+
+        if self.ipxe_chainload && (!has_tftp_path || self.http_port.is_none()) {
+            return Err(anyhow!(
+                "ipxe_chainload needs tftp_server_dir and http_port to be configured."
+            ));
+        }
+
+        let has_boot_config = has_boot_filename || self.netbootxyz || self.secure_boot;
+        if self.dhcp_enabled && !has_boot_config && !self.ipxe_chainload {
+            return Err(anyhow!("No boot filename configured."));
+        }
+
+        if self.https_port.is_some() && self.https_port == self.http_port {
+            return Err(anyhow!("http_port and https_port need to be different."));
+        }
+
+        if let Some(hostname) = &self.boot_hostname {
+            if !is_hostname(hostname) {
+                return Err(anyhow!("Invalid boot_hostname {hostname}"));
+            }
+        }
+
+        if let Some(hook) = &self.boot_hook {
+            if !hook.is_file() {
+                return Err(anyhow!("boot_hook {} not found", hook.display()));
+            }
+            crate::hook::compile(hook).context("Invalid boot_hook")?;
+        }
+
+        for (event, hook) in &self.event_hooks {
+            if !hook.is_file() {
+                return Err(anyhow!("The {event} hook {} not found", hook.display()));
+            }
+        }
+
+        for webhook in &self.event_webhooks {
+            if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+                let url = redact_url(&webhook.url);
+                return Err(anyhow!("Invalid event_webhooks URL {url}, expected an http(s) URL"));
+            }
+        }
+
+        for (index, notification) in self.notifications.iter().enumerate() {
+            let is_smtp = notification.kind == NotificationKind::Smtp;
+            if is_smtp {
+                notification.url.parse::<SmtpServer>()?;
+                if notification.from.is_none() || notification.to.is_empty() {
+                    return Err(anyhow!("The smtp notifications[{index}] needs from and to to be configured."));
+                }
+            } else {
+                if !notification.url.starts_with("http://") && !notification.url.starts_with("https://") {
+                    let url = redact_url(&notification.url);
+                    return Err(anyhow!("Invalid notifications[{index}] URL {url}, expected an http(s) URL"));
+                }
+                if notification.from.is_some() || !notification.to.is_empty() {
+                    return Err(anyhow!("from and to of notifications[{index}] are for the smtp kind only."));
+                }
+            }
+            let addresses = notification.from.iter().chain(&notification.to);
+            for address in addresses {
+                let is_address = address.contains('@')
+                    && !address.contains(|c: char| c.is_whitespace() || c.is_control() || c == '<' || c == '>');
+                if !is_address {
+                    return Err(anyhow!("Invalid mail address {address} in notifications[{index}]"));
+                }
+            }
+        }
+
+        let has_mqtt_fields = self.mqtt_topic.is_some() || self.mqtt_username.is_some() || self.mqtt_password.is_some();
+        if self.mqtt_broker.is_none() && has_mqtt_fields {
+            return Err(anyhow!("mqtt_topic, mqtt_username and mqtt_password need mqtt_broker to be configured."));
+        }
+        if self.mqtt_password.is_some() && self.mqtt_username.is_none() {
+            return Err(anyhow!("mqtt_password needs mqtt_username to be configured."));
+        }
+        if let Some(topic) = &self.mqtt_topic {
+            if topic.is_empty() || topic.contains(['+', '#']) {
+                return Err(anyhow!("Invalid mqtt_topic {topic}, expected a topic without wildcards"));
+            }
+        }
+
+        if let Some(url) = &self.webhook_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                let url = redact_url(url);
+                return Err(anyhow!("Invalid webhook_url {url}, expected an http(s) URL"));
+            }
+        }
+
+        if let Some(url) = &self.netbox_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                let url = redact_url(url);
+                return Err(anyhow!("Invalid netbox_url {url}, expected an http(s) URL"));
+            }
+        }
+
+        if self.https_client_ca.is_some() && self.https_port.is_none() {
+            return Err(anyhow!("https_client_ca needs https_port to be configured."));
+        }
+        if self.upload_max_size == 0 {
+            return Err(anyhow!("upload_max_size needs to be at least 1."));
+        }
+
+        if let Some(block_size) = self.tftp_block_size_limit {
+            if !TFTP_BLOCK_SIZE_RANGE.contains(&block_size) {
+                return Err(anyhow!(
+                    "TFTP block size limit {block_size} is outside of the valid range {}-{}.",
+                    TFTP_BLOCK_SIZE_RANGE.start(),
+                    TFTP_BLOCK_SIZE_RANGE.end()
+                ));
+            }
+        }
+
+        let missing_boot_files = self.missing_boot_files();
+        if self.strict && !missing_boot_files.is_empty() {
+            return Err(anyhow!("{}", missing_boot_files.join("; ")));
+        }
+        for missing in missing_boot_files {
+            warn!("{missing}");
+        }
+        for warning in self.lint_match_rules() {
+            warn!("{warning}");
+        }
+        if let Some(ifaces) = &self.ifaces {
+            for iface in self.max_sessions_per_iface.keys().filter(|i| !ifaces.contains(i)) {
+                warn!("max_sessions_per_iface has a quota for {iface}, which isn't in ifaces.");
+            }
+        }
+        let is_answered_elsewhere =
+            self.webhook_url.is_some() || self.boot_hook.is_some() || self.netbox_url.is_some();
+        let has_match_rules = self.match_map.iter().flatten().next().is_some();
+        if self.require_match && !has_match_rules && !is_answered_elsewhere {
+            warn!("require_match is set without match rules, no client will be answered.");
+        }
+
+        Ok(())
+    }
+
+    /// Checks the configuration of each of the `tenants`, and that they're
+    /// served on interfaces of their own.
+    fn validate_tenants(&self) -> Result<()> {
+        let mut owners: HashMap<&String, String> = HashMap::new();
+        for iface in self.ifaces.iter().flatten() {
+            owners.insert(iface, "the top level configuration".to_string());
+        }
+        for (name, tenant) in &self.tenants {
+            tenant.validate().map_err(|e| anyhow!("{e}, in tenant {name}"))?;
+            let ifaces = tenant
+                .ifaces
+                .as_ref()
+                .filter(|ifaces| !ifaces.is_empty())
+                .ok_or(anyhow!("Tenant {name} needs ifaces to be configured."))?;
+            for iface in ifaces {
+                if let Some(owner) = owners.insert(iface, format!("tenant {name}")) {
+                    bail!("Interface {iface} of tenant {name} is also served by {owner}.");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The entries of `default`, `defaults` and `match`, those of `match`
+    /// completed with `default`, named by where they're configured.
+    fn named_entries(&self) -> Vec<(String, ConfEntryRef<'_>)> {
+        let default = self.default.as_ref();
+        default
+            .map(|entry| ("default".to_string(), entry.merge_refs(None)))
+            .into_iter()
+            .chain(
+                self.iface_defaults
+                    .iter()
+                    .map(|(iface, entry)| (format!("defaults.{iface}"), entry.merge_refs(None))),
+            )
+            .chain(
+                self.match_map.iter().flatten().enumerate().flat_map(|(index, m)| {
+                    m.confs()
+                        .map(move |conf| (format!("match[{index}]"), conf.merge_refs(default)))
+                }),
+            )
+            .collect()
+    }
+
+    /// The `boot_file`s served by the TFTP service missing from its root, as
+    /// errors telling where they're configured. Those of the image store and
+    /// the mirrors are fetched later on, and with `tftp_upstream` missing
+    /// files are served by the upstream server, so these aren't checked.
+    fn missing_boot_files(&self) -> Vec<String> {
+        let Some(tftp_dir) = self.tftp_server_dir.as_ref().map(PathBuf::from) else {
+            return Vec::new();
+        };
+        if self.tftp_upstream.is_some() {
+            return Vec::new();
+        }
+
+        let is_fetched = |file: &Path| {
+            file.starts_with(IMAGES_DIR) || self.mirrors.iter().any(|m| file.starts_with(&m.path))
+        };
+
+        self.named_entries()
+            .into_iter()
+            .filter(|(_, entry)| entry.boot_server_ipv4.is_none())
+            .filter_map(|(name, entry)| Some((name, entry.boot_file?)))
+            .filter(|(_, file)| {
+                let file = Path::new(file.trim_start_matches('/'));
+                !is_fetched(file) && !tftp_dir.join(file).is_file()
+            })
+            .map(|(name, file)| {
+                format!("boot_file {file} of {name} not found in {}", tftp_dir.display())
+            })
+            // Once for the rows of `hosts` booting the same file
+            .fold(Vec::new(), |mut missing, error| {
+                if !missing.contains(&error) {
+                    missing.push(error);
+                }
+                missing
+            })
+    }
+
+    /// Mistakes of the `match` entries, found before confused clients do:
+    /// entries that can never match, their conditions contradicting
+    /// themselves, and entries shadowed by an earlier or higher priority one
+    /// matching all of their clients. Only what can be told from the
+    /// configuration is reported, e.g. overlapping patterns aren't.
+    pub fn lint_match_rules(&self) -> Vec<String> {
+        let entries: Vec<&MatchEntry> = self.match_map.iter().flatten().collect();
+        let mut warnings = Vec::new();
+        for (index, entry) in entries.iter().enumerate() {
+            if let Some(reason) = entry.condition.contradiction() {
+                warnings.push(format!("match[{index}] can never match: {reason}"));
+                continue;
+            }
+
+            // Only a higher priority wins over more specific entries
+            let shadows = |(other_index, other): &(usize, &&MatchEntry)| {
+                let wins = match self.match_policy {
+                    MatchPolicy::First => {
+                        other.priority > entry.priority
+                            || (other.priority == entry.priority && *other_index < index)
+                    }
+                    MatchPolicy::MostSpecific => other.priority > entry.priority,
+                };
+                *other_index != index
+                    && wins
+                    && other.hosts.is_none()
+                    && other.condition.covers(&entry.condition)
+            };
+            if let Some((other_index, _)) = entries.iter().enumerate().find(shadows) {
+                warnings.push(format!(
+                    "match[{index}] is never used: match[{other_index}] is used for all of \
+                     its clients"
+                ));
+            }
+        }
+
+        warnings
+    }
+
+    /// Path of the configuration file, the override or the first of
+    /// `CONFIG_FILENAMES` found in the default location, the YAML one when
+    /// none is.
+    pub fn config_path(path_override: Option<&PathBuf>) -> PathBuf {
+        path_override.map(PathBuf::from).unwrap_or_else(|| {
+            let candidates = Self::config_candidates();
+            candidates
+                .iter()
+                .find(|path| path.is_file())
+                .unwrap_or(&candidates[0])
+                .clone()
+        })
+    }
+
+    /// The files of `CONFIG_FILENAMES` in the default location, in the order
+    /// they're looked for.
+    pub fn config_candidates() -> Vec<PathBuf> {
+        let dir = dirs::config_local_dir()
+            .map(|config_path| config_path.join(CONFIG_FOLDER))
+            .unwrap_or_default();
+        CONFIG_FILENAMES.iter().map(|filename| dir.join(filename)).collect()
+    }
+
+    /// Loads the configuration file, in the format told by its extension,
+    /// and merges the files of its include directory into it.
+    pub fn from_config_file(path_override: Option<&PathBuf>) -> Result<Self> {
+        Self::load_file(&Self::config_path(path_override), &Overrides::default())
+    }
+
+    fn load_file(path: &Path, overrides: &Overrides) -> Result<Self> {
+        let format = ConfFormat::of(path);
+        let mut conf = Self::from_file(path, format, overrides)
+            .map_err(|e| anyhow!("{e}, from {format} file: {}", path.display()))?;
+        info!("Loaded configuration from {format} file {}", path.display());
+        conf.merge_includes(&Self::include_dir(path))?;
+        conf.merge_profiles()?;
+        conf.merge_default_into_iface_defaults();
+
+        Ok(conf)
+    }
+
+    /// Directory of the files merged into the configuration file `path`, e.g.
+    /// `preboot-oxide.d/` next to `preboot-oxide.yaml`.
+    pub fn include_dir(path: &Path) -> PathBuf {
+        path.with_extension("d")
+    }
+
+    /// Loads the configuration fetched from a remote source, which has no
+    /// include directory.
+    pub fn from_remote(source: &ConfSource) -> Result<Self> {
+        Self::load_remote(source, &Overrides::default())
+    }
+
+    fn load_remote(source: &ConfSource, overrides: &Overrides) -> Result<Self> {
+        let content = source.fetch()?;
+        let format = source.format();
+        let mut conf = Self::from_content(&content, format, overrides)
+            .map_err(|e| anyhow!("{e}, from {format} configuration at {source}"))?;
+        info!("Loaded configuration from {format} configuration at {source}");
+        conf.merge_profiles()?;
+        conf.merge_default_into_iface_defaults();
+
+        Ok(conf)
+    }
+
+    /// The configuration of `content`, in `format`, as given by a program
+    /// embedding the server rather than read from a file or the environment.
+    pub fn from_text(content: &str, format: ConfFormat) -> Result<Self> {
+        let mut conf = Self::from_content(content, format, &Overrides::default())?;
+        conf.merge_profiles()?;
+        conf.merge_default_into_iface_defaults();
+
+        Ok(conf)
+    }
+
+    /// Loads the configuration from where `PO_CONF_PATH` tells, a file or a
+    /// remote source.
+    pub fn from_source(source: &ConfSource) -> Result<Self> {
+        Self::from_source_with(source, &Overrides::default())
+    }
+
+    /// Loads the configuration from `source`, with the values of `--set`
+    /// replacing its own.
+    pub fn from_source_with(source: &ConfSource, overrides: &Overrides) -> Result<Self> {
+        match source {
+            ConfSource::File(path) => Self::load_file(path, overrides),
+            _ => Self::load_remote(source, overrides),
+        }
+    }
+
+    /// The configuration of the environment variables, when there's no
+    /// configuration file, with the `match` entries of `PO_MATCH_JSON`.
+    pub fn from_env() -> Result<Self> {
+        let mut conf = Self::from(ProcessEnvConf::from_process_env());
+        let var = format!("{ENV_VAR_PREFIX}MATCH_JSON");
+        if let Ok(json) = std::env::var(&var) {
+            let match_map = serde_json::from_str(&json)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| {
+                    let document = yaml_mapping([("match", json_to_yaml(json))]);
+                    schema::check(&document, false, None)?;
+                    Self::match_map_from_yaml(&document["match"])
+                })
+                .with_context(|| format!("Parsing {var}."))?;
+            conf.match_map = match_map;
+            conf.merge_profiles()?;
+        }
+
+        Ok(conf)
+    }
+
+    /// The configuration made of the values of `--set` alone, when there's
+    /// no configuration file.
+    pub fn from_overrides(overrides: &Overrides) -> Result<Self> {
+        let mut conf = Self::from_content("", ConfFormat::Yaml, overrides)?;
+        info!("Loaded configuration from --set {overrides}");
+        conf.merge_profiles()?;
+        conf.merge_default_into_iface_defaults();
+
+        Ok(conf)
+    }
+
+    fn from_file(path: &Path, format: ConfFormat, overrides: &Overrides) -> Result<Self> {
+        let mut file = std::fs::File::open(path)?;
+        let mut buf = String::new();
+        file.read_to_string(&mut buf)?;
+
+        Self::from_content(&buf, format, overrides)
+    }
+
+    fn from_content(buf: &str, format: ConfFormat, overrides: &Overrides) -> Result<Self> {
+        // The fields are read the same way whatever the format
+        let mut yaml_conf = format.load(buf)?;
+        let version = migrate::version_of(&yaml_conf)?;
+        for change in migrate::migrate(&mut yaml_conf, MIGRATIONS, CONF_VERSION)? {
+            warn!(
+                "{change}, the configuration being of version {version}, run `preboot-oxide \
+                 migrate` to update it."
+            );
+        }
+        overrides.apply(&mut yaml_conf)?;
+        schema::check(&yaml_conf, false, (format == ConfFormat::Yaml).then_some(buf))?;
+
+        let mut conf = Self::from_yaml_conf(&yaml_conf)?;
+        conf.tenants = Self::tenants_from_yaml(&yaml_conf)?;
+
+        Ok(conf)
+    }
+
+    /// The configurations of `tenants`, each the top level one with the
+    /// fields set by the tenant replaced.
+    fn tenants_from_yaml(yaml_conf: &Yaml) -> Result<BTreeMap<String, Conf>> {
+        let Some(tenants) = yaml_conf["tenants"].as_hash() else {
+            return Ok(BTreeMap::new());
+        };
+        let mut top_level = yaml_conf.as_hash().cloned().unwrap_or_default();
+        top_level.remove(&Yaml::from_str("tenants"));
+
+        tenants
+            .iter()
+            .map(|(name, tenant)| {
+                let name = name
+                    .as_str()
+                    .ok_or(anyhow!("Expected tenant names as keys in tenants"))?;
+                let mut yaml = top_level.clone();
+                for (key, value) in tenant.as_hash().into_iter().flatten() {
+                    yaml.insert(key.clone(), value.clone());
+                }
+                let conf = Self::from_yaml_conf(&Yaml::Hash(yaml))
+                    .map_err(|e| anyhow!("{e}, in tenant {name}"))?;
+                Ok((name.to_string(), conf))
+            })
+            .collect()
+    }
+
+    fn from_yaml_conf(yaml_conf: &Yaml) -> Result<Self> {
+        let default: Option<ConfEntry> = Conf::base_conf_from_yaml(&yaml_conf["default"])?;
+        let tftp_server_dir: Option<String> = yaml_conf["tftp_server_dir"]
+            .as_str()
+            .map(|s| s.to_string());
+        let tftp_symlinks = yaml_conf["tftp_symlinks"]
+            .as_str()
+            .map(SymlinkPolicy::from_str)
+            .transpose()
+            .context("Parsing tftp_symlinks from the configuration file.")?
+            .unwrap_or_default();
+        let ifaces: Option<Vec<String>> = yaml_conf["ifaces"].as_vec().map(|v| {
+            v.iter()
+                .filter_map(|i| i.as_str().map(|s| s.to_string()))
+                .collect()
+        });
+        let max_sessions = yaml_conf["max_sessions"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(DEFAULT_MAX_SESSIONS))
+            .context("Parsing max_sessions from the configuration file.")?;
+        let max_sessions_per_iface = quotas_from_yaml(&yaml_conf["max_sessions_per_iface"])
+            .context("Parsing max_sessions_per_iface from the configuration file.")?
+            .into_iter()
+            .collect();
+        let max_sessions_per_subnet = quotas_from_yaml(&yaml_conf["max_sessions_per_subnet"])
+            .and_then(|quotas| {
+                quotas
+                    .into_iter()
+                    .map(|(network, quota)| Ok((network.parse()?, quota)))
+                    .collect::<Result<Vec<(Network, u64)>>>()
+            })
+            .context("Parsing max_sessions_per_subnet from the configuration file.")?;
+        let dhcp_max_handlers = yaml_conf["dhcp_max_handlers"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(DEFAULT_DHCP_MAX_HANDLERS))
+            .context("Parsing dhcp_max_handlers from the configuration file.")?;
+        let pxe_boot_server = yaml_conf["pxe_boot_server"].as_bool().unwrap_or_default();
+        let pxe_boot_server_workers = yaml_conf["pxe_boot_server_workers"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(1))
+            .context("Parsing pxe_boot_server_workers from the configuration file.")?;
+        let dhcp_enabled = yaml_conf["dhcp"]["enabled"].as_bool().unwrap_or(true);
+        let tftp_enabled = yaml_conf["tftp"]["enabled"].as_bool().unwrap_or(true);
+        let tftp_max_transfers = yaml_conf["tftp_max_transfers"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(DEFAULT_TFTP_MAX_TRANSFERS))
+            .context("Parsing tftp_max_transfers from the configuration file.")?;
+        let tftp_max_transfers_per_client = yaml_conf["tftp_max_transfers_per_client"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(DEFAULT_TFTP_MAX_TRANSFERS_PER_CLIENT))
+            .context("Parsing tftp_max_transfers_per_client from the configuration file.")?;
+        let tftp_block_size_limit = yaml_conf["tftp_block_size_limit"]
+            .as_i64()
+            .map(u16::try_from)
+            .transpose()
+            .context("Parsing tftp_block_size_limit from the configuration file.")?;
+        let tftp_upstream = yaml_conf["tftp_upstream"]
+            .as_str()
+            .map(parse_tftp_upstream)
+            .transpose()
+            .context("Parsing tftp_upstream from the configuration file.")?;
+        let http_port = yaml_conf["http_port"]
+            .as_i64()
+            .map(u16::try_from)
+            .transpose()
+            .context("Parsing http_port from the configuration file.")?;
+        let https_port = yaml_conf["https_port"]
+            .as_i64()
+            .map(u16::try_from)
+            .transpose()
+            .context("Parsing https_port from the configuration file.")?;
+        let https_cert = yaml_conf["https_cert"].as_str().map(PathBuf::from);
+        let https_key = yaml_conf["https_key"].as_str().map(PathBuf::from);
+        let autoinstall_dir = yaml_conf["autoinstall_dir"].as_str().map(PathBuf::from);
+        let netbootxyz = yaml_conf["netbootxyz"].as_bool().unwrap_or_default();
+        let images = yaml_conf["images"]
+            .as_hash()
+            .map(|yaml_obj| {
+                yaml_obj
+                    .iter()
+                    .map(|(path, source)| ImageSource::from_yaml(path, source))
+                    .collect::<Result<Vec<ImageSource>>>()
+            })
+            .transpose()
+            .context("Parsing images from the configuration file.")?
+            .unwrap_or_default();
+        let mirrors = yaml_conf["mirrors"]
+            .as_hash()
+            .map(|yaml_obj| {
+                yaml_obj
+                    .iter()
+                    .map(|(path, url)| MirrorSource::from_yaml(path, url))
+                    .collect::<Result<Vec<MirrorSource>>>()
+            })
+            .transpose()
+            .context("Parsing mirrors from the configuration file.")?
+            .unwrap_or_default();
+        let image_versions = yaml_conf["image_versions"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(DEFAULT_IMAGE_VERSIONS))
+            .context("Parsing image_versions from the configuration file.")?;
+        let secure_boot = yaml_conf["secure_boot"].as_bool().unwrap_or_default();
+        let mut secret_refs = BTreeMap::new();
+        let mut secret = |name: String, value: &str| -> Result<String> {
+            if is_secret_ref(value) {
+                secret_refs.insert(name.clone(), value.to_string());
+            }
+            resolve_secret(value)
+                .with_context(|| format!("Parsing {name} from the configuration file."))
+        };
+        let upload_token = yaml_conf["upload_token"]
+            .as_str()
+            .map(|token| secret("upload_token".to_string(), token))
+            .transpose()?;
+        let signing_cert = yaml_conf["signing_cert"].as_str().map(PathBuf::from);
+        let signing_key = yaml_conf["signing_key"].as_str().map(PathBuf::from);
+        let image_refresh_interval = yaml_conf["image_refresh_interval"]
+            .as_i64()
+            .map(u64::try_from)
+            .transpose()
+            .context("Parsing image_refresh_interval from the configuration file.")?;
+        let upload_users = yaml_conf["upload_users"]
+            .as_hash()
+            .map(|users| {
+                users
+                    .iter()
+                    .map(|(user, password)| {
+                        let user = user
+                            .as_str()
+                            .ok_or(anyhow!("Expected a string key in upload_users"))?;
+                        let password = password
+                            .as_str()
+                            .ok_or(anyhow!("Expected a string password for {user}"))?;
+                        let password = secret(format!("upload_users.{user}"), password)?;
+                        Ok((user.to_string(), password))
+                    })
+                    .collect::<Result<BTreeMap<String, String>>>()
+            })
+            .transpose()
+            .context("Parsing upload_users from the configuration file.")?
+            .unwrap_or_default();
+        let upload_max_size = yaml_conf["upload_max_size"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(DEFAULT_UPLOAD_MAX_SIZE))
+            .context("Parsing upload_max_size from the configuration file.")?;
+        let https_client_ca = yaml_conf["https_client_ca"].as_str().map(PathBuf::from);
+        let boot_hostname = yaml_conf["boot_hostname"].as_str().map(|s| s.to_string());
+        let dns_port = yaml_conf["dns_port"]
+            .as_i64()
+            .map(u16::try_from)
+            .transpose()
+            .context("Parsing dns_port from the configuration file.")?;
+        let reverse_dns = yaml_conf["reverse_dns"].as_bool().unwrap_or_default();
+        let reverse_dns_server = yaml_conf["reverse_dns_server"]
+            .as_str()
+            .map(parse_dns_server)
+            .transpose()
+            .context("Parsing reverse_dns_server from the configuration file.")?;
+        let ipxe_chainload = yaml_conf["ipxe_chainload"].as_bool().unwrap_or_default();
+        let webhook_url = yaml_conf["webhook_url"]
+            .as_str()
+            .map(|url| secret("webhook_url".to_string(), url))
+            .transpose()?;
+        let webhook_timeout = yaml_conf["webhook_timeout"]
+            .as_i64()
+            .map(u64::try_from)
+            .transpose()
+            .context("Parsing webhook_timeout from the configuration file.")?;
+        let boot_hook = yaml_conf["boot_hook"].as_str().map(PathBuf::from);
+        let event_hooks = yaml_conf["event_hooks"]
+            .as_hash()
+            .map(|hooks| {
+                hooks
+                    .iter()
+                    .map(|(event, hook)| {
+                        let event = event.as_str().ok_or(anyhow!("Expected a string key in event_hooks"))?;
+                        let hook = hook.as_str().ok_or(anyhow!("Expected a string value in event_hooks"))?;
+                        Ok((event.parse::<Event>()?, PathBuf::from(hook)))
+                    })
+                    .collect::<Result<BTreeMap<_, _>>>()
+            })
+            .transpose()
+            .context("Parsing event_hooks from the configuration file.")?
+            .unwrap_or_default();
+        let event_webhooks = yaml_conf["event_webhooks"]
+            .as_vec()
+            .map(|webhooks| {
+                webhooks
+                    .iter()
+                    .enumerate()
+                    .map(|(index, webhook)| {
+                        let url = webhook["url"]
+                            .as_str()
+                            .ok_or(anyhow!("Expected a url in event_webhooks[{index}]"))?;
+                        let url = secret(format!("event_webhooks[{index}].url"), url)?;
+                        let events = webhook["events"]
+                            .as_vec()
+                            .map(|events| {
+                                events
+                                    .iter()
+                                    .map(|event| {
+                                        event
+                                            .as_str()
+                                            .ok_or(anyhow!("Expected a string in event_webhooks[{index}].events"))?
+                                            .parse::<Event>()
+                                    })
+                                    .collect::<Result<Vec<_>>>()
+                            })
+                            .transpose()?
+                            .unwrap_or_default();
+                        Ok(EventWebhook { url, events })
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .transpose()
+            .context("Parsing event_webhooks from the configuration file.")?
+            .unwrap_or_default();
+        let notifications = yaml_conf["notifications"]
+            .as_vec()
+            .map(|notifications| {
+                notifications
+                    .iter()
+                    .enumerate()
+                    .map(|(index, notification)| {
+                        let kind = notification["kind"]
+                            .as_str()
+                            .ok_or(anyhow!("Expected a kind in notifications[{index}]"))?
+                            .parse::<NotificationKind>()?;
+                        let url = notification["url"]
+                            .as_str()
+                            .ok_or(anyhow!("Expected a url in notifications[{index}]"))?;
+                        let url = secret(format!("notifications[{index}].url"), url)?;
+                        let from = notification["from"].as_str().map(str::to_string);
+                        let to = notification["to"]
+                            .as_vec()
+                            .map(|to| {
+                                to.iter()
+                                    .map(|to| {
+                                        to.as_str()
+                                            .map(str::to_string)
+                                            .ok_or(anyhow!("Expected a string in notifications[{index}].to"))
+                                    })
+                                    .collect::<Result<Vec<_>>>()
+                            })
+                            .transpose()?
+                            .unwrap_or_default();
+                        let events = notification["events"]
+                            .as_vec()
+                            .map(|events| {
+                                events
+                                    .iter()
+                                    .map(|event| {
+                                        event
+                                            .as_str()
+                                            .ok_or(anyhow!("Expected a string in notifications[{index}].events"))?
+                                            .parse::<Event>()
+                                    })
+                                    .collect::<Result<Vec<_>>>()
+                            })
+                            .transpose()?
+                            .unwrap_or_default();
+                        Ok(Notification { kind, url, from, to, events })
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .transpose()
+            .context("Parsing notifications from the configuration file.")?
+            .unwrap_or_default();
+        let mqtt_broker = yaml_conf["mqtt_broker"]
+            .as_str()
+            .map(MqttBroker::from_str)
+            .transpose()
+            .context("Parsing mqtt_broker from the configuration file.")?;
+        let mqtt_topic = yaml_conf["mqtt_topic"].as_str().map(str::to_string);
+        let mqtt_username = yaml_conf["mqtt_username"].as_str().map(str::to_string);
+        let mqtt_password = yaml_conf["mqtt_password"]
+            .as_str()
+            .map(|password| secret("mqtt_password".to_string(), password))
+            .transpose()?;
+        let mqtt_retain = yaml_conf["mqtt_retain"].as_bool().unwrap_or_default();
+        let netbox_url = yaml_conf["netbox_url"].as_str().map(|s| s.to_string());
+        let netbox_token = yaml_conf["netbox_token"]
+            .as_str()
+            .map(|token| secret("netbox_token".to_string(), token))
+            .transpose()?;
+        let inventory_cache_ttl = yaml_conf["inventory_cache_ttl"]
+            .as_i64()
+            .map(u64::try_from)
+            .transpose()
+            .context("Parsing inventory_cache_ttl from the configuration file.")?;
+        let control_socket = yaml_conf["control_socket"].as_str().map(PathBuf::from);
+        let dashboard_addr = yaml_conf["dashboard_addr"]
+            .as_str()
+            .map(parse_listen_addr)
+            .transpose()
+            .context("Parsing dashboard_addr from the configuration file.")?;
+        let api_addr = yaml_conf["api_addr"]
+            .as_str()
+            .map(parse_listen_addr)
+            .transpose()
+            .context("Parsing api_addr from the configuration file.")?;
+        let grpc_addr = yaml_conf["grpc_addr"]
+            .as_str()
+            .map(parse_listen_addr)
+            .transpose()
+            .context("Parsing grpc_addr from the configuration file.")?;
+        let health_addr = yaml_conf["health_addr"]
+            .as_str()
+            .map(parse_listen_addr)
+            .transpose()
+            .context("Parsing health_addr from the configuration file.")?;
+        let api_token = yaml_conf["api_token"]
+            .as_str()
+            .map(|token| secret("api_token".to_string(), token))
+            .transpose()?;
+        let snmp_addr = yaml_conf["snmp_addr"]
+            .as_str()
+            .map(parse_listen_addr)
+            .transpose()
+            .context("Parsing snmp_addr from the configuration file.")?;
+        let snmp_community = yaml_conf["snmp_community"]
+            .as_str()
+            .map(|community| secret("snmp_community".to_string(), community))
+            .transpose()?;
+        let log_output = yaml_conf["log_output"]
+            .as_str()
+            .map(LogOutput::from_str)
+            .transpose()
+            .context("Parsing log_output from the configuration file.")?
+            .unwrap_or_default();
+        let syslog_server = yaml_conf["syslog_server"]
+            .as_str()
+            .map(SyslogServer::from_str)
+            .transpose()
+            .context("Parsing syslog_server from the configuration file.")?;
+        let pcap_file = yaml_conf["pcap_file"].as_str().map(PathBuf::from);
+        let pcap_max_size = yaml_conf["pcap_max_size"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(DEFAULT_PCAP_MAX_SIZE))
+            .context("Parsing pcap_max_size from the configuration file.")?;
+        let pcap_files = yaml_conf["pcap_files"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(DEFAULT_PCAP_FILES))
+            .context("Parsing pcap_files from the configuration file.")?;
+        let pcap_tftp = yaml_conf["pcap_tftp"].as_bool().unwrap_or_default();
+        let audit_log = yaml_conf["audit_log"].as_str().map(PathBuf::from);
+        let audit_log_max_size = yaml_conf["audit_log_max_size"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(DEFAULT_AUDIT_LOG_MAX_SIZE))
+            .context("Parsing audit_log_max_size from the configuration file.")?;
+        let audit_log_files = yaml_conf["audit_log_files"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(DEFAULT_AUDIT_LOG_FILES))
+            .context("Parsing audit_log_files from the configuration file.")?;
+        let traffic_log = yaml_conf["traffic_log"].as_str().map(PathBuf::from);
+        let traffic_log_max_size = yaml_conf["traffic_log_max_size"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(DEFAULT_TRAFFIC_LOG_MAX_SIZE))
+            .context("Parsing traffic_log_max_size from the configuration file.")?;
+        let traffic_log_files = yaml_conf["traffic_log_files"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(DEFAULT_TRAFFIC_LOG_FILES))
+            .context("Parsing traffic_log_files from the configuration file.")?;
+        let event_log = yaml_conf["event_log"].as_str().map(PathBuf::from);
+        let event_log_max_size = yaml_conf["event_log_max_size"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(DEFAULT_EVENT_LOG_MAX_SIZE))
+            .context("Parsing event_log_max_size from the configuration file.")?;
+        let event_log_files = yaml_conf["event_log_files"]
+            .as_i64()
+            .map(u64::try_from)
+            .unwrap_or(Ok(DEFAULT_EVENT_LOG_FILES))
+            .context("Parsing event_log_files from the configuration file.")?;
+        let stats_file = yaml_conf["stats_file"].as_str().map(PathBuf::from);
+        let assets_file = yaml_conf["assets_file"].as_str().map(PathBuf::from);
+        let user = yaml_conf["user"].as_str().map(str::to_string);
+        let group = yaml_conf["group"].as_str().map(str::to_string);
+        let strict = yaml_conf["strict"].as_bool().unwrap_or_default();
+        let sandbox = yaml_conf["sandbox"].as_bool().unwrap_or_default();
+        let string_list = |yaml: &Yaml| -> Option<Vec<String>> {
+            yaml.as_vec().map(|v| {
+                v.iter()
+                    .filter_map(|i| i.as_str().map(|s| s.to_string()))
+                    .collect()
+            })
+        };
+        let default_filter = FileFilter::default();
+        let tftp_file_filter = FileFilter {
+            serve_hidden: yaml_conf["tftp_serve_hidden"]
+                .as_bool()
+                .unwrap_or(default_filter.serve_hidden),
+            allowed_extensions: string_list(&yaml_conf["tftp_allowed_extensions"]),
+            denied_extensions: string_list(&yaml_conf["tftp_denied_extensions"])
+                .unwrap_or(default_filter.denied_extensions),
+        };
+        let tftp_fallbacks = yaml_conf["tftp_fallbacks"]
+            .as_hash()
+            .map(|yaml_obj| {
+                yaml_obj
+                    .iter()
+                    .map(|(prefix, file)| {
+                        Ok(TftpFallback {
+                            prefix: PathBuf::from(
+                                prefix
+                                    .as_str()
+                                    .ok_or(anyhow!("Expected a string key in tftp_fallbacks"))?,
+                            ),
+                            file: PathBuf::from(
+                                file.as_str()
+                                    .ok_or(anyhow!("Expected a string value in tftp_fallbacks"))?,
+                            ),
+                        })
+                    })
+                    .collect::<Result<Vec<TftpFallback>>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        let match_map = Self::match_map_from_yaml(&yaml_conf["match"])?;
+        let iface_defaults = yaml_conf["defaults"]
+            .as_hash()
+            .map(|defaults| {
+                defaults
+                    .iter()
+                    .map(|(iface, entry)| {
+                        let iface = iface
+                            .as_str()
+                            .ok_or(anyhow!("Expected interface names as keys in defaults"))?;
+                        let entry = Conf::base_conf_from_yaml(entry)?.unwrap_or_default();
+                        Ok((iface.to_string(), entry))
+                    })
+                    .collect::<Result<BTreeMap<String, ConfEntry>>>()
+            })
+            .transpose()
+            .context("Parsing defaults from the configuration file.")?
+            .unwrap_or_default();
+        let profiles = yaml_conf["profiles"]
+            .as_hash()
+            .map(|profiles| {
+                profiles
+                    .iter()
+                    .map(|(name, entry)| {
+                        let name = name
+                            .as_str()
+                            .ok_or(anyhow!("Expected profile names as keys in profiles"))?;
+                        let entry = Conf::base_conf_from_yaml(entry)?.unwrap_or_default();
+                        if entry.profile.is_some() {
+                            bail!("Profile {name} can't reference another profile");
+                        }
+                        Ok((name.to_string(), entry))
+                    })
+                    .collect::<Result<BTreeMap<String, ConfEntry>>>()
+            })
+            .transpose()
+            .context("Parsing profiles from the configuration file.")?
+            .unwrap_or_default();
+        let match_policy = yaml_conf["match_policy"]
+            .as_str()
+            .map(MatchPolicy::from_str)
+            .transpose()
+            .context("Parsing match_policy from the configuration file.")?
+            .unwrap_or_default();
+        let require_match = yaml_conf["require_match"].as_bool().unwrap_or_default();
+
+        Ok(Self {
+            default,
+            ifaces,
+            tftp_server_dir,
+            tftp_symlinks,
+            tftp_max_transfers,
+            tftp_max_transfers_per_client,
+            tftp_file_filter,
+            tftp_fallbacks,
+            tftp_block_size_limit,
+            tftp_upstream,
+            http_port,
+            https_port,
+            https_cert,
+            https_key,
+            autoinstall_dir,
+            netbootxyz,
+            images,
+            mirrors,
+            image_versions,
+            secure_boot,
+            upload_token,
+            signing_cert,
+            signing_key,
+            image_refresh_interval,
+            upload_users,
+            upload_max_size,
+            https_client_ca,
+            boot_hostname,
+            dns_port,
+            reverse_dns,
+            reverse_dns_server,
+            ipxe_chainload,
+            webhook_url,
+            webhook_timeout,
+            boot_hook,
+            event_hooks,
+            event_webhooks,
+            notifications,
+            mqtt_broker,
+            mqtt_topic,
+            mqtt_username,
+            mqtt_password,
+            mqtt_retain,
+            netbox_url,
+            netbox_token,
+            inventory_cache_ttl,
+            control_socket,
+            dashboard_addr,
+            api_addr,
+            grpc_addr,
+            health_addr,
+            api_token,
+            snmp_addr,
+            snmp_community,
+            log_output,
+            syslog_server,
+            pcap_file,
+            pcap_max_size,
+            pcap_files,
+            pcap_tftp,
+            audit_log,
+            audit_log_max_size,
+            audit_log_files,
+            traffic_log,
+            traffic_log_max_size,
+            traffic_log_files,
+            event_log,
+            event_log_max_size,
+            event_log_files,
+            stats_file,
+            assets_file,
+            user,
+            group,
+            sandbox,
+            instance_name: None,
+            strict,
+            max_sessions,
+            max_sessions_per_iface,
+            max_sessions_per_subnet,
+            dhcp_max_handlers,
+            pxe_boot_server,
+            pxe_boot_server_workers,
+            dhcp_enabled,
+            tftp_enabled,
+            match_map,
+            decisions: Default::default(),
+            match_policy,
+            require_match,
+            iface_defaults,
+            profiles,
+            tenants: BTreeMap::new(),
+            secret_refs,
+        })
+    }
+
+    fn match_map_from_yaml(yaml: &Yaml) -> Result<Option<Vec<MatchEntry>>> {
+        yaml.as_vec()
+            .map(|match_entry| -> Result<Vec<MatchEntry>> {
+                match_entry
+                    .iter()
+                    .map(Self::match_entry_from_yaml)
+                    .collect::<Result<Vec<MatchEntry>>>()
+                    .map_err(|e| anyhow!("{e}, reading entries from 'match' section"))
+            })
+            .transpose()
+    }
+
+    /// Merges the files of the include directory into the configuration, in
+    /// the lexical order of their names: their `default` section into
+    /// `default`, the fields already set taking precedence, and their `match`
+    /// entries after those already known.
+    fn merge_includes(&mut self, dir: &Path) -> Result<()> {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Ok(());
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && is_include_file(path))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            self.merge_include(&path)
+                .map_err(|e| anyhow!("{e}, from included file: {}", path.display()))?;
+            info!("Merged configuration from included file {}", path.display());
+        }
+
+        Ok(())
+    }
+
+    fn merge_include(&mut self, path: &Path) -> Result<()> {
+        let content = std::fs::read_to_string(path)?;
+        let format = ConfFormat::of(path);
+        let yaml_conf = format.load(&content)?;
+        schema::check(&yaml_conf, true, (format == ConfFormat::Yaml).then_some(&content))?;
+
+        if let Some(default) = Conf::base_conf_from_yaml(&yaml_conf["default"])? {
+            self.merge_left_into_default(&default);
+        }
+        if let Some(match_entries) = Self::match_map_from_yaml(&yaml_conf["match"])? {
+            self.match_map.get_or_insert_with(Vec::new).extend(match_entries);
+        }
+
+        Ok(())
+    }
+
+    fn match_entry_from_yaml(item: &yaml_rust2::Yaml) -> Result<MatchEntry> {
+        // `profile` alone stands for a `conf` of that profile
+        let profile = item["profile"].as_str().map(|s| s.to_string());
+        let mut hosts = item["hosts"]
+            .as_str()
+            .map(|path| HostList::load(Path::new(path)))
+            .transpose()?;
+        let conf = match (Conf::base_conf_from_yaml(&item["conf"])?, profile) {
+            (Some(conf), None) => conf,
+            (conf, Some(profile)) => ConfEntry {
+                profile: Some(profile),
+                ..conf.unwrap_or_default()
+            },
+            (None, None) if hosts.is_some() => ConfEntry::default(),
+            (None, None) => bail!("No configuration found for match entry"),
+        };
+        for host in hosts.iter_mut().flat_map(HostList::entries_mut) {
+            *host = host.merged_with(&conf);
+        }
+
+        // The hosts alone select the clients
+        let has_condition =
+            ["select", "not", "any", "all", "active"].iter().any(|key| !item[*key].is_badvalue());
+        let condition = match hosts.is_some() && !has_condition {
+            true => MatchCondition::everyone(),
+            false => Self::match_condition_from_yaml(item, MatchMode::Exact)?,
+        };
+
+        let canary = item["canary"]
+            .as_hash()
+            .map(|_| Self::canary_from_yaml(&item["canary"], &conf))
+            .transpose()
+            .context("Reading canary")?;
+
+        Ok(MatchEntry {
+            condition,
+            conf,
+            priority: item["priority"].as_i64().unwrap_or(0),
+            hosts,
+            canary,
+        })
+    }
+
+    /// `canary` of a match entry, its configuration completed by `entry_conf`.
+    fn canary_from_yaml(item: &Yaml, entry_conf: &ConfEntry) -> Result<Canary> {
+        let percent = item["percent"]
+            .as_i64()
+            .and_then(|percent| u8::try_from(percent).ok())
+            .filter(|percent| *percent <= 100)
+            .ok_or(anyhow!("Expected a percent from 0 to 100"))?;
+        let profile = item["profile"].as_str().map(|s| s.to_string());
+        let conf = match (Conf::base_conf_from_yaml(&item["conf"])?, profile) {
+            (Some(conf), None) => conf,
+            (conf, Some(profile)) => ConfEntry {
+                profile: Some(profile),
+                ..conf.unwrap_or_default()
+            },
+            (None, None) => bail!("No configuration found for the canary"),
+        };
+
+        Ok(Canary {
+            percent,
+            seed: item["seed"].as_str().unwrap_or_default().to_string(),
+            conf: conf.merged_with(entry_conf),
+        })
+    }
+
+    /// Condition of a match entry or of one of its groups, matching values as
+    /// told by `regex` or `glob`, else like the entry or group it's part of.
+    fn match_condition_from_yaml(item: &Yaml, parent_mode: MatchMode) -> Result<MatchCondition> {
+        let match_type = item["match_type"]
+            .as_str()
+            .map(|s| match s.to_lowercase().as_str() {
+                "any" => Ok(MatchType::Any),
+                "all" => Ok(MatchType::All),
+                _ => Err(anyhow!("Invalid match type: {s}")),
+            })
+            .unwrap_or(Ok(MatchType::All))?;
+
+        let mode = match (item["regex"].as_bool(), item["glob"].as_bool()) {
+            (Some(true), Some(true)) => bail!("Expected either regex or glob, not both"),
+            (Some(true), _) => MatchMode::Regex,
+            (_, Some(true)) => MatchMode::Glob,
+            (None, None) => parent_mode,
+            _ => MatchMode::Exact,
+        };
+        let select = item["select"].as_hash();
+        let not = item["not"].as_hash();
+        let groups = |name: &str| {
+            item[name]
+                .as_vec()
+                .into_iter()
+                .flatten()
+                .map(|group| Self::match_condition_from_yaml(group, mode))
+                .collect::<Result<Vec<MatchCondition>>>()
+                .with_context(|| format!("Reading a group of {name}"))
+        };
+        let (any_groups, all_groups) = (groups("any")?, groups("all")?);
+        let active = item["active"]
+            .as_hash()
+            .map(|_| TimeWindow::from_yaml(&item["active"]))
+            .transpose()
+            .context("Reading active")?;
+        let has_fields = select.is_some() || not.is_some();
+        if !has_fields && any_groups.is_empty() && all_groups.is_empty() && active.is_none() {
+            bail!("Expected a hash for select, or not, any, all or active");
+        }
+        let fields_values = select
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| {
+                let key = key.as_str().map(field_name).ok_or(anyhow!("Expected a string key"))?;
+                Ok((key.to_string(), Self::field_value_from_yaml(key, value, mode)?))
+            })
+            .collect::<Result<HashMap<String, FieldValue>>>()?;
+        let excluded_values = not
+            .into_iter()
+            .flatten()
+            .map(|(key, values)| {
+                let key = key
+                    .as_str()
+                    .map(field_name)
+                    .ok_or(anyhow!("Expected a string key in not"))?;
+                let values = match values {
+                    Yaml::Array(values) => values
+                        .iter()
+                        .map(|value| Self::field_value_from_yaml(key, value, mode))
+                        .collect::<Result<Vec<FieldValue>>>()?,
+                    value => vec![Self::field_value_from_yaml(key, value, mode)?],
+                };
+                Ok((key.to_string(), values))
+            })
+            .collect::<Result<HashMap<String, Vec<FieldValue>>>>()?;
+
+        Ok(MatchCondition {
+            fields_values,
+            excluded_values,
+            match_type,
+            mode,
+            any_groups,
+            all_groups,
+            active,
+        })
+    }
+
+    /// Value of the field `key` of `select` or `not`, matched as told by `mode`.
+    fn field_value_from_yaml(key: &str, value: &Yaml, mode: MatchMode) -> Result<FieldValue> {
+        // Codes of architectures may be given as numbers
+        let mut value = value
+            .as_str()
+            .map(|s| s.to_string())
+            .or(value.as_i64().map(|n| n.to_string()))
+            .ok_or(anyhow!("Expected a string value, reading field \"{key}\""))?;
+        if key == "ClientSystemArchitecture" && mode == MatchMode::Exact {
+            value = parse_arch(&value)?;
+        }
+
+        if NETWORK_FIELDS.contains(&key) && mode == MatchMode::Exact {
+            FieldValue::from_network(value)
+        } else {
+            FieldValue::from_string(value, mode)
+        }
+        .map_err(|e| anyhow!("{e}, reading field \"{key}\""))
+    }
+
+    fn base_conf_from_yaml(yaml_conf: &yaml_rust2::Yaml) -> Result<Option<ConfEntry>> {
+        yaml_conf
+            .as_hash()
+            .map(|yaml_obj| {
+                let boot_file = yaml_obj
+                    .get(&Yaml::from_str("boot_file"))
+                    .and_then(|v| v.as_str().map(|s| s.to_string()));
+                let boot_server_ipv4 = yaml_obj
+                    .get(&Yaml::from_str("boot_server_ipv4"))
+                    .map(|v| {
+                        v.as_str().map_or(Result::Ok(None), |s: &str| {
+                            Ok(Some(Ipv4Addr::from_str(s).map_err(|o| {
+                                anyhow!("IPv4 parsing error: {}", o.to_string())
+                            })?))
+                        })
+                    })
+                    .map_or(Ok(None), |i: Result<Option<Ipv4Addr>>| i)?;
+                let ipxe_script = yaml_obj
+                    .get(&Yaml::from_str("ipxe_script"))
+                    .and_then(|v| v.as_str().map(|s| s.to_string()));
+                let grub_cfg = yaml_obj
+                    .get(&Yaml::from_str("grub_cfg"))
+                    .and_then(|v| v.as_str().map(|s| s.to_string()));
+                let ignition = yaml_obj
+                    .get(&Yaml::from_str("ignition"))
+                    .and_then(|v| v.as_str().map(|s| s.to_string()));
+                let boot_iso = yaml_obj
+                    .get(&Yaml::from_str("boot_iso"))
+                    .and_then(|v| v.as_str().map(PathBuf::from))
+                    .map(|path| {
+                        if !is_boot_iso_path(&path) {
+                            bail!(
+                                "Invalid boot_iso {}, expected the path of an .iso in images",
+                                path.display()
+                            );
+                        }
+                        Ok(path)
+                    })
+                    .transpose()?;
+                let windows = yaml_obj
+                    .get(&Yaml::from_str("windows"))
+                    .and_then(|v| v.as_str().map(PathBuf::from))
+                    .map(|path| {
+                        if !is_relative_path(&path) {
+                            bail!(
+                                "Invalid windows media {}, expected a path relative to tftp_server_dir",
+                                path.display()
+                            );
+                        }
+                        Ok(path)
+                    })
+                    .transpose()?;
+                let menu_label = yaml_obj
+                    .get(&Yaml::from_str("menu_label"))
+                    .and_then(|v| v.as_str().map(|s| s.to_string()));
+                let subnet_mask = yaml_obj
+                    .get(&Yaml::from_str("subnet_mask"))
+                    .and_then(|v| v.as_str())
+                    .map(|mask| {
+                        mask.parse::<Ipv4Addr>()
+                            .ok()
+                            .filter(|mask| is_subnet_mask(*mask))
+                            .ok_or(anyhow!("Invalid subnet_mask {mask}"))
+                    })
+                    .transpose()?;
+                let lease_time = yaml_obj
+                    .get(&Yaml::from_str("lease_time"))
+                    .and_then(|v| v.as_i64())
+                    .map(|secs| {
+                        u32::try_from(secs)
+                            .ok()
+                            .filter(|secs| *secs > 0)
+                            .ok_or(anyhow!("Invalid lease_time {secs}, expected seconds"))
+                    })
+                    .transpose()?;
+                let reply_delay_ms = yaml_obj
+                    .get(&Yaml::from_str("reply_delay_ms"))
+                    .and_then(|v| v.as_i64())
+                    .map(|ms| {
+                        u64::try_from(ms)
+                            .ok()
+                            .filter(|ms| *ms <= MAX_REPLY_DELAY_MS)
+                            .ok_or(anyhow!(
+                                "Invalid reply_delay_ms {ms}, expected at most {MAX_REPLY_DELAY_MS}"
+                            ))
+                    })
+                    .transpose()?;
+                let log_level = yaml_obj
+                    .get(&Yaml::from_str("log_level"))
+                    .and_then(|v| v.as_str())
+                    .map(|level| {
+                        level
+                            .parse::<LevelFilter>()
+                            .ok()
+                            .filter(|level| *level != LevelFilter::Off)
+                            .ok_or(anyhow!(
+                                "Invalid log_level {level}, expected one of: error, warn, info, \
+                                 debug, trace"
+                            ))
+                    })
+                    .transpose()?;
+                let vars = yaml_obj
+                    .get(&Yaml::from_str("vars"))
+                    .and_then(|v| v.as_hash())
+                    .map(|vars| {
+                        vars.iter()
+                            .map(|(name, value)| {
+                                let name = name
+                                    .as_str()
+                                    .ok_or(anyhow!("Expected a string key in vars"))?;
+                                let value = match value {
+                                    Yaml::String(s) | Yaml::Real(s) => s.clone(),
+                                    Yaml::Integer(i) => i.to_string(),
+                                    Yaml::Boolean(b) => b.to_string(),
+                                    _ => bail!("Expected a scalar value for {name} in vars"),
+                                };
+                                Ok((name.to_string(), value))
+                            })
+                            .collect::<Result<BTreeMap<String, String>>>()
+                    })
+                    .transpose()?
+                    .unwrap_or_default();
+                let pinned_images = yaml_obj
+                    .get(&Yaml::from_str("pinned_images"))
+                    .and_then(|v| v.as_hash())
+                    .map(|pins| {
+                        pins.iter()
+                            .map(|(image, version)| {
+                                let image = image
+                                    .as_str()
+                                    .map(PathBuf::from)
+                                    .filter(|image| is_relative_path(image))
+                                    .ok_or(anyhow!(
+                                        "Expected image paths as keys in pinned_images"
+                                    ))?;
+                                let version = version
+                                    .as_str()
+                                    .filter(|version| is_sha256(version))
+                                    .ok_or(anyhow!(
+                                        "Invalid pinned version of {}, expected a SHA-256",
+                                        image.display()
+                                    ))?;
+                                Ok((image, version.to_ascii_lowercase()))
+                            })
+                            .collect::<Result<BTreeMap<PathBuf, String>>>()
+                    })
+                    .transpose()?
+                    .unwrap_or_default();
+                let profile = yaml_obj
+                    .get(&Yaml::from_str("profile"))
+                    .and_then(|v| v.as_str().map(|s| s.to_string()));
+
+                Ok(ConfEntry {
+                    boot_file,
+                    boot_server_ipv4,
+                    ipxe_script,
+                    grub_cfg,
+                    ignition,
+                    boot_iso,
+                    windows,
+                    menu_label,
+                    subnet_mask,
+                    lease_time,
+                    reply_delay_ms,
+                    log_level,
+                    vars,
+                    pinned_images,
+                    profile,
+                })
+            })
+            .transpose()
+    }
+
+    pub fn merge_left_into_default(&mut self, other: &ConfEntry) {
+        self.default = self
+            .default
+            .as_ref()
+            .map(|mine| mine.merged_with(other))
+            .or(Some(other.clone()));
+    }
+
+    /// `entry` completed with the fields of the profile it references, if
+    /// any, for the entries answered by the boot hook, webhook or inventory.
+    pub fn with_profile(&self, entry: ConfEntry) -> Result<ConfEntry> {
+        match &entry.profile {
+            Some(name) => Ok(entry.merged_with(profile(&self.profiles, name)?)),
+            None => Ok(entry),
+        }
+    }
+
+    /// Completes the entries referencing a profile with its fields, the
+    /// fields of the entries taking precedence.
+    fn merge_profiles(&mut self) -> Result<()> {
+        self.tenants.values_mut().try_for_each(Conf::merge_profiles)?;
+        let profiles = &self.profiles;
+        let merge_profile = |entry: &mut ConfEntry| -> Result<()> {
+            if let Some(name) = &entry.profile {
+                *entry = entry.merged_with(profile(profiles, name)?);
+            }
+            Ok(())
+        };
+
+        self.default.iter_mut().try_for_each(merge_profile)?;
+        self.iface_defaults.values_mut().try_for_each(merge_profile)?;
+        self.match_map.iter_mut().flatten().try_for_each(|match_entry| {
+            merge_profile(&mut match_entry.conf)?;
+            match_entry.canary.iter_mut().try_for_each(|canary| merge_profile(&mut canary.conf))?;
+            match_entry
+                .hosts
+                .iter_mut()
+                .flat_map(HostList::entries_mut)
+                .try_for_each(merge_profile)
+        })
+    }
+
+    /// Completes the `defaults` of the interfaces with `default`, the fields
+    /// of the interfaces taking precedence.
+    fn merge_default_into_iface_defaults(&mut self) {
+        self.tenants.values_mut().for_each(Conf::merge_default_into_iface_defaults);
+        if let Some(default) = self.default.as_ref() {
+            for iface_default in self.iface_defaults.values_mut() {
+                *iface_default = iface_default.merged_with(default);
+            }
+        }
+    }
+
+    /// The configurations the services are run with, by tenant name: those
+    /// of `tenants`, and the top level one, named `None`, unless there are
+    /// tenants and it doesn't list `ifaces` of its own.
+    pub fn served(&self) -> Vec<(Option<&str>, &Conf)> {
+        let top_level = (self.tenants.is_empty() || self.ifaces.is_some()).then_some((None, self));
+        top_level
+            .into_iter()
+            .chain(self.tenants.iter().map(|(name, tenant)| (Some(name.as_str()), tenant)))
+            .collect()
+    }
+
+    pub fn get_tenants(&self) -> &BTreeMap<String, Conf> {
+        &self.tenants
+    }
+
+    pub fn get_ifaces(&self) -> Option<&Vec<String>> {
+        self.ifaces.as_ref()
+    }
+
+    pub fn get_tftp_serve_path(&self) -> Option<String> {
+        self.tftp_server_dir.clone()
+    }
+
+    pub fn get_tftp_symlink_policy(&self) -> SymlinkPolicy {
+        self.tftp_symlinks
+    }
+
+    pub fn get_tftp_max_transfers(&self) -> u64 {
+        self.tftp_max_transfers
+    }
+
+    pub fn get_tftp_max_transfers_per_client(&self) -> u64 {
+        self.tftp_max_transfers_per_client
+    }
+
+    pub fn get_tftp_file_filter(&self) -> &FileFilter {
+        &self.tftp_file_filter
+    }
+
+    pub fn get_tftp_fallbacks(&self) -> &Vec<TftpFallback> {
+        &self.tftp_fallbacks
+    }
+
+    pub fn get_tftp_block_size_limit(&self) -> Option<u16> {
+        self.tftp_block_size_limit
+    }
+
+    pub fn get_tftp_upstream(&self) -> Option<SocketAddr> {
+        self.tftp_upstream
+    }
+
+    pub fn get_http_port(&self) -> Option<u16> {
+        self.http_port
+    }
+
+    pub fn get_https_port(&self) -> Option<u16> {
+        self.https_port
+    }
+
+    pub fn get_https_cert(&self) -> Option<&PathBuf> {
+        self.https_cert.as_ref()
+    }
+
+    pub fn get_https_key(&self) -> Option<&PathBuf> {
+        self.https_key.as_ref()
+    }
+
+    pub fn get_autoinstall_dir(&self) -> Option<&PathBuf> {
+        self.autoinstall_dir.as_ref()
+    }
+
+    /// Whether clients without a `boot_file` get the netboot.xyz menu.
+    pub fn get_netbootxyz(&self) -> bool {
+        self.netbootxyz
+    }
+
+    pub fn get_images(&self) -> &Vec<ImageSource> {
+        &self.images
+    }
+
+    pub fn get_mirrors(&self) -> &Vec<MirrorSource> {
+        &self.mirrors
+    }
+
+    pub fn get_image_versions(&self) -> u64 {
+        self.image_versions
+    }
+
+    /// Whether `default` or any `match` entry boots Windows media, needing wimboot.
+    pub fn has_windows_profile(&self) -> bool {
+        self.any_entry(|e| e.windows.is_some())
+    }
+
+    fn any_entry(&self, predicate: impl Fn(&ConfEntry) -> bool) -> bool {
+        self.default.as_ref().is_some_and(&predicate)
+            || self.iface_defaults.values().any(&predicate)
+            || self
+                .match_map
+                .iter()
+                .flatten()
+                .flat_map(MatchEntry::confs)
+                .any(predicate)
+    }
+
+    /// Whether UEFI clients without a `boot_file` get the signed shim and GRUB.
+    pub fn get_secure_boot(&self) -> bool {
+        self.secure_boot
+    }
+
+    /// Token HTTP uploads are authorized with, uploads are disabled without.
+    pub fn get_upload_token(&self) -> Option<&String> {
+        self.upload_token.as_ref()
+    }
+
+    pub fn get_signing_cert(&self) -> Option<&PathBuf> {
+        self.signing_cert.as_ref()
+    }
+
+    pub fn get_signing_key(&self) -> Option<&PathBuf> {
+        self.signing_key.as_ref()
+    }
+
+    /// How often the images are checked for updates, only on start and
+    /// reloads when not set.
+    pub fn get_image_refresh_interval(&self) -> Option<Duration> {
+        self.image_refresh_interval.map(Duration::from_secs)
+    }
+
+    /// Entries of the generated iPXE menu: `default` and the `match` entries
+    /// booting an ISO, Windows media or an iPXE script of their own, combined
+    /// with `default`, without duplicates.
+    pub fn get_menu_entries(&self) -> Vec<ConfEntryRef<'_>> {
+        let mut entries: Vec<ConfEntryRef> = Vec::new();
+        let confs = self
+            .default
+            .iter()
+            .chain(self.match_map.iter().flatten().map(|me| &me.conf));
+        for conf in confs {
+            if conf.boot_iso.is_none() && conf.windows.is_none() && conf.ipxe_script.is_none() {
+                continue;
+            }
+            let entry = conf.merge_refs(self.default.as_ref());
+            if !entries.iter().any(|known| known.boots_same_as(&entry)) {
+                entries.push(entry);
+            }
+        }
+
+        entries
+    }
+
+    /// The `hosts` files of the `match` entries.
+    pub fn get_host_files(&self) -> Vec<&Path> {
+        self.match_map
+            .iter()
+            .flatten()
+            .filter_map(|match_entry| Some(match_entry.hosts.as_ref()?.path.as_path()))
+            .collect()
+    }
+
+    /// Whether `default` or any `match` entry pins versions of images.
+    pub fn any_pinned_images(&self) -> bool {
+        self.any_entry(|e| !e.pinned_images.is_empty())
+    }
+
+    /// Versions of `image` pinned by the `default` or a `match` entry.
+    pub fn get_pinned_versions(&self, image: &Path) -> Vec<String> {
+        self.default
+            .iter()
+            .chain(self.match_map.iter().flatten().map(|me| &me.conf))
+            .filter_map(|entry| entry.pinned_images.get(image).cloned())
+            .collect()
+    }
+
+    /// Users HTTP uploads are authorized for with basic authentication, by
+    /// name, with their passwords.
+    pub fn get_upload_users(&self) -> &BTreeMap<String, String> {
+        &self.upload_users
+    }
+
+    /// Certificate authority of the client certificates authorizing HTTPS
+    /// uploads.
+    pub fn get_https_client_ca(&self) -> Option<&PathBuf> {
+        self.https_client_ca.as_ref()
+    }
+
+    /// Size in bytes an upload is refused above.
+    pub fn get_upload_max_size(&self) -> u64 {
+        self.upload_max_size.saturating_mul(1024 * 1024)
+    }
+
+    /// Whether uploads are authorized by any means, they are refused without.
+    pub fn accepts_uploads(&self) -> bool {
+        self.upload_token.is_some()
+            || !self.upload_users.is_empty()
+            || self.https_client_ca.is_some()
+    }
+
+    /// Name answered with the server's address by the DNS responder.
+    pub fn get_boot_hostname(&self) -> Option<&String> {
+        self.boot_hostname.as_ref()
+    }
+
+    pub fn get_dns_port(&self) -> u16 {
+        self.dns_port.unwrap_or(DEFAULT_DNS_PORT)
+    }
+
+    /// Whether the hostnames of the DHCP clients are looked up in the
+    /// reverse DNS.
+    pub fn get_reverse_dns(&self) -> bool {
+        self.reverse_dns
+    }
+
+    /// DNS server of the reverse lookups, when not the system's.
+    pub fn get_reverse_dns_server(&self) -> Option<SocketAddr> {
+        self.reverse_dns_server
+    }
+
+    /// Whether PXE clients without a `boot_file` are chainloaded into iPXE,
+    /// booting over HTTP.
+    pub fn get_ipxe_chainload(&self) -> bool {
+        self.ipxe_chainload
+    }
+
+    /// Endpoint the DHCP messages of the clients are posted to, answering
+    /// with their configuration.
+    pub fn get_webhook_url(&self) -> Option<&String> {
+        self.webhook_url.as_ref()
+    }
+
+    /// Time the webhook is waited for before falling back to `match`.
+    pub fn get_webhook_timeout(&self) -> Duration {
+        Duration::from_millis(self.webhook_timeout.unwrap_or(DEFAULT_WEBHOOK_TIMEOUT_MS))
+    }
+
+    /// Rhai script deciding the configuration of the DHCP clients.
+    pub fn get_boot_hook(&self) -> Option<&PathBuf> {
+        self.boot_hook.as_ref()
+    }
+
+    /// Program run on `event`, if any.
+    pub fn get_event_hook(&self, event: Event) -> Option<&PathBuf> {
+        self.event_hooks.get(&event)
+    }
+
+    /// Programs run on the events of the clients.
+    pub fn get_event_hooks(&self) -> &BTreeMap<Event, PathBuf> {
+        &self.event_hooks
+    }
+
+    /// URLs the events of the clients are posted to.
+    pub fn get_event_webhooks(&self) -> &[EventWebhook] {
+        &self.event_webhooks
+    }
+
+    /// Chats and mailboxes the events of the clients are sent to, see
+    /// [`crate::notifications`].
+    pub fn get_notifications(&self) -> &[Notification] {
+        &self.notifications
+    }
+
+    /// Broker the events of the clients are published to, see
+    /// [`crate::mqtt`].
+    pub fn get_mqtt_broker(&self) -> Option<&MqttBroker> {
+        self.mqtt_broker.as_ref()
+    }
 
-impl ProcessEnvConf {
-    pub fn from_process_env() -> ProcessEnvConf {
-        let boot_server_ipv4: Option<Ipv4Addr> =
-            std::env::var(format!("{ENV_VAR_PREFIX}TFTP_SERVER_IPV4"))
-                .unwrap_or_default()
-                .parse()
-                .ok();
-        let boot_file = std::env::var(format!("{ENV_VAR_PREFIX}BOOT_FILE")).ok();
-        let tftp_server_dir = std::env::var(format!("{ENV_VAR_PREFIX}TFTP_SERVER_DIR_PATH")).ok();
-        let ifaces_csv = std::env::var(format!("{ENV_VAR_PREFIX}IFACES")).ok();
-        let ifaces = ifaces_csv.map(|csv| csv.split(",").map(|s| s.to_string()).collect());
-        let max_sessions = std::env::var(format!("{ENV_VAR_PREFIX}MAX_SESSIONS"))
-            .map(|s| s.parse::<u64>().ok())
-            .ok()
-            .flatten();
+    /// Topic the events are published under, with its placeholders.
+    pub fn get_mqtt_topic(&self) -> &str {
+        self.mqtt_topic.as_deref().unwrap_or(DEFAULT_MQTT_TOPIC)
+    }
 
-        Self {
-            conf: ConfEntry {
-                boot_server_ipv4,
-                boot_file,
-            },
-            tftp_server_dir,
-            ifaces,
-            max_sessions,
-        }
+    pub fn get_mqtt_username(&self) -> Option<&String> {
+        self.mqtt_username.as_ref()
     }
-}
 
-impl From<ProcessEnvConf> for Conf {
-    fn from(env_conf: ProcessEnvConf) -> Self {
-        let mut conf = Self {
-            default: None,
-            ifaces: None,
-            max_sessions: env_conf.max_sessions.unwrap_or(DEFAULT_MAX_SESSIONS),
-            match_map: None,
-            tftp_server_dir: None,
-        };
+    pub fn get_mqtt_password(&self) -> Option<&String> {
+        self.mqtt_password.as_ref()
+    }
 
-        conf.merge_left_into_default(&env_conf.conf);
-        conf.ifaces = env_conf.ifaces;
-        conf.tftp_server_dir = env_conf.tftp_server_dir;
+    /// Whether the broker keeps the last event of each topic.
+    pub fn get_mqtt_retain(&self) -> bool {
+        self.mqtt_retain
+    }
 
-        conf
+    /// NetBox instance the clients are looked up in, by MAC address or serial.
+    pub fn get_netbox_url(&self) -> Option<&String> {
+        self.netbox_url.as_ref()
     }
-}
 
-impl Conf {
-    pub fn validate(&self) -> Result<()> {
-        let has_external_tftp_server = self
-            .match_map
-            .as_ref()
-            .map(|m| m.iter().any(|me| me.conf.boot_server_ipv4.is_some()))
-            .or(self.default.as_ref().map(|d| d.boot_server_ipv4.is_some()))
-            .unwrap_or(false);
-        let has_tftp_path = self.tftp_server_dir.is_some();
-        let has_boot_filename = self
-            .match_map
-            .as_ref()
-            .map(|m| m.iter().any(|me| me.conf.boot_file.is_some()))
-            .or(self.default.as_ref().map(|d| d.boot_file.is_some()))
-            .unwrap_or(false);
+    /// API token of `netbox_url`.
+    pub fn get_netbox_token(&self) -> Option<&String> {
+        self.netbox_token.as_ref()
+    }
 
-        if !has_external_tftp_server && !has_tftp_path {
-            return Err(anyhow!(
-                "No TFTP server path or external TFTP server configured."
-            ));
-        }
+    /// Time the answers of the inventory are reused for.
+    pub fn get_inventory_cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.inventory_cache_ttl.unwrap_or(DEFAULT_INVENTORY_CACHE_TTL_SECS))
+    }
 
-        if !has_boot_filename {
-            return Err(anyhow!("No boot filename configured."));
-        }
-        Ok(())
+    /// Unix socket the running server answers the `sessions` command on,
+    /// the default one being of the instance.
+    pub fn get_control_socket(&self) -> PathBuf {
+        self.control_socket.clone().unwrap_or_else(|| match &self.instance_name {
+            Some(name) => PathBuf::from(format!("/run/preboot-oxide-{name}.sock")),
+            None => PathBuf::from(DEFAULT_CONTROL_SOCKET),
+        })
     }
 
-    pub fn from_yaml_config(path_override: Option<&PathBuf>) -> Result<Self> {
-        let path = path_override
-            .map(|path| PathBuf::from(path))
-            .unwrap_or_else(|| {
-                dirs::config_local_dir()
-                    .map(|config_path| config_path.join(&CONFIG_FOLDER).join(&YAML_FILENAME))
-                    .unwrap_or_else(|| PathBuf::from(&YAML_FILENAME))
-            });
+    pub fn get_dashboard_addr(&self) -> Option<SocketAddr> {
+        self.dashboard_addr
+    }
 
-        Self::from_yaml_file(&path).map_err(|e| anyhow!("{e}, from YAML file: {}", path.display()))
-            .inspect(|_| info!("Loaded configuration from YAML file {}", path.display()))
+    pub fn get_api_addr(&self) -> Option<SocketAddr> {
+        self.api_addr
     }
 
-    fn from_yaml_file(path: &Path) -> Result<Self> {
-        let mut file = std::fs::File::open(&path)?;
-        let mut buf = String::new();
-        file.read_to_string(&mut buf)?;
+    pub fn get_grpc_addr(&self) -> Option<SocketAddr> {
+        self.grpc_addr
+    }
 
-        let yaml_conf = yaml_rust2::YamlLoader::load_from_str(&buf)?;
+    /// Address of `/healthz` and `/readyz`, see [`crate::health`].
+    pub fn get_health_addr(&self) -> Option<SocketAddr> {
+        self.health_addr
+    }
 
-        let default: Option<ConfEntry> = Conf::base_conf_from_yaml(&yaml_conf[0]["default"])?;
-        let tftp_server_dir: Option<String> = yaml_conf[0]["tftp_server_dir"]
-            .as_str()
-            .map(|s| s.to_string());
-        let ifaces: Option<Vec<String>> = yaml_conf[0]["ifaces"].as_vec().map(|v| {
-            v.iter()
-                .map(|i| i.as_str().map(|s| s.to_string()))
-                .flatten()
-                .collect()
-        });
-        let max_sessions = yaml_conf[0]["max_sessions"]
-            .as_i64()
-            .map(u64::try_from)
-            .unwrap_or(Ok(DEFAULT_MAX_SESSIONS))
-            .context("Parsing max_sessions from YAML file.")?;
+    pub fn get_api_token(&self) -> Option<&String> {
+        self.api_token.as_ref()
+    }
 
-        let match_map: Option<Vec<MatchEntry>> = yaml_conf[0]["match"]
-            .as_vec()
-            .map(|match_entry| -> Result<Vec<MatchEntry>> {
-                Result::Ok(
-                    match_entry
-                        .iter()
-                        .map(Self::match_entry_from_yaml)
-                        .collect::<Result<Vec<MatchEntry>>>()
-                        .map_err(|e| anyhow!("{e}, reading entries from 'match' section"))?,
-                )
-            })
-            .transpose()?;
+    /// Address of the SNMP agent, see [`crate::snmp`].
+    pub fn get_snmp_addr(&self) -> Option<SocketAddr> {
+        self.snmp_addr
+    }
 
-        Ok(Self {
-            default,
-            ifaces,
-            tftp_server_dir,
-            max_sessions,
-            match_map,
-        })
+    /// Community of the SNMP requests, `public` by default.
+    pub fn get_snmp_community(&self) -> &str {
+        self.snmp_community.as_deref().unwrap_or(DEFAULT_SNMP_COMMUNITY)
     }
 
-    fn match_entry_from_yaml(item: &yaml_rust2::Yaml) -> Result<MatchEntry> {
-        let conf = Conf::base_conf_from_yaml(&item["conf"])?
-            .ok_or(anyhow!("No configuration found for match entry"))?;
+    pub fn get_log_output(&self) -> LogOutput {
+        self.log_output
+    }
 
-        let match_type = item["match_type"]
-            .as_str()
-            .map(|s| match s.to_lowercase().as_str() {
-                "any" => Ok(MatchType::Any),
-                "all" => Ok(MatchType::All),
-                _ => Err(anyhow!("Invalid match type: {s}")),
-            })
-            .unwrap_or(Ok(MatchType::All))?;
+    pub fn get_syslog_server(&self) -> Option<SyslogServer> {
+        self.syslog_server
+    }
 
-        let regex = item["regex"].as_bool().unwrap_or(false);
-        let fields_values = item["select"]
-            .as_hash()
-            .map(|yaml_obj| -> Result<HashMap<String, FieldValue>> {
-                Result::Ok(
-                    yaml_obj
-                        .iter()
-                        .map(|(key, value)| {
-                            let key_str = key
-                                .as_str()
-                                .ok_or(anyhow!("Expected a string key"))?
-                                .to_string();
-                            Ok((
-                                key_str,
-                                FieldValue::from_string(
-                                    value
-                                        .as_str()
-                                        .ok_or(anyhow!("Expected a string value"))?
-                                        .to_string(),
-                                    regex,
-                                )
-                                .map_err(|e| {
-                                    anyhow!(
-                                        "{e}, reading field \"{}\"",
-                                        key.as_str().unwrap_or_default()
-                                    )
-                                })?,
-                            ))
-                        })
-                        .collect::<Result<HashMap<String, FieldValue>>>()?,
-                )
-            })
-            .transpose()?
-            .ok_or(anyhow!("Expected a hash for select"))?;
+    pub fn get_pcap_file(&self) -> Option<&PathBuf> {
+        self.pcap_file.as_ref()
+    }
 
-        Ok(MatchEntry {
-            conf,
-            fields_values,
-            match_type,
-            regex,
-        })
+    /// Size in bytes a capture file is rotated at.
+    pub fn get_pcap_max_size(&self) -> u64 {
+        self.pcap_max_size.saturating_mul(1024 * 1024)
     }
 
-    fn base_conf_from_yaml(yaml_conf: &yaml_rust2::Yaml) -> Result<Option<ConfEntry>> {
-        yaml_conf
-            .as_hash()
-            .map(|yaml_obj| {
-                let boot_file = yaml_obj
-                    .get(&Yaml::from_str("boot_file"))
-                    .map(|v| v.as_str().map(|s| s.to_string()))
-                    .flatten();
-                let boot_server_ipv4 = yaml_obj
-                    .get(&Yaml::from_str("boot_server_ipv4"))
-                    .map(|v| {
-                        v.as_str().map_or(Result::Ok(None), |s: &str| {
-                            Ok(Some(Ipv4Addr::from_str(s).map_err(|o| {
-                                anyhow!("IPv4 parsing error: {}", o.to_string())
-                            })?))
-                        })
-                    })
-                    .map_or(Ok(None), |i: Result<Option<Ipv4Addr>>| i)?;
+    pub fn get_pcap_files(&self) -> u64 {
+        self.pcap_files
+    }
 
-                Ok(ConfEntry {
-                    boot_file,
-                    boot_server_ipv4,
-                })
-            })
-            .transpose()
+    pub fn get_pcap_tftp(&self) -> bool {
+        self.pcap_tftp
     }
 
-    pub fn merge_left_into_default(&mut self, other: &ConfEntry) {
-        self.default = self
-            .default
-            .as_ref()
-            .map(|mine| ConfEntry {
-                boot_file: mine.boot_file.clone().or(other.boot_file.clone()),
-                boot_server_ipv4: mine.boot_server_ipv4.clone().or(other.boot_server_ipv4),
-            })
-            .or(Some(other.clone()));
+    /// File the boot decisions are appended to, see [`crate::audit`].
+    pub fn get_audit_log(&self) -> Option<&PathBuf> {
+        self.audit_log.as_ref()
     }
 
-    pub fn get_ifaces(&self) -> Option<&Vec<String>> {
-        self.ifaces.as_ref()
+    /// Size in bytes the audit log is rotated at.
+    pub fn get_audit_log_max_size(&self) -> u64 {
+        self.audit_log_max_size.saturating_mul(1024 * 1024)
     }
 
-    pub fn get_tftp_serve_path(&self) -> Option<String> {
-        self.tftp_server_dir.clone()
+    pub fn get_audit_log_files(&self) -> u64 {
+        self.audit_log_files
+    }
+
+    /// File the decoded DHCP messages are appended to, see [`crate::traffic`].
+    pub fn get_traffic_log(&self) -> Option<&PathBuf> {
+        self.traffic_log.as_ref()
+    }
+
+    /// Size in bytes the traffic log is rotated at.
+    pub fn get_traffic_log_max_size(&self) -> u64 {
+        self.traffic_log_max_size.saturating_mul(1024 * 1024)
+    }
+
+    pub fn get_traffic_log_files(&self) -> u64 {
+        self.traffic_log_files
+    }
+
+    /// File the events of the clients are appended to, see
+    /// [`crate::timeline`].
+    pub fn get_event_log(&self) -> Option<&PathBuf> {
+        self.event_log.as_ref()
+    }
+
+    /// Size in bytes the event log is rotated at.
+    pub fn get_event_log_max_size(&self) -> u64 {
+        self.event_log_max_size.saturating_mul(1024 * 1024)
+    }
+
+    pub fn get_event_log_files(&self) -> u64 {
+        self.event_log_files
+    }
+
+    /// File the boot statistics are saved to, see [`crate::stats`].
+    pub fn get_stats_file(&self) -> Option<&PathBuf> {
+        self.stats_file.as_ref()
+    }
+
+    /// File the clients seen are saved to, see [`crate::assets`].
+    pub fn get_assets_file(&self) -> Option<&PathBuf> {
+        self.assets_file.as_ref()
+    }
+
+    /// The configuration of the instance `name` of `--instance-name`, the
+    /// default one without it.
+    pub fn with_instance_name(mut self, name: Option<String>) -> Self {
+        self.instance_name = name;
+        self
+    }
+
+    pub fn get_instance_name(&self) -> Option<&str> {
+        self.instance_name.as_deref()
+    }
+
+    /// Instance names go in the paths of the lock and the control socket,
+    /// so they're limited to `[A-Za-z0-9_-]`.
+    pub fn is_valid_instance_name(name: &str) -> bool {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    /// Account the server drops to once its sockets are bound, `None` to
+    /// keep running as started.
+    pub fn get_user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Group the server drops to, that of `user` without it.
+    pub fn get_group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    /// Whether the process is confined once started, see [`crate::sandbox`].
+    pub fn get_sandbox(&self) -> bool {
+        self.sandbox
+    }
+
+    /// The files and directories read after starting, on reloads or for
+    /// the requests: the certificates and keys, the autoinstall templates,
+    /// the hosts files and the secrets of `file:` references, those of the
+    /// tenants included.
+    pub fn read_paths(&self) -> Vec<PathBuf> {
+        let files = [
+            &self.https_cert,
+            &self.https_key,
+            &self.https_client_ca,
+            &self.signing_cert,
+            &self.signing_key,
+            &self.autoinstall_dir,
+        ];
+        let secrets = self
+            .secret_refs
+            .values()
+            .filter_map(|reference| reference.strip_prefix("file:"))
+            .map(PathBuf::from);
+        let mut paths: Vec<PathBuf> = files
+            .into_iter()
+            .flatten()
+            .cloned()
+            .chain(self.get_host_files().into_iter().map(Path::to_path_buf))
+            .chain(secrets)
+            .chain(self.tenants.values().flat_map(Conf::read_paths))
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    /// Whether boot files missing from the TFTP root fail the validation
+    /// rather than being warned about.
+    pub fn get_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Whether only the clients matching a `match` entry are answered.
+    pub fn get_match_policy(&self) -> MatchPolicy {
+        self.match_policy
+    }
+
+    pub fn get_require_match(&self) -> bool {
+        self.require_match
     }
 
     fn get_mac_from_doc_string(doc: &serde_json::Value) -> Result<String> {
         let client_mac: String = doc
             .as_array()
             .and_then(|list| {
-                Some(
-                    list.iter()
-                        .take(6)
-                        .map(|value| {
-                            value
-                                .as_u64()
-                                .map(|byte| Some(format!("{:0>2X}", byte)))
-                                .flatten()
-                        })
-                        .collect::<Option<Vec<String>>>(),
-                )
+                list.iter()
+                    .take(6)
+                    .map(|value| value.as_u64().map(|byte| format!("{:0>2X}", byte)))
+                    .collect::<Option<Vec<String>>>()
             })
-            .flatten()
             .ok_or(anyhow!("Expected MAC address to be an array of numbers."))?
             .join(":");
 
         Ok(client_mac)
     }
 
-    fn is_match<'a>(doc: &'a serde_json::Value, match_entry: &'a MatchEntry) -> bool {
-        let matcher = |cfg_key: &'a String, cfg_value: &'a FieldValue| {
-            move |doc_value: &serde_json::Value| {
-                let default_converter: FieldConverter =
-                    |v: &serde_json::Value| -> Result<String> { Ok(v.to_string()) };
-                let doc_val_converter = FIELD_CONVERTERS
-                    .get(cfg_key.as_str())
-                    .unwrap_or(&default_converter);
-                let converted_value = doc_val_converter(doc_value).unwrap_or(doc_value.to_string());
-                let match_result = cfg_value.matches(&converted_value);
-                let match_type = if match_entry.regex { "regex" } else { "exact" };
-
-                trace!("Matching {match_type} field {cfg_key}=\"{converted_value}\" to \"{cfg_value}\", matching = {match_result}");
-                match_result
-            }
-        };
-
-        match match_entry.match_type {
-            MatchType::Any => match_entry.fields_values.iter().any(|(key, config_value)| {
-                doc.get(Self::get_remapped_key(key))
-                    .or(doc
-                        .get("opts")
-                        .and_then(|opts| opts.get(key))
-                        .and_then(|opts_key| opts_key.get(key)))
-                    .map(matcher(key, config_value))
-                    .unwrap_or(false)
-            }),
-            MatchType::All => match_entry.fields_values.iter().all(|(key, config_value)| {
-                doc.get(Self::get_remapped_key(key))
-                    .or(doc
-                        .get("opts")
-                        .and_then(|opts| opts.get(key))
-                        .and_then(|opts_key| opts_key.get(key)))
-                    .map(matcher(key, config_value))
-                    .unwrap_or(false)
-            }),
-        }
+    fn get_remapped_key(key: &str) -> &str {
+        FIELD_MAP.get(key).unwrap_or(&key)
     }
 
-    fn get_remapped_key<'a>(key: &'a str) -> &'a str {
-        FIELD_MAP.get(key).unwrap_or(&key)
+    /// `default` of the client of `doc`, the one of its interface in
+    /// `defaults` when there's one.
+    fn default_for(&self, doc: &dyn ClientFields) -> Option<&ConfEntry> {
+        doc.field("Interface")
+            .and_then(|iface| self.iface_defaults.get(iface.as_ref()))
+            .or(self.default.as_ref())
+    }
+
+    /// Configuration of the client of `doc` given by `entry`, completed with
+    /// its `default`, like match entries are.
+    pub fn with_default<'a>(
+        &'a self,
+        entry: &'a ConfEntry,
+        doc: &dyn ClientFields,
+    ) -> ConfEntryRef<'a> {
+        entry.merge_refs(self.default_for(doc))
     }
 
-    pub fn get_from_doc<'a>(&'a self, doc: serde_json::Value) -> Result<Option<ConfEntryRef>> {
+    pub fn get_from_doc(&self, doc: impl ClientFields) -> Result<Option<ConfEntryRef<'_>>> {
+        self.get_from_doc_at(doc, SystemTime::now())
+    }
+
+    /// Configuration of the client of `doc` at the time `now`, for the match
+    /// entries active at some times only.
+    pub fn get_from_doc_at(
+        &self,
+        doc: impl ClientFields,
+        now: SystemTime,
+    ) -> Result<Option<ConfEntryRef<'_>>> {
+        let default = self.default_for(&doc);
         let matched_conf = self
-            .match_map
-            .as_ref()
-            .map(|matches| {
-                matches
-                    .iter()
-                    .find(|match_entry| Self::is_match(&doc, match_entry))
+            .used_match_entry(&doc, now)
+            .inspect(|(index, match_entry)| {
+                debug!(
+                    "Using match[{index}], priority {}, selected by the {} policy.",
+                    match_entry.priority, self.match_policy
+                )
             })
-            .flatten()
-            .map(|m| &m.conf)
+            .map(|(_, m)| m.conf_for(&doc))
             .inspect(|conf| trace!("Found matching entry from 'match' rule.\n{:#?}", conf))
             .or_else(|| {
                 trace!("No matching entry found from 'match' rule.");
-                self.default.as_ref()
+                if self.require_match {
+                    debug!("Ignoring the client, none of the 'match' rules matches it.");
+                    return None;
+                }
+                default
             });
 
         let result = matched_conf
-            .map(|cfg| cfg.merge_refs(self.default.as_ref()))
+            .map(|cfg| cfg.merge_refs(default))
             .inspect(|conf| trace!("Final result combined with default:\n{:#?}", conf))
             .or_else(|| {
                 trace!(
@@ -507,7 +4713,329 @@ impl Conf {
         Ok(result)
     }
 
+    /// Indexes of the `match` entries matching the client of `doc` at the
+    /// time `now`, in their order.
+    pub fn matching_entries_at(&self, doc: &dyn ClientFields, now: SystemTime) -> Vec<usize> {
+        let now = OffsetDateTime::from(now);
+        self.match_map
+            .iter()
+            .flatten()
+            .enumerate()
+            .filter(|(_, match_entry)| match_entry.matches(doc, now))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Index of the `match` entry used for the client of `doc` at the time
+    /// `now`, `None` when none matches it.
+    pub fn used_entry_at(&self, doc: &dyn ClientFields, now: SystemTime) -> Option<usize> {
+        self.used_match_entry(doc, now).map(|(index, _)| index)
+    }
+
+    fn used_match_entry(
+        &self,
+        doc: &dyn ClientFields,
+        now: SystemTime,
+    ) -> Option<(usize, &MatchEntry)> {
+        let entries = self.match_map.as_deref().unwrap_or_default();
+        let Some(key) = self.decisions.key(entries, doc) else {
+            return self.decide_match_entry(doc, now);
+        };
+        let decided = Instant::now();
+        if let Some(index) = self.decisions.get(&key, decided) {
+            trace!("Using the match entry decided lately for the client.");
+            return index.map(|index| (index, &entries[index]));
+        }
+        let used = self.decide_match_entry(doc, now);
+        self.decisions.insert(key, used.map(|(index, _)| index), decided);
+        used
+    }
+
+    /// The `match` entry used for the client of `doc` at the time `now`,
+    /// with its index, none when none matches it.
+    fn decide_match_entry(
+        &self,
+        doc: &dyn ClientFields,
+        now: SystemTime,
+    ) -> Option<(usize, &MatchEntry)> {
+        let now = OffsetDateTime::from(now);
+
+        // The highest priority wins, then the most specific entry when chosen
+        // so, then the first in the order of definition
+        let matches = self
+            .match_map
+            .iter()
+            .flatten()
+            .enumerate()
+            .filter(|(_, match_entry)| match_entry.matches(doc, now));
+        match self.match_policy {
+            MatchPolicy::First => {
+                matches.max_by_key(|(index, match_entry)| (match_entry.priority, Reverse(*index)))
+            }
+            MatchPolicy::MostSpecific => matches.max_by_key(|(index, match_entry)| {
+                (
+                    match_entry.priority,
+                    match_entry.specificity(doc),
+                    match_entry.condition.mode == MatchMode::Exact,
+                    Reverse(*index),
+                )
+            }),
+        }
+    }
+
     pub fn get_max_sessions(&self) -> u64 {
         self.max_sessions
     }
+
+    pub fn get_max_sessions_per_iface(&self) -> &BTreeMap<String, u64> {
+        &self.max_sessions_per_iface
+    }
+
+    pub fn get_max_sessions_per_subnet(&self) -> &[(Network, u64)] {
+        &self.max_sessions_per_subnet
+    }
+
+    pub fn get_dhcp_max_handlers(&self) -> u64 {
+        self.dhcp_max_handlers
+    }
+
+    /// Whether the PXE boot server requests of port 4011 are answered.
+    pub fn get_pxe_boot_server(&self) -> bool {
+        self.pxe_boot_server
+    }
+
+    /// Sockets the PXE boot server receives on, on each interface.
+    pub fn get_pxe_boot_server_workers(&self) -> u64 {
+        self.pxe_boot_server_workers
+    }
+
+    /// Whether the DHCP service is run, `dhcp.enabled`.
+    pub fn get_dhcp_enabled(&self) -> bool {
+        self.dhcp_enabled
+    }
+
+    /// Whether the TFTP servers are started, `tftp.enabled`. The files of
+    /// `tftp_server_dir` are served over HTTP either way.
+    pub fn get_tftp_enabled(&self) -> bool {
+        self.tftp_enabled
+    }
+
+    /// The configuration in effect, as YAML: the files and environment
+    /// variables merged, the defaults of the fields not set included. The
+    /// secrets are redacted.
+    pub fn to_yaml(&self) -> Result<String> {
+        let mut yaml = String::new();
+        yaml_rust2::YamlEmitter::new(&mut yaml).dump(&self.to_yaml_value())?;
+        yaml.push('\n');
+
+        Ok(yaml)
+    }
+
+    fn to_yaml_value(&self) -> Yaml {
+        const REDACTED: &str = "<redacted>";
+        let int = |value: Option<u64>| {
+            value.map_or(Yaml::Null, |value| Yaml::Integer(value as i64))
+        };
+        let path = |path: &Option<PathBuf>| yaml_str(path.as_ref().map(|path| path.display()));
+        let strings = |values: &[String]| yaml_list(values.iter().map(|s| yaml_str(Some(s))));
+        let tftp_symlinks = match self.tftp_symlinks {
+            SymlinkPolicy::Deny => "deny",
+            SymlinkPolicy::DenyEscaping => "deny-escaping",
+            SymlinkPolicy::Allow => "allow",
+        };
+        // Secrets shown as the references they're read from, if any
+        let secret = |is_set: bool, name: &str| {
+            let reference = self.secret_refs.get(name).map(String::as_str);
+            yaml_str(is_set.then_some(reference.unwrap_or(REDACTED)))
+        };
+        let upload_users = self
+            .upload_users
+            .keys()
+            .map(|user| (user, secret(true, &format!("upload_users.{user}"))));
+        let webhook_url = match self.secret_refs.get("webhook_url") {
+            Some(reference) => yaml_str(Some(reference)),
+            None => yaml_str(self.webhook_url.as_deref().map(redact_url)),
+        };
+        let sessions_per_iface =
+            self.max_sessions_per_iface.iter().map(|(iface, quota)| (iface, int(Some(*quota))));
+        let sessions_per_subnet = self
+            .max_sessions_per_subnet
+            .iter()
+            .map(|(network, quota)| (network.to_string(), int(Some(*quota))));
+        let matches = self.match_map.iter().flatten().map(|match_entry| {
+            let mut fields = match_entry.condition.yaml_fields();
+            // After `active`, in the order of the reference
+            fields.insert(5, ("conf", match_entry.conf.to_yaml()));
+            let hosts = match_entry.hosts.as_ref().map(|hosts| hosts.path.display());
+            fields.push(("hosts", yaml_str(hosts.as_ref())));
+            let canary = match_entry.canary.as_ref().map_or(Yaml::Null, |canary| {
+                yaml_mapping([
+                    ("percent", Yaml::Integer(canary.percent.into())),
+                    ("seed", yaml_str(Some(&canary.seed).filter(|seed| !seed.is_empty()))),
+                    ("conf", canary.conf.to_yaml()),
+                ])
+            });
+            fields.push(("canary", canary));
+            let priority = (match_entry.priority != 0).then_some(match_entry.priority);
+            fields.push(("priority", priority.map_or(Yaml::Null, Yaml::Integer)));
+            yaml_mapping(fields)
+        });
+        let images = self.images.iter().map(|image| {
+            let source = yaml_mapping([
+                ("url", yaml_str(Some(&image.url))),
+                ("sha256", yaml_str(image.sha256.as_ref())),
+                ("sha256sums_url", yaml_str(image.sha256sums_url.as_ref())),
+                ("minisign_key", yaml_str(image.minisign_key.as_ref())),
+            ]);
+            (image.path.display(), source)
+        });
+        let mirrors = self
+            .mirrors
+            .iter()
+            .map(|mirror| (mirror.path.display(), yaml_str(Some(&mirror.url))));
+        let iface_defaults = self
+            .iface_defaults
+            .iter()
+            .map(|(name, entry)| (name, entry.to_yaml()));
+        let profiles = self
+            .profiles
+            .iter()
+            .map(|(name, entry)| (name, entry.to_yaml()));
+        let event_hooks = self
+            .event_hooks
+            .iter()
+            .map(|(event, hook)| (event, yaml_str(Some(hook.display()))));
+        let event_webhooks = self.event_webhooks.iter().enumerate().map(|(index, webhook)| {
+            let url = match self.secret_refs.get(&format!("event_webhooks[{index}].url")) {
+                Some(reference) => yaml_str(Some(reference)),
+                None => yaml_str(Some(redact_url(&webhook.url))),
+            };
+            let events = webhook.events.iter().map(|event| yaml_str(Some(event)));
+            yaml_mapping([("url", url), ("events", yaml_list(events))])
+        });
+        let notifications = self.notifications.iter().enumerate().map(|(index, notification)| {
+            let url = match self.secret_refs.get(&format!("notifications[{index}].url")) {
+                Some(reference) => yaml_str(Some(reference)),
+                None => yaml_str(Some(redact_url(&notification.url))),
+            };
+            let to = notification.to.iter().map(|to| yaml_str(Some(to)));
+            let events = notification.events.iter().map(|event| yaml_str(Some(event)));
+            yaml_mapping([
+                ("kind", yaml_str(Some(notification.kind))),
+                ("url", url),
+                ("from", yaml_str(notification.from.as_ref())),
+                ("to", yaml_list(to)),
+                ("events", yaml_list(events)),
+            ])
+        });
+        let fallbacks = self
+            .tftp_fallbacks
+            .iter()
+            .map(|fallback| (fallback.prefix.display(), yaml_str(Some(fallback.file.display()))));
+
+        // The fields tenants set, the others being those of the top level
+        let tenants = self.tenants.iter().map(|(name, tenant)| {
+            let mut fields = tenant.to_yaml_value().into_hash().unwrap_or_default();
+            fields.retain(|key, _| key.as_str().is_some_and(|key| TENANT_FIELDS.contains(&key)));
+            (name, Yaml::Hash(fields))
+        });
+
+        yaml_mapping([
+            ("version", int(Some(CONF_VERSION))),
+            ("default", self.default.as_ref().map_or(Yaml::Null, ConfEntry::to_yaml)),
+            ("defaults", yaml_mapping(iface_defaults)),
+            ("profiles", yaml_mapping(profiles)),
+            ("match", yaml_list(matches)),
+            ("match_policy", yaml_str(Some(self.match_policy))),
+            ("require_match", Yaml::Boolean(self.require_match)),
+            ("ifaces", self.ifaces.as_deref().map_or(Yaml::Null, strings)),
+            ("max_sessions", int(Some(self.max_sessions))),
+            ("max_sessions_per_iface", yaml_mapping(sessions_per_iface)),
+            ("max_sessions_per_subnet", yaml_mapping(sessions_per_subnet)),
+            ("dhcp_max_handlers", int(Some(self.dhcp_max_handlers))),
+            ("pxe_boot_server", Yaml::Boolean(self.pxe_boot_server)),
+            ("pxe_boot_server_workers", int(Some(self.pxe_boot_server_workers))),
+            ("dhcp", yaml_mapping([("enabled", Yaml::Boolean(self.dhcp_enabled))])),
+            ("tftp", yaml_mapping([("enabled", Yaml::Boolean(self.tftp_enabled))])),
+            ("tftp_server_dir", yaml_str(self.tftp_server_dir.as_ref())),
+            ("tftp_symlinks", yaml_str(Some(tftp_symlinks))),
+            ("tftp_max_transfers", int(Some(self.tftp_max_transfers))),
+            ("tftp_max_transfers_per_client", int(Some(self.tftp_max_transfers_per_client))),
+            ("tftp_serve_hidden", Yaml::Boolean(self.tftp_file_filter.serve_hidden)),
+            (
+                "tftp_allowed_extensions",
+                self.tftp_file_filter.allowed_extensions.as_deref().map_or(Yaml::Null, strings),
+            ),
+            ("tftp_denied_extensions", strings(&self.tftp_file_filter.denied_extensions)),
+            ("tftp_fallbacks", yaml_mapping(fallbacks)),
+            ("tftp_block_size_limit", int(self.tftp_block_size_limit.map(u64::from))),
+            ("tftp_upstream", yaml_str(self.tftp_upstream)),
+            ("http_port", int(self.http_port.map(u64::from))),
+            ("https_port", int(self.https_port.map(u64::from))),
+            ("https_cert", path(&self.https_cert)),
+            ("https_key", path(&self.https_key)),
+            ("https_client_ca", path(&self.https_client_ca)),
+            ("autoinstall_dir", path(&self.autoinstall_dir)),
+            ("netbootxyz", Yaml::Boolean(self.netbootxyz)),
+            ("images", yaml_mapping(images)),
+            ("mirrors", yaml_mapping(mirrors)),
+            ("image_versions", int(Some(self.image_versions))),
+            ("image_refresh_interval", int(self.image_refresh_interval)),
+            ("secure_boot", Yaml::Boolean(self.secure_boot)),
+            ("ipxe_chainload", Yaml::Boolean(self.ipxe_chainload)),
+            ("strict", Yaml::Boolean(self.strict)),
+            ("upload_token", secret(self.upload_token.is_some(), "upload_token")),
+            ("upload_users", yaml_mapping(upload_users)),
+            ("upload_max_size", int(Some(self.upload_max_size))),
+            ("signing_cert", path(&self.signing_cert)),
+            ("signing_key", path(&self.signing_key)),
+            ("boot_hostname", yaml_str(self.boot_hostname.as_ref())),
+            ("dns_port", int(Some(self.get_dns_port().into()))),
+            ("reverse_dns", Yaml::Boolean(self.reverse_dns)),
+            ("reverse_dns_server", yaml_str(self.reverse_dns_server.as_ref())),
+            ("webhook_url", webhook_url),
+            ("webhook_timeout", int(Some(self.get_webhook_timeout().as_millis() as u64))),
+            ("boot_hook", path(&self.boot_hook)),
+            ("event_hooks", yaml_mapping(event_hooks)),
+            ("event_webhooks", yaml_list(event_webhooks)),
+            ("notifications", yaml_list(notifications)),
+            ("mqtt_broker", yaml_str(self.mqtt_broker.as_ref())),
+            ("mqtt_topic", yaml_str(self.mqtt_topic.as_ref())),
+            ("mqtt_username", yaml_str(self.mqtt_username.as_ref())),
+            ("mqtt_password", secret(self.mqtt_password.is_some(), "mqtt_password")),
+            ("mqtt_retain", Yaml::Boolean(self.mqtt_retain)),
+            ("netbox_url", yaml_str(self.netbox_url.as_deref().map(redact_url))),
+            ("netbox_token", secret(self.netbox_token.is_some(), "netbox_token")),
+            ("inventory_cache_ttl", int(Some(self.get_inventory_cache_ttl().as_secs()))),
+            ("control_socket", yaml_str(Some(self.get_control_socket().display()))),
+            ("dashboard_addr", yaml_str(self.dashboard_addr)),
+            ("api_addr", yaml_str(self.api_addr)),
+            ("grpc_addr", yaml_str(self.grpc_addr)),
+            ("health_addr", yaml_str(self.health_addr)),
+            ("api_token", secret(self.api_token.is_some(), "api_token")),
+            ("snmp_addr", yaml_str(self.snmp_addr)),
+            ("snmp_community", secret(self.snmp_community.is_some(), "snmp_community")),
+            ("log_output", yaml_str(Some(self.log_output))),
+            ("syslog_server", yaml_str(self.syslog_server)),
+            ("pcap_file", path(&self.pcap_file)),
+            ("pcap_max_size", int(Some(self.pcap_max_size))),
+            ("pcap_files", int(Some(self.pcap_files))),
+            ("pcap_tftp", Yaml::Boolean(self.pcap_tftp)),
+            ("audit_log", path(&self.audit_log)),
+            ("audit_log_max_size", int(Some(self.audit_log_max_size))),
+            ("audit_log_files", int(Some(self.audit_log_files))),
+            ("traffic_log", path(&self.traffic_log)),
+            ("traffic_log_max_size", int(Some(self.traffic_log_max_size))),
+            ("traffic_log_files", int(Some(self.traffic_log_files))),
+            ("event_log", path(&self.event_log)),
+            ("event_log_max_size", int(Some(self.event_log_max_size))),
+            ("event_log_files", int(Some(self.event_log_files))),
+            ("stats_file", path(&self.stats_file)),
+            ("assets_file", path(&self.assets_file)),
+            ("user", yaml_str(self.user.as_ref())),
+            ("group", yaml_str(self.group.as_ref())),
+            ("sandbox", Yaml::Boolean(self.sandbox)),
+            ("tenants", yaml_mapping(tenants)),
+        ])
+    }
 }
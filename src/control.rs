@@ -0,0 +1,351 @@
+//! Control socket of the running server, a Unix socket local commands such
+//! as `sessions` and `status` ask it on. A command is a line with its name, answered with
+//! a JSON document before the connection is closed, the answer of `handover`
+//! carrying the descriptors of the sockets too. `watch` is answered with
+//! the events of the clients instead, a line each, until the connection is
+//! closed. There's none on Windows.
+#[cfg(unix)]
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::{DirBuilderExt, PermissionsExt},
+        net::UnixStream,
+    },
+};
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+#[cfg(unix)]
+use anyhow::Context;
+#[cfg(unix)]
+use async_std::{
+    future::timeout,
+    io::{prelude::BufReadExt, BufReader as AsyncBufReader, WriteExt},
+    os::unix::net::{UnixListener, UnixStream as AsyncUnixStream},
+    stream::StreamExt,
+    task,
+};
+#[cfg(unix)]
+use log::{debug, warn};
+use log::info;
+
+use crate::{
+    conf::Conf,
+    dhcp::{self, SessionInfo, Sessions, SharedConf},
+    handover::Handover,
+    reload::Reloader,
+    status::{Health, ServiceHealth},
+    tracker::BootTracker,
+    Result,
+};
+
+/// Time a command is waited for, and answered within.
+#[cfg_attr(not(unix), allow(dead_code))]
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+/// Time `reload` is answered within, remote configurations being fetched.
+#[cfg_attr(not(unix), allow(dead_code))]
+const RELOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the commands are answered from.
+pub struct Control {
+    pub started: SystemTime,
+    /// Where the configuration was loaded from.
+    pub origin: String,
+    pub tracker: Arc<BootTracker>,
+    /// The top level configuration, then the tenants.
+    pub services: Vec<ControlledService>,
+    pub reloader: Reloader,
+    /// Told once the server is taken over.
+    pub handover: Handover,
+}
+
+/// The top level configuration or a tenant, as it runs.
+pub struct ControlledService {
+    pub tenant: Option<String>,
+    pub shared_conf: SharedConf,
+    /// Sessions of its DHCP service, `None` with DHCP disabled.
+    pub sessions: Option<Sessions>,
+}
+
+#[cfg_attr(not(unix), allow(dead_code))]
+impl Control {
+    async fn answer(&self, command: &str) -> serde_json::Value {
+        match command {
+            "sessions" => serde_json::json!(self.sessions().await),
+            "status" => serde_json::json!(self.health().await),
+            "stats" => serde_json::json!(self.tracker.stats().snapshot()),
+            "assets" => serde_json::json!(self.tracker.assets().list()),
+            "transfers" => serde_json::json!(self.tracker.analytics().snapshot()),
+            "reload" => match self.reloader.reload().await {
+                Ok(()) => serde_json::json!({ "reloaded": true }),
+                Err(e) => serde_json::json!({ "error": format!("{e:#}") }),
+            },
+            "handover" => serde_json::json!({ "error": "Taking over a running server is only supported on Linux" }),
+            _ => serde_json::json!({ "error": format!("Unknown command {command}") }),
+        }
+    }
+
+    pub(crate) async fn sessions(&self) -> Vec<SessionInfo> {
+        let now = SystemTime::now();
+        let mut listed = Vec::new();
+        for service in &self.services {
+            let Some(sessions) = &service.sessions else {
+                continue;
+            };
+            let sessions = sessions.list(now);
+            listed.extend(sessions.into_iter().map(|session| SessionInfo {
+                tenant: service.tenant.clone(),
+                ..session
+            }));
+        }
+
+        listed
+    }
+
+    /// Ends the DHCP session of `xid`, in whichever service has it, returns
+    /// whether one had.
+    pub(crate) async fn expire_session(&self, xid: u32) -> bool {
+        let mut expired = false;
+        for sessions in self.services.iter().filter_map(|service| service.sessions.as_ref()) {
+            expired |= sessions.expire(xid);
+        }
+
+        expired
+    }
+
+    pub(crate) async fn health(&self) -> Health {
+        let mut services = Vec::new();
+        for service in &self.services {
+            let conf = dhcp::current_conf(&service.shared_conf);
+            let dhcp_ifaces = service.sessions.as_ref().map(|sessions| sessions.ifaces());
+            services.push(ServiceHealth {
+                tenant: service.tenant.clone(),
+                dhcp_ifaces,
+                tftp_dir: conf.get_tftp_serve_path().filter(|_| conf.get_tftp_enabled()),
+            });
+        }
+
+        Health {
+            version: crate_version!().to_string(),
+            uptime_secs: self.started.elapsed().unwrap_or_default().as_secs(),
+            configuration: self.origin.clone(),
+            counts: self.tracker.counts(),
+            ifaces: self.tracker.iface_counts(),
+            services,
+        }
+    }
+}
+
+/// Listens on the `control_socket` of `conf`, answering with `control`. The
+/// server runs without it when the socket can't be created, e.g. without
+/// the permission to.
+#[cfg(unix)]
+pub fn spawn_control_service_async(conf: &Conf, control: Arc<Control>) -> Result<()> {
+    let path = conf.get_control_socket();
+    let listener = match task::block_on(bind(&path)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("Control socket not started, sessions and status won't answer: {e:#}");
+            return Ok(());
+        }
+    };
+    info!("Control socket listening on {}", path.display());
+
+    task::spawn(async move {
+        let mut incoming = listener.incoming();
+        while let Some(stream) = incoming.next().await {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let control = Arc::clone(&control);
+            task::spawn(async move {
+                if let Err(e) = serve_connection(stream, &control).await {
+                    debug!("Control socket connection failed: {e}");
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn spawn_control_service_async(_conf: &Conf, _control: Arc<Control>) -> Result<()> {
+    info!("Control socket not started, sessions and status won't answer: Unix sockets are needed");
+    Ok(())
+}
+
+/// Binds `path`, replacing the socket a previous run left, readable by the
+/// user of the server only since it tells about the clients. It's bound in
+/// a directory of that user only and moved to `path` once restricted, so
+/// it's never open to others, even for a moment.
+#[cfg(unix)]
+async fn bind(path: &Path) -> Result<UnixListener> {
+    let name = path.file_name().context("The control socket needs a file name")?;
+    let private_dir = path.with_file_name(format!(
+        ".{}.{}",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private_dir)
+        .with_context(|| format!("Creating {}", private_dir.display()))?;
+
+    let staged = private_dir.join("socket");
+    let bound = async {
+        let listener = UnixListener::bind(&staged)
+            .await
+            .with_context(|| format!("Binding {}", path.display()))?;
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&staged, path)
+            .with_context(|| format!("Replacing the socket {}", path.display()))?;
+        Ok(listener)
+    }
+    .await;
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&private_dir);
+
+    bound
+}
+
+#[cfg(unix)]
+async fn serve_connection(stream: AsyncUnixStream, control: &Control) -> Result<()> {
+    let mut command = String::new();
+    timeout(
+        COMMAND_TIMEOUT,
+        AsyncBufReader::new(&stream).read_line(&mut command),
+    )
+    .await??;
+    let command = command.trim();
+    #[cfg(target_os = "linux")]
+    if command == "handover" {
+        return timeout(answer_timeout(command), crate::handover::hand_over(&stream, control)).await?;
+    }
+    if let Some(mac_address) = watched(command) {
+        return watch(&stream, mac_address).await;
+    }
+    let answer = timeout(answer_timeout(command), control.answer(command)).await?;
+    (&stream)
+        .write_all(format!("{answer}\n").as_bytes())
+        .await?;
+
+    Ok(())
+}
+
+/// The clients watched by `command`, when it's `watch`, all of them or
+/// those of the MAC address following it.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn watched(command: &str) -> Option<Option<String>> {
+    match command.split_once(' ') {
+        Some(("watch", mac_address)) => Some(Some(mac_address.trim().to_string())),
+        None if command == "watch" => Some(None),
+        _ => None,
+    }
+}
+
+/// Sends the events of the clients of `mac_address`, or all of them, as
+/// lines of JSON, with empty lines as keep-alives, until the watcher is
+/// gone.
+#[cfg(unix)]
+async fn watch(stream: &AsyncUnixStream, mac_address: Option<String>) -> Result<()> {
+    let mut events = crate::events::watch_json(mac_address);
+    let mut writer = stream;
+    while let Some(event) = events.next().await {
+        let line = event.map(|event| event.to_string()).unwrap_or_default();
+        writer.write_all(format!("{line}\n").as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+/// Time `command` is answered within.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn answer_timeout(command: &str) -> Duration {
+    match command {
+        "reload" => RELOAD_TIMEOUT,
+        _ => COMMAND_TIMEOUT,
+    }
+}
+
+/// Asks the server listening on `path` for `command`, answering its JSON.
+#[cfg(unix)]
+pub fn request(path: &Path, command: &str) -> Result<serde_json::Value> {
+    let mut stream = UnixStream::connect(path).with_context(|| {
+        format!(
+            "Connecting to {}, is preboot-oxide running?",
+            path.display()
+        )
+    })?;
+    stream.set_read_timeout(Some(answer_timeout(command) + COMMAND_TIMEOUT))?;
+    stream.write_all(format!("{command}\n").as_bytes())?;
+    let mut answer = String::new();
+    BufReader::new(stream).read_line(&mut answer)?;
+    let answer: serde_json::Value =
+        serde_json::from_str(&answer).context("Reading the answer of the server")?;
+    if let Some(error) = answer.get("error").and_then(|error| error.as_str()) {
+        bail!("{error}");
+    }
+
+    Ok(answer)
+}
+
+#[cfg(not(unix))]
+pub fn request(_path: &Path, _command: &str) -> Result<serde_json::Value> {
+    bail!("The control socket of the running server needs Unix sockets")
+}
+
+/// The `sessions` of the server as a table, the tenant told when the server
+/// has tenants.
+pub fn sessions_report(sessions: &[SessionInfo]) -> String {
+    if sessions.is_empty() {
+        return "No DHCP sessions in progress.\n".to_string();
+    }
+    let has_tenants = sessions.iter().any(|session| session.tenant.is_some());
+    let mut rows = vec![[
+        "XID",
+        "MAC",
+        "STAGE",
+        "AGE",
+        "RULE",
+        "INTERFACE",
+        "IP",
+        "TENANT",
+    ]
+    .map(str::to_string)];
+    rows.extend(sessions.iter().map(|session| {
+        [
+            format!("{:#010x}", session.xid),
+            session.mac_address.clone(),
+            session.stage.clone(),
+            format!("{}s", session.age_secs),
+            session.rule.clone().unwrap_or("-".to_string()),
+            session.iface.clone(),
+            session
+                .client_ip
+                .map_or("-".to_string(), |ip| ip.to_string()),
+            session.tenant.clone().unwrap_or("-".to_string()),
+        ]
+    }));
+
+    let columns = if has_tenants { 8 } else { 7 };
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].len())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    rows.iter()
+        .map(|row| {
+            let cells: Vec<String> = (0..columns)
+                .map(|column| format!("{:width$}", row[column], width = widths[column]))
+                .collect();
+            cells.join("  ").trim_end().to_string() + "\n"
+        })
+        .collect()
+}
@@ -0,0 +1,169 @@
+//! Detaching from the terminal for the classic init systems, with `--daemon`:
+//! the process forks twice around a `setsid`, so it's neither a session
+//! leader nor can get a controlling terminal back, and its standard streams
+//! are redirected to `/dev/null`, or the output to `--log-file`. The PID of
+//! the process left is written to `--pid-file`, removed on SIGTERM and
+//! SIGINT. On Windows, where the server runs as a service instead, only the
+//! PID file and the log file apply.
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::Context;
+use async_signal::{Signal, Signals};
+use futures::StreamExt;
+use log::{info, warn};
+
+use crate::Result;
+
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DaemonSettings {
+    /// Whether to detach, `--daemon`.
+    pub detach: bool,
+    pub pid_file: Option<PathBuf>,
+    /// Where the output goes once detached, `/dev/null` without it.
+    pub log_file: Option<PathBuf>,
+}
+
+/// Detaches the process when asked, then writes its PID file. To be called
+/// before any thread is started, those not surviving the forks. The output
+/// goes to the log file without detaching for the Windows service.
+pub fn start(settings: &DaemonSettings) -> Result<()> {
+    // Opened first, for their errors to be seen on the terminal
+    let output = match &settings.log_file {
+        Some(path) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .context(format!("Opening the log file {}", path.display()))?,
+        ),
+        None => None,
+    };
+    if settings.detach {
+        #[cfg(unix)]
+        {
+            let stdin = File::open("/dev/null").context("Opening /dev/null")?;
+            let output = match output {
+                Some(output) => output,
+                None => OpenOptions::new().write(true).open("/dev/null")?,
+            };
+            detach(&stdin, &output)?;
+        }
+        #[cfg(not(unix))]
+        bail!("--daemon is only supported on Unix, run it as a service instead");
+    } else if let Some(output) = output {
+        redirect_output(output)?;
+    }
+    if let Some(path) = &settings.pid_file {
+        write_pid_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// Forks twice, the parents exiting, and redirects the standard streams.
+/// The working directory is kept, the paths of the configuration may be
+/// relative to it.
+#[cfg(unix)]
+fn detach(stdin: &File, output: &File) -> Result<()> {
+    fork_to_child().context("Forking")?;
+    // SAFETY: setsid has no preconditions, it fails for a process group leader
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error()).context("Starting a new session");
+    }
+    fork_to_child().context("Forking")?;
+    for (file, fd) in [(stdin, 0), (output, 1), (output, 2)] {
+        // SAFETY: both descriptors are open, the one replaced being a standard stream
+        if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error()).context("Redirecting the standard streams");
+        }
+    }
+    info!("Detached, running as PID {}.", process::id());
+
+    Ok(())
+}
+
+/// Appends the output of the process to `output`.
+#[cfg(unix)]
+fn redirect_output(output: File) -> Result<()> {
+    for fd in [1, 2] {
+        // SAFETY: the file is open, the descriptor replaced being a standard stream
+        if unsafe { libc::dup2(output.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error()).context("Redirecting the standard streams");
+        }
+    }
+
+    Ok(())
+}
+
+/// Appends the output of the process to `output`, the standard streams of
+/// Rust asking for their handle on each write.
+#[cfg(windows)]
+fn redirect_output(output: File) -> Result<()> {
+    use std::os::windows::io::IntoRawHandle;
+    use windows_sys::Win32::System::Console::{SetStdHandle, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE};
+
+    // Kept open for the life of the process
+    let handle = output.into_raw_handle();
+    for stream in [STD_OUTPUT_HANDLE, STD_ERROR_HANDLE] {
+        // SAFETY: the handle is of an open file, never closed
+        if unsafe { SetStdHandle(stream, handle as _) } == 0 {
+            return Err(io::Error::last_os_error()).context("Redirecting the standard streams");
+        }
+    }
+
+    Ok(())
+}
+
+/// Forks, returning in the child while the parent exits.
+#[cfg(unix)]
+fn fork_to_child() -> io::Result<()> {
+    // SAFETY: no thread has been started yet, the child is a complete copy
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(()),
+        // SAFETY: _exit leaves the destructors and the buffers to the child
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+/// Writes the PID of the process to `path`, replacing the one a previous
+/// run left.
+pub fn write_pid_file(path: &Path) -> Result<()> {
+    fs::write(path, format!("{}\n", process::id()))
+        .context(format!("Writing the PID file {}", path.display()))
+}
+
+/// Exits on SIGTERM or SIGINT, removing the PID file, when there's one or
+/// the process is PID 1, as in a container, the kernel dropping the signals
+/// PID 1 has no handler for. Called after [`start`] and the sandbox, as it
+/// starts the threads of the runtime.
+pub fn exit_on_signal(settings: &DaemonSettings) {
+    let path = settings.pid_file.clone();
+    if path.is_none() && process::id() != 1 {
+        return;
+    }
+    let mut signals = match Signals::new([Signal::Term, Signal::Int]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            warn!("SIGTERM and SIGINT won't be handled: {e}");
+            return;
+        }
+    };
+    async_std::task::spawn(async move {
+        if let Some(Ok(signal)) = signals.next().await {
+            info!("{signal:?} received, exiting.");
+            if let Some(path) = path {
+                let _ = fs::remove_file(&path);
+            }
+            process::exit(0);
+        }
+    });
+}
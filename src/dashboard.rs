@@ -0,0 +1,311 @@
+//! Read-only web dashboard on `dashboard_addr`: the DHCP sessions, the
+//! recent boots, the rules that decided the sessions, the transfers in
+//! progress and the services of the configuration, in a page refreshing
+//! itself, the same state being served as JSON on `/state.json`. It has no
+//! authentication, what it tells about the clients being readable by
+//! anyone reaching it.
+use std::{
+    cmp::Reverse,
+    fmt::Write,
+    net::Ipv4Addr,
+    sync::Arc,
+    time::SystemTime,
+};
+
+use log::{error, info};
+use serde::Serialize;
+
+use crate::{
+    conf::Conf,
+    control::Control,
+    dhcp::{self, SessionInfo},
+    http::{self, Body, Request, Response},
+    status,
+    tracker::{BootTracker, Counts, IfaceCounts, TransferInfo},
+    Result,
+};
+
+/// Seconds between the refreshes of the page.
+const REFRESH_SECS: u64 = 5;
+/// Most recent boots listed.
+const MAX_RECENT_BOOTS: usize = 50;
+
+/// What the dashboard shows, served as is on `/state.json`.
+#[derive(Clone, Debug, Serialize)]
+pub struct DashboardState {
+    pub version: String,
+    pub uptime_secs: u64,
+    /// Where the configuration was loaded from.
+    pub configuration: String,
+    pub counts: Counts,
+    pub ifaces: Vec<IfaceCounts>,
+    pub sessions: Vec<SessionInfo>,
+    /// The clients handed boot information, the most recent first.
+    pub recent_boots: Vec<RecentBoot>,
+    pub rules: Vec<RuleCount>,
+    pub transfers: Vec<TransferInfo>,
+    /// The top level configuration, then the tenants.
+    pub services: Vec<ServiceSummary>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RecentBoot {
+    pub ip: Ipv4Addr,
+    pub mac_address: String,
+    pub xid: u32,
+    pub boot_file: String,
+    pub boot_file_delivered: bool,
+    pub age_secs: u64,
+}
+
+/// Sessions a rule decided the configuration of, since the start.
+#[derive(Clone, Debug, Serialize)]
+pub struct RuleCount {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub rule: String,
+    pub sessions: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ServiceSummary {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// The services started, one per line, as `status` tells them.
+    pub lines: Vec<String>,
+}
+
+/// Serves the dashboard pages on `dashboard_addr`, read-only and without a
+/// token. Failing to bind it only logs an error.
+pub fn spawn_dashboard_async(conf: &Conf, control: Arc<Control>) -> Result<()> {
+    let Some(addr) = conf.get_dashboard_addr() else {
+        return Ok(());
+    };
+    let listener = match http::bind_listener(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Dashboard on {addr} failed: {e:#}");
+            return Ok(());
+        }
+    };
+    info!("Dashboard listening on http://{addr}/");
+
+    http::serve(listener, "dashboard", 0, move |request, _| {
+        let control = Arc::clone(&control);
+        async move { respond(&request, &control).await }
+    });
+
+    Ok(())
+}
+
+async fn respond(request: &Request, control: &Control) -> Response {
+    if !matches!(request.method.as_str(), "GET" | "HEAD") {
+        return Response::text(405, "Method not allowed").header("Allow", "GET, HEAD");
+    }
+    let (content, content_type) = match request.path.as_str() {
+        "/" => (render(&state(control).await), "text/html; charset=utf-8"),
+        "/state.json" => (
+            serde_json::to_string_pretty(&state(control).await).unwrap_or_default(),
+            "application/json",
+        ),
+        _ => return Response::text(404, "Not found"),
+    };
+
+    Response::new(200)
+        .header("Content-Type", content_type)
+        .header("Cache-Control", "no-store")
+        .body(Body::Bytes(content.into_bytes()))
+}
+
+/// The state of the running server, as the dashboard shows it.
+pub async fn state(control: &Control) -> DashboardState {
+    let health = control.health().await;
+    let mut recent_boots = recent_boots(&control.tracker);
+    recent_boots.truncate(MAX_RECENT_BOOTS);
+
+    let mut rules = Vec::new();
+    let mut services = Vec::new();
+    for service in &control.services {
+        if let Some(sessions) = &service.sessions {
+            rules.extend(sessions.decided().into_iter().map(|(rule, count)| RuleCount {
+                tenant: service.tenant.clone(),
+                rule,
+                sessions: count,
+            }));
+        }
+        let conf = dhcp::current_conf(&service.shared_conf);
+        services.push(ServiceSummary {
+            tenant: service.tenant.clone(),
+            lines: status::services(&conf),
+        });
+    }
+
+    DashboardState {
+        version: health.version,
+        uptime_secs: health.uptime_secs,
+        configuration: health.configuration,
+        counts: health.counts,
+        ifaces: health.ifaces,
+        sessions: control.sessions().await,
+        recent_boots,
+        rules,
+        transfers: control.tracker.transfers(),
+        services,
+    }
+}
+
+/// The clients `tracker` has, the most recent first.
+pub(crate) fn recent_boots(tracker: &BootTracker) -> Vec<RecentBoot> {
+    let now = SystemTime::now();
+    let mut clients = tracker.clients();
+    clients.sort_by_key(|(_, client)| Reverse(client.updated));
+    clients
+        .into_iter()
+        .map(|(ip, client)| RecentBoot {
+            ip,
+            age_secs: now.duration_since(client.updated).unwrap_or_default().as_secs(),
+            mac_address: client.mac_address,
+            xid: client.xid,
+            boot_file: client.boot_file,
+            boot_file_delivered: client.boot_file_delivered,
+        })
+        .collect()
+}
+
+/// The page of the dashboard, refreshing itself every few seconds.
+pub fn render(state: &DashboardState) -> String {
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta http-equiv=\"refresh\" content=\"{REFRESH_SECS}\">\n<title>preboot-oxide</title>\n\
+         <style>body{{font-family:sans-serif}}table{{border-collapse:collapse;margin-bottom:1em}}\
+         th,td{{border:1px solid #ccc;padding:2px 8px;text-align:left}}</style>\n</head>\n<body>\n\
+         <h1>preboot-oxide {}</h1>\n<p>Up {}s, configuration {}. {} offers, {} acks, {} transfers.</p>\n",
+        escape(&state.version),
+        state.uptime_secs,
+        escape(&state.configuration),
+        state.counts.offers,
+        state.counts.acks,
+        state.counts.transfers,
+    );
+
+    table(
+        &mut page,
+        "DHCP sessions",
+        &["Tenant", "MAC", "XID", "Stage", "Rule", "Interface", "Client IP", "Age"],
+        state.sessions.iter().map(|session| {
+            vec![
+                session.tenant.clone().unwrap_or_default(),
+                session.mac_address.clone(),
+                format!("{:#010x}", session.xid),
+                session.stage.clone(),
+                session.rule.clone().unwrap_or_default(),
+                session.iface.clone(),
+                session.client_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+                format!("{}s", session.age_secs),
+            ]
+        }),
+    );
+    table(
+        &mut page,
+        "Recent boots",
+        &["IP", "MAC", "XID", "Boot file", "Delivered", "Age"],
+        state.recent_boots.iter().map(|boot| {
+            vec![
+                boot.ip.to_string(),
+                boot.mac_address.clone(),
+                format!("{:#010x}", boot.xid),
+                boot.boot_file.clone(),
+                if boot.boot_file_delivered { "yes" } else { "no" }.to_string(),
+                format!("{}s", boot.age_secs),
+            ]
+        }),
+    );
+    table(
+        &mut page,
+        "Rule matches",
+        &["Tenant", "Rule", "Sessions"],
+        state.rules.iter().map(|rule| {
+            vec![rule.tenant.clone().unwrap_or_default(), rule.rule.clone(), rule.sessions.to_string()]
+        }),
+    );
+    table(
+        &mut page,
+        "Interfaces",
+        &["Interface", "Received", "Ignored", "Answered", "Transfers", "DHCP server"],
+        state.ifaces.iter().map(|iface| {
+            vec![
+                iface.iface.clone(),
+                iface.received.to_string(),
+                iface.ignored.to_string(),
+                iface.answered.to_string(),
+                iface.transfers.to_string(),
+                if iface.upstream_missing { "missing" } else { "" }.to_string(),
+            ]
+        }),
+    );
+    table(
+        &mut page,
+        "Transfers",
+        &["Client", "Path", "Progress", "Age"],
+        state.transfers.iter().map(|transfer| {
+            let progress = match transfer.size {
+                Some(size) => format!("{} of {size} bytes", transfer.bytes),
+                None => format!("{} bytes", transfer.bytes),
+            };
+            vec![
+                transfer.client.to_string(),
+                transfer.path.clone(),
+                progress,
+                format!("{}s", transfer.age_secs),
+            ]
+        }),
+    );
+
+    page.push_str("<h2>Configuration</h2>\n");
+    for service in &state.services {
+        if let Some(tenant) = &service.tenant {
+            let _ = writeln!(page, "<h3>Tenant {}</h3>", escape(tenant));
+        }
+        page.push_str("<ul>\n");
+        for line in &service.lines {
+            let _ = writeln!(page, "<li>{}</li>", escape(line));
+        }
+        page.push_str("</ul>\n");
+    }
+    page.push_str("</body>\n</html>\n");
+
+    page
+}
+
+/// Appends a table titled `title`, or a line telling there's nothing to
+/// show when `rows` is empty.
+fn table(page: &mut String, title: &str, columns: &[&str], rows: impl Iterator<Item = Vec<String>>) {
+    let _ = writeln!(page, "<h2>{title}</h2>");
+    let mut rows = rows.peekable();
+    if rows.peek().is_none() {
+        page.push_str("<p>None.</p>\n");
+        return;
+    }
+
+    page.push_str("<table>\n<tr>");
+    for column in columns {
+        let _ = write!(page, "<th>{column}</th>");
+    }
+    page.push_str("</tr>\n");
+    for row in rows {
+        page.push_str("<tr>");
+        for cell in row {
+            let _ = write!(page, "<td>{}</td>", escape(&cell));
+        }
+        page.push_str("</tr>\n");
+    }
+    page.push_str("</table>\n");
+}
+
+/// Escapes `text` for the content of an HTML element.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
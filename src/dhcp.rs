@@ -1,284 +1,1168 @@
 use std::{
-    collections::HashMap,
-    net::{Ipv4Addr, SocketAddrV4},
-    os::fd::{AsRawFd, BorrowedFd},
-    sync::Arc,
-    time::Duration,
+    collections::{BTreeMap, HashMap},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::{Context, Ok};
-use async_std::{future::timeout, sync::RwLock};
 use async_std::{net::UdpSocket, task};
-use log::{debug, error, info, trace};
-
-use crate::{conf::ConfEntryRef, util::bytes_to_mac_address};
+use log::{debug, error, info, trace, warn};
+use once_cell::sync::Lazy;
+
+#[cfg(target_os = "linux")]
+use crate::activation;
+use crate::{
+    audit, chainload,
+    conf::{ClientFields, ConfEntry, ConfEntryRef, MessageFields, Network},
+    dns,
+    events::{self, ClientEvent},
+    hook, inventory,
+    logging::{self, Chatter},
+    netbootxyz,
+    notify::Heartbeat,
+    pcap,
+    pool::{BufferPool, PooledBuffer},
+    quota::QuotaMap,
+    runtime::{self, JoinHandle},
+    secureboot,
+    timeline::{self, Entry, Step},
+    tracker::{BootTracker, UPSTREAM_MISSING_AFTER},
+    traffic::{self, Direction},
+    util::bytes_to_mac_address,
+    webhook,
+};
 use dhcproto::v4::{
     Decodable, Decoder, DhcpOption, DhcpOptions, Encodable, Encoder, Flags, Message, MessageType,
     Opcode, OptionCode,
 };
 use network_interface::{Addr, NetworkInterface, NetworkInterfaceConfig};
-use polling::{Event, Events, Poller as IOPoller}; // TODO: Migrate to mio
+use serde::{Deserialize, Serialize};
 use socket2::{Domain, Protocol, SockAddr, Socket, Type};
 
 use crate::conf::{Conf, MacAddress};
 use crate::Result;
 
-struct Session {
+/// Size of the messages received at most, see
+/// https://www.rfc-editor.org/rfc/rfc1122, 3.3.3 Fragmentation.
+const MAX_MESSAGE_SIZE: usize = 576;
+/// User class (option 77) of the requests of iPXE.
+const IPXE_USER_CLASS: &[u8] = b"iPXE";
+/// Subnet mask and lease time acknowledged when neither the offer of the
+/// DHCP server nor the configuration of the client gave them.
+const DEFAULT_SUBNET_MASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+const DEFAULT_LEASE_TIME_SECS: u32 = 60;
+/// Port of the PXE boot server, the clients offered no boot file by a PXE
+/// offer send their REQUEST to.
+pub const PXE_BOOT_SERVER_PORT: u16 = 4011;
+/// Vendor class (option 60) of the offers and acknowledgements of a PXE
+/// boot server.
+const PXE_CLIENT_CLASS: &[u8] = b"PXEClient";
+/// How often the interfaces are listed, DHCP listening on those that come
+/// up and no longer on those gone.
+const INTERFACE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// First wait before binding again the sockets of an interface that failed
+/// to, doubled on each try up to `BIND_RETRY_MAX`.
+const BIND_RETRY_MIN: Duration = Duration::from_secs(1);
+const BIND_RETRY_MAX: Duration = Duration::from_secs(30);
+/// Wait before receiving again on a socket that failed to, so one failing
+/// until its interface is removed doesn't spin.
+const RECV_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Messages read at once, with one `recvmmsg` on Linux.
+#[cfg(target_os = "linux")]
+const RECV_BATCH: usize = 32;
+#[cfg(not(target_os = "linux"))]
+const RECV_BATCH: usize = 1;
+/// Locks the sessions are spread over by XID.
+const SESSION_SHARDS: usize = 16;
+/// Age of the sessions not acknowledged that are removed.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Whether the answers are only logged rather than sent, toggled by the
+/// control API.
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+/// The DHCP messages ignored, told as a summary every minute.
+static CHATTER: Lazy<std::sync::Mutex<Chatter>> = Lazy::new(Default::default);
+/// Buffers of the messages received and of the answers encoded, enough
+/// kept for a boot storm.
+static BUFFERS: BufferPool = BufferPool::new(MAX_MESSAGE_SIZE, 1024);
+
+/// Logs the DHCP answers rather than sending them, while `dry_run`, the
+/// sessions carrying on as usual.
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Exchange the configuration of a client is looked up for, given to the
+/// `match` entries as the `Stage` field so they can differ by it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stage {
+    /// The OFFER answering a DISCOVER.
+    Offer,
+    /// The ACK answering a REQUEST.
+    Ack,
+    /// The ACK answering a REQUEST to the PXE boot server, on port 4011.
+    BootServer,
+}
+
+impl Stage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stage::Offer => "offer",
+            Stage::Ack => "ack",
+            Stage::BootServer => "boot-server",
+        }
+    }
+}
+
+/// A DHCP handshake in progress, handed over to the server taking over.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub mac_address: MacAddress,
+    /// Exchange last answered, `None` until the DHCP server offers.
+    pub stage: Option<Stage>,
+    /// What decided the configuration of the client, see `decided_by`.
+    pub rule: Option<String>,
     pub client_ip: Option<Ipv4Addr>,
     pub subnet: Option<DhcpOption>,
     pub lease_time: Option<DhcpOption>,
     pub start_time: std::time::SystemTime,
     pub discover_message: Option<Message>,
+    /// Interface and address of the segment of the client, the relay agent
+    /// or else the receiving interface, the session counts against.
+    pub iface: String,
+    pub segment_ip: Ipv4Addr,
+    /// Whether no configuration was found for the client, `unknown_client`
+    /// being fired once a session.
+    #[serde(default)]
+    pub unknown: bool,
+}
+
+impl Session {
+    /// A session starting now, before the DHCP server offered, of the
+    /// client of `mac_address` on the segment of `iface` and `segment_ip`.
+    pub fn new(mac_address: MacAddress, iface: String, segment_ip: Ipv4Addr) -> Self {
+        Self {
+            mac_address,
+            stage: None,
+            rule: None,
+            client_ip: None,
+            subnet: None,
+            lease_time: None,
+            start_time: std::time::SystemTime::now(),
+            discover_message: None,
+            iface,
+            segment_ip,
+            unknown: false,
+        }
+    }
 }
 
 pub struct Interface {
     pub iface: NetworkInterface,
     pub client: UdpSocket,
     pub server: UdpSocket,
+    /// Sockets of port 4011, with `pxe_boot_server` only, one per worker of
+    /// `pxe_boot_server_workers`.
+    pub boot_servers: Vec<UdpSocket>,
+}
+
+/// A socket of an interface.
+#[derive(Clone, Copy, Debug)]
+enum Port {
+    Server,
+    Client,
+    /// The socket of port 4011 of a worker.
+    BootServer(usize),
+}
+
+impl Interface {
+    fn socket(&self, port: Port) -> Option<&UdpSocket> {
+        match port {
+            Port::Server => Some(&self.server),
+            Port::Client => Some(&self.client),
+            Port::BootServer(worker) => self.boot_servers.get(worker),
+        }
+    }
+
+    /// The sockets of the interface.
+    fn ports(&self) -> impl Iterator<Item = Port> {
+        let boot_servers = (0..self.boot_servers.len()).map(Port::BootServer);
+        [Port::Server, Port::Client].into_iter().chain(boot_servers)
+    }
+}
+
+/// What the DHCP messages received are answered with.
+#[derive(Clone)]
+struct Responder {
+    provider: Arc<dyn ConfigProvider>,
+    sessions: Sessions,
+    tracker: Arc<BootTracker>,
+    handlers: Handlers,
+}
+
+/// The DHCP messages being answered, `dhcp_max_handlers` at most, those
+/// received beyond being dropped, so a flood of them is answered in part
+/// rather than exhausting the memory.
+#[derive(Clone, Default)]
+pub struct Handlers(Arc<AtomicUsize>);
+
+impl Handlers {
+    /// A slot for answering a message received on `iface`, `None` when
+    /// `max` are being answered already, the message being counted as
+    /// dropped by `tracker`.
+    pub fn admit(&self, max: u64, iface: &str, tracker: &BootTracker) -> Option<HandlerSlot> {
+        let max = usize::try_from(max).unwrap_or(usize::MAX);
+        let admitted = self
+            .0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < max).then_some(count + 1)
+            });
+        match admitted {
+            std::result::Result::Ok(_) => Some(HandlerSlot(Arc::clone(&self.0))),
+            Err(_) => {
+                tracker.dhcp_received(iface);
+                tracker.dhcp_dropped(iface);
+                None
+            }
+        }
+    }
+
+    /// Messages being answered.
+    pub fn active(&self) -> usize {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// A message being answered, counted by its [`Handlers`] until dropped.
+pub struct HandlerSlot(Arc<AtomicUsize>);
+
+impl Drop for HandlerSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
 }
 
+/// The interfaces listened on, indexed by name as they're bound, each
+/// with the tasks receiving on its sockets.
 pub struct Interfaces {
-    pub interfaces: Vec<Interface>,
+    index: BTreeMap<String, Bound>,
 }
 
+/// An interface of [`Interfaces`].
+struct Bound {
+    interface: Arc<Interface>,
+    /// Empty until listened on.
+    receivers: Vec<JoinHandle<()>>,
+}
+
+/// The interfaces a DHCP loop listens on, as they come and go.
+type SharedInterfaces = Arc<std::sync::RwLock<Interfaces>>;
+
 impl Interfaces {
-    pub fn sockets<'a>(&'a self) -> Vec<&'a UdpSocket> {
-        self.interfaces
-            .iter()
-            .map(|iface| vec![&iface.server, &iface.client])
-            .collect::<Vec<Vec<&'a UdpSocket>>>()
-            .into_iter()
-            .flatten()
+    /// The interfaces listened on.
+    pub fn bound(&self) -> impl Iterator<Item = &Arc<Interface>> {
+        self.index.values().map(|bound| &bound.interface)
+    }
+
+    /// The interface `name`, `None` when it isn't listened on.
+    pub fn get(&self, name: &str) -> Option<&Arc<Interface>> {
+        self.index.get(name).map(|bound| &bound.interface)
+    }
+
+    /// Names of the interfaces listened on.
+    pub fn names(&self) -> Vec<String> {
+        self.index.keys().cloned().collect()
+    }
+
+    /// Starts receiving on the sockets of the interfaces bound beforehand,
+    /// answering with `responder`.
+    fn listen(&mut self, responder: &Responder) {
+        for bound in self.index.values_mut() {
+            if bound.receivers.is_empty() {
+                bound.receivers = spawn_receivers(&bound.interface, responder);
+            }
+        }
+    }
+
+    /// Adds `interface`, receiving on its sockets with `responder`. Returns
+    /// the tasks receiving on the sockets of the interface it replaces, to
+    /// cancel.
+    fn add(&mut self, interface: Interface, responder: &Responder) -> Vec<JoinHandle<()>> {
+        let interface = Arc::new(interface);
+        let receivers = spawn_receivers(&interface, responder);
+        let name = interface.iface.name.clone();
+        self.index
+            .insert(name, Bound { interface, receivers })
+            .map(|replaced| replaced.receivers)
+            .unwrap_or_default()
+    }
+
+    /// Removes the interface `name`, with the tasks receiving on its sockets
+    /// to cancel. They're closed once the messages being answered on them
+    /// are.
+    fn remove(&mut self, name: &str) -> Vec<JoinHandle<()>> {
+        self.index
+            .remove(name)
+            .map(|bound| bound.receivers)
+            .unwrap_or_default()
+    }
+
+    /// Removes all the interfaces, with the tasks receiving on them to
+    /// cancel.
+    fn clear(&mut self) -> Vec<JoinHandle<()>> {
+        std::mem::take(&mut self.index)
+            .into_values()
+            .flat_map(|bound| bound.receivers)
             .collect()
     }
+}
 
-    pub fn interface_from_event<'a>(&'a self, ev: &Event) -> Option<&'a Interface> {
-        let index = ev.key as usize / 2;
-        self.interfaces.get(index)
+/// Spawns a task receiving on each socket of `interface`.
+fn spawn_receivers(interface: &Arc<Interface>, responder: &Responder) -> Vec<JoinHandle<()>> {
+    interface
+        .ports()
+        .map(|port| runtime::spawn(receive(Arc::clone(interface), port, responder.clone())))
+        .collect()
+}
+
+/// Receives the DHCP messages on the socket `port` of `interface`, the
+/// runtime waking it up as they come, each answered in a task of its own.
+/// The messages queued are read [`RECV_BATCH`] at a time without waiting,
+/// the runtime only waiting for the socket once none is left, so a burst is
+/// taken in with a system call per batch rather than a wake-up per message.
+async fn receive(interface: Arc<Interface>, port: Port, responder: Responder) {
+    let Some(socket) = interface.socket(port) else {
+        return;
+    };
+    let mut batch = RecvBatch::new();
+    loop {
+        let received = match recv_batch(socket, &mut batch, &interface.iface).await {
+            std::result::Result::Ok(received) => received,
+            Err(e) => {
+                debug!("Receiving on interface {} failed: {e}", interface.iface.name);
+                task::sleep(RECV_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        for (rcv_data, peer) in received {
+            answer(&interface, port, &responder, rcv_data, peer);
+        }
     }
+}
 
-    pub fn socket_from_event<'a>(&'a self, ev: &Event) -> Option<&'a UdpSocket> {
-        let sockets = self.sockets();
+/// Answers the message `rcv_data` received from `peer` on the socket `port`
+/// of `interface` in a task of its own, unless `dhcp_max_handlers` are
+/// being answered already.
+fn answer(
+    interface: &Arc<Interface>,
+    port: Port,
+    responder: &Responder,
+    rcv_data: PooledBuffer<'static>,
+    peer: SocketAddr,
+) {
+    let max_handlers = responder.provider.conf().get_dhcp_max_handlers();
+    let admitted = responder.handlers.admit(max_handlers, &interface.iface.name, &responder.tracker);
+    let Some(slot) = admitted else {
+        debug!(
+            "Dropped a DHCP message from {peer} on {}, {max_handlers} being answered already.",
+            interface.iface.name
+        );
+        return;
+    };
+    let interface = Arc::clone(interface);
+    let responder = responder.clone();
+    runtime::spawn(async move {
+        let _slot = slot;
+        let Some(socket) = interface.socket(port) else {
+            return;
+        };
+        let server_config = responder.provider.conf();
+        end_timed_out_sessions(&responder.sessions, &server_config, &responder.tracker);
+        let handled = handle_dhcp_message(
+            socket,
+            &interface,
+            &rcv_data,
+            peer,
+            &server_config,
+            responder.sessions,
+            &responder.tracker,
+        )
+        .await;
+        if let Err(e) = handled {
+            error!("{e}");
+        }
+    });
+}
 
-        Some(sockets[ev.key as usize])
+/// What changed between the interfaces a DHCP loop listens on and those of
+/// the host.
+#[derive(Debug, Default, PartialEq)]
+pub struct InterfaceChanges {
+    /// Interfaces to bind, new or with another IPv4 address.
+    pub added: Vec<NetworkInterface>,
+    /// Names of the interfaces to stop listening on, gone, no longer served
+    /// or with another IPv4 address.
+    pub removed: Vec<String>,
+}
+
+impl InterfaceChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
     }
 }
 
-impl From<Vec<Interface>> for Interfaces {
-    fn from(interfaces: Vec<Interface>) -> Self {
-        Self { interfaces }
+/// A session as listed by the `sessions` command.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    /// Tenant of the DHCP service of the session, filled in by the control
+    /// service.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub xid: u32,
+    pub mac_address: String,
+    /// `discover` until the DHCP server offers, then that of `Stage`.
+    pub stage: String,
+    pub age_secs: u64,
+    pub rule: Option<String>,
+    pub iface: String,
+    pub client_ip: Option<Ipv4Addr>,
+}
+
+/// The sessions of a DHCP service, shared with the control service.
+pub type Sessions = Arc<SessionMap>;
+
+/// A session, by the interface it's taken on, its XID and the MAC address
+/// of its client, so clients picking the same XID on different segments
+/// each have their own.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SessionKey {
+    pub iface: String,
+    pub xid: u32,
+    pub mac_address: MacAddress,
+}
+
+impl SessionKey {
+    /// The key `session` of `xid` is kept under.
+    pub fn of(xid: u32, session: &Session) -> Self {
+        Self {
+            iface: session.iface.clone(),
+            xid,
+            mac_address: session.mac_address,
+        }
     }
 }
 
-struct SessionMap {
-    sessions: HashMap<u32, Session>,
+/// The sessions in progress, spread by XID over `SESSION_SHARDS` locked on
+/// their own, so the messages of different clients don't wait on one
+/// another. Each shard keeps when its sessions were last used, only the
+/// counts against the quotas being shared, locked for counting only. The
+/// locks are held for a lookup or a change only, never across an await.
+pub struct SessionMap {
+    shards: Vec<std::sync::Mutex<SessionShard>>,
+    /// Counts of the sessions against `max_sessions` and the quotas, taken
+    /// before a session is inserted.
+    counts: std::sync::Mutex<SessionCounts>,
     max_sessions: u64,
+    /// Networks of `per_subnet`.
+    networks: Vec<Network>,
+    /// Interfaces the sessions are taken on, once the service listens.
+    ifaces: std::sync::Mutex<Vec<String>>,
+    /// Sessions decided by each rule, see `decided_by`, since the start.
+    decided: std::sync::Mutex<BTreeMap<String, u64>>,
+}
+
+struct SessionShard {
+    sessions: HashMap<SessionKey, Session>,
+    /// When the sessions were last used, expiring `SESSION_TIMEOUT` after
+    /// their last message.
+    used: QuotaMap<SessionKey>,
+}
+
+impl Default for SessionShard {
+    fn default() -> Self {
+        Self {
+            sessions: HashMap::new(),
+            used: QuotaMap::new([], None).with_ttl(SESSION_TIMEOUT),
+        }
+    }
+}
+
+struct SessionCounts {
+    sessions: u64,
+    per_iface: QuotaMap<String>,
+    per_subnet: QuotaMap<Network>,
+}
+
+/// The quota a session was refused by.
+#[derive(Clone, Debug, PartialEq)]
+enum QuotaReached {
+    Sessions(u64),
+    Iface(String, u64),
+    Subnet(Network, u64),
+}
+
+impl std::fmt::Display for QuotaReached {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuotaReached::Sessions(limit) => write!(f, "Max sessions of {limit} reached"),
+            QuotaReached::Iface(iface, limit) => write!(f, "Max sessions of {limit} on interface {iface} reached"),
+            QuotaReached::Subnet(network, limit) => write!(f, "Max sessions of {limit} in subnet {network} reached"),
+        }
+    }
+}
+
+fn locked<T>(mutex: &std::sync::Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
 }
 
 impl SessionMap {
-    fn new(max_sessions: u64) -> Self {
+    pub fn new(conf: &Conf) -> Self {
+        let per_iface = conf.get_max_sessions_per_iface().clone();
+        let per_subnet = conf.get_max_sessions_per_subnet();
         Self {
-            sessions: Default::default(),
-            max_sessions,
+            shards: (0..SESSION_SHARDS).map(|_| Default::default()).collect(),
+            counts: std::sync::Mutex::new(SessionCounts {
+                sessions: 0,
+                per_iface: QuotaMap::new(per_iface, None),
+                per_subnet: QuotaMap::new(per_subnet.to_vec(), None),
+            }),
+            max_sessions: conf.get_max_sessions(),
+            networks: per_subnet.iter().map(|(network, _)| *network).collect(),
+            ifaces: Default::default(),
+            decided: Default::default(),
         }
     }
 
-    pub fn insert(&mut self, key: u32, value: Session) -> Result<()> {
-        if u64::try_from(self.sessions.len())? > self.max_sessions {
-            bail!("Max sessions of {} reached. Ignoring.", self.max_sessions)
+    /// The network with a quota of `ip`, the most specific one when several
+    /// have it.
+    fn network_of(&self, ip: Ipv4Addr) -> Option<Network> {
+        self.networks
+            .iter()
+            .copied()
+            .filter(|network| network.contains(ip))
+            .max_by_key(Network::prefix_len)
+    }
+
+    fn shard(&self, xid: u32) -> std::sync::MutexGuard<'_, SessionShard> {
+        locked(&self.shards[xid as usize % SESSION_SHARDS])
+    }
+
+    /// Inserts the session `value` of `key`. When it's over a quota, the
+    /// sessions counted against it used the longest ago are evicted to make
+    /// room, the session being refused only when there's none to evict.
+    pub fn insert(&self, key: SessionKey, value: Session) -> Result<()> {
+        // Counted anew
+        self.remove(&key);
+        while let Err(reached) = self.acquire(&value) {
+            let Some(lru) = self.least_recently_used(&reached) else {
+                bail!("{reached}. Ignoring.")
+            };
+            // Unless it ended meanwhile
+            if let Some(evicted) = self.remove(&lru) {
+                debug!(
+                    "{reached}, evicted the session for XID: {} of {}.",
+                    lru.xid,
+                    bytes_to_mac_address(&evicted.mac_address)
+                );
+            }
         }
 
-        self.sessions.insert(key, value);
+        let replaced = {
+            let mut shard = self.shard(key.xid);
+            shard.used.acquire(&key, Instant::now());
+            let replaced = shard.sessions.insert(key.clone(), value);
+            if replaced.is_some() {
+                shard.used.release(&key);
+            }
+            replaced
+        };
+        // Inserted meanwhile
+        if let Some(replaced) = replaced {
+            self.release(&replaced);
+        }
         Ok(())
     }
 
-    pub fn remove(&mut self, key: &u32) -> Option<Session> {
-        self.sessions.remove(key)
+    pub fn remove(&self, key: &SessionKey) -> Option<Session> {
+        let session = {
+            let mut shard = self.shard(key.xid);
+            let session = shard.sessions.remove(key)?;
+            shard.used.release(key);
+            session
+        };
+        self.release(&session);
+        Some(session)
     }
 
-    pub fn get(&self, key: &u32) -> Option<&Session> {
-        self.sessions.get(key)
+    /// Counts `session` against `max_sessions` and the quotas, unless one
+    /// of them is reached.
+    fn acquire(&self, session: &Session) -> std::result::Result<(), QuotaReached> {
+        let now = Instant::now();
+        let network = self.network_of(session.segment_ip);
+        let mut counts = locked(&self.counts);
+        if counts.sessions >= self.max_sessions {
+            return Err(QuotaReached::Sessions(self.max_sessions));
+        }
+        if !counts.per_iface.acquire(&session.iface, now).is_counted() {
+            let limit = counts.per_iface.limit(&session.iface).unwrap_or_default();
+            return Err(QuotaReached::Iface(session.iface.clone(), limit));
+        }
+        if let Some(network) = network {
+            if !counts.per_subnet.acquire(&network, now).is_counted() {
+                counts.per_iface.release(&session.iface);
+                let limit = counts.per_subnet.limit(&network).unwrap_or_default();
+                return Err(QuotaReached::Subnet(network, limit));
+            }
+        }
+        counts.sessions += 1;
+        std::result::Result::Ok(())
     }
 
-    pub fn get_mut(&mut self, key: &u32) -> Option<&mut Session> {
-        self.sessions.get_mut(key)
+    /// Gives back what `session` counted against.
+    fn release(&self, session: &Session) {
+        let network = self.network_of(session.segment_ip);
+        let mut counts = locked(&self.counts);
+        counts.sessions = counts.sessions.saturating_sub(1);
+        counts.per_iface.release(&session.iface);
+        if let Some(network) = network {
+            counts.per_subnet.release(&network);
+        }
     }
 
-    pub fn retain<F>(&mut self, f: F)
-    where
-        F: FnMut(&u32, &mut Session) -> bool,
-    {
-        self.sessions.retain(f);
+    /// The session counted against `reached` used the longest ago, none
+    /// when there's none. The shards are looked at one after the other.
+    fn least_recently_used(&self, reached: &QuotaReached) -> Option<SessionKey> {
+        self.shards
+            .iter()
+            .filter_map(|shard| {
+                let shard = locked(shard);
+                let key = shard.used.least_recently_used(|key| match reached {
+                    QuotaReached::Sessions(_) => true,
+                    QuotaReached::Iface(iface, _) => &key.iface == iface,
+                    QuotaReached::Subnet(network, _) => shard
+                        .sessions
+                        .get(key)
+                        .is_some_and(|session| self.network_of(session.segment_ip) == Some(*network)),
+                })?;
+                Some((shard.used.last_used(&key)?, key))
+            })
+            .min_by_key(|(last_used, _)| *last_used)
+            .map(|(_, key)| key)
     }
 
-    pub fn iter(&self) -> std::collections::hash_map::Iter<u32, Session> {
-        self.sessions.iter()
+    /// Applies `f` to the session of `key`, none when there's none. The
+    /// session is used, putting off its expiry.
+    fn update<R>(&self, key: &SessionKey, f: impl FnOnce(&mut Session) -> R) -> Option<R> {
+        let mut shard = self.shard(key.xid);
+        let updated = shard.sessions.get_mut(key).map(f);
+        if updated.is_some() {
+            shard.used.touch(key, Instant::now());
+        }
+        updated
     }
-}
 
-pub async fn server_loop(server_config: Conf) -> Result<()> {
-    let server_config = Arc::new(server_config);
-    let listen_ips = ["0.0.0.0:67", "255.255.255.255:68"];
-    let max_sessions = server_config.get_max_sessions();
-    let sessions = Arc::new(RwLock::new(SessionMap::new(max_sessions)));
-    let network_interfaces = NetworkInterface::show()
-        .context("Listing network interfaces")?
-        .into_iter()
-        .filter(|iface| {
-            // only listen on the configured network interfaces
-            server_config
-                .get_ifaces()
-                .map(|ifaces| ifaces.contains(&iface.name))
-                .unwrap_or(true) // or on all if no interfaces are configured
-        })
-        .collect::<Vec<NetworkInterface>>();
-    let interfaces: Arc<Interfaces> = Arc::new(
-        network_interfaces
+    /// Removes the sessions without a message for `SESSION_TIMEOUT` at `now`.
+    fn remove_timed_out(&self, now: Instant) -> Vec<(u32, Session)> {
+        let expired: Vec<(u32, Session)> = self
+            .shards
             .iter()
-            .map(|iface| {
-                let server = socket_from_iface_ip(iface, &listen_ips[0])?;
-                let client = socket_from_iface_ip(iface, &listen_ips[1])?;
-                Ok(Interface {
-                    iface: iface.clone(),
-                    client,
-                    server,
-                })
+            .flat_map(|shard| {
+                let mut shard = locked(shard);
+                let expired = shard.used.expire(now);
+                expired
+                    .into_iter()
+                    .filter_map(|key| Some((key.xid, shard.sessions.remove(&key)?)))
+                    .collect::<Vec<_>>()
             })
-            .collect::<Result<Vec<Interface>>>()?
-            .into(),
-    );
+            .collect();
+        expired.iter().for_each(|(_, session)| self.release(session));
+        expired
+    }
 
-    start_session_cleaner(Arc::clone(&sessions));
+    /// Interfaces the DHCP service listens on.
+    pub fn ifaces(&self) -> Vec<String> {
+        locked(&self.ifaces).clone()
+    }
 
-    let poller = Arc::new(IOPoller::new().context("Setting up OS IO polling.")?);
-    enlist_sockets_for_events(&poller, &interfaces)?;
-    
-    loop {
-        let closure_poller = Arc::clone(&poller);
-        let mut events = async_std::task::spawn_blocking(move || { 
-            let mut events = Events::new();
-            closure_poller.wait(&mut events, None)?;
-
-            Ok(events)
-         }).await?; // blocks until we get notified by the OS
-         re_enlist_sockets_for_events(&poller, &interfaces)?;
-
-        for event in events.iter() {
-            let task_interfaces = Arc::clone(&interfaces);
-            let sessions = sessions.clone();
-            let server_config = Arc::clone(&server_config);
-            task::spawn(async move {
-                let incoming_iface = task_interfaces
-                    .interface_from_event(&event)
-                    .ok_or(anyhow!(
-                        "No interface found for event with key: {}. Very likely a bug.",
-                        event.key
-                    ))
-                    .unwrap();
-                let incoming_socket = task_interfaces
-                    .socket_from_event(&event)
-                    .ok_or(anyhow!(
-                        "No socket found for event with key: {}. Very likely a bug.",
-                        event.key
-                    ))
-                    .unwrap();
-                let _ =
-                    handle_dhcp_message(incoming_socket, incoming_iface, &server_config, sessions)
-                        .await
-                        .map_err(|e| error!("{}", e));
-            });
+    fn set_ifaces(&self, ifaces: Vec<String>) {
+        *locked(&self.ifaces) = ifaces;
+    }
+
+    /// How many sessions each rule decided the configuration of, by
+    /// `match[<index>]`, `default` or the external source.
+    pub fn decided(&self) -> BTreeMap<String, u64> {
+        locked(&self.decided).clone()
+    }
+
+    /// Ends the sessions of `xid` before their time, whatever their client
+    /// and interface, returns whether there was one.
+    pub fn expire(&self, xid: u32) -> bool {
+        let keys: Vec<SessionKey> = self
+            .shard(xid)
+            .sessions
+            .keys()
+            .filter(|key| key.xid == xid)
+            .cloned()
+            .collect();
+        let expired = keys.iter().filter(|key| self.remove(key).is_some()).count();
+        expired > 0
+    }
+
+    /// The sessions by XID, for the server taking over.
+    pub(crate) fn handed_over(&self) -> Vec<(u32, Session)> {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                locked(shard)
+                    .sessions
+                    .iter()
+                    .map(|(key, session)| (key.xid, session.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Carries on the sessions of the server taken over, within the quotas.
+    pub(crate) fn inherit(&self, sessions: Vec<(u32, Session)>) {
+        for (xid, session) in sessions {
+            if let Err(e) = self.insert(SessionKey::of(xid, &session), session) {
+                debug!("Session for XID: {xid} not carried on: {e}");
+            }
         }
+    }
+
+    /// The sessions in progress at `now`, the oldest first.
+    pub fn list(&self, now: SystemTime) -> Vec<SessionInfo> {
+        let instant = Instant::now();
+        let mut sessions: Vec<SessionInfo> = self
+            .shards
+            .iter()
+            .flat_map(|shard| {
+                let shard = locked(shard);
+                shard
+                    .sessions
+                    .iter()
+                    .filter(|(key, _)| !shard.used.is_expired(key, instant))
+                    .map(|(key, session)| SessionInfo {
+                        tenant: None,
+                        xid: key.xid,
+                        mac_address: bytes_to_mac_address(&session.mac_address),
+                        stage: session.stage.map_or("discover", Stage::as_str).to_string(),
+                        age_secs: now
+                            .duration_since(session.start_time)
+                            .unwrap_or_default()
+                            .as_secs(),
+                        rule: session.rule.clone(),
+                        iface: session.iface.clone(),
+                        client_ip: session.client_ip,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.age_secs));
+        sessions
+    }
+}
+
+/// The configuration DHCP messages are answered with, replaced on reloads
+/// while the sessions carry on.
+pub type SharedConf = Arc<std::sync::RwLock<Arc<Conf>>>;
+
+/// Where the DHCP server takes its configuration from: the boot
+/// configuration of the clients, by their `match` rules, the interfaces
+/// served and the quotas of the sessions among it. It's asked again for each
+/// message, so that a provider changing it, as the configuration file being
+/// reloaded or one kept in a database, applies to those received next.
+pub trait ConfigProvider: Send + Sync {
+    /// The configuration in effect.
+    fn conf(&self) -> Arc<Conf>;
+}
 
-        events.clear();
+/// The configuration file and environment, replaced on reloads.
+impl ConfigProvider for std::sync::RwLock<Arc<Conf>> {
+    fn conf(&self) -> Arc<Conf> {
+        Arc::clone(&self.read().unwrap_or_else(|poisoned| poisoned.into_inner()))
     }
 }
 
-fn start_session_cleaner(active_sessions: Arc<RwLock<SessionMap>>) {
-    task::spawn(async move {
-        loop {
-            task::sleep(Duration::from_secs(60)).await;
-            let now = std::time::SystemTime::now();
-            let mut items_to_remove = Vec::with_capacity(50);
-            let sessions = timeout(std::time::Duration::from_millis(500), active_sessions.read()).await;
-            if sessions.is_err() {
-                debug!("Session cleaner could not acquire read lock. Skipping.");
+/// A configuration that doesn't change.
+impl ConfigProvider for Arc<Conf> {
+    fn conf(&self) -> Arc<Conf> {
+        Arc::clone(self)
+    }
+}
+
+/// Binds the DHCP sockets of the interfaces of `server_config`, all of them
+/// without `ifaces`. Those of `ifaces` missing or without an IPv4 address,
+/// as right after boot, are left for [`server_loop`] to bind once they're
+/// up rather than failing. An interface listed more than once is bound
+/// once.
+pub fn bind_interfaces(server_config: &Conf) -> Result<Interfaces> {
+    let mut index = BTreeMap::new();
+    let listed = NetworkInterface::show().context("Listing network interfaces")?;
+    for iface in listed.into_iter().filter(|iface| is_served(server_config, iface)) {
+        if index.contains_key(&iface.name) {
+            continue;
+        }
+        let bound = Bound {
+            interface: Arc::new(bind_interface(server_config, iface)?),
+            receivers: Vec::new(),
+        };
+        index.insert(bound.interface.iface.name.clone(), bound);
+    }
+    let interfaces = Interfaces { index };
+    for name in server_config.get_ifaces().into_iter().flatten() {
+        if interfaces.get(name).is_none() {
+            warn!("Interface {name} is missing or has no IPv4 address, DHCP waits for it to come up.");
+        }
+    }
+
+    Ok(interfaces)
+}
+
+/// Binds the DHCP sockets of `iface`. The PXE boot server, only sent
+/// unicasts, has a socket per worker, the kernel spreading the requests
+/// among them; the broadcasts reaching every socket of a port, the others
+/// have one.
+fn bind_interface(server_config: &Conf, iface: NetworkInterface) -> Result<Interface> {
+    let listen_ips = ["0.0.0.0:67", "255.255.255.255:68", "0.0.0.0:4011"];
+    let server = socket_from_iface_ip(&iface, &listen_ips[0])?;
+    let client = socket_from_iface_ip(&iface, &listen_ips[1])?;
+    let workers = match server_config.get_pxe_boot_server() {
+        true => boot_server_workers(server_config),
+        false => 0,
+    };
+    let boot_servers = (0..workers)
+        .map(|_| socket_from_iface_ip(&iface, &listen_ips[2]))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Interface {
+        iface,
+        client,
+        server,
+        boot_servers,
+    })
+}
+
+/// Sockets of the PXE boot server on each interface. Only Linux spreads
+/// the messages among the sockets sharing a port, the other systems giving
+/// them all to one.
+fn boot_server_workers(server_config: &Conf) -> u64 {
+    match cfg!(target_os = "linux") {
+        true => server_config.get_pxe_boot_server_workers(),
+        false => 1,
+    }
+}
+
+/// Whether DHCP listens on `iface` with `server_config`: the configured
+/// network interfaces once they have an IPv4 address, or all if no
+/// interfaces are configured.
+fn is_served(server_config: &Conf, iface: &NetworkInterface) -> bool {
+    server_config
+        .get_ifaces()
+        .map(|ifaces| ifaces.contains(&iface.name) && first_ipv4(iface).is_some())
+        .unwrap_or(true)
+}
+
+fn first_ipv4(iface: &NetworkInterface) -> Option<Ipv4Addr> {
+    iface.addr.iter().find_map(|addr| match addr {
+        Addr::V4(v4) => Some(v4.ip),
+        Addr::V6(_) => None,
+    })
+}
+
+/// The changes to apply to the `interfaces` listened on for the interfaces
+/// `listed` on the host, to serve those of `server_config` as they are now.
+pub fn interface_changes(
+    server_config: &Conf,
+    interfaces: &Interfaces,
+    listed: Vec<NetworkInterface>,
+) -> InterfaceChanges {
+    let mut served: Vec<NetworkInterface> = Vec::new();
+    for iface in listed.into_iter().filter(|iface| is_served(server_config, iface)) {
+        if !served.iter().any(|known| known.name == iface.name) {
+            served.push(iface);
+        }
+    }
+    let bound_ipv4 = |name: &str| interfaces.get(name).map(|interface| first_ipv4(&interface.iface));
+    let removed = interfaces
+        .names()
+        .into_iter()
+        .filter(|name| {
+            let current = served.iter().find(|iface| &iface.name == name).map(first_ipv4);
+            current != bound_ipv4(name)
+        })
+        .collect();
+    let added = served
+        .into_iter()
+        .filter(|iface| bound_ipv4(&iface.name) != Some(first_ipv4(iface)))
+        .collect();
+
+    InterfaceChanges { added, removed }
+}
+
+/// The wait before the try `attempt`, from 0, at binding again the sockets
+/// of an interface that failed to.
+pub fn bind_retry_delay(attempt: u32) -> Duration {
+    BIND_RETRY_MIN
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(BIND_RETRY_MAX)
+}
+
+/// Lists the interfaces every `INTERFACE_POLL_INTERVAL`, binding those of
+/// the current configuration of the provider that come up, and closing the
+/// sockets of those gone, the interfaces of `ifaces` changing at runtime as
+/// well. An interface whose sockets fail to bind is tried again with backoff.
+async fn watch_interfaces(responder: Responder, interfaces: SharedInterfaces) {
+    let Responder {
+        provider, sessions, ..
+    } = &responder;
+    // Binding attempts failed by interface, with the time of the next one
+    let mut failures: HashMap<String, (u32, Instant)> = HashMap::new();
+    loop {
+        task::sleep(INTERFACE_POLL_INTERVAL).await;
+        let listed = match NetworkInterface::show() {
+            std::result::Result::Ok(listed) => listed,
+            Err(e) => {
+                warn!("Listing network interfaces failed: {e}");
                 continue;
             }
-            let sessions = sessions.unwrap();
-
-            for (_, (client_xid, session)) in sessions.iter().enumerate() {
-                if let Some(age) = now.duration_since(session.start_time).ok() {
-                    if age > Duration::from_secs(120) {
-                        items_to_remove.push(client_xid);
-                    }
+        };
+        let conf = provider.conf();
+        let changes = interface_changes(&conf, &read_interfaces(&interfaces), listed);
+        if changes.is_empty() {
+            continue;
+        }
+        for name in &changes.removed {
+            let receivers = write_interfaces(&interfaces).remove(name);
+            for receiver in receivers {
+                receiver.cancel().await;
+            }
+            info!("DHCP stopped listening on interface {name}, gone or readdressed.");
+        }
+        for iface in changes.added {
+            let name = iface.name.clone();
+            let attempts = match failures.get(&name) {
+                Some((_, next)) if Instant::now() < *next => continue,
+                Some((attempts, _)) => *attempts,
+                None => 0,
+            };
+            let added =
+                bind_interface(&conf, iface).map(|interface| write_interfaces(&interfaces).add(interface, &responder));
+            let replaced = match added {
+                std::result::Result::Ok(replaced) => replaced,
+                Err(e) => {
+                    let delay = bind_retry_delay(attempts);
+                    warn!("Binding the DHCP sockets of interface {name} failed, trying again in {delay:?}: {e:#}");
+                    failures.insert(name, (attempts + 1, Instant::now() + delay));
+                    continue;
                 }
+            };
+            for receiver in replaced {
+                receiver.cancel().await;
             }
+            failures.remove(&name);
+            info!("Interface {name} is up, DHCP serves it.");
+        }
+        let names = read_interfaces(&interfaces).names();
+        sessions.set_ifaces(names);
+    }
+}
 
-            if items_to_remove.is_empty() {
-                continue;
-            }
+fn read_interfaces(interfaces: &SharedInterfaces) -> std::sync::RwLockReadGuard<'_, Interfaces> {
+    interfaces.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
 
-            let sessions = timeout(std::time::Duration::from_millis(500), active_sessions.write()).await;
-            if sessions.is_err() {
-                debug!("Session cleaner could not acquire write lock. Skipping.");
-                continue;
-            }
-            let mut sessions = sessions.unwrap();
+fn write_interfaces(interfaces: &SharedInterfaces) -> std::sync::RwLockWriteGuard<'_, Interfaces> {
+    interfaces.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
 
-            sessions.retain(|client_xid, _| !items_to_remove.contains(&client_xid));
-            drop(sessions); // unlock the RwLock
-                            // would have been dropped anyway at the end of the loop
-                            // but best to keep awareness of this happing to avoid deadlocks
+/// The DHCP proxy answering in the background until stopped, for the
+/// programs embedding the server as well: it reads no environment variable
+/// nor instance lock, its configuration coming from its provider.
+pub struct DhcpService {
+    sessions: Sessions,
+    interfaces: SharedInterfaces,
+    watcher: JoinHandle<()>,
+}
 
-            trace!(
-                "Session cleaner removed {} timed out sessions.",
-                items_to_remove.len()
-            );
+impl DhcpService {
+    /// Answers the DHCP messages on the `interfaces` bound beforehand, see
+    /// [`bind_interfaces`], and on those coming up later, with the current
+    /// configuration of `provider`, keeping the handshakes in progress in
+    /// `sessions`.
+    pub fn start(
+        provider: Arc<dyn ConfigProvider>,
+        interfaces: Interfaces,
+        tracker: Arc<BootTracker>,
+        sessions: Sessions,
+    ) -> Self {
+        sessions.set_ifaces(interfaces.names());
+        let interfaces: SharedInterfaces = Arc::new(std::sync::RwLock::new(interfaces));
+        let responder = Responder {
+            provider,
+            sessions: Arc::clone(&sessions),
+            tracker,
+            handlers: Default::default(),
+        };
+        write_interfaces(&interfaces).listen(&responder);
+        let watcher = runtime::spawn(watch_interfaces(responder, Arc::clone(&interfaces)));
+
+        Self {
+            sessions,
+            interfaces,
+            watcher,
         }
-    });
+    }
+
+    /// The handshakes in progress.
+    pub fn sessions(&self) -> &Sessions {
+        &self.sessions
+    }
+
+    /// Names of the interfaces listened on.
+    pub fn interfaces(&self) -> Vec<String> {
+        read_interfaces(&self.interfaces).names()
+    }
+
+    /// Stops answering, closing the sockets once the messages being
+    /// answered are.
+    pub async fn stop(self) {
+        self.watcher.cancel().await;
+        let receivers = write_interfaces(&self.interfaces).clear();
+        for receiver in receivers {
+            receiver.cancel().await;
+        }
+        info!("DHCP service stopped.");
+    }
 }
 
-fn enlist_sockets_for_events(poller: &IOPoller, interfaces: &Arc<Interfaces>) -> Result<()> {
-    interfaces
-        .sockets()
-        .iter()
-        .enumerate()
-        .map(|(index, socket)| {
-            // SAFETY: sources have to be deleted before the poller is dropped
-            unsafe { poller.add(*socket, polling::Event::readable(index)) }
-        })
-        .collect::<std::io::Result<()>>()?;
-    Ok(())
+/// Answers the DHCP messages with a [`DhcpService`], telling `heartbeat`
+/// it's going on.
+pub async fn server_loop(
+    provider: Arc<dyn ConfigProvider>,
+    interfaces: Interfaces,
+    tracker: Arc<BootTracker>,
+    sessions: Sessions,
+    heartbeat: Heartbeat,
+) -> Result<()> {
+    let _service = DhcpService::start(provider, interfaces, tracker, sessions);
+    heartbeat.bound();
+
+    // The receiving tasks answer, the loop only tells it's going on
+    loop {
+        match heartbeat.interval() {
+            Some(interval) => task::sleep(interval).await,
+            None => std::future::pending().await,
+        }
+        heartbeat.beat();
+    }
 }
 
-fn re_enlist_sockets_for_events(poller: &IOPoller, interfaces: &Arc<Interfaces>) -> Result<()> {
-    interfaces
-        .sockets()
-        .iter()
-        .enumerate()
-        .map(|(index, socket)| {
-            unsafe {
-                // SAFETY: The resource pointed to by fd must remain open for the duration of the returned BorrowedFd, and it must not have the value -1.
-                let fd = BorrowedFd::borrow_raw(socket.as_raw_fd());
-
-                // SAFETY: sources have to be deleted before the poller is dropped
-                poller.modify(fd, polling::Event::readable(index))
-            }
-        })
-        .collect::<std::io::Result<()>>()?;
-    Ok(())
+/// The configuration in effect of `shared_conf`.
+pub fn current_conf(shared_conf: &SharedConf) -> Arc<Conf> {
+    shared_conf.conf()
+}
+
+/// Removes the sessions without a message for 2 minutes, the expiry being
+/// checked as messages are received rather than by a task of its own. Runs
+/// the `session_timed_out` hook of `conf` for each, those expiring before
+/// the ACK counted as timed out by `tracker`. Those expiring before the
+/// DHCP server of the network offered count towards the alert of its
+/// interface, `upstream_dhcp_missing` being fired when it's raised. The
+/// ignored messages of the networks gone quiet are told as well.
+fn end_timed_out_sessions(sessions: &SessionMap, conf: &Conf, tracker: &BootTracker) {
+    let summary = CHATTER.lock().unwrap_or_else(|e| e.into_inner()).flush(Instant::now());
+    if let Some(summary) = summary {
+        debug!("{summary}");
+    }
+    let timed_out = sessions.remove_timed_out(Instant::now());
+    if timed_out.is_empty() {
+        return;
+    }
+    let removed = timed_out.len();
+
+    let now = std::time::SystemTime::now();
+    for (xid, session) in timed_out {
+        if matches!(session.stage, None | Some(Stage::Offer)) {
+            tracker.stats().timed_out(&bytes_to_mac_address(&session.mac_address), now);
+        }
+        let upstream_missing =
+            session.client_ip.is_none() && tracker.upstream_timed_out(&session.iface);
+        let client = ClientEvent {
+            mac_address: bytes_to_mac_address(&session.mac_address),
+            xid,
+            ip: session.client_ip,
+            iface: Some(session.iface),
+            stage: Some(session.stage.map_or("discover", Stage::as_str).to_string()),
+            ..Default::default()
+        };
+        timeline::record(Entry {
+            xid: Some(xid),
+            iface: client.iface.clone(),
+            ip: client.ip,
+            detail: client.stage.as_ref().map(|stage| format!("after the {stage}")),
+            ..Entry::new(Step::Timeout, &client.mac_address)
+        });
+        if upstream_missing {
+            warn!(
+                "No DHCP server offered an address to the last {UPSTREAM_MISSING_AFTER} clients on {}, is there one on its network?",
+                client.iface.as_deref().unwrap_or_default()
+            );
+            events::fire(conf, events::Event::UpstreamDhcpMissing, client.clone());
+        }
+        events::fire(conf, events::Event::SessionTimedOut, client);
+    }
+    trace!("Removed {removed} timed out sessions.");
 }
 
-fn socket_from_iface_ip(iface: &NetworkInterface, ip: &&str) -> Result<UdpSocket> {
+/// A socket receiving the messages sent to `ip` on `iface` only. On Linux,
+/// it's bound to the device. On Windows, it's bound to the address of the
+/// interface instead, such sockets getting the broadcasts received on their
+/// interface. The BSDs and macOS don't give them the broadcasts, so the
+/// sockets of all the interfaces share `ip`, telling the interface of each
+/// message with `IP_RECVIF` for [`recv_on_iface`] to drop those of the
+/// others; the PXE boot server, only sent unicasts, is bound to the address.
+pub(crate) fn socket_from_iface_ip(iface: &NetworkInterface, ip: &&str) -> Result<UdpSocket> {
+    #[cfg(target_os = "linux")]
+    if let Some(socket) = activation::socket(ip.parse()?, Some(&iface.name))? {
+        socket.set_broadcast(true)?;
+        info!("Listening on IP {ip} on device {} with the socket passed", iface.name);
+        return Ok(UdpSocket::from(socket));
+    }
     let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
     socket.set_broadcast(true)?;
-    socket
-        .bind_device(Some(iface.name.as_bytes()))
-        .context(format!("Binding socket to network device: {}", iface.name))?;
+    #[cfg(target_os = "linux")]
+    let addr = {
+        socket
+            .bind_device(Some(iface.name.as_bytes()))
+            .context(format!("Binding socket to network device: {}", iface.name))?;
+        ip.parse::<SocketAddrV4>()?
+    };
+    #[cfg(windows)]
+    let addr = SocketAddrV4::new(iface_ipv4(iface)?, ip.parse::<SocketAddrV4>()?.port());
+    #[cfg(any(target_os = "freebsd", target_os = "openbsd", target_os = "macos"))]
+    let addr = match ip.parse::<SocketAddrV4>()? {
+        addr if addr.port() == PXE_BOOT_SERVER_PORT => SocketAddrV4::new(iface_ipv4(iface)?, addr.port()),
+        addr => {
+            set_recv_iface(&socket).context(format!("Enabling IP_RECVIF on {ip}"))?;
+            addr
+        }
+    };
+    // For the sockets of the interfaces sharing `ip`, the workers of the
+    // PXE boot server and the server taking over
+    #[cfg(unix)]
     socket.set_reuse_port(true)?;
     socket.set_reuse_address(true)?;
     socket
-        .bind(&SockAddr::from(ip.parse::<SocketAddrV4>()?))
+        .bind(&SockAddr::from(addr))
         .context(format!(
             "Binding socket to {ip} on network device: {}",
             iface.name
@@ -288,17 +1172,248 @@ fn socket_from_iface_ip(iface: &NetworkInterface, ip: &&str) -> Result<UdpSocket
     Ok(socket2_to_async_std(socket))
 }
 
+/// The first IPv4 address of `iface`.
+#[cfg(any(windows, target_os = "freebsd", target_os = "openbsd", target_os = "macos"))]
+fn iface_ipv4(iface: &NetworkInterface) -> Result<Ipv4Addr> {
+    iface
+        .addr
+        .iter()
+        .find_map(|addr| match addr {
+            Addr::V4(v4) => Some(v4.ip),
+            Addr::V6(_) => None,
+        })
+        .ok_or(anyhow!("Network device {} has no IPv4 address", iface.name))
+}
+
+/// Has the interface each message is received on given along with it.
+#[cfg(any(target_os = "freebsd", target_os = "openbsd", target_os = "macos"))]
+fn set_recv_iface(socket: &Socket) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let enable: libc::c_int = 1;
+    // SAFETY: the option value is a c_int living through the call
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_RECVIF,
+            (&enable as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    match result {
+        0 => std::io::Result::Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+/// Buffers the messages of a socket are read into, [`RECV_BATCH`] at once.
+struct RecvBatch {
+    buffers: Vec<[u8; MAX_MESSAGE_SIZE]>,
+}
+
+impl RecvBatch {
+    fn new() -> Self {
+        Self {
+            buffers: vec![[0; MAX_MESSAGE_SIZE]; RECV_BATCH],
+        }
+    }
+}
+
+/// Receives the messages queued on `socket`, [`RECV_BATCH`] at most with a
+/// `recvmmsg`, waiting for the socket when none is.
+#[cfg(target_os = "linux")]
+async fn recv_batch(
+    socket: &UdpSocket,
+    batch: &mut RecvBatch,
+    _iface: &NetworkInterface,
+) -> Result<Vec<(PooledBuffer<'static>, SocketAddr)>> {
+    loop {
+        match recv_queued(socket, batch) {
+            std::result::Result::Ok(received) => return Ok(received),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                // Woken up once a message is queued, left for recvmmsg to read
+                socket.peek_from(&mut [0u8; 1]).await?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Reads the messages queued on `socket` into `batch` with one `recvmmsg`,
+/// without waiting, each copied into a buffer of its own.
+#[cfg(target_os = "linux")]
+fn recv_queued(
+    socket: &UdpSocket,
+    batch: &mut RecvBatch,
+) -> std::io::Result<Vec<(PooledBuffer<'static>, SocketAddr)>> {
+    use std::{io, mem, os::fd::AsRawFd, ptr};
+
+    // SAFETY: plain C structures, zeroes being valid values
+    let mut peers: Vec<libc::sockaddr_in> = vec![unsafe { mem::zeroed() }; RECV_BATCH];
+    let mut iovs: Vec<libc::iovec> = batch
+        .buffers
+        .iter_mut()
+        .map(|buf| libc::iovec {
+            iov_base: buf.as_mut_ptr().cast(),
+            iov_len: buf.len(),
+        })
+        .collect();
+    let mut headers: Vec<libc::mmsghdr> = iovs
+        .iter_mut()
+        .zip(&mut peers)
+        .map(|(iov, peer)| {
+            // SAFETY: a plain C structure, zeroes being a valid value
+            let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+            header.msg_hdr.msg_name = ptr::from_mut(peer).cast();
+            header.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+            header.msg_hdr.msg_iov = iov;
+            header.msg_hdr.msg_iovlen = 1;
+            header
+        })
+        .collect();
+    // SAFETY: the headers point to buffers living through the call, of the lengths given
+    let count = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            headers.as_mut_ptr(),
+            headers.len() as libc::c_uint,
+            libc::MSG_DONTWAIT,
+            ptr::null_mut(),
+        )
+    };
+    if count < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let received = headers
+        .iter()
+        .zip(&peers)
+        .zip(&batch.buffers)
+        .take(count as usize)
+        .map(|((header, peer), buf)| {
+            let mut rcv_data = BUFFERS.take();
+            rcv_data.extend_from_slice(&buf[..(header.msg_len as usize).min(buf.len())]);
+            let peer = SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(peer.sin_addr.s_addr)),
+                u16::from_be(peer.sin_port),
+            );
+            (rcv_data, peer.into())
+        })
+        .collect();
+    std::result::Result::Ok(received)
+}
+
+/// Receives a message on `socket` of `iface`, none when it was received on
+/// another interface, see [`recv_on_iface`].
+#[cfg(not(target_os = "linux"))]
+async fn recv_batch(
+    socket: &UdpSocket,
+    batch: &mut RecvBatch,
+    iface: &NetworkInterface,
+) -> Result<Vec<(PooledBuffer<'static>, SocketAddr)>> {
+    let buf = &mut batch.buffers[0];
+    let Some((len, peer)) = recv_on_iface(socket, buf, iface).await? else {
+        return Ok(Vec::new());
+    };
+    let mut rcv_data = BUFFERS.take();
+    rcv_data.extend_from_slice(&buf[..len]);
+    Ok(vec![(rcv_data, peer)])
+}
+
+/// Receives a message on `socket` of `iface`, `None` when it was received
+/// on another interface, as the sockets of all of them get the broadcasts
+/// on the BSDs and macOS.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "macos"
+)))]
+async fn recv_on_iface(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    _iface: &NetworkInterface,
+) -> Result<Option<(usize, SocketAddr)>> {
+    Ok(Some(socket.recv_from(buf).await?))
+}
+
+/// Receives a message on `socket` of `iface`, `None` when it was received
+/// on another interface, as the sockets of all of them get the broadcasts
+/// on the BSDs and macOS. The messages queued are read right away, the
+/// runtime being waited for once the socket would block, `None` then.
+#[cfg(any(target_os = "freebsd", target_os = "openbsd", target_os = "macos"))]
+async fn recv_on_iface(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    iface: &NetworkInterface,
+) -> Result<Option<(usize, SocketAddr)>> {
+    use std::{io, mem, os::fd::AsRawFd, ptr};
+
+    // SAFETY: both are plain C structures, zeroes being valid values
+    let (mut peer, mut hdr): (libc::sockaddr_in, libc::msghdr) = unsafe { (mem::zeroed(), mem::zeroed()) };
+    // Aligned for the headers of the control messages
+    let mut control = [0u64; 32];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    hdr.msg_name = ptr::addr_of_mut!(peer).cast();
+    hdr.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    hdr.msg_iov = &mut iov;
+    hdr.msg_iovlen = 1;
+    hdr.msg_control = control.as_mut_ptr().cast();
+    hdr.msg_controllen = mem::size_of_val(&control) as _;
+    // SAFETY: the header points to buffers living through the call, of the lengths given
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut hdr, 0) };
+    if len < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::WouldBlock {
+            return Err(e.into());
+        }
+        socket.peek_from(&mut [0u8; 1]).await?;
+        return Ok(None);
+    }
+
+    let mut index = None;
+    // SAFETY: the control messages were written by recvmsg within the buffer the header points to
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&hdr);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::IPPROTO_IP && (*cmsg).cmsg_type == libc::IP_RECVIF {
+                let dl = libc::CMSG_DATA(cmsg).cast::<libc::sockaddr_dl>();
+                index = Some(ptr::addr_of!((*dl).sdl_index).read_unaligned() as u32);
+            }
+            cmsg = libc::CMSG_NXTHDR(&hdr, cmsg);
+        }
+    }
+    // Sockets bound to an address aren't given it
+    if index.is_some_and(|index| index != iface.index) {
+        return Ok(None);
+    }
+    let peer = SocketAddrV4::new(
+        Ipv4Addr::from(u32::from_be(peer.sin_addr.s_addr)),
+        u16::from_be(peer.sin_port),
+    );
+
+    Ok(Some((len as usize, peer.into())))
+}
+
+/// Answers the message `rcv_data` received from `peer` on
+/// `receiving_socket` of `incoming_interface`.
 async fn handle_dhcp_message(
     receiving_socket: &UdpSocket,
     incoming_interface: &Interface,
+    rcv_data: &[u8],
+    peer: SocketAddr,
     server_config: &Conf,
-    sessions: Arc<RwLock<SessionMap>>,
+    sessions: Sessions,
+    tracker: &BootTracker,
 ) -> Result<()> {
-    let mut rcv_data = [0u8; 576]; // https://www.rfc-editor.org/rfc/rfc1122, 3.3.3 Fragmentation
-    let (bytes_read, peer) = receiving_socket.recv_from(&mut rcv_data).await?;
-    if bytes_read == 0 {
+    if rcv_data.is_empty() {
         return Ok(());
     }
+    tracker.dhcp_received(&incoming_interface.iface.name);
 
     let receiving_interface = &incoming_interface.iface;
     let self_ipv4: &Ipv4Addr = receiving_interface
@@ -317,12 +1432,29 @@ async fn handle_dhcp_message(
             receiving_interface.name
         ))?;
 
-    let incoming_msg = Message::decode(&mut Decoder::new(&rcv_data))?;
+    // Captured before decoding, the malformed messages being of interest too
+    let dst_ip = match peer.ip().is_unspecified() {
+        true => Ipv4Addr::BROADCAST,
+        false => *self_ipv4,
+    };
+    let dst = SocketAddr::new(dst_ip.into(), receiving_socket.local_addr()?.port());
+    pcap::record(&receiving_interface.name, peer, dst, rcv_data);
+
+    // Counted, and summed up in the debug messages rather than told each
+    let ignored = |client: Option<MacAddress>| {
+        tracker.dhcp_ignored(&receiving_interface.name);
+        ignore_chatter(client);
+    };
+    let incoming_msg = Message::decode(&mut Decoder::new(rcv_data)).inspect_err(|_| ignored(None))?;
+    traffic::record(Direction::Received, &receiving_interface.name, peer, dst, &incoming_msg);
     let client_xid = incoming_msg.xid();
+    let client = incoming_msg.chaddr().first_chunk::<6>().copied();
     let opts = incoming_msg.opts();
-    let msg_type = opts.msg_type().context("No message type found")?;
-
-    debug!(
+    let msg_type = opts
+        .msg_type()
+        .context("No message type found")
+        .inspect_err(|_| ignored(client))?;
+    trace!(
         "Received from IP: {} on {}, port: {}, DHCP Msg type: {:?}",
         peer.ip(),
         receiving_interface.name,
@@ -332,15 +1464,29 @@ async fn handle_dhcp_message(
     trace!("{:#?}", incoming_msg);
 
     if !matches_filter(&incoming_msg) {
+        ignored(client);
         return Ok(());
     }
 
-    let client_mac_address: MacAddress = *incoming_msg.chaddr().first_chunk().ok_or(anyhow!(
+    let client_mac_address: MacAddress = client.ok_or(anyhow!(
         "The client MAC address does not fit the size requirements of exactly 6 bytes."
     ))?;
     let client_mac_address_str = bytes_to_mac_address(&client_mac_address);
+    logging::enter_mac(client_mac_address);
+    logging::enter_xid(incoming_msg.xid());
+    let session_key = SessionKey {
+        iface: receiving_interface.name.clone(),
+        xid: client_xid,
+        mac_address: client_mac_address,
+    };
+    let is_boot_server = receiving_socket.local_addr()?.port() == PXE_BOOT_SERVER_PORT;
+    if is_boot_server && msg_type != MessageType::Request {
+        ignored(client);
+        return Ok(());
+    }
 
-    let response = match msg_type {
+    // Answered to the address it came from, else broadcast
+    let (response, reply_delay_ms, reply_to, decision) = match msg_type {
         MessageType::Discover => {
             let has_boot_info_request = match incoming_msg.opts().get(OptionCode::ParameterRequestList) {
                 Some(DhcpOption::ParameterRequestList(params)) => params.contains(&OptionCode::BootfileName),
@@ -348,6 +1494,7 @@ async fn handle_dhcp_message(
             };
 
             if !has_boot_info_request {
+                ignored(client);
                 return Ok(())
             }
 
@@ -355,19 +1502,36 @@ async fn handle_dhcp_message(
                 "Received DISCOVER boot request from client {client_mac_address_str} with XID: {client_xid} on interface {}.",
                 receiving_interface.name,
             );
+            timeline::record(Entry {
+                xid: Some(client_xid),
+                iface: Some(receiving_interface.name.clone()),
+                ..Entry::new(Step::Discover, &client_mac_address_str)
+            });
 
-            let mut sessions =
-                timeout(std::time::Duration::from_millis(500), sessions.write()).await?;
-            let mut session = sessions.remove(&client_xid).unwrap_or(Session {
-                client_ip: None,
-                subnet: None,
-                lease_time: None,
-                start_time: std::time::SystemTime::now(),
-                discover_message: None,
+            let known_session = sessions.remove(&session_key);
+            let is_new = known_session.is_none();
+            let mut session = known_session.unwrap_or_else(|| {
+                Session::new(
+                    client_mac_address,
+                    receiving_interface.name.clone(),
+                    Some(incoming_msg.giaddr())
+                        .filter(|relay_ip| !relay_ip.is_unspecified())
+                        .unwrap_or(*self_ipv4),
+                )
             });
+            tracker.assets().seen(&incoming_msg, std::time::SystemTime::now());
             session.discover_message = Some(incoming_msg);
-            sessions.insert(client_xid, session)?;
-            drop(sessions);
+            sessions.insert(session_key, session)?;
+            // Once a session, the clients retransmitting their DISCOVER
+            if is_new {
+                let client = ClientEvent {
+                    mac_address: client_mac_address_str.clone(),
+                    xid: client_xid,
+                    iface: Some(receiving_interface.name.clone()),
+                    ..Default::default()
+                };
+                events::fire(server_config, events::Event::DiscoverSeen, client);
+            }
 
             /*
             We will not respond to the discover message until the authoritative
@@ -378,96 +1542,251 @@ async fn handle_dhcp_message(
             return Ok(());
         }
         MessageType::Offer => {
-            let mut sessions =
-                timeout(std::time::Duration::from_millis(500), sessions.write()).await?;
-            let session = sessions.get_mut(&client_xid);
-            if session.is_none() {
-                debug!(
-                    "No session with XID: {client_xid}. Most likely regular DHCP on the network. Ignoring.",
-                );
+            let discover_message = sessions.update(&session_key, |session| {
+                if tracker.upstream_offered(&session.iface) {
+                    info!("The DHCP server of the network offers again on {}.", session.iface);
+                }
+                session.client_ip = Some(incoming_msg.yiaddr());
+                session.subnet = incoming_msg.opts().get(OptionCode::SubnetMask).cloned();
+                session.lease_time = incoming_msg
+                    .opts()
+                    .get(OptionCode::AddressLeaseTime)
+                    .cloned();
+                session.discover_message.clone()
+            });
+            // Most likely regular DHCP on the network
+            let Some(discover_message) = discover_message else {
+                ignored(client);
                 return Ok(());
-            }
-
-            let session = session.unwrap();
-            session.client_ip = Some(incoming_msg.yiaddr());
-            session.subnet = incoming_msg.opts().get(OptionCode::SubnetMask).cloned();
-            session.lease_time = incoming_msg
-                .opts()
-                .get(OptionCode::AddressLeaseTime)
-                .cloned();
+            };
 
-            let initial_discover_msg = session.discover_message.clone().ok_or(anyhow!(
+            let initial_discover_msg = discover_message.ok_or(anyhow!(
                 "Initial discovery message for XID {client_xid} not found due to either a bug or incorrect DHCP server behavior. Skipping.",
             ))?;
-            drop(sessions);
 
-            let discover_msg_doc = serde_json::to_value(initial_discover_msg)?;
-            let client_cfg = server_config
-                .get_from_doc(discover_msg_doc)?
+            let client_arch = client_architecture(&initial_discover_msg);
+            let client_is_ipxe = is_ipxe(&initial_discover_msg);
+            let relay_ip = initial_discover_msg.giaddr();
+            let discover_fields = with_client_network(
+                MessageFields::new(&initial_discover_msg),
+                &receiving_interface.name,
+                incoming_msg.yiaddr(),
+                relay_ip,
+                Stage::Offer,
+            );
+            let discover_fields =
+                with_reverse_hostname(server_config, discover_fields, incoming_msg.yiaddr()).await;
+            let external_cfg = external_conf(server_config, &discover_fields).await;
+            let rule = decided_by(server_config, external_cfg.as_ref(), &discover_fields);
+            let client_cfg = match &external_cfg {
+                Some((entry, _)) => Some(server_config.with_default(entry, &discover_fields)),
+                None => server_config.get_from_doc(&discover_fields)?,
+            };
+            let client_ip = Some(incoming_msg.yiaddr());
+            let Some(client_cfg) =
+                with_profile_boot_file(client_cfg, server_config, client_arch, client_is_ipxe)
+            else {
+                if record_unknown(&sessions, &session_key) {
+                    let client = ClientEvent {
+                        mac_address: client_mac_address_str.clone(),
+                        xid: client_xid,
+                        ip: client_ip.filter(|ip| !ip.is_unspecified()),
+                        iface: Some(receiving_interface.name.clone()),
+                        ..Default::default()
+                    };
+                    events::fire(server_config, events::Event::UnknownClient, client);
+                }
+                bail!("No configuration found for client {client_mac_address_str}. Skipping");
+            };
+            logging::set_client_level(client_mac_address, client_ip, client_cfg.log_level.copied());
+            if record_session(&sessions, &session_key, Stage::Offer, rule.clone()) {
+                let client = rule_matched(
+                    &client_mac_address_str,
+                    client_xid,
+                    client_ip,
+                    &client_cfg,
+                    receiving_interface,
+                    rule.clone(),
+                );
+                events::fire(server_config, events::Event::RuleMatched, client);
+            }
+            let msg = apply_self_to_message(incoming_msg, self_ipv4);
+            let offer = match client_cfg.boot_file {
+                // Left to the PXE boot server, which the client asks next
+                None if server_config.get_pxe_boot_server() => {
+                    debug!("Offering no boot file to {client_mac_address_str}, sent to the PXE boot server.");
+                    with_pxe_client_class(msg)
+                }
+                _ => add_boot_info_to_message(
+                    msg,
+                    &client_cfg,
+                    &client_mac_address_str,
+                    Some(self_ipv4),
+                )?,
+            };
+
+            (offer, client_cfg.reply_delay_ms.copied(), None, (Stage::Offer, rule))
+        }
+        MessageType::Request if is_boot_server => {
+            info!(
+                "Received PXE boot server REQUEST from client {client_mac_address_str} with XID: {client_xid} on interface {}.",
+                receiving_interface.name,
+            );
+
+            let client_ip = incoming_msg.ciaddr();
+            timeline::record(Entry {
+                xid: Some(client_xid),
+                iface: Some(receiving_interface.name.clone()),
+                ip: Some(client_ip).filter(|ip| !ip.is_unspecified()),
+                detail: Some("PXE boot server".to_string()),
+                ..Entry::new(Step::Request, &client_mac_address_str)
+            });
+            let client_arch = client_architecture(&incoming_msg);
+            let client_is_ipxe = is_ipxe(&incoming_msg);
+            let relay_ip = incoming_msg.giaddr();
+            let incoming_fields = with_client_network(
+                MessageFields::new(&incoming_msg),
+                &receiving_interface.name,
+                client_ip,
+                relay_ip,
+                Stage::BootServer,
+            );
+            let incoming_fields =
+                with_reverse_hostname(server_config, incoming_fields, client_ip).await;
+            let external_cfg = external_conf(server_config, &incoming_fields).await;
+            let rule = decided_by(server_config, external_cfg.as_ref(), &incoming_fields);
+            let client_cfg = match &external_cfg {
+                Some((entry, _)) => Some(server_config.with_default(entry, &incoming_fields)),
+                None => server_config.get_from_doc(&incoming_fields)?,
+            };
+            let client_cfg =
+                with_profile_boot_file(client_cfg, server_config, client_arch, client_is_ipxe)
                 .ok_or(anyhow!(
                     "No configuration found for client {client_mac_address_str}. Skipping",
                 ))?;
-            let msg = apply_self_to_message(incoming_msg, &self_ipv4);
-            add_boot_info_to_message(msg, &client_cfg, &client_mac_address_str, Some(&self_ipv4))?
+            let log_level = client_cfg.log_level.copied();
+            logging::set_client_level(client_mac_address, Some(client_ip), log_level);
+
+            let mut opts = DhcpOptions::default();
+            opts.insert(DhcpOption::MessageType(MessageType::Ack));
+            let mut ack = Message::default();
+            ack.set_opcode(Opcode::BootReply)
+                .set_ciaddr(client_ip)
+                .set_giaddr(relay_ip)
+                .set_opts(opts)
+                .set_chaddr(&client_mac_address)
+                .set_xid(client_xid);
+            ack = with_pxe_client_class(apply_self_to_message(ack, self_ipv4));
+            ack = add_boot_info_to_message(
+                ack,
+                &client_cfg,
+                &client_mac_address_str,
+                Some(self_ipv4),
+            )?;
+
+            if let (false, Some(boot_file)) = (client_ip.is_unspecified(), client_cfg.boot_file) {
+                tracker.boot_info_sent(client_ip, &client_mac_address_str, client_xid, boot_file, &receiving_interface.name);
+            }
+
+            (ack, client_cfg.reply_delay_ms.copied(), Some(peer), (Stage::BootServer, rule))
         }
         MessageType::Request => {
-            let sessions =
-                timeout(std::time::Duration::from_millis(500), sessions.read()).await?;
-            let session = sessions.get(&client_xid);
-            if session.is_none() {
-                debug!("No session found for client {client_mac_address_str}, XID: {client_xid}, ignoring.");
+            let offered = sessions.update(&session_key, |session| {
+                (session.client_ip, session.subnet.clone(), session.lease_time.clone())
+            });
+            let Some((client_ip, offered_subnet, offered_lease_time)) = offered else {
+                ignored(client);
                 return Ok(());
+            };
+            timeline::record(Entry {
+                xid: Some(client_xid),
+                iface: Some(receiving_interface.name.clone()),
+                ip: client_ip,
+                ..Entry::new(Step::Request, &client_mac_address_str)
+            });
+
+            tracker.assets().seen(&incoming_msg, std::time::SystemTime::now());
+            let client_arch = client_architecture(&incoming_msg);
+            let client_is_ipxe = is_ipxe(&incoming_msg);
+            let relay_ip = incoming_msg.giaddr();
+            let incoming_fields = with_client_network(
+                MessageFields::new(&incoming_msg),
+                &receiving_interface.name,
+                client_ip.unwrap_or(Ipv4Addr::UNSPECIFIED),
+                relay_ip,
+                Stage::Ack,
+            );
+            let incoming_fields = with_reverse_hostname(
+                server_config,
+                incoming_fields,
+                client_ip.unwrap_or(Ipv4Addr::UNSPECIFIED),
+            )
+            .await;
+            let external_cfg = external_conf(server_config, &incoming_fields).await;
+            let rule = decided_by(server_config, external_cfg.as_ref(), &incoming_fields);
+            let client_cfg = match &external_cfg {
+                Some((entry, _)) => Some(server_config.with_default(entry, &incoming_fields)),
+                None => server_config.get_from_doc(&incoming_fields)?,
+            };
+            let client_cfg =
+                with_profile_boot_file(client_cfg, server_config, client_arch, client_is_ipxe)
+                .ok_or(anyhow!(
+                    "No configuration found for client {client_mac_address_str}. Skipping",
+                ))?;
+            logging::set_client_level(client_mac_address, client_ip, client_cfg.log_level.copied());
+            if record_session(&sessions, &session_key, Stage::Ack, rule.clone()) {
+                let client = rule_matched(
+                    &client_mac_address_str,
+                    client_xid,
+                    client_ip,
+                    &client_cfg,
+                    receiving_interface,
+                    rule.clone(),
+                );
+                events::fire(server_config, events::Event::RuleMatched, client);
             }
-            let session = session.unwrap();
-            let mut ack = Message::default();
+
+            // Those of the offer, else those configured for the client
             let mut opts = DhcpOptions::default();
             opts.insert(DhcpOption::MessageType(MessageType::Ack));
-            opts.insert(
-                session
-                    .subnet
-                    .clone()
-                    .unwrap_or(DhcpOption::SubnetMask(Ipv4Addr::new(255, 255, 255, 0))),
-            );
-            opts.insert(
-                session
-                    .lease_time
-                    .clone()
-                    .unwrap_or(DhcpOption::AddressLeaseTime(60)),
-            ); // in minutes
+            opts.insert(offered_subnet.unwrap_or(DhcpOption::SubnetMask(
+                client_cfg.subnet_mask.copied().unwrap_or(DEFAULT_SUBNET_MASK),
+            )));
+            opts.insert(offered_lease_time.unwrap_or(DhcpOption::AddressLeaseTime(
+                client_cfg.lease_time.copied().unwrap_or(DEFAULT_LEASE_TIME_SECS),
+            )));
 
+            let mut ack = Message::default();
             ack.set_flags(Flags::new(0).set_broadcast())
-                .set_yiaddr(session.client_ip.unwrap_or(Ipv4Addr::new(0, 0, 0, 0)))
+                .set_yiaddr(client_ip.unwrap_or(Ipv4Addr::new(0, 0, 0, 0)))
                 .set_opcode(Opcode::BootReply)
                 .set_opts(opts)
                 .set_chaddr(&client_mac_address)
                 .set_xid(client_xid);
-            drop(sessions);
-
-            let incoming_msg_doc = serde_json::to_value(incoming_msg)?;
-            let client_cfg = server_config
-                .get_from_doc(incoming_msg_doc)?
-                .ok_or(anyhow!(
-                    "No configuration found for client {client_mac_address_str}. Skipping",
-                ))?;
-
-            ack = apply_self_to_message(ack, &self_ipv4);
+            ack = apply_self_to_message(ack, self_ipv4);
             ack = add_boot_info_to_message(
                 ack,
                 &client_cfg,
                 &client_mac_address_str,
-                Some(&self_ipv4),
+                Some(self_ipv4),
             )?;
 
-            ack
+            if let (Some(ip), Some(boot_file)) = (client_ip, client_cfg.boot_file) {
+                tracker.boot_info_sent(ip, &client_mac_address_str, client_xid, boot_file, &receiving_interface.name);
+            }
+
+            (ack, client_cfg.reply_delay_ms.copied(), None, (Stage::Ack, rule))
         }
         MessageType::Decline | MessageType::Ack => {
-            let mut sessions = 
-                timeout(std::time::Duration::from_millis(500), sessions.write()).await?;
-            sessions.remove(&client_xid);
-            drop(sessions);
+            sessions.remove(&session_key);
             debug!("Session for XID: {client_xid} ended.");
 
             return if msg_type == MessageType::Decline {
+                timeline::record(Entry {
+                    xid: Some(client_xid),
+                    iface: Some(receiving_interface.name.clone()),
+                    ..Entry::new(Step::Decline, &client_mac_address_str)
+                });
                 bail!(
                     "Client {} declined REQUEST.",
                     bytes_to_mac_address(incoming_msg.chaddr())
@@ -476,25 +1795,88 @@ async fn handle_dhcp_message(
                 Ok(())
             };
         }
-        _ => return Ok(()),
+        _ => {
+            ignored(client);
+            return Ok(());
+        }
     };
 
-    let to_addr = "255.255.255.255:68";
-    let mut buf = Vec::new();
-    let mut e = Encoder::new(&mut buf);
+    let (socket, to_addr) = match reply_to {
+        Some(peer) => (receiving_socket, peer.to_string()),
+        None => (&incoming_interface.server, "255.255.255.255:68".to_string()),
+    };
+    let mut buf = BUFFERS.take();
     let iface_name = &receiving_interface.name;
-    response.encode(&mut e)?;
+    response.encode(&mut Encoder::new(&mut buf))?;
 
+    if let Some(delay) = reply_delay_ms.filter(|ms| *ms > 0) {
+        debug!("Delaying the reply to XID: {client_xid} by {delay} ms.");
+        task::sleep(Duration::from_millis(delay)).await;
+    }
+    if is_dry_run() {
+        info!("Dry run, not responding with message to {to_addr} on interface {iface_name}.");
+        trace!("{:#?}", response);
+        return Ok(());
+    }
     info!("Responding with message to {to_addr} on interface {iface_name}.");
     trace!("{:#?}", response);
 
-    let socket = &incoming_interface.server;
-    socket.send_to(&buf, to_addr).await?;
-    debug!(
-        "DHCP reply ({:?}) sent to: {}",
-        response.opts().get(OptionCode::MessageType).unwrap(),
-        to_addr
-    );
+    socket.send_to(&buf, &to_addr).await?;
+    tracker.dhcp_answered(iface_name);
+    if let std::result::Result::Ok(to_addr) = to_addr.parse() {
+        let src = SocketAddr::new((*self_ipv4).into(), socket.local_addr()?.port());
+        pcap::record(iface_name, src, to_addr, &buf);
+        traffic::record(Direction::Sent, iface_name, src, to_addr, &response);
+    }
+    let (stage, rule) = decision;
+    let client_ip = match stage {
+        Stage::BootServer => response.ciaddr(),
+        _ => response.yiaddr(),
+    };
+    audit::record(&audit::Decision {
+        mac_address: bytes_to_mac_address(response.chaddr()),
+        xid: response.xid(),
+        client_ip: Some(client_ip).filter(|ip| !ip.is_unspecified()),
+        relay_ip: Some(response.giaddr()).filter(|ip| !ip.is_unspecified()),
+        iface: iface_name.clone(),
+        reply: stage.as_str().to_string(),
+        rule,
+        boot_file: boot_file(&response),
+        boot_server: Some(response.siaddr()).filter(|ip| !ip.is_unspecified()),
+    });
+    let step = match response.opts().msg_type() {
+        Some(MessageType::Offer) => Some(Step::Offer),
+        Some(MessageType::Ack) => Some(Step::Ack),
+        _ => None,
+    };
+    if let Some(step) = step {
+        timeline::record(Entry {
+            xid: Some(response.xid()),
+            iface: Some(iface_name.clone()),
+            ip: Some(client_ip).filter(|ip| !ip.is_unspecified()),
+            detail: boot_file(&response),
+            ..Entry::new(step, &bytes_to_mac_address(response.chaddr()))
+        });
+    }
+    match response.opts().msg_type() {
+        Some(MessageType::Offer) => {
+            tracker.offer_sent(&bytes_to_mac_address(response.chaddr()), boot_file(&response).as_deref());
+            let client = ClientEvent {
+                mac_address: bytes_to_mac_address(response.chaddr()),
+                xid: response.xid(),
+                ip: Some(response.yiaddr()).filter(|ip| !ip.is_unspecified()),
+                boot_file: boot_file(&response),
+                iface: Some(iface_name.clone()),
+                ..Default::default()
+            };
+            events::fire(server_config, events::Event::OfferSent, client);
+        }
+        Some(MessageType::Ack) => {
+            tracker.ack_sent(&bytes_to_mac_address(response.chaddr()), boot_file(&response).as_deref())
+        }
+        _ => {}
+    }
+    debug!("DHCP reply ({:?}) sent to: {}", response.opts().msg_type(), to_addr);
 
     Ok(())
 }
@@ -509,18 +1891,210 @@ fn matches_filter(msg: &Message) -> bool {
 
     let matches = (!has_boot_file_name && is_offer) | is_request | is_ack | is_discover;
     if !matches {
-        debug!(
+        trace!(
             "DHCP message ignored due to not matching filter. \
           Required: has_boot_file_name: {has_boot_file_name}, is_request: {is_request} \
           is_offer: {is_offer}, is_ack: {is_ack}, is_discover: {is_discover}"
         );
-    } else {
-        debug!("Eligible DHCP message found.");
     }
 
     matches
 }
 
+/// Counts a DHCP message of `client` ignored, telling those ignored in the
+/// last minute once it passed.
+fn ignore_chatter(client: Option<MacAddress>) {
+    let mut chatter = CHATTER.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(summary) = chatter.ignored(client, Instant::now()) {
+        debug!("{summary}");
+    }
+}
+
+/// Adds the network of a client to the `fields` of its message: the
+/// `Interface` it came in on and the address the `Subnet` of match entries is
+/// matched to, the one it was offered or else that of the relay agent it came
+/// through. The `Stage` of the exchange is added too.
+fn with_client_network<'a>(
+    fields: MessageFields<'a>,
+    iface_name: &str,
+    offered_ip: Ipv4Addr,
+    relay_ip: Ipv4Addr,
+    stage: Stage,
+) -> MessageFields<'a> {
+    let subnet_ip = [offered_ip, relay_ip].into_iter().find(|ip| !ip.is_unspecified());
+    let fields = fields.with("Interface", iface_name).with("Stage", stage.as_str());
+    match subnet_ip {
+        Some(ip) => fields.with("Subnet", ip.to_string()),
+        None => fields,
+    }
+}
+
+/// Adds the name of the client at `client_ip` in the reverse DNS to the
+/// `fields` of its message, the `ReverseHostname` of match entries, with
+/// `reverse_dns`.
+async fn with_reverse_hostname<'a>(
+    conf: &Conf,
+    fields: MessageFields<'a>,
+    client_ip: Ipv4Addr,
+) -> MessageFields<'a> {
+    if !conf.get_reverse_dns() || client_ip.is_unspecified() {
+        return fields;
+    }
+    match dns::reverse_hostname(conf, client_ip).await {
+        Some(name) => fields.with("ReverseHostname", name),
+        None => fields,
+    }
+}
+
+/// Configuration of a client decided by the boot hook, or else by the
+/// webhook or the inventory, before the `match` rules are looked at, with the
+/// field of the one answering. They're given the JSON document of the message
+/// of the client, only made when one of them is set.
+async fn external_conf(
+    conf: &Conf,
+    fields: &MessageFields<'_>,
+) -> Option<(ConfEntry, &'static str)> {
+    let is_set = [
+        conf.get_boot_hook().is_some(),
+        conf.get_webhook_url().is_some(),
+        conf.get_netbox_url().is_some(),
+    ];
+    if !is_set.contains(&true) {
+        return None;
+    }
+    let doc = &fields.to_doc();
+    let (entry, source) = match hook::client_conf(conf, doc).await {
+        Some(entry) => (entry, "boot_hook"),
+        None => match webhook::client_conf(conf, doc).await {
+            Some(entry) => (entry, "webhook_url"),
+            None => (inventory::client_conf(conf, doc).await?, "netbox_url"),
+        },
+    };
+
+    conf.with_profile(entry)
+        .inspect_err(|e| warn!("Ignoring the configuration answered for the client: {e}"))
+        .ok()
+        .map(|entry| (entry, source))
+}
+
+/// What decided the configuration of the client of `doc`, for the `sessions`
+/// command: the external source answering it, else the `match` entry used or
+/// `default`.
+fn decided_by(
+    conf: &Conf,
+    external_cfg: Option<&(ConfEntry, &'static str)>,
+    doc: &dyn ClientFields,
+) -> String {
+    match (external_cfg, conf.used_entry_at(doc, SystemTime::now())) {
+        (Some((_, source)), _) => source.to_string(),
+        (None, Some(index)) => format!("match[{index}]"),
+        (None, None) => "default".to_string(),
+    }
+}
+
+/// Records the exchange the session of `key` was answered at, and what
+/// decided the configuration it was answered with. Whether it's the first
+/// time the configuration of the session was decided.
+fn record_session(sessions: &SessionMap, key: &SessionKey, stage: Stage, rule: String) -> bool {
+    let is_decided = sessions.update(key, |session| {
+        session.stage = Some(stage);
+        // Counted once a session, the DHCP server retransmitting its offers
+        let is_decided = session.rule.is_none();
+        session.rule = Some(rule.clone());
+        is_decided
+    });
+    if is_decided != Some(true) {
+        return false;
+    }
+    *locked(&sessions.decided).entry(rule).or_default() += 1;
+    true
+}
+
+/// Marks the session of `key` as that of a client no configuration was
+/// found for. Whether it wasn't already, the DHCP server retransmitting its
+/// offers.
+fn record_unknown(sessions: &SessionMap, key: &SessionKey) -> bool {
+    sessions
+        .update(key, |session| !std::mem::replace(&mut session.unknown, true))
+        .unwrap_or(false)
+}
+
+/// The client of the event `rule_matched`, answered with `client_cfg`.
+fn rule_matched(
+    mac_address: &str,
+    xid: u32,
+    ip: Option<Ipv4Addr>,
+    client_cfg: &ConfEntryRef,
+    iface: &NetworkInterface,
+    rule: String,
+) -> ClientEvent {
+    ClientEvent {
+        mac_address: mac_address.to_string(),
+        xid,
+        ip: ip.filter(|ip| !ip.is_unspecified()),
+        boot_file: client_cfg.boot_file.cloned(),
+        iface: Some(iface.name.clone()),
+        rule: Some(rule),
+        ..Default::default()
+    }
+}
+
+/// Boot file the reply `msg` gives, from its `file` field.
+fn boot_file(msg: &Message) -> Option<String> {
+    msg.fname()
+        .map(|fname| String::from_utf8_lossy(fname).trim_end_matches('\0').to_string())
+        .filter(|fname| !fname.is_empty())
+}
+
+/// Processor architecture of the client, option 93.
+fn client_architecture(msg: &Message) -> Option<u16> {
+    match msg.opts().get(OptionCode::ClientSystemArchitecture) {
+        Some(DhcpOption::ClientSystemArchitecture(arch)) => Some((*arch).into()),
+        _ => None,
+    }
+}
+
+/// Whether the client is iPXE, which tells so with its user class.
+fn is_ipxe(msg: &Message) -> bool {
+    matches!(
+        msg.opts().get(OptionCode::UserClass),
+        Some(DhcpOption::UserClass(class)) if class == IPXE_USER_CLASS
+    )
+}
+
+/// Gives a client without a boot file the one of the enabled profiles for its
+/// architecture: the signed shim to UEFI clients with `secure_boot`, iPXE or
+/// the script chaining the HTTP service with `ipxe_chainload`, the
+/// netboot.xyz one with `netbootxyz`.
+fn with_profile_boot_file<'a>(
+    client_cfg: Option<ConfEntryRef<'a>>,
+    server_config: &Conf,
+    arch: Option<u16>,
+    is_ipxe: bool,
+) -> Option<ConfEntryRef<'a>> {
+    let profile_boot_file = server_config
+        .get_secure_boot()
+        .then(|| secureboot::boot_file(arch))
+        .flatten()
+        .or_else(|| {
+            server_config
+                .get_ipxe_chainload()
+                .then(|| chainload::boot_file(arch, is_ipxe))
+        })
+        .or_else(|| {
+            server_config
+                .get_netbootxyz()
+                .then(|| netbootxyz::boot_file(arch))
+        });
+    let Some(profile_boot_file) = profile_boot_file else {
+        return client_cfg;
+    };
+
+    let mut client_cfg = client_cfg.unwrap_or_default();
+    client_cfg.boot_file.get_or_insert(profile_boot_file);
+    Some(client_cfg)
+}
+
 fn socket2_to_async_std(socket: Socket) -> UdpSocket {
     let std_socket = Into::<std::net::UdpSocket>::into(socket);
     UdpSocket::from(std_socket)
@@ -547,13 +2121,21 @@ fn add_boot_info_to_message(
 
     msg.set_siaddr(*tfpt_srv_addr).set_fname_str(boot_filename);
 
-    return Ok(msg);
+    Ok(msg)
+}
+
+/// Tells a PXE client the message comes from a PXE boot server, so it asks
+/// the server on port 4011 for the boot file it was offered none of.
+fn with_pxe_client_class(mut msg: Message) -> Message {
+    msg.opts_mut()
+        .insert(DhcpOption::ClassIdentifier(PXE_CLIENT_CLASS.to_vec()));
+    msg
 }
 
 fn apply_self_to_message(mut msg: Message, my_ipv4: &Ipv4Addr) -> Message {
     let opts = msg.opts_mut();
-    opts.insert(DhcpOption::ServerIdentifier(my_ipv4.clone()));
-    msg.set_siaddr(my_ipv4.clone());
+    opts.insert(DhcpOption::ServerIdentifier(*my_ipv4));
+    msg.set_siaddr(*my_ipv4);
 
     msg
 }
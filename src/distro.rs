@@ -0,0 +1,148 @@
+//! Boot entries for the installer and live ISOs of common distributions and
+//! for Windows installation media. The kernel and initrd are loop-read from
+//! the image in the image store, the TFTP and HTTP services serving the
+//! files inside ISOs, so booting an ISO needs no extraction.
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{iso::IsoImage, images::IMAGES_DIR};
+
+/// Path of wimboot in the image store, fetched when Windows media is booted.
+pub const WIMBOOT_IMAGE: &str = "wimboot";
+pub const WIMBOOT_URL: &str = "https://github.com/ipxe/wimboot/releases/latest/download/wimboot";
+
+/// Where a distribution keeps its kernel and initrd within its ISOs and the
+/// kernel arguments booting them over the network. `{iso_url}` is replaced
+/// with the URL of the ISO, also the base URL of the files inside it.
+struct Distro {
+    name: &'static str,
+    kernel: &'static str,
+    initrd: &'static str,
+    args: &'static str,
+}
+
+/// Probed in order, the first whose kernel and initrd are found is used.
+const DISTROS: [Distro; 4] = [
+    Distro {
+        name: "Ubuntu",
+        kernel: "casper/vmlinuz",
+        initrd: "casper/initrd",
+        args: "ip=dhcp url={iso_url} cloud-config-url=/dev/null",
+    },
+    Distro {
+        name: "Fedora",
+        kernel: "images/pxeboot/vmlinuz",
+        initrd: "images/pxeboot/initrd.img",
+        args: "ip=dhcp inst.repo={iso_url}",
+    },
+    Distro {
+        name: "Arch Linux",
+        kernel: "arch/boot/x86_64/vmlinuz-linux",
+        initrd: "arch/boot/x86_64/initramfs-linux.img",
+        args: "ip=dhcp archisobasedir=arch archiso_http_srv={iso_url}/",
+    },
+    Distro {
+        name: "Debian Live",
+        kernel: "live/vmlinuz",
+        initrd: "live/initrd.img",
+        args: "ip=dhcp boot=live components fetch={iso_url}/live/filesystem.squashfs",
+    },
+];
+
+/// Boot entry found in an ISO image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootEntry {
+    pub distro: &'static str,
+    /// Path of the image relative to the image store.
+    pub image: PathBuf,
+    pub kernel: &'static str,
+    pub initrd: &'static str,
+    args: &'static str,
+}
+
+impl BootEntry {
+    /// Finds the distribution of the ISO `image` of the image store under
+    /// `tftp_root`, `None` when it's none of the known ones.
+    pub fn detect(tftp_root: &Path, image: &Path) -> io::Result<Option<Self>> {
+        let mut iso = IsoImage::open(&tftp_root.join(IMAGES_DIR).join(image))?;
+        for distro in &DISTROS {
+            if iso.find(Path::new(distro.kernel))?.is_some()
+                && iso.find(Path::new(distro.initrd))?.is_some()
+            {
+                return Ok(Some(Self {
+                    distro: distro.name,
+                    image: image.to_path_buf(),
+                    kernel: distro.kernel,
+                    initrd: distro.initrd,
+                    args: distro.args,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// iPXE script template booting the entry, to be rendered with the
+    /// client's variables. Further kernel arguments are taken from the
+    /// `kernel_args` variable.
+    pub fn ipxe_template(&self) -> String {
+        let iso_url = format!("{{{{base_url}}}}/{IMAGES_DIR}/{}", self.image.display());
+        let initrd_name = self.initrd.rsplit('/').next().unwrap_or(self.initrd);
+        let args = self.args.replace("{iso_url}", &iso_url);
+
+        format!(
+            "#!ipxe\n\
+             echo Booting {distro} from {image}\n\
+             kernel {iso_url}/{kernel} initrd={initrd_name} {args} {{{{kernel_args}}}}\n\
+             initrd {iso_url}/{initrd}\n\
+             boot\n",
+            distro = self.distro,
+            image = self.image.display(),
+            kernel = self.kernel,
+            initrd = self.initrd,
+        )
+    }
+
+    /// GRUB configuration template booting the entry, the files being loaded
+    /// over HTTP. Further kernel arguments are taken from the `kernel_args`
+    /// variable.
+    pub fn grub_template(&self) -> String {
+        let image_path = format!("/{IMAGES_DIR}/{}", self.image.display());
+        let iso_url = format!("{{{{base_url}}}}{image_path}");
+        let args = self.args.replace("{iso_url}", &iso_url);
+
+        format!(
+            "set timeout=0\n\
+             menuentry 'Boot {distro} from {image}' {{\n\
+             \x20   linux {{{{grub_root}}}}{image_path}/{kernel} {args} {{{{kernel_args}}}}\n\
+             \x20   initrd {{{{grub_root}}}}{image_path}/{initrd}\n\
+             }}\n",
+            distro = self.distro,
+            image = self.image.display(),
+            kernel = self.kernel,
+            initrd = self.initrd,
+        )
+    }
+}
+
+/// iPXE script template booting Windows installation media or WinPE with
+/// wimboot, `media` being a directory or ISO of the TFTP root. The client
+/// picks the BCD store of its firmware, BIOS or UEFI. Arguments for wimboot
+/// are taken from the `kernel_args` variable.
+pub fn windows_ipxe_template(media: &Path) -> String {
+    let media_url = format!("{{{{base_url}}}}/{}", media.display());
+
+    format!(
+        "#!ipxe\n\
+         echo Booting Windows from {media}\n\
+         kernel {{{{base_url}}}}/{IMAGES_DIR}/{WIMBOOT_IMAGE} {{{{kernel_args}}}}\n\
+         iseq ${{platform}} efi && set bcd efi/microsoft/boot/bcd || set bcd boot/bcd\n\
+         initrd {media_url}/${{bcd}} BCD\n\
+         initrd {media_url}/boot/boot.sdi boot.sdi\n\
+         initrd {media_url}/sources/boot.wim boot.wim\n\
+         boot\n",
+        media = media.display(),
+    )
+}
@@ -0,0 +1,413 @@
+//! DNS responder answering queries for `boot_hostname` with the address of
+//! the server on the interface the query came in, so boot URLs and iPXE
+//! scripts can use a name on networks without DNS. Other names are refused.
+//! Also looks the names of the clients up in the reverse DNS, for `match`.
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_std::{future::timeout, io, net::UdpSocket, task};
+use log::{debug, info, trace, warn};
+use once_cell::sync::Lazy;
+
+#[cfg(target_os = "linux")]
+use crate::activation;
+use crate::{conf::Conf, util::listen_ips, Result};
+
+pub const DEFAULT_DNS_PORT: u16 = 53;
+
+/// Seconds clients may cache the answers.
+const TTL: u32 = 300;
+const HEADER_LEN: usize = 12;
+/// Largest response of DNS over UDP without EDNS.
+const MAX_MESSAGE_LEN: usize = 512;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const OPCODE_MASK: u16 = 0x7800;
+
+const RCODE_FORMAT_ERROR: u16 = 1;
+const RCODE_NAME_ERROR: u16 = 3;
+const RCODE_NOT_IMPLEMENTED: u16 = 4;
+const RCODE_REFUSED: u16 = 5;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Compression pointer to the name of the question, right after the header.
+const QUESTION_NAME_POINTER: [u8; 2] = [0xc0, HEADER_LEN as u8];
+const RCODE_MASK: u16 = 0x000f;
+/// Compression pointers followed in a name before giving up on it as a loop.
+const MAX_POINTERS: usize = 16;
+
+/// Time the reverse lookups are waited for, short enough for the clients not
+/// to give up on the offer.
+pub const REVERSE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Time the names found in the reverse DNS, or their absence, are reused for.
+const REVERSE_CACHE_TTL: Duration = Duration::from_secs(300);
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// The names of the addresses looked up, with the time of the lookup.
+type ReverseCache = HashMap<Ipv4Addr, (Instant, Option<String>)>;
+static REVERSE_CACHE: Lazy<Mutex<ReverseCache>> = Lazy::new(Default::default);
+
+pub fn spawn_dns_service_async(conf: &Conf) -> Result<DnsService> {
+    let mut service = DnsService::default();
+    task::block_on(service.reload(conf))?;
+
+    Ok(service)
+}
+
+/// The DNS responders listening on each configured interface address.
+#[derive(Default)]
+pub struct DnsService {
+    settings: Option<DnsSettings>,
+    listeners: HashMap<SocketAddr, task::JoinHandle<()>>,
+}
+
+/// Settings the responders are restarted for when they change.
+#[derive(Clone, PartialEq)]
+struct DnsSettings {
+    hostname: String,
+    addrs: Vec<SocketAddr>,
+}
+
+impl DnsService {
+    /// Starts, restarts or stops the responders to match `conf`.
+    pub async fn reload(&mut self, conf: &Conf) -> Result<()> {
+        let Some(hostname) = conf.get_boot_hostname() else {
+            if self.settings.is_some() {
+                info!("boot_hostname no longer configured, stopping DNS responder.");
+            } else {
+                debug!("DNS responder not started, no boot_hostname configured.");
+            }
+            self.stop().await;
+            return Ok(());
+        };
+
+        let addrs = listen_ips(conf)?
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip.into(), conf.get_dns_port()))
+            .collect();
+        let settings = DnsSettings {
+            hostname: hostname.trim_end_matches('.').to_ascii_lowercase(),
+            addrs,
+        };
+        if self.settings.as_ref() == Some(&settings) {
+            return Ok(());
+        }
+
+        self.stop().await;
+        for &addr in &settings.addrs {
+            #[cfg(target_os = "linux")]
+            let activated = match addr {
+                SocketAddr::V4(addr) => activation::socket(addr, None)?,
+                SocketAddr::V6(_) => None,
+            };
+            #[cfg(not(target_os = "linux"))]
+            let activated: Option<std::net::UdpSocket> = None;
+            let socket = match activated {
+                Some(socket) => UdpSocket::from(socket),
+                None => UdpSocket::bind(addr).await?,
+            };
+            info!("DNS responder for {} started on {addr}", settings.hostname);
+            let hostname = settings.hostname.clone();
+            let listener = task::spawn(async move {
+                if let Err(e) = respond(socket, &hostname).await {
+                    warn!("DNS responder on {addr} failed: {e}");
+                }
+            });
+            self.listeners.insert(addr, listener);
+        }
+        self.settings = Some(settings);
+
+        Ok(())
+    }
+
+    pub async fn stop(&mut self) {
+        for (addr, listener) in self.listeners.drain() {
+            info!("DNS responder on {addr} stopped.");
+            listener.cancel().await;
+        }
+        self.settings = None;
+    }
+}
+
+/// Answers the queries received on `socket` until cancelled, a failure to
+/// receive or answer one query leaving the others answered.
+async fn respond(socket: UdpSocket, hostname: &str) -> io::Result<()> {
+    let SocketAddr::V4(local_addr) = socket.local_addr()? else {
+        return Ok(());
+    };
+    let mut buf = [0u8; MAX_MESSAGE_LEN];
+
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("Failed receiving DNS query on {local_addr}: {e}");
+                continue;
+            }
+        };
+        // No reply can reach port 0
+        if peer.port() == 0 {
+            debug!("DNS query from port 0 of {}, dropped.", peer.ip());
+            continue;
+        }
+        if let Some(response) = answer(&buf[..len], hostname, *local_addr.ip()) {
+            trace!("Answering DNS query of {peer}");
+            if let Err(e) = socket.send_to(&response, peer).await {
+                warn!("Failed answering DNS query of {peer}: {e}");
+            }
+        }
+    }
+}
+
+/// Response to the DNS `query`, resolving `hostname` to `ip`. `None` for
+/// messages that aren't queries, which are ignored.
+pub fn answer(query: &[u8], hostname: &str, ip: Ipv4Addr) -> Option<Vec<u8>> {
+    let header = query.get(..HEADER_LEN)?;
+    let flags = u16::from_be_bytes([header[2], header[3]]);
+    if flags & FLAG_RESPONSE != 0 {
+        return None;
+    }
+
+    let reply = |rcode: u16, question: &[u8], answers: &[Vec<u8>]| {
+        let flags = FLAG_RESPONSE
+            | FLAG_AUTHORITATIVE
+            | (flags & (OPCODE_MASK | FLAG_RECURSION_DESIRED))
+            | rcode;
+        let mut response = Vec::with_capacity(HEADER_LEN + question.len() + 16);
+        response.extend_from_slice(&header[..2]);
+        response.extend_from_slice(&flags.to_be_bytes());
+        response.extend_from_slice(&u16::from(!question.is_empty()).to_be_bytes());
+        response.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        response.extend_from_slice(&[0, 0, 0, 0]);
+        response.extend_from_slice(question);
+        answers.iter().for_each(|record| response.extend_from_slice(record));
+        response
+    };
+
+    let question_count = u16::from_be_bytes([header[4], header[5]]);
+    if flags & OPCODE_MASK != 0 {
+        return Some(reply(RCODE_NOT_IMPLEMENTED, &[], &[]));
+    }
+    let Some((name, question)) = (question_count == 1)
+        .then(|| parse_question(&query[HEADER_LEN..]))
+        .flatten()
+    else {
+        return Some(reply(RCODE_FORMAT_ERROR, &[], &[]));
+    };
+    let qtype = u16::from_be_bytes([question[question.len() - 4], question[question.len() - 3]]);
+    let qclass = u16::from_be_bytes([question[question.len() - 2], question[question.len() - 1]]);
+    if name != hostname {
+        debug!("Refusing DNS query for {name}");
+        return Some(reply(RCODE_REFUSED, question, &[]));
+    }
+
+    // Other record types of the name exist but are empty
+    let answers = match (qtype, qclass) {
+        (TYPE_A | TYPE_ANY, CLASS_IN) => {
+            let mut record = QUESTION_NAME_POINTER.to_vec();
+            record.extend_from_slice(&TYPE_A.to_be_bytes());
+            record.extend_from_slice(&CLASS_IN.to_be_bytes());
+            record.extend_from_slice(&TTL.to_be_bytes());
+            record.extend_from_slice(&4u16.to_be_bytes());
+            record.extend_from_slice(&ip.octets());
+            vec![record]
+        }
+        _ => Vec::new(),
+    };
+
+    Some(reply(0, question, &answers))
+}
+
+/// Lowercase name of the question at the start of `message`, and the
+/// question as a whole, its type and class included.
+fn parse_question(message: &[u8]) -> Option<(String, &[u8])> {
+    let mut labels = Vec::new();
+    let mut offset = 0;
+    loop {
+        let len = *message.get(offset)? as usize;
+        offset += 1;
+        if len == 0 {
+            break;
+        }
+        // Compression pointers and extended labels don't occur in questions
+        if len > 63 {
+            return None;
+        }
+        let label = message.get(offset..offset + len)?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        offset += len;
+    }
+    let question = message.get(..offset + 4)?;
+
+    Some((labels.join("."), question))
+}
+
+/// Name of `ip` in the reverse DNS, asked to `reverse_dns_server` or else to
+/// the first name server of the system. `None` when it has none or the lookup
+/// failed, which is logged. Answers are cached, failures aren't.
+pub async fn reverse_hostname(conf: &Conf, ip: Ipv4Addr) -> Option<String> {
+    {
+        let cache = REVERSE_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((time, name)) = cache.get(&ip) {
+            if time.elapsed() < REVERSE_CACHE_TTL {
+                return name.clone();
+            }
+        }
+    }
+
+    let Some(server) = conf.get_reverse_dns_server().or_else(system_name_server) else {
+        warn!("No DNS server for the reverse lookup of {ip}, set reverse_dns_server.");
+        return None;
+    };
+    let name = match timeout(REVERSE_TIMEOUT, reverse_lookup(server, ip)).await {
+        Ok(Ok(name)) => name,
+        Ok(Err(e)) => {
+            warn!("Reverse lookup of {ip} on {server} failed: {e}");
+            return None;
+        }
+        Err(_) => {
+            warn!("Reverse lookup of {ip} on {server} timed out.");
+            return None;
+        }
+    };
+    debug!("Reverse lookup of {ip}: {}", name.as_deref().unwrap_or("no name"));
+
+    let mut cache = REVERSE_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache.retain(|_, (time, _)| time.elapsed() < REVERSE_CACHE_TTL);
+    cache.insert(ip, (Instant::now(), name.clone()));
+    name
+}
+
+/// Asks the DNS `server` for the `PTR` record of `ip`, `None` when it has
+/// none.
+pub async fn reverse_lookup(server: SocketAddr, ip: Ipv4Addr) -> Result<Option<String>> {
+    let local_ip: IpAddr = match server {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(local_ip, 0)).await?;
+    let id: u16 = rand::random();
+    socket.send_to(&ptr_query(id, ip), server).await?;
+
+    let mut buf = [0u8; MAX_MESSAGE_LEN];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+        // Anything else is stale or spoofed
+        if peer == server && buf[..len].starts_with(&id.to_be_bytes()) {
+            return parse_ptr_response(&buf[..len]);
+        }
+    }
+}
+
+/// Query of the `PTR` record of `ip`, recursion desired.
+pub fn ptr_query(id: u16, ip: Ipv4Addr) -> Vec<u8> {
+    let mut query = id.to_be_bytes().to_vec();
+    query.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    let [a, b, c, d] = ip.octets();
+    for label in [d.to_string(), c.to_string(), b.to_string(), a.to_string()] {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    for label in ["in-addr", "arpa"] {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    query
+}
+
+/// Lowercase name of the first `PTR` record answered in `response`, `None`
+/// when the name doesn't exist or has none.
+pub fn parse_ptr_response(response: &[u8]) -> Result<Option<String>> {
+    let malformed = || anyhow!("Malformed DNS response");
+    let header = response.get(..HEADER_LEN).ok_or_else(malformed)?;
+    let flags = u16::from_be_bytes([header[2], header[3]]);
+    if flags & FLAG_RESPONSE == 0 {
+        return Err(malformed());
+    }
+    match flags & RCODE_MASK {
+        0 => {}
+        RCODE_NAME_ERROR => return Ok(None),
+        rcode => bail!("The DNS server answered with error {rcode}"),
+    }
+
+    let question_count = u16::from_be_bytes([header[4], header[5]]);
+    let answer_count = u16::from_be_bytes([header[6], header[7]]);
+    let mut offset = HEADER_LEN;
+    for _ in 0..question_count {
+        let (_, end) = read_name(response, offset).ok_or_else(malformed)?;
+        offset = end + 4;
+    }
+    for _ in 0..answer_count {
+        let (_, end) = read_name(response, offset).ok_or_else(malformed)?;
+        let record = response.get(end..end + 10).ok_or_else(malformed)?;
+        let rtype = u16::from_be_bytes([record[0], record[1]]);
+        let data_len = usize::from(u16::from_be_bytes([record[8], record[9]]));
+        if rtype == TYPE_PTR {
+            let (name, _) = read_name(response, end + 10)
+                .context("Reading the name of a PTR record")?;
+            return Ok(Some(name));
+        }
+        offset = end + 10 + data_len;
+    }
+
+    Ok(None)
+}
+
+/// Lowercase name at `offset` of `message`, following the compression
+/// pointers, and the offset after it.
+fn read_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *message.get(offset)? as usize;
+        match len {
+            0 => {
+                end.get_or_insert(offset + 1);
+                break;
+            }
+            _ if len & 0xc0 == 0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                end.get_or_insert(offset + 2);
+                offset = ((len & 0x3f) << 8) | *message.get(offset + 1)? as usize;
+            }
+            _ if len > 63 => return None,
+            _ => {
+                let label = message.get(offset + 1..offset + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+                offset += 1 + len;
+            }
+        }
+    }
+
+    Some((labels.join("."), end?))
+}
+
+/// First name server of the system's resolver configuration.
+fn system_name_server() -> Option<SocketAddr> {
+    let resolv_conf = std::fs::read_to_string(RESOLV_CONF).ok()?;
+    resolv_conf.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        (words.next() == Some("nameserver"))
+            .then(|| words.next()?.parse::<IpAddr>().ok())
+            .flatten()
+            .map(|ip| SocketAddr::new(ip, DEFAULT_DNS_PORT))
+    })
+}
@@ -0,0 +1,428 @@
+//! `doctor` subcommand: checks the host for the usual reasons of clients not
+//! booting while the configuration is right, the ports being taken by
+//! another DHCP or TFTP server, the rights to bind them missing, the firewall
+//! dropping the broadcasts and the interfaces having no IPv4 address, and
+//! tells how to fix each.
+use std::{fs, net::UdpSocket, process};
+
+use network_interface::{Addr, NetworkInterface, NetworkInterfaceConfig};
+
+use crate::conf::Conf;
+
+const CAP_NET_BIND_SERVICE: u32 = 10;
+const CAP_NET_RAW: u32 = 13;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Ok(String),
+    Problem { what: String, fix: String },
+    /// The check couldn't be run, e.g. for lacking a tool.
+    Skipped(String),
+}
+
+/// The outcome of one check, e.g. of `Port 67`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Check {
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+impl Check {
+    pub fn new(name: impl Into<String>, outcome: Outcome) -> Self {
+        Self { name: name.into(), outcome }
+    }
+
+    pub fn is_problem(&self) -> bool {
+        matches!(self.outcome, Outcome::Problem { .. })
+    }
+}
+
+/// A process holding a socket.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Owner {
+    pub pid: u32,
+    pub name: String,
+}
+
+/// Runs the checks for the services `conf` starts.
+pub fn run(conf: &Conf) -> Vec<Check> {
+    let ports = ports(conf);
+    let mut checks: Vec<Check> = ports.iter().map(|port| port_check(*port)).collect();
+    checks.extend(capability_checks(
+        fs::read_to_string("/proc/self/status").ok().as_deref(),
+    ));
+    checks.push(firewall_check(&ports));
+    match NetworkInterface::show() {
+        Ok(found) => checks.extend(iface_checks(&ifaces(conf), &found)),
+        Err(e) => checks.push(Check::new(
+            "Interfaces",
+            Outcome::Skipped(format!("listing them failed, {e}")),
+        )),
+    }
+
+    checks
+}
+
+/// The UDP ports the services of `conf` listen on.
+pub fn ports(conf: &Conf) -> Vec<u16> {
+    let mut ports = Vec::new();
+    for (_, conf) in conf.served() {
+        if conf.get_dhcp_enabled() {
+            ports.extend([67, 68]);
+            if conf.get_pxe_boot_server() {
+                ports.push(4011);
+            }
+        }
+        if conf.get_tftp_enabled() && conf.get_tftp_serve_path().is_some() {
+            ports.push(69);
+        }
+    }
+    ports.sort_unstable();
+    ports.dedup();
+
+    ports
+}
+
+/// The interfaces checked, those of `ifaces` of the configuration and its
+/// tenants, `None` for all of them.
+fn ifaces(conf: &Conf) -> Option<Vec<String>> {
+    let mut names = Vec::new();
+    for (_, conf) in conf.served() {
+        for name in conf.get_ifaces()? {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+    }
+
+    Some(names)
+}
+
+/// Whether another process has bound `port`, the DHCP client of the OS and
+/// a running preboot-oxide excepted.
+fn port_check(port: u16) -> Check {
+    let name = format!("Port {port}");
+    let tables = ["/proc/net/udp", "/proc/net/udp6"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .collect::<Vec<_>>();
+    if tables.is_empty() {
+        // Not Linux, trying to bind it tells as much
+        return match UdpSocket::bind(("0.0.0.0", port)) {
+            Ok(_) => Check::new(name, Outcome::Ok("free".to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                Check::new(name, in_use(port, &[]))
+            }
+            Err(e) => Check::new(name, Outcome::Skipped(format!("binding it failed, {e}"))),
+        };
+    }
+    let inodes: Vec<u64> = tables.iter().flat_map(|table| udp_inodes(table, port)).collect();
+    let owners: Vec<Owner> = socket_owners(&inodes)
+        .into_iter()
+        .filter(|owner| owner.pid != process::id())
+        .collect();
+    let is_preboot_oxide = |owner: &Owner| owner.name == "preboot-oxide";
+    let outcome = match owners.is_empty() {
+        true if inodes.is_empty() => Outcome::Ok("free".to_string()),
+        // Its sockets can't be told apart without the rights to read the
+        // descriptors of other processes
+        true => Outcome::Skipped("bound by a process that can't be told, run as root".to_string()),
+        false if owners.iter().all(is_preboot_oxide) => {
+            Outcome::Ok("bound by the running preboot-oxide".to_string())
+        }
+        false if port == 68 && owners.iter().all(|owner| is_dhcp_client(&owner.name)) => {
+            Outcome::Ok(format!("shared with the DHCP client {}", owners[0].name))
+        }
+        false => in_use(port, &owners),
+    };
+
+    Check::new(name, outcome)
+}
+
+fn in_use(port: u16, owners: &[Owner]) -> Outcome {
+    let what = match owners.is_empty() {
+        true => "in use by another process".to_string(),
+        false => format!(
+            "in use by {}",
+            owners
+                .iter()
+                .map(|owner| format!("{} (pid {})", owner.name, owner.pid))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let service = match port {
+        69 => "TFTP server, or disable the TFTP service of preboot-oxide with `tftp.enabled: false`",
+        _ => "DHCP or PXE server, such as dnsmasq, or limit it to other interfaces",
+    };
+
+    Outcome::Problem {
+        what,
+        fix: format!("Stop the other {service}. `sudo ss -ulpn sport = :{port}` lists it."),
+    }
+}
+
+fn is_dhcp_client(name: &str) -> bool {
+    ["dhclient", "dhcpcd", "NetworkManager", "systemd-network", "udhcpc"].contains(&name)
+}
+
+/// The inodes of the sockets bound to the local `port` in `table`, the
+/// content of `/proc/net/udp` or `/proc/net/udp6`.
+pub fn udp_inodes(table: &str, port: u16) -> Vec<u64> {
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local_port = fields.get(1)?.rsplit(':').next()?;
+            (u16::from_str_radix(local_port, 16).ok()? == port)
+                .then(|| fields.get(9)?.parse().ok())
+                .flatten()
+        })
+        .collect()
+}
+
+/// The processes holding the sockets of `inodes`, found among the file
+/// descriptors of those of `/proc` that can be read.
+fn socket_owners(inodes: &[u64]) -> Vec<Owner> {
+    if inodes.is_empty() {
+        return Vec::new();
+    }
+    let links: Vec<String> = inodes.iter().map(|inode| format!("socket:[{inode}]")).collect();
+    let Ok(procs) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut owners = Vec::new();
+    for entry in procs.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|pid| pid.parse().ok()) else {
+            continue;
+        };
+        let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        let holds_one = fds.flatten().any(|fd| {
+            fs::read_link(fd.path())
+                .map(|target| links.iter().any(|link| target.as_os_str() == link.as_str()))
+                .unwrap_or(false)
+        });
+        if holds_one {
+            let name = fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+            owners.push(Owner { pid, name: name.trim().to_string() });
+        }
+    }
+
+    owners
+}
+
+/// The effective capabilities of `/proc/self/status`, `None` when it
+/// doesn't tell them.
+pub fn effective_capabilities(status: &str) -> Option<u64> {
+    let caps = status.lines().find_map(|line| line.strip_prefix("CapEff:"))?;
+    u64::from_str_radix(caps.trim(), 16).ok()
+}
+
+/// Whether the process may bind the ports below 1024, and its sockets to an
+/// interface, from its `/proc/self/status`.
+pub fn capability_checks(status: Option<&str>) -> Vec<Check> {
+    let Some(caps) = status.and_then(effective_capabilities) else {
+        return vec![Check::new(
+            "Capabilities",
+            Outcome::Skipped("they can't be read, outside of Linux".to_string()),
+        )];
+    };
+    let fix = "Run it as root, or grant them to the binary: \
+        `sudo setcap cap_net_bind_service,cap_net_raw+ep $(which preboot-oxide)`, \
+        or `AmbientCapabilities=CAP_NET_BIND_SERVICE CAP_NET_RAW` in its systemd unit.";
+    let check = |cap: u32, name: &str, needed_for: &str| {
+        let outcome = match caps & (1 << cap) != 0 {
+            true => Outcome::Ok("granted".to_string()),
+            false => Outcome::Problem {
+                what: format!("missing, needed {needed_for}"),
+                fix: fix.to_string(),
+            },
+        };
+        Check::new(name, outcome)
+    };
+
+    vec![
+        check(CAP_NET_BIND_SERVICE, "CAP_NET_BIND_SERVICE", "to bind ports 67, 68 and 69"),
+        check(CAP_NET_RAW, "CAP_NET_RAW", "to bind the sockets to the interfaces"),
+    ]
+}
+
+/// Whether the firewall lets the broadcasts to `ports` in, from the rules
+/// of `iptables -S INPUT`.
+fn firewall_check(ports: &[u16]) -> Check {
+    let rules = process::Command::new("iptables").args(["-S", "INPUT"]).output();
+    let outcome = match rules {
+        Ok(output) if output.status.success() => {
+            firewall_outcome(ports, &firewall_drops(&String::from_utf8_lossy(&output.stdout), ports))
+        }
+        Ok(output) => Outcome::Skipped(format!(
+            "iptables failed, {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+        Err(_) => Outcome::Skipped("iptables isn't installed".to_string()),
+    };
+
+    Check::new("Firewall", outcome)
+}
+
+fn firewall_outcome(ports: &[u16], dropped: &[u16]) -> Outcome {
+    if dropped.is_empty() {
+        return Outcome::Ok("lets the DHCP and TFTP requests in".to_string());
+    }
+    let list = |ports: &[u16]| ports.iter().map(u16::to_string).collect::<Vec<_>>().join(",");
+    Outcome::Problem {
+        what: format!("drops UDP port {}", list(dropped).replace(',', ", ")),
+        fix: format!(
+            "Let them in: `sudo iptables -I INPUT -p udp -m multiport --dports {} -j ACCEPT`, \
+             or with firewalld `sudo firewall-cmd --permanent --add-service=dhcp --add-service=tftp`.",
+            list(ports)
+        ),
+    }
+}
+
+/// The `ports` a broadcast to which the `rules` of the INPUT chain, as
+/// printed by `iptables -S INPUT`, drop or reject. The rules limited to
+/// sources or connection states are left out, they don't decide about new
+/// clients.
+pub fn firewall_drops(rules: &str, ports: &[u16]) -> Vec<u16> {
+    let mut policy_drops = false;
+    let mut decided: Vec<(u16, bool)> = Vec::new();
+    for rule in rules.lines() {
+        let args: Vec<&str> = rule.split_whitespace().collect();
+        match args.as_slice() {
+            ["-P", "INPUT", policy, ..] => policy_drops = *policy != "ACCEPT",
+            ["-A", "INPUT", rest @ ..] => {
+                let arg = |name: &str| {
+                    rest.iter()
+                        .position(|arg| *arg == name)
+                        .and_then(|i| rest.get(i + 1).copied())
+                };
+                let Some(drops) = (match arg("-j") {
+                    Some("ACCEPT") => Some(false),
+                    Some("DROP" | "REJECT") => Some(true),
+                    _ => None,
+                }) else {
+                    continue;
+                };
+                let is_limited = ["-s", "--ctstate", "--state", "--sport", "--sports"]
+                    .iter()
+                    .any(|name| rest.contains(name));
+                if is_limited || !matches!(arg("-p"), None | Some("udp" | "17" | "all")) {
+                    continue;
+                }
+                let rule_ports = arg("--dport").or(arg("--dports")).map(|list| {
+                    list.split(',')
+                        .filter_map(|port| {
+                            let (first, last) = port.split_once(':').unwrap_or((port, port));
+                            Some(first.parse::<u16>().ok()?..=last.parse::<u16>().ok()?)
+                        })
+                        .collect::<Vec<_>>()
+                });
+                for port in ports {
+                    let applies = rule_ports
+                        .as_ref()
+                        .map(|ranges| ranges.iter().any(|range| range.contains(port)))
+                        .unwrap_or(true);
+                    if applies && !decided.iter().any(|(decided, _)| decided == port) {
+                        decided.push((*port, drops));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    ports
+        .iter()
+        .copied()
+        .filter(|port| match decided.iter().find(|(decided, _)| decided == port) {
+            Some((_, drops)) => *drops,
+            None => policy_drops,
+        })
+        .collect()
+}
+
+/// Whether each of the interfaces `ifaces` exists and has an IPv4 address
+/// to answer from, or, when `None`, whether any of those `found` has one.
+pub fn iface_checks(ifaces: &Option<Vec<String>>, found: &[NetworkInterface]) -> Vec<Check> {
+    let ipv4 = |name: &str| {
+        found
+            .iter()
+            .filter(|iface| iface.name == name)
+            .flat_map(|iface| &iface.addr)
+            .find_map(|addr| match addr {
+                Addr::V4(addr) => Some(addr.ip),
+                Addr::V6(_) => None,
+            })
+    };
+    let Some(names) = ifaces else {
+        let mut checks: Vec<Check> = Vec::new();
+        for iface in found.iter().filter(|iface| iface.name != "lo") {
+            let name = format!("Interface {}", iface.name);
+            if let (Some(ip), false) = (ipv4(&iface.name), checks.iter().any(|c| c.name == name)) {
+                checks.push(Check::new(name, Outcome::Ok(ip.to_string())));
+            }
+        }
+        if checks.is_empty() {
+            checks.push(Check::new(
+                "Interfaces",
+                Outcome::Problem {
+                    what: "none has an IPv4 address, the DHCP answers can't be sent".to_string(),
+                    fix: "Give the interface of the network of the clients an address, e.g. \
+                        `sudo ip addr add 192.168.1.2/24 dev eth0`."
+                        .to_string(),
+                },
+            ));
+        }
+        return checks;
+    };
+
+    names
+        .iter()
+        .map(|name| {
+            let outcome = match ipv4(name) {
+                Some(ip) => Outcome::Ok(ip.to_string()),
+                None if !found.iter().any(|iface| &iface.name == name) => Outcome::Problem {
+                    what: "not found".to_string(),
+                    fix: "Correct its name in `ifaces`, `ip link` lists them.".to_string(),
+                },
+                None => Outcome::Problem {
+                    what: "has no IPv4 address, the DHCP answers can't be sent from it".to_string(),
+                    fix: format!(
+                        "Give it an address of its network, e.g. \
+                         `sudo ip addr add 192.168.1.2/24 dev {name}`."
+                    ),
+                },
+            };
+            Check::new(format!("Interface {name}"), outcome)
+        })
+        .collect()
+}
+
+/// The outcomes of `checks`, one per line, followed by how to fix the
+/// problems.
+pub fn report(checks: &[Check]) -> String {
+    let mut lines = Vec::new();
+    for check in checks {
+        match &check.outcome {
+            Outcome::Ok(detail) => lines.push(format!("[ok]   {}: {detail}", check.name)),
+            Outcome::Skipped(reason) => {
+                lines.push(format!("[skip] {}: not checked, {reason}", check.name))
+            }
+            Outcome::Problem { what, fix } => {
+                lines.push(format!("[fail] {}: {what}", check.name));
+                lines.push(format!("       Fix: {fix}"));
+            }
+        }
+    }
+    lines.push(match checks.iter().filter(|check| check.is_problem()).count() {
+        0 => "No problems found.".to_string(),
+        1 => "1 problem found.".to_string(),
+        count => format!("{count} problems found."),
+    });
+
+    lines.join("\n") + "\n"
+}
@@ -0,0 +1,339 @@
+//! Event hooks: programs run on the events of the clients, `event_hooks`
+//! naming the program of each event, for sites to trigger their own
+//! automation, such as DNS updates or ticket comments, without an API
+//! client. The details of the client are passed in environment variables.
+//! Unlike `boot_hook`, the programs are run aside, the clients not waiting
+//! for them. The events can be watched as they happen too, see [`watch`],
+//! and are posted as JSON to the URLs of `event_webhooks`, for inventory
+//! systems and chat-ops to be told rather than to poll, or as messages to
+//! the chats and mailboxes of `notifications`, see [`crate::notifications`].
+use std::{
+    fmt,
+    net::Ipv4Addr,
+    path::Path,
+    process::{Command, Stdio},
+    str::FromStr,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_std::task;
+use futures::{
+    channel::mpsc,
+    future,
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    conf::{Conf, ENV_VAR_PREFIX},
+    logging, mqtt, notifications,
+    template::parse_mac,
+    util::redact_url,
+    Result,
+};
+
+/// Time a hook may run for before it's killed.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+/// Time a webhook is waited for on each attempt.
+pub const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Attempts at posting an event to a webhook, the first one included.
+pub const WEBHOOK_ATTEMPTS: u32 = 4;
+/// Time waited before the second attempt, doubled for each next one.
+const WEBHOOK_BACKOFF: Duration = Duration::from_secs(1);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Events queued for a watcher, those past it being dropped for the
+/// watcher not to hold the server back.
+const WATCH_QUEUE: usize = 256;
+/// Time between the keep-alives of the event streams, for the watchers
+/// gone to be found out while no client boots.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+type Watcher = mpsc::Sender<(Event, ClientEvent)>;
+
+/// The watchers of the events, see [`watch`].
+static WATCHERS: Lazy<Mutex<Vec<Watcher>>> = Lazy::new(Default::default);
+
+/// What happened to a client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Event {
+    /// A client asking for a boot file started a DHCP handshake.
+    DiscoverSeen,
+    /// The configuration of the client was decided, by a `match` rule,
+    /// `default`, the inventory or the webhook.
+    RuleMatched,
+    /// No configuration was found for a client asking for a boot file,
+    /// which is left unanswered.
+    UnknownClient,
+    /// The boot information was offered to the client, after the DHCP
+    /// server of the network offered it an address.
+    OfferSent,
+    /// The client downloaded whole over TFTP the boot file it was given.
+    BootFileDelivered,
+    /// The DHCP handshake of the client wasn't acknowledged within 2
+    /// minutes.
+    SessionTimedOut,
+    /// The DHCP sessions of an interface kept expiring without the DHCP
+    /// server of the network offering an address, as when there's none on
+    /// its network, the client being the last of them.
+    UpstreamDhcpMissing,
+}
+
+impl Event {
+    pub const ALL: [Event; 7] = [
+        Event::DiscoverSeen,
+        Event::RuleMatched,
+        Event::UnknownClient,
+        Event::OfferSent,
+        Event::BootFileDelivered,
+        Event::SessionTimedOut,
+        Event::UpstreamDhcpMissing,
+    ];
+
+    /// Its key in `event_hooks`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::DiscoverSeen => "discover_seen",
+            Event::RuleMatched => "rule_matched",
+            Event::UnknownClient => "unknown_client",
+            Event::OfferSent => "offer_sent",
+            Event::BootFileDelivered => "boot_file_delivered",
+            Event::SessionTimedOut => "session_timed_out",
+            Event::UpstreamDhcpMissing => "upstream_dhcp_missing",
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Event {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Event::ALL
+            .into_iter()
+            .find(|event| event.name() == s)
+            .ok_or(anyhow!(
+                "Unknown event {s}, expected one of discover_seen, rule_matched, unknown_client, offer_sent, boot_file_delivered, session_timed_out, upstream_dhcp_missing"
+            ))
+    }
+}
+
+/// The client an event is about, as much as is known of it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientEvent {
+    pub mac_address: String,
+    pub xid: u32,
+    pub ip: Option<Ipv4Addr>,
+    pub boot_file: Option<String>,
+    /// Interface the client's messages came in on.
+    pub iface: Option<String>,
+    /// Last exchange of its DHCP handshake, for timed out sessions.
+    pub stage: Option<String>,
+    /// What decided its configuration, as `sessions` tells it.
+    pub rule: Option<String>,
+}
+
+impl ClientEvent {
+    /// The environment variables `event` about the client is passed in,
+    /// those unknown left out.
+    pub fn env(&self, event: Event) -> Vec<(String, String)> {
+        let vars = [
+            ("EVENT", Some(event.name().to_string())),
+            ("CLIENT_MAC", Some(self.mac_address.clone())),
+            ("XID", Some(format!("{:#010x}", self.xid))),
+            ("SESSION", parse_mac(&self.mac_address).map(|mac| logging::session_id(&mac, self.xid))),
+            ("CLIENT_IP", self.ip.map(|ip| ip.to_string())),
+            ("BOOT_FILE", self.boot_file.clone()),
+            ("IFACE", self.iface.clone()),
+            ("STAGE", self.stage.clone()),
+            ("RULE", self.rule.clone()),
+        ];
+        vars.into_iter()
+            .filter_map(|(name, value)| Some((format!("{ENV_VAR_PREFIX}{name}"), value?)))
+            .collect()
+    }
+
+    /// The JSON document `event` about the client is posted as, with the
+    /// time it happened at, those unknown being `null`.
+    pub fn to_json(&self, event: Event, time: OffsetDateTime) -> serde_json::Value {
+        serde_json::json!({
+            "event": event.name(),
+            "time": time.format(&Rfc3339).unwrap_or_default(),
+            "mac_address": self.mac_address,
+            "xid": format!("{:#010x}", self.xid),
+            "session": parse_mac(&self.mac_address).map(|mac| logging::session_id(&mac, self.xid)),
+            "ip": self.ip,
+            "boot_file": self.boot_file,
+            "iface": self.iface,
+            "stage": self.stage,
+            "rule": self.rule,
+        })
+    }
+}
+
+/// The events of the clients from now on, as they happen, until the
+/// receiver is dropped.
+pub fn watch() -> mpsc::Receiver<(Event, ClientEvent)> {
+    let (sender, receiver) = mpsc::channel(WATCH_QUEUE);
+    WATCHERS.lock().unwrap_or_else(|e| e.into_inner()).push(sender);
+    receiver
+}
+
+/// The events of the clients from now on as their JSON documents, those of
+/// `mac_address` only when given, as the `watch` command and the event
+/// stream of the control API send them. `None` comes every
+/// `KEEPALIVE_INTERVAL`, for a keep-alive to be sent.
+pub fn watch_json(mac_address: Option<String>) -> BoxStream<'static, Option<serde_json::Value>> {
+    let events = watch()
+        .filter(move |(_, client)| {
+            let watched = mac_address
+                .as_ref()
+                .is_none_or(|mac| client.mac_address.eq_ignore_ascii_case(mac));
+            future::ready(watched)
+        })
+        .map(|(event, client)| Some(client.to_json(event, OffsetDateTime::now_utc())));
+    let keepalives = stream::unfold((), |()| async {
+        task::sleep(KEEPALIVE_INTERVAL).await;
+        Some((None, ()))
+    });
+
+    stream::select(events, keepalives).boxed()
+}
+
+/// Tells the watchers about `event`, forgetting those gone.
+fn notify_watchers(event: Event, client: &ClientEvent) {
+    let mut watchers = WATCHERS.lock().unwrap_or_else(|e| e.into_inner());
+    watchers.retain_mut(|watcher| match watcher.try_send((event, client.clone())) {
+        Ok(()) => true,
+        Err(e) if e.is_full() => {
+            debug!("Event watcher lagging behind, {event} for {} dropped.", client.mac_address);
+            true
+        }
+        Err(_) => false,
+    });
+}
+
+/// Runs the hook of `event` in `conf`, if any, and posts it to the
+/// webhooks and notifications taking it, in the background, their failures
+/// being only logged, and tells the watchers about it.
+pub fn fire(conf: &Conf, event: Event, client: ClientEvent) {
+    notify_watchers(event, &client);
+    post_webhooks(conf, event, &client);
+    mqtt::publish(conf, event, &client);
+    notifications::send(conf, event, &client);
+    let Some(hook) = conf.get_event_hook(event).cloned() else {
+        return;
+    };
+    task::spawn_blocking(move || {
+        debug!("Running the {event} hook {} for {}.", hook.display(), client.mac_address);
+        if let Err(e) = run(&hook, TIMEOUT, &client.env(event)) {
+            warn!("The {event} hook {} failed for {}: {e:#}", hook.display(), client.mac_address);
+        }
+    });
+}
+
+/// Posts `event` to the `event_webhooks` of `conf` taking it, each in a
+/// task of its own.
+fn post_webhooks(conf: &Conf, event: Event, client: &ClientEvent) {
+    let urls: Vec<String> = conf
+        .get_event_webhooks()
+        .iter()
+        .filter(|webhook| webhook.takes(event))
+        .map(|webhook| webhook.url.clone())
+        .collect();
+    if urls.is_empty() {
+        return;
+    }
+    let body = client.to_json(event, OffsetDateTime::now_utc()).to_string();
+    for url in urls {
+        let body = body.clone();
+        let mac_address = client.mac_address.clone();
+        task::spawn(async move {
+            if let Err(e) = post(&url, &body, WEBHOOK_ATTEMPTS).await {
+                warn!("Posting {event} for {mac_address} to {} failed: {e:#}", redact_url(&url));
+            }
+        });
+    }
+}
+
+/// Posts the JSON `body` to `url`, trying again up to `attempts` times in
+/// all, after 1 second and twice as long each time, when it can't be
+/// reached or answers with a server error or 429 Too Many Requests. Other
+/// errors aren't tried again.
+pub async fn post(url: &str, body: &str, attempts: u32) -> Result<()> {
+    let mut backoff = WEBHOOK_BACKOFF;
+    for attempt in 1..=attempts {
+        let (target, json) = (url.to_string(), body.to_string());
+        // Told without the URL, which may hold a secret
+        let result = task::spawn_blocking(move || {
+            let response = ureq::AgentBuilder::new()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .post(&target)
+                .set("Content-Type", "application/json")
+                .send_string(&json);
+            match response {
+                Ok(_) => Ok(()),
+                Err(ureq::Error::Status(status, _)) => Err((Some(status), format!("Answered with status {status}"))),
+                Err(ureq::Error::Transport(e)) => match e.message() {
+                    Some(message) => Err((None, format!("{}: {message}", e.kind()))),
+                    None => Err((None, e.kind().to_string())),
+                },
+            }
+        })
+        .await;
+        let retry = match result {
+            Ok(()) => return Ok(()),
+            Err((Some(status), error)) if status != 429 && status < 500 => bail!(error),
+            Err((_, error)) => error,
+        };
+        if attempt == attempts {
+            bail!("{retry}, after {attempts} attempts");
+        }
+        debug!("Posting to {} failed, trying again in {backoff:?}: {retry}", redact_url(url));
+        task::sleep(backoff).await;
+        backoff *= 2;
+    }
+
+    Ok(())
+}
+
+/// Runs `hook` with `env` added to its environment, waiting for it to exit.
+pub fn run(hook: &Path, timeout: Duration, env: &[(String, String)]) -> Result<()> {
+    let mut child = Command::new(hook)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("Starting {}", hook.display()))?;
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("Timed out after {timeout:?}");
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    if !status.success() {
+        bail!("Exited with {status}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//! Downloads of the remote files kept up to date by the server.
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use async_std::task;
+use log::debug;
+
+use crate::{util::part_path, Result};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Downloads stalling for longer than this are given up.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Downloads `url` to `dest`, skipping it when `dest` is newer than the
+/// remote file. The file is only replaced once completely downloaded, so it
+/// can be served meanwhile. Returns whether it was updated.
+pub async fn download(url: &str, dest: &Path) -> Result<bool> {
+    let modified = fs::metadata(dest).and_then(|m| m.modified()).ok();
+    let Some(Download { part, .. }) = download_part(url, dest, modified, None).await? else {
+        return Ok(false);
+    };
+
+    fs::rename(&part, dest)
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&part);
+        })
+        .with_context(|| format!("Saving {url} to {}", dest.display()))?;
+    Ok(true)
+}
+
+/// A remote file downloaded next to its destination.
+pub struct Download {
+    pub part: PathBuf,
+    /// Identifies the downloaded version of the remote file.
+    pub etag: Option<String>,
+}
+
+/// Downloads `url` to a temporary file next to `dest`, unless the remote file
+/// wasn't modified since `since`, or still has the `etag` of the previous
+/// download. The caller moves the file into place or removes it.
+pub async fn download_part(
+    url: &str,
+    dest: &Path,
+    since: Option<SystemTime>,
+    etag: Option<&str>,
+) -> Result<Option<Download>> {
+    let (url, dest) = (url.to_string(), dest.to_path_buf());
+    let etag = etag.map(|etag| etag.to_string());
+    task::spawn_blocking(move || download_part_blocking(&url, &dest, since, etag.as_deref())).await
+}
+
+/// Fetches a small text file, like a checksum list or a signature.
+pub async fn get_text(url: &str) -> Result<String> {
+    let url = url.to_string();
+    task::spawn_blocking(move || {
+        agent()
+            .get(&url)
+            .call()
+            .with_context(|| format!("Downloading {url}"))?
+            .into_string()
+            .with_context(|| format!("Reading {url}"))
+    })
+    .await
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_read(READ_TIMEOUT)
+        .build()
+}
+
+fn download_part_blocking(
+    url: &str,
+    dest: &Path,
+    since: Option<SystemTime>,
+    etag: Option<&str>,
+) -> Result<Option<Download>> {
+    let mut request = agent().get(url);
+    if let Some(since) = since {
+        request = request.set("If-Modified-Since", &httpdate::fmt_http_date(since));
+    }
+    if let Some(etag) = etag {
+        request = request.set("If-None-Match", etag);
+    }
+
+    let response = request
+        .call()
+        .with_context(|| format!("Downloading {url}"))?;
+    if response.status() == 304 {
+        debug!("{} is up to date with {url}", dest.display());
+        return Ok(None);
+    }
+
+    let etag = response.header("ETag").map(|etag| etag.to_string());
+    let part = part_path(dest);
+    let result = (|| -> io::Result<()> {
+        let mut file = fs::File::create(&part)?;
+        io::copy(&mut response.into_reader(), &mut file)?;
+        file.flush()?;
+        file.sync_all()
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&part);
+    }
+    result.with_context(|| format!("Saving {url} to {}", part.display()))?;
+
+    Ok(Some(Download { part, etag }))
+}
@@ -0,0 +1,595 @@
+//! gRPC control API on `grpc_addr`, mirroring the HTTP one of [`crate::api`]
+//! for integrators preferring typed clients, with `WatchEvents` streaming
+//! the events of the clients as they happen rather than polling for them.
+//! The service is described by `proto/control.proto`, its messages written
+//! here rather than generated, for building without `protoc`. It runs on a
+//! Tokio runtime of its own, the rest of the server running on async-std.
+use std::{
+    convert::Infallible,
+    future::Future,
+    marker::PhantomData,
+    net::SocketAddr,
+    sync::Arc,
+    task::{Context, Poll},
+    thread,
+};
+
+use futures::{stream::BoxStream, StreamExt};
+use log::{error, info};
+use tonic::{
+    body::BoxBody,
+    codec::ProstCodec,
+    codegen::{http, BoxFuture, Service},
+    server::{Grpc, NamedService, ServerStreamingService, UnaryService},
+    transport::{server::TcpIncoming, Server},
+    Status,
+};
+
+#[cfg(target_os = "linux")]
+use crate::activation;
+use crate::{
+    conf::Conf,
+    control::Control,
+    dashboard, dhcp,
+    events::{self, Event},
+    logging,
+    template::parse_mac,
+    Result,
+};
+
+const SERVICE_NAME: &str = "preboot_oxide.v1.Control";
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListSessionsRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Session {
+    #[prost(string, optional, tag = "1")]
+    pub tenant: Option<String>,
+    #[prost(uint32, tag = "2")]
+    pub xid: u32,
+    #[prost(string, tag = "3")]
+    pub mac_address: String,
+    #[prost(string, tag = "4")]
+    pub stage: String,
+    #[prost(uint64, tag = "5")]
+    pub age_secs: u64,
+    #[prost(string, optional, tag = "6")]
+    pub rule: Option<String>,
+    #[prost(string, tag = "7")]
+    pub iface: String,
+    #[prost(string, optional, tag = "8")]
+    pub client_ip: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListSessionsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub sessions: Vec<Session>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExpireSessionRequest {
+    #[prost(uint32, tag = "1")]
+    pub xid: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExpireSessionResponse {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListClientsRequest {
+    #[prost(string, optional, tag = "1")]
+    pub mac_address: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Client {
+    #[prost(string, tag = "1")]
+    pub ip: String,
+    #[prost(string, tag = "2")]
+    pub mac_address: String,
+    #[prost(uint32, tag = "3")]
+    pub xid: u32,
+    #[prost(string, tag = "4")]
+    pub boot_file: String,
+    #[prost(bool, tag = "5")]
+    pub boot_file_delivered: bool,
+    #[prost(uint64, tag = "6")]
+    pub age_secs: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListClientsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub clients: Vec<Client>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListAssetsRequest {
+    #[prost(string, optional, tag = "1")]
+    pub mac_address: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Asset {
+    #[prost(string, tag = "1")]
+    pub mac_address: String,
+    #[prost(string, optional, tag = "2")]
+    pub uuid: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub arch: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub vendor: Option<String>,
+    #[prost(uint64, tag = "5")]
+    pub first_seen: u64,
+    #[prost(uint64, tag = "6")]
+    pub last_seen: u64,
+    #[prost(string, optional, tag = "7")]
+    pub last_boot_file: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListAssetsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub assets: Vec<Asset>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReloadRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReloadResponse {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetDryRunRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DryRun {
+    #[prost(bool, tag = "1")]
+    pub dry_run: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetStatsRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RuleCount {
+    #[prost(string, optional, tag = "1")]
+    pub tenant: Option<String>,
+    #[prost(string, tag = "2")]
+    pub rule: String,
+    #[prost(uint64, tag = "3")]
+    pub sessions: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct IfaceCount {
+    #[prost(string, tag = "1")]
+    pub iface: String,
+    #[prost(uint64, tag = "2")]
+    pub received: u64,
+    #[prost(uint64, tag = "3")]
+    pub ignored: u64,
+    #[prost(uint64, tag = "4")]
+    pub answered: u64,
+    #[prost(uint64, tag = "5")]
+    pub transfers: u64,
+    #[prost(uint64, tag = "6")]
+    pub unoffered: u64,
+    #[prost(bool, tag = "7")]
+    pub upstream_missing: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Stats {
+    #[prost(string, tag = "1")]
+    pub version: String,
+    #[prost(uint64, tag = "2")]
+    pub uptime_secs: u64,
+    #[prost(uint64, tag = "3")]
+    pub offers: u64,
+    #[prost(uint64, tag = "4")]
+    pub acks: u64,
+    #[prost(uint64, tag = "5")]
+    pub transfers: u64,
+    #[prost(uint64, tag = "6")]
+    pub sessions: u64,
+    #[prost(uint64, tag = "7")]
+    pub active_transfers: u64,
+    #[prost(message, repeated, tag = "8")]
+    pub rules: Vec<RuleCount>,
+    #[prost(bool, tag = "9")]
+    pub dry_run: bool,
+    #[prost(message, repeated, tag = "10")]
+    pub ifaces: Vec<IfaceCount>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WatchEventsRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ClientEvent {
+    #[prost(string, tag = "1")]
+    pub event: String,
+    #[prost(string, tag = "2")]
+    pub mac_address: String,
+    #[prost(uint32, tag = "3")]
+    pub xid: u32,
+    #[prost(string, tag = "4")]
+    pub session: String,
+    #[prost(string, optional, tag = "5")]
+    pub ip: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    pub boot_file: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    pub iface: Option<String>,
+    #[prost(string, optional, tag = "8")]
+    pub stage: Option<String>,
+    #[prost(string, optional, tag = "9")]
+    pub rule: Option<String>,
+}
+
+impl ClientEvent {
+    pub fn new(event: Event, client: events::ClientEvent) -> Self {
+        let session = parse_mac(&client.mac_address)
+            .map(|mac| logging::session_id(&mac, client.xid))
+            .unwrap_or_default();
+        Self {
+            event: event.name().to_string(),
+            session,
+            mac_address: client.mac_address,
+            xid: client.xid,
+            ip: client.ip.map(|ip| ip.to_string()),
+            boot_file: client.boot_file,
+            iface: client.iface,
+            stage: client.stage,
+            rule: client.rule,
+        }
+    }
+}
+
+/// Runs the gRPC service on `grpc_addr` in a Tokio runtime of its own
+/// thread, the rest of the server being on async-std. Needs `api_token`;
+/// an address that can't be bound is logged and skipped.
+pub fn spawn_grpc_async(conf: &Conf, control: Arc<Control>) -> Result<()> {
+    let (Some(addr), Some(token)) = (conf.get_grpc_addr(), conf.get_api_token()) else {
+        return Ok(());
+    };
+    let listener = match bind(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("gRPC API on {addr} failed: {e:#}");
+            return Ok(());
+        }
+    };
+    let service = ControlService {
+        control,
+        token: Arc::new(token.clone()),
+    };
+
+    thread::Builder::new()
+        .name("grpc".to_string())
+        .spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(2)
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(e) => return error!("gRPC API on {addr} failed: {e}"),
+            };
+            runtime.block_on(async move {
+                let incoming = tokio::net::TcpListener::from_std(listener)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|listener| {
+                        TcpIncoming::from_listener(listener, true, None).map_err(|e| anyhow!(e))
+                    });
+                let served = match incoming {
+                    Ok(incoming) => Server::builder()
+                        .add_service(service)
+                        .serve_with_incoming(incoming)
+                        .await
+                        .map_err(anyhow::Error::from),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = served {
+                    error!("gRPC API on {addr} failed: {e:#}");
+                }
+            });
+        })?;
+    info!("gRPC API listening on {addr}");
+
+    Ok(())
+}
+
+/// Binds `addr`, taking the listener passed for it when there's one.
+fn bind(addr: SocketAddr) -> Result<std::net::TcpListener> {
+    #[cfg(target_os = "linux")]
+    let activated = activation::tcp_listener(addr)?;
+    #[cfg(not(target_os = "linux"))]
+    let activated: Option<std::net::TcpListener> = None;
+    let listener = match activated {
+        Some(listener) => listener,
+        None => std::net::TcpListener::bind(addr)?,
+    };
+    listener.set_nonblocking(true)?;
+
+    Ok(listener)
+}
+
+/// The `Control` service, routing the calls by their path as the code
+/// generated by `tonic-build` does.
+#[derive(Clone)]
+struct ControlService {
+    control: Arc<Control>,
+    token: Arc<String>,
+}
+
+impl NamedService for ControlService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl Service<http::Request<BoxBody>> for ControlService {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let authorized = request
+            .headers()
+            .get("authorization")
+            .and_then(|authorization| authorization.to_str().ok())
+            .and_then(|authorization| authorization.strip_prefix("Bearer "))
+            .is_some_and(|given| crate::http::secret_eq(given.trim(), &self.token));
+        if !authorized {
+            let status = Status::unauthenticated("Missing or invalid api_token");
+            return Box::pin(async move { Ok(status.into_http()) });
+        }
+
+        let control = Arc::clone(&self.control);
+        let method = request
+            .uri()
+            .path()
+            .rsplit_once('/')
+            .map(|(_, method)| method.to_string());
+        Box::pin(async move {
+            let response = match method.as_deref().unwrap_or_default() {
+                "ListSessions" => unary(request, list_sessions, control).await,
+                "ExpireSession" => unary(request, expire_session, control).await,
+                "ListClients" => unary(request, list_clients, control).await,
+                "ListAssets" => unary(request, list_assets, control).await,
+                "Reload" => unary(request, reload, control).await,
+                "GetDryRun" => unary(request, get_dry_run, control).await,
+                "SetDryRun" => unary(request, set_dry_run, control).await,
+                "GetStats" => unary(request, get_stats, control).await,
+                "WatchEvents" => {
+                    let mut grpc = Grpc::new(ProstCodec::default());
+                    grpc.server_streaming(WatchEvents, request).await
+                }
+                method => Status::unimplemented(format!("Unknown method {method}")).into_http(),
+            };
+            Ok(response)
+        })
+    }
+}
+
+/// Answers `request` with `handler`.
+async fn unary<Req, Resp, Fut>(
+    request: http::Request<BoxBody>,
+    handler: fn(Arc<Control>, Req) -> Fut,
+    control: Arc<Control>,
+) -> http::Response<BoxBody>
+where
+    Req: prost::Message + Default + Send + 'static,
+    Resp: prost::Message + Send + 'static,
+    Fut: Future<Output = std::result::Result<Resp, Status>> + Send + 'static,
+{
+    let mut grpc = Grpc::new(ProstCodec::<Resp, Req>::default());
+    grpc.unary(
+        Handler {
+            handler,
+            control,
+            response: PhantomData,
+        },
+        request,
+    )
+    .await
+}
+
+struct Handler<Req, Resp, Fut> {
+    handler: fn(Arc<Control>, Req) -> Fut,
+    control: Arc<Control>,
+    response: PhantomData<fn() -> Resp>,
+}
+
+impl<Req, Resp, Fut> UnaryService<Req> for Handler<Req, Resp, Fut>
+where
+    Fut: Future<Output = std::result::Result<Resp, Status>> + Send + 'static,
+{
+    type Response = Resp;
+    type Future = BoxFuture<tonic::Response<Resp>, Status>;
+
+    fn call(&mut self, request: tonic::Request<Req>) -> Self::Future {
+        let answer = (self.handler)(Arc::clone(&self.control), request.into_inner());
+        Box::pin(async move { answer.await.map(tonic::Response::new) })
+    }
+}
+
+struct WatchEvents;
+
+impl ServerStreamingService<WatchEventsRequest> for WatchEvents {
+    type Response = ClientEvent;
+    type ResponseStream = BoxStream<'static, std::result::Result<ClientEvent, Status>>;
+    type Future = BoxFuture<tonic::Response<Self::ResponseStream>, Status>;
+
+    // `Status` is the error of the gRPC services, large as it is
+    #[allow(clippy::result_large_err)]
+    fn call(&mut self, _request: tonic::Request<WatchEventsRequest>) -> Self::Future {
+        let events = events::watch()
+            .map(|(event, client)| Ok(ClientEvent::new(event, client)))
+            .boxed();
+        Box::pin(async move { Ok(tonic::Response::new(events)) })
+    }
+}
+
+async fn list_sessions(
+    control: Arc<Control>,
+    _request: ListSessionsRequest,
+) -> std::result::Result<ListSessionsResponse, Status> {
+    let sessions = control.sessions().await.into_iter().map(|session| Session {
+        tenant: session.tenant,
+        xid: session.xid,
+        mac_address: session.mac_address,
+        stage: session.stage,
+        age_secs: session.age_secs,
+        rule: session.rule,
+        iface: session.iface,
+        client_ip: session.client_ip.map(|ip| ip.to_string()),
+    });
+
+    Ok(ListSessionsResponse {
+        sessions: sessions.collect(),
+    })
+}
+
+async fn expire_session(
+    control: Arc<Control>,
+    request: ExpireSessionRequest,
+) -> std::result::Result<ExpireSessionResponse, Status> {
+    let xid = request.xid;
+    match control.expire_session(xid).await {
+        true => {
+            info!("Session for XID: {xid} expired by the gRPC API.");
+            Ok(ExpireSessionResponse {})
+        }
+        false => Err(Status::not_found(format!("No session for XID {xid:#010x}"))),
+    }
+}
+
+async fn list_clients(
+    control: Arc<Control>,
+    request: ListClientsRequest,
+) -> std::result::Result<ListClientsResponse, Status> {
+    let clients = dashboard::recent_boots(&control.tracker)
+        .into_iter()
+        .filter(|client| {
+            request
+                .mac_address
+                .as_ref()
+                .is_none_or(|mac| client.mac_address.eq_ignore_ascii_case(mac))
+        })
+        .map(|client| Client {
+            ip: client.ip.to_string(),
+            mac_address: client.mac_address,
+            xid: client.xid,
+            boot_file: client.boot_file,
+            boot_file_delivered: client.boot_file_delivered,
+            age_secs: client.age_secs,
+        });
+
+    Ok(ListClientsResponse {
+        clients: clients.collect(),
+    })
+}
+
+async fn list_assets(
+    control: Arc<Control>,
+    request: ListAssetsRequest,
+) -> std::result::Result<ListAssetsResponse, Status> {
+    let assets = control
+        .tracker
+        .assets()
+        .list()
+        .into_iter()
+        .filter(|asset| {
+            request
+                .mac_address
+                .as_ref()
+                .is_none_or(|mac| asset.mac_address.eq_ignore_ascii_case(mac))
+        })
+        .map(|asset| Asset {
+            mac_address: asset.mac_address,
+            uuid: asset.uuid,
+            arch: asset.arch,
+            vendor: asset.vendor,
+            first_seen: asset.first_seen,
+            last_seen: asset.last_seen,
+            last_boot_file: asset.last_boot_file,
+        });
+
+    Ok(ListAssetsResponse {
+        assets: assets.collect(),
+    })
+}
+
+async fn reload(
+    control: Arc<Control>,
+    _request: ReloadRequest,
+) -> std::result::Result<ReloadResponse, Status> {
+    match control.reloader.reload().await {
+        Ok(()) => Ok(ReloadResponse {}),
+        Err(e) => Err(Status::invalid_argument(format!("{e:#}"))),
+    }
+}
+
+async fn get_dry_run(
+    _control: Arc<Control>,
+    _request: GetDryRunRequest,
+) -> std::result::Result<DryRun, Status> {
+    Ok(DryRun {
+        dry_run: dhcp::is_dry_run(),
+    })
+}
+
+async fn set_dry_run(
+    _control: Arc<Control>,
+    request: DryRun,
+) -> std::result::Result<DryRun, Status> {
+    if request.dry_run != dhcp::is_dry_run() {
+        match request.dry_run {
+            true => info!("DHCP dry run started by the gRPC API, the answers are only logged."),
+            false => info!("DHCP dry run stopped by the gRPC API."),
+        }
+    }
+    dhcp::set_dry_run(request.dry_run);
+    Ok(request)
+}
+
+async fn get_stats(
+    control: Arc<Control>,
+    _request: GetStatsRequest,
+) -> std::result::Result<Stats, Status> {
+    let state = dashboard::state(&control).await;
+    let rules = state.rules.into_iter().map(|rule| RuleCount {
+        tenant: rule.tenant,
+        rule: rule.rule,
+        sessions: rule.sessions,
+    });
+    let ifaces = state.ifaces.into_iter().map(|iface| IfaceCount {
+        iface: iface.iface,
+        received: iface.received,
+        ignored: iface.ignored,
+        answered: iface.answered,
+        transfers: iface.transfers,
+        unoffered: iface.unoffered,
+        upstream_missing: iface.upstream_missing,
+    });
+
+    Ok(Stats {
+        version: state.version,
+        uptime_secs: state.uptime_secs,
+        offers: state.counts.offers,
+        acks: state.counts.acks,
+        transfers: state.counts.transfers,
+        sessions: state.sessions.len() as u64,
+        active_transfers: state.transfers.len() as u64,
+        rules: rules.collect(),
+        dry_run: dhcp::is_dry_run(),
+        ifaces: ifaces.collect(),
+    })
+}
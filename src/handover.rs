@@ -0,0 +1,389 @@
+//! Zero-downtime restarts: a server started with `--takeover` replaces the
+//! running one, for upgrades in the middle of an imaging campaign. It asks
+//! the running server over its control socket for the descriptors of its
+//! DHCP, TFTP, DNS and HTTP sockets, so the packets keep being received
+//! rather than dropped for want of a listener, with the DHCP sessions in
+//! progress and the clients tracked. The server taken over then stops
+//! answering DHCP, releases the instance lock and drains: the transfers it
+//! has in progress run to their end, up to `DRAIN_TIMEOUT`, before it exits.
+//! Linux only, the sockets being used as those of socket activation.
+use std::{
+    net::Ipv4Addr,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use async_std::{
+    channel::{self, Receiver, Sender},
+    task,
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analytics::AnalyticsState,
+    assets::Asset,
+    conf::Conf,
+    control::Control,
+    dhcp::{Session, SessionMap, PXE_BOOT_SERVER_PORT},
+    stats::StatsState,
+    tracker::{BootTracker, TrackedClient},
+    upstream::DEFAULT_TFTP_PORT,
+    Result,
+};
+
+/// Time the server taken over waits for its transfers before exiting.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Time the instance lock is waited for, the server taken over releasing it
+/// once it has answered.
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Time the running server is waited for to answer.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tells the serving loop the server was taken over.
+#[derive(Clone)]
+pub struct Handover {
+    done: (Sender<()>, Receiver<()>),
+}
+
+impl Default for Handover {
+    fn default() -> Self {
+        Self {
+            done: channel::bounded(1),
+        }
+    }
+}
+
+impl Handover {
+    /// Records that the sockets were handed over.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    fn handed_over(&self) {
+        let _ = self.done.0.try_send(());
+    }
+
+    /// Waits for the server to be taken over.
+    pub async fn requested(&self) {
+        let _ = self.done.1.recv().await;
+    }
+}
+
+/// The DHCP sessions of the top level configuration or of a tenant, by XID.
+type TenantSessions = (Option<String>, Vec<(u32, Session)>);
+
+/// What the server taking over carries on with.
+#[derive(Default, Serialize, Deserialize)]
+pub struct State {
+    /// The DHCP sessions in progress, by tenant.
+    sessions: Vec<TenantSessions>,
+    /// The clients of the boot tracker, by IP address.
+    clients: Vec<(Ipv4Addr, TrackedClient)>,
+    /// The boot statistics, from then on saved by the server taking over.
+    #[serde(default)]
+    stats: Option<StatsState>,
+    /// The clients seen, from then on saved by the server taking over.
+    #[serde(default)]
+    assets: Vec<Asset>,
+    /// The transfer analytics.
+    #[serde(default)]
+    analytics: Option<AnalyticsState>,
+}
+
+impl State {
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    async fn of(control: &Control) -> Self {
+        let mut sessions = Vec::new();
+        for service in &control.services {
+            if let Some(service_sessions) = &service.sessions {
+                sessions.push((service.tenant.clone(), service_sessions.handed_over()));
+            }
+        }
+
+        Self {
+            sessions,
+            clients: control.tracker.clients(),
+            stats: Some(control.tracker.stats().hand_over()),
+            assets: control.tracker.assets().hand_over(),
+            analytics: Some(control.tracker.analytics().snapshot()),
+        }
+    }
+
+    /// Carries on the sessions of `tenant`, the top level configuration
+    /// without, in `sessions`.
+    pub fn inherit_sessions(&mut self, tenant: Option<&str>, sessions: &SessionMap) {
+        let inherited = self
+            .sessions
+            .iter_mut()
+            .filter(|(of, _)| of.as_deref() == tenant)
+            .flat_map(|(_, sessions)| std::mem::take(sessions))
+            .collect();
+        sessions.inherit(inherited);
+    }
+
+    /// Tracks the clients of the server taken over with `tracker`, carrying
+    /// on its boot statistics, assets and transfer analytics.
+    pub fn inherit_clients(&mut self, tracker: &BootTracker) {
+        tracker.inherit(std::mem::take(&mut self.clients));
+        if let Some(stats) = self.stats.take() {
+            tracker.stats().inherit(stats);
+        }
+        tracker.assets().inherit(std::mem::take(&mut self.assets));
+        if let Some(analytics) = self.analytics.take() {
+            tracker.analytics().inherit(analytics);
+        }
+    }
+
+    /// What's carried on, for the logs.
+    pub fn summary(&self) -> String {
+        let sessions: usize = self.sessions.iter().map(|(_, sessions)| sessions.len()).sum();
+        format!("{sessions} DHCP sessions and {} tracked clients", self.clients.len())
+    }
+}
+
+/// The UDP ports and the TCP ports of the services of `confs`, DHCP, TFTP,
+/// DNS and HTTP.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn ports<'a>(confs: impl IntoIterator<Item = &'a Conf>) -> (Vec<u16>, Vec<u16>) {
+    // The DHCP server and client ports, of the proxy and of its messages
+    let mut udp = vec![67, 68, PXE_BOOT_SERVER_PORT, DEFAULT_TFTP_PORT];
+    let mut tcp = Vec::new();
+    for conf in confs {
+        udp.push(conf.get_dns_port());
+        tcp.extend(conf.get_http_port());
+        tcp.extend(conf.get_https_port());
+        tcp.extend(conf.get_dashboard_addr().map(|addr| addr.port()));
+        tcp.extend(conf.get_api_addr().map(|addr| addr.port()));
+        tcp.extend(conf.get_grpc_addr().map(|addr| addr.port()));
+    }
+    udp.sort_unstable();
+    udp.dedup();
+    tcp.sort_unstable();
+    tcp.dedup();
+
+    (udp, tcp)
+}
+
+/// Waits for the transfers in progress of `tracker` to end, for at most
+/// `timeout`.
+pub async fn drain(tracker: &BootTracker, timeout: Duration) {
+    let start = Instant::now();
+    loop {
+        let active = tracker.active_transfers();
+        if active == 0 {
+            info!("No transfers left in progress, exiting.");
+            return;
+        }
+        if start.elapsed() >= timeout {
+            warn!("{active} transfers still in progress after {timeout:?}, exiting.");
+            return;
+        }
+        task::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+}
+
+/// Hands the sockets and the state of the server over on `stream`, to the
+/// server taking over, the first bytes of the answer carrying the socket
+/// descriptors.
+#[cfg(target_os = "linux")]
+pub(crate) async fn hand_over(stream: &async_std::os::unix::net::UnixStream, control: &Control) -> Result<()> {
+    use std::os::fd::AsRawFd;
+
+    use async_std::io::WriteExt;
+
+    let confs: Vec<_> = control
+        .services
+        .iter()
+        .map(|service| crate::dhcp::current_conf(&service.shared_conf))
+        .collect();
+    let (udp, tcp) = ports(confs.iter().map(AsRef::as_ref));
+    let fds = fds::listening(&udp, &tcp)?;
+    let state = State::of(control).await;
+    let answer = format!("{}\n", serde_json::to_string(&state)?);
+
+    let chunks: Vec<_> = fds.chunks(fds::MAX_FDS).collect();
+    if chunks.len() > answer.len() {
+        bail!("Too many sockets to hand over: {}", fds.len());
+    }
+    for (index, chunk) in chunks.iter().enumerate() {
+        fds::send(stream.as_raw_fd(), answer.as_bytes()[index], chunk)?;
+    }
+    let mut writer = stream;
+    writer.write_all(&answer.as_bytes()[chunks.len()..]).await?;
+    info!(
+        "Handed {} sockets, {}, over to the server taking over.",
+        fds.len(),
+        state.summary()
+    );
+    control.handover.handed_over();
+
+    Ok(())
+}
+
+/// Takes over the server running with the control socket at `path`, its
+/// sockets being used in place of binding them. Returns what's carried on.
+#[cfg(target_os = "linux")]
+pub fn take_over(path: &Path) -> Result<State> {
+    use std::{
+        io::Write,
+        os::{fd::AsRawFd, unix::net::UnixStream},
+    };
+
+    use anyhow::Context;
+
+    let mut stream = UnixStream::connect(path)
+        .with_context(|| format!("Connecting to {}, is preboot-oxide running?", path.display()))?;
+    stream.set_read_timeout(Some(ANSWER_TIMEOUT))?;
+    stream.write_all(b"handover\n")?;
+    let (answer, fds) = fds::receive_line(stream.as_raw_fd()).context("Reading the answer of the server")?;
+    // SAFETY: the descriptors were just received, owned by nothing else
+    unsafe { crate::activation::add_handed_over(&fds) }?;
+
+    let answer: serde_json::Value = serde_json::from_slice(&answer).context("Reading the answer of the server")?;
+    if let Some(error) = answer.get("error").and_then(|error| error.as_str()) {
+        bail!("{error}");
+    }
+    let state: State = serde_json::from_value(answer).context("Reading the state of the server")?;
+    info!("Took over {} sockets, {}.", fds.len(), state.summary());
+
+    Ok(state)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn take_over(_path: &Path) -> Result<State> {
+    bail!("Taking over a running server is only supported on Linux")
+}
+
+/// Passing descriptors over Unix sockets, with `SCM_RIGHTS`.
+#[cfg(target_os = "linux")]
+mod fds {
+    use std::{
+        fs, io, mem,
+        os::fd::{BorrowedFd, RawFd},
+        ptr,
+    };
+
+    use socket2::{SockRef, Type};
+
+    use crate::Result;
+
+    /// Most descriptors passed in a message, `SCM_MAX_FD`.
+    pub const MAX_FDS: usize = 253;
+    const READ_SIZE: usize = 64 * 1024;
+
+    /// The descriptors of the process of the UDP sockets bound to the `udp`
+    /// ports, and of the TCP listeners of the `tcp` ports, IPv4 or IPv6.
+    pub fn listening(udp: &[u16], tcp: &[u16]) -> Result<Vec<RawFd>> {
+        let mut fds = Vec::new();
+        for entry in fs::read_dir("/proc/self/fd")? {
+            let Some(fd) = entry?.file_name().to_str().and_then(|name| name.parse::<RawFd>().ok()) else {
+                continue;
+            };
+            // SAFETY: only used while listed open, the process closing none meanwhile
+            let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+            let socket = SockRef::from(&borrowed);
+            // Not a socket, or the descriptor of the listing itself, closed since
+            let (Ok(kind), Ok(addr)) = (socket.r#type(), socket.local_addr()) else {
+                continue;
+            };
+            let Some(addr) = addr.as_socket() else {
+                continue;
+            };
+            let is_listening = match kind {
+                Type::DGRAM => udp.contains(&addr.port()),
+                // The connections accepted share the port of the listener
+                Type::STREAM => tcp.contains(&addr.port()) && socket.peer_addr().is_err(),
+                _ => false,
+            };
+            if is_listening {
+                fds.push(fd);
+            }
+        }
+
+        Ok(fds)
+    }
+
+    /// Sends `byte` on the Unix socket `socket`, with the descriptors `fds`.
+    pub fn send(socket: RawFd, byte: u8, fds: &[RawFd]) -> io::Result<()> {
+        let mut data = [byte];
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr().cast(),
+            iov_len: data.len(),
+        };
+        let payload = mem::size_of_val(fds) as u32;
+        // SAFETY: computes a size only
+        let space = unsafe { libc::CMSG_SPACE(payload) } as usize;
+        // u64 for the alignment of the headers
+        let mut control = vec![0u64; space.div_ceil(mem::size_of::<u64>())];
+        // SAFETY: a zeroed msghdr is an empty message
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        if !fds.is_empty() {
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = space as _;
+            // SAFETY: the control buffer has room for a header and `fds`
+            unsafe {
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                (*cmsg).cmsg_level = libc::SOL_SOCKET;
+                (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(payload) as _;
+                ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg).cast::<RawFd>(), fds.len());
+            }
+        }
+        // SAFETY: the message points to buffers living through the call
+        match unsafe { libc::sendmsg(socket, &msg, libc::MSG_NOSIGNAL) } {
+            1 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Reads a line from the Unix socket `socket`, with the descriptors
+    /// passed along with it.
+    pub fn receive_line(socket: RawFd) -> Result<(Vec<u8>, Vec<RawFd>)> {
+        let mut line = Vec::new();
+        let mut fds = Vec::new();
+        let mut data = vec![0u8; READ_SIZE];
+        // SAFETY: computes a size only
+        let space = unsafe { libc::CMSG_SPACE((MAX_FDS * mem::size_of::<RawFd>()) as u32) } as usize;
+        let mut control = vec![0u64; space.div_ceil(mem::size_of::<u64>())];
+        while !line.ends_with(b"\n") {
+            let mut iov = libc::iovec {
+                iov_base: data.as_mut_ptr().cast(),
+                iov_len: data.len(),
+            };
+            // SAFETY: a zeroed msghdr is an empty message
+            let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = space as _;
+            // SAFETY: the message points to buffers living through the call
+            let read = unsafe { libc::recvmsg(socket, &mut msg, libc::MSG_CMSG_CLOEXEC) };
+            if read < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            // SAFETY: the headers are those recvmsg filled in
+            unsafe {
+                let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+                while !cmsg.is_null() {
+                    if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                        let len = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / mem::size_of::<RawFd>();
+                        let received = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                        fds.extend((0..len).map(|index| ptr::read_unaligned(received.add(index))));
+                    }
+                    cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+                }
+            }
+            if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+                bail!("Descriptors passed were dropped, the buffer being too small");
+            }
+            if read == 0 {
+                bail!("The server closed the connection before answering");
+            }
+            line.extend_from_slice(&data[..read as usize]);
+        }
+
+        Ok((line, fds))
+    }
+}
@@ -0,0 +1,188 @@
+//! Health checks on `health_addr`, for Kubernetes probes and the monitors
+//! of the hosts to restart a degraded server: `/healthz` tells whether it's
+//! alive, its DHCP loops going around, and
+//! `/readyz` whether it serves, its DHCP sockets being bound and its TFTP
+//! roots readable too. Both answer 200 when the checks pass and 503
+//! otherwise, with the outcome of each check as JSON. They have no
+//! authentication, telling nothing about the clients.
+use std::{sync::Arc, time::Duration};
+
+use log::{error, info};
+use serde::Serialize;
+
+use crate::{
+    conf::Conf,
+    control::Control,
+    dhcp,
+    http::{self, Body, Request, Response},
+    notify::Notifier,
+    Result,
+};
+
+/// Longest the DHCP loops wait for messages with `health_addr`, for their
+/// liveness to be told on quiet networks.
+pub const LIVENESS_INTERVAL: Duration = Duration::from_secs(10);
+/// Time a DHCP loop may not go around for before it's told stalled.
+const STALLED_AFTER: Duration = Duration::from_secs(30);
+
+/// The outcome of a check.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    /// What failed, when it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, errors: Vec<String>) -> Self {
+        Self {
+            name,
+            ok: errors.is_empty(),
+            error: (!errors.is_empty()).then(|| errors.join("; ")),
+        }
+    }
+}
+
+/// The checks of `/healthz` or `/readyz`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Report {
+    pub ok: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    fn new(checks: Vec<Check>) -> Self {
+        Self {
+            ok: checks.iter().all(|check| check.ok),
+            checks,
+        }
+    }
+}
+
+/// Whether the server is alive, its DHCP loops going around.
+pub fn liveness(notifier: &Notifier) -> Report {
+    Report::new(vec![dhcp_loops(notifier)])
+}
+
+/// Whether the server serves, its DHCP sockets being bound and its TFTP
+/// roots readable, on top of being alive.
+pub fn readiness(control: &Control, notifier: &Notifier) -> Report {
+    Report::new(vec![
+        dhcp_sockets(control, notifier),
+        dhcp_loops(notifier),
+        tftp_roots(control),
+    ])
+}
+
+/// The DHCP loops bound their sockets, each service listening on an
+/// interface at least.
+fn dhcp_sockets(control: &Control, notifier: &Notifier) -> Check {
+    let mut errors = Vec::new();
+    let state = notifier.loops_state(STALLED_AFTER);
+    if state.bound < state.loops {
+        errors.push(format!(
+            "{} of {} DHCP services bound their sockets",
+            state.bound, state.loops
+        ));
+    }
+    for service in &control.services {
+        let Some(sessions) = &service.sessions else {
+            continue;
+        };
+        if sessions.ifaces().is_empty() {
+            errors.push(format!(
+                "DHCP{} listens on no interface",
+                of(&service.tenant)
+            ));
+        }
+    }
+    Check::new("dhcp_sockets", errors)
+}
+
+/// The DHCP loops went around lately.
+fn dhcp_loops(notifier: &Notifier) -> Check {
+    let state = notifier.loops_state(STALLED_AFTER);
+    let errors = match state.stalled {
+        0 => Vec::new(),
+        stalled => vec![format!(
+            "{stalled} DHCP loops didn't go around for over {STALLED_AFTER:?}"
+        )],
+    };
+    Check::new("dhcp_loops", errors)
+}
+
+/// The TFTP roots served can be listed.
+fn tftp_roots(control: &Control) -> Check {
+    let mut errors = Vec::new();
+    for service in &control.services {
+        let conf = dhcp::current_conf(&service.shared_conf);
+        let Some(dir) = conf
+            .get_tftp_serve_path()
+            .filter(|_| conf.get_tftp_enabled())
+        else {
+            continue;
+        };
+        if let Err(e) = std::fs::read_dir(&dir) {
+            errors.push(format!("{dir}{}: {e}", of(&service.tenant)));
+        }
+    }
+    Check::new("tftp_roots", errors)
+}
+
+/// ` of tenant <name>` for a tenant, nothing for the top level.
+fn of(tenant: &Option<String>) -> String {
+    tenant
+        .as_ref()
+        .map(|tenant| format!(" of tenant {tenant}"))
+        .unwrap_or_default()
+}
+
+/// Listens on the `health_addr` of `conf`, when set, checking `control` and
+/// the DHCP loops of `notifier`. The server runs without it when the
+/// address can't be bound.
+pub fn spawn_health_async(
+    conf: &Conf,
+    control: Arc<Control>,
+    notifier: Arc<Notifier>,
+) -> Result<()> {
+    let Some(addr) = conf.get_health_addr() else {
+        return Ok(());
+    };
+    let listener = match http::bind_listener(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Health checks on {addr} failed: {e:#}");
+            return Ok(());
+        }
+    };
+    info!("Health checks listening on http://{addr}/healthz and /readyz");
+
+    http::serve(listener, "health check", 0, move |request, _| {
+        let (control, notifier) = (Arc::clone(&control), Arc::clone(&notifier));
+        async move { respond(&request, &control, &notifier).await }
+    });
+
+    Ok(())
+}
+
+async fn respond(request: &Request, control: &Control, notifier: &Notifier) -> Response {
+    if !matches!(request.method.as_str(), "GET" | "HEAD") {
+        return Response::text(405, "Method not allowed").header("Allow", "GET, HEAD");
+    }
+    let report = match request.path.as_str() {
+        "/healthz" => liveness(notifier),
+        "/readyz" => readiness(control, notifier),
+        _ => return Response::text(404, "Not found"),
+    };
+    let status = match report.ok {
+        true => 200,
+        false => 503,
+    };
+
+    Response::new(status)
+        .header("Content-Type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(Body::Bytes(serde_json::to_vec(&report).unwrap_or_default()))
+}
@@ -0,0 +1,157 @@
+//! Boot hook: a Rhai script deciding the configuration of DHCP clients where
+//! the `match` rules aren't expressive enough, e.g. canarying by a hash of
+//! the MAC address or looking hosts up in local files. It's run in the
+//! server for each message with the decoded DHCP message as `client`, and
+//! evaluates to a map of the fields of a `default` section, or to `()` to
+//! leave the client to the webhook and `match`.
+//!
+//! The script is compiled once, again when its file changes, and each run is
+//! limited to [`MAX_OPERATIONS`], so a script looping forever fails rather
+//! than holding up the message. [`MAX_RUNNING`] runs happen at once at most,
+//! for a flood of messages not to take up the blocking threads.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use async_lock::Semaphore;
+use async_std::{future, task};
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
+use rhai::{module_resolvers::DummyModuleResolver, Dynamic, Engine, Scope, AST};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    conf::{Conf, ConfEntry},
+    util::bytes_to_mac_address,
+    Result,
+};
+
+/// Operations a run of the script may take before it's stopped, far more
+/// than a decision takes.
+pub const MAX_OPERATIONS: u64 = 1_000_000;
+/// Length of the strings, arrays and maps a script may build.
+const MAX_SIZE: usize = 1 << 20;
+/// Runs of the script at once, those of the other messages waiting their
+/// turn for [`WAIT_TIMEOUT`].
+pub const MAX_RUNNING: usize = 16;
+/// Time a run may wait for its turn before the client is left to the
+/// webhook and `match`, short enough for it not to give up on the offer.
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+static RUNNING: Semaphore = Semaphore::new(MAX_RUNNING);
+
+static ENGINE: Lazy<Engine> = Lazy::new(|| {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(MAX_SIZE)
+        .set_max_array_size(MAX_SIZE)
+        .set_max_map_size(MAX_SIZE)
+        .set_module_resolver(DummyModuleResolver::new())
+        .on_print(|text| info!("Boot hook: {text}"))
+        .on_debug(|text, _, position| debug!("Boot hook at {position}: {text}"));
+    engine.disable_symbol("eval");
+    engine.register_fn("sha256", |text: &str| {
+        Sha256::digest(text.as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>()
+    });
+    engine.register_fn("read_file", |path: &str| -> Dynamic {
+        std::fs::read_to_string(path).map_or(Dynamic::UNIT, Dynamic::from)
+    });
+    engine
+});
+
+/// A script compiled, with the time its file was modified.
+type Compiled = (SystemTime, Arc<AST>);
+
+/// The scripts compiled, by path.
+static SCRIPTS: Lazy<Mutex<HashMap<PathBuf, Compiled>>> = Lazy::new(Default::default);
+
+/// Configuration of the client of `doc` decided by the hook, `None` when
+/// none is configured, it has no answer for the client or it failed.
+pub async fn client_conf(conf: &Conf, doc: &serde_json::Value) -> Option<ConfEntry> {
+    let hook = conf.get_boot_hook()?;
+    match run(hook, doc).await {
+        Ok(entry) => entry,
+        Err(e) => {
+            warn!("Boot hook {} failed, ignoring it: {e:#}", hook.display());
+            None
+        }
+    }
+}
+
+/// Runs the script `hook` with `doc` as `client`, returning the
+/// configuration it evaluated to, `None` for `()`. The MAC address of the
+/// client is given as `mac` as well, `52:54:00:AB:CD:EF`, when known.
+/// Fails when no run ends within [`WAIT_TIMEOUT`] for it to take its turn.
+pub async fn run(hook: &Path, doc: &serde_json::Value) -> Result<Option<ConfEntry>> {
+    let ast = compiled(hook)?;
+    let _running = future::timeout(WAIT_TIMEOUT, RUNNING.acquire())
+        .await
+        .map_err(|_| anyhow!("Timed out after {WAIT_TIMEOUT:?}, {MAX_RUNNING} runs at once"))?;
+    let client = rhai::serde::to_dynamic(doc).context("Passing the client to the boot hook")?;
+    let mac = mac_address(doc).map_or(Dynamic::UNIT, Dynamic::from);
+    let value = task::spawn_blocking(move || {
+        let mut scope = Scope::new();
+        scope.push_constant_dynamic("client", client);
+        scope.push_constant_dynamic("mac", mac);
+        ENGINE
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &ast)
+            .map_err(|e| anyhow!("{e}"))
+    })
+    .await?;
+    if value.is_unit() {
+        debug!("Boot hook has no configuration for the client.");
+        return Ok(None);
+    }
+
+    let value: serde_json::Value =
+        rhai::serde::from_dynamic(&value).context("Reading the answer of the boot hook")?;
+    ConfEntry::from_json(value).map(Some)
+}
+
+/// Compiles the script `hook`, for the configuration to tell its errors
+/// when loaded.
+pub fn compile(hook: &Path) -> Result<()> {
+    compiled(hook).map(|_| ())
+}
+
+/// The script `hook` compiled, again when its file was modified since. The
+/// cache is only locked to look it up and to store it, not to compile it.
+fn compiled(hook: &Path) -> Result<Arc<AST>> {
+    let modified = std::fs::metadata(hook)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Reading {}", hook.display()))?;
+    let scripts = || SCRIPTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some((compiled_at, ast)) = scripts().get(hook) {
+        if *compiled_at == modified {
+            return Ok(Arc::clone(ast));
+        }
+    }
+
+    let ast = ENGINE
+        .compile_file(hook.to_path_buf())
+        .map_err(|e| anyhow!("Compiling {}: {e}", hook.display()))?;
+    let ast = Arc::new(ast);
+    scripts().insert(hook.to_path_buf(), (modified, Arc::clone(&ast)));
+    Ok(ast)
+}
+
+/// The MAC address of the client of `doc`, from its `chaddr`.
+fn mac_address(doc: &serde_json::Value) -> Option<String> {
+    let chaddr = doc.get("chaddr")?.as_array()?;
+    let mac = chaddr
+        .iter()
+        .take(6)
+        .map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+        .collect::<Option<Vec<u8>>>()?;
+    (mac.len() == 6).then(|| bytes_to_mac_address(&mac))
+}
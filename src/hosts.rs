@@ -0,0 +1,127 @@
+//! Host lists of `match` entries: CSV or TSV files of `mac,boot_file,profile`
+//! rows, e.g. exported from a spreadsheet, for sites with more hosts than
+//! can be listed in the configuration. They're reloaded with it, and when
+//! they change.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{
+    conf::{ConfEntry, MacAddress},
+    template::parse_mac,
+    util::bytes_to_mac_address,
+    Result,
+};
+
+/// Columns of the rows, in their order when the file has no header.
+const COLUMNS: [&str; 3] = ["mac", "boot_file", "profile"];
+
+/// The hosts of a file, by MAC address, with their configuration.
+#[derive(Clone, Debug)]
+pub struct HostList {
+    pub path: PathBuf,
+    hosts: HashMap<MacAddress, ConfEntry>,
+}
+
+impl HostList {
+    /// Reads the hosts of the file at `path`, tab separated when its name
+    /// ends with `.tsv`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading the hosts file {}", path.display()))?;
+        let is_tsv = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("tsv"));
+        let hosts = parse(&content, if is_tsv { '\t' } else { ',' })
+            .with_context(|| format!("Reading the hosts file {}", path.display()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            hosts,
+        })
+    }
+
+    /// Configuration of the client with the MAC address `mac`, `None` when
+    /// it's not listed.
+    pub fn get(&self, mac: &MacAddress) -> Option<&ConfEntry> {
+        self.hosts.get(mac)
+    }
+
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &ConfEntry> {
+        self.hosts.values()
+    }
+
+    pub fn entries_mut(&mut self) -> impl Iterator<Item = &mut ConfEntry> {
+        self.hosts.values_mut()
+    }
+}
+
+/// Hosts of the rows of `content`, their fields separated by `delimiter`.
+/// A first row naming the columns, `mac` among them, tells their order,
+/// the other columns being ignored. Empty lines and those starting with `#`
+/// are skipped.
+pub fn parse(content: &str, delimiter: char) -> Result<HashMap<MacAddress, ConfEntry>> {
+    let mut rows = content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| (number, line.split(delimiter).map(field).collect::<Vec<&str>>()))
+        .peekable();
+
+    let is_header = rows
+        .peek()
+        .is_some_and(|(_, fields)| fields.iter().any(|name| name.eq_ignore_ascii_case("mac")));
+    let columns: Vec<Option<usize>> = match is_header {
+        true => {
+            let (_, names) = rows.next().unwrap_or_default();
+            COLUMNS
+                .iter()
+                .map(|column| names.iter().position(|name| name.eq_ignore_ascii_case(column)))
+                .collect()
+        }
+        false => (0..COLUMNS.len()).map(Some).collect(),
+    };
+
+    let mut hosts = HashMap::new();
+    for (number, fields) in rows {
+        let value = |column: usize| {
+            columns[column]
+                .and_then(|index| fields.get(index).copied())
+                .filter(|value| !value.is_empty())
+        };
+        let mac = value(0).ok_or(anyhow!("No MAC address on line {number}"))?;
+        let mac = parse_mac(mac).ok_or(anyhow!("Invalid MAC address {mac} on line {number}"))?;
+        let entry = ConfEntry {
+            boot_file: value(1).map(str::to_string),
+            profile: value(2).map(str::to_string),
+            ..Default::default()
+        };
+        if entry.boot_file.is_none() && entry.profile.is_none() {
+            bail!("No boot_file or profile on line {number}");
+        }
+        if hosts.insert(mac, entry).is_some() {
+            bail!("Host {} listed again on line {number}", bytes_to_mac_address(&mac));
+        }
+    }
+
+    Ok(hosts)
+}
+
+/// `value` trimmed, without the quotes of spreadsheet exports.
+fn field(value: &str) -> &str {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
@@ -0,0 +1,1457 @@
+//! HTTP server for the files of the TFTP root, for the clients able to fetch
+//! over HTTP (iPXE, UEFI HTTP Boot, ...) at far higher speeds than TFTP.
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use async_std::{
+    io::{self, BufReader},
+    net::{TcpListener, TcpStream},
+    task,
+};
+use async_tftp::packet;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use futures::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use futures_rustls::TlsAcceptor;
+use log::{debug, error, info, trace, warn};
+use sha2::{Digest, Sha256};
+
+#[cfg(target_os = "linux")]
+use crate::activation;
+use crate::{
+    conf::{Conf, ConfEntryRef, MacAddress},
+    distro::{self, BootEntry},
+    logging,
+    mirror,
+    signing::{Detached, FileDigests, ImageSigner},
+    template,
+    tftp::{DirHandler, SharedDirHandler},
+    tls,
+    readahead::READ_AHEAD_SIZE,
+    util::{bytes_to_mac_address, listen_ips, part_path, to_hex},
+    Result,
+};
+
+/// Upper limit for the request line and headers of a request.
+const REQUEST_HEAD_LIMIT: u64 = 16 * 1024;
+/// Keep-alive connections without a new request for this long are closed.
+pub(crate) const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn spawn_http_service_async(conf: &Conf, handler: SharedDirHandler) -> Result<HttpService> {
+    let mut service = HttpService::new(handler);
+    task::block_on(service.reload(conf))?;
+
+    Ok(service)
+}
+
+/// The HTTP and HTTPS servers listening on each configured interface
+/// address, serving the files of the TFTP root with the same rules.
+pub struct HttpService {
+    handler: SharedDirHandler,
+    conf: SharedConf,
+    digests: SharedDigests,
+    settings: Option<ListenSettings>,
+    tls: Option<TlsAcceptor>,
+    listeners: HashMap<SocketAddr, task::JoinHandle<()>>,
+}
+
+/// The configuration clients are matched against, following reloads.
+type SharedConf = Arc<RwLock<Option<Arc<Conf>>>>;
+
+/// Checksums and signatures of the files, replaced with the signing
+/// certificate on reloads.
+type SharedDigests = Arc<RwLock<Arc<FileDigests>>>;
+
+/// What the connections of a server are served from.
+#[derive(Clone)]
+struct Site {
+    handler: SharedDirHandler,
+    conf: SharedConf,
+    digests: SharedDigests,
+    /// Address and scheme of the server, for the links of iPXE scripts.
+    addr: SocketAddr,
+    scheme: &'static str,
+}
+
+impl Site {
+    fn conf(&self) -> Option<Arc<Conf>> {
+        self.conf.read().ok().and_then(|conf| conf.clone())
+    }
+
+    fn digests(&self) -> Option<Arc<FileDigests>> {
+        self.digests.read().ok().map(|digests| Arc::clone(&digests))
+    }
+}
+
+/// Settings the servers are restarted for when they change.
+#[derive(Clone, PartialEq)]
+struct ListenSettings {
+    http_port: Option<u16>,
+    https_port: Option<u16>,
+    https_cert: Option<PathBuf>,
+    https_key: Option<PathBuf>,
+    https_client_ca: Option<PathBuf>,
+}
+
+impl ListenSettings {
+    fn from_conf(conf: &Conf) -> Self {
+        Self {
+            http_port: conf.get_http_port(),
+            https_port: conf.get_https_port(),
+            https_cert: conf.get_https_cert().cloned(),
+            https_key: conf.get_https_key().cloned(),
+            https_client_ca: conf.get_https_client_ca().cloned(),
+        }
+    }
+}
+
+impl HttpService {
+    pub fn new(handler: SharedDirHandler) -> Self {
+        Self {
+            handler,
+            conf: Default::default(),
+            digests: Arc::new(RwLock::new(Arc::new(FileDigests::new(None)))),
+            settings: None,
+            tls: None,
+            listeners: HashMap::new(),
+        }
+    }
+
+    /// Applies the HTTP settings of `conf`, starting, stopping or rebinding
+    /// the servers as needed.
+    pub async fn reload(&mut self, conf: &Conf) -> Result<()> {
+        if let Ok(mut current) = self.conf.write() {
+            *current = Some(Arc::new(conf.clone()));
+        }
+        let signer = ImageSigner::load(
+            conf.get_signing_cert().map(PathBuf::as_path),
+            conf.get_signing_key().map(PathBuf::as_path),
+        )?;
+        if let Ok(mut digests) = self.digests.write() {
+            *digests = Arc::new(FileDigests::new(signer));
+        }
+        let settings = ListenSettings::from_conf(conf);
+        if settings.http_port.is_none() && settings.https_port.is_none() {
+            if self.settings.is_some() {
+                info!("HTTP port no longer configured, stopping HTTP service.");
+            } else {
+                debug!("HTTP server not started, no port configured.");
+            }
+            self.stop().await;
+            return Ok(());
+        }
+
+        if let Some(port) = settings
+            .http_port
+            .filter(|_| !conf.get_upload_users().is_empty())
+        {
+            warn!("upload_users are refused on the plain HTTP port {port}, only accepted over HTTPS.");
+        }
+
+        let listen_ips = listen_ips(conf)?;
+        if self.settings.as_ref() != Some(&settings) {
+            let tls = match settings.https_port {
+                Some(_) => {
+                    let names: Vec<String> = listen_ips.iter().map(|ip| ip.to_string()).collect();
+                    Some(tls::acceptor(
+                        settings.https_cert.as_deref(),
+                        settings.https_key.as_deref(),
+                        settings.https_client_ca.as_deref(),
+                        &names,
+                    )?)
+                }
+                None => None,
+            };
+
+            if !self.listeners.is_empty() {
+                info!("HTTP settings changed, restarting HTTP servers.");
+            }
+            self.stop().await;
+            self.tls = tls;
+            self.settings = Some(settings.clone());
+        }
+
+        let mut addrs: HashMap<SocketAddr, Option<TlsAcceptor>> = HashMap::new();
+        for ip in listen_ips {
+            if let Some(port) = settings.http_port {
+                addrs.insert(SocketAddr::new(ip.into(), port), None);
+            }
+            if let Some(port) = settings.https_port {
+                addrs.insert(SocketAddr::new(ip.into(), port), self.tls.clone());
+            }
+        }
+
+        let removed_addrs: Vec<SocketAddr> = self
+            .listeners
+            .keys()
+            .filter(|addr| !addrs.contains_key(addr))
+            .copied()
+            .collect();
+        for addr in removed_addrs {
+            if let Some(listener) = self.listeners.remove(&addr) {
+                info!("HTTP server on {addr} stopped.");
+                listener.cancel().await;
+            }
+        }
+
+        for (addr, tls) in addrs {
+            if self.listeners.contains_key(&addr) {
+                continue;
+            }
+
+            let site = Site {
+                handler: self.handler.clone(),
+                conf: Arc::clone(&self.conf),
+                digests: Arc::clone(&self.digests),
+                addr,
+                scheme: if tls.is_some() { "https" } else { "http" },
+            };
+            #[cfg(target_os = "linux")]
+            let activated = activation::tcp_listener(addr)?;
+            #[cfg(not(target_os = "linux"))]
+            let activated: Option<std::net::TcpListener> = None;
+            // Bound before returning, the rights to bind port 80 being
+            // dropped once the services are started
+            let bound = match activated {
+                Some(listener) => Ok(TcpListener::from(listener)),
+                None => TcpListener::bind(addr).await,
+            };
+            let tcp_listener = match bound {
+                Ok(tcp_listener) => tcp_listener,
+                Err(e) => {
+                    error!("HTTP server on {addr} failed: {e}");
+                    continue;
+                }
+            };
+            let listener = task::spawn(async move {
+                if let Err(e) = listen(tcp_listener, addr, site, tls).await {
+                    error!("HTTP server on {addr} failed: {e}");
+                }
+            });
+            self.listeners.insert(addr, listener);
+        }
+
+        Ok(())
+    }
+
+    pub async fn stop(&mut self) {
+        for (addr, listener) in self.listeners.drain() {
+            info!("HTTP server on {addr} stopped.");
+            listener.cancel().await;
+        }
+        self.settings = None;
+        self.tls = None;
+    }
+}
+
+/// Binds `addr` for the dashboard or the API, taking the listener passed
+/// for it when there's one.
+pub(crate) fn bind_listener(addr: SocketAddr) -> Result<TcpListener> {
+    #[cfg(target_os = "linux")]
+    let activated = activation::tcp_listener(addr)?;
+    #[cfg(not(target_os = "linux"))]
+    let activated: Option<std::net::TcpListener> = None;
+    match activated {
+        Some(listener) => Ok(TcpListener::from(listener)),
+        None => Ok(task::block_on(TcpListener::bind(addr))?),
+    }
+}
+
+/// Serves the connections of `listener` on tasks of their own for the
+/// dashboard, the API and the health checks, `respond` answering each
+/// request given its body. Bodies over `body_limit` bytes are refused with
+/// 413. `name` tells the server in the messages.
+pub(crate) fn serve<F, Fut>(listener: TcpListener, name: &'static str, body_limit: u64, respond: F)
+where
+    F: Fn(Request, Vec<u8>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    let respond = Arc::new(respond);
+    task::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("Failed accepting {name} connection: {e}");
+                    continue;
+                }
+            };
+            let respond = Arc::clone(&respond);
+            task::spawn(async move {
+                if let Err(e) = serve_requests(stream, body_limit, &*respond).await {
+                    debug!("Ended {name} connection with {peer}: {e}");
+                }
+            });
+        }
+    });
+}
+
+/// Serves the requests of a connection of [`serve`] until either side
+/// closes it.
+async fn serve_requests<F, Fut>(stream: TcpStream, body_limit: u64, respond: &F) -> io::Result<()>
+where
+    F: Fn(Request, Vec<u8>) -> Fut,
+    Fut: Future<Output = Response>,
+{
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader);
+    loop {
+        let request = match io::timeout(IDLE_TIMEOUT, read_request(&mut reader)).await {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                let response = Response::text(400, "Bad request");
+                write_response(&mut writer, response, false, false).await?;
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let len = request
+            .header("Content-Length")
+            .map(|len| len.parse::<u64>().unwrap_or(u64::MAX))
+            .unwrap_or_default();
+        if len > body_limit {
+            let response = Response::text(413, "Request body too large");
+            write_response(&mut writer, response, false, false).await?;
+            break;
+        }
+        let mut body = Vec::new();
+        io::timeout(IDLE_TIMEOUT, (&mut reader).take(len).read_to_end(&mut body)).await?;
+
+        let head_only = request.method == "HEAD";
+        let keep_alive = request.keep_alive();
+        let response = respond(request, body).await;
+        if !write_response(&mut writer, response, head_only, keep_alive).await? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+async fn listen(
+    listener: TcpListener,
+    addr: SocketAddr,
+    site: Site,
+    tls: Option<TlsAcceptor>,
+) -> io::Result<()> {
+    let scheme = if tls.is_some() { "HTTPS" } else { "HTTP" };
+    info!("{scheme} server started on {addr}");
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Failed accepting {scheme} connection on {addr}: {e}");
+                continue;
+            }
+        };
+
+        let site = site.clone();
+        let tls = tls.clone();
+        task::spawn(async move {
+            let result = match tls {
+                Some(tls) => match io::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                    Ok(stream) => {
+                        // Only certificates issued by `https_client_ca` are accepted
+                        let client_cert = stream.get_ref().1.peer_certificates().is_some();
+                        serve_connection(stream, peer, site, client_cert).await
+                    }
+                    Err(e) => Err(e),
+                },
+                None => serve_connection(stream, peer, site, false).await,
+            };
+            if let Err(e) = result {
+                debug!("{scheme} connection with {peer} ended: {e}");
+            }
+        });
+    }
+}
+
+/// Serves the requests of a client connection until either side closes it.
+/// `client_cert` tells whether the client authenticated with a certificate.
+async fn serve_connection<S>(
+    stream: S,
+    peer: SocketAddr,
+    site: Site,
+    client_cert: bool,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader);
+    logging::enter_ip(peer.ip());
+
+    loop {
+        let request = match io::timeout(IDLE_TIMEOUT, read_request(&mut reader)).await {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                debug!("Invalid HTTP request from {peer}: {e}");
+                write_response(&mut writer, Response::text(400, "Bad request"), false, false)
+                    .await?;
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+
+        trace!("HTTP request from {peer}: {} {}", request.method, request.path);
+        let (response, keep_alive) = match request.method.as_str() {
+            // The body may be left unread, the connection can't be reused
+            "PUT" | "POST" => (
+                receive_upload(&request, peer, &site, client_cert, &mut reader, &mut writer)
+                    .await?,
+                false,
+            ),
+            _ => (respond(&request, peer, &site).await, request.keep_alive()),
+        };
+        let head_only = request.method == "HEAD";
+        let keep_alive = write_response(&mut writer, response, head_only, keep_alive).await?;
+        if !keep_alive {
+            break;
+        }
+    }
+
+    // Lets TLS clients know the response wasn't truncated
+    writer.close().await
+}
+
+async fn respond(request: &Request, peer: SocketAddr, site: &Site) -> Response {
+    match request.method.as_str() {
+        "GET" | "HEAD" if request.path == template::IPXE_SCRIPT_PATH => {
+            serve_boot_config(request, peer, site, BootConfig::Ipxe).await
+        }
+        "GET" | "HEAD" if request.path == template::MENU_PATH => {
+            serve_menu(request, peer, site).await
+        }
+        "GET" | "HEAD" if request.path == template::GRUB_CFG_PATH => {
+            serve_boot_config(request, peer, site, BootConfig::Grub).await
+        }
+        "GET" | "HEAD" if has_autoinstall_dir(site) && request.path == template::IGNITION_PATH => {
+            serve_ignition(request, peer, site).await
+        }
+        "GET" | "HEAD" => match request.path.strip_prefix(template::AUTOINSTALL_PATH) {
+            Some(name) if has_autoinstall_dir(site) => {
+                serve_autoinstall(request, peer, site, name).await
+            }
+            _ => serve_file(request, peer, site).await,
+        },
+        _ => Response::text(405, "Method not allowed").header("Allow", "GET, HEAD"),
+    }
+}
+
+fn has_autoinstall_dir(site: &Site) -> bool {
+    site.conf()
+        .is_some_and(|conf| conf.get_autoinstall_dir().is_some())
+}
+
+async fn serve_file(request: &Request, peer: SocketAddr, site: &Site) -> Response {
+    let Some(path) = percent_decode(&request.path) else {
+        return Response::text(400, "Bad request");
+    };
+
+    if let Some(response) = fetch_mirrored(site, &path).await {
+        return response;
+    }
+
+    let handler = match site.handler.get() {
+        Ok(handler) => handler,
+        Err(e) => return error_response(&path, e),
+    };
+    let file = match handler.resolve(&peer, Path::new(&path), false).await {
+        Ok(file) => file,
+        Err(packet::Error::FileNotFound) if Detached::of(&path).is_some() => {
+            return serve_detached(peer, site, &handler, &path).await;
+        }
+        Err(e) => return error_response(&path, e),
+    };
+
+    let (len, modified) = file.metadata();
+    // HTTP dates have a precision of seconds
+    let modified = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| Duration::from_secs(since_epoch.as_secs()));
+    let etag = len
+        .zip(modified)
+        .map(|(len, modified)| format!("\"{len:x}-{:x}\"", modified.as_secs()));
+    let last_modified = modified.map(|modified| httpdate::fmt_http_date(UNIX_EPOCH + modified));
+
+    let mut response = Response::new(200);
+    if let Some(etag) = &etag {
+        response = response.header("ETag", etag);
+    }
+    if let Some(last_modified) = &last_modified {
+        response = response.header("Last-Modified", last_modified);
+    }
+    if is_not_modified(request, etag.as_deref(), modified) {
+        return Response {
+            status: 304,
+            ..response
+        };
+    }
+
+    let range = match len {
+        Some(len) if is_range_current(request, etag.as_deref(), last_modified.as_deref()) => {
+            request.header("Range").and_then(|range| parse_range(range, len))
+        }
+        _ => None,
+    };
+    if len.is_some() {
+        response = response.header("Accept-Ranges", "bytes");
+    }
+    let (start, range_len) = match (range, len) {
+        (Some(Ok((start, end))), Some(len)) => {
+            response.status = 206;
+            response = response.header("Content-Range", format!("bytes {start}-{end}/{len}"));
+            (start, Some(end - start + 1))
+        }
+        (Some(Err(())), Some(len)) => {
+            return Response::text(416, "Range not satisfiable")
+                .header("Content-Range", format!("bytes */{len}"));
+        }
+        _ => (0, None),
+    };
+
+    let (reader, len) = match handler.open_resolved(&peer, file, start).await {
+        Ok(opened) => opened,
+        Err(e) => return error_response(&path, e),
+    };
+    let body = match range_len {
+        Some(range_len) => Body::Reader(Box::new(reader.limited(range_len)), Some(range_len)),
+        None => Body::Reader(Box::new(reader), len),
+    };
+
+    response
+        .header("Content-Type", content_type(Path::new(&path)))
+        .body(body)
+}
+
+/// Serves the checksum or signature of a file, at its path with the suffix
+/// appended, computed on the first request after it changes.
+async fn serve_detached(
+    peer: SocketAddr,
+    site: &Site,
+    handler: &DirHandler,
+    path: &str,
+) -> Response {
+    let Some((target, detached)) = Detached::of(path) else {
+        return Response::text(404, "Not found");
+    };
+    let Some(digests) = site.digests() else {
+        return Response::text(503, "Service unavailable");
+    };
+    if detached == Detached::Signature && !digests.can_sign() {
+        return error_response(path, packet::Error::FileNotFound);
+    }
+    let file = match handler.resolve(&peer, Path::new(target), false).await {
+        Ok(file) => file,
+        Err(e) => return error_response(path, e),
+    };
+
+    let metadata = file.metadata();
+    let content = match digests.cached(Path::new(target), detached, metadata) {
+        Some(content) => content,
+        None => {
+            let computed = match file.open().await {
+                Ok(reader) => {
+                    digests
+                        .compute(Path::new(target), detached, metadata, reader)
+                        .await
+                }
+                Err(e) => Err(e.into()),
+            };
+            match computed {
+                Ok(content) => content,
+                Err(e) => {
+                    error!("Failed computing {path}: {e:?}");
+                    return Response::text(500, "Internal server error");
+                }
+            }
+        }
+    };
+
+    Response::new(200)
+        .header("Content-Type", detached.content_type())
+        .body(Body::Bytes(content))
+}
+
+/// Saves the body of a PUT or POST request to the file of the request path,
+/// swapping it in once completely received and matching the checksum given
+/// in `X-Checksum-Sha256`, if any.
+async fn receive_upload<R, W>(
+    request: &Request,
+    peer: SocketAddr,
+    site: &Site,
+    client_cert: bool,
+    reader: &mut R,
+    writer: &mut W,
+) -> io::Result<Response>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let Some(conf) = site.conf().filter(|conf| conf.accepts_uploads()) else {
+        return Ok(Response::text(405, "Method not allowed").header("Allow", "GET, HEAD"));
+    };
+    let tls = site.scheme == "https";
+    if !is_authorized(request, &conf, tls, client_cert) {
+        warn!("Unauthorized upload to {} from {peer}", request.path);
+        let mut response = Response::text(401, "Unauthorized");
+        if conf.get_upload_token().is_some() {
+            response = response.header("WWW-Authenticate", "Bearer");
+        }
+        if tls && !conf.get_upload_users().is_empty() {
+            response = response.header("WWW-Authenticate", "Basic realm=\"preboot-oxide\"");
+        }
+        return Ok(response);
+    }
+
+    let Some(len) = request
+        .header("Content-Length")
+        .filter(|_| request.header("Transfer-Encoding").is_none())
+        .and_then(|len| len.parse::<u64>().ok())
+    else {
+        return Ok(Response::text(411, "Length required"));
+    };
+    if len > conf.get_upload_max_size() {
+        warn!("Refused upload to {} from {peer}, {len} bytes", request.path);
+        return Ok(Response::text(413, "Upload too large"));
+    }
+    let expected = request
+        .header("X-Checksum-Sha256")
+        .map(|checksum| checksum.to_ascii_lowercase());
+    if expected.as_ref().is_some_and(|checksum| {
+        checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit())
+    }) {
+        return Ok(Response::text(400, "Invalid X-Checksum-Sha256"));
+    }
+    let Some(path) = percent_decode(&request.path) else {
+        return Ok(Response::text(400, "Bad request"));
+    };
+    let handler = match site.handler.get() {
+        Ok(handler) => handler,
+        Err(e) => return Ok(error_response(&path, e)),
+    };
+    let dest = match handler
+        .upload_path(Path::new(&path))
+        .and_then(|dest| handler.create_upload_dirs(&dest).map(|_| dest))
+    {
+        Ok(dest) => dest,
+        Err(e) => return Ok(error_response(&path, e)),
+    };
+
+    if request
+        .header("Expect")
+        .is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"))
+    {
+        writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+        writer.flush().await?;
+    }
+
+    let part = part_path(&dest);
+    let digest = match save_upload(reader, &part, len).await {
+        Ok(digest) => digest,
+        Err(e) => {
+            let _ = async_std::fs::remove_file(&part).await;
+            return Err(e);
+        }
+    };
+    if let Some(expected) = expected.filter(|expected| *expected != digest) {
+        let _ = async_std::fs::remove_file(&part).await;
+        warn!("Rejected upload to {path} from {peer}, checksum mismatch");
+        return Ok(Response::text(
+            422,
+            &format!("Checksum mismatch, expected {expected}, got {digest}"),
+        ));
+    }
+
+    let replaced = dest.is_file();
+    if let Err(e) = async_std::fs::rename(&part, &dest).await {
+        let _ = async_std::fs::remove_file(&part).await;
+        error!("Failed saving upload to {}: {e}", dest.display());
+        return Ok(Response::text(500, "Internal server error"));
+    }
+    info!("Received {path} from {peer}, {len} bytes, SHA-256 {digest}");
+
+    Ok(Response::text(if replaced { 200 } else { 201 }, &digest))
+}
+
+/// Whether the request is authorized to upload: by a client certificate, the
+/// upload token as a bearer token, or the credentials of an upload user, only
+/// accepted over TLS not to be sent in clear.
+fn is_authorized(request: &Request, conf: &Conf, tls: bool, client_cert: bool) -> bool {
+    if client_cert && conf.get_https_client_ca().is_some() {
+        return true;
+    }
+    let Some(authorization) = request.header("Authorization") else {
+        return false;
+    };
+
+    if let Some(given) = authorization.strip_prefix("Bearer ") {
+        return conf
+            .get_upload_token()
+            .is_some_and(|token| secret_eq(given, token));
+    }
+    let credentials = authorization
+        .strip_prefix("Basic ")
+        .filter(|_| tls)
+        .and_then(|encoded| BASE64_STANDARD.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+    let Some((user, password)) = credentials
+        .as_ref()
+        .and_then(|credentials| credentials.split_once(':'))
+    else {
+        return false;
+    };
+
+    conf.get_upload_users()
+        .get(user)
+        .is_some_and(|expected| secret_eq(password, expected))
+}
+
+/// Compares in constant time, not to leak secrets through timing.
+pub(crate) fn secret_eq(given: &str, secret: &str) -> bool {
+    given.len() == secret.len()
+        && given
+            .bytes()
+            .zip(secret.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Writes `len` bytes of the request body to `path`, returning their SHA-256.
+async fn save_upload<R>(reader: &mut R, path: &Path, len: u64) -> io::Result<String>
+where
+    R: AsyncRead + Unpin,
+{
+    // The file has a random name, never reuse one found in its place
+    let mut file = async_std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await?;
+    let mut hasher = Sha256::new();
+    let mut body = reader.take(len);
+    let mut buf = vec![0u8; READ_AHEAD_SIZE];
+    loop {
+        let read = io::timeout(IDLE_TIMEOUT, body.read(&mut buf)).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        file.write_all(&buf[..read]).await?;
+    }
+    if body.limit() > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Request body ended early",
+        ));
+    }
+    file.sync_all().await?;
+
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Boot loader configurations rendered per client.
+#[derive(Clone, Copy)]
+enum BootConfig {
+    Ipxe,
+    Grub,
+}
+
+impl BootConfig {
+    fn path(self) -> &'static str {
+        match self {
+            Self::Ipxe => template::IPXE_SCRIPT_PATH,
+            Self::Grub => template::GRUB_CFG_PATH,
+        }
+    }
+}
+
+/// Renders the iPXE script or GRUB configuration of the requesting client.
+async fn serve_boot_config(
+    request: &Request,
+    peer: SocketAddr,
+    site: &Site,
+    kind: BootConfig,
+) -> Response {
+    let handler = match site.handler.get() {
+        Ok(handler) => handler,
+        Err(e) => return error_response(kind.path(), e),
+    };
+    let mac = match (requested_client(request, peer, Some(&handler)), kind) {
+        (Ok(Some(mac)), _) => mac,
+        (Ok(None), BootConfig::Ipxe) => {
+            return Response::text(400, "Unknown client, request the script with ?mac=${net0/mac}")
+        }
+        (Ok(None), BootConfig::Grub) => {
+            return Response::text(
+                400,
+                "Unknown client, request the configuration with ?mac=${net_default_mac}",
+            )
+        }
+        (Err(response), _) => return response,
+    };
+
+    let Some(conf) = site.conf() else {
+        return Response::text(404, "Not found");
+    };
+    // Entries chosen in the iPXE menu replace the configuration of the client
+    let client_conf = match request.query_param("entry") {
+        Some(entry) => match entry
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| conf.get_menu_entries().into_iter().nth(index))
+        {
+            Some(entry) => entry,
+            None => return Response::text(404, "No such menu entry"),
+        },
+        None => match conf.get_from_doc(request_doc(request, Some(&mac))) {
+            Ok(Some(client_conf)) => client_conf,
+            Ok(None) if conf.get_ipxe_chainload() => ConfEntryRef::default(),
+            _ => return Response::text(404, "No configuration found for this client"),
+        },
+    };
+
+    let vars = template_vars(&client_conf, request, Some(&mac), peer, site);
+    let root = conf.get_tftp_serve_path();
+    let template = match (kind, &client_conf, root) {
+        (_, ConfEntryRef { boot_iso: Some(image), .. }, Some(root)) => {
+            detect_boot_entry(root.into(), image.to_path_buf())
+                .await
+                .map(|entry| match kind {
+                    BootConfig::Ipxe => entry.ipxe_template(),
+                    BootConfig::Grub => entry.grub_template(),
+                })
+        }
+        (BootConfig::Ipxe, ConfEntryRef { windows: Some(media), .. }, Some(_)) => {
+            Ok(distro::windows_ipxe_template(media))
+        }
+        (BootConfig::Ipxe, ConfEntryRef { ipxe_script: Some(path), .. }, _)
+        | (BootConfig::Grub, ConfEntryRef { grub_cfg: Some(path), .. }, _) => {
+            read_template(&handler, &peer, path).await
+        }
+        (BootConfig::Ipxe, ConfEntryRef { boot_file: Some(_), .. }, _) => {
+            Ok(template::DEFAULT_IPXE_TEMPLATE.to_string())
+        }
+        // Clients chainloaded into iPXE choose what to boot
+        (BootConfig::Ipxe, ..) if conf.get_ipxe_chainload() => {
+            Ok(template::MENU_IPXE_TEMPLATE.to_string())
+        }
+        (BootConfig::Ipxe, ..) => {
+            Err(Response::text(404, "No boot file configured for this client"))
+        }
+        (BootConfig::Grub, ..) => {
+            Err(Response::text(404, "No GRUB configuration for this client"))
+        }
+    };
+    let template = match template {
+        Ok(template) => template,
+        Err(response) => return response,
+    };
+    debug!(
+        "Serving {} for client {} at {}",
+        kind.path(),
+        bytes_to_mac_address(&mac),
+        peer.ip()
+    );
+
+    rendered_response(template::render(&template, &vars), "text/plain; charset=utf-8")
+}
+
+/// Brings the cached copy of a file under one of the `mirrors` up to date,
+/// before it's served as any other file. An error response when the file
+/// can't be fetched.
+async fn fetch_mirrored(site: &Site, path: &str) -> Option<Response> {
+    let conf = site.conf()?;
+    let root = conf.get_tftp_serve_path()?;
+    let (url, dest) = mirror::locate(Path::new(&root), conf.get_mirrors(), Path::new(path))?;
+
+    match mirror::fetch(&url, &dest).await {
+        Ok(true) => None,
+        Ok(false) => Some(Response::text(404, "Not found")),
+        Err(e) => {
+            warn!("Fetching {url} failed: {e:#}");
+            Some(Response::text(502, "Mirror unavailable"))
+        }
+    }
+}
+
+/// Renders the iPXE menu of the boot entries of the configuration, the entry
+/// of the requesting client, if any, being the default.
+async fn serve_menu(request: &Request, peer: SocketAddr, site: &Site) -> Response {
+    let Some(conf) = site.conf() else {
+        return Response::text(404, "Not found");
+    };
+    let handler = site.handler.get().ok();
+    let mac = match requested_client(request, peer, handler.as_ref()) {
+        Ok(mac) => mac,
+        Err(response) => return response,
+    };
+
+    let entries = conf.get_menu_entries();
+    let root = conf.get_tftp_serve_path().map(PathBuf::from);
+    let mut labels = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        labels.push(menu_label(root.as_ref(), entry).await);
+    }
+    let client_conf = conf
+        .get_from_doc(request_doc(request, mac.as_ref()))
+        .ok()
+        .flatten();
+    let default = client_conf.and_then(|client_conf| {
+        entries
+            .iter()
+            .position(|entry| entry.boots_same_as(&client_conf))
+    });
+    debug!(
+        "Serving {} for client {} at {}",
+        template::MENU_PATH,
+        mac.map(|mac| bytes_to_mac_address(&mac)).unwrap_or("(unknown)".into()),
+        peer.ip()
+    );
+
+    let base_url = format!("{}://{}", site.scheme, site.addr);
+    rendered_response(
+        template::ipxe_menu(&labels, default, &base_url, conf.get_netbootxyz()),
+        "text/plain; charset=utf-8",
+    )
+}
+
+/// Label of a menu entry, its `menu_label` or else told from what it boots.
+async fn menu_label(root: Option<&PathBuf>, entry: &ConfEntryRef<'_>) -> String {
+    if let Some(label) = entry.menu_label {
+        return label.clone();
+    }
+    match (entry.boot_iso, entry.windows, entry.ipxe_script) {
+        (Some(image), ..) => {
+            let detected = match root {
+                Some(root) => detect_boot_entry(root.clone(), image.clone()).await.ok(),
+                None => None,
+            };
+            match detected {
+                Some(detected) => format!("Boot {} from {}", detected.distro, image.display()),
+                None => format!("Boot {}", image.display()),
+            }
+        }
+        (None, Some(media), _) => format!("Install Windows from {}", media.display()),
+        (None, None, Some(script)) => format!("Boot {script}"),
+        (None, None, None) => "Boot".to_string(),
+    }
+}
+
+/// Renders the template `name` of `autoinstall_dir` for the requesting
+/// client. Clients that can't be identified get the `default` configuration.
+async fn serve_autoinstall(request: &Request, peer: SocketAddr, site: &Site, name: &str) -> Response {
+    let Some(conf) = site.conf() else {
+        return Response::text(404, "Not found");
+    };
+    let Some(dir) = conf.get_autoinstall_dir() else {
+        return Response::text(404, "Not found");
+    };
+    let Some(name) = percent_decode(name) else {
+        return Response::text(400, "Bad request");
+    };
+    let name = Path::new(&name);
+    if !name
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Response::text(403, "Forbidden");
+    }
+
+    let handler = site.handler.get().ok();
+    let mac = match requested_client(request, peer, handler.as_ref()) {
+        Ok(mac) => mac,
+        Err(response) => return response,
+    };
+    let client_conf = match conf.get_from_doc(request_doc(request, mac.as_ref())) {
+        Ok(Some(client_conf)) => client_conf,
+        _ => return Response::text(404, "No configuration found for this client"),
+    };
+
+    let template = match read_autoinstall_template(dir, name).await {
+        Ok(template) => template,
+        Err(response) => return response,
+    };
+    let vars = template_vars(&client_conf, request, mac.as_ref(), peer, site);
+    info!(
+        "Serving autoinstall file {} for client {} at {}",
+        name.display(),
+        mac.map(|mac| bytes_to_mac_address(&mac)).unwrap_or("(unknown)".into()),
+        peer.ip()
+    );
+
+    rendered_response(template::render(&template, &vars), content_type(name))
+}
+
+/// Renders the Ignition config of the requesting client, from its `ignition`
+/// template of `autoinstall_dir`, translated when a Butane config. Clients
+/// that can't be identified get the `default` configuration.
+async fn serve_ignition(request: &Request, peer: SocketAddr, site: &Site) -> Response {
+    let Some(conf) = site.conf() else {
+        return Response::text(404, "Not found");
+    };
+    let Some(dir) = conf.get_autoinstall_dir() else {
+        return Response::text(404, "Not found");
+    };
+
+    let handler = site.handler.get().ok();
+    let mac = match requested_client(request, peer, handler.as_ref()) {
+        Ok(mac) => mac,
+        Err(response) => return response,
+    };
+    let client_conf = match conf.get_from_doc(request_doc(request, mac.as_ref())) {
+        Ok(Some(client_conf)) => client_conf,
+        _ => return Response::text(404, "No configuration found for this client"),
+    };
+    let Some(name) = client_conf.ignition.map(Path::new) else {
+        return Response::text(404, "No Ignition config for this client");
+    };
+
+    let template = match read_autoinstall_template(dir, name).await {
+        Ok(template) => template,
+        Err(response) => return response,
+    };
+    let vars = template_vars(&client_conf, request, mac.as_ref(), peer, site);
+    let mut config = template::render(&template, &vars);
+    if template::is_butane(name) {
+        config = match template::butane_to_ignition(config).await {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Failed translating Butane config {}: {e:#}", name.display());
+                return Response::text(500, "Internal server error");
+            }
+        };
+    }
+    info!(
+        "Serving Ignition config {} for client {} at {}",
+        name.display(),
+        mac.map(|mac| bytes_to_mac_address(&mac)).unwrap_or("(unknown)".into()),
+        peer.ip()
+    );
+
+    rendered_response(config, "application/vnd.coreos.ignition+json")
+}
+
+async fn read_autoinstall_template(
+    dir: &Path,
+    name: &Path,
+) -> std::result::Result<String, Response> {
+    match async_std::fs::read_to_string(dir.join(name)).await {
+        Ok(template) => Ok(template),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(Response::text(404, "Not found")),
+        Err(e) => {
+            error!("Failed reading autoinstall template {}: {e}", name.display());
+            Err(Response::text(500, "Internal server error"))
+        }
+    }
+}
+
+/// What the match rules are evaluated against for a request: the client's
+/// MAC address and the `?uuid=` and `?serial=` it gave.
+fn request_doc(request: &Request, mac: Option<&MacAddress>) -> serde_json::Value {
+    template::client_doc(
+        mac,
+        request.query_param("uuid").as_deref(),
+        request.query_param("serial").as_deref(),
+    )
+}
+
+/// The client a request is for, from `?mac=` or else from the address it was
+/// handed boot information at over DHCP.
+fn requested_client(
+    request: &Request,
+    peer: SocketAddr,
+    handler: Option<&DirHandler>,
+) -> std::result::Result<Option<MacAddress>, Response> {
+    let tracked_mac = || match peer.ip() {
+        IpAddr::V4(ip) => handler?.tracker()?.get(&ip).map(|client| client.mac_address),
+        IpAddr::V6(_) => None,
+    };
+    match request.query_param("mac").or_else(tracked_mac) {
+        Some(mac) => template::parse_mac(&mac)
+            .map(Some)
+            .ok_or_else(|| Response::text(400, "Invalid MAC address")),
+        None => Ok(None),
+    }
+}
+
+/// Values of the placeholders of the templates rendered for a client over
+/// HTTP.
+fn template_vars<'a>(
+    client_conf: &ConfEntryRef<'a>,
+    request: &Request,
+    mac: Option<&MacAddress>,
+    peer: SocketAddr,
+    site: &Site,
+) -> Vec<(&'a str, String)> {
+    template::client_vars(
+        client_conf,
+        mac,
+        peer.ip(),
+        site.addr.ip().to_string(),
+        vec![
+            ("base_url", format!("{}://{}", site.scheme, site.addr)),
+            ("uuid", request.query_param("uuid").unwrap_or_default()),
+            ("serial", request.query_param("serial").unwrap_or_default()),
+            ("grub_root", format!("(http,{})", site.addr)),
+        ],
+    )
+}
+
+fn rendered_response(content: String, content_type: &str) -> Response {
+    Response::new(200)
+        .header("Content-Type", content_type)
+        .header("Cache-Control", "no-store")
+        .body(Body::Bytes(content.into_bytes()))
+}
+
+/// Boot entry of the ISO `image` of the image store.
+async fn detect_boot_entry(
+    tftp_root: PathBuf,
+    image: PathBuf,
+) -> std::result::Result<BootEntry, Response> {
+    let entry = task::spawn_blocking({
+        let image = image.clone();
+        move || BootEntry::detect(&tftp_root, &image)
+    })
+    .await
+    .map_err(|e| {
+        warn!("Failed reading ISO image {}: {e}", image.display());
+        match e.kind() {
+            std::io::ErrorKind::NotFound => Response::text(404, "Image not found"),
+            _ => Response::text(500, "Internal server error"),
+        }
+    })?;
+
+    match entry {
+        Some(entry) => Ok(entry),
+        None => {
+            warn!("No known distribution found in ISO image {}", image.display());
+            Err(Response::text(404, "No boot entry found in the image"))
+        }
+    }
+}
+
+async fn read_template(
+    handler: &DirHandler,
+    peer: &SocketAddr,
+    path: &str,
+) -> std::result::Result<String, Response> {
+    let (mut reader, _) = handler
+        .open(peer, Path::new(path), false)
+        .await
+        .map_err(|e| error_response(path, e))?;
+    let mut template = String::new();
+    reader.read_to_string(&mut template).await.map_err(|e| {
+        error!("Failed reading iPXE script template {path}: {e}");
+        Response::text(500, "Internal server error")
+    })?;
+
+    Ok(template)
+}
+
+fn error_response(path: &str, error: packet::Error) -> Response {
+    match error {
+        packet::Error::FileNotFound => Response::text(404, "Not found"),
+        packet::Error::PermissionDenied => Response::text(403, "Forbidden"),
+        packet::Error::Msg(message) => Response::text(503, &message),
+        e => {
+            error!("Failed serving {path} over HTTP: {e:?}");
+            Response::text(500, "Internal server error")
+        }
+    }
+}
+
+/// Whether the client's cached copy is still current, `If-None-Match` taking
+/// precedence over `If-Modified-Since`.
+fn is_not_modified(request: &Request, etag: Option<&str>, modified: Option<Duration>) -> bool {
+    if let Some(if_none_match) = request.header("If-None-Match") {
+        let Some(etag) = etag else {
+            return false;
+        };
+        return if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag);
+    }
+
+    let since = request
+        .header("If-Modified-Since")
+        .and_then(|since| httpdate::parse_http_date(since).ok());
+    match (since, modified) {
+        (Some(since), Some(modified)) => UNIX_EPOCH + modified <= since,
+        _ => false,
+    }
+}
+
+/// Whether a `Range` applies, which with `If-Range` is only when the client's
+/// partial copy is of the same version of the file.
+fn is_range_current(request: &Request, etag: Option<&str>, last_modified: Option<&str>) -> bool {
+    match request.header("If-Range") {
+        Some(if_range) => Some(if_range) == etag || Some(if_range) == last_modified,
+        None => true,
+    }
+}
+
+/// Parses a `Range` header for a file of `len` bytes into the first and last
+/// byte to send, `Err` when the range is past the end of the file. Malformed
+/// headers and multiple ranges are ignored, the whole file being sent instead.
+fn parse_range(range: &str, len: u64) -> Option<std::result::Result<(u64, u64), ()>> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        return Some(Ok((len.saturating_sub(suffix), len - 1)));
+    }
+
+    let start: u64 = start.parse().ok()?;
+    let end = match end {
+        "" => u64::MAX,
+        end => end.parse().ok().filter(|end| *end >= start)?,
+    };
+    if start >= len {
+        return Some(Err(()));
+    }
+
+    Some(Ok((start, end.min(len - 1))))
+}
+
+pub(crate) struct Request {
+    pub(crate) method: String,
+    /// Path of the request target, still percent encoded.
+    pub(crate) path: String,
+    query: String,
+    version: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn query_param(&self, name: &str) -> Option<String> {
+        self.query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .find(|(param, _)| *param == name)
+            .and_then(|(_, value)| percent_decode(value))
+    }
+
+    pub(crate) fn keep_alive(&self) -> bool {
+        let connection = self.header("Connection").map(str::to_ascii_lowercase);
+        match self.version.as_str() {
+            "HTTP/1.0" => connection.as_deref() == Some("keep-alive"),
+            _ => connection.as_deref() != Some("close"),
+        }
+    }
+}
+
+/// Reads the next request head, returns `None` when the client closed the
+/// connection before sending one.
+pub(crate) async fn read_request<R>(reader: &mut R) -> io::Result<Option<Request>>
+where
+    R: futures::AsyncBufRead + Unpin,
+{
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut head = reader.take(REQUEST_HEAD_LIMIT);
+
+    let mut line = String::new();
+    if head.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid("Malformed request line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(invalid("Unsupported HTTP version"));
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (path, query) = (path.to_string(), query.to_string());
+    let (method, version) = (method.to_string(), version.to_string());
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if head.read_line(&mut line).await? == 0 {
+            return Err(invalid("Request head too large or incomplete"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').ok_or_else(|| invalid("Malformed header"))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    Ok(Some(Request {
+        method,
+        path,
+        query,
+        version,
+        headers,
+    }))
+}
+
+pub(crate) enum Body {
+    Empty,
+    Bytes(Vec<u8>),
+    /// Content streamed from a reader, of the given length when known.
+    Reader(Box<dyn AsyncRead + Send + Unpin>, Option<u64>),
+}
+
+pub(crate) struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Body,
+}
+
+impl Response {
+    pub(crate) fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Body::Empty,
+        }
+    }
+
+    pub(crate) fn text(status: u16, message: &str) -> Self {
+        Self::new(status)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(Body::Bytes(format!("{message}\n").into_bytes()))
+    }
+
+    pub(crate) fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    pub(crate) fn body(mut self, body: Body) -> Self {
+        self.body = body;
+        self
+    }
+}
+
+/// Writes the response, returns whether the connection can be kept open.
+pub(crate) async fn write_response<W>(
+    writer: &mut W,
+    response: Response,
+    head_only: bool,
+    keep_alive: bool,
+) -> io::Result<bool>
+where
+    W: AsyncWrite + Unpin,
+{
+    let len = match &response.body {
+        Body::Empty => Some(0),
+        Body::Bytes(bytes) => Some(bytes.len() as u64),
+        Body::Reader(_, len) => *len,
+    };
+    // Without a length the end of the body is signaled by closing the connection
+    let keep_alive = keep_alive && (len.is_some() || head_only);
+
+    let mut head = format!("HTTP/1.1 {} {}\r\n", response.status, reason(response.status));
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    // Not modified responses have no body, their length would be the file's
+    if let Some(len) = len.filter(|_| !matches!(response.status, 204 | 304)) {
+        head.push_str(&format!("Content-Length: {len}\r\n"));
+    }
+    let connection = if keep_alive { "keep-alive" } else { "close" };
+    head.push_str(&format!("Connection: {connection}\r\n\r\n"));
+    writer.write_all(head.as_bytes()).await?;
+
+    if !head_only {
+        match response.body {
+            Body::Empty => {}
+            Body::Bytes(bytes) => writer.write_all(&bytes).await?,
+            Body::Reader(reader, len) => {
+                let copied = futures::io::copy(reader, writer).await?;
+                if len.is_some_and(|len| len != copied) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("Sent {copied} bytes of {len:?}"),
+                    ));
+                }
+            }
+        }
+    }
+    writer.flush().await?;
+
+    Ok(keep_alive)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        416 => "Range Not Satisfiable",
+        422 => "Unprocessable Content",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "ipxe" | "cfg" | "conf" | "txt" | "ks" | "seed" | "yaml" | "yml" => "text/plain; charset=utf-8",
+        "iso" => "application/x-iso9660-image",
+        "efi" => "application/efi",
+        _ => "application/octet-stream",
+    }
+}
+
+fn percent_decode(path: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut chars = path.bytes();
+    while let Some(byte) = chars.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hex = [chars.next()?, chars.next()?];
+        bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+    }
+
+    String::from_utf8(bytes).ok()
+}
@@ -0,0 +1,330 @@
+//! Image store: boot assets fetched from the URLs declared in `images`,
+//! verified, kept in a few versions and served at stable paths under
+//! `<tftp_server_dir>/images/`, optionally checked for updates periodically.
+use std::{
+    fs,
+    io::Read,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use async_std::task;
+use log::{debug, error, info};
+use minisign_verify::{PublicKey, Signature};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    chainload,
+    conf::{Conf, ImageSource},
+    distro::{WIMBOOT_IMAGE, WIMBOOT_URL},
+    fetch::{self, Download},
+    secureboot,
+    readahead::READ_AHEAD_SIZE,
+    util::{part_path, to_hex},
+    Result,
+};
+
+/// Directory of the TFTP root the images are served from.
+pub const IMAGES_DIR: &str = "images";
+/// Hidden, so only the current version of an image is served.
+pub const VERSIONS_DIR: &str = ".versions";
+/// ETag of the served version, in the versions directory of an image.
+const ETAG_FILENAME: &str = ".etag";
+
+pub fn spawn_image_service_async(conf: &Conf) -> Result<ImageService> {
+    let mut service = ImageService::new();
+    task::block_on(service.reload(conf))?;
+
+    Ok(service)
+}
+
+/// Keeps the image store in sync with the configuration.
+#[derive(Default)]
+pub struct ImageService {
+    settings: Option<StoreSettings>,
+    sync: Option<task::JoinHandle<()>>,
+}
+
+/// Settings the images are fetched again for when they change.
+#[derive(Clone, PartialEq)]
+struct StoreSettings {
+    root: PathBuf,
+    images: Vec<ImageSource>,
+    versions: usize,
+    /// Versions kept regardless of `versions`, for the clients pinning them.
+    pinned: HashMap<PathBuf, Vec<String>>,
+    refresh_interval: Option<Duration>,
+}
+
+impl ImageService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fetches the images of `conf` in the background when they changed.
+    pub async fn reload(&mut self, conf: &Conf) -> Result<()> {
+        let mut images = conf.get_images().clone();
+        let mut profile_images = Vec::new();
+        if conf.has_windows_profile() {
+            profile_images.push(ImageSource {
+                path: PathBuf::from(WIMBOOT_IMAGE),
+                url: WIMBOOT_URL.to_string(),
+                sha256: None,
+                sha256sums_url: None,
+                minisign_key: None,
+            });
+        }
+        if conf.get_secure_boot() {
+            profile_images.extend(secureboot::images());
+            if let (Some(root), Some(http_port)) =
+                (conf.get_tftp_serve_path(), conf.get_http_port())
+            {
+                secureboot::write_bootstrap(Path::new(&root), http_port)
+                    .context("Writing the GRUB bootstrap configuration")?;
+            }
+        }
+        if conf.get_ipxe_chainload() {
+            profile_images.extend(chainload::images());
+            if let (Some(root), Some(http_port)) =
+                (conf.get_tftp_serve_path(), conf.get_http_port())
+            {
+                chainload::write_script(Path::new(&root), http_port)
+                    .context("Writing the iPXE chainloading script")?;
+            }
+        }
+        // Images given in the configuration replace those of the profiles
+        for image in profile_images {
+            if !images.iter().any(|configured| configured.path == image.path) {
+                images.push(image);
+            }
+        }
+
+        let settings = match conf.get_tftp_serve_path() {
+            Some(root) if !images.is_empty() => Some(StoreSettings {
+                root: PathBuf::from(root),
+                pinned: images
+                    .iter()
+                    .map(|image| (image.path.clone(), conf.get_pinned_versions(&image.path)))
+                    .collect(),
+                images,
+                versions: usize::try_from(conf.get_image_versions())?,
+                refresh_interval: conf.get_image_refresh_interval(),
+            }),
+            _ => None,
+        };
+        if settings == self.settings {
+            return Ok(());
+        }
+
+        if let Some(sync) = self.sync.take() {
+            sync.cancel().await;
+        }
+        self.settings = settings.clone();
+        if let Some(settings) = settings {
+            self.sync = Some(task::spawn(keep_synced(settings)));
+        }
+
+        Ok(())
+    }
+}
+
+/// Syncs the images, then again after each refresh interval, if any.
+async fn keep_synced(settings: StoreSettings) {
+    loop {
+        sync_all(&settings).await;
+        match settings.refresh_interval {
+            Some(interval) => task::sleep(interval).await,
+            None => break,
+        }
+    }
+}
+
+async fn sync_all(settings: &StoreSettings) {
+    for image in &settings.images {
+        let path = image.path.display();
+        let pinned = settings.pinned.get(&image.path).map(Vec::as_slice).unwrap_or_default();
+        match sync_image(&settings.root, image, settings.versions, pinned).await {
+            Ok(true) => info!("Image {path} updated from {}", image.url),
+            Ok(false) => debug!("Image {path} is up to date with {}", image.url),
+            Err(e) => error!("Failed updating image {path}: {e:#}"),
+        }
+    }
+}
+
+/// Fetches `image` into the store under `root` unless the served version is
+/// current, verifies it and swaps it in, keeping up to `versions` versions
+/// besides the `pinned` ones. Returns whether the served version changed.
+pub async fn sync_image(
+    root: &Path,
+    image: &ImageSource,
+    versions: usize,
+    pinned: &[String],
+) -> Result<bool> {
+    let images_dir = root.join(IMAGES_DIR);
+    let served = images_dir.join(&image.path);
+    let versions_dir = images_dir.join(VERSIONS_DIR).join(&image.path);
+    async_std::fs::create_dir_all(&versions_dir).await?;
+
+    let etag_path = versions_dir.join(ETAG_FILENAME);
+    let since = fs::metadata(&served).and_then(|m| m.modified()).ok();
+    let etag = since.and_then(|_| fs::read_to_string(&etag_path).ok());
+    let download =
+        fetch::download_part(&image.url, &versions_dir.join("download"), since, etag.as_deref())
+            .await?;
+    let Some(Download { part, etag }) = download else {
+        return Ok(false);
+    };
+
+    let digest = match verify(&part, image).await {
+        Ok(digest) => digest,
+        Err(e) => {
+            let _ = fs::remove_file(&part);
+            return Err(e);
+        }
+    };
+
+    // Versions are named by checksum, the same content downloaded again
+    // replaces its version
+    let version = versions_dir.join(&digest);
+    let known = version.is_file();
+    fs::rename(&part, &version)?;
+    serve_version(&version, &served).with_context(|| format!("Serving {}", served.display()))?;
+    match etag {
+        Some(etag) => fs::write(&etag_path, etag)?,
+        None if etag_path.exists() => fs::remove_file(&etag_path)?,
+        None => {}
+    }
+    prune_versions(&versions_dir, &version, versions, pinned)?;
+
+    Ok(!known)
+}
+
+/// Checks the downloaded file against the configured checksum and signature,
+/// returns its hex encoded SHA-256.
+async fn verify(path: &Path, image: &ImageSource) -> Result<String> {
+    let expected = match (&image.sha256, &image.sha256sums_url) {
+        (Some(sha256), _) => Some(sha256.clone()),
+        (None, Some(sums_url)) => Some(checksum_from_list(
+            &fetch::get_text(sums_url).await?,
+            &image.url,
+        )?),
+        (None, None) => None,
+    };
+    let signature = match &image.minisign_key {
+        Some(key) => Some((
+            key.clone(),
+            fetch::get_text(&format!("{}.minisig", image.url)).await?,
+        )),
+        None => None,
+    };
+
+    let path = path.to_path_buf();
+    let digest = task::spawn_blocking(move || hash_and_verify(&path, signature)).await?;
+    if let Some(expected) = expected {
+        if digest != expected {
+            bail!("Checksum mismatch, expected {expected}, got {digest}");
+        }
+    }
+
+    Ok(digest)
+}
+
+/// Computes the SHA-256 of the file, verifying its minisign signature in
+/// the same pass when given a public key and signature.
+fn hash_and_verify(path: &Path, signature: Option<(String, String)>) -> Result<String> {
+    let signature = signature
+        .map(|(key, signature)| -> Result<_> {
+            Ok((PublicKey::from_base64(&key)?, Signature::decode(&signature)?))
+        })
+        .transpose()
+        .context("Reading the minisign key and signature")?;
+    let mut verifier = signature
+        .as_ref()
+        .map(|(key, signature)| key.verify_stream(signature))
+        .transpose()?;
+
+    let mut hasher = Sha256::new();
+    let mut file = fs::File::open(path)?;
+    let mut buf = vec![0u8; READ_AHEAD_SIZE];
+    loop {
+        let len = file.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+        if let Some(verifier) = verifier.as_mut() {
+            verifier.update(&buf[..len]);
+        }
+    }
+    if let Some(verifier) = verifier.as_mut() {
+        verifier.finalize().context("Verifying the minisign signature")?;
+    }
+
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Finds the checksum of the file of `url` in a `sha256sum` (or BSD style)
+/// checksum list.
+fn checksum_from_list(list: &str, url: &str) -> Result<String> {
+    let url = url.split(['?', '#']).next().unwrap_or(url);
+    let name = url.rsplit('/').next().unwrap_or(url);
+
+    list.lines()
+        .find_map(|line| {
+            let line = line.trim();
+            if let Some(bsd) = line.strip_prefix("SHA256 (") {
+                let (file, checksum) = bsd.split_once(") = ")?;
+                return (file == name).then(|| checksum.trim().to_ascii_lowercase());
+            }
+            let (checksum, file) = line.split_once(char::is_whitespace)?;
+            let file = file.trim_start().trim_start_matches(['*', '.', '/']);
+            (file == name || file.ends_with(&format!("/{name}")))
+                .then(|| checksum.to_ascii_lowercase())
+        })
+        .ok_or(anyhow!("No checksum listed for {name}"))
+}
+
+/// Atomically replaces the served file with a hard link to `version`, or a
+/// copy when linking isn't possible.
+fn serve_version(version: &Path, served: &Path) -> std::io::Result<()> {
+    if let Some(parent) = served.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let next = part_path(served);
+    fs::hard_link(version, &next).or_else(|_| fs::copy(version, &next).map(|_| ()))?;
+    fs::rename(&next, served).inspect_err(|_| {
+        let _ = fs::remove_file(&next);
+    })
+}
+
+/// Removes all but the `keep` most recent versions, never the current or a
+/// pinned one.
+fn prune_versions(
+    versions_dir: &Path,
+    current: &Path,
+    keep: usize,
+    pinned: &[String],
+) -> std::io::Result<()> {
+    let is_kept = |path: &Path| {
+        path.file_name()
+            .map(|name| name.to_string_lossy())
+            .is_some_and(|name| name.starts_with('.') || pinned.iter().any(|pin| *pin == name))
+    };
+    let mut versions: Vec<(std::time::SystemTime, PathBuf)> = fs::read_dir(versions_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.as_path() != current && !is_kept(path))
+        .filter(|path| !path.to_string_lossy().ends_with(".part"))
+        .filter_map(|path| Some((fs::metadata(&path).ok()?.modified().ok()?, path)))
+        .collect();
+    versions.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+
+    for (_, path) in versions.into_iter().skip(keep.saturating_sub(1)) {
+        debug!("Removing old image version {}", path.display());
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
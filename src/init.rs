@@ -0,0 +1,229 @@
+//! `init` subcommand: asks a few questions, with defaults taken from the
+//! local network interfaces, and writes a starter configuration file, checked
+//! to be valid, for first time users.
+use std::{
+    fs,
+    io::{BufRead, Write},
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use network_interface::{Addr, NetworkInterface, NetworkInterfaceConfig};
+
+use crate::{conf::Conf, migrate::CONF_VERSION, util::part_path, Result};
+
+pub const DEFAULT_TFTP_DIR: &str = "/srv/tftp";
+pub const DEFAULT_BOOT_FILE: &str = "ipxe.efi";
+
+/// Network interface of the machine, with its IPv4 addresses.
+pub struct Interface {
+    pub name: String,
+    pub ips: Vec<Ipv4Addr>,
+}
+
+/// The interfaces with IPv4 addresses, the loopback one excluded.
+pub fn local_interfaces() -> Result<Vec<Interface>> {
+    let mut interfaces: Vec<Interface> = Vec::new();
+    for iface in NetworkInterface::show().context("Listing network interfaces")? {
+        let ips: Vec<Ipv4Addr> = iface
+            .addr
+            .iter()
+            .filter_map(|addr| match addr {
+                Addr::V4(v4) if !v4.ip.is_loopback() => Some(v4.ip),
+                _ => None,
+            })
+            .collect();
+        if ips.is_empty() {
+            continue;
+        }
+        // Listed once per address on some platforms
+        match interfaces.iter_mut().find(|known| known.name == iface.name) {
+            Some(known) => known.ips.extend(ips),
+            None => interfaces.push(Interface {
+                name: iface.name,
+                ips,
+            }),
+        }
+    }
+
+    Ok(interfaces)
+}
+
+/// Runs the wizard on the terminal, writing the configuration to `path`, or
+/// to the default location.
+pub fn run_interactive(path: Option<PathBuf>) -> Result<()> {
+    let path = path.unwrap_or_else(|| Conf::config_path(None));
+    let interfaces = local_interfaces()?;
+    run(&mut std::io::stdin().lock(), &mut std::io::stdout(), &interfaces, &path)
+}
+
+/// Asks the questions on `output`, reading the answers from `input`, then
+/// writes the configuration to `path`.
+pub fn run(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    interfaces: &[Interface],
+    path: &Path,
+) -> Result<()> {
+    writeln!(output, "This writes a starter configuration to {}.", path.display())?;
+    if path.exists() && !confirm(input, output, "The file exists, overwrite it?", false)? {
+        writeln!(output, "Nothing written.")?;
+        return Ok(());
+    }
+
+    writeln!(output, "\nNetwork interfaces:")?;
+    for iface in interfaces {
+        let ips: Vec<String> = iface.ips.iter().map(Ipv4Addr::to_string).collect();
+        writeln!(output, "  {}: {}", iface.name, ips.join(", "))?;
+    }
+    let suggested = interfaces.first().map(|iface| iface.name.as_str()).unwrap_or("");
+    let ifaces = ask(
+        input,
+        output,
+        "Interfaces to serve, comma separated, `all` for all of them",
+        suggested,
+    )?;
+    let ifaces: Vec<String> = match ifaces.as_str() {
+        "" | "all" => Vec::new(),
+        _ => ifaces.split(',').map(|name| name.trim().to_string()).collect(),
+    };
+    for name in &ifaces {
+        if !interfaces.iter().any(|iface| &iface.name == name) {
+            writeln!(output, "Warning: no interface {name} with an IPv4 address found.")?;
+        }
+    }
+
+    writeln!(
+        output,
+        "\npreboot-oxide works next to the DHCP server of the network (e.g. the router), \
+         adding the boot information to its offers, it doesn't hand out addresses itself."
+    )?;
+    if !confirm(input, output, "Is there a DHCP server on the network?", true)? {
+        writeln!(
+            output,
+            "Warning: clients won't boot until a DHCP server hands them addresses."
+        )?;
+    }
+
+    let tftp_dir = ask(
+        input,
+        output,
+        "\nDirectory of the boot files, served over TFTP",
+        DEFAULT_TFTP_DIR,
+    )?;
+    let tftp_path = Path::new(&tftp_dir);
+    if !tftp_path.is_dir() && confirm(input, output, "It doesn't exist, create it?", true)? {
+        fs::create_dir_all(tftp_path)
+            .with_context(|| format!("Creating {}", tftp_path.display()))?;
+    }
+
+    let boot_file = ask(
+        input,
+        output,
+        "\nDefault boot file, relative to that directory, `netboot.xyz` for its menu",
+        DEFAULT_BOOT_FILE,
+    )?;
+    if boot_file != "netboot.xyz" && !tftp_path.join(&boot_file).is_file() {
+        writeln!(output, "Note: copy {boot_file} to {tftp_dir} before booting clients.")?;
+    }
+
+    let yaml = starter_yaml(&ifaces, &tftp_dir, &boot_file);
+    write_checked(path, &yaml)?;
+    writeln!(
+        output,
+        "\nWrote {}, start preboot-oxide to use it, see doc/manual.md for more.",
+        path.display()
+    )?;
+
+    Ok(())
+}
+
+/// The configuration file for the answers, `netboot.xyz` as `boot_file`
+/// enabling `netbootxyz` instead.
+pub fn starter_yaml(ifaces: &[String], tftp_dir: &str, boot_file: &str) -> String {
+    let mut yaml = format!("# Written by preboot-oxide init\nversion: {CONF_VERSION}\n");
+    if !ifaces.is_empty() {
+        let ifaces: Vec<String> = ifaces.iter().map(|name| quote(name)).collect();
+        yaml += &format!("ifaces: [{}]\n", ifaces.join(", "));
+    }
+    yaml += &format!("tftp_server_dir: {}\n", quote(tftp_dir));
+    match boot_file {
+        "netboot.xyz" => yaml += "netbootxyz: true\n",
+        _ => yaml += &format!("default:\n  boot_file: {}\n", quote(boot_file)),
+    }
+
+    yaml
+}
+
+/// `value` as a YAML string, quoted when it would read as something else.
+fn quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._-".contains(c))
+        && value.parse::<f64>().is_err();
+    match plain {
+        true => value.to_string(),
+        false => serde_json::Value::from(value).to_string(),
+    }
+}
+
+/// Writes `yaml` to `path` once it's loaded and validated.
+fn write_checked(path: &Path, yaml: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let part = part_path(path);
+    fs::write(&part, yaml)?;
+    let result = Conf::from_config_file(Some(&part))
+        .and_then(|conf| conf.validate())
+        .context("Checking the configuration written")
+        .and_then(|_| Ok(fs::rename(&part, path)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&part);
+    }
+
+    result
+}
+
+/// Answer to `question`, `default` when left empty.
+fn ask(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    default: &str,
+) -> Result<String> {
+    match default {
+        "" => write!(output, "{question}: ")?,
+        _ => write!(output, "{question} [{default}]: ")?,
+    }
+    output.flush()?;
+    let mut answer = String::new();
+    if input.read_line(&mut answer)? == 0 {
+        bail!("No answer to: {}", question.trim());
+    }
+
+    Ok(match answer.trim() {
+        "" => default.to_string(),
+        answer => answer.to_string(),
+    })
+}
+
+fn confirm(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    default: bool,
+) -> Result<bool> {
+    let choices = if default { "Y/n" } else { "y/N" };
+    loop {
+        let answer = ask(input, output, &format!("{question} ({choices})"), "")?;
+        match answer.to_ascii_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => writeln!(output, "Please answer y or n.")?,
+        }
+    }
+}
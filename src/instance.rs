@@ -0,0 +1,48 @@
+//! Telling whether another server is running. On the BSDs, where the crate
+//! used elsewhere doesn't build, a lock is held on `<name>.lock` in the
+//! temporary directory, the way that crate does on the other Unixes.
+#[cfg(not(any(target_os = "freebsd", target_os = "openbsd")))]
+pub use single_instance::SingleInstance;
+
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+pub use bsd::SingleInstance;
+
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+mod bsd {
+    use std::{
+        env,
+        fs::{File, OpenOptions},
+        os::fd::AsRawFd,
+    };
+
+    use anyhow::Context;
+
+    use crate::Result;
+
+    pub struct SingleInstance {
+        /// Holds the lock until dropped.
+        _file: File,
+        is_single: bool,
+    }
+
+    impl SingleInstance {
+        pub fn new(name: &str) -> Result<Self> {
+            let path = env::temp_dir().join(format!("{name}.lock"));
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .context(format!("Opening the lock file {}", path.display()))?;
+            // SAFETY: the descriptor is of the file just opened
+            let is_single = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0;
+
+            Ok(Self { _file: file, is_single })
+        }
+
+        /// Whether no other instance holds the lock.
+        pub fn is_single(&self) -> bool {
+            self.is_single
+        }
+    }
+}
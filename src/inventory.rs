@@ -0,0 +1,207 @@
+//! Lookup of the configuration of clients in the inventory of the site, so
+//! provisioning follows its source of truth: the host is found by its MAC
+//! address, or else its serial number, and its boot profile is read from
+//! it. NetBox is the inventory supported, behind the `Inventory` trait.
+//! Answers are cached for `inventory_cache_ttl`, unknown hosts included.
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_std::task;
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+
+use crate::{
+    conf::{Conf, ConfEntry, MacAddress},
+    util::mac_from_doc,
+    Result,
+};
+
+/// Time the inventory is waited for, short enough for the clients not to
+/// give up on the offer.
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+/// Key of the NetBox config context holding the boot profile of a device.
+pub const NETBOX_CONTEXT_KEY: &str = "preboot_oxide";
+
+/// The hosts looked up, by the inventory and key they were looked up with,
+/// with the time of the lookup.
+type Cache = HashMap<(String, String), (Instant, Option<ConfEntry>)>;
+static CACHE: Lazy<Mutex<Cache>> = Lazy::new(Default::default);
+
+/// Source of truth of the hosts booted.
+pub trait Inventory: Send + Sync {
+    /// Name of the inventory in the logs and cache, e.g. its URL.
+    fn name(&self) -> &str;
+
+    /// Boot profile of the host with the given MAC address, `None` when
+    /// unknown or without one.
+    fn by_mac(&self, mac: &MacAddress) -> Result<Option<ConfEntry>>;
+
+    /// Boot profile of the host with the given serial number.
+    fn by_serial(&self, serial: &str) -> Result<Option<ConfEntry>>;
+}
+
+/// The inventory configured, if any.
+pub fn from_conf(conf: &Conf) -> Option<Box<dyn Inventory>> {
+    let url = conf.get_netbox_url()?;
+    Some(Box::new(NetBox::new(url, conf.get_netbox_token(), TIMEOUT)))
+}
+
+/// Configuration of the client of `doc` from the inventory, `None` when
+/// none is configured, the host isn't in it or it failed, for `match` to be
+/// used.
+pub async fn client_conf(conf: &Conf, doc: &serde_json::Value) -> Option<ConfEntry> {
+    let inventory = from_conf(conf)?;
+    let ttl = conf.get_inventory_cache_ttl();
+    let doc = doc.clone();
+    let name = inventory.name().to_string();
+    let result = task::spawn_blocking(move || lookup(inventory.as_ref(), ttl, &doc)).await;
+    match result {
+        Ok(entry) => entry,
+        Err(e) => {
+            warn!("Lookup in the inventory {name} failed, using the match rules: {e:#}");
+            None
+        }
+    }
+}
+
+/// Looks the client of `doc` up by its MAC address, then by its serial
+/// number, reusing the answers younger than `ttl`.
+pub fn lookup(
+    inventory: &dyn Inventory,
+    ttl: Duration,
+    doc: &serde_json::Value,
+) -> Result<Option<ConfEntry>> {
+    if let Some(mac) = mac_from_doc(doc) {
+        let key = format!("mac:{}", mac.map(|byte| format!("{byte:02x}")).join(":"));
+        if let Some(entry) = cached(inventory, ttl, key, || inventory.by_mac(&mac))? {
+            return Ok(Some(entry));
+        }
+    }
+    if let Some(serial) = doc["Serial"].as_str().filter(|serial| !serial.is_empty()) {
+        let key = format!("serial:{serial}");
+        return cached(inventory, ttl, key, || inventory.by_serial(serial));
+    }
+
+    Ok(None)
+}
+
+/// Forgets the hosts looked up, for them to be looked up again.
+pub fn clear_cache() {
+    CACHE.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+fn cached(
+    inventory: &dyn Inventory,
+    ttl: Duration,
+    key: String,
+    lookup: impl FnOnce() -> Result<Option<ConfEntry>>,
+) -> Result<Option<ConfEntry>> {
+    let cache_key = (inventory.name().to_string(), key);
+    {
+        let cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((time, entry)) = cache.get(&cache_key) {
+            if time.elapsed() < ttl {
+                debug!("Using the cached answer of {} for {}.", cache_key.0, cache_key.1);
+                return Ok(entry.clone());
+            }
+        }
+    }
+
+    // Failures aren't cached, for the inventory to be asked again
+    let entry = lookup()?;
+    if !ttl.is_zero() {
+        let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        cache.retain(|_, (time, _)| time.elapsed() < ttl);
+        cache.insert(cache_key, (Instant::now(), entry.clone()));
+    }
+
+    Ok(entry)
+}
+
+/// NetBox, the boot profile of a device being the `preboot_oxide` key of
+/// its rendered config context, so profiles can be assigned by role, site,
+/// platform or tag. The device name is given to templates as `hostname`.
+pub struct NetBox {
+    url: String,
+    token: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl NetBox {
+    pub fn new(url: &str, token: Option<&String>, timeout: Duration) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            token: token.cloned(),
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+        }
+    }
+
+    fn device(&self, filter: &str, value: &str) -> Result<Option<ConfEntry>> {
+        let url = format!("{}/api/dcim/devices/", self.url);
+        let mut request = self
+            .agent
+            .get(&url)
+            .query(filter, value)
+            .set("Accept", "application/json");
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Token {token}"));
+        }
+        let response = request
+            .call()
+            .map_err(anyhow::Error::from)
+            .and_then(|response| Ok(response.into_string()?))
+            .with_context(|| format!("Looking up {filter} {value} in {url}"))?;
+
+        let devices: serde_json::Value =
+            serde_json::from_str(&response).context("Parsing the answer of NetBox")?;
+        let Some(devices) = devices["results"].as_array() else {
+            bail!("Expected the answer of NetBox to list results");
+        };
+        let device = match devices.as_slice() {
+            [] => {
+                debug!("No device of {filter} {value} in NetBox.");
+                return Ok(None);
+            }
+            [device] => device,
+            _ => {
+                warn!("{} devices of {filter} {value} in NetBox, ignoring them.", devices.len());
+                return Ok(None);
+            }
+        };
+        let profile = &device["config_context"][NETBOX_CONTEXT_KEY];
+        if profile.is_null() {
+            debug!("Device of {filter} {value} has no {NETBOX_CONTEXT_KEY} config context.");
+            return Ok(None);
+        }
+
+        let mut entry = ConfEntry::from_json(profile.clone())
+            .with_context(|| format!("Parsing the {NETBOX_CONTEXT_KEY} config context"))?;
+        if let Some(name) = device["name"].as_str() {
+            entry
+                .vars
+                .entry("hostname".to_string())
+                .or_insert_with(|| name.to_string());
+        }
+
+        Ok(Some(entry))
+    }
+}
+
+impl Inventory for NetBox {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    fn by_mac(&self, mac: &MacAddress) -> Result<Option<ConfEntry>> {
+        let mac = mac.map(|byte| format!("{byte:02x}")).join(":");
+        self.device("mac_address", &mac)
+    }
+
+    fn by_serial(&self, serial: &str) -> Result<Option<ConfEntry>> {
+        self.device("serial", serial)
+    }
+}
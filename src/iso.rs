@@ -0,0 +1,269 @@
+//! Read-only lookup of files stored in ISO9660 images, including the Joliet
+//! and Rock Ridge extensions most distribution images use for their file names.
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Component, Path},
+};
+
+use log::trace;
+
+pub const SECTOR_SIZE: u64 = 2048;
+pub const ISO_EXTENSION: &str = "iso";
+
+const FIRST_VOLUME_DESCRIPTOR: u64 = 16;
+const MAX_VOLUME_DESCRIPTORS: u64 = 64;
+const ROOT_RECORD_OFFSET: usize = 156;
+const DIRECTORY_FLAG: u8 = 0x02;
+
+/// Location of a file's content within an ISO image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsoFile {
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum NameFormat {
+    Plain,
+    Joliet,
+    RockRidge { skip: usize },
+}
+
+#[derive(Debug, Clone)]
+struct DirRecord {
+    extent: u32,
+    size: u32,
+    is_dir: bool,
+    name: String,
+}
+
+pub struct IsoImage {
+    file: File,
+    root: DirRecord,
+    names: NameFormat,
+}
+
+impl IsoImage {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut primary_root = None;
+        let mut joliet_root = None;
+
+        for index in FIRST_VOLUME_DESCRIPTOR..FIRST_VOLUME_DESCRIPTOR + MAX_VOLUME_DESCRIPTORS {
+            let sector = read_sector(&mut file, index)?;
+            if &sector[1..6] != b"CD001" {
+                return Err(invalid_data("Not an ISO9660 image"));
+            }
+
+            let root_record = &sector[ROOT_RECORD_OFFSET..ROOT_RECORD_OFFSET + 34];
+            match sector[0] {
+                1 => primary_root = parse_record(root_record, NameFormat::Plain),
+                2 if is_joliet(&sector[88..120]) => {
+                    joliet_root = parse_record(root_record, NameFormat::Joliet)
+                }
+                255 => break,
+                _ => {}
+            }
+        }
+
+        let primary_root = primary_root.ok_or(invalid_data("No primary volume descriptor"))?;
+        let rock_ridge_skip = rock_ridge_skip(&mut file, &primary_root)?;
+        let (root, names) = match (rock_ridge_skip, joliet_root) {
+            (Some(skip), _) => (primary_root, NameFormat::RockRidge { skip }),
+            (None, Some(joliet_root)) => (joliet_root, NameFormat::Joliet),
+            (None, None) => (primary_root, NameFormat::Plain),
+        };
+        trace!("ISO image {} uses {:?} file names", path.display(), names);
+
+        Ok(Self { file, root, names })
+    }
+
+    /// Finds a regular file by its path within the image. Names are compared
+    /// case insensitively.
+    pub fn find(&mut self, path: &Path) -> io::Result<Option<IsoFile>> {
+        let mut current = self.root.clone();
+
+        for component in path.components() {
+            let name = match component {
+                Component::Normal(name) => name.to_string_lossy(),
+                Component::RootDir | Component::CurDir => continue,
+                _ => return Ok(None),
+            };
+            if !current.is_dir {
+                return Ok(None);
+            }
+
+            let entries = self.read_dir(&current)?;
+            match entries
+                .into_iter()
+                .find(|entry| entry.name.eq_ignore_ascii_case(&name))
+            {
+                Some(entry) => current = entry,
+                None => return Ok(None),
+            }
+        }
+
+        if current.is_dir {
+            return Ok(None);
+        }
+
+        Ok(Some(IsoFile {
+            offset: u64::from(current.extent) * SECTOR_SIZE,
+            size: u64::from(current.size),
+        }))
+    }
+
+    fn read_dir(&mut self, dir: &DirRecord) -> io::Result<Vec<DirRecord>> {
+        let mut data = vec![0u8; dir.size as usize];
+        self.file
+            .seek(SeekFrom::Start(u64::from(dir.extent) * SECTOR_SIZE))?;
+        self.file.read_exact(&mut data)?;
+
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let len = data[offset] as usize;
+            if len == 0 {
+                // Records don't cross sector boundaries, the rest of the sector is padding
+                offset = (offset / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+                continue;
+            }
+            if offset + len > data.len() {
+                break;
+            }
+
+            let record = &data[offset..offset + len];
+            if !is_self_or_parent(record) {
+                entries.extend(parse_record(record, self.names));
+            }
+            offset += len;
+        }
+
+        Ok(entries)
+    }
+}
+
+fn read_sector(file: &mut File, index: u64) -> io::Result<[u8; SECTOR_SIZE as usize]> {
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+    file.seek(SeekFrom::Start(index * SECTOR_SIZE))?;
+    file.read_exact(&mut sector)?;
+
+    Ok(sector)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn is_joliet(escape_sequences: &[u8]) -> bool {
+    [b"%/@", b"%/C", b"%/E"]
+        .iter()
+        .any(|seq| escape_sequences.windows(3).any(|window| window == *seq))
+}
+
+/// Whether the record is the `.` or `..` entry of a directory.
+fn is_self_or_parent(record: &[u8]) -> bool {
+    record.len() > 33 && record[32] == 1 && record[33] <= 1
+}
+
+fn parse_record(record: &[u8], names: NameFormat) -> Option<DirRecord> {
+    if record.len() < 34 {
+        return None;
+    }
+
+    let name_len = record[32] as usize;
+    let raw_name = record.get(33..33 + name_len)?;
+
+    let plain_name = || decode_plain_name(raw_name);
+    let name = match names {
+        NameFormat::Plain => plain_name(),
+        NameFormat::Joliet => decode_joliet_name(raw_name),
+        NameFormat::RockRidge { skip } => {
+            let system_use_start = 33 + name_len + (1 - name_len % 2);
+            record
+                .get(system_use_start + skip..)
+                .and_then(rock_ridge_name)
+                .unwrap_or_else(plain_name)
+        }
+    };
+
+    Some(DirRecord {
+        extent: u32::from_le_bytes(record[2..6].try_into().ok()?),
+        size: u32::from_le_bytes(record[10..14].try_into().ok()?),
+        is_dir: record[25] & DIRECTORY_FLAG != 0,
+        name,
+    })
+}
+
+fn decode_plain_name(raw_name: &[u8]) -> String {
+    let name = String::from_utf8_lossy(raw_name);
+    let name = name.split(';').next().unwrap_or_default();
+
+    name.strip_suffix('.').unwrap_or(name).to_string()
+}
+
+fn decode_joliet_name(raw_name: &[u8]) -> String {
+    let units: Vec<u16> = raw_name
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    let name = String::from_utf16_lossy(&units);
+
+    name.split(';').next().unwrap_or_default().to_string()
+}
+
+/// Iterates over the System Use Sharing Protocol entries of a record.
+fn system_use_entries(area: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let header = area.get(offset..offset + 4)?;
+        let len = header[2] as usize;
+        if len < 4 {
+            return None;
+        }
+        let entry = area.get(offset..offset + len)?;
+        offset += len;
+
+        Some((&entry[0..2], &entry[4..]))
+    })
+}
+
+fn rock_ridge_name(system_use: &[u8]) -> Option<String> {
+    let mut name = Vec::new();
+    for (signature, data) in system_use_entries(system_use) {
+        if signature == b"NM" && !data.is_empty() {
+            // The first byte holds flags, bit 0 means the name continues in the next entry
+            name.extend_from_slice(&data[1..]);
+            if data[0] & 0x01 == 0 {
+                break;
+            }
+        }
+    }
+
+    (!name.is_empty()).then(|| String::from_utf8_lossy(&name).to_string())
+}
+
+/// Detects Rock Ridge from the `SP` entry of the root directory's `.` record,
+/// returning the number of bytes to skip in each record's system use area.
+fn rock_ridge_skip(file: &mut File, root: &DirRecord) -> io::Result<Option<usize>> {
+    let sector = read_sector(file, u64::from(root.extent))?;
+    let len = sector[0] as usize;
+    let name_len = sector[32] as usize;
+    let system_use_start = 33 + name_len + (1 - name_len % 2);
+    let system_use = sector.get(system_use_start..len).unwrap_or_default();
+
+    let skip = system_use_entries(system_use)
+        .find(|(signature, data)| *signature == b"SP" && data.starts_with(&[0xBE, 0xEF]))
+        .and_then(|(_, data)| data.get(2).map(|skip| *skip as usize));
+
+    Ok(skip)
+}
+
+/// Whether `path` is an existing file with the `.iso` extension.
+pub fn is_iso_image(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case(ISO_EXTENSION))
+        .unwrap_or(false)
+        && path.is_file()
+}
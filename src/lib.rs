@@ -5,10 +5,78 @@ extern crate phf;
 #[macro_use]
 extern crate clap;
 
+#[cfg(target_os = "linux")]
+pub mod activation;
+pub mod analytics;
+pub mod api;
+pub mod assets;
+pub mod audit;
+pub mod bench;
+pub mod chainload;
 pub mod conf;
+pub mod daemon;
+pub mod control;
+pub mod dashboard;
 pub mod dhcp;
+pub mod distro;
+pub mod doctor;
+pub mod dns;
+pub mod events;
+pub mod fetch;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod handover;
+pub mod health;
+pub mod hook;
+pub mod hosts;
+pub mod http;
+pub mod images;
+pub mod init;
+pub mod locate;
+pub mod instance;
+pub mod inventory;
+pub mod iso;
+pub mod logging;
+pub mod migrate;
+pub mod mirror;
+pub mod mqtt;
+pub mod netbootxyz;
+pub mod notifications;
+pub mod notify;
+pub mod oneshot;
+pub mod overrides;
+pub mod pcap;
+pub mod pool;
+pub mod privileges;
+pub mod probe;
+pub mod quota;
+pub mod readahead;
+pub mod secureboot;
+pub mod signing;
+pub mod snmp;
+pub mod reload;
+pub mod sandbox;
+pub mod selftest;
+pub mod remote;
+pub mod runtime;
+pub mod schema;
+pub mod stats;
+pub mod status;
+pub mod syslog;
+pub mod template;
+pub mod test_match;
 pub mod tftp;
+pub mod tftp_get;
+pub mod timeline;
+pub mod tls;
+pub mod tracker;
+pub mod traffic;
+pub mod upstream;
 pub mod util;
+pub mod watch;
+pub mod webhook;
+#[cfg(windows)]
+pub mod winservice;
 pub mod cli;
 
 pub type Result<T> = anyhow::Result<T, anyhow::Error>;
@@ -0,0 +1,209 @@
+//! Finding the configuration: the file or URL of `--config`, else of
+//! `PO_CONF_PATH`, else the first of `CONFIG_FILENAMES` in the default
+//! location, falling back to the values of `--set` without a file, or else
+//! to the environment variables. The places looked at are kept with why
+//! each was passed over, printed by `which-config`.
+use std::path::PathBuf;
+
+use log::info;
+
+use crate::{
+    conf::{Conf, ENV_VAR_PREFIX},
+    overrides::Overrides,
+    remote::ConfSource,
+    util::redact_url,
+    Result,
+};
+
+/// A place the configuration was looked for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Probe {
+    /// The option, variable, file or URL.
+    pub place: String,
+    /// Why it was passed over, `None` for the one loaded.
+    pub rejected: Option<String>,
+}
+
+impl Probe {
+    fn loaded(place: impl Into<String>) -> Self {
+        Self {
+            place: place.into(),
+            rejected: None,
+        }
+    }
+
+    fn rejected(place: impl Into<String>, why: impl Into<String>) -> Self {
+        Self {
+            place: place.into(),
+            rejected: Some(why.into()),
+        }
+    }
+}
+
+/// The configuration found, with the places looked at on the way.
+pub struct Search {
+    pub probes: Vec<Probe>,
+    /// Source watched for changes, the default file when none was given nor
+    /// found, so it's loaded once created.
+    pub source: ConfSource,
+    /// The configuration, with where it was loaded from.
+    pub loaded: Result<(Conf, String)>,
+}
+
+/// Looks for the configuration, given by `--config` as `config` or by
+/// `PO_CONF_PATH` as `conf_path`, the default files being left out in a
+/// `container`, the values of `overrides` applied to it.
+pub fn search(config: Option<&str>, conf_path: Option<&str>, container: bool, overrides: &Overrides) -> Search {
+    let var = format!("{ENV_VAR_PREFIX}CONF_PATH");
+    let candidates = Conf::config_candidates();
+    let mut probes = Vec::new();
+    let mut given = None;
+    match config {
+        Some(config) => given = Some(load_given(&mut probes, format!("--config {}", redact_url(config)), config, overrides)),
+        None => probes.push(Probe::rejected("--config", "not given")),
+    }
+    match conf_path {
+        Some(conf_path) if given.is_some() => {
+            probes.push(Probe::rejected(format!("{var}={}", redact_url(conf_path)), "overridden by --config"))
+        }
+        Some(conf_path) => {
+            given = Some(load_given(&mut probes, format!("{var}={}", redact_url(conf_path)), conf_path, overrides))
+        }
+        None => probes.push(Probe::rejected(var, "not set")),
+    }
+
+    let default_dir = candidates[0].parent().map(PathBuf::from).unwrap_or_default();
+    let (source, conf, is_file_missing) = match given {
+        Some(Err(e)) => {
+            return Search {
+                probes,
+                source: ConfSource::File(candidates[0].clone()),
+                loaded: Err(e),
+            }
+        }
+        Some(Ok((source, conf))) => {
+            probes.push(Probe::rejected(default_dir.display().to_string(), "not looked in, a configuration is given"));
+            let is_file_missing = matches!(&source, ConfSource::File(path) if !path.exists());
+            (source, conf, is_file_missing)
+        }
+        // A container is configured by its environment, not a file of the image
+        None if container => {
+            probes.push(Probe::rejected(default_dir.display().to_string(), "not looked in, in container mode"));
+            (ConfSource::File(candidates[0].clone()), None, true)
+        }
+        None => load_default(&mut probes, &candidates, overrides),
+    };
+    if let Some(conf) = conf {
+        let origin = source.to_string();
+        return Search {
+            probes,
+            source,
+            loaded: Ok((conf, origin)),
+        };
+    }
+
+    // Without a file, the values of --set are a configuration of their own
+    let loaded = match is_file_missing && !overrides.is_empty() {
+        true => {
+            info!("No configuration file, using the values of --set.");
+            let loaded = Conf::from_overrides(overrides).map(|conf| (conf, "--set".to_string()));
+            probes.push(outcome("--set", &loaded));
+            loaded
+        }
+        false => {
+            let why = match overrides.is_empty() {
+                true => "no values given",
+                false => "only used without a configuration file",
+            };
+            probes.push(Probe::rejected("--set", why));
+            info!("Falling back to environment variables.");
+            let loaded = Conf::from_env().map(|conf| (conf, "environment variables".to_string()));
+            probes.push(outcome("environment variables", &loaded));
+            loaded
+        }
+    };
+
+    Search {
+        probes,
+        source,
+        loaded,
+    }
+}
+
+/// The probe of `place`, loaded unless `loaded` failed.
+fn outcome<T>(place: &str, loaded: &Result<T>) -> Probe {
+    match loaded {
+        Ok(_) => Probe::loaded(place),
+        Err(e) => Probe::rejected(place, format!("{e:#}")),
+    }
+}
+
+/// Loads the configuration of `value`, the file or URL given at `place`,
+/// failing only when it's neither.
+fn load_given(
+    probes: &mut Vec<Probe>,
+    place: String,
+    value: &str,
+    overrides: &Overrides,
+) -> Result<(ConfSource, Option<Conf>)> {
+    let source = match value.parse::<ConfSource>() {
+        Ok(source) => source,
+        Err(e) => {
+            probes.push(Probe::rejected(place, format!("{e:#}")));
+            return Err(e);
+        }
+    };
+    let conf = load(probes, place, &source, overrides);
+    Ok((source, conf))
+}
+
+/// Loads the first of the default files found, telling whether there's none.
+fn load_default(
+    probes: &mut Vec<Probe>,
+    candidates: &[PathBuf],
+    overrides: &Overrides,
+) -> (ConfSource, Option<Conf>, bool) {
+    let mut found: Option<(ConfSource, Option<Conf>)> = None;
+    for path in candidates {
+        let place = path.display().to_string();
+        match &found {
+            Some((first, _)) => probes.push(Probe::rejected(place, format!("not looked at, {first} comes first"))),
+            None if !path.exists() => probes.push(Probe::rejected(place, "not found")),
+            None if !path.is_file() => probes.push(Probe::rejected(place, "not a file")),
+            None => {
+                let source = ConfSource::File(path.clone());
+                let conf = load(probes, place, &source, overrides);
+                found = Some((source, conf));
+            }
+        }
+    }
+    match found {
+        Some((source, conf)) => (source, conf, false),
+        None => (ConfSource::File(candidates[0].clone()), None, true),
+    }
+}
+
+fn load(probes: &mut Vec<Probe>, place: String, source: &ConfSource, overrides: &Overrides) -> Option<Conf> {
+    let loaded = Conf::from_source_with(source, overrides);
+    if let Err(e) = &loaded {
+        info!("Not loading configuration file: {e}");
+    }
+    probes.push(outcome(&place, &loaded));
+    loaded.ok()
+}
+
+/// The places looked at, in order, and where the configuration was loaded
+/// from.
+pub fn report(search: &Search) -> String {
+    let mut report = String::from("Configuration looked for, in order:\n");
+    for probe in &search.probes {
+        let outcome = probe.rejected.as_deref().unwrap_or("loaded");
+        report.push_str(&format!("  {}: {outcome}\n", probe.place));
+    }
+    match &search.loaded {
+        Ok((_, origin)) => report.push_str(&format!("Loaded from {origin}.\n")),
+        Err(e) => report.push_str(&format!("Not loaded: {e:#}\n")),
+    }
+
+    report
+}
@@ -0,0 +1,373 @@
+//! Logger of the service: env_logger, with the level raised for the clients
+//! of the entries setting `log_level`, so one machine can be debugged in
+//! detail without the messages of the others. The level applies to the task
+//! handling the client, from the time its configuration is known, and to
+//! those handling it next, by its MAC or IP address, for a while. With
+//! `--log-format json`, or `--container`, the messages are written to stdout
+//! as lines of JSON, with the client handled by the task, for log collectors
+//! to filter them by client. Once the configuration is loaded, `log_output`
+//! can send them to syslog or journald instead. The messages about a client
+//! carry the ID of its session, derived from its MAC address and the XID of
+//! its DHCP handshake, in those of DHCP, TFTP and HTTP alike, so one boot can
+//! be followed with a grep.
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    future::Future,
+    net::{IpAddr, Ipv4Addr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_std::task_local;
+use env_logger::fmt::Color;
+use log::{LevelFilter, Log, Metadata, Record};
+use once_cell::sync::{Lazy, OnceCell};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    conf::MacAddress,
+    syslog::{Emitter, LogOutput, SyslogServer},
+    util::bytes_to_mac_address,
+    Result,
+};
+
+/// How long the level of a client is kept after its last DHCP message.
+const CLIENT_LEVEL_TTL: Duration = Duration::from_secs(600);
+/// Prefix of the targets of the messages of this crate, the only ones
+/// raised, those of the dependencies staying at the level of the service.
+const TARGET_PREFIX: &str = env!("CARGO_CRATE_NAME");
+
+/// How the messages are written.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum LogFormat {
+    /// The text of env_logger, on stderr.
+    #[default]
+    Text,
+    /// A line of JSON a message, on stdout, for the log collectors of the
+    /// container runtimes.
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Client {
+    Mac(MacAddress),
+    Ip(IpAddr),
+}
+
+/// The system log the messages are written to, in place of env_logger.
+static OUTPUT: OnceCell<Emitter> = OnceCell::new();
+
+static CLIENT_LEVELS: Lazy<Mutex<HashMap<Client, (Instant, LevelFilter)>>> =
+    Lazy::new(Default::default);
+
+/// The client handled by a task, as much as is known of it, for the lines
+/// of JSON.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TaskClient {
+    pub mac: Option<MacAddress>,
+    /// Transaction ID of the DHCP message handled.
+    pub xid: Option<u32>,
+    pub ip: Option<IpAddr>,
+}
+
+impl TaskClient {
+    /// The correlation ID of the session of the client, once its MAC
+    /// address and XID are known.
+    pub fn session_id(&self) -> Option<String> {
+        Some(session_id(&self.mac?, self.xid?))
+    }
+}
+
+/// The correlation ID of the DHCP session `xid` of the client of `mac`, 8
+/// hexadecimal digits, the same for the servers taking over.
+pub fn session_id(mac: &MacAddress, xid: u32) -> String {
+    // FNV-1a
+    let hash = mac.iter().chain(&xid.to_be_bytes()).fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x01000193)
+    });
+    format!("{hash:08x}")
+}
+
+task_local! {
+    static TASK_LEVEL: Cell<LevelFilter> = Cell::new(LevelFilter::Off);
+    static TASK_CLIENT: Cell<TaskClient> = Cell::new(TaskClient::default());
+}
+
+thread_local! {
+    /// The client of the future being polled, for those run outside of the
+    /// tasks of async-std, as the TFTP transfers are.
+    static POLLED_CLIENT: Cell<Option<TaskClient>> = const { Cell::new(None) };
+}
+
+struct ClientLogger {
+    /// Filter of the service, `RUST_LOG` or the level it's started with.
+    filter: env_logger::filter::Filter,
+    /// Writes the messages, any level.
+    logger: env_logger::Logger,
+}
+
+impl ClientLogger {
+    fn is_raised(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with(TARGET_PREFIX) && metadata.level() <= task_level()
+    }
+}
+
+impl Log for ClientLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata) || self.is_raised(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) && !self.is_raised(record.metadata()) {
+            return;
+        }
+        // The messages the system log fails to take aren't lost
+        let is_emitted = OUTPUT
+            .get()
+            .is_some_and(|output| output.emit(OffsetDateTime::now_utc(), record, &task_client()).is_ok());
+        if !is_emitted {
+            self.logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.logger.flush();
+    }
+}
+
+/// Installs the logger, with `RUST_LOG` as filter when set, else `level`.
+pub fn init(level: &str, format: LogFormat) -> Result<()> {
+    use std::io::Write;
+
+    let spec = std::env::var(env_logger::DEFAULT_FILTER_ENV).unwrap_or(level.to_string());
+    let filter = env_logger::filter::Builder::new().parse(&spec).build();
+    let max_level = filter.filter();
+    let mut builder = env_logger::Builder::from_env(
+        env_logger::Env::new().write_style(env_logger::DEFAULT_WRITE_STYLE_ENV),
+    );
+    builder.filter_level(LevelFilter::Trace);
+    match format {
+        LogFormat::Json => {
+            builder.target(env_logger::Target::Stdout).format(|buf, record| {
+                writeln!(buf, "{}", json_line(OffsetDateTime::now_utc(), record, &task_client()))
+            });
+        }
+        // The header of env_logger, with the session
+        LogFormat::Text => {
+            builder.format(|buf, record| {
+                let mut subtle = buf.style();
+                subtle.set_color(Color::Black).set_intense(true);
+                let level = buf.default_styled_level(record.level());
+                let timestamp = buf.timestamp();
+                write!(buf, "{}{timestamp} {level:<5} {}", subtle.value("["), record.target())?;
+                if let Some(session) = task_client().session_id() {
+                    write!(buf, " session={session}")?;
+                }
+                writeln!(buf, "{} {}", subtle.value("]"), record.args())
+            });
+        }
+    }
+    let logger = builder.build();
+
+    log::set_boxed_logger(Box::new(ClientLogger { filter, logger }))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// The line of JSON of `record`, logged at `time` by the task handling
+/// `client`, with the fields `time`, `level`, `target`, `subsystem` and
+/// `message`, then `mac`, `xid`, `session` and `client_ip` when known.
+pub fn json_line(time: OffsetDateTime, record: &Record, client: &TaskClient) -> String {
+    let mut line = serde_json::json!({
+        "time": time.format(&Rfc3339).unwrap_or_default(),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "subsystem": subsystem(record.target()),
+        "message": record.args().to_string(),
+    });
+    let fields = [
+        ("mac", client.mac.map(|mac| bytes_to_mac_address(&mac))),
+        ("xid", client.xid.map(|xid| format!("{xid:#010x}"))),
+        ("session", client.session_id()),
+        ("client_ip", client.ip.map(|ip| ip.to_string())),
+    ];
+    for (name, value) in fields {
+        if let Some(value) = value {
+            line[name] = value.into();
+        }
+    }
+    line.to_string()
+}
+
+/// The module of this crate a message is from, `dhcp` for
+/// `preboot_oxide::dhcp::...`, or the crate for those of the dependencies.
+pub(crate) fn subsystem(target: &str) -> &str {
+    let target = target
+        .strip_prefix(TARGET_PREFIX)
+        .and_then(|module| module.strip_prefix("::"))
+        .unwrap_or(target);
+    target.split("::").next().unwrap_or(target)
+}
+
+/// Writes the messages to `output` from now on, once the configuration is
+/// loaded, those of before staying on stderr. Only the first call applies.
+pub fn set_output(output: LogOutput, server: Option<SyslogServer>) -> Result<()> {
+    if let Some(emitter) = Emitter::open(output, server)? {
+        let _ = OUTPUT.set(emitter);
+    }
+    Ok(())
+}
+
+/// Sets the level of the client of `mac`, and of `ip` when known, for the
+/// current task and the next ones handling it. `None` clears it.
+pub fn set_client_level(mac: MacAddress, ip: Option<Ipv4Addr>, level: Option<LevelFilter>) {
+    let ip = ip.filter(|ip| !ip.is_unspecified());
+    let keys = [Some(Client::Mac(mac)), ip.map(|ip| Client::Ip(ip.into()))];
+    let mut levels = CLIENT_LEVELS.lock().unwrap_or_else(|e| e.into_inner());
+    levels.retain(|_, (time, _)| time.elapsed() < CLIENT_LEVEL_TTL);
+    for key in keys.into_iter().flatten() {
+        match level {
+            Some(level) => levels.insert(key, (Instant::now(), level)),
+            None => levels.remove(&key),
+        };
+    }
+    drop(levels);
+
+    let level = level.unwrap_or(LevelFilter::Off);
+    if level > log::max_level() {
+        log::set_max_level(level);
+    }
+    let _ = TASK_LEVEL.try_with(|task_level| task_level.set(level));
+}
+
+/// Applies the level set for the client of `mac` to the current task, its
+/// messages then tagged with `mac`.
+pub fn enter_mac(mac: MacAddress) {
+    update_task_client(|client| client.mac = Some(mac));
+    enter(Client::Mac(mac));
+}
+
+/// Tags the messages of the current task with the DHCP transaction `xid`.
+pub fn enter_xid(xid: u32) {
+    update_task_client(|client| client.xid = Some(xid));
+}
+
+/// Applies the level set for the client at `ip` to the current task, its
+/// messages then tagged with `ip`.
+pub fn enter_ip(ip: IpAddr) {
+    update_task_client(|client| client.ip = Some(ip));
+    enter(Client::Ip(ip));
+}
+
+fn update_task_client(update: impl FnOnce(&mut TaskClient)) {
+    let _ = TASK_CLIENT.try_with(|task_client| {
+        let mut client = task_client.get();
+        update(&mut client);
+        task_client.set(client);
+    });
+}
+
+fn enter(client: Client) {
+    let levels = CLIENT_LEVELS.lock().unwrap_or_else(|e| e.into_inner());
+    let level = levels
+        .get(&client)
+        .filter(|(time, _)| time.elapsed() < CLIENT_LEVEL_TTL)
+        .map(|(_, level)| *level);
+    drop(levels);
+    if let Some(level) = level {
+        let _ = TASK_LEVEL.try_with(|task_level| task_level.set(level));
+    }
+}
+
+/// Level of the client handled by the current task, `Off` when none is set.
+pub fn task_level() -> LevelFilter {
+    TASK_LEVEL.try_with(Cell::get).unwrap_or(LevelFilter::Off)
+}
+
+/// The client handled by the current task, or of the future polled by
+/// [`with_client`], empty outside of tasks.
+pub fn task_client() -> TaskClient {
+    POLLED_CLIENT
+        .get()
+        .unwrap_or_else(|| TASK_CLIENT.try_with(Cell::get).unwrap_or_default())
+}
+
+/// Runs `f` with its messages about `client`, whatever the task.
+pub fn scoped<T>(client: TaskClient, f: impl FnOnce() -> T) -> T {
+    let previous = POLLED_CLIENT.replace(Some(client));
+    let output = f();
+    POLLED_CLIENT.set(previous);
+    output
+}
+
+/// Awaits `future` with its messages about `client`, for the futures polled
+/// by other executors than async-std.
+pub async fn with_client<F: Future>(client: TaskClient, future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    std::future::poll_fn(|cx| scoped(client, || future.as_mut().poll(cx))).await
+}
+
+/// The DHCP messages ignored, the regular DHCP traffic of busy networks,
+/// told once every `CHATTER_INTERVAL` rather than each, for the debug
+/// messages to stay readable.
+#[derive(Debug, Default)]
+pub struct Chatter {
+    /// When the first message counted was ignored.
+    since: Option<Instant>,
+    messages: u64,
+    /// The clients they're from, up to `MAX_CHATTER_CLIENTS`.
+    clients: HashSet<MacAddress>,
+}
+
+/// Time the ignored DHCP messages are added up for before they're told.
+pub const CHATTER_INTERVAL: Duration = Duration::from_secs(60);
+/// Clients told apart among the ignored messages, the others only counted
+/// as messages.
+const MAX_CHATTER_CLIENTS: usize = 10_000;
+
+impl Chatter {
+    /// Counts a message ignored at `now`, from `client` when known. Returns
+    /// the summary of the messages counted once `CHATTER_INTERVAL` passed
+    /// since the first, the counting starting over.
+    pub fn ignored(&mut self, client: Option<MacAddress>, now: Instant) -> Option<String> {
+        let since = *self.since.get_or_insert(now);
+        self.messages += 1;
+        if let Some(client) = client.filter(|_| self.clients.len() < MAX_CHATTER_CLIENTS) {
+            self.clients.insert(client);
+        }
+        match now.duration_since(since) >= CHATTER_INTERVAL {
+            true => self.summary(now),
+            false => None,
+        }
+    }
+
+    /// The summary of the messages counted, when `CHATTER_INTERVAL` passed
+    /// since the first, for those of the networks gone quiet to be told.
+    pub fn flush(&mut self, now: Instant) -> Option<String> {
+        let since = self.since?;
+        match now.duration_since(since) >= CHATTER_INTERVAL {
+            true => self.summary(now),
+            false => None,
+        }
+    }
+
+    fn summary(&mut self, now: Instant) -> Option<String> {
+        let since = self.since.take()?;
+        let messages = std::mem::take(&mut self.messages);
+        let clients = std::mem::take(&mut self.clients).len();
+        let clients = match clients {
+            MAX_CHATTER_CLIENTS => format!("{clients} or more clients"),
+            1 => "1 client".to_string(),
+            _ => format!("{clients} clients"),
+        };
+        let messages = match messages {
+            1 => "1 non-PXE DHCP message".to_string(),
+            _ => format!("{messages} non-PXE DHCP messages"),
+        };
+        Some(format!(
+            "Ignored {messages} from {clients} in the last {}s.",
+            now.duration_since(since).as_secs()
+        ))
+    }
+}
@@ -1,53 +1,529 @@
 #[macro_use]
 extern crate anyhow;
 
-use std::env;
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
 
 use anyhow::Context;
 use async_std::task;
-use log::{debug, info};
-use single_instance::SingleInstance;
+use futures::future;
+use log::{debug, info, warn};
 
+#[cfg(target_os = "linux")]
+use preboot_oxide::activation;
+#[cfg(feature = "grpc")]
+use preboot_oxide::grpc;
+#[cfg(windows)]
+use preboot_oxide::winservice;
 use preboot_oxide::{
-    cli,
-    conf::{Conf, ProcessEnvConf, ENV_VAR_PREFIX},
-    dhcp,
+    analytics, api,
+    assets::{self, AssetDb},
+    audit,
+    bench::{self, BenchSettings},
+    cli::{self, Command},
+    conf::{Conf, ENV_VAR_PREFIX},
+    control::{self, Control, ControlledService},
+    daemon::{self, DaemonSettings},
+    dashboard,
+    dhcp::{self, SessionInfo, SessionMap},
+    dns::spawn_dns_service_async,
+    doctor,
+    handover::{self, Handover},
+    health::{self, LIVENESS_INTERVAL},
+    http::spawn_http_service_async,
+    images::spawn_image_service_async,
+    init,
+    instance::SingleInstance,
+    locate,
+    logging::{self, LogFormat},
+    migrate,
+    netbootxyz,
+    notify::Notifier,
+    oneshot::{Oneshot, Target},
+    overrides::Overrides,
+    pcap,
+    privileges,
+    probe,
+    tftp_get,
+    reload::{self, Services},
+    sandbox,
+    selftest,
+    remote::ConfSource,
+    runtime,
+    snmp,
+    stats::{self, BootStats},
+    status,
+    test_match,
     tftp::spawn_tftp_service_async,
+    timeline,
+    tracker::BootTracker,
+    traffic,
+    watch,
     Result,
 };
 
+const INSTANCE_NAME: &str = "preboot-oxide";
+
 fn main() -> Result<()> {
-    let instance = SingleInstance::new("preboot-oxide")?;
-    if !instance.is_single() {
-        return Err(anyhow!("Another instance is already running"));
+    let cli = cli::parse();
+    let command = cli.command();
+    match &command {
+        Command::Init { path } => return init::run_interactive(path.clone()),
+        Command::Migrate { path } => return migrate_config(path.clone()),
+        Command::Generate { what } => return cli::generate(*what, &mut std::io::stdout()),
+        _ => {}
     }
-    let mut dot_env_path = env::current_exe().unwrap_or_default();
-    dot_env_path.set_file_name(".env");
 
-    let _ = dotenv::from_path(dot_env_path);
+    // A container is configured by its environment, not a file of the image
+    if !cli.container {
+        let mut dot_env_path = env::current_exe().unwrap_or_default();
+        dot_env_path.set_file_name(".env");
+
+        let _ = dotenv::from_path(dot_env_path);
+    }
 
-    let arg_log_level = cli::parse();
-    let log_level = arg_log_level
+    let log_level = cli
+        .log_level()
         .or(env::var(format!("{ENV_VAR_PREFIX}LOG_LEVEL")).ok())
-        .unwrap_or("error".into());
-
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level))
-        .init();
-
-    let conf_path = env::var(format!("{ENV_VAR_PREFIX}CONF_PATH"))
-        .map(std::path::PathBuf::from)
-        .ok();
-    let server_config = Conf::from_yaml_config(conf_path.as_ref())
-        .unwrap_or_else(|e| {
-            info!("Not loading YAML configuration: {}\nFalling back to environment variables.", e.to_string());
-            Conf::from(ProcessEnvConf::from_process_env())
-        });
+        // The warnings are what `test` is run for
+        .unwrap_or(if command == Command::Test { "warn" } else { "error" }.into());
+
+    let log_format = match cli.container {
+        true => LogFormat::Json,
+        false => match cli.log_format {
+            Some(format) => format,
+            None => env::var(format!("{ENV_VAR_PREFIX}LOG_FORMAT"))
+                .ok()
+                .map(|format| <LogFormat as clap::ValueEnum>::from_str(&format, true))
+                .transpose()
+                .map_err(|e| anyhow!("{ENV_VAR_PREFIX}LOG_FORMAT: {e}"))?
+                .unwrap_or_default(),
+        },
+    };
+    logging::init(&log_level, log_format)?;
+    // These don't need a configuration
+    match &command {
+        Command::Probe { iface, timeout } => {
+            return probe_network(iface, Duration::from_secs(*timeout))
+        }
+        Command::Bench { clients, iface, file, server, timeout } => {
+            let server = server.as_deref().map(|server| tftp_get::server_addr(Some(server)));
+            let settings = BenchSettings {
+                clients: *clients,
+                iface: iface.clone(),
+                file: file.clone(),
+                server: server.transpose()?,
+                timeout: Duration::from_secs(*timeout),
+            };
+            let start = Instant::now();
+            let results = task::block_on(bench::run(&settings))?;
+            print!("{}", bench::report(&results, start.elapsed()));
+            return Ok(());
+        }
+        Command::TftpGet { path, server, output } => {
+            let server = tftp_get::server_addr(server.as_deref())?;
+            let download = task::block_on(tftp_get::download(server, path, output.as_deref()))?;
+            print!("{}", tftp_get::report(&download));
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let conf_path = env::var(format!("{ENV_VAR_PREFIX}CONF_PATH")).ok();
+    let overrides = Overrides::parse(&cli.set)?;
+    let search = locate::search(cli.config.as_deref(), conf_path.as_deref(), cli.container, &overrides);
+    if command == Command::WhichConfig {
+        print!("{}", locate::report(&search));
+        return search.loaded.map(|_| ());
+    }
+    let conf_source = search.source;
+    let (server_config, origin) = search.loaded?;
+    let server_config = server_config.with_instance_name(cli.instance_name.clone());
+
+    match command {
+        Command::Config => {
+            print!("{}", server_config.to_yaml()?);
+            server_config.validate()
+        }
+        Command::Test => {
+            server_config.validate()?;
+            println!("The configuration of {origin} is valid.");
+            Ok(())
+        }
+        Command::Status => {
+            let running = !SingleInstance::new(&instance_lock(&server_config))?.is_single();
+            print!("{}", status::report(&server_config, &origin, running));
+            if running {
+                match status::ask(&server_config.get_control_socket()) {
+                    Ok(health) => print!("{}", status::health_report(&health)),
+                    Err(e) => println!("Running server: not answering, {e:#}"),
+                }
+            }
+            Ok(())
+        }
+        Command::Doctor => {
+            let checks = doctor::run(&server_config);
+            print!("{}", doctor::report(&checks));
+            match checks.iter().any(doctor::Check::is_problem) {
+                true => Err(anyhow!("The host has problems keeping clients from booting")),
+                false => Ok(()),
+            }
+        }
+        Command::SelfTest { timeout } => {
+            let checks = selftest::run(&server_config, Duration::from_secs(timeout));
+            print!("{}", doctor::report(&checks));
+            match checks.iter().any(doctor::Check::is_problem) {
+                true => Err(anyhow!("The self-test failed")),
+                false => Ok(()),
+            }
+        }
+        Command::Reload => {
+            control::request(&server_config.get_control_socket(), "reload")?;
+            println!("Configuration reloaded.");
+            Ok(())
+        }
+        Command::Sessions => {
+            let answer = control::request(&server_config.get_control_socket(), "sessions")?;
+            let sessions: Vec<SessionInfo> = serde_json::from_value(answer)?;
+            print!("{}", control::sessions_report(&sessions));
+            Ok(())
+        }
+        Command::Watch { mac, json } => {
+            watch::run(&server_config.get_control_socket(), mac.as_deref(), json)
+        }
+        Command::Assets { csv } => {
+            let assets = assets::ask(&server_config.get_control_socket())?;
+            print!("{}", assets::report(&assets, csv));
+            Ok(())
+        }
+        Command::Transfers { top, json } => {
+            let state = analytics::ask(&server_config.get_control_socket())?;
+            match json {
+                true => println!("{}", serde_json::to_string_pretty(&state)?),
+                false => print!("{}", analytics::report(&state, top)),
+            }
+            Ok(())
+        }
+        Command::Timeline { mac, json } => {
+            let path = server_config.get_event_log().ok_or(anyhow!(
+                "No event_log in the configuration, the events of the clients aren't recorded"
+            ))?;
+            let entries = timeline::read(path, server_config.get_event_log_files(), &mac)?;
+            match json {
+                true => entries.iter().for_each(|entry| println!("{}", entry.to_json())),
+                false => print!("{}", timeline::report(&mac, &entries)),
+            }
+            Ok(())
+        }
+        Command::Stats { since } => {
+            let state = stats::ask(&server_config.get_control_socket())?;
+            let now = SystemTime::now();
+            let since = since.map(|since| now - since);
+            print!("{}", stats::report(&state, since, now));
+            Ok(())
+        }
+        Command::TestMatch { client, fields } => {
+            let doc = test_match::client_doc(&client, &fields)?;
+            let report = test_match::report(&server_config, &doc, SystemTime::now())?;
+            print!("{report}");
+            Ok(())
+        }
+        Command::Serve => {
+            let daemon = cli.daemon_settings();
+            let modes = Modes {
+                container: cli.container,
+                oneshot: cli.oneshot.clone(),
+                takeover: cli.takeover,
+            };
+            serve(server_config, origin, conf_source, overrides, &daemon, modes)
+        }
+        #[cfg(windows)]
+        Command::Service => {
+            let daemon = cli.daemon_settings();
+            winservice::run(move || serve(server_config, origin, conf_source, overrides, &daemon, Modes::default()))
+        }
+        Command::Init { .. }
+        | Command::Migrate { .. }
+        | Command::Generate { .. }
+        | Command::WhichConfig
+        | Command::Probe { .. }
+        | Command::Bench { .. }
+        | Command::TftpGet { .. } => unreachable!(),
+    }
+}
+
+/// Name of the lock held by the running server, of its instance.
+fn instance_lock(conf: &Conf) -> String {
+    match conf.get_instance_name() {
+        Some(name) => format!("{INSTANCE_NAME}-{name}"),
+        None => INSTANCE_NAME.to_string(),
+    }
+}
+
+/// Takes the instance lock of `conf`, waiting up to `wait` for the server
+/// holding it to release it.
+fn single_instance(conf: &Conf, wait: Duration) -> Result<SingleInstance> {
+    let deadline = Instant::now() + wait;
+    loop {
+        let instance = SingleInstance::new(&instance_lock(conf))?;
+        if instance.is_single() {
+            return Ok(instance);
+        }
+        if Instant::now() >= deadline {
+            return Err(match conf.get_instance_name() {
+                Some(name) => anyhow!("Instance {name} is already running"),
+                None => anyhow!("Another instance is already running"),
+            });
+        }
+        drop(instance);
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// How the server runs, by the options of the command line.
+#[derive(Default)]
+struct Modes {
+    /// In a container, which runs one server of its own.
+    container: bool,
+    oneshot: Option<Target>,
+    /// Taking over the running server.
+    takeover: bool,
+}
+
+/// Runs the services until the DHCP service fails, or forever without it,
+/// until the clients of `oneshot` are served, or until taken over. In a
+/// container the instance lock is left out.
+fn serve(
+    server_config: Conf,
+    origin: String,
+    conf_source: ConfSource,
+    overrides: Overrides,
+    daemon: &DaemonSettings,
+    modes: Modes,
+) -> Result<()> {
     server_config.validate()?;
-    spawn_tftp_service_async(&server_config)?;
+    logging::set_output(server_config.get_log_output(), server_config.get_syslog_server())?;
+    pcap::start(&server_config)?;
+    audit::start(&server_config)?;
+    traffic::start(&server_config)?;
+    timeline::start(&server_config)?;
 
-    let result: Result<()> =
-        task::block_on(dhcp::server_loop(server_config)).context("Starting DHCP service");
+    let mut inherited = match modes.takeover {
+        true => Some(handover::take_over(&server_config.get_control_socket()).context("Taking over the running server")?),
+        false => None,
+    };
+    // Released by the server taken over once it has handed over
+    let lock_wait = match inherited {
+        Some(_) => handover::LOCK_TIMEOUT,
+        None => Duration::ZERO,
+    };
+    let instance = match modes.container {
+        true => None,
+        false => Some(single_instance(&server_config, lock_wait)?),
+    };
+    #[cfg(target_os = "linux")]
+    activation::init()?;
+    daemon::start(daemon)?;
+    if server_config.get_sandbox() {
+        let conf_file = match &conf_source {
+            ConfSource::File(path) => Some(path.as_path()),
+            _ => None,
+        };
+        sandbox::apply(&sandbox::access(&server_config, conf_file, daemon.pid_file.as_deref()))?;
+    }
+    daemon::exit_on_signal(daemon);
+    let notifier = match server_config.get_health_addr() {
+        Some(_) => Notifier::from_env().with_liveness_interval(LIVENESS_INTERVAL),
+        None => Notifier::from_env(),
+    };
+    let notifier = Arc::new(notifier);
+    let oneshot = modes.oneshot.map(|target| {
+        info!("Exiting once boot files are served to {target}, for --oneshot.");
+        Arc::new(Oneshot::new(target))
+    });
+    let mut tracker = BootTracker::new(server_config.get_max_sessions());
+    if let Some(oneshot) = &oneshot {
+        tracker = tracker.with_oneshot(Arc::clone(oneshot));
+    }
+    if let Some(path) = server_config.get_stats_file() {
+        tracker = tracker.with_stats(BootStats::load(path)?);
+    }
+    if let Some(path) = server_config.get_assets_file() {
+        tracker = tracker.with_assets(AssetDb::load(path)?);
+    }
+    let tracker = Arc::new(tracker);
+    if let Some(path) = server_config.get_stats_file() {
+        stats::spawn_saving(Arc::clone(&tracker), path.clone());
+    }
+    if let Some(path) = server_config.get_assets_file() {
+        assets::spawn_saving(Arc::clone(&tracker), path.clone());
+    }
+    if let Some(state) = &mut inherited {
+        state.inherit_clients(&tracker);
+    }
+    let handover = Handover::default();
+    let mut services = Vec::new();
+    let mut server_loops = Vec::new();
+    let (reloader, reload_requests) = reload::reloader();
+    let mut control = Control {
+        started: SystemTime::now(),
+        origin,
+        tracker: Arc::clone(&tracker),
+        services: Vec::new(),
+        reloader,
+        handover: handover.clone(),
+    };
+    info!("DHCP and TFTP tasks run on the {} runtime.", runtime::name());
+    for (tenant, conf) in server_config.served() {
+        if let Some(tenant) = tenant {
+            info!("Starting the services of tenant {tenant}.");
+        }
+        let tenant_services = spawn_services(conf, &tracker)?;
+        let shared_conf = Arc::clone(&tenant_services.shared_conf);
+        let sessions = match conf.get_dhcp_enabled() {
+            true => {
+                let session_map = SessionMap::new(conf);
+                if let Some(state) = &mut inherited {
+                    state.inherit_sessions(tenant, &session_map);
+                }
+                let sessions = Arc::new(session_map);
+                let tracker = Arc::clone(&tracker);
+                let heartbeat = notifier.register();
+                let interfaces = dhcp::bind_interfaces(conf).context("Starting DHCP service")?;
+                server_loops.push(dhcp::server_loop(
+                    shared_conf,
+                    interfaces,
+                    tracker,
+                    Arc::clone(&sessions),
+                    heartbeat,
+                ));
+                Some(sessions)
+            }
+            false => {
+                info!("DHCP service not started, disabled by dhcp.enabled.");
+                None
+            }
+        };
+        control.services.push(ControlledService {
+            tenant: tenant.map(str::to_string),
+            shared_conf: Arc::clone(&tenant_services.shared_conf),
+            sessions,
+        });
+        services.push((tenant.map(str::to_string), tenant_services));
+    }
+    let control = Arc::new(control);
+    control::spawn_control_service_async(&server_config, Arc::clone(&control))?;
+    dashboard::spawn_dashboard_async(&server_config, Arc::clone(&control))?;
+    #[cfg(feature = "grpc")]
+    grpc::spawn_grpc_async(&server_config, Arc::clone(&control))?;
+    health::spawn_health_async(&server_config, Arc::clone(&control), Arc::clone(&notifier))?;
+    snmp::spawn_snmp_async(&server_config, Arc::clone(&control))?;
+    api::spawn_api_async(&server_config, control)?;
+    // Every socket is bound by now
+    let owned: Vec<&Path> = daemon.pid_file.iter().map(PathBuf::as_path).collect();
+    privileges::drop_to(server_config.get_user(), server_config.get_group(), &owned)?;
+    task::spawn(reload::watch_config(conf_source, overrides, services, reload_requests));
+    notifier.start();
+
+    let serving = async {
+        match server_loops.is_empty() {
+            // The other services run in the background
+            true => future::pending().await,
+            false => future::try_join_all(server_loops)
+                .await
+                .map(|_| ())
+                .context("Starting DHCP service"),
+        }
+    };
+    let oneshot_finished = async {
+        match &oneshot {
+            Some(oneshot) => oneshot.finished().await,
+            None => future::pending().await,
+        }
+    };
+    let result: Result<()> = task::block_on(async {
+        let stopped = future::select(Box::pin(oneshot_finished), Box::pin(handover.requested()));
+        match future::select(Box::pin(serving), stopped).await {
+            future::Either::Left((result, _)) => result,
+            future::Either::Right((future::Either::Left(_), _)) => {
+                info!("All the clients of --oneshot are served, exiting.");
+                if let Some(path) = server_config.get_stats_file() {
+                    if let Err(e) = tracker.stats().save(path) {
+                        warn!("Saving the boot statistics to {} failed: {e:#}", path.display());
+                    }
+                }
+                if let Some(path) = server_config.get_assets_file() {
+                    if let Err(e) = tracker.assets().save(path) {
+                        warn!("Saving the assets to {} failed: {e:#}", path.display());
+                    }
+                }
+                if let Some(path) = &daemon.pid_file {
+                    let _ = std::fs::remove_file(path);
+                }
+                Ok(())
+            }
+            // The PID file is that of the server taking over by now
+            future::Either::Right((future::Either::Right(_), _)) => {
+                info!("Taken over, no longer answering DHCP, draining the transfers in progress.");
+                drop(instance);
+                handover::drain(&tracker, handover::DRAIN_TIMEOUT).await;
+                Ok(())
+            }
+        }
+    });
 
     debug!("Exiting");
     result
 }
+
+/// Rewrites the configuration file at `path`, or the default location, to
+/// the current version of its schema.
+fn migrate_config(path: Option<std::path::PathBuf>) -> Result<()> {
+    let path = path.unwrap_or_else(|| Conf::config_path(None));
+    let changes = migrate::migrate_file(&path)?;
+    for change in &changes {
+        println!("{change}");
+    }
+    match changes.is_empty() {
+        true => println!("{} is up to date.", path.display()),
+        false => println!(
+            "Wrote {}, the original kept as {}.",
+            path.display(),
+            migrate::backup_path(&path).display()
+        ),
+    }
+
+    Ok(())
+}
+
+/// Reports the DHCP servers answering a DISCOVER on `iface` within `wait`.
+fn probe_network(iface: &str, wait: Duration) -> Result<()> {
+    let (mac, answers) = probe::probe(iface, wait)?;
+    print!("{}", probe::summary(iface, &mac, wait, answers.len()));
+    print!("{}", probe::report(&answers));
+    Ok(())
+}
+
+/// Starts the services of the top level configuration or of a tenant, but
+/// for DHCP.
+fn spawn_services(conf: &Conf, tracker: &Arc<BootTracker>) -> Result<Services> {
+    if conf.get_netbootxyz() {
+        if let Some(tftp_dir) = conf.get_tftp_serve_path() {
+            task::spawn(netbootxyz::keep_updated(tftp_dir.into()));
+        }
+    }
+    let tftp = spawn_tftp_service_async(conf, Arc::clone(tracker))?;
+    let http = spawn_http_service_async(conf, tftp.handler())?;
+
+    Ok(Services {
+        images: spawn_image_service_async(conf)?,
+        dns: spawn_dns_service_async(conf)?,
+        shared_conf: Arc::new(RwLock::new(Arc::new(conf.clone()))),
+        tftp,
+        http,
+    })
+}
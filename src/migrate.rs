@@ -0,0 +1,201 @@
+//! `version` of the configuration file, and the migrations rewriting the
+//! configurations of older versions to the current schema as fields get
+//! renamed or moved, so upgrades don't silently drop settings.
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use yaml_rust2::{Yaml, YamlEmitter, YamlLoader};
+
+use crate::{
+    conf::{Conf, ConfFormat},
+    util::part_path,
+    Result,
+};
+
+/// Version of the schema of the configuration, set as `version`.
+/// Configurations without one are of version 1.
+pub const CONF_VERSION: u64 = 1;
+
+/// Changes of the schema made by a version.
+pub struct Migration {
+    pub version: u64,
+    /// Fields moved or renamed, by their path before and after, their keys
+    /// separated by dots. `*` stands for each entry of a list or mapping,
+    /// e.g. `match.*.conf.boot_file`, in the part both paths share only.
+    pub moves: &'static [(&'static str, &'static str)],
+}
+
+/// Migrations of the versions after the first, in order.
+pub const MIGRATIONS: &[Migration] = &[];
+
+/// Version of the configuration `document`.
+pub fn version_of(document: &Yaml) -> Result<u64> {
+    match &document["version"] {
+        Yaml::BadValue | Yaml::Null => Ok(1),
+        version => version
+            .as_i64()
+            .and_then(|version| u64::try_from(version).ok())
+            .filter(|version| *version >= 1)
+            .ok_or(anyhow!("Invalid version of the configuration, expected a number from 1")),
+    }
+}
+
+/// Moves the fields of `document` by the `migrations` of the versions after
+/// its own, up to `to`. Returns the changes made.
+pub fn migrate(document: &mut Yaml, migrations: &[Migration], to: u64) -> Result<Vec<String>> {
+    let version = version_of(document)?;
+    if version > to {
+        bail!(
+            "The configuration is of version {version}, newer than version {to} of this \
+             release, upgrade preboot-oxide"
+        );
+    }
+
+    let mut changes = Vec::new();
+    let pending = migrations
+        .iter()
+        .filter(|migration| migration.version > version && migration.version <= to);
+    for migration in pending {
+        for (from, to) in migration.moves {
+            let from_path: Vec<&str> = from.split('.').collect();
+            let to_path: Vec<&str> = to.split('.').collect();
+            if move_field(document, &from_path, &to_path)
+                .map_err(|e| anyhow!("Can't move {from} to {to}: {e}"))?
+            {
+                changes.push(format!("Moved {from} to {to} (version {})", migration.version));
+            }
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Rewrites the YAML configuration file at `path` to the current version,
+/// the original kept as `<file>.bak`. Returns the changes made, none when
+/// it's up to date.
+pub fn migrate_file(path: &Path) -> Result<Vec<String>> {
+    if ConfFormat::of(path) != ConfFormat::Yaml {
+        bail!(
+            "Only YAML files can be rewritten, the changes to make to {} are logged when \
+             it's loaded",
+            path.display()
+        );
+    }
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+    let mut document = YamlLoader::load_from_str(&content)?
+        .into_iter()
+        .next()
+        .unwrap_or(Yaml::Null);
+
+    let mut changes = migrate(&mut document, MIGRATIONS, CONF_VERSION)?;
+    let version = Yaml::Integer(CONF_VERSION as i64);
+    if document["version"] != version {
+        changes.push(format!("Set version {CONF_VERSION}"));
+    }
+    if changes.is_empty() {
+        return Ok(changes);
+    }
+
+    // The version first, then the fields in their order
+    let mut hash = yaml_rust2::yaml::Hash::new();
+    hash.insert(Yaml::from_str("version"), version);
+    for (key, value) in document.into_hash().into_iter().flatten() {
+        if key.as_str() != Some("version") {
+            hash.insert(key, value);
+        }
+    }
+    let mut yaml = String::new();
+    YamlEmitter::new(&mut yaml).dump(&Yaml::Hash(hash))?;
+    yaml.push('\n');
+
+    let part = part_path(path);
+    fs::write(&part, yaml)?;
+    let result = Conf::from_config_file(Some(&part))
+        .context("Checking the migrated configuration")
+        .and_then(|_| Ok(fs::copy(path, backup_path(path))?))
+        .and_then(|_| Ok(fs::rename(&part, path)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&part);
+    }
+
+    result.map(|_| changes)
+}
+
+/// Where `migrate_file` keeps the original of `path`.
+pub fn backup_path(path: &Path) -> std::path::PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    backup.into()
+}
+
+/// Moves the value of `node` at `from` to `to`. Returns whether there was
+/// one.
+fn move_field(node: &mut Yaml, from: &[&str], to: &[&str]) -> Result<bool> {
+    match (from, to) {
+        (["*", from_rest @ ..], ["*", to_rest @ ..]) => {
+            let children: Vec<&mut Yaml> = match node {
+                Yaml::Array(items) => items.iter_mut().collect(),
+                Yaml::Hash(hash) => hash.values_mut().collect(),
+                _ => Vec::new(),
+            };
+            let mut moved = false;
+            for child in children {
+                moved |= move_field(child, from_rest, to_rest)?;
+            }
+            Ok(moved)
+        }
+        ([from_key, from_rest @ ..], [to_key, to_rest @ ..])
+            if from_key == to_key && !from_rest.is_empty() && !to_rest.is_empty() =>
+        {
+            match hash_mut(node).and_then(|hash| hash.get_mut(&Yaml::String(from_key.to_string()))) {
+                Some(child) => move_field(child, from_rest, to_rest),
+                None => Ok(false),
+            }
+        }
+        _ => {
+            if from.contains(&"*") || to.contains(&"*") {
+                bail!("* is only allowed in the part of the paths they share");
+            }
+            let Some(value) = take_value(node, from) else {
+                return Ok(false);
+            };
+            set_value(node, to, value)?;
+            Ok(true)
+        }
+    }
+}
+
+fn hash_mut(node: &mut Yaml) -> Option<&mut yaml_rust2::yaml::Hash> {
+    match node {
+        Yaml::Hash(hash) => Some(hash),
+        _ => None,
+    }
+}
+
+fn take_value(node: &mut Yaml, path: &[&str]) -> Option<Yaml> {
+    let (key, rest) = path.split_first()?;
+    let hash = hash_mut(node)?;
+    let key = Yaml::String(key.to_string());
+    if rest.is_empty() {
+        return hash.remove(&key).filter(|value| !value.is_null());
+    }
+
+    take_value(hash.get_mut(&key)?, rest)
+}
+
+fn set_value(node: &mut Yaml, path: &[&str], value: Yaml) -> Result<()> {
+    let Some((key, rest)) = path.split_first() else {
+        if !node.is_null() {
+            bail!("it's already set");
+        }
+        *node = value;
+        return Ok(());
+    };
+    if node.is_null() {
+        *node = Yaml::Hash(Default::default());
+    }
+    let hash = hash_mut(node).ok_or(anyhow!("{key} would be in a value that isn't a mapping"))?;
+
+    set_value(hash.entry(Yaml::String(key.to_string())).or_insert(Yaml::Null), rest, value)
+}
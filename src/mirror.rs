@@ -0,0 +1,92 @@
+//! Caching proxy of the upstream directories declared in `mirrors`: files
+//! requested over HTTP are fetched once into `<tftp_server_dir>/<path>/` and
+//! served from there, so installers pull them from the LAN.
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use log::{debug, warn};
+
+use crate::{conf::MirrorSource, fetch, Result};
+
+/// Cached files are checked for updates upstream when older than this.
+pub const MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Upstream URL and cache location of the file at `path` of the TFTP root,
+/// when it's under one of the `mirrors`.
+pub fn locate(root: &Path, mirrors: &[MirrorSource], path: &Path) -> Option<(String, PathBuf)> {
+    let path = path.strip_prefix("/").unwrap_or(path);
+    mirrors.iter().find_map(|mirror| {
+        let rest = path.strip_prefix(&mirror.path).ok()?;
+        let parts = rest
+            .components()
+            .map(|component| match component {
+                Component::Normal(part) => part.to_str(),
+                _ => None,
+            })
+            .collect::<Option<Vec<&str>>>()?;
+        if parts.is_empty() {
+            return None;
+        }
+        let url = parts.iter().fold(mirror.url.clone(), |url, part| {
+            format!("{url}/{}", percent_encode(part))
+        });
+
+        Some((url, root.join(path)))
+    })
+}
+
+/// Fetches `url` into the cached copy `dest` unless it was checked for
+/// updates in the last `MAX_AGE`. Returns whether there's a copy to serve,
+/// `false` when the file doesn't exist upstream. A stale copy is served when
+/// the mirror can't be reached.
+pub async fn fetch(url: &str, dest: &Path) -> Result<bool> {
+    let checked = fs::metadata(dest).and_then(|metadata| metadata.modified()).ok();
+    if checked.is_some_and(|checked| checked.elapsed().is_ok_and(|age| age < MAX_AGE)) {
+        return Ok(true);
+    }
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fetch::download(url, dest).await {
+        Ok(true) => debug!("Cached {url} at {}", dest.display()),
+        // Unchanged upstream, checked again after another `MAX_AGE`
+        Ok(false) => fs::File::options()
+            .write(true)
+            .open(dest)
+            .and_then(|file| file.set_modified(SystemTime::now()))?,
+        Err(e) if is_not_found(&e) => {
+            let _ = fs::remove_file(dest);
+            return Ok(false);
+        }
+        Err(e) if checked.is_some() => {
+            warn!("Serving the cached {}, mirror unavailable: {e:#}", dest.display());
+        }
+        Err(e) => return Err(e),
+    }
+
+    Ok(true)
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<ureq::Error>(),
+        Some(ureq::Error::Status(404 | 410, _))
+    )
+}
+
+/// Percent encodes a path segment of a URL.
+fn percent_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
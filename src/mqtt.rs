@@ -0,0 +1,349 @@
+//! Publishing of the events of the clients to an MQTT broker, `mqtt_broker`,
+//! for home automation such as Home Assistant or Node-RED to act on the
+//! machines booting. The events are published as the JSON documents of
+//! `event_webhooks` under `mqtt_topic`, over MQTT 3.1.1 with QoS 0, from a
+//! thread of their own keeping the connection, so the clients never wait
+//! for the broker.
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+use time::OffsetDateTime;
+
+use crate::{
+    conf::Conf,
+    events::{ClientEvent, Event},
+    logging,
+    template::parse_mac,
+    Result,
+};
+
+pub const DEFAULT_MQTT_PORT: u16 = 1883;
+pub const DEFAULT_MQTT_TOPIC: &str = "preboot-oxide/{event}";
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Time the broker isn't connected to again after failing.
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+/// Events queued for the broker, those past it being dropped while it's
+/// slow or unreachable.
+const QUEUE: usize = 256;
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+
+static PUBLISHER: OnceLock<SyncSender<Publication>> = OnceLock::new();
+
+/// MQTT broker, `[mqtt://]<host>[:<port>]`, port 1883 by default.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MqttBroker {
+    pub host: String,
+    pub port: u16,
+}
+
+impl FromStr for MqttBroker {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let addr = match s.split_once("://") {
+            Some(("mqtt" | "tcp", addr)) => addr,
+            Some(_) => bail!("Invalid MQTT broker: {s}, expected mqtt://"),
+            None => s,
+        };
+        let invalid = || anyhow!("Invalid MQTT broker: {s}, expected [mqtt://]<host>[:<port>]");
+        if let Ok(addr) = addr.parse::<SocketAddr>() {
+            return Ok(Self {
+                host: addr.ip().to_string(),
+                port: addr.port(),
+            });
+        }
+        let unbracketed = addr
+            .strip_prefix('[')
+            .and_then(|ip| ip.strip_suffix(']'))
+            .unwrap_or(addr);
+        if let Ok(ip) = unbracketed.parse::<IpAddr>() {
+            return Ok(Self {
+                host: ip.to_string(),
+                port: DEFAULT_MQTT_PORT,
+            });
+        }
+        let (host, port) = match addr.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (addr, DEFAULT_MQTT_PORT),
+        };
+        let is_hostname = !host.is_empty()
+            && host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+        if !is_hostname {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for MqttBroker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => write!(f, "mqtt://[{ip}]:{}", self.port),
+            _ => write!(f, "mqtt://{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// How the broker is connected to, from the configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub broker: MqttBroker,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// An event to publish, with the broker it goes to.
+struct Publication {
+    settings: Settings,
+    topic: String,
+    payload: String,
+    retain: bool,
+}
+
+/// Publishes `event` about `client` to the `mqtt_broker` of `conf`, if any,
+/// in the background.
+pub fn publish(conf: &Conf, event: Event, client: &ClientEvent) {
+    let Some(broker) = conf.get_mqtt_broker() else {
+        return;
+    };
+    let publication = Publication {
+        settings: Settings {
+            broker: broker.clone(),
+            client_id: client_id(conf),
+            username: conf.get_mqtt_username().cloned(),
+            password: conf.get_mqtt_password().cloned(),
+        },
+        topic: topic(conf.get_mqtt_topic(), event, client),
+        payload: client.to_json(event, OffsetDateTime::now_utc()).to_string(),
+        retain: conf.get_mqtt_retain(),
+    };
+    let publisher = PUBLISHER.get_or_init(|| {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE);
+        thread::spawn(move || run(receiver));
+        sender
+    });
+    if let Err(TrySendError::Full(publication)) = publisher.try_send(publication) {
+        debug!(
+            "MQTT broker lagging behind, {event} for {} dropped.",
+            publication.topic
+        );
+    }
+}
+
+/// The topic `event` about `client` is published under, `template` with
+/// `{event}`, `{mac}` and `{session}` replaced.
+pub fn topic(template: &str, event: Event, client: &ClientEvent) -> String {
+    let session = parse_mac(&client.mac_address)
+        .map(|mac| logging::session_id(&mac, client.xid))
+        .unwrap_or_default();
+    template
+        .replace("{event}", event.name())
+        .replace("{mac}", &client.mac_address)
+        .replace("{session}", &session)
+}
+
+/// The client ID of the server, per instance, brokers closing the
+/// connection of a client when another one connects with its ID.
+fn client_id(conf: &Conf) -> String {
+    match conf.get_instance_name() {
+        Some(name) => format!("preboot-oxide-{name}"),
+        None => "preboot-oxide".to_string(),
+    }
+}
+
+/// Publishes what's received, connecting again when the settings change or
+/// the connection is lost.
+fn run(receiver: Receiver<Publication>) {
+    let mut connection: Option<Connection> = None;
+    let mut failed: Option<(Settings, Instant)> = None;
+    for publication in receiver {
+        let settings = &publication.settings;
+        if connection
+            .as_ref()
+            .is_some_and(|connection| &connection.settings != settings)
+        {
+            connection = None;
+        }
+        let is_waiting = failed
+            .as_ref()
+            .is_some_and(|(failed, time)| failed == settings && time.elapsed() < RETRY_INTERVAL);
+        if connection.is_none() && is_waiting {
+            debug!(
+                "MQTT broker {} unreachable, {} dropped.",
+                settings.broker, publication.topic
+            );
+            continue;
+        }
+
+        // Connected again once when the connection was lost meanwhile
+        let was_connected = connection.is_some();
+        let mut result = publish_on(&mut connection, &publication);
+        if result.is_err() && was_connected {
+            result = publish_on(&mut connection, &publication);
+        }
+        match result {
+            Ok(()) => failed = None,
+            Err(e) => {
+                warn!(
+                    "Publishing {} to the MQTT broker {} failed: {e}",
+                    publication.topic, settings.broker
+                );
+                failed = Some((settings.clone(), Instant::now()));
+            }
+        }
+    }
+}
+
+/// Publishes `publication` on `connection`, connected first when it's
+/// `None`, and set back to `None` when it fails.
+fn publish_on(connection: &mut Option<Connection>, publication: &Publication) -> io::Result<()> {
+    let current = match connection.take() {
+        Some(current) => current,
+        None => {
+            let opened = Connection::open(&publication.settings)?;
+            info!(
+                "Connected to the MQTT broker {}.",
+                publication.settings.broker
+            );
+            opened
+        }
+    };
+    let current = connection.insert(current);
+    let result = current.publish(&publication.topic, &publication.payload, publication.retain);
+    if result.is_err() {
+        *connection = None;
+    }
+    result
+}
+
+/// A connection to a broker.
+pub struct Connection {
+    settings: Settings,
+    stream: TcpStream,
+}
+
+impl Connection {
+    /// Connects to the broker of `settings`, with a clean session and no
+    /// keep alive, the connections lost being found on the next publish.
+    pub fn open(settings: &Settings) -> io::Result<Self> {
+        let broker = &settings.broker;
+        let mut last_error = io::Error::from(io::ErrorKind::AddrNotAvailable);
+        let mut stream = None;
+        for addr in (broker.host.as_str(), broker.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, TIMEOUT) {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    break;
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        let mut stream = stream.ok_or(last_error)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.write_all(&connect_packet(settings))?;
+
+        let mut connack = [0u8; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != CONNACK || connack[1] != 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Expected CONNACK",
+            ));
+        }
+        if connack[3] != 0 {
+            let reason = match connack[3] {
+                1 => "unacceptable protocol version",
+                2 => "client ID rejected",
+                3 => "server unavailable",
+                4 => "bad user name or password",
+                5 => "not authorized",
+                _ => "refused",
+            };
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("Connection {reason}"),
+            ));
+        }
+
+        Ok(Self {
+            settings: settings.clone(),
+            stream,
+        })
+    }
+
+    /// Publishes `payload` under `topic` with QoS 0.
+    pub fn publish(&mut self, topic: &str, payload: &str, retain: bool) -> io::Result<()> {
+        let mut body = string(topic);
+        body.extend(payload.as_bytes());
+        self.stream
+            .write_all(&packet(PUBLISH | u8::from(retain), &body))
+    }
+}
+
+/// CONNECT of MQTT 3.1.1.
+fn connect_packet(settings: &Settings) -> Vec<u8> {
+    let mut flags = 0x02; // Clean session
+    if settings.username.is_some() {
+        flags |= 0x80;
+    }
+    if settings.password.is_some() {
+        flags |= 0x40;
+    }
+    let mut body = string("MQTT");
+    body.extend([4, flags, 0, 0]);
+    body.extend(string(&settings.client_id));
+    for field in [&settings.username, &settings.password]
+        .into_iter()
+        .flatten()
+    {
+        body.extend(string(field));
+    }
+    packet(CONNECT, &body)
+}
+
+/// A packet of `header` and `body`, its length encoded 7 bits a byte.
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        match len {
+            0 => {
+                packet.push(byte);
+                break;
+            }
+            _ => packet.push(byte | 0x80),
+        }
+    }
+    packet.extend(body);
+    packet
+}
+
+/// A string prefixed by its length.
+fn string(s: &str) -> Vec<u8> {
+    let mut bytes = (s.len() as u16).to_be_bytes().to_vec();
+    bytes.extend(s.as_bytes());
+    bytes
+}
@@ -0,0 +1,65 @@
+//! Built in netboot.xyz profile: its iPXE binaries are downloaded into the
+//! TFTP root, kept up to date and handed to clients without a `boot_file`,
+//! giving them the netboot.xyz boot menu.
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use async_std::task;
+use log::{error, info};
+use once_cell::sync::Lazy;
+
+use crate::{fetch, Result};
+
+/// Directory of the TFTP root the binaries are kept in.
+pub const DIR: &str = "netboot.xyz";
+const RELEASE_URL: &str = "https://boot.netboot.xyz/ipxe";
+const UPDATE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Retry sooner when an update failed, e.g. while the network is down.
+const RETRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The BIOS, x64 UEFI and ARM64 UEFI binaries.
+const BINARIES: [&str; 3] = [
+    "netboot.xyz.kpxe",
+    "netboot.xyz.efi",
+    "netboot.xyz-arm64.efi",
+];
+static BOOT_FILES: Lazy<[String; 3]> =
+    Lazy::new(|| BINARIES.map(|binary| format!("{DIR}/{binary}")));
+
+/// Boot file for a client of the given processor architecture, option 93.
+pub fn boot_file(arch: Option<u16>) -> &'static String {
+    match arch {
+        Some(0x07 | 0x09) => &BOOT_FILES[1],
+        Some(0x0b) => &BOOT_FILES[2],
+        _ => &BOOT_FILES[0],
+    }
+}
+
+/// Downloads the binaries into `<tftp_root>/netboot.xyz`, then checks for new
+/// releases once a day.
+pub async fn keep_updated(tftp_root: PathBuf) {
+    let dir = tftp_root.join(DIR);
+    loop {
+        let interval = match update(&dir).await {
+            Ok(()) => UPDATE_INTERVAL,
+            Err(e) => {
+                error!("Failed updating netboot.xyz: {e:#}");
+                RETRY_INTERVAL
+            }
+        };
+        task::sleep(interval).await;
+    }
+}
+
+async fn update(dir: &Path) -> Result<()> {
+    async_std::fs::create_dir_all(dir).await?;
+    for binary in BINARIES {
+        if fetch::download(&format!("{RELEASE_URL}/{binary}"), &dir.join(binary)).await? {
+            info!("Downloaded netboot.xyz {binary}");
+        }
+    }
+
+    Ok(())
+}
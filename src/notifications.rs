@@ -0,0 +1,336 @@
+//! Notifications: the events of the clients sent as short messages to the
+//! people running the site, to a Slack or Discord channel through its
+//! incoming webhook, or by mail through an SMTP relay, each of
+//! `notifications` taking the events it's configured with, such as an
+//! unknown machine asking to boot. Unlike those of `event_webhooks`, the
+//! messages are written to be read rather than parsed.
+use std::{
+    fmt,
+    io::{BufRead, BufReader, Write},
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::Context;
+use async_std::task;
+use log::{debug, warn};
+use time::{format_description::well_known::Rfc2822, OffsetDateTime};
+
+use crate::{
+    conf::{Conf, Notification},
+    events::{self, ClientEvent, Event, WEBHOOK_ATTEMPTS},
+    logging,
+    template::parse_mac,
+    tracker::UPSTREAM_MISSING_AFTER,
+    util::redact_url,
+    Result,
+};
+
+pub const DEFAULT_SMTP_PORT: u16 = 25;
+/// Time the SMTP relay is waited for on each exchange.
+const SMTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where a notification is sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotificationKind {
+    /// The incoming webhook of a Slack channel.
+    Slack,
+    /// The webhook of a Discord channel.
+    Discord,
+    /// Mails, through an SMTP relay.
+    Smtp,
+}
+
+impl FromStr for NotificationKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "slack" => Ok(Self::Slack),
+            "discord" => Ok(Self::Discord),
+            "smtp" => Ok(Self::Smtp),
+            _ => bail!("Unknown notification kind {s}, expected one of slack, discord, smtp"),
+        }
+    }
+}
+
+impl fmt::Display for NotificationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Slack => "slack",
+            Self::Discord => "discord",
+            Self::Smtp => "smtp",
+        })
+    }
+}
+
+/// SMTP relay, `smtp://<host>[:<port>]`, port 25 by default.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SmtpServer {
+    pub host: String,
+    pub port: u16,
+}
+
+impl FromStr for SmtpServer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid SMTP server: {s}, expected smtp://<host>[:<port>]");
+        let addr = s.strip_prefix("smtp://").ok_or_else(invalid)?;
+        if let Ok(addr) = addr.parse::<SocketAddr>() {
+            return Ok(Self {
+                host: addr.ip().to_string(),
+                port: addr.port(),
+            });
+        }
+        let unbracketed = addr
+            .strip_prefix('[')
+            .and_then(|ip| ip.strip_suffix(']'))
+            .unwrap_or(addr);
+        if let Ok(ip) = unbracketed.parse::<IpAddr>() {
+            return Ok(Self {
+                host: ip.to_string(),
+                port: DEFAULT_SMTP_PORT,
+            });
+        }
+        let (host, port) = match addr.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (addr, DEFAULT_SMTP_PORT),
+        };
+        let is_hostname = !host.is_empty()
+            && host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+        if !is_hostname {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+/// Sends `event` about `client` to the `notifications` of `conf` taking
+/// it, each in a task of its own, their failures being only logged.
+pub fn send(conf: &Conf, event: Event, client: &ClientEvent) {
+    let notifications: Vec<Notification> = conf
+        .get_notifications()
+        .iter()
+        .filter(|notification| notification.takes(event))
+        .cloned()
+        .collect();
+    if notifications.is_empty() {
+        return;
+    }
+    let text = message(conf.get_instance_name(), event, client);
+    let details = details(event, client);
+    for notification in notifications {
+        let (text, details) = (text.clone(), details.clone());
+        let mac_address = client.mac_address.clone();
+        task::spawn(async move {
+            if let Err(e) = deliver(&notification, &text, &details).await {
+                warn!(
+                    "Sending {event} for {mac_address} to the {} notification {} failed: {e:#}",
+                    notification.kind,
+                    redact_url(&notification.url)
+                );
+            }
+        });
+    }
+}
+
+/// Sends `text` to `notification`, with `details` in the body of the mails.
+async fn deliver(notification: &Notification, text: &str, details: &str) -> Result<()> {
+    if notification.kind != NotificationKind::Smtp {
+        let body = payload(notification.kind, text).to_string();
+        return events::post(&notification.url, &body, WEBHOOK_ATTEMPTS).await;
+    }
+
+    let server: SmtpServer = notification.url.parse()?;
+    let from = notification
+        .from
+        .clone()
+        .ok_or(anyhow!("Expected the sender of the mails"))?;
+    let to = notification.to.clone();
+    let (subject, body) = (text.to_string(), format!("{text}\n\n{details}"));
+    task::spawn_blocking(move || send_mail(&server, &from, &to, &subject, &body)).await
+}
+
+/// The message of `event` about `client`, one line, prefixed with
+/// `instance` when the server has a name.
+pub fn message(instance: Option<&str>, event: Event, client: &ClientEvent) -> String {
+    let who = match client.ip {
+        Some(ip) => format!("{} ({ip})", client.mac_address),
+        None => client.mac_address.clone(),
+    };
+    let on = client
+        .iface
+        .as_ref()
+        .map(|iface| format!(" on {iface}"))
+        .unwrap_or_default();
+    let boot_file = client.boot_file.as_deref().unwrap_or("no boot file");
+    let text = match event {
+        Event::DiscoverSeen => format!("{who} is asking for a boot file{on}"),
+        Event::RuleMatched => format!(
+            "{who} is configured by {}{on}",
+            client.rule.as_deref().unwrap_or("an unknown rule")
+        ),
+        Event::UnknownClient => format!("Unknown client {who} requested PXE boot{on}"),
+        Event::OfferSent => format!("{who} was offered {boot_file}{on}"),
+        Event::BootFileDelivered => format!("{who} downloaded {boot_file}{on}"),
+        Event::SessionTimedOut => match &client.stage {
+            Some(stage) => format!("The DHCP handshake of {who} timed out after the {stage}{on}"),
+            None => format!("The DHCP handshake of {who} timed out{on}"),
+        },
+        Event::UpstreamDhcpMissing => format!(
+            "No DHCP server offered an address to the last {UPSTREAM_MISSING_AFTER} clients{on}, {who} the last"
+        ),
+    };
+    match instance {
+        Some(instance) => format!("[{instance}] {text}"),
+        None => text,
+    }
+}
+
+/// The details of `client` a mail ends with, one per line, those unknown
+/// left out.
+pub fn details(event: Event, client: &ClientEvent) -> String {
+    let session = parse_mac(&client.mac_address).map(|mac| logging::session_id(&mac, client.xid));
+    let fields = [
+        ("Event", Some(event.name().to_string())),
+        ("MAC address", Some(client.mac_address.clone())),
+        ("XID", Some(format!("{:#010x}", client.xid))),
+        ("Session", session),
+        ("IP", client.ip.map(|ip| ip.to_string())),
+        ("Boot file", client.boot_file.clone()),
+        ("Interface", client.iface.clone()),
+        ("Stage", client.stage.clone()),
+        ("Rule", client.rule.clone()),
+    ];
+    fields
+        .into_iter()
+        .filter_map(|(name, value)| Some(format!("{name}: {}", value?)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The JSON document `text` is posted to the webhook of a chat as.
+pub fn payload(kind: NotificationKind, text: &str) -> serde_json::Value {
+    match kind {
+        NotificationKind::Discord => serde_json::json!({ "content": text }),
+        _ => serde_json::json!({ "text": text }),
+    }
+}
+
+/// Mails `body` under `subject` from `from` to `to` through `server`, in
+/// plain SMTP, without authentication nor TLS, as the relays of local
+/// networks take them.
+pub fn send_mail(
+    server: &SmtpServer,
+    from: &str,
+    to: &[String],
+    subject: &str,
+    body: &str,
+) -> Result<()> {
+    let stream =
+        connect(server).context(format!("Connecting to {}:{}", server.host, server.port))?;
+    let local_ip = stream.local_addr()?.ip();
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    reply(&mut reader, 2).context("Waiting for the greeting")?;
+    let helo = match local_ip {
+        IpAddr::V4(ip) => format!("EHLO [{ip}]"),
+        IpAddr::V6(ip) => format!("EHLO [IPv6:{ip}]"),
+    };
+    command(&mut writer, &mut reader, &helo, 2)?;
+    command(&mut writer, &mut reader, &format!("MAIL FROM:<{from}>"), 2)?;
+    for recipient in to {
+        command(
+            &mut writer,
+            &mut reader,
+            &format!("RCPT TO:<{recipient}>"),
+            2,
+        )?;
+    }
+    command(&mut writer, &mut reader, "DATA", 3)?;
+
+    let date = OffsetDateTime::now_utc().format(&Rfc2822)?;
+    let subject = subject.replace(['\r', '\n'], " ");
+    let mut mail = format!(
+        "From: <{from}>\r\nTo: {}\r\nSubject: {subject}\r\nDate: {date}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+        to.iter()
+            .map(|recipient| format!("<{recipient}>"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    for line in body.lines() {
+        // Lines starting with a dot doubled, not to end the mail
+        if line.starts_with('.') {
+            mail.push('.');
+        }
+        mail.push_str(line);
+        mail.push_str("\r\n");
+    }
+    mail.push_str(".\r\n");
+    writer.write_all(mail.as_bytes())?;
+    reply(&mut reader, 2).context("Sending the mail")?;
+
+    if let Err(e) = command(&mut writer, &mut reader, "QUIT", 2) {
+        debug!("Quitting {}:{} failed: {e:#}", server.host, server.port);
+    }
+    Ok(())
+}
+
+fn connect(server: &SmtpServer) -> std::io::Result<TcpStream> {
+    let mut last_error = std::io::Error::from(std::io::ErrorKind::AddrNotAvailable);
+    for addr in (server.host.as_str(), server.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, SMTP_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(SMTP_TIMEOUT))?;
+                stream.set_write_timeout(Some(SMTP_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Sends `line`, expecting a reply of the class `class`, 2 for a success.
+fn command(
+    writer: &mut impl Write,
+    reader: &mut impl BufRead,
+    line: &str,
+    class: u16,
+) -> Result<()> {
+    writer.write_all(format!("{line}\r\n").as_bytes())?;
+    let verb = line.split([' ', ':']).next().unwrap_or(line);
+    reply(reader, class).context(format!("Answering {verb}"))
+}
+
+/// Reads a reply, of one line or more, failing unless its code is of the
+/// class `class`.
+fn reply(reader: &mut impl BufRead, class: u16) -> Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("Connection closed");
+        }
+        let line = line.trim_end();
+        let code = line
+            .get(..3)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or(anyhow!("Invalid reply {line}"))?;
+        // The lines but the last have a dash after their code
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        if code / 100 != class {
+            bail!("Refused with {line}");
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,250 @@
+//! systemd service notifications, for `Type=notify` units: `READY=1` is sent
+//! once the TFTP root is checked and every DHCP service has bound its
+//! sockets, and, with `WatchdogSec=`, `WATCHDOG=1` is sent as long as every
+//! DHCP loop keeps going around, so a wedged poller gets the service
+//! restarted. The same state answers the health checks of `health_addr`,
+//! see [`crate::health`].
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_std::task;
+use log::warn;
+
+use crate::Result;
+
+/// The state of a DHCP loop.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Slot {
+    bound: bool,
+    /// Whether it went around since the last watchdog ping.
+    beaten: bool,
+    /// When it last went around, or bound its sockets.
+    last_beat: Option<Instant>,
+}
+
+/// Tells systemd how the service is doing, the messages being dropped
+/// when it wasn't started by systemd.
+#[derive(Debug, Default)]
+pub struct Notifier {
+    /// `NOTIFY_SOCKET`
+    socket: Option<PathBuf>,
+    /// Half of `WATCHDOG_USEC`, the pings being sent twice as often as
+    /// needed.
+    watchdog_interval: Option<Duration>,
+    /// Longest the loops wait for messages, for their liveness to be told.
+    liveness_interval: Option<Duration>,
+    slots: Mutex<Vec<Slot>>,
+    is_ready: Mutex<bool>,
+}
+
+/// How the DHCP loops are doing, for the health checks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LoopsState {
+    pub loops: usize,
+    /// Those that bound their sockets.
+    pub bound: usize,
+    /// Those bound that didn't go around for longer than asked.
+    pub stalled: usize,
+}
+
+/// A DHCP loop telling the [`Notifier`] it bound its sockets and goes on.
+pub struct Heartbeat {
+    notifier: Arc<Notifier>,
+    slot: usize,
+}
+
+/// The interval the watchdog is pinged at for the values of `WATCHDOG_USEC`
+/// and `WATCHDOG_PID`, `None` when it isn't enabled for the process of PID
+/// `pid`.
+pub fn watchdog_interval(usec: Option<&str>, watchdog_pid: Option<&str>, pid: u32) -> Option<Duration> {
+    let is_ours = watchdog_pid
+        .map(|watchdog_pid| watchdog_pid.parse::<u32>().ok() == Some(pid))
+        .unwrap_or(true);
+    let usec = usec?.parse::<u64>().ok().filter(|usec| *usec > 0)?;
+    is_ours.then(|| Duration::from_micros(usec / 2))
+}
+
+impl Notifier {
+    /// The notifier of the environment set by systemd, which is removed so
+    /// the processes started don't notify in the name of the service.
+    pub fn from_env() -> Self {
+        let socket = env::var_os("NOTIFY_SOCKET").map(PathBuf::from);
+        let watchdog_interval = watchdog_interval(
+            env::var("WATCHDOG_USEC").ok().as_deref(),
+            env::var("WATCHDOG_PID").ok().as_deref(),
+            process::id(),
+        );
+        for name in ["NOTIFY_SOCKET", "WATCHDOG_USEC", "WATCHDOG_PID"] {
+            env::remove_var(name);
+        }
+
+        Self::new(socket, watchdog_interval)
+    }
+
+    pub fn new(socket: Option<PathBuf>, watchdog_interval: Option<Duration>) -> Self {
+        Self {
+            socket,
+            watchdog_interval,
+            ..Default::default()
+        }
+    }
+
+    /// The loops waking up at least every `interval`, even without the
+    /// watchdog, so they can be told stalled when they don't.
+    pub fn with_liveness_interval(mut self, interval: Duration) -> Self {
+        self.liveness_interval = Some(interval);
+        self
+    }
+
+    /// The state of the DHCP loops, those that didn't go around for longer
+    /// than `stalled_after` being stalled.
+    pub fn loops_state(&self, stalled_after: Duration) -> LoopsState {
+        let slots = self.lock_slots();
+        let bound: Vec<&Slot> = slots.iter().filter(|slot| slot.bound).collect();
+        let stalled = bound
+            .iter()
+            .filter(|slot| slot.last_beat.is_none_or(|beat| beat.elapsed() > stalled_after))
+            .count();
+        LoopsState {
+            loops: slots.len(),
+            bound: bound.len(),
+            stalled,
+        }
+    }
+
+    /// A heartbeat for a DHCP loop, to be taken before [`Notifier::start`].
+    pub fn register(self: &Arc<Self>) -> Heartbeat {
+        let mut slots = self.slots.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        slots.push(Slot::default());
+        Heartbeat {
+            notifier: Arc::clone(self),
+            slot: slots.len() - 1,
+        }
+    }
+
+    /// Tells systemd the service is ready once the DHCP loops registered
+    /// have bound their sockets, right away without any. The watchdog is
+    /// pinged in the background without them.
+    pub fn start(self: &Arc<Self>) {
+        self.ready_when_bound();
+        let Some(interval) = self.watchdog_interval.filter(|_| self.lock_slots().is_empty()) else {
+            return;
+        };
+        let notifier = Arc::clone(self);
+        task::spawn(async move {
+            loop {
+                notifier.send("WATCHDOG=1");
+                task::sleep(interval).await;
+            }
+        });
+    }
+
+    fn lock_slots(&self) -> std::sync::MutexGuard<'_, Vec<Slot>> {
+        self.slots.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Whether all the loops are bound, `READY=1` being sent the first time.
+    fn ready_when_bound(&self) -> bool {
+        if !self.lock_slots().iter().all(|slot| slot.bound) {
+            return false;
+        }
+        let mut is_ready = self.is_ready.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if !*is_ready {
+            *is_ready = true;
+            self.send("READY=1\nSTATUS=Serving");
+        }
+        true
+    }
+
+    /// Records that the loop of `slot` went around, telling whether all of
+    /// them did since the last time, the watchdog then being pinged.
+    fn beat(&self, slot: usize) -> bool {
+        let mut slots = self.lock_slots();
+        slots[slot].beaten = true;
+        slots[slot].last_beat = Some(Instant::now());
+        if !slots.iter().all(|slot| slot.beaten) {
+            return false;
+        }
+        slots.iter_mut().for_each(|slot| slot.beaten = false);
+        drop(slots);
+        if self.watchdog_interval.is_some() {
+            self.send("WATCHDOG=1");
+        }
+        true
+    }
+
+    /// Sends `state` to systemd, failures being only logged as the service
+    /// runs on.
+    fn send(&self, state: &str) {
+        let Some(path) = &self.socket else {
+            return;
+        };
+        if let Err(e) = send_to(path, state) {
+            warn!("Notifying systemd of {state:?} failed: {e}");
+        }
+    }
+}
+
+impl Heartbeat {
+    /// How long the loop may wait for messages before telling it goes on,
+    /// `None` without the watchdog nor the health checks.
+    pub fn interval(&self) -> Option<Duration> {
+        let notifier = &self.notifier;
+        match (notifier.watchdog_interval, notifier.liveness_interval) {
+            (Some(watchdog), Some(liveness)) => Some(watchdog.min(liveness)),
+            (watchdog, liveness) => watchdog.or(liveness),
+        }
+    }
+
+    /// Tells the loop has bound its sockets, returning whether all of them
+    /// did and the service is ready.
+    pub fn bound(&self) -> bool {
+        let mut slots = self.notifier.lock_slots();
+        slots[self.slot].bound = true;
+        slots[self.slot].last_beat = Some(Instant::now());
+        drop(slots);
+        self.notifier.ready_when_bound()
+    }
+
+    /// Tells the loop went around, returning whether the watchdog was
+    /// pinged, all the loops having done so.
+    pub fn beat(&self) -> bool {
+        self.notifier.beat(self.slot)
+    }
+}
+
+/// Sends `state` on the socket of `NOTIFY_SOCKET`, `@` starting the name
+/// of an abstract one.
+#[cfg(target_os = "linux")]
+fn send_to(path: &Path, state: &str) -> Result<()> {
+    use log::debug;
+    use std::os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    match path.to_str().and_then(|path| path.strip_prefix('@')) {
+        Some(name) => {
+            let addr = SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        None => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    debug!("Notified systemd: {}", state.replace('\n', ", "));
+
+    Ok(())
+}
+
+/// systemd only runs on Linux.
+#[cfg(not(target_os = "linux"))]
+fn send_to(_path: &Path, _state: &str) -> Result<()> {
+    Ok(())
+}
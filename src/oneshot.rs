@@ -0,0 +1,116 @@
+//! `--oneshot`: the server exits once it has served boot files to a number
+//! of clients, or to each of the clients listed, for scripts reimaging a
+//! machine then getting out of the way. A client is served once it has
+//! downloaded whole the boot file it was given over DHCP.
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt,
+    str::FromStr,
+    sync::Mutex,
+    time::Duration,
+};
+
+use async_std::{
+    channel::{self, Receiver, Sender},
+    task,
+};
+use log::info;
+
+use crate::{conf::MacAddress, template::parse_mac, util::bytes_to_mac_address, Result};
+
+/// Time the server runs on once the last client is served, the boot file
+/// being read whole before its last blocks are sent and acknowledged.
+const LINGER: Duration = Duration::from_secs(3);
+
+/// The clients to serve before exiting.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    /// Any clients, as many as that.
+    Clients(usize),
+    /// Each of these MAC addresses.
+    Macs(BTreeSet<MacAddress>),
+}
+
+impl FromStr for Target {
+    type Err = anyhow::Error;
+
+    /// A number of clients, or MAC addresses separated by commas.
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(clients) = s.parse::<usize>() {
+            return match clients {
+                0 => bail!("The number of clients needs to be at least 1"),
+                clients => Ok(Self::Clients(clients)),
+            };
+        }
+        let macs = s
+            .split(',')
+            .map(|mac| parse_mac(mac.trim()).ok_or(anyhow!("{mac} is neither a number nor a MAC address")))
+            .collect::<Result<BTreeSet<_>>>()?;
+
+        Ok(Self::Macs(macs))
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Clients(1) => write!(f, "1 client"),
+            Self::Clients(clients) => write!(f, "{clients} clients"),
+            Self::Macs(macs) => {
+                let macs: Vec<String> = macs.iter().map(|mac| bytes_to_mac_address(mac)).collect();
+                write!(f, "{}", macs.join(", "))
+            }
+        }
+    }
+}
+
+/// Counts the clients served, telling when the target is reached.
+pub struct Oneshot {
+    target: Target,
+    /// MAC addresses, as the boot tracker writes them.
+    served: Mutex<HashSet<String>>,
+    done: (Sender<()>, Receiver<()>),
+}
+
+impl Oneshot {
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            served: Default::default(),
+            done: channel::bounded(1),
+        }
+    }
+
+    /// Records that the client of `mac_address` downloaded its boot file,
+    /// returning whether the target is reached. Clients served twice, and
+    /// those not listed, count once and not at all.
+    pub fn served(&self, mac_address: &str) -> bool {
+        let Ok(mut served) = self.served.lock() else {
+            return false;
+        };
+        let is_counted = match &self.target {
+            Target::Clients(_) => true,
+            Target::Macs(macs) => macs.iter().any(|mac| bytes_to_mac_address(mac) == mac_address),
+        };
+        if !is_counted || !served.insert(mac_address.to_string()) {
+            return false;
+        }
+        let total = match &self.target {
+            Target::Clients(clients) => *clients,
+            Target::Macs(macs) => macs.len(),
+        };
+        info!("Client {mac_address} served, {} of {total} for --oneshot.", served.len());
+        if served.len() < total {
+            return false;
+        }
+        let _ = self.done.0.try_send(());
+
+        true
+    }
+
+    /// Waits for the target to be reached, and the last transfer to end.
+    pub async fn finished(&self) {
+        let _ = self.done.1.recv().await;
+        task::sleep(LINGER).await;
+    }
+}
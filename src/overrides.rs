@@ -0,0 +1,91 @@
+//! Values given with `--set key=value` on the command line, replacing those
+//! of the configuration for quick experiments and containers, without
+//! editing its file.
+use std::fmt;
+
+use yaml_rust2::{Yaml, YamlLoader};
+
+use crate::Result;
+
+/// One `--set`: the path of the value, its keys separated by dots and the
+/// entries of lists given by their index, and the value, read as YAML.
+#[derive(Clone, Debug)]
+struct Override {
+    path: Vec<String>,
+    value: Yaml,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Overrides(Vec<Override>);
+
+impl Overrides {
+    /// Parses the `key=value` arguments, e.g. `default.boot_file=ipxe.efi`
+    /// or `ifaces=[eth0, eth1]`.
+    pub fn parse(args: &[String]) -> Result<Self> {
+        args.iter()
+            .map(|arg| {
+                let (key, value) = arg
+                    .split_once('=')
+                    .ok_or(anyhow!("Expected --set key=value, got {arg}"))?;
+                let path: Vec<String> = key.trim().split('.').map(str::to_string).collect();
+                if path.iter().any(String::is_empty) {
+                    bail!("Invalid key {key} of --set {arg}");
+                }
+                let value = YamlLoader::load_from_str(value)
+                    .map_err(|e| anyhow!("Invalid value of --set {arg}: {e}"))?
+                    .into_iter()
+                    .next()
+                    .unwrap_or(Yaml::Null);
+
+                Ok(Override { path, value })
+            })
+            .collect::<Result<Vec<Override>>>()
+            .map(Self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Sets the values in the configuration `document`, in the order given,
+    /// adding the mappings on their path when missing.
+    pub fn apply(&self, document: &mut Yaml) -> Result<()> {
+        for set in &self.0 {
+            set_value(document, &set.path, set.value.clone())
+                .map_err(|e| anyhow!("{e}, applying --set {}", set.path.join(".")))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Overrides {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys: Vec<String> = self.0.iter().map(|set| set.path.join(".")).collect();
+        write!(f, "{}", keys.join(", "))
+    }
+}
+
+fn set_value(node: &mut Yaml, path: &[String], value: Yaml) -> Result<()> {
+    let Some((key, rest)) = path.split_first() else {
+        *node = value;
+        return Ok(());
+    };
+    if matches!(node, Yaml::Null) {
+        *node = Yaml::Hash(Default::default());
+    }
+
+    let child = match node {
+        Yaml::Hash(hash) => hash.entry(Yaml::String(key.clone())).or_insert(Yaml::Null),
+        Yaml::Array(items) => {
+            let len = items.len();
+            key.parse::<usize>()
+                .ok()
+                .and_then(|index| items.get_mut(index))
+                .ok_or(anyhow!("No entry {key} in a list of {len}"))?
+        }
+        _ => bail!("Can't set {key} in a value that isn't a mapping or a list"),
+    };
+
+    set_value(child, rest, value)
+}
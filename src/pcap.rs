@@ -0,0 +1,287 @@
+//! Capture of the boot traffic to `pcap_file`, in the pcapng format
+//! Wireshark and tcpdump read, so a failed boot can be analyzed without
+//! running tcpdump as root next to the server. The DHCP messages received
+//! and sent are written as the IPv4 packets carrying them, each tagged with
+//! its interface, and with `pcap_tftp` the read and write requests of the
+//! TFTP clients too. The file is rotated once it reaches `pcap_max_size`,
+//! `pcap_files` of them being kept.
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use log::{info, warn};
+use network_interface::{Addr, NetworkInterface, NetworkInterfaceConfig};
+
+use crate::{conf::Conf, util::rotate, Result};
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+/// Packets starting with their IPv4 header, no link layer being captured.
+const LINKTYPE_RAW: u16 = 101;
+const OPTION_IF_NAME: u16 = 2;
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const TFTP_PORT: u16 = 69;
+
+static CAPTURE: OnceLock<Mutex<Capture>> = OnceLock::new();
+static CAPTURE_TFTP: OnceLock<bool> = OnceLock::new();
+
+/// Opcode of a TFTP request, RFC 1350.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TftpRequest {
+    Read = 1,
+    Write = 2,
+}
+
+/// A pcapng file the packets are appended to, rotated by size.
+pub struct Capture {
+    path: PathBuf,
+    max_size: u64,
+    files: u64,
+    file: File,
+    size: u64,
+    /// Interfaces described in the current file, by their ID there.
+    ifaces: Vec<String>,
+}
+
+impl Capture {
+    /// Starts capturing to `path`, the capture found there being rotated
+    /// rather than overwritten. The file is rotated once writing a packet
+    /// would make it larger than `max_size` bytes, keeping `files` of them,
+    /// `path.1` being the most recent before `path`.
+    pub fn create(path: &Path, max_size: u64, files: u64) -> io::Result<Self> {
+        let exists = fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0);
+        if exists {
+            rotate(path, files)?;
+        }
+        let (file, size) = open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_size,
+            files,
+            file,
+            size,
+            ifaces: Vec::new(),
+        })
+    }
+
+    /// Appends the UDP datagram of `payload` from `src` to `dst`, as seen
+    /// on the interface `iface`.
+    pub fn write(
+        &mut self,
+        iface: &str,
+        src: SocketAddrV4,
+        dst: SocketAddrV4,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let packet = udp_packet(src, dst, payload);
+        let block_len = 32 + padded(packet.len()) as u64;
+        if self.size + block_len > self.max_size && !self.ifaces.is_empty() {
+            rotate(&self.path, self.files)?;
+            (self.file, self.size) = open(&self.path)?;
+            self.ifaces.clear();
+        }
+
+        let iface_id = match self.ifaces.iter().position(|name| name == iface) {
+            Some(id) => id,
+            None => {
+                self.write_block(INTERFACE_DESCRIPTION_BLOCK, &interface_description(iface))?;
+                self.ifaces.push(iface.to_string());
+                self.ifaces.len() - 1
+            }
+        };
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let mut body = Vec::with_capacity(20 + packet.len());
+        body.extend((iface_id as u32).to_le_bytes());
+        body.extend(((micros >> 32) as u32).to_le_bytes());
+        body.extend((micros as u32).to_le_bytes());
+        body.extend((packet.len() as u32).to_le_bytes());
+        body.extend((packet.len() as u32).to_le_bytes());
+        body.extend(packet);
+        self.write_block(ENHANCED_PACKET_BLOCK, &body)
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let block = block(block_type, body);
+        self.file.write_all(&block)?;
+        self.size += block.len() as u64;
+        Ok(())
+    }
+}
+
+/// Starts capturing to the `pcap_file` of `conf`, when set. Only the first
+/// call applies, the capture being read on start only.
+pub fn start(conf: &Conf) -> Result<()> {
+    let Some(path) = conf.get_pcap_file() else {
+        return Ok(());
+    };
+    if CAPTURE.get().is_some() {
+        return Ok(());
+    }
+    let capture = Capture::create(path, conf.get_pcap_max_size(), conf.get_pcap_files())
+        .context(format!("Opening the capture file {}", path.display()))?;
+    let _ = CAPTURE.set(Mutex::new(capture));
+    let _ = CAPTURE_TFTP.set(conf.get_pcap_tftp());
+    match conf.get_pcap_tftp() {
+        true => info!(
+            "Capturing the DHCP and TFTP requests to {}.",
+            path.display()
+        ),
+        false => info!("Capturing the DHCP messages to {}.", path.display()),
+    }
+    Ok(())
+}
+
+/// Captures the DHCP message of `payload` from `src` to `dst` on the
+/// interface `iface`, when capturing.
+pub fn record(iface: &str, src: SocketAddr, dst: SocketAddr, payload: &[u8]) {
+    let Some(capture) = CAPTURE.get() else {
+        return;
+    };
+    let (SocketAddr::V4(src), SocketAddr::V4(dst)) = (src, dst) else {
+        return;
+    };
+    let mut capture = capture.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(e) = capture.write(iface, src, dst, payload) {
+        warn!("Failed capturing to {}: {e}", capture.path.display());
+    }
+}
+
+/// Where a TFTP server listens, for its requests to be captured.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TftpEndpoint {
+    pub iface: String,
+    pub ip: Ipv4Addr,
+}
+
+/// The endpoint of the TFTP server on `ip`, `None` unless `pcap_tftp` is
+/// set. The interface is the one with `ip`, `any` for the unspecified
+/// address of a socket handed by systemd.
+pub fn tftp_endpoint(ip: Ipv4Addr) -> Option<TftpEndpoint> {
+    if !CAPTURE_TFTP.get().copied().unwrap_or_default() {
+        return None;
+    }
+    let iface = NetworkInterface::show()
+        .unwrap_or_default()
+        .into_iter()
+        .find(|iface| {
+            iface
+                .addr
+                .iter()
+                .any(|addr| matches!(addr, Addr::V4(v4) if v4.ip == ip))
+        })
+        .map(|iface| iface.name)
+        .unwrap_or_else(|| "any".to_string());
+    Some(TftpEndpoint { iface, ip })
+}
+
+/// Captures the TFTP request of `client` for `path` to `endpoint`. The
+/// request is rebuilt from what the TFTP server passes on, in octet mode
+/// and with the `tsize` of a write request, its other options and the
+/// packets of the transfer not being captured.
+pub fn record_tftp(
+    endpoint: &TftpEndpoint,
+    client: &SocketAddr,
+    request: TftpRequest,
+    path: &Path,
+    size: Option<u64>,
+) {
+    let mut payload = (request as u16).to_be_bytes().to_vec();
+    payload.extend(path.to_string_lossy().as_bytes());
+    payload.extend(b"\0octet\0");
+    if let Some(size) = size {
+        payload.extend(format!("tsize\0{size}\0").as_bytes());
+    }
+    let server = SocketAddr::new(IpAddr::V4(endpoint.ip), TFTP_PORT);
+    record(&endpoint.iface, *client, server, &payload);
+}
+
+fn open(path: &Path) -> io::Result<(File, u64)> {
+    let mut file = File::create(path)?;
+    let mut body = Vec::with_capacity(16);
+    body.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+    body.extend(1u16.to_le_bytes());
+    body.extend(0u16.to_le_bytes());
+    // Section length not known
+    body.extend((-1i64).to_le_bytes());
+    let header = block(SECTION_HEADER_BLOCK, &body);
+    file.write_all(&header)?;
+    Ok((file, header.len() as u64))
+}
+
+fn interface_description(iface: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend(LINKTYPE_RAW.to_le_bytes());
+    body.extend(0u16.to_le_bytes());
+    // No snapshot length limit
+    body.extend(0u32.to_le_bytes());
+    body.extend(OPTION_IF_NAME.to_le_bytes());
+    body.extend((iface.len() as u16).to_le_bytes());
+    body.extend(iface.as_bytes());
+    body.resize(padded(body.len()), 0);
+    // End of the options
+    body.extend(0u32.to_le_bytes());
+    body
+}
+
+/// The block of `block_type` around `body`, padded to 32 bits.
+fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let len = (12 + padded(body.len())) as u32;
+    let mut block = Vec::with_capacity(len as usize);
+    block.extend(block_type.to_le_bytes());
+    block.extend(len.to_le_bytes());
+    block.extend(body);
+    block.resize(8 + padded(body.len()), 0);
+    block.extend(len.to_le_bytes());
+    block
+}
+
+fn padded(len: usize) -> usize {
+    len.div_ceil(4) * 4
+}
+
+/// The IPv4 packet carrying `payload` from `src` to `dst` over UDP, its
+/// UDP checksum left out as IPv4 allows.
+fn udp_packet(src: SocketAddrV4, dst: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+    let udp_len = (UDP_HEADER_LEN + payload.len()) as u16;
+    let total_len = IPV4_HEADER_LEN as u16 + udp_len;
+    let mut packet = Vec::with_capacity(total_len as usize);
+    packet.extend([0x45, 0]);
+    packet.extend(total_len.to_be_bytes());
+    // Identification, then no fragmentation
+    packet.extend([0, 0, 0x40, 0]);
+    packet.extend([64, 17, 0, 0]);
+    packet.extend(src.ip().octets());
+    packet.extend(dst.ip().octets());
+    let checksum = ipv4_checksum(&packet);
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    packet.extend(src.port().to_be_bytes());
+    packet.extend(dst.port().to_be_bytes());
+    packet.extend(udp_len.to_be_bytes());
+    packet.extend([0, 0]);
+    packet.extend(payload);
+    packet
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|word| u32::from(u16::from_be_bytes([word[0], word[1]])))
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
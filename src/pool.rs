@@ -0,0 +1,74 @@
+//! Buffers of the DHCP messages received and sent, kept once a message is
+//! done with for the next one rather than freed, so a boot storm of
+//! hundreds of clients doesn't allocate and free a buffer for each of their
+//! messages.
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Mutex,
+};
+
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    /// Capacity the buffers are created with.
+    capacity: usize,
+    /// Buffers kept at most, those beyond being freed.
+    max_kept: usize,
+}
+
+impl BufferPool {
+    pub const fn new(capacity: usize, max_kept: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::new()),
+            capacity,
+            max_kept,
+        }
+    }
+
+    /// An empty buffer, one kept when there's one, given back to the pool
+    /// once dropped.
+    pub fn take(&self) -> PooledBuffer<'_> {
+        let buf = self
+            .buffers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(self.capacity));
+        PooledBuffer { buf, pool: self }
+    }
+
+    /// Number of the buffers kept.
+    pub fn kept(&self) -> usize {
+        self.buffers.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+/// A buffer of a [`BufferPool`].
+pub struct PooledBuffer<'a> {
+    buf: Vec<u8>,
+    pool: &'a BufferPool,
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+        let mut buffers = self.pool.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        if buffers.len() < self.pool.max_kept {
+            buffers.push(buf);
+        }
+    }
+}
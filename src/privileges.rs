@@ -0,0 +1,151 @@
+//! Dropping the rights of root once the services are started, with `user`
+//! and `group`: the sockets of ports 67, 68, 69 and 80 are bound by then,
+//! so the process serves on from the descriptors it keeps open as an
+//! account that can neither bind them again nor write outside what it owns.
+use std::path::Path;
+
+use crate::Result;
+
+/// The IDs of the account the server runs as.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Account {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Looks up the account of `user`, a name or a numeric ID, with the group
+/// `group`, a name or a numeric ID as well, or else the primary group of
+/// the user.
+#[cfg(unix)]
+pub fn lookup(user: &str, group: Option<&str>) -> Result<Account> {
+    let (uid, primary_gid) = match user.parse::<u32>() {
+        Ok(uid) => (uid, unix::primary_gid(uid)?),
+        Err(_) => unix::user_ids(user)?.ok_or(anyhow!("No user named {user}"))?,
+    };
+    let gid = match group {
+        Some(group) => match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => unix::group_id(group)?.ok_or(anyhow!("No group named {group}"))?,
+        },
+        None => primary_gid.ok_or(anyhow!("User {user} has no account, group needs to be configured"))?,
+    };
+
+    Ok(Account { uid, gid })
+}
+
+/// Runs as `user` and `group` from now on, when `user` is configured, the
+/// `owned` files written as root being handed over so they can be removed
+/// on exit.
+#[cfg(unix)]
+pub fn drop_to(user: Option<&str>, group: Option<&str>, owned: &[&Path]) -> Result<()> {
+    use anyhow::Context;
+    use log::info;
+
+    let Some(user) = user else {
+        return Ok(());
+    };
+    let account = lookup(user, group)?;
+    for path in owned {
+        std::os::unix::fs::chown(path, Some(account.uid), Some(account.gid))
+            .context(format!("Handing {} over to {user}", path.display()))?;
+    }
+    unix::set_ids(account).context(format!("Dropping privileges to {user}"))?;
+    info!("Running as {user} (uid {}, gid {}).", account.uid, account.gid);
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_to(user: Option<&str>, _group: Option<&str>, _owned: &[&Path]) -> Result<()> {
+    match user {
+        Some(_) => bail!("user and group are only supported on Unix"),
+        None => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{ffi::CString, io, mem, ptr};
+
+    use super::Account;
+    use crate::Result;
+
+    /// Large enough for the entries of any account database.
+    const BUFFER_SIZE: usize = 16 * 1024;
+
+    /// The UID and primary GID of the user named `name`, `None` when there
+    /// is no such user.
+    pub fn user_ids(name: &str) -> Result<Option<(u32, Option<u32>)>> {
+        let name = CString::new(name)?;
+        // SAFETY: a zeroed passwd is valid, getpwnam_r fills it
+        let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut buf = vec![0 as libc::c_char; BUFFER_SIZE];
+        let mut found = ptr::null_mut();
+        // SAFETY: the pointers are of buffers living through the call, of the lengths given
+        let code =
+            unsafe { libc::getpwnam_r(name.as_ptr(), &mut passwd, buf.as_mut_ptr(), buf.len(), &mut found) };
+        match (code, found.is_null()) {
+            (0, true) => Ok(None),
+            (0, false) => Ok(Some((passwd.pw_uid, Some(passwd.pw_gid)))),
+            (code, _) => Err(io::Error::from_raw_os_error(code).into()),
+        }
+    }
+
+    /// The primary GID of the user of `uid`, `None` for an ID without an
+    /// account, as those of containers often are.
+    pub fn primary_gid(uid: u32) -> Result<Option<u32>> {
+        // SAFETY: a zeroed passwd is valid, getpwuid_r fills it
+        let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut buf = vec![0 as libc::c_char; BUFFER_SIZE];
+        let mut found = ptr::null_mut();
+        // SAFETY: the pointers are of buffers living through the call, of the lengths given
+        let code = unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut found) };
+        match (code, found.is_null()) {
+            (0, true) => Ok(None),
+            (0, false) => Ok(Some(passwd.pw_gid)),
+            (code, _) => Err(io::Error::from_raw_os_error(code).into()),
+        }
+    }
+
+    /// The GID of the group named `name`, `None` when there is no such
+    /// group.
+    pub fn group_id(name: &str) -> Result<Option<u32>> {
+        let name = CString::new(name)?;
+        // SAFETY: a zeroed group is valid, getgrnam_r fills it
+        let mut group: libc::group = unsafe { mem::zeroed() };
+        let mut buf = vec![0 as libc::c_char; BUFFER_SIZE];
+        let mut found = ptr::null_mut();
+        // SAFETY: the pointers are of buffers living through the call, of the lengths given
+        let code =
+            unsafe { libc::getgrnam_r(name.as_ptr(), &mut group, buf.as_mut_ptr(), buf.len(), &mut found) };
+        match (code, found.is_null()) {
+            (0, true) => Ok(None),
+            (0, false) => Ok(Some(group.gr_gid)),
+            (code, _) => Err(io::Error::from_raw_os_error(code).into()),
+        }
+    }
+
+    /// Replaces the groups then the user of the process, the supplementary
+    /// groups of root being cleared, and checks root can't be taken back.
+    pub fn set_ids(account: Account) -> Result<()> {
+        let gid = account.gid as libc::gid_t;
+        // SAFETY: the list is the one GID living through the call
+        if unsafe { libc::setgroups(1, &gid) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+        // SAFETY: setgid and setuid have no preconditions, they apply to every thread
+        if unsafe { libc::setgid(gid) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+        // SAFETY: as above
+        if unsafe { libc::setuid(account.uid as libc::uid_t) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+        // SAFETY: as above, it fails once root is given up
+        if account.uid != 0 && unsafe { libc::setuid(0) } != -1 {
+            bail!("Root could be taken back");
+        }
+
+        Ok(())
+    }
+}
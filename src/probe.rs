@@ -0,0 +1,219 @@
+//! `probe` subcommand: broadcasts a DISCOVER of a PXE client on an interface
+//! and reports the DHCP servers offering, with whether they give boot
+//! information, to find the servers a deployment would conflict with.
+use std::{
+    net::Ipv4Addr,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_std::{future::timeout, task};
+use dhcproto::v4::{
+    Decodable, Decoder, DhcpOption, Encodable, Encoder, Flags, Message, MessageType, Opcode,
+    OptionCode,
+};
+use network_interface::{NetworkInterface, NetworkInterfaceConfig};
+
+use crate::{conf::MacAddress, dhcp, template, util::bytes_to_mac_address, Result};
+
+/// The DISCOVER is that of a UEFI x64 PXE client, which the proxy DHCP
+/// servers answer too.
+const PXE_CLIENT_CLASS: &str = "PXEClient:Arch:00007:UNDI:003016";
+const CLIENT_ARCH_EFI_X64: u16 = 7;
+
+/// A DHCP server answering the DISCOVER.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProbeAnswer {
+    /// Its server identifier, else the address the answer came from.
+    pub server: Ipv4Addr,
+    pub offered_ip: Option<Ipv4Addr>,
+    /// Boot file of option 67 or of the `file` field.
+    pub boot_file: Option<String>,
+    /// TFTP server of option 66 or of the `siaddr` field.
+    pub boot_server: Option<String>,
+    /// Whether it answers as a PXE server, with the `PXEClient` class.
+    pub is_pxe: bool,
+    pub options: Vec<OptionCode>,
+}
+
+impl ProbeAnswer {
+    /// What the server sending `message` from `source` gives.
+    pub fn from_message(message: &Message, source: Ipv4Addr) -> Self {
+        let opts = message.opts();
+        let server = match opts.get(OptionCode::ServerIdentifier) {
+            Some(DhcpOption::ServerIdentifier(ip)) => *ip,
+            _ => source,
+        };
+        let boot_file = match opts.get(OptionCode::BootfileName) {
+            Some(DhcpOption::BootfileName(name)) => Some(String::from_utf8_lossy(name).to_string()),
+            _ => message
+                .fname_str()
+                .and_then(|name| name.ok())
+                .map(|name| name.trim_end_matches('\0').to_string()),
+        };
+        let boot_server = match opts.get(OptionCode::TFTPServerName) {
+            Some(DhcpOption::TFTPServerName(name)) => {
+                Some(String::from_utf8_lossy(name).to_string())
+            }
+            _ => Some(message.siaddr())
+                .filter(|ip| !ip.is_unspecified())
+                .map(|ip| ip.to_string()),
+        };
+        let is_pxe = matches!(
+            opts.get(OptionCode::ClassIdentifier),
+            Some(DhcpOption::ClassIdentifier(class)) if class.starts_with(b"PXEClient")
+        );
+        let mut options: Vec<OptionCode> = opts.iter().map(|(code, _)| *code).collect();
+        options.sort_by_key(|code| u8::from(*code));
+
+        Self {
+            server,
+            offered_ip: Some(message.yiaddr()).filter(|ip| !ip.is_unspecified()),
+            boot_file: boot_file.filter(|name| !name.is_empty()),
+            boot_server,
+            is_pxe,
+            options,
+        }
+    }
+
+    pub fn has_boot_info(&self) -> bool {
+        self.boot_file.is_some() || self.is_pxe
+    }
+}
+
+/// The DISCOVER of the client `mac`, with the transaction ID `xid`.
+pub fn discover_message(mac: &MacAddress, xid: u32) -> Message {
+    let mut message = Message::default();
+    message
+        .set_opcode(Opcode::BootRequest)
+        .set_xid(xid)
+        .set_flags(Flags::new(0).set_broadcast())
+        .set_chaddr(mac);
+    let opts = message.opts_mut();
+    opts.insert(DhcpOption::MessageType(MessageType::Discover));
+    opts.insert(DhcpOption::ParameterRequestList(vec![
+        OptionCode::SubnetMask,
+        OptionCode::Router,
+        OptionCode::DomainNameServer,
+        OptionCode::ClassIdentifier,
+        OptionCode::TFTPServerName,
+        OptionCode::BootfileName,
+    ]));
+    opts.insert(DhcpOption::ClassIdentifier(
+        PXE_CLIENT_CLASS.as_bytes().to_vec(),
+    ));
+    opts.insert(DhcpOption::ClientSystemArchitecture(
+        CLIENT_ARCH_EFI_X64.into(),
+    ));
+
+    message
+}
+
+/// The answer to the DISCOVER `xid` in `message`, sent from `source`.
+pub fn parse_answer(message: &Message, xid: u32, source: Ipv4Addr) -> Option<ProbeAnswer> {
+    let opts = message.opts();
+    if message.opcode() != Opcode::BootReply
+        || message.xid() != xid
+        || opts.msg_type() != Some(MessageType::Offer)
+    {
+        return None;
+    }
+
+    Some(ProbeAnswer::from_message(message, source))
+}
+
+/// Broadcasts a DISCOVER on `iface_name` and gathers the answers for `wait`.
+pub fn probe(iface_name: &str, wait: Duration) -> Result<(MacAddress, Vec<ProbeAnswer>)> {
+    let iface = NetworkInterface::show()
+        .context("Listing network interfaces")?
+        .into_iter()
+        .find(|iface| iface.name == iface_name)
+        .ok_or(anyhow!("No network interface {iface_name}"))?;
+    let mac = iface
+        .mac_addr
+        .as_deref()
+        .and_then(template::parse_mac)
+        .ok_or(anyhow!("No MAC address found on interface {iface_name}"))?;
+    let xid = rand::random();
+    let socket = dhcp::socket_from_iface_ip(&iface, &"0.0.0.0:68")?;
+
+    let mut buf = Vec::new();
+    discover_message(&mac, xid).encode(&mut Encoder::new(&mut buf))?;
+    let answers = task::block_on(async {
+        socket.send_to(&buf, "255.255.255.255:67").await?;
+        let mut answers: Vec<ProbeAnswer> = Vec::new();
+        let deadline = Instant::now() + wait;
+        let mut rcv_data = [0u8; 1500];
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            let Ok(received) = timeout(left, socket.recv_from(&mut rcv_data)).await else {
+                break;
+            };
+            let (len, peer) = received?;
+            let Ok(message) = Message::decode(&mut Decoder::new(&rcv_data[..len])) else {
+                continue;
+            };
+            let std::net::IpAddr::V4(source) = peer.ip() else {
+                continue;
+            };
+            // Servers retransmit their offers
+            match parse_answer(&message, xid, source) {
+                Some(answer) if !answers.contains(&answer) => answers.push(answer),
+                _ => {}
+            }
+        }
+
+        Result::Ok(answers)
+    })?;
+
+    Ok((mac, answers))
+}
+
+/// The servers of `answers` with what they offered, warning about those
+/// competing to give the boot information.
+pub fn report(answers: &[ProbeAnswer]) -> String {
+    if answers.is_empty() {
+        return "No DHCP server answered.\n".to_string();
+    }
+
+    let mut lines = Vec::new();
+    for answer in answers {
+        let kind = if answer.is_pxe { ", PXE" } else { "" };
+        lines.push(format!("Server {}{kind}", answer.server));
+        lines.push(match answer.offered_ip {
+            Some(ip) => format!("  Offered address: {ip}"),
+            None => "  Offered address: none, proxy DHCP".to_string(),
+        });
+        lines.push(match (&answer.boot_file, &answer.boot_server) {
+            (Some(file), Some(server)) => format!("  Boot information: {file} from {server}"),
+            (Some(file), None) => format!("  Boot information: {file}"),
+            (None, _) if answer.is_pxe => "  Boot information: from its PXE boot server".into(),
+            (None, _) => "  Boot information: none".to_string(),
+        });
+        let options: Vec<String> = answer
+            .options
+            .iter()
+            .map(|code| format!("{code:?} ({})", u8::from(*code)))
+            .collect();
+        lines.push(format!("  Options: {}", options.join(", ")));
+    }
+    let boot_servers = answers
+        .iter()
+        .filter(|answer| answer.has_boot_info())
+        .count();
+    if boot_servers > 1 {
+        lines.push(format!(
+            "Warning: {boot_servers} servers give boot information, the clients may boot from either."
+        ));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// The header of the report, telling what was probed.
+pub fn summary(iface_name: &str, mac: &MacAddress, wait: Duration, answers: usize) -> String {
+    format!(
+        "Sent a DISCOVER from {} on {iface_name}, {answers} answer(s) in {}s.\n",
+        bytes_to_mac_address(mac),
+        wait.as_secs()
+    )
+}
@@ -0,0 +1,204 @@
+//! Counts of what's in use by key, e.g. DHCP sessions by interface or TFTP
+//! transfers by client, each limited to its own quota so one key can't use
+//! up what the others share. The keys can expire once unused for a while,
+//! and their number be capped, the least recently used key making room for
+//! a new one.
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug)]
+pub struct QuotaMap<K> {
+    /// Quotas of the keys given one.
+    limits: HashMap<K, u64>,
+    /// Quota of the other keys, unlimited when `None`.
+    default_limit: Option<u64>,
+    used: HashMap<K, Usage>,
+    /// Time a key is kept for since it was last used, forever when `None`.
+    ttl: Option<Duration>,
+    /// Most keys counted at once, unlimited when `None`.
+    capacity: Option<usize>,
+    /// No key expires before then, for [`Self::expire`] not to look at them
+    /// all every time.
+    next_expiry: Option<Instant>,
+    /// Uses counted so far, telling apart the keys used at the same instant.
+    uses: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Usage {
+    count: u64,
+    last_used: Instant,
+    /// Value of `uses` when last used.
+    last_use: u64,
+}
+
+/// What [`QuotaMap::acquire`] did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Acquired<K> {
+    /// The use was counted.
+    Counted,
+    /// The use was counted, the least recently used key evicted for it.
+    Evicted(K),
+    /// The quota of the key is reached, or no key can be evicted.
+    Refused,
+}
+
+impl<K> Acquired<K> {
+    pub fn is_counted(&self) -> bool {
+        !matches!(self, Acquired::Refused)
+    }
+}
+
+impl<K: Eq + Hash + Clone> QuotaMap<K> {
+    /// Quotas of `limits` by key, the other keys limited to `default_limit`
+    /// when given.
+    pub fn new(limits: impl IntoIterator<Item = (K, u64)>, default_limit: Option<u64>) -> Self {
+        Self {
+            limits: limits.into_iter().collect(),
+            default_limit,
+            used: HashMap::new(),
+            ttl: None,
+            capacity: None,
+            next_expiry: None,
+            uses: 0,
+        }
+    }
+
+    /// Forgets the keys not used for `ttl`, see [`Self::expire`].
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Counts `capacity` keys at most, the least recently used one evicted
+    /// for a new one.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    pub fn limit(&self, key: &K) -> Option<u64> {
+        self.limits.get(key).copied().or(self.default_limit)
+    }
+
+    pub fn used(&self, key: &K) -> u64 {
+        self.used.get(key).map_or(0, |usage| usage.count)
+    }
+
+    /// When `key` was last used, none when it's not in use.
+    pub fn last_used(&self, key: &K) -> Option<Instant> {
+        self.used.get(key).map(|usage| usage.last_used)
+    }
+
+    /// Number of keys in use.
+    pub fn len(&self) -> usize {
+        self.used.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.used.is_empty()
+    }
+
+    /// Counts one more use of `key` at `now`, unless its quota is reached.
+    /// A new key over the capacity evicts the least recently used one.
+    pub fn acquire(&mut self, key: &K, now: Instant) -> Acquired<K> {
+        let used = self.used(key);
+        if self.limit(key).is_some_and(|limit| used >= limit) {
+            return Acquired::Refused;
+        }
+
+        let mut acquired = Acquired::Counted;
+        let is_full = self.capacity.is_some_and(|capacity| self.used.len() >= capacity);
+        if used == 0 && is_full {
+            match self.least_recently_used(|_| true) {
+                Some(evicted) => {
+                    self.used.remove(&evicted);
+                    acquired = Acquired::Evicted(evicted);
+                }
+                None => return Acquired::Refused,
+            }
+        }
+
+        self.uses += 1;
+        self.used.insert(
+            key.clone(),
+            Usage {
+                count: used + 1,
+                last_used: now,
+                last_use: self.uses,
+            },
+        );
+        if let Some(ttl) = self.ttl {
+            let expiry = now + ttl;
+            self.next_expiry = Some(self.next_expiry.map_or(expiry, |next| next.min(expiry)));
+        }
+        acquired
+    }
+
+    /// Tells `key` is used at `now`, putting off its expiry and eviction.
+    pub fn touch(&mut self, key: &K, now: Instant) {
+        if let Some(usage) = self.used.get_mut(key) {
+            self.uses += 1;
+            usage.last_used = usage.last_used.max(now);
+            usage.last_use = self.uses;
+        }
+    }
+
+    /// Counts one use of `key` less, as when it's done with what it acquired.
+    pub fn release(&mut self, key: &K) {
+        if let Some(usage) = self.used.get_mut(key) {
+            usage.count = usage.count.saturating_sub(1);
+            if usage.count == 0 {
+                self.used.remove(key);
+            }
+        }
+    }
+
+    /// Whether `key` wasn't used for the TTL at `now`, none expiring
+    /// without one.
+    pub fn is_expired(&self, key: &K, now: Instant) -> bool {
+        self.used
+            .get(key)
+            .is_some_and(|usage| self.has_expired(usage, now))
+    }
+
+    fn has_expired(&self, usage: &Usage, now: Instant) -> bool {
+        self.ttl
+            .is_some_and(|ttl| now.saturating_duration_since(usage.last_used) >= ttl)
+    }
+
+    /// Forgets the keys not used for the TTL at `now`, returning them.
+    pub fn expire(&mut self, now: Instant) -> Vec<K> {
+        let Some(ttl) = self.ttl else {
+            return Vec::new();
+        };
+        if self.next_expiry.is_none_or(|next| now < next) {
+            return Vec::new();
+        }
+
+        let expired: Vec<K> = self
+            .used
+            .iter()
+            .filter(|(_, usage)| self.has_expired(usage, now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            self.used.remove(key);
+        }
+        self.next_expiry = self.used.values().map(|usage| usage.last_used + ttl).min();
+        expired
+    }
+
+    /// The key used the longest ago among those `counted`, none when there's
+    /// none.
+    pub fn least_recently_used(&self, counted: impl Fn(&K) -> bool) -> Option<K> {
+        self.used
+            .iter()
+            .filter(|(key, _)| counted(key))
+            .min_by_key(|(_, usage)| (usage.last_used, usage.last_use))
+            .map(|(key, _)| key.clone())
+    }
+}
@@ -0,0 +1,133 @@
+//! Large read-ahead buffers for file transfers. TFTP reads files one block at
+//! a time, usually 512 bytes, each read going through the async runtime's
+//! blocking thread pool. Reading ahead in large chunks turns a multi-hundred-MB
+//! download into a few thousand reads instead of hundreds of thousands.
+use std::{
+    io,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+};
+
+use futures::AsyncRead;
+use once_cell::sync::Lazy;
+
+pub const READ_AHEAD_SIZE: usize = 128 * 1024;
+/// Buffers kept for reuse once their transfers end, any above are freed.
+const MAX_IDLE_BUFFERS: usize = 16;
+
+/// Buffers shared by all file transfers.
+pub static READ_AHEAD_POOL: Lazy<Arc<BufferPool>> =
+    Lazy::new(|| BufferPool::new(READ_AHEAD_SIZE, MAX_IDLE_BUFFERS));
+
+/// Recycles equally sized buffers so transfers don't allocate and zero a
+/// new one each time.
+pub struct BufferPool {
+    idle: Mutex<Vec<Vec<u8>>>,
+    buffer_size: usize,
+    max_idle: usize,
+}
+
+impl BufferPool {
+    pub fn new(buffer_size: usize, max_idle: usize) -> Arc<Self> {
+        Arc::new(Self {
+            idle: Default::default(),
+            buffer_size,
+            max_idle,
+        })
+    }
+
+    pub fn take(pool: &Arc<Self>) -> PooledBuffer {
+        let buffer = pool
+            .idle
+            .lock()
+            .ok()
+            .and_then(|mut idle| idle.pop())
+            .unwrap_or_else(|| vec![0u8; pool.buffer_size]);
+
+        PooledBuffer {
+            buffer,
+            pool: Arc::clone(pool),
+        }
+    }
+
+    pub fn idle_buffers(&self) -> usize {
+        self.idle.lock().map(|idle| idle.len()).unwrap_or(0)
+    }
+}
+
+/// Buffer returned to its pool when dropped.
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Ok(mut idle) = self.pool.idle.lock() {
+            if idle.len() < self.pool.max_idle {
+                idle.push(std::mem::take(&mut self.buffer));
+            }
+        }
+    }
+}
+
+/// Reader filling a pooled buffer from `inner` in large chunks and handing
+/// it out in the small reads the caller asks for.
+pub struct ReadAhead<R> {
+    inner: R,
+    buffer: PooledBuffer,
+    pos: usize,
+    filled: usize,
+}
+
+impl<R: AsyncRead + Unpin> ReadAhead<R> {
+    pub fn new(inner: R, pool: &Arc<BufferPool>) -> Self {
+        Self {
+            inner,
+            buffer: BufferPool::take(pool),
+            pos: 0,
+            filled: 0,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ReadAhead<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.pos == this.filled {
+            // Nothing gained from buffering reads larger than the buffer
+            if buf.len() >= this.buffer.len() {
+                return Pin::new(&mut this.inner).poll_read(cx, buf);
+            }
+
+            this.filled = ready!(Pin::new(&mut this.inner).poll_read(cx, &mut this.buffer))?;
+            this.pos = 0;
+        }
+
+        let len = buf.len().min(this.filled - this.pos);
+        buf[..len].copy_from_slice(&this.buffer[this.pos..this.pos + len]);
+        this.pos += len;
+
+        Poll::Ready(Ok(len))
+    }
+}
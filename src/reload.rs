@@ -0,0 +1,265 @@
+use std::{
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use async_signal::{Signal, Signals};
+use async_std::{future, task};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::Either,
+    StreamExt,
+};
+use log::{debug, error, info, warn};
+
+use crate::{
+    conf::Conf,
+    dhcp::{self, SharedConf},
+    dns::DnsService,
+    http::HttpService,
+    images::ImageService,
+    overrides::Overrides,
+    remote::ConfSource,
+    tftp::TftpService,
+    util, Result,
+};
+
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Remote sources are shared by many servers, they're polled less often.
+const REMOTE_CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What tells the configuration changed.
+#[derive(PartialEq)]
+enum Version {
+    /// Latest modification of the configuration file or its includes.
+    Modified(SystemTime),
+    /// Content of a remote source.
+    Content(String),
+}
+
+/// What made the configuration be checked.
+enum Trigger {
+    Poll,
+    Hangup,
+    /// A reload asked for, answered with its outcome.
+    Request(oneshot::Sender<Result<()>>),
+}
+
+/// Asks [`watch_config`] to reload the configuration, as `reload` does on
+/// the control socket.
+#[derive(Clone)]
+pub struct Reloader(mpsc::UnboundedSender<oneshot::Sender<Result<()>>>);
+
+/// The reloads asked for by the [`Reloader`]s.
+pub type ReloadRequests = mpsc::UnboundedReceiver<oneshot::Sender<Result<()>>>;
+
+pub fn reloader() -> (Reloader, ReloadRequests) {
+    let (sender, receiver) = mpsc::unbounded();
+    (Reloader(sender), receiver)
+}
+
+impl Reloader {
+    /// Reloads the configuration, failing with why it wasn't applied.
+    pub async fn reload(&self) -> Result<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.0
+            .unbounded_send(sender)
+            .map_err(|_| anyhow!("The configuration isn't watched"))?;
+        receiver
+            .await
+            .map_err(|_| anyhow!("The reload was dropped"))?
+    }
+}
+
+/// The services run with the configuration of the top level or of one of
+/// the tenants.
+pub struct Services {
+    pub shared_conf: SharedConf,
+    pub tftp: TftpService,
+    pub http: HttpService,
+    pub images: ImageService,
+    pub dns: DnsService,
+}
+
+impl Services {
+    async fn reload(&mut self, conf: &Conf) -> Result<()> {
+        self.tftp.reload(conf).await?;
+        self.http.reload(conf).await?;
+        self.images.reload(conf).await?;
+        self.dns.reload(conf).await?;
+        if let Ok(mut current) = self.shared_conf.write() {
+            *current = Arc::new(conf.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Polls the configuration file and its include directory, or the remote
+/// source, and the `hosts` files of its `match` entries, and applies them to
+/// the running services, by tenant name, whenever they change, or when the
+/// process receives SIGHUP or a reload is asked for by `requests`. Invalid
+/// configurations are reported and skipped, to the requester as well, the
+/// services keep running with the last valid one. DHCP sessions and TFTP transfers in progress carry on.
+/// Adding or removing tenants needs a restart. The addresses of the
+/// interfaces are polled as well, the services listening on those that come
+/// up, as right after boot, and no longer on those gone.
+pub async fn watch_config(
+    source: ConfSource,
+    overrides: Overrides,
+    mut services: Vec<(Option<String>, Services)>,
+    mut requests: ReloadRequests,
+) {
+    let poll_interval = match source.is_remote() {
+        true => REMOTE_CONFIG_POLL_INTERVAL,
+        false => CONFIG_POLL_INTERVAL,
+    };
+    let mut host_files = host_files(&services);
+    let mut last_version = (version(&source).await, latest_modification(&host_files));
+    let mut last_addresses = listen_addresses(&services);
+    let mut signals = Signals::new([Signal::Hup])
+        .inspect_err(|e| warn!("Not reloading the configuration on SIGHUP: {e}"))
+        .ok();
+    debug!("Watching configuration {source} for changes.");
+
+    loop {
+        let trigger = next_trigger(signals.as_mut(), &mut requests, poll_interval).await;
+        let addresses = listen_addresses(&services);
+        if addresses != last_addresses {
+            last_addresses = addresses;
+            info!("The addresses of the interfaces changed, applying them.");
+            for (_, services) in services.iter_mut() {
+                let conf = dhcp::current_conf(&services.shared_conf);
+                if let Err(e) = services.reload(&conf).await {
+                    error!("Not applying the addresses of the interfaces: {e}");
+                }
+            }
+        }
+
+        let current_version = (version(&source).await, latest_modification(&host_files));
+        if current_version == last_version && matches!(trigger, Trigger::Poll) {
+            continue;
+        }
+        last_version = current_version;
+
+        match trigger {
+            Trigger::Poll => info!("Configuration {source} changed, reloading."),
+            Trigger::Hangup => info!("SIGHUP received, reloading {source}."),
+            Trigger::Request(_) => info!("Reload asked on the control socket, reloading {source}."),
+        }
+        let result: Result<()> = async {
+            let (source, overrides) = (source.clone(), overrides.clone());
+            let conf =
+                task::spawn_blocking(move || Conf::from_source_with(&source, &overrides)).await?;
+            conf.validate()?;
+            let served = conf.served();
+            let names = served.iter().map(|(name, _)| *name);
+            if !names.eq(services.iter().map(|(name, _)| name.as_deref())) {
+                bail!("The tenants changed, restart preboot-oxide to apply it");
+            }
+            for ((_, conf), (_, services)) in served.into_iter().zip(services.iter_mut()) {
+                services.reload(conf).await?;
+            }
+            Ok(())
+        }
+        .await;
+
+        match &result {
+            Ok(()) => {
+                info!("Configuration reloaded.");
+                host_files = self::host_files(&services);
+                last_version.1 = latest_modification(&host_files);
+                last_addresses = listen_addresses(&services);
+            }
+            Err(e) => error!("Not applying configuration changes: {e}"),
+        }
+        if let Trigger::Request(answer) = trigger {
+            let _ = answer.send(result);
+        }
+    }
+}
+
+/// Waits for SIGHUP or a reload request, for up to `poll_interval`.
+async fn next_trigger(
+    signals: Option<&mut Signals>,
+    requests: &mut ReloadRequests,
+    poll_interval: Duration,
+) -> Trigger {
+    let hangup = std::pin::pin!(async {
+        if let Some(signals) = signals {
+            if signals.next().await.is_some() {
+                return Trigger::Hangup;
+            }
+        }
+        futures::future::pending().await
+    });
+    let request = std::pin::pin!(async {
+        match requests.next().await {
+            Some(answer) => Trigger::Request(answer),
+            None => futures::future::pending().await,
+        }
+    });
+    let either = futures::future::select(hangup, request);
+    match future::timeout(poll_interval, either).await {
+        Ok(Either::Left((trigger, _)) | Either::Right((trigger, _))) => trigger,
+        Err(_) => Trigger::Poll,
+    }
+}
+
+/// The addresses the services of each tenant listen on, none when the
+/// interfaces can't be listed.
+fn listen_addresses(services: &[(Option<String>, Services)]) -> Vec<Vec<Ipv4Addr>> {
+    services
+        .iter()
+        .map(|(_, services)| util::listen_ips(&dhcp::current_conf(&services.shared_conf)).unwrap_or_default())
+        .collect()
+}
+
+/// Version of the configuration, `None` when it can't be read, e.g. the
+/// remote source isn't reachable.
+async fn version(source: &ConfSource) -> Option<Version> {
+    match source {
+        ConfSource::File(path) => modified_time(path).map(Version::Modified),
+        _ => {
+            let remote = source.clone();
+            task::spawn_blocking(move || remote.fetch())
+                .await
+                .inspect_err(|e| warn!("Not checking {source} for changes: {e}"))
+                .ok()
+                .map(Version::Content)
+        }
+    }
+}
+
+/// Latest modification of the configuration file and its include directory,
+/// the directory itself changing when files are added or removed.
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    let include_dir = Conf::include_dir(path);
+    let paths: Vec<PathBuf> = std::fs::read_dir(&include_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .chain([include_dir.clone(), path.to_path_buf()])
+        .collect();
+    latest_modification(&paths)
+}
+
+fn latest_modification(paths: &[PathBuf]) -> Option<SystemTime> {
+    paths
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+/// The `hosts` files of the configurations served.
+fn host_files(services: &[(Option<String>, Services)]) -> Vec<PathBuf> {
+    services
+        .iter()
+        .filter_map(|(_, services)| services.shared_conf.read().ok())
+        .flat_map(|conf| {
+            let files = conf.get_host_files();
+            files.into_iter().map(Path::to_path_buf).collect::<Vec<PathBuf>>()
+        })
+        .collect()
+}
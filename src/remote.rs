@@ -0,0 +1,127 @@
+//! Sources of the configuration told by `PO_CONF_PATH`: a file, or for
+//! fleets of boot servers sharing theirs, an HTTP(S) URL or a key of etcd or
+//! Consul, polled for changes.
+use std::{fmt, path::Path, str::FromStr, time::Duration};
+
+use anyhow::Context;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use log::debug;
+
+use crate::{conf::ConfFormat, util::redact_url, Result};
+
+/// Time a remote source is waited for.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Environment variable of the ACL token of Consul, as for its own CLI.
+const CONSUL_TOKEN_VAR: &str = "CONSUL_HTTP_TOKEN";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfSource {
+    File(std::path::PathBuf),
+    /// `http://` or `https://` URL the configuration is fetched from.
+    Http(String),
+    /// Key of etcd, given as `etcd://host:port/key`, read through the JSON
+    /// gateway of its v3 API.
+    Etcd { endpoint: String, key: String },
+    /// Key of the KV store of Consul, given as `consul://host:port/key`.
+    Consul { endpoint: String, key: String },
+}
+
+impl FromStr for ConfSource {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let remote_key = |scheme: &str| -> Option<Result<(String, String)>> {
+            let rest = value.strip_prefix(scheme)?.strip_prefix("://")?;
+            Some(match rest.split_once('/') {
+                Some((host, key)) if !host.is_empty() && !key.is_empty() => {
+                    Ok((format!("http://{host}"), key.to_string()))
+                }
+                _ => Err(anyhow!("Expected {scheme}://host:port/key, got {value}")),
+            })
+        };
+
+        if value.starts_with("http://") || value.starts_with("https://") {
+            Ok(Self::Http(value.to_string()))
+        } else if let Some(parsed) = remote_key("etcd") {
+            let (endpoint, key) = parsed?;
+            Ok(Self::Etcd {
+                endpoint,
+                key: format!("/{key}"),
+            })
+        } else if let Some(parsed) = remote_key("consul") {
+            let (endpoint, key) = parsed?;
+            Ok(Self::Consul { endpoint, key })
+        } else {
+            Ok(Self::File(value.into()))
+        }
+    }
+}
+
+impl fmt::Display for ConfSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let host = |endpoint: &str| endpoint.trim_start_matches("http://").to_string();
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Http(url) => write!(f, "{}", redact_url(url)),
+            Self::Etcd { endpoint, key } => write!(f, "etcd://{}{key}", host(endpoint)),
+            Self::Consul { endpoint, key } => write!(f, "consul://{}/{key}", host(endpoint)),
+        }
+    }
+}
+
+impl ConfSource {
+    /// Whether the configuration is fetched over the network.
+    pub fn is_remote(&self) -> bool {
+        !matches!(self, Self::File(_))
+    }
+
+    /// The format told by the extension of the file, URL or key, YAML when
+    /// unknown.
+    pub fn format(&self) -> ConfFormat {
+        match self {
+            Self::File(path) => ConfFormat::of(path),
+            Self::Http(url) => {
+                let path = url.split(['?', '#']).next().unwrap_or_default();
+                ConfFormat::of(Path::new(path))
+            }
+            Self::Etcd { key, .. } | Self::Consul { key, .. } => ConfFormat::of(Path::new(key)),
+        }
+    }
+
+    /// Content of the configuration.
+    pub fn fetch(&self) -> Result<String> {
+        let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+        let content = match self {
+            Self::File(path) => std::fs::read_to_string(path)?,
+            Self::Http(url) => agent.get(url).call()?.into_string()?,
+            Self::Etcd { endpoint, key } => {
+                let body = serde_json::json!({ "key": BASE64_STANDARD.encode(key) });
+                let response: serde_json::Value = serde_json::from_str(
+                    &agent
+                        .post(&format!("{endpoint}/v3/kv/range"))
+                        .set("Content-Type", "application/json")
+                        .send_string(&body.to_string())?
+                        .into_string()?,
+                )
+                .context("Parsing the answer of etcd")?;
+                let value = response["kvs"][0]["value"]
+                    .as_str()
+                    .ok_or(anyhow!("Key {key} not found in etcd"))?;
+                String::from_utf8(BASE64_STANDARD.decode(value)?)?
+            }
+            Self::Consul { endpoint, key } => {
+                let mut request = agent.get(&format!("{endpoint}/v1/kv/{key}")).query("raw", "");
+                if let Ok(token) = std::env::var(CONSUL_TOKEN_VAR) {
+                    request = request.set("X-Consul-Token", &token);
+                }
+                match request.call() {
+                    Err(ureq::Error::Status(404, _)) => bail!("Key {key} not found in Consul"),
+                    response => response?.into_string()?,
+                }
+            }
+        };
+        debug!("Fetched {} bytes of configuration from {self}", content.len());
+
+        Ok(content)
+    }
+}
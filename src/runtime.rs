@@ -0,0 +1,87 @@
+//! Runtime the DHCP and TFTP services run their tasks on: async-std's, or,
+//! with the `tokio-runtime` feature, a multi-threaded Tokio runtime of one
+//! worker per CPU, for the messages of a mass boot to be answered across the
+//! cores. The other services stay on async-std either way, the sockets and
+//! timers of both services working on any runtime.
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(feature = "tokio-runtime")]
+use log::error;
+#[cfg(feature = "tokio-runtime")]
+use once_cell::sync::Lazy;
+
+/// The Tokio runtime, none when it failed to start, the tasks then being
+/// run on async-std.
+#[cfg(feature = "tokio-runtime")]
+static TOKIO: Lazy<Option<tokio::runtime::Runtime>> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .thread_name("po-worker")
+        .build()
+        .inspect_err(|e| error!("Tokio runtime failed, running on async-std instead: {e}"))
+        .ok()
+});
+
+/// A task of [`spawn`], detached when dropped.
+pub enum JoinHandle<T> {
+    AsyncStd(async_std::task::JoinHandle<T>),
+    #[cfg(feature = "tokio-runtime")]
+    Tokio(tokio::task::JoinHandle<T>),
+}
+
+/// Runs `future` in a task of its own on the runtime of the services.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(feature = "tokio-runtime")]
+    if let Some(runtime) = TOKIO.as_ref() {
+        return JoinHandle::Tokio(runtime.spawn(future));
+    }
+    JoinHandle::AsyncStd(async_std::task::spawn(future))
+}
+
+/// Name of the runtime the tasks of [`spawn`] run on.
+pub fn name() -> &'static str {
+    #[cfg(feature = "tokio-runtime")]
+    if TOKIO.is_some() {
+        return "Tokio";
+    }
+    "async-std"
+}
+
+impl<T> JoinHandle<T> {
+    /// Cancels the task, waiting for it to end. Its output when it ended
+    /// before.
+    pub async fn cancel(self) -> Option<T> {
+        match self {
+            JoinHandle::AsyncStd(handle) => handle.cancel().await,
+            #[cfg(feature = "tokio-runtime")]
+            JoinHandle::Tokio(handle) => {
+                handle.abort();
+                handle.await.ok()
+            }
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    /// The output of the task, which panics when the task did, as on
+    /// async-std.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        match self.get_mut() {
+            JoinHandle::AsyncStd(handle) => Pin::new(handle).poll(cx),
+            #[cfg(feature = "tokio-runtime")]
+            JoinHandle::Tokio(handle) => Pin::new(handle).poll(cx).map(|output| match output {
+                Ok(output) => output,
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            }),
+        }
+    }
+}
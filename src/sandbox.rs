@@ -0,0 +1,219 @@
+//! Confining the server once it's set up, with `sandbox`, as the TFTP and
+//! HTTP servers resolve the paths clients send by design. On Linux,
+//! Landlock limits the files the process can open to the TFTP roots, read
+//! and written for the uploads and images, to what is read again on
+//! reloads, and to the system directories, and a seccomp filter denies the
+//! system calls a server has no use for, such as loading kernel modules,
+//! mounting or tracing processes, and running programs without
+//! `event_hooks`.
+//! Both are applied before any thread is started, the threads inheriting
+//! them.
+use std::path::{Path, PathBuf};
+
+use crate::{conf::Conf, tls, Result};
+
+/// Read for name resolution, time zones, the accounts of `user`, the
+/// libraries and interpreters of `event_hooks`, and the sockets handed over
+/// to the server taking over.
+const SYSTEM_PATHS: [&str; 9] = [
+    "/etc",
+    "/usr",
+    "/lib",
+    "/lib64",
+    "/bin",
+    "/sbin",
+    "/dev/null",
+    "/dev/urandom",
+    "/proc/self/fd",
+];
+
+/// What the sandbox lets the process do with the files, directories
+/// standing for what's beneath them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Access {
+    pub read: Vec<PathBuf>,
+    pub write: Vec<PathBuf>,
+    /// Directories files are removed from and sockets created in, those of
+    /// the PID file and of the control socket.
+    pub remove: Vec<PathBuf>,
+    /// Whether programs can be run, for `event_hooks`.
+    pub exec: bool,
+}
+
+/// The access the services of `conf` and its tenants need, with the
+/// configuration read again from `conf_file`, and the PID file at
+/// `pid_file` removed on exit.
+pub fn access(conf: &Conf, conf_file: Option<&Path>, pid_file: Option<&Path>) -> Access {
+    let served = conf.served();
+    let mut read: Vec<PathBuf> = SYSTEM_PATHS.iter().map(PathBuf::from).collect();
+    read.extend(conf.read_paths());
+    if let Some(path) = conf_file {
+        read.push(path.to_path_buf());
+        read.push(Conf::include_dir(path));
+    }
+    read.extend(served.iter().filter_map(|(_, conf)| conf.get_boot_hook().cloned()));
+    read.extend(conf.get_event_hooks().values().cloned());
+
+    let mut write: Vec<PathBuf> = served
+        .iter()
+        .filter_map(|(_, conf)| conf.get_tftp_serve_path().map(PathBuf::from))
+        .collect();
+    let self_signed = served
+        .iter()
+        .any(|(_, conf)| conf.get_https_port().is_some() && conf.get_https_cert().is_none());
+    if self_signed {
+        write.extend(tls::self_signed_dir());
+    }
+    // Where the capture files, audit, traffic and event logs are rotated,
+    // and the boot statistics and assets replaced
+    let rotated = [
+        conf.get_pcap_file(),
+        conf.get_audit_log(),
+        conf.get_traffic_log(),
+        conf.get_event_log(),
+        conf.get_stats_file(),
+        conf.get_assets_file(),
+    ];
+    for dir in rotated.into_iter().flatten().filter_map(|path| path.parent()) {
+        match dir.as_os_str().is_empty() {
+            true => write.push(PathBuf::from(".")),
+            false => write.push(dir.to_path_buf()),
+        }
+    }
+
+    let remove = [pid_file, Some(conf.get_control_socket().as_path())]
+        .into_iter()
+        .flatten()
+        .filter_map(|path| path.parent().map(Path::to_path_buf))
+        .collect();
+    let exec = !conf.get_event_hooks().is_empty();
+
+    let dedup = |mut paths: Vec<PathBuf>| {
+        paths.sort();
+        paths.dedup();
+        paths
+    };
+    Access {
+        read: dedup(read),
+        write: dedup(write),
+        remove: dedup(remove),
+        exec,
+    }
+}
+
+/// Confines the process to `access`, those of the paths missing but the
+/// directories written to being left out. To be called before any thread
+/// is started, Landlock applying to the calling thread and those it starts.
+#[cfg(target_os = "linux")]
+pub fn apply(access: &Access) -> Result<()> {
+    use anyhow::Context;
+    use landlock::{
+        path_beneath_rules, Access as _, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus,
+        ABI,
+    };
+    use log::{info, warn};
+
+    // Such as the directory of the self-signed certificate, saved later
+    for dir in access.write.iter().filter(|dir| !dir.exists()) {
+        std::fs::create_dir_all(dir).context(format!("Creating {}", dir.display()))?;
+    }
+    let existing = |paths: &[PathBuf]| paths.iter().filter(|path| path.exists()).cloned().collect::<Vec<_>>();
+    let abi = ABI::V3;
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))?
+        .create()?
+        .add_rules(path_beneath_rules(existing(&access.read), AccessFs::from_read(abi)))?
+        .add_rules(path_beneath_rules(existing(&access.write), AccessFs::from_all(abi)))?
+        .add_rules(path_beneath_rules(
+            existing(&access.remove),
+            AccessFs::RemoveFile | AccessFs::MakeSock,
+        ))?
+        .restrict_self()
+        .context("Restricting the files with Landlock")?;
+    match status.ruleset {
+        RulesetStatus::NotEnforced => warn!("Landlock isn't enabled in the kernel, the files aren't restricted."),
+        _ => info!(
+            "Files restricted to {} with Landlock.",
+            access.write.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+        ),
+    }
+    seccomp::apply(access.exec).context("Filtering system calls with seccomp")?;
+    info!("System calls filtered with seccomp.");
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply(_access: &Access) -> Result<()> {
+    bail!("sandbox is only supported on Linux")
+}
+
+#[cfg(target_os = "linux")]
+mod seccomp {
+    use std::collections::BTreeMap;
+
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, TargetArch};
+
+    use crate::Result;
+
+    /// Denied with `EPERM`, none being needed to serve.
+    const DENIED: &[libc::c_long] = &[
+        libc::SYS_acct,
+        libc::SYS_add_key,
+        libc::SYS_adjtimex,
+        libc::SYS_bpf,
+        libc::SYS_chroot,
+        libc::SYS_clock_settime,
+        libc::SYS_delete_module,
+        libc::SYS_fanotify_init,
+        libc::SYS_finit_module,
+        libc::SYS_fsconfig,
+        libc::SYS_fsmount,
+        libc::SYS_fsopen,
+        libc::SYS_fspick,
+        libc::SYS_init_module,
+        libc::SYS_kexec_load,
+        libc::SYS_keyctl,
+        libc::SYS_mount,
+        libc::SYS_move_mount,
+        libc::SYS_name_to_handle_at,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_open_tree,
+        libc::SYS_perf_event_open,
+        libc::SYS_pivot_root,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_ptrace,
+        libc::SYS_quotactl,
+        libc::SYS_reboot,
+        libc::SYS_request_key,
+        libc::SYS_setns,
+        libc::SYS_settimeofday,
+        libc::SYS_swapoff,
+        libc::SYS_swapon,
+        libc::SYS_syslog,
+        libc::SYS_umount2,
+        libc::SYS_unshare,
+        libc::SYS_userfaultfd,
+    ];
+
+    /// Applies the filter to the process, `execve` being allowed with
+    /// `exec`. It sets `no_new_privs`, the programs run not gaining any.
+    pub fn apply(exec: bool) -> Result<()> {
+        let exec_calls = [libc::SYS_execve, libc::SYS_execveat];
+        let denied = DENIED.iter().chain(exec_calls.iter().filter(|_| !exec));
+        let rules = denied.map(|call| (*call, vec![])).collect::<BTreeMap<_, _>>();
+        let arch = TargetArch::try_from(std::env::consts::ARCH)
+            .map_err(|e| anyhow!("Unsupported architecture: {e:?}"))?;
+        let filter = SeccompFilter::new(
+            rules,
+            SeccompAction::Allow,
+            SeccompAction::Errno(libc::EPERM as u32),
+            arch,
+        )?;
+        let program: BpfProgram = filter.try_into()?;
+        seccompiler::apply_filter_all_threads(&program)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,443 @@
+//! Schema of the configuration file, checked before its fields are read so
+//! misspelled keys and values of the wrong type are reported, with their
+//! position in YAML files, rather than silently ignored.
+use std::collections::HashMap;
+
+use yaml_rust2::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::Marker,
+    Yaml,
+};
+
+use crate::Result;
+
+/// What a value of the configuration is expected to be. Empty (`null`)
+/// values are accepted everywhere, as if they were left out.
+#[derive(Clone, Copy)]
+enum Kind {
+    Str,
+    Int,
+    Bool,
+    /// String, number or boolean.
+    Scalar,
+    /// List of strings.
+    Strings,
+    /// String, number or boolean, or a list of them.
+    Values,
+    /// Mapping of the given keys to their values.
+    Table(&'static [(&'static str, Kind)]),
+    /// Mapping of any names to values of the given kind.
+    Map(&'static Kind),
+    /// List of values of the given kind.
+    List(&'static Kind),
+}
+
+use Kind::*;
+
+const ENTRY: Kind = Table(&[
+    ("boot_file", Str),
+    ("boot_server_ipv4", Str),
+    ("ipxe_script", Str),
+    ("grub_cfg", Str),
+    ("ignition", Str),
+    ("boot_iso", Str),
+    ("windows", Str),
+    ("menu_label", Str),
+    ("subnet_mask", Str),
+    ("lease_time", Int),
+    ("reply_delay_ms", Int),
+    ("log_level", Str),
+    ("vars", Map(&Scalar)),
+    ("pinned_images", Map(&Str)),
+    ("profile", Str),
+]);
+
+const ACTIVE: Kind = Table(&[
+    ("days", Strings),
+    ("hours", Str),
+    ("from", Str),
+    ("until", Str),
+    ("utc_offset", Str),
+]);
+
+const CANARY: Kind = Table(&[
+    ("percent", Int),
+    ("seed", Str),
+    ("profile", Str),
+    ("conf", ENTRY),
+]);
+
+static MATCH_ENTRY: Kind = Table(&[
+    ("select", Map(&Scalar)),
+    ("not", Map(&Values)),
+    ("any", List(&MATCH_GROUP)),
+    ("all", List(&MATCH_GROUP)),
+    ("active", ACTIVE),
+    ("conf", ENTRY),
+    ("profile", Str),
+    ("hosts", Str),
+    ("canary", CANARY),
+    ("match_type", Str),
+    ("regex", Bool),
+    ("glob", Bool),
+    ("priority", Int),
+]);
+
+/// Groups of `any` and `all` of match entries, which may nest more groups.
+static MATCH_GROUP: Kind = Table(&[
+    ("select", Map(&Scalar)),
+    ("not", Map(&Values)),
+    ("any", List(&MATCH_GROUP)),
+    ("all", List(&MATCH_GROUP)),
+    ("active", ACTIVE),
+    ("match_type", Str),
+    ("regex", Bool),
+    ("glob", Bool),
+]);
+
+const IMAGE: Kind = Table(&[
+    ("url", Str),
+    ("sha256", Str),
+    ("sha256sums_url", Str),
+    ("minisign_key", Str),
+]);
+
+/// `dhcp` and `tftp`, the services that can be turned off.
+const SERVICE: Kind = Table(&[("enabled", Bool)]);
+const EVENT_WEBHOOK: Kind = Table(&[("url", Str), ("events", Strings)]);
+const NOTIFICATION: Kind = Table(&[
+    ("kind", Str),
+    ("url", Str),
+    ("from", Str),
+    ("to", Strings),
+    ("events", Strings),
+]);
+
+const CONF: Kind = Table(&[
+    ("version", Int),
+    ("default", ENTRY),
+    ("defaults", Map(&ENTRY)),
+    ("profiles", Map(&ENTRY)),
+    ("match", List(&MATCH_ENTRY)),
+    ("match_policy", Str),
+    ("require_match", Bool),
+    ("ifaces", Strings),
+    ("max_sessions", Int),
+    ("max_sessions_per_iface", Map(&Int)),
+    ("max_sessions_per_subnet", Map(&Int)),
+    ("dhcp_max_handlers", Int),
+    ("pxe_boot_server", Bool),
+    ("pxe_boot_server_workers", Int),
+    ("dhcp", SERVICE),
+    ("tftp", SERVICE),
+    ("tftp_server_dir", Str),
+    ("tftp_symlinks", Str),
+    ("tftp_max_transfers", Int),
+    ("tftp_max_transfers_per_client", Int),
+    ("tftp_serve_hidden", Bool),
+    ("tftp_allowed_extensions", Strings),
+    ("tftp_denied_extensions", Strings),
+    ("tftp_fallbacks", Map(&Str)),
+    ("tftp_block_size_limit", Int),
+    ("tftp_upstream", Str),
+    ("http_port", Int),
+    ("https_port", Int),
+    ("https_cert", Str),
+    ("https_key", Str),
+    ("https_client_ca", Str),
+    ("autoinstall_dir", Str),
+    ("netbootxyz", Bool),
+    ("images", Map(&IMAGE)),
+    ("mirrors", Map(&Str)),
+    ("image_versions", Int),
+    ("image_refresh_interval", Int),
+    ("secure_boot", Bool),
+    ("ipxe_chainload", Bool),
+    ("strict", Bool),
+    ("upload_token", Str),
+    ("upload_users", Map(&Str)),
+    ("upload_max_size", Int),
+    ("signing_cert", Str),
+    ("signing_key", Str),
+    ("boot_hostname", Str),
+    ("dns_port", Int),
+    ("reverse_dns", Bool),
+    ("reverse_dns_server", Str),
+    ("webhook_url", Str),
+    ("webhook_timeout", Int),
+    ("boot_hook", Str),
+    (
+        "event_hooks",
+        Table(&[
+            ("discover_seen", Str),
+            ("rule_matched", Str),
+            ("unknown_client", Str),
+            ("offer_sent", Str),
+            ("boot_file_delivered", Str),
+            ("session_timed_out", Str),
+            ("upstream_dhcp_missing", Str),
+        ]),
+    ),
+    ("event_webhooks", List(&EVENT_WEBHOOK)),
+    ("notifications", List(&NOTIFICATION)),
+    ("mqtt_broker", Str),
+    ("mqtt_topic", Str),
+    ("mqtt_username", Str),
+    ("mqtt_password", Str),
+    ("mqtt_retain", Bool),
+    ("netbox_url", Str),
+    ("netbox_token", Str),
+    ("inventory_cache_ttl", Int),
+    ("control_socket", Str),
+    ("dashboard_addr", Str),
+    ("api_addr", Str),
+    ("grpc_addr", Str),
+    ("health_addr", Str),
+    ("api_token", Str),
+    ("snmp_addr", Str),
+    ("snmp_community", Str),
+    ("log_output", Str),
+    ("syslog_server", Str),
+    ("pcap_file", Str),
+    ("pcap_max_size", Int),
+    ("pcap_files", Int),
+    ("pcap_tftp", Bool),
+    ("audit_log", Str),
+    ("audit_log_max_size", Int),
+    ("audit_log_files", Int),
+    ("traffic_log", Str),
+    ("traffic_log_max_size", Int),
+    ("traffic_log_files", Int),
+    ("event_log", Str),
+    ("event_log_max_size", Int),
+    ("event_log_files", Int),
+    ("stats_file", Str),
+    ("assets_file", Str),
+    ("user", Str),
+    ("group", Str),
+    ("sandbox", Bool),
+    ("tenants", Map(&TENANT)),
+]);
+
+/// Tenants replace the fields of the top level configuration telling where
+/// and what they serve.
+static TENANT: Kind = Table(&[
+    ("default", ENTRY),
+    ("defaults", Map(&ENTRY)),
+    ("profiles", Map(&ENTRY)),
+    ("match", List(&MATCH_ENTRY)),
+    ("match_policy", Str),
+    ("require_match", Bool),
+    ("ifaces", Strings),
+    ("tftp_server_dir", Str),
+]);
+
+/// Files of the include directory only add to `default` and `match`.
+const INCLUDE: Kind = Table(&[("default", ENTRY), ("match", List(&MATCH_ENTRY))]);
+
+/// Checks the configuration `document`, or an included one, against the
+/// schema. `source` is the YAML the document was loaded from, if it was, to
+/// tell where the errors are.
+pub fn check(document: &Yaml, included: bool, source: Option<&str>) -> Result<()> {
+    let mut errors = Vec::new();
+    let kind = if included { INCLUDE } else { CONF };
+    check_value(document, kind, "", &mut errors);
+    if errors.is_empty() {
+        return Ok(());
+    }
+
+    let positions = source.map(Positions::of).unwrap_or_default();
+    let errors: Vec<String> = errors
+        .into_iter()
+        .map(|(path, error)| match positions.get(&path) {
+            Some(mark) => format!("line {}, column {}: {error}", mark.line(), mark.col() + 1),
+            None => error,
+        })
+        .collect();
+
+    Err(anyhow!("Invalid configuration: {}", errors.join("; ")))
+}
+
+/// Errors found, by the path of the value they are about.
+type Errors = Vec<(String, String)>;
+
+fn check_value(value: &Yaml, kind: Kind, path: &str, errors: &mut Errors) {
+    let name = if path.is_empty() {
+        "the configuration"
+    } else {
+        path
+    };
+    let expected = match (kind, value) {
+        (_, Yaml::Null)
+        | (Str, Yaml::String(_))
+        | (Int, Yaml::Integer(_))
+        | (Bool, Yaml::Boolean(_))
+        | (Scalar, Yaml::String(_) | Yaml::Integer(_) | Yaml::Real(_) | Yaml::Boolean(_)) => return,
+        (Values, Yaml::Array(values)) => {
+            for (index, value) in values.iter().enumerate() {
+                check_value(value, Scalar, &format!("{path}[{index}]"), errors);
+            }
+            return;
+        }
+        (Values, _) => return check_value(value, Scalar, path, errors),
+        (Strings, Yaml::Array(values)) => {
+            for (index, value) in values.iter().enumerate() {
+                check_value(value, Str, &format!("{path}[{index}]"), errors);
+            }
+            return;
+        }
+        (List(item_kind), Yaml::Array(values)) => {
+            for (index, value) in values.iter().enumerate() {
+                check_value(value, *item_kind, &format!("{path}[{index}]"), errors);
+            }
+            return;
+        }
+        (Table(fields), Yaml::Hash(hash)) => {
+            for (key, value) in hash {
+                let Some(key) = key.as_str() else {
+                    errors.push((path.to_string(), format!("Expected string keys in {name}")));
+                    continue;
+                };
+                let key_path = join(path, key);
+                match fields.iter().find(|(field, _)| *field == key) {
+                    Some((_, field_kind)) => check_value(value, *field_kind, &key_path, errors),
+                    None => {
+                        let suggestion = fields
+                            .iter()
+                            .find(|(field, _)| is_typo(key, field))
+                            .map(|(field, _)| format!(", did you mean {field}?"))
+                            .unwrap_or_default();
+                        errors.push((
+                            key_path.clone(),
+                            format!("Unknown key {key_path}{suggestion}"),
+                        ));
+                    }
+                }
+            }
+            return;
+        }
+        (Map(value_kind), Yaml::Hash(hash)) => {
+            for (key, value) in hash {
+                match key.as_str() {
+                    Some(key) => check_value(value, *value_kind, &join(path, key), errors),
+                    None => {
+                        errors.push((path.to_string(), format!("Expected string keys in {name}")))
+                    }
+                }
+            }
+            return;
+        }
+        (Str, _) => "a string",
+        (Int, _) => "an integer",
+        (Bool, _) => "true or false",
+        (Scalar, _) => "a string, number or boolean",
+        (Strings, _) => "a list of strings",
+        (Table(_) | Map(_), _) => "a mapping",
+        (List(_), _) => "a list",
+    };
+
+    errors.push((path.to_string(), format!("Expected {expected} for {name}")));
+}
+
+fn join(path: &str, key: &str) -> String {
+    match path {
+        "" => key.to_string(),
+        _ => format!("{path}.{key}"),
+    }
+}
+
+/// Whether `key` is likely a misspelling of `field`: one character added,
+/// removed or replaced, or two adjacent ones swapped.
+fn is_typo(key: &str, field: &str) -> bool {
+    let (key, field): (Vec<char>, Vec<char>) = (key.chars().collect(), field.chars().collect());
+    let prefix = key.iter().zip(&field).take_while(|(a, b)| a == b).count();
+    let (key, field) = (&key[prefix..], &field[prefix..]);
+
+    match (key.len(), field.len()) {
+        (k, f) if k == f + 1 => key[1..] == *field,
+        (k, f) if k + 1 == f => *key == field[1..],
+        (k, f) if k == f && k > 0 => {
+            key[1..] == field[1..]
+                || (k > 1 && key[0] == field[1] && key[1] == field[0] && key[2..] == field[2..])
+        }
+        _ => false,
+    }
+}
+
+/// Where the keys and values of a YAML document are, by their path in the
+/// form of the error messages, e.g. `match[0].conf.boot_file`.
+#[derive(Default)]
+struct Positions {
+    marks: HashMap<String, Marker>,
+    /// The mappings and sequences being read, with the key of the value to
+    /// come or the index of the next item.
+    stack: Vec<(String, Container)>,
+}
+
+enum Container {
+    Mapping(Option<String>),
+    Sequence(usize),
+}
+
+impl Positions {
+    fn of(source: &str) -> Self {
+        let mut positions = Self::default();
+        // Errors were reported when the document was loaded
+        let _ = Parser::new_from_str(source).load(&mut positions, false);
+        positions
+    }
+
+    fn get(&self, path: &str) -> Option<&Marker> {
+        self.marks.get(path)
+    }
+
+    /// Path of the node starting, `None` for the keys of mappings, whose
+    /// position is recorded.
+    fn node_path(&mut self, event: &Event, mark: Marker) -> Option<String> {
+        let Some((path, container)) = self.stack.last_mut() else {
+            return Some(String::new());
+        };
+        match container {
+            Container::Mapping(key @ None) => {
+                let name = match event {
+                    Event::Scalar(name, ..) => name.clone(),
+                    _ => String::new(),
+                };
+                let key_path = join(path, &name);
+                self.marks.entry(key_path).or_insert(mark);
+                *key = Some(name);
+                None
+            }
+            Container::Mapping(key @ Some(_)) => key.take().map(|key| join(path, &key)),
+            Container::Sequence(index) => {
+                let item_path = format!("{path}[{index}]");
+                *index += 1;
+                self.marks.entry(item_path.clone()).or_insert(mark);
+                Some(item_path)
+            }
+        }
+    }
+}
+
+impl MarkedEventReceiver for Positions {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+            }
+            Event::Scalar(..) | Event::Alias(_) => {
+                self.node_path(&event, mark);
+            }
+            Event::MappingStart(..) | Event::SequenceStart(..) => {
+                // Mappings as keys aren't supported, their content is skipped
+                let path = self.node_path(&event, mark).unwrap_or_default();
+                let container = match event {
+                    Event::MappingStart(..) => Container::Mapping(None),
+                    _ => Container::Sequence(0),
+                };
+                self.stack.push((path, container));
+            }
+            _ => {}
+        }
+    }
+}
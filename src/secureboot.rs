@@ -0,0 +1,101 @@
+//! Secure Boot preset: Debian's signed shim and GRUB netboot binaries, shim
+//! being signed with the Microsoft UEFI CA most machines trust, fetched into
+//! the image store and handed to UEFI clients without a `boot_file`. GRUB
+//! then loads its configuration from the HTTP service, rendered per client.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use once_cell::sync::Lazy;
+
+use crate::{conf::ImageSource, images::IMAGES_DIR, util::part_path, Result};
+
+/// Directory of the image store the binaries are kept in.
+pub const DIR: &str = "secureboot";
+const RELEASE_URL: &str = "https://deb.debian.org/debian/dists/stable/main";
+
+/// Binaries of a UEFI architecture, shim loading GRUB from its own directory.
+struct Arch {
+    debian: &'static str,
+    shim: &'static str,
+    grub: &'static str,
+}
+
+const ARCHES: [Arch; 2] = [
+    Arch {
+        debian: "amd64",
+        shim: "bootnetx64.efi",
+        grub: "grubx64.efi",
+    },
+    Arch {
+        debian: "arm64",
+        shim: "bootnetaa64.efi",
+        grub: "grubaa64.efi",
+    },
+];
+static BOOT_FILES: Lazy<[String; 2]> =
+    Lazy::new(|| ARCHES.map(|arch| format!("{IMAGES_DIR}/{DIR}/{}/{}", arch.debian, arch.shim)));
+
+/// shim for a UEFI client of the given processor architecture, option 93.
+/// BIOS clients have no Secure Boot and get none.
+pub fn boot_file(arch: Option<u16>) -> Option<&'static String> {
+    match arch {
+        Some(0x07 | 0x09) => Some(&BOOT_FILES[0]),
+        Some(0x0b) => Some(&BOOT_FILES[1]),
+        _ => None,
+    }
+}
+
+/// The signed binaries, for the image store to fetch.
+pub fn images() -> Vec<ImageSource> {
+    ARCHES
+        .iter()
+        .flat_map(|arch| [arch.shim, arch.grub].map(|binary| (arch.debian, binary)))
+        .map(|(debian, binary)| ImageSource {
+            path: Path::new(DIR).join(debian).join(binary),
+            url: format!(
+                "{RELEASE_URL}/installer-{debian}/current/images/netboot/debian-installer/{debian}/{binary}"
+            ),
+            sha256: None,
+            sha256sums_url: None,
+            minisign_key: None,
+        })
+        .collect()
+}
+
+/// Writes the configuration Debian's GRUB reads from its prefix,
+/// `debian-installer/<arch>/grub/` of the TFTP root. It can't be rendered per
+/// client over TFTP, so it loads the client's configuration over HTTP.
+pub fn write_bootstrap(tftp_root: &Path, http_port: u16) -> Result<()> {
+    let bootstrap = format!(
+        "# Written by preboot-oxide, loads the configuration of the client\n\
+         configfile (http,${{net_default_server}}:{http_port})/grub.cfg?mac=${{net_default_mac}}\n"
+    );
+
+    for arch in &ARCHES {
+        let path = bootstrap_path(tftp_root, arch.debian);
+        if fs::read_to_string(&path).is_ok_and(|current| current == bootstrap) {
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let part = part_path(&path);
+        fs::write(&part, &bootstrap)?;
+        fs::rename(&part, &path).inspect_err(|_| {
+            let _ = fs::remove_file(&part);
+        })?;
+    }
+
+    Ok(())
+}
+
+fn bootstrap_path(tftp_root: &Path, debian_arch: &str) -> PathBuf {
+    tftp_root
+        .join("debian-installer")
+        .join(debian_arch)
+        .join("grub")
+        .join("grub.cfg")
+}
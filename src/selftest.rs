@@ -0,0 +1,345 @@
+//! `self-test` subcommand: checks the running server boots a client end to
+//! end, as a smoke test after installing it. A veth pair is created, one end
+//! moved to a network namespace of its own, where a simulated PXE client
+//! runs the DHCP handshake then downloads its boot file over TFTP, along
+//! with a stand-in for the DHCP server of the network, whose offers the
+//! proxy answers, while the server serves the other end as it comes up.
+//! Both are removed afterwards. Linux only, as root.
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use dhcproto::v4::{DhcpOption, Flags, Message, MessageType, Opcode};
+
+use crate::{
+    conf::Conf,
+    doctor::{Check, Outcome},
+    probe::ProbeAnswer,
+    status::{self, Health},
+    upstream, Result,
+};
+
+/// Network namespace of the client.
+pub const NAMESPACE: &str = "po-selftest";
+/// End of the veth pair served, on the host.
+pub const HOST_IFACE: &str = "po-selftest0";
+/// End of the veth pair of the client, in `NAMESPACE`.
+pub const CLIENT_IFACE: &str = "po-selftest1";
+/// Addresses of the benchmarking range of RFC 2544, not to clash with the
+/// networks of the host.
+const HOST_IP: Ipv4Addr = Ipv4Addr::new(198, 18, 247, 1);
+/// Offered to the client by the stand-in, which has it as well.
+pub const CLIENT_IP: Ipv4Addr = Ipv4Addr::new(198, 18, 247, 2);
+const PREFIX_LEN: u8 = 30;
+/// How often the server is asked whether it serves `HOST_IFACE`, and the
+/// download tried again until TFTP listens on its address.
+const RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Runs the test against the server `conf` is the configuration of, each
+/// step waited for up to `wait`.
+pub fn run(conf: &Conf, wait: Duration) -> Vec<Check> {
+    let health = match status::ask(&conf.get_control_socket()) {
+        Ok(health) => health,
+        Err(e) => {
+            return vec![Check::new(
+                "Server",
+                Outcome::Problem {
+                    what: format!("not answering, {e:#}"),
+                    fix: "Start preboot-oxide, e.g. `sudo systemctl start preboot-oxide`, then run the test again."
+                        .to_string(),
+                },
+            )]
+        }
+    };
+    let mut checks = vec![Check::new("Server", Outcome::Ok(format!("running, version {}", health.version)))];
+    let network = match TestNetwork::create() {
+        Ok(network) => network,
+        Err(e) => {
+            checks.push(Check::new(
+                "Test network",
+                Outcome::Problem {
+                    what: format!("not created, {e:#}"),
+                    fix: "Run the test as root, with iproute2 installed.".to_string(),
+                },
+            ));
+            return checks;
+        }
+    };
+    checks.push(Check::new(
+        "Test network",
+        Outcome::Ok(format!(
+            "{HOST_IFACE} {HOST_IP}/{PREFIX_LEN}, {CLIENT_IFACE} {CLIENT_IP}/{PREFIX_LEN} in namespace {NAMESPACE}"
+        )),
+    ));
+    checks.extend(boot(conf, wait));
+    drop(network);
+
+    checks
+}
+
+/// The checks of the DHCP handshake and of the download of the boot file.
+fn boot(conf: &Conf, wait: Duration) -> Vec<Check> {
+    if !wait_until(wait, || status::ask(&conf.get_control_socket()).is_ok_and(|health| serves(&health))) {
+        let dhcp = Check::new(
+            "DHCP proxy",
+            Outcome::Problem {
+                what: format!("{HOST_IFACE} isn't served within {}s", wait.as_secs()),
+                fix: format!("Add {HOST_IFACE} to `ifaces`, or leave `ifaces` out, for the test."),
+            },
+        );
+        return vec![dhcp, Check::new("TFTP download", Outcome::Skipped("no boot file offered".to_string()))];
+    }
+
+    let start = Instant::now();
+    let ack = match client::in_namespace(move || client::handshake(wait)) {
+        Ok(ack) => ack,
+        Err(e) => {
+            let dhcp = Check::new(
+                "DHCP proxy",
+                Outcome::Problem {
+                    what: format!("{e:#}"),
+                    fix: "Look at the log of the server, with -vvv, for why the client wasn't answered.".to_string(),
+                },
+            );
+            return vec![dhcp, Check::new("TFTP download", Outcome::Skipped("no boot file offered".to_string()))];
+        }
+    };
+    let boot_file = ack.boot_file.clone().unwrap_or_default();
+    let dhcp = Check::new(
+        "DHCP proxy",
+        Outcome::Ok(format!(
+            "{boot_file} offered by {} in {} ms",
+            ack.server,
+            start.elapsed().as_millis()
+        )),
+    );
+
+    let Some(server) = tftp_server(&ack) else {
+        return vec![dhcp, Check::new("TFTP download", Outcome::Skipped(format!("{boot_file} isn't served over TFTP")))];
+    };
+    let tftp = match client::in_namespace(move || client::download(server, &boot_file, wait)) {
+        Ok(download) => Outcome::Ok(format!(
+            "{} bytes of {} from {server} in {} ms",
+            download.size,
+            download.path.display(),
+            download.elapsed.as_millis()
+        )),
+        Err(e) => Outcome::Problem {
+            what: format!("{e:#}"),
+            fix: "Check the file is in `tftp_server_dir`, with `preboot-oxide tftp-get`.".to_string(),
+        },
+    };
+
+    vec![dhcp, Check::new("TFTP download", tftp)]
+}
+
+/// Whether the server listens for DHCP on `HOST_IFACE`.
+fn serves(health: &Health) -> bool {
+    health
+        .services
+        .iter()
+        .filter_map(|service| service.dhcp_ifaces.as_ref())
+        .any(|ifaces| ifaces.iter().any(|iface| iface == HOST_IFACE))
+}
+
+/// The TFTP server of the boot file of `ack`, `None` when it's fetched by
+/// another protocol.
+pub fn tftp_server(ack: &ProbeAnswer) -> Option<SocketAddr> {
+    let boot_file = ack.boot_file.as_deref()?;
+    if boot_file.contains("://") {
+        return None;
+    }
+    let ip: IpAddr = ack.boot_server.as_deref().unwrap_or(&ack.server.to_string()).parse().ok()?;
+    Some(SocketAddr::new(ip, upstream::DEFAULT_TFTP_PORT))
+}
+
+/// The OFFER of the stand-in for the DHCP server of the network to
+/// `discover`, `None` for the other messages.
+pub fn network_offer(discover: &Message) -> Option<Message> {
+    if discover.opts().msg_type() != Some(MessageType::Discover) {
+        return None;
+    }
+    let mut offer = Message::default();
+    offer
+        .set_opcode(Opcode::BootReply)
+        .set_xid(discover.xid())
+        .set_flags(Flags::new(0).set_broadcast())
+        .set_yiaddr(CLIENT_IP)
+        .set_chaddr(discover.chaddr());
+    let opts = offer.opts_mut();
+    opts.insert(DhcpOption::MessageType(MessageType::Offer));
+    opts.insert(DhcpOption::ServerIdentifier(CLIENT_IP));
+    opts.insert(DhcpOption::SubnetMask(Ipv4Addr::from(u32::MAX << (32 - PREFIX_LEN))));
+    opts.insert(DhcpOption::AddressLeaseTime(60));
+
+    Some(offer)
+}
+
+/// Whether `is_done` within `wait`, asked every `RETRY_INTERVAL`.
+fn wait_until(wait: Duration, mut is_done: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + wait;
+    loop {
+        if is_done() {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+/// The veth pair and the namespace of the client, removed when dropped.
+struct TestNetwork;
+
+impl TestNetwork {
+    #[cfg(target_os = "linux")]
+    fn create() -> Result<Self> {
+        // Left by an interrupted test
+        Self::remove();
+        let network = Self;
+        let (host_addr, client_addr) = (format!("{HOST_IP}/{PREFIX_LEN}"), format!("{CLIENT_IP}/{PREFIX_LEN}"));
+        let commands: [&[&str]; 6] = [
+            &["netns", "add", NAMESPACE],
+            &["link", "add", HOST_IFACE, "type", "veth", "peer", "name", CLIENT_IFACE, "netns", NAMESPACE],
+            &["addr", "add", &host_addr, "dev", HOST_IFACE],
+            &["link", "set", HOST_IFACE, "up"],
+            &["-n", NAMESPACE, "addr", "add", &client_addr, "dev", CLIENT_IFACE],
+            &["-n", NAMESPACE, "link", "set", CLIENT_IFACE, "up"],
+        ];
+        for args in commands {
+            ip(args)?;
+        }
+
+        Ok(network)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn create() -> Result<Self> {
+        bail!("self-test is only supported on Linux")
+    }
+
+    /// Removing the namespace removes the veth pair along with its end.
+    fn remove() {
+        let _ = ip(&["netns", "del", NAMESPACE]);
+        let _ = ip(&["link", "del", HOST_IFACE]);
+    }
+}
+
+impl Drop for TestNetwork {
+    fn drop(&mut self) {
+        Self::remove();
+    }
+}
+
+/// Runs `ip` with `args`, failing with what it printed.
+fn ip(args: &[&str]) -> Result<()> {
+    let output = std::process::Command::new("ip")
+        .args(args)
+        .output()
+        .map_err(|e| anyhow!("Running ip: {e}"))?;
+    if !output.status.success() {
+        bail!(
+            "ip {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod client {
+    use std::{fs::File, io, net::SocketAddr, os::fd::AsRawFd, path::Path, pin::pin, thread, time::Duration};
+
+    use anyhow::Context;
+    use async_std::{net::UdpSocket, task};
+    use dhcproto::v4::{Decodable, Decoder, Encodable, Encoder, Message};
+    use futures::future::{self, Either};
+    use network_interface::{NetworkInterface, NetworkInterfaceConfig};
+
+    use super::{network_offer, wait_until, CLIENT_IFACE, NAMESPACE};
+    use crate::{bench, dhcp, probe::ProbeAnswer, tftp_get, Result};
+
+    /// Runs `f` on a thread of its own moved to the network namespace of
+    /// the client, the sockets it opens being of that namespace.
+    pub fn in_namespace<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+        thread::spawn(move || {
+            let namespace = File::open(format!("/run/netns/{NAMESPACE}"))?;
+            // SAFETY: the descriptor is of the namespace file open through the call
+            if unsafe { libc::setns(namespace.as_raw_fd(), libc::CLONE_NEWNET) } == -1 {
+                return Err(io::Error::last_os_error().into());
+            }
+            f()
+        })
+        .join()
+        .map_err(|_| anyhow!("The client panicked"))?
+    }
+
+    /// The ACK giving the boot information to a new client, the stand-in
+    /// for the DHCP server of the network answering it meanwhile.
+    pub fn handshake(wait: Duration) -> Result<ProbeAnswer> {
+        task::block_on(async {
+            let iface = NetworkInterface::show()?
+                .into_iter()
+                .find(|iface| iface.name == CLIENT_IFACE)
+                .ok_or(anyhow!("No network interface {CLIENT_IFACE}"))?;
+            let server = dhcp::socket_from_iface_ip(&iface, &"0.0.0.0:67").context("Starting the stand-in DHCP server")?;
+            let (socket, dispatch) = bench::dhcp_socket(CLIENT_IFACE)?;
+            let client = pin!(bench::handshake(&socket, &dispatch, wait));
+            let stand_in = pin!(stand_in(&server));
+            let ack = match future::select(client, stand_in).await {
+                Either::Left((result, _)) => result.map(|(ack, _)| ack),
+                Either::Right((result, _)) => Err(result.err().unwrap_or(anyhow!("The stand-in DHCP server stopped"))),
+            };
+            ack
+        })
+    }
+
+    /// Answers the DISCOVERs received on `socket` as the DHCP server of the
+    /// network would, the proxy only answering after it.
+    async fn stand_in(socket: &UdpSocket) -> Result<()> {
+        let mut buf = [0u8; 1500];
+        loop {
+            let (len, _) = socket.recv_from(&mut buf).await?;
+            let Some(offer) = Message::decode(&mut Decoder::new(&buf[..len])).ok().as_ref().and_then(network_offer)
+            else {
+                continue;
+            };
+            let mut out = Vec::new();
+            offer.encode(&mut Encoder::new(&mut out))?;
+            socket.send_to(&out, "255.255.255.255:68").await?;
+        }
+    }
+
+    /// Downloads `path` from `server`, tried again within `wait` as TFTP
+    /// only listens on the address of the test network once it's noticed.
+    pub fn download(server: SocketAddr, path: &str, wait: Duration) -> Result<tftp_get::Download> {
+        let mut result = Err(anyhow!("Not tried"));
+        wait_until(wait, || {
+            result = task::block_on(tftp_get::download(server, Path::new(path), None));
+            result.is_ok()
+        });
+        result
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod client {
+    use std::{net::SocketAddr, time::Duration};
+
+    use crate::{probe::ProbeAnswer, tftp_get, Result};
+
+    pub fn in_namespace<T>(_f: impl FnOnce() -> Result<T>) -> Result<T> {
+        bail!("self-test is only supported on Linux")
+    }
+
+    pub fn handshake(_wait: Duration) -> Result<ProbeAnswer> {
+        bail!("self-test is only supported on Linux")
+    }
+
+    pub fn download(_server: SocketAddr, _path: &str, _wait: Duration) -> Result<tftp_get::Download> {
+        bail!("self-test is only supported on Linux")
+    }
+}
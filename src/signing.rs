@@ -0,0 +1,309 @@
+//! Checksums and detached signatures of the served files, published next to
+//! them so iPXE scripts can check images (`imgverify`) before executing them.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use async_std::task;
+use futures::{AsyncRead, AsyncReadExt};
+use futures_rustls::rustls::{
+    crypto::ring::sign::any_supported_type,
+    pki_types::CertificateDer,
+    sign::Signer,
+    SignatureAlgorithm, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{tls, util::to_hex, Result};
+
+pub const SHA256_SUFFIX: &str = ".sha256";
+pub const SIGNATURE_SUFFIX: &str = ".sig";
+
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OID: u8 = 0x06;
+const CONTEXT_0: u8 = 0xa0;
+
+const OID_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01];
+const OID_SIGNED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+
+/// What is published next to a file, under the path of the file with its
+/// suffix appended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Detached {
+    /// SHA-256 checksum, in the format of `sha256sum`.
+    Sha256,
+    /// DER encoded CMS signature, as verified by iPXE `imgverify`.
+    Signature,
+}
+
+impl Detached {
+    /// Path of the file `path` is the checksum or signature of, and which.
+    pub fn of(path: &str) -> Option<(&str, Self)> {
+        [(SHA256_SUFFIX, Self::Sha256), (SIGNATURE_SUFFIX, Self::Signature)]
+            .into_iter()
+            .find_map(|(suffix, detached)| {
+                path.strip_suffix(suffix)
+                    .filter(|file| !file.is_empty() && !file.ends_with('/'))
+                    .map(|file| (file, detached))
+            })
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Sha256 => "text/plain; charset=utf-8",
+            Self::Signature => "application/pkcs7-signature",
+        }
+    }
+}
+
+/// Signs files with the configured certificate, as `openssl cms -sign
+/// -binary -noattr -outform DER` does, the form iPXE verifies.
+pub struct ImageSigner {
+    signer: Box<dyn Signer>,
+    certs: Vec<CertificateDer<'static>>,
+    /// Identifies the signing certificate, the first of `certs`.
+    issuer_and_serial: Vec<u8>,
+}
+
+impl ImageSigner {
+    /// Loads the PEM encoded certificate (chain) and RSA private key files,
+    /// if configured.
+    pub fn load(cert: Option<&Path>, key: Option<&Path>) -> Result<Option<Self>> {
+        let (cert, key) = match (cert, key) {
+            (Some(cert), Some(key)) => (cert, key),
+            (None, None) => return Ok(None),
+            _ => return Err(anyhow!("Both signing_cert and signing_key need to be configured.")),
+        };
+
+        let (certs, private_key) = tls::load_pem(cert, key)?;
+        let signing_key = any_supported_type(&private_key)
+            .map_err(|e| anyhow!("Unsupported signing key {}: {e}", key.display()))?;
+        if signing_key.algorithm() != SignatureAlgorithm::RSA {
+            return Err(anyhow!(
+                "The signing key {} needs to be an RSA key, as verified by iPXE.",
+                key.display()
+            ));
+        }
+        let signer = signing_key
+            .choose_scheme(&[SignatureScheme::RSA_PKCS1_SHA256])
+            .ok_or_else(|| anyhow!("The signing key {} can't sign with SHA-256.", key.display()))?;
+        let issuer_and_serial = issuer_and_serial(&certs[0])
+            .ok_or_else(|| anyhow!("Invalid signing certificate {}", cert.display()))?;
+
+        Ok(Some(Self {
+            signer,
+            certs,
+            issuer_and_serial,
+        }))
+    }
+
+    /// Detached CMS signature of `content`, embedding the certificates.
+    pub fn sign(&self, content: &[u8]) -> Result<Vec<u8>> {
+        let signature = self
+            .signer
+            .sign(content)
+            .map_err(|e| anyhow!("Signing failed: {e}"))?;
+
+        let sha256 = der(SEQUENCE, &der(OID, OID_SHA256));
+        let signer_info = der(
+            SEQUENCE,
+            &[
+                der(INTEGER, &[1]),
+                self.issuer_and_serial.clone(),
+                sha256.clone(),
+                der(SEQUENCE, &[der(OID, OID_RSA_ENCRYPTION), der(NULL, &[])].concat()),
+                der(OCTET_STRING, &signature),
+            ]
+            .concat(),
+        );
+        let certs: Vec<u8> = self.certs.iter().flat_map(|cert| cert.to_vec()).collect();
+        let signed_data = der(
+            SEQUENCE,
+            &[
+                der(INTEGER, &[1]),
+                der(SET, &sha256),
+                // Detached, the content isn't included
+                der(SEQUENCE, &der(OID, OID_DATA)),
+                der(CONTEXT_0, &certs),
+                der(SET, &signer_info),
+            ]
+            .concat(),
+        );
+
+        Ok(der(
+            SEQUENCE,
+            &[der(OID, OID_SIGNED_DATA), der(CONTEXT_0, &signed_data)].concat(),
+        ))
+    }
+}
+
+/// Checksums and signatures computed for the served files, reused until the
+/// files change.
+pub struct FileDigests {
+    signer: Option<Arc<ImageSigner>>,
+    cache: Mutex<HashMap<(PathBuf, Detached), CachedDigest>>,
+}
+
+struct CachedDigest {
+    len: Option<u64>,
+    modified: SystemTime,
+    content: Vec<u8>,
+}
+
+impl FileDigests {
+    pub fn new(signer: Option<ImageSigner>) -> Self {
+        Self {
+            signer: signer.map(Arc::new),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn can_sign(&self) -> bool {
+        self.signer.is_some()
+    }
+
+    /// The checksum or signature computed for `path` while it had the given
+    /// length and modification time.
+    pub fn cached(
+        &self,
+        path: &Path,
+        detached: Detached,
+        (len, modified): (Option<u64>, Option<SystemTime>),
+    ) -> Option<Vec<u8>> {
+        let cache = self.cache.lock().ok()?;
+        cache
+            .get(&(path.to_path_buf(), detached))
+            .filter(|cached| cached.len == len && Some(cached.modified) == modified)
+            .map(|cached| cached.content.clone())
+    }
+
+    /// Computes the checksum or signature of the content of `path` read from
+    /// `reader`, cached when the modification time of the file is known.
+    pub async fn compute<R>(
+        &self,
+        path: &Path,
+        detached: Detached,
+        (len, modified): (Option<u64>, Option<SystemTime>),
+        mut reader: R,
+    ) -> Result<Vec<u8>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let content = match detached {
+            Detached::Sha256 => {
+                let mut hasher = Sha256::new();
+                let mut buf = vec![0; 64 * 1024];
+                loop {
+                    let read = reader.read(&mut buf).await?;
+                    if read == 0 {
+                        break;
+                    }
+                    hasher.update(&buf[..read]);
+                }
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                format!("{}  {name}\n", to_hex(&hasher.finalize())).into_bytes()
+            }
+            Detached::Signature => {
+                let signer = self
+                    .signer
+                    .clone()
+                    .ok_or_else(|| anyhow!("No signing certificate configured."))?;
+                let mut content = Vec::new();
+                reader.read_to_end(&mut content).await?;
+                task::spawn_blocking(move || signer.sign(&content)).await?
+            }
+        };
+
+        if let (Some(modified), Ok(mut cache)) = (modified, self.cache.lock()) {
+            cache.insert(
+                (path.to_path_buf(), detached),
+                CachedDigest {
+                    len,
+                    modified,
+                    content: content.clone(),
+                },
+            );
+        }
+
+        Ok(content)
+    }
+}
+
+/// DER encoding of the element of type `tag`.
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        encoded.push(len as u8);
+    } else {
+        let len_bytes = len.to_be_bytes();
+        let zeros = len_bytes.iter().take_while(|&&byte| byte == 0).count();
+        encoded.push(0x80 | (len_bytes.len() - zeros) as u8);
+        encoded.extend_from_slice(&len_bytes[zeros..]);
+    }
+    encoded.extend_from_slice(content);
+
+    encoded
+}
+
+/// An element of DER encoded data.
+struct DerElement<'a> {
+    tag: u8,
+    content: &'a [u8],
+    /// The element as a whole, tag and length included.
+    encoded: &'a [u8],
+}
+
+/// Splits the DER element at the start of `input` from what follows it.
+fn split_der(input: &[u8]) -> Option<(DerElement<'_>, &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first_len, rest) = rest.split_first()?;
+    let (len, rest) = match first_len {
+        0..=0x7f => (first_len as usize, rest),
+        0x81..=0x84 => {
+            let len_bytes = rest.get(..(first_len & 0x7f) as usize)?;
+            let len = len_bytes
+                .iter()
+                .fold(0usize, |len, &byte| len << 8 | byte as usize);
+            (len, &rest[len_bytes.len()..])
+        }
+        _ => return None,
+    };
+    let content = rest.get(..len)?;
+    let header_len = input.len() - rest.len();
+
+    let element = DerElement {
+        tag,
+        content,
+        encoded: &input[..header_len + len],
+    };
+
+    Some((element, &rest[len..]))
+}
+
+/// The `IssuerAndSerialNumber` a CMS signer info identifies `cert` with.
+fn issuer_and_serial(cert: &[u8]) -> Option<Vec<u8>> {
+    let (cert, _) = split_der(cert)?;
+    let (tbs_certificate, _) = split_der(cert.content)?;
+    let (mut serial, mut rest) = split_der(tbs_certificate.content)?;
+    // The version comes first, unless it's the default one
+    if serial.tag == CONTEXT_0 {
+        (serial, rest) = split_der(rest)?;
+    }
+    let (_signature_algorithm, rest) = split_der(rest)?;
+    let (issuer, _) = split_der(rest)?;
+
+    Some(der(SEQUENCE, &[issuer.encoded, serial.encoded].concat()))
+}
@@ -0,0 +1,623 @@
+//! SNMP agent on `snmp_addr`, for the network teams monitoring their boxes
+//! with SNMP only: the counters of `status` and whether DHCP and TFTP serve,
+//! answered read-only to the SNMPv1 and SNMPv2c requests of
+//! `snmp_community`. The objects are those of the `system` group of MIB-2,
+//! then those of preboot-oxide, under `PREFIX` in the experimental subtree
+//! of Net-SNMP, as no enterprise number is registered for it. The requests
+//! of other communities are dropped, as agents do.
+use std::{net::UdpSocket as StdUdpSocket, sync::Arc, time::Duration};
+
+use async_std::{net::UdpSocket, task};
+use log::{debug, error, info, warn};
+
+use crate::{conf::Conf, control::Control, dhcp, Result};
+
+/// `netSnmpPlaypen.1`, the objects of preboot-oxide.
+pub const PREFIX: [u32; 10] = [1, 3, 6, 1, 4, 1, 8072, 9999, 9999, 1];
+/// The `system` group of MIB-2.
+const SYSTEM: [u32; 7] = [1, 3, 6, 1, 2, 1, 1];
+/// Largest response sent, the bulk requests being answered with fewer
+/// objects beyond.
+const MAX_RESPONSE: usize = 1472;
+/// Objects answered to a bulk request at most.
+const MAX_REPETITIONS: usize = 64;
+/// Objects of a response at most, as none encodes in fewer than 7 bytes.
+const MAX_VARBINDS: usize = MAX_RESPONSE / 7;
+/// Bytes the lengths of the varbinds, the PDU and the message may grow by
+/// with their content, from one byte each to three.
+const LENGTHS_GROWTH: usize = 3 * 2;
+
+pub const VERSION_1: i64 = 0;
+pub const VERSION_2C: i64 = 1;
+
+pub const GET: u8 = 0xa0;
+pub const GET_NEXT: u8 = 0xa1;
+pub const RESPONSE: u8 = 0xa2;
+pub const SET: u8 = 0xa3;
+pub const GET_BULK: u8 = 0xa5;
+
+pub const TOO_BIG: i64 = 1;
+pub const NO_SUCH_NAME: i64 = 2;
+pub const NOT_WRITABLE: i64 = 17;
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_ID: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const COUNTER32: u8 = 0x41;
+const GAUGE32: u8 = 0x42;
+const TIME_TICKS: u8 = 0x43;
+const NO_SUCH_OBJECT: u8 = 0x80;
+const NO_SUCH_INSTANCE: u8 = 0x81;
+const END_OF_MIB_VIEW: u8 = 0x82;
+
+/// An object identifier, by its arcs.
+pub type Oid = Vec<u32>;
+
+/// The value of an object.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    OctetString(Vec<u8>),
+    Null,
+    ObjectId(Oid),
+    Counter32(u32),
+    Gauge32(u32),
+    /// Hundredths of a second.
+    TimeTicks(u32),
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+    /// Any other type, by its tag, as sent.
+    Other(u8, Vec<u8>),
+}
+
+impl Value {
+    /// `TruthValue`, 1 for true and 2 for false.
+    pub fn truth(value: bool) -> Self {
+        Value::Integer(if value { 1 } else { 2 })
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Integer(value) => tlv(out, INTEGER, &encode_integer(*value)),
+            Value::OctetString(value) => tlv(out, OCTET_STRING, value),
+            Value::Null => tlv(out, NULL, &[]),
+            Value::ObjectId(oid) => tlv(out, OBJECT_ID, &encode_oid(oid)),
+            Value::Counter32(value) => tlv(out, COUNTER32, &encode_unsigned((*value).into())),
+            Value::Gauge32(value) => tlv(out, GAUGE32, &encode_unsigned((*value).into())),
+            Value::TimeTicks(value) => tlv(out, TIME_TICKS, &encode_unsigned((*value).into())),
+            Value::NoSuchObject => tlv(out, NO_SUCH_OBJECT, &[]),
+            Value::NoSuchInstance => tlv(out, NO_SUCH_INSTANCE, &[]),
+            Value::EndOfMibView => tlv(out, END_OF_MIB_VIEW, &[]),
+            Value::Other(tag, content) => tlv(out, *tag, content),
+        }
+    }
+
+    fn decode(tag: u8, content: &[u8]) -> Result<Self> {
+        let unsigned = || {
+            let value = decode_integer(content)?;
+            u32::try_from(value).map_err(|_| anyhow!("Invalid unsigned value {value}"))
+        };
+        Ok(match tag {
+            INTEGER => Value::Integer(decode_integer(content)?),
+            OCTET_STRING => Value::OctetString(content.to_vec()),
+            NULL => Value::Null,
+            OBJECT_ID => Value::ObjectId(decode_oid(content)?),
+            COUNTER32 => Value::Counter32(unsigned()?),
+            GAUGE32 => Value::Gauge32(unsigned()?),
+            TIME_TICKS => Value::TimeTicks(unsigned()?),
+            NO_SUCH_OBJECT => Value::NoSuchObject,
+            NO_SUCH_INSTANCE => Value::NoSuchInstance,
+            END_OF_MIB_VIEW => Value::EndOfMibView,
+            _ => Value::Other(tag, content.to_vec()),
+        })
+    }
+}
+
+/// A request or response, `GET`, `RESPONSE`... For `GET_BULK`, the error
+/// status and index are the non-repeaters and the maximum repetitions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pdu {
+    pub kind: u8,
+    pub request_id: i64,
+    pub error_status: i64,
+    pub error_index: i64,
+    pub varbinds: Vec<(Oid, Value)>,
+}
+
+/// An SNMPv1 or SNMPv2c message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    /// `VERSION_1` or `VERSION_2C`.
+    pub version: i64,
+    pub community: Vec<u8>,
+    pub pdu: Pdu,
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut varbinds = Vec::new();
+        for (oid, value) in &self.pdu.varbinds {
+            encode_varbind(&mut varbinds, oid, value);
+        }
+        let mut pdu = Vec::new();
+        tlv(&mut pdu, INTEGER, &encode_integer(self.pdu.request_id));
+        tlv(&mut pdu, INTEGER, &encode_integer(self.pdu.error_status));
+        tlv(&mut pdu, INTEGER, &encode_integer(self.pdu.error_index));
+        tlv(&mut pdu, SEQUENCE, &varbinds);
+
+        let mut message = Vec::new();
+        tlv(&mut message, INTEGER, &encode_integer(self.version));
+        tlv(&mut message, OCTET_STRING, &self.community);
+        tlv(&mut message, self.pdu.kind, &pdu);
+        let mut out = Vec::new();
+        tlv(&mut out, SEQUENCE, &message);
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let (message, _) = expect(data, SEQUENCE)?;
+        let (version, rest) = expect(message, INTEGER)?;
+        let (community, rest) = expect(rest, OCTET_STRING)?;
+        let (kind, pdu, _) = read_tlv(rest)?;
+        let (request_id, rest) = expect(pdu, INTEGER)?;
+        let (error_status, rest) = expect(rest, INTEGER)?;
+        let (error_index, rest) = expect(rest, INTEGER)?;
+        let (mut rest, _) = expect(rest, SEQUENCE)?;
+        let mut varbinds = Vec::new();
+        while !rest.is_empty() {
+            let (varbind, next) = expect(rest, SEQUENCE)?;
+            let (oid, value) = expect(varbind, OBJECT_ID)?;
+            let (tag, value, _) = read_tlv(value)?;
+            varbinds.push((decode_oid(oid)?, Value::decode(tag, value)?));
+            rest = next;
+        }
+
+        Ok(Self {
+            version: decode_integer(version)?,
+            community: community.to_vec(),
+            pdu: Pdu {
+                kind,
+                request_id: decode_integer(request_id)?,
+                error_status: decode_integer(error_status)?,
+                error_index: decode_integer(error_index)?,
+                varbinds,
+            },
+        })
+    }
+}
+
+fn encode_varbind(out: &mut Vec<u8>, oid: &[u32], value: &Value) {
+    let mut varbind = Vec::new();
+    tlv(&mut varbind, OBJECT_ID, &encode_oid(oid));
+    value.encode(&mut varbind);
+    tlv(out, SEQUENCE, &varbind);
+}
+
+/// Appends the type `tag`, the length of `content` and `content` to `out`.
+fn tlv(out: &mut Vec<u8>, tag: u8, content: &[u8]) {
+    out.push(tag);
+    match content.len() {
+        len @ 0..=0x7f => out.push(len as u8),
+        len => {
+            let bytes = len.to_be_bytes();
+            let skip = bytes.iter().take_while(|byte| **byte == 0).count();
+            out.push(0x80 | (bytes.len() - skip) as u8);
+            out.extend_from_slice(&bytes[skip..]);
+        }
+    }
+    out.extend_from_slice(content);
+}
+
+/// The type, the content and what follows of the first element of `data`.
+fn read_tlv(data: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    let truncated = || anyhow!("Truncated SNMP message");
+    let (&tag, rest) = data.split_first().ok_or_else(truncated)?;
+    let (&first, rest) = rest.split_first().ok_or_else(truncated)?;
+    let (len, rest) = match first {
+        0..=0x7f => (first as usize, rest),
+        0x81..=0x84 => {
+            let count = (first & 0x7f) as usize;
+            let bytes = rest.get(..count).ok_or_else(truncated)?;
+            let len = bytes
+                .iter()
+                .fold(0usize, |len, byte| len << 8 | *byte as usize);
+            (len, &rest[count..])
+        }
+        _ => bail!("Unsupported length in SNMP message"),
+    };
+    let content = rest.get(..len).ok_or_else(truncated)?;
+    Ok((tag, content, &rest[len..]))
+}
+
+fn expect(data: &[u8], tag: u8) -> Result<(&[u8], &[u8])> {
+    let (found, content, rest) = read_tlv(data)?;
+    if found != tag {
+        bail!("Expected the type {tag:#04x} in SNMP message, found {found:#04x}");
+    }
+    Ok((content, rest))
+}
+
+fn encode_integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // The leading bytes only extending the sign are left out
+    let skip = (0..bytes.len() - 1)
+        .take_while(|i| {
+            (bytes[*i] == 0 && bytes[i + 1] & 0x80 == 0)
+                || (bytes[*i] == 0xff && bytes[i + 1] & 0x80 != 0)
+        })
+        .count();
+    bytes[skip..].to_vec()
+}
+
+fn encode_unsigned(value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes[..bytes.len() - 1]
+        .iter()
+        .take_while(|byte| **byte == 0)
+        .count();
+    let mut encoded = bytes[skip..].to_vec();
+    if encoded[0] & 0x80 != 0 {
+        encoded.insert(0, 0);
+    }
+    encoded
+}
+
+fn decode_integer(content: &[u8]) -> Result<i64> {
+    match content {
+        [] => bail!("Empty integer in SNMP message"),
+        [0, rest @ ..] if rest.len() == 8 => Ok(rest
+            .iter()
+            .fold(0u64, |value, byte| value << 8 | *byte as u64)
+            as i64),
+        _ if content.len() > 8 => bail!("Integer too large in SNMP message"),
+        _ => {
+            let sign = if content[0] & 0x80 != 0 { -1i64 } else { 0 };
+            Ok(content
+                .iter()
+                .fold(sign, |value, byte| value << 8 | *byte as i64))
+        }
+    }
+}
+
+fn encode_oid(oid: &[u32]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let (first, rest) = match oid {
+        [a, b, rest @ ..] => (a * 40 + b, rest),
+        [a] => (a * 40, &[][..]),
+        [] => (0, &[][..]),
+    };
+    for arc in std::iter::once(first).chain(rest.iter().copied()) {
+        let mut bytes = vec![(arc & 0x7f) as u8];
+        let mut arc = arc >> 7;
+        while arc > 0 {
+            bytes.push(0x80 | (arc & 0x7f) as u8);
+            arc >>= 7;
+        }
+        out.extend(bytes.iter().rev());
+    }
+    out
+}
+
+fn decode_oid(content: &[u8]) -> Result<Oid> {
+    let mut arcs = Vec::new();
+    let mut arc: u32 = 0;
+    for byte in content {
+        if arc > u32::MAX >> 7 {
+            bail!("Object identifier arc too large in SNMP message");
+        }
+        arc = arc << 7 | (byte & 0x7f) as u32;
+        if byte & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        }
+    }
+    let Some((&first, rest)) = arcs.split_first() else {
+        bail!("Empty object identifier in SNMP message");
+    };
+    let (a, b) = match first {
+        0..=39 => (0, first),
+        40..=79 => (1, first - 40),
+        _ => (2, first - 80),
+    };
+    Ok([a, b].into_iter().chain(rest.iter().copied()).collect())
+}
+
+/// `1.3.6.1...` as its arcs.
+pub fn parse_oid(s: &str) -> Result<Oid> {
+    s.trim_start_matches('.')
+        .split('.')
+        .map(|arc| {
+            arc.parse::<u32>()
+                .map_err(|_| anyhow!("Invalid object identifier {s}"))
+        })
+        .collect()
+}
+
+/// The answer to `request`, its community checked already, from `objects`
+/// sorted by OID. Other requests than those reading objects aren't answered.
+pub fn answer(request: &Message, objects: &[(Oid, Value)]) -> Option<Message> {
+    let v1 = match request.version {
+        VERSION_1 => true,
+        VERSION_2C => false,
+        _ => return None,
+    };
+    let pdu = &request.pdu;
+    let mut response = Pdu {
+        kind: RESPONSE,
+        request_id: pdu.request_id,
+        error_status: 0,
+        error_index: 0,
+        varbinds: Vec::new(),
+    };
+    let failed = |status: i64, index: usize| Pdu {
+        kind: RESPONSE,
+        request_id: pdu.request_id,
+        error_status: status,
+        error_index: index as i64,
+        varbinds: pdu.varbinds.clone(),
+    };
+
+    match pdu.kind {
+        GET => {
+            for (index, (oid, _)) in pdu.varbinds.iter().enumerate() {
+                let value = match objects.binary_search_by(|(known, _)| known.cmp(oid)) {
+                    Ok(found) => objects[found].1.clone(),
+                    Err(_) if v1 => return Some(reply(request, failed(NO_SUCH_NAME, index + 1))),
+                    Err(_) => missing(objects, oid),
+                };
+                response.varbinds.push((oid.clone(), value));
+            }
+        }
+        GET_NEXT => {
+            for (index, (oid, _)) in pdu.varbinds.iter().enumerate() {
+                match next(objects, oid) {
+                    Some((next, value)) => response.varbinds.push((next.clone(), value.clone())),
+                    None if v1 => return Some(reply(request, failed(NO_SUCH_NAME, index + 1))),
+                    None => response.varbinds.push((oid.clone(), Value::EndOfMibView)),
+                }
+            }
+        }
+        GET_BULK if !v1 => {
+            let non_repeaters = usize::try_from(pdu.error_status)
+                .unwrap_or(0)
+                .min(pdu.varbinds.len());
+            let (single, repeated) = pdu.varbinds.split_at(non_repeaters.min(MAX_VARBINDS));
+            let repeated = &repeated[..repeated.len().min(MAX_VARBINDS - single.len())];
+            let repetitions = usize::try_from(pdu.error_index)
+                .unwrap_or(0)
+                .min(MAX_REPETITIONS)
+                .min(MAX_VARBINDS / repeated.len().max(1));
+
+            // As many objects as fit, each encoded once
+            let mut bulk = Bulk {
+                varbinds: Vec::new(),
+                room: MAX_RESPONSE.saturating_sub(
+                    reply(request, response.clone()).encode().len() + LENGTHS_GROWTH,
+                ),
+            };
+            let mut full = false;
+            for (oid, _) in single {
+                full = !bulk.push(match next(objects, oid) {
+                    Some((next, value)) => (next.clone(), value.clone()),
+                    None => (oid.clone(), Value::EndOfMibView),
+                });
+                if full {
+                    break;
+                }
+            }
+            let mut cursors: Vec<Oid> = repeated.iter().map(|(oid, _)| oid.clone()).collect();
+            'repetitions: for _ in 0..repetitions {
+                if full || cursors.is_empty() {
+                    break;
+                }
+                let mut ended = 0;
+                for cursor in &mut cursors {
+                    let varbind = match next(objects, cursor) {
+                        Some((next, value)) => {
+                            *cursor = next.clone();
+                            (next.clone(), value.clone())
+                        }
+                        None => {
+                            ended += 1;
+                            (cursor.clone(), Value::EndOfMibView)
+                        }
+                    };
+                    if !bulk.push(varbind) {
+                        break 'repetitions;
+                    }
+                }
+                if ended == cursors.len() {
+                    break;
+                }
+            }
+            response.varbinds = bulk.varbinds;
+            return Some(reply(request, response));
+        }
+        SET => {
+            let status = if v1 { NO_SUCH_NAME } else { NOT_WRITABLE };
+            return Some(reply(request, failed(status, 1)));
+        }
+        _ => return None,
+    }
+
+    let answer = reply(request, response);
+    if answer.encode().len() > MAX_RESPONSE {
+        let mut too_big = failed(TOO_BIG, 0);
+        if !v1 {
+            too_big.varbinds.clear();
+        }
+        return Some(reply(request, too_big));
+    }
+    Some(answer)
+}
+
+/// The objects answered to a bulk request, with the bytes left for them.
+struct Bulk {
+    varbinds: Vec<(Oid, Value)>,
+    room: usize,
+}
+
+impl Bulk {
+    /// Adds `varbind` if it fits, the first one always. False once full.
+    fn push(&mut self, varbind: (Oid, Value)) -> bool {
+        let mut encoded = Vec::new();
+        encode_varbind(&mut encoded, &varbind.0, &varbind.1);
+        if encoded.len() > self.room && !self.varbinds.is_empty() {
+            return false;
+        }
+        self.room = self.room.saturating_sub(encoded.len());
+        self.varbinds.push(varbind);
+        true
+    }
+}
+
+fn reply(request: &Message, pdu: Pdu) -> Message {
+    Message {
+        version: request.version,
+        community: request.community.clone(),
+        pdu,
+    }
+}
+
+/// The first of `objects` after `oid`.
+fn next<'a>(objects: &'a [(Oid, Value)], oid: &Oid) -> Option<&'a (Oid, Value)> {
+    let index = match objects.binary_search_by(|(known, _)| known.cmp(oid)) {
+        Ok(found) => found + 1,
+        Err(after) => after,
+    };
+    objects.get(index)
+}
+
+/// What an unknown `oid` is answered with, `NoSuchInstance` when it's that
+/// of a known object with another instance.
+fn missing(objects: &[(Oid, Value)], oid: &Oid) -> Value {
+    let object = &oid[..oid.len().saturating_sub(1)];
+    match objects
+        .iter()
+        .any(|(known, _)| known.starts_with(object) && known.len() == oid.len())
+    {
+        true => Value::NoSuchInstance,
+        false => Value::NoSuchObject,
+    }
+}
+
+/// The objects of the running server told by `control`, sorted by OID.
+pub async fn objects(control: &Control) -> Vec<(Oid, Value)> {
+    let health = control.health().await;
+    let sessions = control.sessions().await.len();
+    let instance = control.services.first().and_then(|service| {
+        dhcp::current_conf(&service.shared_conf)
+            .get_instance_name()
+            .map(str::to_string)
+    });
+    let uptime = control.started.elapsed().unwrap_or_default();
+    let counter = |value: u64| Value::Counter32(value as u32);
+    let gauge = |value: u64| Value::Gauge32(value.min(u32::MAX.into()) as u32);
+    let dhcp_serving = health
+        .services
+        .iter()
+        .any(|service| service.dhcp_ifaces.is_some());
+    let tftp_serving = health
+        .services
+        .iter()
+        .any(|service| service.tftp_dir.is_some());
+    let analytics = control.tracker.analytics().snapshot();
+
+    let system = |arc: u32| [&SYSTEM[..], &[arc, 0]].concat();
+    let scalar = |arc: u32| [&PREFIX[..], &[1, arc, 0]].concat();
+    let mut objects = vec![
+        (
+            system(1),
+            Value::OctetString(format!("preboot-oxide {}", health.version).into_bytes()),
+        ),
+        (system(2), Value::ObjectId(PREFIX.to_vec())),
+        (system(3), Value::TimeTicks(uptime_ticks(uptime))),
+        (
+            system(5),
+            Value::OctetString(instance.unwrap_or("preboot-oxide".to_string()).into_bytes()),
+        ),
+        (scalar(1), Value::OctetString(health.version.into_bytes())),
+        (scalar(2), counter(health.counts.offers)),
+        (scalar(3), counter(health.counts.acks)),
+        (scalar(4), counter(health.counts.transfers)),
+        (scalar(5), gauge(sessions as u64)),
+        (scalar(6), gauge(control.tracker.active_transfers())),
+        (scalar(7), Value::truth(dhcp_serving)),
+        (scalar(8), Value::truth(tftp_serving)),
+        (scalar(9), Value::truth(dhcp::is_dry_run())),
+        (scalar(10), counter(analytics.totals.failed)),
+    ];
+    for (index, iface) in health.ifaces.into_iter().enumerate() {
+        let column = |arc: u32| [&PREFIX[..], &[2, 1, arc, index as u32 + 1]].concat();
+        objects.extend([
+            (column(1), Value::OctetString(iface.iface.into_bytes())),
+            (column(2), counter(iface.received)),
+            (column(3), counter(iface.ignored)),
+            (column(4), counter(iface.answered)),
+            (column(5), counter(iface.transfers)),
+            (column(6), Value::truth(iface.upstream_missing)),
+        ]);
+    }
+    objects.sort_by(|(a, _), (b, _)| a.cmp(b));
+    objects
+}
+
+/// Hundredths of a second of `uptime`, wrapping after 497 days as
+/// `sysUpTime` does.
+fn uptime_ticks(uptime: Duration) -> u32 {
+    (uptime.as_millis() / 10 % (u32::MAX as u128 + 1)) as u32
+}
+
+/// Answers the GET, GETNEXT and GETBULK requests of `snmp_community` on the
+/// UDP `snmp_addr`, dropping the others. Logs an error if the port is taken.
+pub fn spawn_snmp_async(conf: &Conf, control: Arc<Control>) -> Result<()> {
+    let Some(addr) = conf.get_snmp_addr() else {
+        return Ok(());
+    };
+    let socket = match StdUdpSocket::bind(addr) {
+        Ok(socket) => UdpSocket::from(socket),
+        Err(e) => {
+            error!("SNMP agent on {addr} failed: {e}");
+            return Ok(());
+        }
+    };
+    info!("SNMP agent listening on {addr}");
+
+    let community = conf.get_snmp_community().as_bytes().to_vec();
+    task::spawn(async move {
+        let mut buffer = vec![0; 65535];
+        loop {
+            let (len, peer) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("Failed receiving SNMP request on {addr}: {e}");
+                    continue;
+                }
+            };
+            let request = match Message::decode(&buffer[..len]) {
+                Ok(request) => request,
+                Err(e) => {
+                    debug!("Invalid SNMP request from {peer}: {e:#}");
+                    continue;
+                }
+            };
+            if request.community != community {
+                debug!("SNMP request from {peer} of another community, dropped.");
+                continue;
+            }
+            let objects = objects(&control).await;
+            let Some(response) = answer(&request, &objects) else {
+                debug!("Unsupported SNMP request from {peer}, dropped.");
+                continue;
+            };
+            if let Err(e) = socket.send_to(&response.encode(), peer).await {
+                debug!("Failed answering SNMP request of {peer}: {e}");
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,412 @@
+//! Boot statistics: how far each client got in booting, offered, acknowledged,
+//! downloading its boot file, or timing out in the DHCP handshake, to tell
+//! whether the machines of a campaign actually booted. A boot attempt of a
+//! client is what it does within `ATTEMPT_WINDOW` of its first offer, the
+//! furthest it got deciding its outcome, as the chainloads of iPXE ask again.
+//! The attempts ended are added up, since the statistics were started, and
+//! with `stats_file` they survive restarts, saved every minute.
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use async_std::task;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{control, tracker::BootTracker, util::part_path, Result};
+
+/// Time the offers, acknowledgements and downloads of a client count as one
+/// boot attempt for, from its first offer.
+pub const ATTEMPT_WINDOW: Duration = Duration::from_secs(15 * 60);
+/// Clients kept, the attempts of those not seen for the longest being ended
+/// beyond.
+pub const MAX_CLIENTS: usize = 10_000;
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How far a boot attempt got, the later variants being further.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Offered a boot file, not acknowledged yet.
+    Offered,
+    /// Its DHCP session expired before the acknowledgement.
+    TimedOut,
+    /// Acknowledged, its boot file not downloaded.
+    Acked,
+    /// Downloaded its boot file whole.
+    Booted,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Offered => "offered",
+            Self::TimedOut => "timed out",
+            Self::Acked => "acknowledged",
+            Self::Booted => "booted",
+        }
+    }
+}
+
+/// A boot attempt, its times in seconds since the Unix epoch.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Attempt {
+    pub started: u64,
+    pub updated: u64,
+    pub outcome: Outcome,
+    pub boot_file: Option<String>,
+}
+
+/// The attempts ended, by outcome.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Totals {
+    pub offered: u64,
+    pub timed_out: u64,
+    pub acked: u64,
+    pub booted: u64,
+}
+
+impl Totals {
+    fn add(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Offered => self.offered += 1,
+            Outcome::TimedOut => self.timed_out += 1,
+            Outcome::Acked => self.acked += 1,
+            Outcome::Booted => self.booted += 1,
+        }
+    }
+
+    pub fn attempts(&self) -> u64 {
+        self.offered + self.timed_out + self.acked + self.booted
+    }
+}
+
+/// A client, its last attempt and those ended before.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClientStats {
+    pub last: Attempt,
+    pub ended: Totals,
+}
+
+/// The statistics, as saved to `stats_file` and answered on the control
+/// socket.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StatsState {
+    /// When the statistics were started, in seconds since the Unix epoch.
+    pub since: u64,
+    /// The attempts ended, of all the clients.
+    pub ended: Totals,
+    /// The clients by MAC address.
+    pub clients: BTreeMap<String, ClientStats>,
+}
+
+impl StatsState {
+    /// The attempts ended and the last ones, of all the clients.
+    pub fn totals(&self) -> Totals {
+        let mut totals = self.ended;
+        self.clients
+            .values()
+            .for_each(|client| totals.add(client.last.outcome));
+        totals
+    }
+}
+
+/// The statistics of the running server.
+#[derive(Default)]
+pub struct BootStats {
+    state: Mutex<StatsState>,
+    /// Changed since saved.
+    changed: AtomicBool,
+    /// Handed over to the server taking over, no longer saved.
+    handed_over: AtomicBool,
+}
+
+impl BootStats {
+    pub fn new(state: StatsState) -> Self {
+        Self {
+            state: Mutex::new(state),
+            ..Default::default()
+        }
+    }
+
+    /// The statistics saved at `path`, started anew when there are none.
+    pub fn load(path: &Path) -> Result<Self> {
+        let state = match fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .context(format!("Reading the boot statistics {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StatsState {
+                since: unix_secs(SystemTime::now()),
+                ..Default::default()
+            },
+            Err(e) => {
+                return Err(e).context(format!("Reading the boot statistics {}", path.display()))
+            }
+        };
+        Ok(Self::new(state))
+    }
+
+    /// Saves the statistics to `path`, replacing it at once.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        self.changed.store(false, Ordering::Relaxed);
+        let content = serde_json::to_vec(&self.snapshot())?;
+        let part = part_path(path);
+        fs::write(&part, content)?;
+        fs::rename(&part, path)?;
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> StatsState {
+        self.lock().clone()
+    }
+
+    /// The statistics for the server taking over, which saves them from
+    /// then on.
+    pub fn hand_over(&self) -> StatsState {
+        self.handed_over.store(true, Ordering::Relaxed);
+        self.snapshot()
+    }
+
+    /// Carries on the statistics of the server taken over.
+    pub fn inherit(&self, state: StatsState) {
+        *self.lock() = state;
+    }
+
+    /// Records that `mac_address` was offered `boot_file` at `now`.
+    pub fn offered(&self, mac_address: &str, boot_file: Option<&str>, now: SystemTime) {
+        self.reached(mac_address, Outcome::Offered, boot_file, now);
+    }
+
+    /// Records that `mac_address` was acknowledged with `boot_file` at `now`.
+    pub fn acked(&self, mac_address: &str, boot_file: Option<&str>, now: SystemTime) {
+        self.reached(mac_address, Outcome::Acked, boot_file, now);
+    }
+
+    /// Records that `mac_address` downloaded its boot file at `now`.
+    pub fn booted(&self, mac_address: &str, now: SystemTime) {
+        self.reached(mac_address, Outcome::Booted, None, now);
+    }
+
+    /// Records that the DHCP session of `mac_address` expired at `now`
+    /// before it was acknowledged.
+    pub fn timed_out(&self, mac_address: &str, now: SystemTime) {
+        self.reached(mac_address, Outcome::TimedOut, None, now);
+    }
+
+    fn reached(
+        &self,
+        mac_address: &str,
+        outcome: Outcome,
+        boot_file: Option<&str>,
+        now: SystemTime,
+    ) {
+        let now = unix_secs(now);
+        let mut state = self.lock();
+        let state = &mut *state;
+        match state.clients.get_mut(mac_address) {
+            Some(client) if now.saturating_sub(client.last.started) < ATTEMPT_WINDOW.as_secs() => {
+                let last = &mut client.last;
+                last.outcome = last.outcome.max(outcome);
+                last.updated = now;
+                if let Some(boot_file) = boot_file {
+                    last.boot_file = Some(boot_file.to_string());
+                }
+            }
+            // A session expiring long after is no new attempt
+            Some(_) if outcome == Outcome::TimedOut => return,
+            found => {
+                let attempt = Attempt {
+                    started: now,
+                    updated: now,
+                    outcome,
+                    boot_file: boot_file.map(str::to_string),
+                };
+                match found {
+                    Some(client) => {
+                        state.ended.add(client.last.outcome);
+                        client.ended.add(client.last.outcome);
+                        client.last = attempt;
+                    }
+                    None => {
+                        if state.clients.len() >= MAX_CLIENTS {
+                            end_oldest(state);
+                        }
+                        let client = ClientStats {
+                            last: attempt,
+                            ended: Totals::default(),
+                        };
+                        state.clients.insert(mac_address.to_string(), client);
+                    }
+                }
+            }
+        }
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, StatsState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Ends the attempt of the client not seen for the longest, forgetting it.
+fn end_oldest(state: &mut StatsState) {
+    let oldest = state
+        .clients
+        .iter()
+        .min_by_key(|(_, client)| client.last.updated)
+        .map(|(mac_address, _)| mac_address.clone());
+    if let Some(client) = oldest.and_then(|oldest| state.clients.remove(&oldest)) {
+        state.ended.add(client.last.outcome);
+    }
+}
+
+/// Saves the statistics of `tracker` to `path` every `SAVE_INTERVAL` they
+/// changed, until they're handed over.
+pub fn spawn_saving(tracker: Arc<BootTracker>, path: PathBuf) {
+    task::spawn(async move {
+        loop {
+            task::sleep(SAVE_INTERVAL).await;
+            let stats = tracker.stats();
+            if stats.handed_over.load(Ordering::Relaxed) {
+                break;
+            }
+            if !stats.changed.load(Ordering::Relaxed) {
+                continue;
+            }
+            match stats.save(&path) {
+                Ok(()) => debug!("Saved the boot statistics to {}.", path.display()),
+                Err(e) => warn!(
+                    "Saving the boot statistics to {} failed: {e:#}",
+                    path.display()
+                ),
+            }
+        }
+    });
+}
+
+/// Asks the server listening on the control socket at `path` for its
+/// statistics.
+pub fn ask(path: &Path) -> Result<StatsState> {
+    Ok(serde_json::from_value(control::request(path, "stats")?)?)
+}
+
+/// The statistics of `state` as a report, of the clients seen since `since`,
+/// or of all of them, at `now`: how many booted, and those that didn't.
+pub fn report(state: &StatsState, since: Option<SystemTime>, now: SystemTime) -> String {
+    let since = since.map(unix_secs);
+    let clients: Vec<(&String, &ClientStats)> = state
+        .clients
+        .iter()
+        .filter(|(_, client)| since.is_none_or(|since| client.last.updated >= since))
+        .collect();
+    let mut window = Totals::default();
+    clients
+        .iter()
+        .for_each(|(_, client)| window.add(client.last.outcome));
+
+    let mut lines = vec![match since {
+        Some(since) => format!(
+            "Clients seen since {}: {}",
+            format_time(since),
+            clients.len()
+        ),
+        None => format!("Clients seen: {}", clients.len()),
+    }];
+    lines.push(format!(
+        "  Booted: {}",
+        with_rate(window.booted, window.attempts())
+    ));
+    lines.push(format!(
+        "  Acknowledged, boot file not downloaded: {}",
+        window.acked
+    ));
+    lines.push(format!(
+        "  Timed out in the DHCP handshake: {}",
+        window.timed_out
+    ));
+    lines.push(format!("  Offered, not acknowledged: {}", window.offered));
+
+    let failed: Vec<&(&String, &ClientStats)> = clients
+        .iter()
+        .filter(|(_, client)| is_failed(&client.last, unix_secs(now)))
+        .collect();
+    if !failed.is_empty() {
+        lines.push("Not booted:".to_string());
+        let mut rows = vec![["MAC", "OUTCOME", "LAST SEEN", "BOOT FILE"].map(str::to_string)];
+        rows.extend(failed.iter().map(|(mac_address, client)| {
+            [
+                mac_address.to_string(),
+                client.last.outcome.as_str().to_string(),
+                format_time(client.last.updated),
+                client.last.boot_file.clone().unwrap_or("-".to_string()),
+            ]
+        }));
+        let widths: Vec<usize> = (0..4)
+            .map(|column| {
+                rows.iter()
+                    .map(|row| row[column].len())
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+        lines.extend(rows.iter().map(|row| {
+            let cells: Vec<String> = (0..4)
+                .map(|column| format!("{:width$}", row[column], width = widths[column]))
+                .collect();
+            format!("  {}", cells.join("  ").trim_end())
+        }));
+    }
+
+    let totals = state.totals();
+    lines.push(format!(
+        "Boot attempts since {}: {}, booted: {}",
+        format_time(state.since),
+        totals.attempts(),
+        with_rate(totals.booted, totals.attempts())
+    ));
+
+    lines.join("\n") + "\n"
+}
+
+/// Whether `attempt` failed by `now`, having timed out, or not gone further
+/// within its window.
+fn is_failed(attempt: &Attempt, now: u64) -> bool {
+    match attempt.outcome {
+        Outcome::Booted => false,
+        Outcome::TimedOut => true,
+        Outcome::Offered | Outcome::Acked => {
+            now.saturating_sub(attempt.started) >= ATTEMPT_WINDOW.as_secs()
+        }
+    }
+}
+
+/// `count` with its share of `total`, e.g. `196 (98.0%)`.
+fn with_rate(count: u64, total: u64) -> String {
+    match total {
+        0 => count.to_string(),
+        _ => format!("{count} ({:.1}%)", count as f64 * 100.0 / total as f64),
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn format_time(secs: u64) -> String {
+    OffsetDateTime::from_unix_timestamp(secs as i64)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or(secs.to_string())
+}
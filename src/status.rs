@@ -0,0 +1,169 @@
+//! `status` subcommand: whether the server is running, and the services the
+//! configuration starts, with where they listen, for a quick look at a host
+//! without reading its configuration. The running server tells its health
+//! on its control socket.
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conf::Conf,
+    control,
+    tracker::{Counts, IfaceCounts},
+    Result,
+};
+
+/// What the running server tells the `status` command.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Health {
+    pub version: String,
+    pub uptime_secs: u64,
+    /// Where the configuration it started with was loaded from.
+    pub configuration: String,
+    pub counts: Counts,
+    /// The counts of the interfaces DHCP messages were received on.
+    #[serde(default)]
+    pub ifaces: Vec<IfaceCounts>,
+    /// The top level configuration, then the tenants.
+    pub services: Vec<ServiceHealth>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServiceHealth {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Interfaces DHCP listens on, `None` with it disabled.
+    pub dhcp_ifaces: Option<Vec<String>>,
+    /// Directory TFTP serves, `None` when it isn't started.
+    pub tftp_dir: Option<String>,
+}
+
+/// The status of the server, `running` or not, with the configuration
+/// loaded from `origin`.
+pub fn report(conf: &Conf, origin: &str, running: bool) -> String {
+    let server = match conf.get_instance_name() {
+        Some(name) => format!("preboot-oxide instance {name}"),
+        None => "preboot-oxide".to_string(),
+    };
+    let mut lines = vec![
+        match running {
+            true => format!("{server} is running."),
+            false => format!("{server} is not running."),
+        },
+        format!("Configuration: {origin}"),
+    ];
+    for (tenant, conf) in conf.served() {
+        let indent = match tenant {
+            Some(tenant) => {
+                lines.push(format!("Tenant {tenant}:"));
+                "  "
+            }
+            None => "",
+        };
+        lines.extend(services(conf).into_iter().map(|line| format!("{indent}{line}")));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Asks the server listening on the control socket at `path` for its health.
+pub fn ask(path: &Path) -> Result<Health> {
+    Ok(serde_json::from_value(control::request(path, "status")?)?)
+}
+
+/// The health of the running server, told after the `report`.
+pub fn health_report(health: &Health) -> String {
+    let counts = health.counts;
+    let mut lines = vec![
+        "Running server:".to_string(),
+        format!("  Version: {}", health.version),
+        format!("  Uptime: {}", format_uptime(health.uptime_secs)),
+        format!("  Configuration: {}", health.configuration),
+        format!(
+            "  Offers: {}, acknowledgements: {}, transfers: {}",
+            counts.offers, counts.acks, counts.transfers
+        ),
+    ];
+    for iface in &health.ifaces {
+        lines.push(format!(
+            "  Interface {}: received {}, ignored {}, answered {}, transfers {}",
+            iface.iface, iface.received, iface.ignored, iface.answered, iface.transfers
+        ));
+        if iface.dropped > 0 {
+            lines.push(format!(
+                "    Dropped {} DHCP messages, too many being answered at once",
+                iface.dropped
+            ));
+        }
+        if iface.upstream_missing {
+            lines.push(format!(
+                "    No DHCP server offering, the last {} sessions expired waiting for it",
+                iface.unoffered
+            ));
+        }
+    }
+    for service in &health.services {
+        let indent = match &service.tenant {
+            Some(tenant) => {
+                lines.push(format!("  Tenant {tenant}:"));
+                "    "
+            }
+            None => "  ",
+        };
+        lines.push(match &service.dhcp_ifaces {
+            Some(ifaces) if ifaces.is_empty() => format!("{indent}DHCP: on no interface"),
+            Some(ifaces) => format!("{indent}DHCP: on {}", ifaces.join(", ")),
+            None => format!("{indent}DHCP: disabled"),
+        });
+        lines.push(match &service.tftp_dir {
+            Some(dir) => format!("{indent}TFTP: {dir}"),
+            None => format!("{indent}TFTP: not started"),
+        });
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// `secs` in days, hours, minutes and seconds, e.g. `2h 5m 0s`.
+fn format_uptime(secs: u64) -> String {
+    let units = [(secs / 86400, "d"), (secs / 3600 % 24, "h"), (secs / 60 % 60, "m")];
+    let mut parts: Vec<String> = units
+        .into_iter()
+        .skip_while(|(value, _)| *value == 0)
+        .map(|(value, unit)| format!("{value}{unit}"))
+        .collect();
+    parts.push(format!("{}s", secs % 60));
+
+    parts.join(" ")
+}
+
+/// The services of `conf`, one per line.
+pub(crate) fn services(conf: &Conf) -> Vec<String> {
+    let ifaces = match conf.get_ifaces() {
+        Some(ifaces) => format!("on {}", ifaces.join(", ")),
+        None => "on all interfaces".to_string(),
+    };
+    let mut lines = vec![match conf.get_dhcp_enabled() {
+        true => format!("DHCP: {ifaces}"),
+        false => "DHCP: disabled".to_string(),
+    }];
+    if conf.get_dhcp_enabled() && conf.get_pxe_boot_server() {
+        lines.push("PXE boot server: port 4011".to_string());
+    }
+    lines.push(match (conf.get_tftp_serve_path(), conf.get_tftp_enabled()) {
+        (Some(dir), true) => format!("TFTP: {dir}, port 69"),
+        (Some(_), false) => "TFTP: disabled".to_string(),
+        (None, _) => "TFTP: not started, no tftp_server_dir".to_string(),
+    });
+    if let Some(port) = conf.get_http_port() {
+        lines.push(format!("HTTP: port {port}"));
+    }
+    if let Some(port) = conf.get_https_port() {
+        lines.push(format!("HTTPS: port {port}"));
+    }
+    if let Some(hostname) = conf.get_boot_hostname() {
+        lines.push(format!("DNS: {hostname}, port {}", conf.get_dns_port()));
+    }
+
+    lines
+}
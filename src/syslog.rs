@@ -0,0 +1,346 @@
+//! Outputs of the messages to the system log, `log_output`: syslog, on the
+//! local socket of the host or to the server of `syslog_server` over UDP or
+//! TCP in RFC 5424, and journald in its native protocol, for the appliances
+//! without log collection beyond them. The client handled by the task is
+//! passed as structured data, or as fields of the journal.
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::{
+    fmt,
+    io::{self, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use log::{Level, Record};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    logging::{self, TaskClient},
+    util::bytes_to_mac_address,
+    Result,
+};
+
+/// Name the messages are logged under.
+pub const APP_NAME: &str = "preboot-oxide";
+pub const DEFAULT_SYSLOG_PORT: u16 = 514;
+#[cfg(unix)]
+const LOCAL_SYSLOG_SOCKET: &str = "/dev/log";
+#[cfg(target_os = "linux")]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+/// Facility of the messages, `daemon`.
+const FACILITY: u8 = 3;
+/// ID of the structured data of the client, under the private enterprise
+/// number reserved for documentation.
+const SD_ID: &str = "client@32473";
+const TCP_TIMEOUT: Duration = Duration::from_secs(1);
+/// Time the TCP server isn't connected to again after failing.
+const TCP_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Where the messages are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogOutput {
+    /// Stderr, or stdout with `--log-format json`.
+    #[default]
+    Stderr,
+    Syslog,
+    Journald,
+}
+
+impl FromStr for LogOutput {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "stderr" => Ok(Self::Stderr),
+            "syslog" => Ok(Self::Syslog),
+            "journald" => Ok(Self::Journald),
+            _ => Err(anyhow!("Invalid log output: {s}, expected one of: stderr, syslog, journald")),
+        }
+    }
+}
+
+impl fmt::Display for LogOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stderr => write!(f, "stderr"),
+            Self::Syslog => write!(f, "syslog"),
+            Self::Journald => write!(f, "journald"),
+        }
+    }
+}
+
+/// Remote syslog server, `[udp://|tcp://]<ip>[:<port>]`, UDP and port 514
+/// by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyslogServer {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+}
+
+impl FromStr for SyslogServer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (is_tcp, addr) = match s.split_once("://") {
+            Some(("udp", addr)) => (false, addr),
+            Some(("tcp", addr)) => (true, addr),
+            Some(_) => bail!("Invalid syslog server: {s}, expected udp:// or tcp://"),
+            None => (false, s),
+        };
+        let addr = addr
+            .parse::<SocketAddr>()
+            .or_else(|_| {
+                let ip = addr.strip_prefix('[').and_then(|ip| ip.strip_suffix(']')).unwrap_or(addr);
+                ip.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, DEFAULT_SYSLOG_PORT))
+            })
+            .map_err(|_| anyhow!("Invalid syslog server: {s}, expected [udp://|tcp://]<ip>[:<port>]"))?;
+
+        Ok(match is_tcp {
+            true => Self::Tcp(addr),
+            false => Self::Udp(addr),
+        })
+    }
+}
+
+impl fmt::Display for SyslogServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Udp(addr) => write!(f, "udp://{addr}"),
+            Self::Tcp(addr) => write!(f, "tcp://{addr}"),
+        }
+    }
+}
+
+/// Writes the messages to the system log, the sockets being opened once,
+/// before the sandbox is applied.
+pub enum Emitter {
+    Syslog { transport: Transport, hostname: String },
+    #[cfg(target_os = "linux")]
+    Journald(UnixDatagram),
+}
+
+pub enum Transport {
+    #[cfg(unix)]
+    Local(UnixDatagram),
+    Udp(UdpSocket),
+    Tcp {
+        addr: SocketAddr,
+        /// The connection, and when it last failed.
+        stream: Mutex<(Option<TcpStream>, Option<Instant>)>,
+    },
+}
+
+impl Emitter {
+    /// The emitter of `output`, `None` for stderr.
+    pub fn open(output: LogOutput, server: Option<SyslogServer>) -> Result<Option<Self>> {
+        let emitter = match (output, server) {
+            (LogOutput::Stderr, _) => return Ok(None),
+            (LogOutput::Syslog, Some(SyslogServer::Udp(addr))) => {
+                let local: IpAddr = match addr {
+                    SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+                    SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+                };
+                let socket = UdpSocket::bind((local, 0))?;
+                socket.connect(addr).with_context(|| format!("Connecting to the syslog server {addr}"))?;
+                Self::syslog(Transport::Udp(socket))
+            }
+            (LogOutput::Syslog, Some(SyslogServer::Tcp(addr))) => {
+                // Servers down on start are retried on the next messages
+                let stream = tcp_connect(addr).ok();
+                let failed = stream.is_none().then(Instant::now);
+                Self::syslog(Transport::Tcp { addr, stream: Mutex::new((stream, failed)) })
+            }
+            #[cfg(unix)]
+            (LogOutput::Syslog, None) => {
+                let socket = UnixDatagram::unbound()?;
+                socket
+                    .connect(LOCAL_SYSLOG_SOCKET)
+                    .with_context(|| format!("Connecting to the syslog socket {LOCAL_SYSLOG_SOCKET}"))?;
+                Self::syslog(Transport::Local(socket))
+            }
+            #[cfg(not(unix))]
+            (LogOutput::Syslog, None) => bail!("log_output syslog needs syslog_server on this system."),
+            #[cfg(target_os = "linux")]
+            (LogOutput::Journald, _) => {
+                let socket = UnixDatagram::unbound()?;
+                socket
+                    .connect(JOURNALD_SOCKET)
+                    .with_context(|| format!("Connecting to the journal socket {JOURNALD_SOCKET}"))?;
+                Self::Journald(socket)
+            }
+            #[cfg(not(target_os = "linux"))]
+            (LogOutput::Journald, _) => bail!("log_output journald is only supported on Linux."),
+        };
+
+        Ok(Some(emitter))
+    }
+
+    fn syslog(transport: Transport) -> Self {
+        Self::Syslog { transport, hostname: hostname() }
+    }
+
+    /// Writes `record`, logged at `time` by the task handling `client`.
+    pub fn emit(&self, time: OffsetDateTime, record: &Record, client: &TaskClient) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Self::Syslog { transport: Transport::Local(socket), .. } => {
+                socket.send(local_line(record, client).as_bytes()).map(|_| ())
+            }
+            Self::Syslog { transport: Transport::Udp(socket), hostname } => {
+                socket.send(rfc5424_line(time, hostname, record, client).as_bytes()).map(|_| ())
+            }
+            Self::Syslog { transport: Transport::Tcp { addr, stream }, hostname } => {
+                let line = rfc5424_line(time, hostname, record, client);
+                let mut stream = stream.lock().unwrap_or_else(|e| e.into_inner());
+                let (connection, failed) = &mut *stream;
+                if connection.is_none() && failed.is_none_or(|failed| failed.elapsed() >= TCP_RETRY_INTERVAL) {
+                    *connection = tcp_connect(*addr).ok();
+                }
+                let result = match connection {
+                    // Octet counting of RFC 6587
+                    Some(connection) => write!(connection, "{} {line}", line.len()),
+                    None => Err(io::Error::from(io::ErrorKind::NotConnected)),
+                };
+                *failed = None;
+                if result.is_err() {
+                    *connection = None;
+                    *failed = Some(Instant::now());
+                }
+                result
+            }
+            #[cfg(target_os = "linux")]
+            Self::Journald(socket) => socket.send(&journald_fields(record, client)).map(|_| ()),
+        }
+    }
+}
+
+fn tcp_connect(addr: SocketAddr) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(&addr, TCP_TIMEOUT)?;
+    stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+    Ok(stream)
+}
+
+/// Severity of RFC 5424, trace being debug as well.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// The message of RFC 5424 of `record`, with the client as structured data
+/// and the subsystem as message ID.
+pub fn rfc5424_line(time: OffsetDateTime, hostname: &str, record: &Record, client: &TaskClient) -> String {
+    let params = [
+        ("mac", client.mac.map(|mac| bytes_to_mac_address(&mac))),
+        ("xid", client.xid.map(|xid| format!("{xid:#010x}"))),
+        ("session", client.session_id()),
+        ("ip", client.ip.map(|ip| ip.to_string())),
+    ];
+    let params: Vec<String> = params
+        .into_iter()
+        .filter_map(|(name, value)| Some(format!(" {name}=\"{}\"", escape_param(&value?))))
+        .collect();
+    let data = match params.is_empty() {
+        true => "-".to_string(),
+        false => format!("[{SD_ID}{}]", params.concat()),
+    };
+
+    format!(
+        "<{}>1 {} {hostname} {APP_NAME} {} {} {data} {}",
+        FACILITY * 8 + severity(record.level()),
+        time.format(&Rfc3339).unwrap_or("-".to_string()),
+        std::process::id(),
+        logging::subsystem(record.target()),
+        record.args()
+    )
+}
+
+/// Values of structured data escape `"`, `\` and `]`.
+fn escape_param(value: &str) -> String {
+    value.chars().fold(String::new(), |mut escaped, c| {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+/// The message of `record` for the local syslog socket, as the C library
+/// writes it, the daemon adding the time and hostname, with the session of
+/// `client` ahead.
+pub fn local_line(record: &Record, client: &TaskClient) -> String {
+    let session = client.session_id().map(|session| format!("session={session} "));
+    format!(
+        "<{}>{APP_NAME}[{}]: {}{}",
+        FACILITY * 8 + severity(record.level()),
+        std::process::id(),
+        session.unwrap_or_default(),
+        record.args()
+    )
+}
+
+/// The datagram of the native protocol of journald for `record`, with the
+/// client as the fields `PO_CLIENT_MAC`, `PO_XID`, `PO_SESSION` and
+/// `PO_CLIENT_IP`.
+pub fn journald_fields(record: &Record, client: &TaskClient) -> Vec<u8> {
+    let fields = [
+        ("MESSAGE", Some(record.args().to_string())),
+        ("PRIORITY", Some(severity(record.level()).to_string())),
+        ("SYSLOG_IDENTIFIER", Some(APP_NAME.to_string())),
+        ("PO_SUBSYSTEM", Some(logging::subsystem(record.target()).to_string())),
+        ("PO_TARGET", Some(record.target().to_string())),
+        ("PO_CLIENT_MAC", client.mac.map(|mac| bytes_to_mac_address(&mac))),
+        ("PO_XID", client.xid.map(|xid| format!("{xid:#010x}"))),
+        ("PO_SESSION", client.session_id()),
+        ("PO_CLIENT_IP", client.ip.map(|ip| ip.to_string())),
+    ];
+    let mut datagram = Vec::new();
+    for (name, value) in fields {
+        let Some(value) = value else {
+            continue;
+        };
+        datagram.extend_from_slice(name.as_bytes());
+        // Values of several lines are given by their length
+        match value.contains('\n') {
+            true => {
+                datagram.push(b'\n');
+                datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            }
+            false => datagram.push(b'='),
+        }
+        datagram.extend_from_slice(value.as_bytes());
+        datagram.push(b'\n');
+    }
+    datagram
+}
+
+/// Name of the host, `-` when unknown as RFC 5424 has it.
+fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut name = [0u8; 256];
+        // SAFETY: the buffer is writable for its length
+        if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } == 0 {
+            let len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+            if let Ok(name) = std::str::from_utf8(&name[..len]) {
+                if !name.is_empty() {
+                    return name.to_string();
+                }
+            }
+        }
+    }
+    #[cfg(windows)]
+    if let Ok(name) = std::env::var("COMPUTERNAME") {
+        return name;
+    }
+
+    "-".to_string()
+}
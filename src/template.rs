@@ -0,0 +1,215 @@
+//! Files rendered per client by the HTTP service from templates and the
+//! configuration matched for the client: iPXE scripts and GRUB
+//! configurations, giving the boot loaders a single dynamic entry point, and
+//! autoinstall answer files (Kickstart, preseed, cloud-init, Ignition, ...)
+//! driving unattended installs.
+use std::{
+    io::Write,
+    net::IpAddr,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use anyhow::Context;
+use async_std::task;
+
+use crate::{
+    conf::{ConfEntryRef, MacAddress},
+    util::bytes_to_mac_address,
+    Result,
+};
+
+/// Path the HTTP service serves the iPXE scripts at.
+pub const IPXE_SCRIPT_PATH: &str = "/boot.ipxe";
+/// Path the HTTP service serves the GRUB configurations at.
+pub const GRUB_CFG_PATH: &str = "/grub.cfg";
+/// Path the HTTP service serves the Ignition configs at.
+pub const IGNITION_PATH: &str = "/ignition";
+/// Path the HTTP service serves the iPXE menu at.
+pub const MENU_PATH: &str = "/menu.ipxe";
+/// Path under which the templates of `autoinstall_dir` are served.
+pub const AUTOINSTALL_PATH: &str = "/autoinstall/";
+
+/// Script for clients whose configuration has no `ipxe_script` template.
+pub const DEFAULT_IPXE_TEMPLATE: &str = "#!ipxe\nchain {{base_url}}/{{boot_file}}\n";
+/// Script of the clients without anything to boot, showing the iPXE menu.
+pub const MENU_IPXE_TEMPLATE: &str = "#!ipxe\nchain {{base_url}}/menu.ipxe?mac={{mac}}\n";
+
+/// How long the iPXE menu waits for a choice before booting the default entry.
+const MENU_TIMEOUT_MS: u32 = 30_000;
+
+/// iPXE menu listing `labels`, their entries being chained from
+/// `<base_url>/boot.ipxe?entry=<index>`, followed by netboot.xyz when served,
+/// the local disk and the iPXE shell. `default` is the index of the entry
+/// booted after the timeout, the local disk otherwise.
+pub fn ipxe_menu(
+    labels: &[String],
+    default: Option<usize>,
+    base_url: &str,
+    netbootxyz: bool,
+) -> String {
+    let mut items = String::new();
+    let mut targets = String::new();
+    for (index, label) in labels.iter().enumerate() {
+        items.push_str(&format!("item entry{index} {label}\n"));
+        let url = format!("{base_url}{IPXE_SCRIPT_PATH}?entry={index}&mac=${{net0/mac}}");
+        targets.push_str(&format!(":entry{index}\nchain {url} || goto failed\n"));
+    }
+    items.push_str("item --gap\n");
+    if netbootxyz {
+        let dir = crate::netbootxyz::DIR;
+        items.push_str("item netbootxyz netboot.xyz\n");
+        let url = format!("{base_url}/{dir}/netboot.xyz");
+        targets.push_str(&format!(
+            ":netbootxyz\n\
+            iseq ${{platform}} efi || chain {url}.kpxe || goto failed\n\
+            iseq ${{buildarch}} arm64 && chain {url}-arm64.efi || goto failed\n\
+            chain {url}.efi || goto failed\n"
+        ));
+    }
+    let default = default
+        .map(|index| format!("entry{index}"))
+        .unwrap_or("local".to_string());
+
+    format!(
+        "#!ipxe\n\
+        :start\n\
+        menu Preboot Oxide\n\
+        {items}\
+        item local Boot from local disk\n\
+        item shell iPXE shell\n\
+        choose --default {default} --timeout {MENU_TIMEOUT_MS} target || goto local\n\
+        goto ${{target}}\n\
+        {targets}\
+        :local\n\
+        exit\n\
+        :shell\n\
+        shell\n\
+        goto start\n\
+        :failed\n\
+        echo Booting ${{target}} failed\n\
+        prompt Press any key to return to the menu\n\
+        goto start\n"
+    )
+}
+
+/// The fields known about a client, in the shape of the DHCP messages the
+/// `match` rules are evaluated against, with the `Uuid` and `Serial` given
+/// by clients over HTTP.
+pub fn client_doc(
+    mac: Option<&MacAddress>,
+    uuid: Option<&str>,
+    serial: Option<&str>,
+) -> serde_json::Value {
+    let mut doc = serde_json::Map::new();
+    if let Some(mac) = mac {
+        doc.insert("chaddr".into(), serde_json::json!(mac));
+    }
+    if let Some(uuid) = uuid {
+        doc.insert("Uuid".into(), uuid.into());
+    }
+    if let Some(serial) = serial {
+        doc.insert("Serial".into(), serial.into());
+    }
+
+    serde_json::Value::Object(doc)
+}
+
+/// Parses a MAC address separated by colons or dashes, as given in
+/// `?mac=${net0/mac}` by iPXE.
+pub fn parse_mac(mac: &str) -> Option<MacAddress> {
+    let bytes = mac
+        .split([':', '-'])
+        .map(|byte| {
+            u8::from_str_radix(byte, 16)
+                .ok()
+                .filter(|_| byte.len() == 2)
+        })
+        .collect::<Option<Vec<u8>>>()?;
+
+    bytes.try_into().ok()
+}
+
+/// Values of the placeholders of the templates rendered for a client: the
+/// built in ones, those of the service rendering the template, then the
+/// `vars` of its configuration, which can't override them. `server` is the
+/// `boot_server_ipv4` of the client or else `local_server`.
+pub fn client_vars<'a>(
+    client_conf: &ConfEntryRef<'a>,
+    mac: Option<&MacAddress>,
+    client_ip: IpAddr,
+    local_server: String,
+    service_vars: Vec<(&'static str, String)>,
+) -> Vec<(&'a str, String)> {
+    let boot_file = client_conf
+        .boot_file
+        .map(|boot_file| boot_file.trim_start_matches('/'))
+        .unwrap_or_default();
+    let server = client_conf
+        .boot_server_ipv4
+        .map(|server| server.to_string())
+        .unwrap_or(local_server);
+
+    let mut vars = vec![
+        ("mac", mac.map(|mac| bytes_to_mac_address(mac)).unwrap_or_default()),
+        ("ip", client_ip.to_string()),
+        ("boot_file", boot_file.to_string()),
+        ("server", server),
+    ];
+    vars.extend(service_vars);
+    vars.extend(
+        client_conf
+            .vars
+            .iter()
+            .map(|(name, value)| (*name, value.to_string())),
+    );
+    // The generated boot entries take further kernel arguments
+    if !vars.iter().any(|(name, _)| *name == "kernel_args") {
+        vars.push(("kernel_args", String::new()));
+    }
+
+    vars
+}
+
+/// Replaces the `{{name}}` placeholders of `template` with their values.
+/// Unknown placeholders are kept, the `${...}` iPXE settings left untouched.
+pub fn render<V: AsRef<str>>(template: &str, vars: &[(&str, V)]) -> String {
+    vars.iter()
+        .fold(template.to_string(), |script, (name, value)| {
+            script.replace(&format!("{{{{{name}}}}}"), value.as_ref())
+        })
+}
+
+/// Whether the template is a Butane config, translated to Ignition.
+pub fn is_butane(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "bu" || extension == "butane")
+}
+
+/// Translates a Butane config to Ignition with the `butane` tool.
+pub async fn butane_to_ignition(config: String) -> Result<String> {
+    task::spawn_blocking(move || {
+        let mut butane = Command::new("butane")
+            .arg("--strict")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Running butane, is it installed?")?;
+        butane
+            .stdin
+            .take()
+            .ok_or(anyhow!("No stdin for butane"))?
+            .write_all(config.as_bytes())?;
+
+        let output = butane.wait_with_output()?;
+        if !output.status.success() {
+            bail!(
+                "butane failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8(output.stdout)?)
+    })
+    .await
+}
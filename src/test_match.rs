@@ -0,0 +1,218 @@
+//! `test-match` subcommand: the `match` entry a client gets, and its
+//! configuration, told without waiting for it to boot, so rule changes can
+//! be checked offline. The client is given by its MAC address, a DHCP message
+//! as JSON, as posted to `webhook_url`, or a pcap capture of its messages.
+use std::{path::Path, time::SystemTime};
+
+use anyhow::Context;
+use dhcproto::v4::{Decodable, Decoder, Message, MessageType, Opcode};
+
+use crate::{
+    conf::Conf,
+    dhcp::{Stage, PXE_BOOT_SERVER_PORT},
+    template,
+    util::{bytes_to_mac_address, mac_from_doc},
+    Result,
+};
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+/// Magic of the captures with nanosecond timestamps.
+const PCAP_MAGIC_NS: u32 = 0xa1b2_3c4d;
+const PCAPNG_MAGIC: u32 = 0x0a0d_0d0a;
+const PCAP_HEADER_LEN: usize = 24;
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IP_PROTOCOL_UDP: u8 = 17;
+const DHCP_SERVER_PORT: u16 = 67;
+
+/// The document of the client of `input`: a MAC address, or the path of a
+/// JSON or pcap file of one of its DHCP messages. The `fields`, given as
+/// `<name>=<value>`, are added to it, e.g. `Interface=eth0`.
+pub fn client_doc(input: &str, fields: &[String]) -> Result<serde_json::Value> {
+    let mut doc = match template::parse_mac(input) {
+        Some(mac) => template::client_doc(Some(&mac), None, None),
+        None => doc_from_file(Path::new(input))?,
+    };
+    let object = doc
+        .as_object_mut()
+        .ok_or(anyhow!("Expected a JSON object of the DHCP message"))?;
+    for field in fields {
+        let (name, value) = field
+            .split_once('=')
+            .ok_or(anyhow!("Invalid field {field}, expected <name>=<value>"))?;
+        object.insert(name.trim().to_string(), value.trim().into());
+    }
+
+    Ok(doc)
+}
+
+fn doc_from_file(path: &Path) -> Result<serde_json::Value> {
+    let content = std::fs::read(path).with_context(|| {
+        format!(
+            "Reading {}, neither a MAC address nor a readable file",
+            path.display()
+        )
+    })?;
+    let is_pcap = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pcap") || ext.eq_ignore_ascii_case("cap"));
+    match is_pcap {
+        true => doc_from_pcap(&content)
+            .with_context(|| format!("Reading the capture {}", path.display())),
+        false => serde_json::from_slice(&content)
+            .with_context(|| format!("Reading the JSON message {}", path.display())),
+    }
+}
+
+/// The document of the first DHCP message sent by a client in the pcap
+/// `capture`, with the `Stage` it's answered at.
+pub fn doc_from_pcap(capture: &[u8]) -> Result<serde_json::Value> {
+    for (port, payload) in udp_payloads(capture)? {
+        if port != DHCP_SERVER_PORT && port != PXE_BOOT_SERVER_PORT {
+            continue;
+        }
+        let Ok(message) = Message::decode(&mut Decoder::new(payload)) else {
+            continue;
+        };
+        if message.opcode() != Opcode::BootRequest {
+            continue;
+        }
+        let stage = match (port, message.opts().msg_type()) {
+            (PXE_BOOT_SERVER_PORT, _) => Some(Stage::BootServer),
+            (_, Some(MessageType::Discover)) => Some(Stage::Offer),
+            (_, Some(MessageType::Request)) => Some(Stage::Ack),
+            _ => None,
+        };
+        let mut doc = serde_json::to_value(message)?;
+        if let (Some(stage), Some(doc)) = (stage, doc.as_object_mut()) {
+            doc.insert("Stage".into(), stage.as_str().into());
+        }
+        return Ok(doc);
+    }
+
+    bail!("No DHCP message of a client found")
+}
+
+/// The destination ports and payloads of the UDP datagrams over IPv4 of
+/// `capture`.
+fn udp_payloads(capture: &[u8]) -> Result<Vec<(u16, &[u8])>> {
+    let header = capture
+        .get(..PCAP_HEADER_LEN)
+        .ok_or(anyhow!("Not a pcap capture, too short"))?;
+    let magic = u32::from_le_bytes(header[..4].try_into()?);
+    let is_little_endian = match magic {
+        PCAP_MAGIC | PCAP_MAGIC_NS => true,
+        _ if [PCAP_MAGIC, PCAP_MAGIC_NS].contains(&magic.swap_bytes()) => false,
+        PCAPNG_MAGIC => bail!("pcapng captures aren't supported, save it as pcap"),
+        _ => bail!("Not a pcap capture"),
+    };
+    let read_u32 = |bytes: &[u8]| {
+        let bytes: [u8; 4] = bytes[..4].try_into().unwrap_or_default();
+        match is_little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        }
+    };
+    let link_type = read_u32(&header[20..]);
+
+    let mut payloads = Vec::new();
+    let mut records = &capture[PCAP_HEADER_LEN..];
+    while records.len() >= PCAP_RECORD_HEADER_LEN {
+        let len = read_u32(&records[8..]) as usize;
+        let Some(packet) = records.get(PCAP_RECORD_HEADER_LEN..PCAP_RECORD_HEADER_LEN + len) else {
+            break;
+        };
+        records = &records[PCAP_RECORD_HEADER_LEN + len..];
+        if let Some(datagram) = ipv4_packet(packet, link_type).and_then(udp_datagram) {
+            payloads.push(datagram);
+        }
+    }
+
+    Ok(payloads)
+}
+
+/// The IPv4 packet of the link layer `frame`.
+fn ipv4_packet(frame: &[u8], link_type: u32) -> Option<&[u8]> {
+    let ethertype = |at: usize| Some(u16::from_be_bytes(frame.get(at..at + 2)?.try_into().ok()?));
+    let offset = match link_type {
+        LINKTYPE_ETHERNET => match ethertype(12)? {
+            ETHERTYPE_VLAN => (ethertype(16)? == ETHERTYPE_IPV4).then_some(18)?,
+            ETHERTYPE_IPV4 => 14,
+            _ => return None,
+        },
+        LINKTYPE_LINUX_SLL => (ethertype(14)? == ETHERTYPE_IPV4).then_some(16)?,
+        LINKTYPE_RAW => 0,
+        _ => return None,
+    };
+
+    frame
+        .get(offset..)
+        .filter(|packet| packet.first().is_some_and(|byte| byte >> 4 == 4))
+}
+
+fn udp_datagram(packet: &[u8]) -> Option<(u16, &[u8])> {
+    let header_len = usize::from(packet.first()? & 0x0f) * 4;
+    if *packet.get(9)? != IP_PROTOCOL_UDP {
+        return None;
+    }
+    let udp = packet.get(header_len..)?;
+    let port = u16::from_be_bytes(udp.get(2..4)?.try_into().ok()?);
+
+    Some((port, udp.get(8..)?))
+}
+
+/// Which of the `match` entries of `conf` match the client of `doc` at the
+/// time `now`, the one used and the configuration it's given.
+pub fn report(conf: &Conf, doc: &serde_json::Value, now: SystemTime) -> Result<String> {
+    let mut lines = Vec::new();
+    if let Some(mac) = mac_from_doc(doc) {
+        lines.push(format!("Client: {}", bytes_to_mac_address(&mac)));
+    }
+    let external = [
+        ("webhook_url", conf.get_webhook_url().is_some()),
+        ("boot_hook", conf.get_boot_hook().is_some()),
+        ("netbox_url", conf.get_netbox_url().is_some()),
+    ];
+    for (name, _) in external.iter().filter(|(_, is_set)| *is_set) {
+        lines.push(format!(
+            "Note: {name} is set, its answers take precedence and aren't asked."
+        ));
+    }
+
+    let matching: Vec<String> = conf
+        .matching_entries_at(doc, now)
+        .into_iter()
+        .map(|index| format!("match[{index}]"))
+        .collect();
+    lines.push(match matching.is_empty() {
+        true => "Matching entries: none".to_string(),
+        false => format!("Matching entries: {}", matching.join(", ")),
+    });
+    lines.push(match conf.used_entry_at(doc, now) {
+        Some(index) => format!(
+            "Used: match[{index}], by the {} policy",
+            conf.get_match_policy()
+        ),
+        None if conf.get_require_match() => "Used: none, ignored with require_match".to_string(),
+        None => "Used: default".to_string(),
+    });
+    match conf.get_from_doc_at(doc.clone(), now)? {
+        Some(entry) => {
+            lines.push("Configuration:".to_string());
+            let yaml = entry.to_entry().to_yaml_string()?;
+            lines.extend(
+                yaml.trim_start_matches("---")
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(|line| format!("  {line}")),
+            );
+        }
+        None => lines.push("Configuration: none".to_string()),
+    }
+
+    Ok(lines.join("\n") + "\n")
+}
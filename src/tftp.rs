@@ -1,80 +1,327 @@
+use std::collections::HashMap;
 use std::io;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+#[cfg(target_os = "linux")]
+use std::net::SocketAddrV4;
 use std::path::Component;
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::{Context, Error};
+use anyhow::Error;
 use async_std::task;
+use async_tftp::server::Handler;
 use async_tftp::{async_trait, packet, server::TftpServerBuilder, Error as TftpError};
-use log::{debug, error, info};
-use network_interface::{Addr, NetworkInterface, NetworkInterfaceConfig};
+use log::{debug, error, info, warn};
 
-use crate::conf::Conf;
+#[cfg(target_os = "linux")]
+use crate::activation;
+use crate::analytics::Protocol;
+use crate::conf::{Conf, FileFilter, MacAddress, SymlinkPolicy, TftpFallback};
+use crate::events::{self, ClientEvent, Event};
+use crate::distro::BootEntry;
+use crate::images::{IMAGES_DIR, VERSIONS_DIR};
+use crate::iso::{is_iso_image, IsoFile, IsoImage};
+use crate::logging::{self, TaskClient};
+use crate::pcap::{self, TftpEndpoint, TftpRequest};
+use crate::quota::QuotaMap;
+use crate::readahead::{ReadAhead, READ_AHEAD_POOL};
+use crate::runtime;
+use crate::template;
+use crate::tracker::{ActiveTransfer, BootTracker};
+use crate::util::{bytes_to_mac_address, listen_ips, part_path};
+use crate::upstream::{self, UpstreamReader};
 use crate::Result;
 
 use async_std::fs::File;
+use futures::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 use log::trace;
 
 type TftpResult<T, E = TftpError> = std::result::Result<T, E>;
 
-pub fn spawn_tftp_service_async(conf: &Conf) -> Result<()> {
-    if let Some(tftp_path) = conf.get_tftp_serve_path() {
+/// Uploads ending within this time of their last write are complete.
+const UPLOAD_COMPLETION_WINDOW: Duration = Duration::from_secs(1);
+/// Time a block is sent again after when it isn't acknowledged, that of the
+/// TFTP server unless the client asks for another.
+const TFTP_RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(3);
+type FileReader = Box<dyn AsyncRead + Send + Unpin>;
+
+pub fn spawn_tftp_service_async(conf: &Conf, tracker: Arc<BootTracker>) -> Result<TftpService> {
+    let mut service = TftpService::new(tracker);
+    task::block_on(service.reload(conf))?;
+
+    Ok(service)
+}
+
+/// The TFTP servers listening on each configured interface address. The
+/// directory handler they share can be replaced at runtime without dropping
+/// the transfers in progress.
+pub struct TftpService {
+    handler: SharedDirHandler,
+    tracker: Arc<BootTracker>,
+    limits: Option<Arc<TransferLimits>>,
+    listeners: HashMap<Ipv4Addr, runtime::JoinHandle<()>>,
+    tftp_dir: Option<String>,
+    block_size_limit: Option<u16>,
+}
+
+impl TftpService {
+    pub fn new(tracker: Arc<BootTracker>) -> Self {
+        Self {
+            handler: Default::default(),
+            tracker,
+            limits: None,
+            listeners: HashMap::new(),
+            tftp_dir: None,
+            block_size_limit: None,
+        }
+    }
+
+    /// The handler serving the TFTP root, following its reloads.
+    pub fn handler(&self) -> SharedDirHandler {
+        self.handler.clone()
+    }
+
+    /// Applies the TFTP settings of `conf`, starting, stopping or rebinding
+    /// the servers as needed.
+    pub async fn reload(&mut self, conf: &Conf) -> Result<()> {
+        let Some(tftp_path) = conf.get_tftp_serve_path() else {
+            if self.tftp_dir.is_some() {
+                info!("TFTP path no longer configured, stopping TFTP service.");
+            } else {
+                info!("TFTP server not started, no path configured.");
+            }
+            self.stop().await;
+            return Ok(());
+        };
+
         let dir = Path::new(&tftp_path);
-        if !dir.exists() || !dir.is_dir() {
+        if !dir.is_dir() && !is_iso_image(dir) {
             return Err(anyhow!(
-                "TFTP path does not exist or is not directory: {:?}",
+                "TFTP path does not exist or is not a directory or ISO image: {:?}",
                 dir
             ));
         }
 
-        let network_interfaces = NetworkInterface::show().context("Listing network interfaces")?;
-        let listen_ips: Vec<Ipv4Addr> = network_interfaces
-            .iter()
-            .filter(|iface| {
-                // only listen on the configured network interfaces
-                conf.get_ifaces()
-                    .map(|ifaces| ifaces.contains(&iface.name))
-                    .unwrap_or(true) // or on all if no interfaces are configured
-            })
-            .map(|iface| {
-                iface
-                    .addr
-                    .iter()
-                    .filter_map(|ip| match ip {
-                        Addr::V4(v4) => Some(v4.ip),
-                        Addr::V6(_) => None,
-                    })
-                    .collect::<Vec<_>>()
-            })
-            .flatten()
+        let limits = match &self.limits {
+            Some(limits)
+                if limits.max_total == conf.get_tftp_max_transfers()
+                    && limits.max_per_client == conf.get_tftp_max_transfers_per_client() =>
+            {
+                Arc::clone(limits)
+            }
+            _ => Arc::new(TransferLimits::new(
+                conf.get_tftp_max_transfers(),
+                conf.get_tftp_max_transfers_per_client(),
+            )),
+        };
+        let handler = DirHandler::new(&tftp_path, DirHandlerMode::ReadOnly)?
+            .with_symlink_policy(conf.get_tftp_symlink_policy())
+            .with_tracker(Arc::clone(&self.tracker))
+            .with_transfer_limits(Arc::clone(&limits))
+            .with_file_filter(conf.get_tftp_file_filter().clone())
+            .with_fallbacks(conf.get_tftp_fallbacks().clone())
+            .with_upstream(conf.get_tftp_upstream())
+            .with_conf(Arc::new(conf.clone()));
+        self.handler.replace(handler);
+        self.limits = Some(limits);
+
+        match &self.tftp_dir {
+            Some(previous) if previous != &tftp_path => {
+                info!("TFTP path changed from {previous} to {tftp_path}")
+            }
+            _ => {}
+        }
+        self.tftp_dir = Some(tftp_path.clone());
+
+        // The directory is still served over HTTP
+        if !conf.get_tftp_enabled() {
+            match self.listeners.is_empty() {
+                true => info!("TFTP server not started, disabled by tftp.enabled."),
+                false => info!("TFTP disabled, stopping TFTP servers."),
+            }
+            for (ip, listener) in self.listeners.drain() {
+                info!("TFTP server on {ip}:69 stopped.");
+                listener.cancel().await;
+            }
+            return Ok(());
+        }
+
+        // The block size limit is a server setting, changing it requires new listeners
+        let block_size_limit = conf.get_tftp_block_size_limit();
+        if block_size_limit != self.block_size_limit && !self.listeners.is_empty() {
+            info!("TFTP block size limit changed, restarting TFTP servers.");
+            for (_, listener) in self.listeners.drain() {
+                listener.cancel().await;
+            }
+        }
+        self.block_size_limit = block_size_limit;
+
+        #[cfg(target_os = "linux")]
+        let listen_ips = match activation::has(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 69)) {
+            // The socket systemd passed decides the addresses
+            true => vec![Ipv4Addr::UNSPECIFIED],
+            false => listen_ips(conf)?,
+        };
+        #[cfg(not(target_os = "linux"))]
+        let listen_ips = listen_ips(conf)?;
+        let removed_ips: Vec<Ipv4Addr> = self
+            .listeners
+            .keys()
+            .filter(|ip| !listen_ips.contains(ip))
+            .copied()
             .collect();
+        for ip in removed_ips {
+            if let Some(listener) = self.listeners.remove(&ip) {
+                info!("TFTP server on {ip}:69 stopped.");
+                listener.cancel().await;
+            }
+        }
+
         for ip in listen_ips {
+            if self.listeners.contains_key(&ip) {
+                continue;
+            }
+
+            let handler = self.handler.captured_on(ip);
             let tftp_dir = tftp_path.clone();
-            task::spawn(async move {
-                let mut tftp_builder = TftpServerBuilder::with_handler(DirHandler::new(
-                    tftp_dir.clone(),
-                    DirHandlerMode::ReadOnly,
-                )?);
-                tftp_builder = tftp_builder.bind(SocketAddr::new(ip.into(), 69));
-                let server = tftp_builder.build().await?;
-
-                info!("TFTP server started on {ip}:69 path: {tftp_dir}");
-                server.serve().await?;
-                async_tftp::Result::<(), Error>::Ok(())
+            #[cfg(target_os = "linux")]
+            let activated = activation::socket(SocketAddrV4::new(ip, 69), None)?;
+            #[cfg(not(target_os = "linux"))]
+            let activated: Option<std::net::UdpSocket> = None;
+            // Bound before returning rather than by the server, the rights to
+            // bind port 69 being dropped once the services are started
+            let socket = match activated {
+                Some(socket) => socket,
+                None => match std::net::UdpSocket::bind(SocketAddr::new(ip.into(), 69)) {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        error!("TFTP server on {ip}:69 failed: {e}");
+                        continue;
+                    }
+                },
+            };
+            let listener = runtime::spawn(async move {
+                let result = async {
+                    let mut tftp_builder =
+                        TftpServerBuilder::with_handler(handler).std_socket(socket)?;
+                    if let Some(block_size) = block_size_limit {
+                        tftp_builder = tftp_builder.block_size_limit(block_size);
+                    }
+                    let server = tftp_builder.build().await?;
+
+                    info!("TFTP server started on {ip}:69 path: {tftp_dir}");
+                    server.serve().await?;
+                    async_tftp::Result::<(), Error>::Ok(())
+                };
+
+                if let Err(e) = result.await {
+                    error!("TFTP server on {ip}:69 failed: {e}");
+                }
             });
+            self.listeners.insert(ip, listener);
+        }
+
+        Ok(())
+    }
+
+    /// Stops all TFTP servers, transfers in progress are interrupted.
+    pub async fn stop(&mut self) {
+        for (ip, listener) in self.listeners.drain() {
+            info!("TFTP server on {ip}:69 stopped.");
+            listener.cancel().await;
+        }
+        self.tftp_dir = None;
+    }
+}
+
+/// Handler delegating to the [`DirHandler`] currently set by [`TftpService`].
+/// Other services serving the same files hold a clone of it.
+#[derive(Clone, Default)]
+pub struct SharedDirHandler {
+    current: Arc<RwLock<Option<DirHandler>>>,
+    /// The TFTP server the requests are captured for, with `pcap_tftp`.
+    capture: Option<TftpEndpoint>,
+}
+
+impl From<DirHandler> for SharedDirHandler {
+    fn from(handler: DirHandler) -> Self {
+        let shared = Self::default();
+        shared.replace(handler);
+        shared
+    }
+}
+
+impl SharedDirHandler {
+    fn replace(&self, handler: DirHandler) {
+        if let Ok(mut current) = self.current.write() {
+            *current = Some(handler);
+        }
+    }
+
+    /// A handler for the TFTP server on `ip`, capturing its requests when
+    /// `pcap_tftp` is set.
+    fn captured_on(&self, ip: Ipv4Addr) -> Self {
+        Self {
+            current: Arc::clone(&self.current),
+            capture: pcap::tftp_endpoint(ip),
+        }
+    }
+
+    pub(crate) fn get(&self) -> TftpResult<DirHandler, packet::Error> {
+        self.current
+            .read()
+            .ok()
+            .and_then(|current| current.clone())
+            .ok_or(packet::Error::FileNotFound)
+    }
+}
+
+#[async_trait]
+impl Handler for SharedDirHandler {
+    type Reader = TrackedReader;
+    type Writer = TrackedWriter;
+
+    async fn read_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> TftpResult<(Self::Reader, Option<u64>), packet::Error> {
+        if let Some(endpoint) = &self.capture {
+            pcap::record_tftp(endpoint, client, TftpRequest::Read, path, None);
         }
-    } else {
-        info!("TFTP server not started, no path configured.");
+        self.get()?.read_req_open(client, path).await
     }
 
-    Ok(())
+    async fn write_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> TftpResult<Self::Writer, packet::Error> {
+        if let Some(endpoint) = &self.capture {
+            pcap::record_tftp(endpoint, client, TftpRequest::Write, path, size);
+        }
+        self.get()?.write_req_open(client, path, size).await
+    }
 }
 
 /// Handler that serves read requests for a directory.
+#[derive(Clone)]
 pub struct DirHandler {
     dir: PathBuf,
     serve_rrq: bool,
     serve_wrq: bool,
+    symlinks: SymlinkPolicy,
+    tracker: Option<Arc<BootTracker>>,
+    limits: Option<Arc<TransferLimits>>,
+    file_filter: Option<FileFilter>,
+    fallbacks: Vec<TftpFallback>,
+    upstream: Option<SocketAddr>,
+    /// Configuration of the clients, for the files served per client.
+    conf: Option<Arc<Conf>>,
 }
 
 #[allow(unused)]
@@ -95,7 +342,7 @@ impl DirHandler {
     {
         let dir = std::fs::canonicalize(dir.as_ref())?;
 
-        if !dir.is_dir() {
+        if !dir.is_dir() && !is_iso_image(&dir) {
             return Err(TftpError::NotDir(dir));
         }
 
@@ -117,65 +364,977 @@ impl DirHandler {
             dir,
             serve_rrq,
             serve_wrq,
+            symlinks: SymlinkPolicy::default(),
+            tracker: None,
+            limits: None,
+            file_filter: None,
+            fallbacks: Vec::new(),
+            upstream: None,
+            conf: None,
         })
     }
-}
 
-#[async_trait]
-impl async_tftp::server::Handler for DirHandler {
-    type Reader = File;
-    type Writer = File;
+    /// Looks for `path` inside an ISO image found along its parent directories,
+    /// e.g. `images/distro.iso/casper/vmlinuz`.
+    fn iso_source(&self, path: &Path) -> TftpResult<Option<FileSource>, packet::Error> {
+        for image in path.ancestors().skip(1) {
+            if !image.starts_with(&self.dir) {
+                break;
+            }
+            if !is_iso_image(image) {
+                continue;
+            }
 
-    async fn read_req_open(
-        &mut self,
-        _client: &SocketAddr,
+            let inner_path = path.strip_prefix(image).unwrap_or(path);
+            let image = check_symlinks(&self.dir, image, self.symlinks)?;
+            let file = IsoImage::open(&image)
+                .and_then(|mut iso| iso.find(inner_path))
+                .inspect_err(|e| warn!("Failed reading ISO image {}: {e}", image.display()))
+                .ok()
+                .flatten();
+
+            return Ok(file.map(|file| FileSource::Iso(image, file)));
+        }
+
+        Ok(None)
+    }
+
+    /// Relay read requests for files missing locally to an upstream TFTP server.
+    pub fn with_upstream(mut self, upstream: Option<SocketAddr>) -> Self {
+        self.upstream = upstream;
+        self
+    }
+
+    /// Serve the GRUB configurations and the pinned image versions of the
+    /// clients from `conf`.
+    pub fn with_conf(mut self, conf: Arc<Conf>) -> Self {
+        self.conf = Some(conf);
+        self
+    }
+
+    /// MAC address of the client handed boot information at `client`.
+    fn client_mac(&self, client: &SocketAddr) -> Option<MacAddress> {
+        match (client.ip(), &self.tracker) {
+            (IpAddr::V4(ip), Some(tracker)) => tracker
+                .get(&ip)
+                .and_then(|tracked| template::parse_mac(&tracked.mac_address)),
+            _ => None,
+        }
+    }
+
+    /// Path of the version of the image store `path` is in, when the client
+    /// has it pinned: `images/<image>` is then read from the file
+    /// `images/.versions/<image>/<sha256>`, and a path within it, like a file
+    /// of an ISO, from within that file.
+    fn pinned_path(&self, client: &SocketAddr, path: &Path) -> Option<PathBuf> {
+        let conf = self.conf.as_ref()?;
+        let path: PathBuf = path
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect();
+        let in_store = path.strip_prefix(IMAGES_DIR).ok()?;
+        if !conf.any_pinned_images() {
+            return None;
+        }
+
+        let mac = self.client_mac(client)?;
+        let client_conf = conf
+            .get_from_doc(template::client_doc(Some(&mac), None, None))
+            .ok()??;
+        let (image, version) = client_conf
+            .pinned_images
+            .into_iter()
+            .find(|(image, _)| in_store.starts_with(image))?;
+        let version_path = self
+            .dir
+            .join(IMAGES_DIR)
+            .join(VERSIONS_DIR)
+            .join(image)
+            .join(version);
+        if !version_path.is_file() {
+            warn!(
+                "Version {version} of image {} pinned for {} is not in the store, \
+                serving the current one",
+                image.display(),
+                bytes_to_mac_address(&mac)
+            );
+            return None;
+        }
+
+        match in_store.strip_prefix(image).ok()? {
+            // Joining an empty path would add a trailing `/`
+            in_image if in_image.as_os_str().is_empty() => Some(version_path),
+            in_image => Some(version_path.join(in_image)),
+        }
+    }
+
+    /// Renders the GRUB configuration `path` names when missing on disk:
+    /// `grub.cfg-01-<mac>` for the client of that MAC address, or `grub.cfg`
+    /// for the client handed boot information at the requesting address.
+    /// Clients with neither `grub_cfg` nor `boot_iso` get none.
+    async fn grub_config_source(
+        &self,
+        client: &SocketAddr,
         path: &Path,
-    ) -> TftpResult<(Self::Reader, Option<u64>), packet::Error> {
-        if !self.serve_rrq {
-            debug!("TFTP read request denied: {:?}", path);
-            return Err(packet::Error::IllegalOperation);
+    ) -> TftpResult<Option<FileSource>, packet::Error> {
+        let Some(conf) = &self.conf else {
+            return Ok(None);
+        };
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return Ok(None);
+        };
+        let mac = match name.strip_prefix("grub.cfg") {
+            Some("") => self.client_mac(client),
+            Some(suffix) => suffix.strip_prefix("-01-").and_then(template::parse_mac),
+            None => None,
+        };
+        let Some(mac) = mac else {
+            return Ok(None);
+        };
+        let Ok(Some(client_conf)) = conf.get_from_doc(template::client_doc(Some(&mac), None, None))
+        else {
+            return Ok(None);
+        };
+
+        let template = match (client_conf.boot_iso, client_conf.grub_cfg) {
+            (Some(image), _) => {
+                let (dir, image) = (self.dir.clone(), image.clone());
+                let entry = task::spawn_blocking(move || BootEntry::detect(&dir, &image))
+                    .await
+                    .map_err(|e| {
+                        warn!("Failed reading ISO image of {name}: {e}");
+                        packet::Error::FileNotFound
+                    })?;
+                match entry {
+                    Some(entry) => entry.grub_template(),
+                    None => return Ok(None),
+                }
+            }
+            (None, Some(template)) => {
+                let template = secure_path(&self.dir, Path::new(template), self.symlinks)?;
+                async_std::fs::read_to_string(&template).await.map_err(|e| {
+                    warn!("Failed reading GRUB template {}: {e}", template.display());
+                    packet::Error::FileNotFound
+                })?
+            }
+            (None, None) => return Ok(None),
+        };
+
+        // GRUB expands its own variables, `net_default_server` being this server
+        let server = "${net_default_server}";
+        let (base_url, grub_root) = match conf.get_http_port() {
+            Some(port) => (
+                format!("http://{server}:{port}"),
+                format!("(http,{server}:{port})"),
+            ),
+            None => (String::new(), format!("(tftp,{server})")),
+        };
+        let vars = template::client_vars(
+            &client_conf,
+            Some(&mac),
+            client.ip(),
+            server.to_string(),
+            vec![("base_url", base_url), ("grub_root", grub_root)],
+        );
+        debug!(
+            "Generated {name} for client {} at {}",
+            bytes_to_mac_address(&mac),
+            client.ip()
+        );
+
+        Ok(Some(FileSource::Generated(
+            template::render(&template, &vars).into_bytes(),
+        )))
+    }
+
+    async fn upstream_source(
+        &self,
+        requested_path: &Path,
+    ) -> TftpResult<Option<FileSource>, packet::Error> {
+        let Some(upstream) = self.upstream else {
+            return Ok(None);
+        };
+
+        let path = requested_path.strip_prefix("/").unwrap_or(requested_path);
+        let transfer = upstream::open(upstream, path)
+            .await
+            .inspect_err(|e| warn!("Upstream TFTP server {upstream} unavailable: {e}"))
+            .ok()
+            .flatten();
+
+        Ok(transfer.map(|(reader, len)| FileSource::Upstream {
+            upstream,
+            path: path.to_path_buf(),
+            reader,
+            len,
+        }))
+    }
+
+    /// Serve a default file when a file requested from under a fallback's
+    /// directory doesn't exist.
+    pub fn with_fallbacks(mut self, fallbacks: Vec<TftpFallback>) -> Self {
+        self.fallbacks = fallbacks;
+        self
+    }
+
+    fn fallback_for(&self, requested_path: &Path) -> TftpResult<Option<PathBuf>, packet::Error> {
+        let relative = requested_path
+            .strip_prefix("/")
+            .or_else(|_| requested_path.strip_prefix("./"))
+            .unwrap_or(requested_path);
+
+        for fallback in &self.fallbacks {
+            if !relative.starts_with(&fallback.prefix) {
+                continue;
+            }
+
+            let path = secure_path(&self.dir, &fallback.file, self.symlinks)?;
+            if path.is_file() {
+                info!(
+                    "File {} not found, serving fallback {}",
+                    requested_path.display(),
+                    path.display()
+                );
+                return Ok(Some(path));
+            }
+            debug!("Fallback file {} not found", path.display());
+        }
+
+        Ok(None)
+    }
+
+    /// Only serve files whose names pass the filter.
+    pub fn with_file_filter(mut self, file_filter: FileFilter) -> Self {
+        self.file_filter = Some(file_filter);
+        self
+    }
+
+    /// Where a file uploaded over HTTP to `path` is saved, checked like the
+    /// paths of TFTP write requests.
+    pub(crate) fn upload_path(&self, path: &Path) -> TftpResult<PathBuf, packet::Error> {
+        let path = translate_backslashes(path);
+        self.check_file_filter(&path)?;
+        secure_path(&self.dir, &path, self.symlinks)
+    }
+
+    /// Creates the missing parent directories of an upload to `dest`, a path
+    /// from [`Self::upload_path`], one at a time and checking each, so none is
+    /// created through a link swapped in after `dest` was checked.
+    pub(crate) fn create_upload_dirs(&self, dest: &Path) -> TftpResult<(), packet::Error> {
+        let Some(relative) = dest
+            .parent()
+            .and_then(|parent| parent.strip_prefix(&self.dir).ok())
+        else {
+            return Err(packet::Error::PermissionDenied);
+        };
+
+        let mut current = self.dir.clone();
+        for component in relative.components() {
+            current.push(component);
+            match std::fs::create_dir(&current) {
+                Ok(()) => continue,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+            check_symlinks(&self.dir, &current, self.symlinks)?;
+            if !current.is_dir() {
+                return Err(packet::Error::PermissionDenied);
+            }
         }
 
-        let path = secure_path(&self.dir, path)?;
+        Ok(())
+    }
+
+    fn check_file_filter(&self, path: &Path) -> TftpResult<(), packet::Error> {
+        match &self.file_filter {
+            Some(filter) if !filter.allows(path) => {
+                warn!("TFTP request for {} denied by file name filter.", path.display());
+                Err(packet::Error::FileNotFound)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Cap the number of transfers in progress, see [`TransferLimits`].
+    pub fn with_transfer_limits(mut self, limits: Arc<TransferLimits>) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    fn acquire_transfer_slot(
+        &self,
+        client: &SocketAddr,
+    ) -> TftpResult<Option<TransferSlot>, packet::Error> {
+        self.limits
+            .as_ref()
+            .map(|limits| {
+                TransferLimits::acquire(limits, client.ip()).ok_or_else(|| {
+                    packet::Error::Msg("Too many concurrent transfers, try again later".into())
+                })
+            })
+            .transpose()
+    }
+
+    /// Report completed downloads to the tracker shared with the DHCP service.
+    pub fn with_tracker(mut self, tracker: Arc<BootTracker>) -> Self {
+        self.tracker = Some(tracker);
+        self
+    }
+
+    pub(crate) fn tracker(&self) -> Option<&Arc<BootTracker>> {
+        self.tracker.as_ref()
+    }
+
+    /// Set how symbolic links under the served directory are treated.
+    pub fn with_symlink_policy(mut self, symlinks: SymlinkPolicy) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Opens a file for reading the way read requests are served, resolving
+    /// `path` under the served directory and applying its rules. Used by
+    /// other services serving the same files.
+    pub(crate) async fn open(
+        &self,
+        client: &SocketAddr,
+        path: &Path,
+        relay_upstream: bool,
+    ) -> TftpResult<(TrackedReader, Option<u64>), packet::Error> {
+        let file = self.resolve(client, path, relay_upstream).await?;
+        self.open_resolved(client, file, 0).await
+    }
+
+    /// Finds where the content of `path` comes from, without opening it yet.
+    pub(crate) async fn resolve(
+        &self,
+        client: &SocketAddr,
+        path: &Path,
+        relay_upstream: bool,
+    ) -> TftpResult<ResolvedFile, packet::Error> {
+        let requested_path = translate_backslashes(path);
+        let path = secure_path(&self.dir, &requested_path, self.symlinks)?;
+        self.check_file_filter(&requested_path)?;
+        let path = match self.pinned_path(client, &requested_path) {
+            Some(pinned) => check_symlinks(&self.dir, &pinned, self.symlinks)?,
+            None => path,
+        };
 
         // Send only regular files
-        if !path.is_file() {
-            error!("File not found or path is not a file: {:?}", path);
-            return Err(packet::Error::FileNotFound);
+        let source = if path.is_file() {
+            Some(FileSource::Disk(path.clone()))
+        } else {
+            self.iso_source(&path)?
+        };
+        let source = match source {
+            Some(source) => Some(source),
+            None => match find_case_insensitive(&self.dir, &path).filter(|path| path.is_file()) {
+                Some(path) => Some(FileSource::Disk(check_symlinks(
+                    &self.dir,
+                    &path,
+                    self.symlinks,
+                )?)),
+                None => None,
+            },
+        };
+        let source = match source {
+            Some(source) => Some(source),
+            None => self.grub_config_source(client, &requested_path).await?,
+        };
+        let source = match source {
+            Some(source) => Some(source),
+            None if relay_upstream => self.upstream_source(&requested_path).await?,
+            None => None,
+        };
+        let source = match source {
+            Some(source) => source,
+            None => self
+                .fallback_for(&requested_path)?
+                .map(FileSource::Disk)
+                .ok_or_else(|| {
+                    error!("File not found or path is not a file: {:?}", path);
+                    packet::Error::FileNotFound
+                })?,
+        };
+
+        Ok(ResolvedFile {
+            requested_path,
+            source,
+            follow_symlinks: self.symlinks == SymlinkPolicy::Allow,
+        })
+    }
+
+    /// The client at `addr` for the messages about it, with the MAC address
+    /// and XID it was given its boot file with.
+    fn log_client(&self, addr: &SocketAddr) -> TaskClient {
+        let tracked = match (addr.ip(), self.tracker()) {
+            (IpAddr::V4(ip), Some(tracker)) => tracker.get(&ip),
+            _ => None,
+        };
+        TaskClient {
+            mac: tracked.as_ref().and_then(|client| template::parse_mac(&client.mac_address)),
+            xid: tracked.map(|client| client.xid),
+            ip: Some(addr.ip()),
         }
+    }
 
-        let (reader, len) = open_file_ro(path.clone())
+    /// Opens a resolved file for `client`, reading from byte `offset` on.
+    /// Only downloads from the start are reported to the tracker.
+    pub(crate) async fn open_resolved(
+        &self,
+        client: &SocketAddr,
+        file: ResolvedFile,
+        offset: u64,
+    ) -> TftpResult<(TrackedReader, Option<u64>), packet::Error> {
+        let slot = self.acquire_transfer_slot(client)?;
+
+        let description = file.source.to_string();
+        let (reader, len) = open_file_ro(file.source, offset, file.follow_symlinks)
             .await
-            .inspect_err(|e| error!("File open error {:?}, path: {:?}", e, path))?;
+            .inspect_err(|e| {
+            error!("File open error {:?}, path: {:?}", e, file.requested_path)
+        })?;
 
-        info!("Serving file: {}", path.display());
+        match offset {
+            0 => info!("Serving file: {description}"),
+            _ => info!("Serving file: {description} from byte {offset}"),
+        }
+
+        let active = self
+            .tracker
+            .as_ref()
+            .map(|tracker| BootTracker::transfer_started(tracker, client.ip(), &file.requested_path, len));
+        let reader = TrackedReader {
+            inner: reader,
+            client: client.ip(),
+            log_client: logging::task_client(),
+            path: file.requested_path,
+            tracker: self.tracker.clone().filter(|_| offset == 0),
+            conf: self.conf.clone(),
+            eof: false,
+            _slot: slot,
+            active,
+            remaining: None,
+            retransmit_timeout: None,
+            last_read: None,
+            reading: false,
+        };
 
         Ok((reader, len))
     }
+}
+
+#[async_trait]
+impl Handler for DirHandler {
+    type Reader = TrackedReader;
+    type Writer = TrackedWriter;
+
+    async fn read_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> TftpResult<(Self::Reader, Option<u64>), packet::Error> {
+        let log_client = self.log_client(client);
+        logging::with_client(log_client, async {
+            if !self.serve_rrq {
+                debug!("TFTP read request denied: {:?}", path);
+                return Err(packet::Error::IllegalOperation);
+            }
+
+            let (reader, len) = self.open(client, path, true).await?;
+            Ok((reader.over_tftp(), len))
+        })
+        .await
+    }
 
     async fn write_req_open(
         &mut self,
-        _client: &SocketAddr,
+        client: &SocketAddr,
         path: &Path,
         size: Option<u64>,
     ) -> TftpResult<Self::Writer, packet::Error> {
-        if !self.serve_wrq {
-            debug!("TFTP write request denied: {:?}", path);
-            return Err(packet::Error::IllegalOperation);
+        let log_client = self.log_client(client);
+        logging::with_client(log_client, async {
+            if !self.serve_wrq {
+                debug!("TFTP write request denied: {:?}", path);
+                return Err(packet::Error::IllegalOperation);
+            }
+
+            let path = translate_backslashes(path);
+            self.check_file_filter(&path)?;
+            let path = secure_path(&self.dir, &path, self.symlinks)?;
+            let slot = self.acquire_transfer_slot(client)?;
+
+            let temp_path = part_path(&path);
+            let file = open_file_wo(temp_path.clone(), size).await?;
+
+            info!("TFTP receiving file: {}", path.display());
+            let mut active = self
+                .tracker
+                .as_ref()
+                .map(|tracker| BootTracker::transfer_started(tracker, client.ip(), &path, size));
+            if let Some(active) = &mut active {
+                active.over(Protocol::Tftp);
+            }
+
+            Ok(TrackedWriter {
+                inner: Some(file),
+                temp_path,
+                path,
+                expected_size: size,
+                written: 0,
+                last_write: Instant::now(),
+                failed: false,
+                log_client,
+                _slot: slot,
+                active,
+            })
+        })
+        .await
+    }
+}
+
+/// A requested file found by [`DirHandler::resolve`].
+pub(crate) struct ResolvedFile {
+    requested_path: PathBuf,
+    source: FileSource,
+    /// Checked paths are opened without following a link found in their place.
+    follow_symlinks: bool,
+}
+
+impl ResolvedFile {
+    /// Size and modification time of the file, when known.
+    pub(crate) fn metadata(&self) -> (Option<u64>, Option<SystemTime>) {
+        match &self.source {
+            FileSource::Disk(path) => std::fs::metadata(path)
+                .map(|metadata| (Some(metadata.len()), metadata.modified().ok()))
+                .unwrap_or_default(),
+            FileSource::Iso(image, iso_file) => (
+                Some(iso_file.size),
+                std::fs::metadata(image).and_then(|m| m.modified()).ok(),
+            ),
+            FileSource::Upstream { len, .. } => (*len, None),
+            FileSource::Generated(content) => (Some(content.len() as u64), None),
+        }
+    }
+
+    /// Opens the whole content, without it counting as a transfer.
+    pub(crate) async fn open(self) -> io::Result<FileReader> {
+        open_file_ro(self.source, 0, self.follow_symlinks)
+            .await
+            .map(|(reader, _)| reader)
+    }
+}
+
+/// Where the content of a requested file is read from.
+enum FileSource {
+    Disk(PathBuf),
+    Iso(PathBuf, IsoFile),
+    /// A transfer already started with an upstream TFTP server.
+    Upstream {
+        upstream: SocketAddr,
+        path: PathBuf,
+        reader: UpstreamReader,
+        len: Option<u64>,
+    },
+    /// Content rendered for the client, like its GRUB configuration.
+    Generated(Vec<u8>),
+}
+
+impl std::fmt::Display for FileSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileSource::Disk(path) => write!(f, "{}", path.display()),
+            FileSource::Iso(image, file) => write!(
+                f,
+                "{} bytes at offset {} of ISO image {}",
+                file.size,
+                file.offset,
+                image.display()
+            ),
+            FileSource::Upstream { upstream, path, .. } => write!(
+                f,
+                "{} from upstream TFTP server {upstream}",
+                path.display()
+            ),
+            FileSource::Generated(content) => write!(f, "{} generated bytes", content.len()),
+        }
+    }
+}
+
+/// File reader reporting to the [`BootTracker`] once the whole file was read,
+/// and running the `boot_file_delivered` hook of `conf` for boot files.
+pub struct TrackedReader {
+    inner: FileReader,
+    client: IpAddr,
+    /// The client of the messages, as when the file was opened.
+    log_client: TaskClient,
+    path: PathBuf,
+    tracker: Option<Arc<BootTracker>>,
+    conf: Option<Arc<Conf>>,
+    eof: bool,
+    _slot: Option<TransferSlot>,
+    /// Listed by the tracker while the file is read.
+    active: Option<ActiveTransfer>,
+    /// Bytes left to read of a range, the end of the range completing the
+    /// transfer.
+    remaining: Option<u64>,
+    /// Time a TFTP block is sent again after when it isn't acknowledged,
+    /// for the blocks sent again to be counted, over TFTP only.
+    retransmit_timeout: Option<Duration>,
+    last_read: Option<Instant>,
+    /// Whether a read is pending.
+    reading: bool,
+}
+
+impl AsyncRead for TrackedReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if !this.reading {
+            this.reading = true;
+            this.count_retransmissions();
+        }
+        let limit = match this.remaining {
+            Some(remaining) => buf.len().min(usize::try_from(remaining).unwrap_or(usize::MAX)),
+            None => buf.len(),
+        };
+        let poll = match limit {
+            0 if this.remaining == Some(0) => Poll::Ready(Ok(0)),
+            _ => Pin::new(&mut this.inner).poll_read(cx, &mut buf[..limit]),
+        };
+        if let Poll::Ready(result) = &poll {
+            this.reading = false;
+            this.last_read = Some(Instant::now());
+            if let Ok(len) = result {
+                if let Some(active) = &this.active {
+                    active.transferred(*len);
+                }
+                if let Some(remaining) = &mut this.remaining {
+                    *remaining -= *len as u64;
+                }
+            }
+        }
+        if let Poll::Ready(Ok(0)) = poll {
+            if this.remaining == Some(0) {
+                if let Some(active) = &mut this.active {
+                    active.completed();
+                }
+            } else if !this.eof && !buf.is_empty() {
+                this.eof = true;
+                if let Some(active) = &mut this.active {
+                    active.completed();
+                }
+                logging::scoped(this.log_client, || this.delivered());
+            }
         }
 
-        let path = secure_path(&self.dir, path)?;
+        poll
+    }
+}
+
+impl TrackedReader {
+    /// The reader of a file served over TFTP, counting the blocks sent
+    /// again.
+    pub(crate) fn over_tftp(mut self) -> Self {
+        if let Some(active) = &mut self.active {
+            active.over(Protocol::Tftp);
+        }
+        self.retransmit_timeout = Some(TFTP_RETRANSMIT_TIMEOUT);
+        self
+    }
+
+    /// Reads `len` bytes at most, the range of a file served over HTTP.
+    pub(crate) fn limited(mut self, len: u64) -> Self {
+        self.remaining = Some(len);
+        self
+    }
+
+    /// Counts the blocks sent again since the last read, a block being read
+    /// once the previous one is acknowledged, and sent again every
+    /// `retransmit_timeout` until it is.
+    fn count_retransmissions(&mut self) {
+        let (Some(timeout), Some(last_read)) = (self.retransmit_timeout, self.last_read) else {
+            return;
+        };
+        let count = last_read.elapsed().as_millis() / timeout.as_millis().max(1);
+        if let (Some(active), true) = (&mut self.active, count > 0) {
+            active.retransmitted(count as u64);
+        }
+    }
 
-        let path_clone = path.clone();
-        let file = open_file_wo(path_clone, size).await?;
+    fn delivered(&self) {
+        debug!("File {} fully read for {}", self.path.display(), self.client);
+        if let (Some(tracker), IpAddr::V4(ip)) = (&self.tracker, self.client) {
+            let delivered = tracker.file_delivered(ip, &self.path);
+            if let (Some(client), Some(conf)) = (delivered, &self.conf) {
+                let client = ClientEvent {
+                    mac_address: client.mac_address,
+                    xid: client.xid,
+                    ip: Some(ip),
+                    boot_file: Some(client.boot_file),
+                    ..Default::default()
+                };
+                events::fire(conf, Event::BootFileDelivered, client);
+            }
+        }
+    }
+}
 
-        info!("TFTP receiving file: {}", path.display());
+/// Writes an upload to a temporary file next to its destination, holding a
+/// transfer slot for as long as the upload lasts. The file is renamed into
+/// place once the upload completes and deleted otherwise, so clients never
+/// see partial uploads.
+pub struct TrackedWriter {
+    inner: Option<File>,
+    temp_path: PathBuf,
+    path: PathBuf,
+    expected_size: Option<u64>,
+    written: u64,
+    last_write: Instant,
+    /// Whether writing to the file failed, the upload then being incomplete
+    /// however soon it ends.
+    failed: bool,
+    /// The client of the messages.
+    log_client: TaskClient,
+    _slot: Option<TransferSlot>,
+    /// Listed by the tracker while the upload is received.
+    active: Option<ActiveTransfer>,
+}
 
-        Ok(file)
+impl TrackedWriter {
+    /// The TFTP server drops the writer right after the last block when an
+    /// upload succeeds and only after the client stopped responding for a
+    /// number of timeouts when it fails. Uploads announcing their size are
+    /// checked against it. Uploads which failed writing are never complete,
+    /// the TFTP server dropping the writer right after the error too.
+    fn is_complete(&self) -> bool {
+        if self.failed {
+            return false;
+        }
+        match self.expected_size {
+            Some(size) => self.written == size,
+            None => self.last_write.elapsed() < UPLOAD_COMPLETION_WINDOW,
+        }
     }
 }
 
-fn secure_path(restricted_dir: &Path, path: &Path) -> TftpResult<PathBuf, packet::Error> {
+impl AsyncWrite for TrackedWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let Some(inner) = self.inner.as_mut() else {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        };
+
+        let poll = Pin::new(inner).poll_write(cx, buf);
+        match poll {
+            Poll::Ready(Ok(len)) => {
+                self.written += len as u64;
+                self.last_write = Instant::now();
+                if let Some(active) = &self.active {
+                    active.transferred(len);
+                }
+            }
+            Poll::Ready(Err(_)) => self.failed = true,
+            Poll::Pending => {}
+        }
+
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let poll = match self.inner.as_mut() {
+            Some(inner) => Pin::new(inner).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        };
+        if let Poll::Ready(Err(_)) = poll {
+            self.failed = true;
+        }
+        poll
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let poll = match self.inner.as_mut() {
+            Some(inner) => Pin::new(inner).poll_close(cx),
+            None => Poll::Ready(Ok(())),
+        };
+        if let Poll::Ready(Err(_)) = poll {
+            self.failed = true;
+        }
+        poll
+    }
+}
+
+impl Drop for TrackedWriter {
+    fn drop(&mut self) {
+        logging::scoped(self.log_client, || self.finish());
+    }
+}
+
+impl TrackedWriter {
+    /// Moves the upload into place when complete, discards it otherwise.
+    fn finish(&mut self) {
+        // What is left of the upload is flushed here rather than when the
+        // file is dropped, for its errors not to go unnoticed
+        if let Some(mut file) = self.inner.take() {
+            if let Err(e) = task::block_on(file.flush()) {
+                error!("Failed writing {}: {e}", self.temp_path.display());
+                self.failed = true;
+            }
+        }
+
+        if !self.is_complete() {
+            warn!(
+                "TFTP upload of {} incomplete after {} bytes, discarding it.",
+                self.path.display(),
+                self.written
+            );
+            if let Err(e) = std::fs::remove_file(&self.temp_path) {
+                error!("Failed removing {}: {e}", self.temp_path.display());
+            }
+            return;
+        }
+
+        match std::fs::rename(&self.temp_path, &self.path) {
+            Ok(()) => {
+                info!(
+                    "TFTP received file: {} ({} bytes)",
+                    self.path.display(),
+                    self.written
+                );
+                if let Some(active) = &mut self.active {
+                    active.completed();
+                }
+            }
+            Err(e) => {
+                error!("Failed moving upload to {}: {e}", self.path.display());
+                let _ = std::fs::remove_file(&self.temp_path);
+            }
+        }
+    }
+}
+
+/// Limits the number of TFTP transfers in progress, both in total and for
+/// each client IP, as some network boot ROMs open far more transfers than
+/// they need.
+pub struct TransferLimits {
+    max_total: u64,
+    max_per_client: u64,
+    active: Mutex<ActiveTransfers>,
+}
+
+struct ActiveTransfers {
+    total: u64,
+    per_client: QuotaMap<IpAddr>,
+}
+
+impl TransferLimits {
+    pub fn new(max_total: u64, max_per_client: u64) -> Self {
+        Self {
+            max_total,
+            max_per_client,
+            active: Mutex::new(ActiveTransfers {
+                total: 0,
+                per_client: QuotaMap::new([], Some(max_per_client)),
+            }),
+        }
+    }
+
+    /// Reserves a transfer for `client`, released when the returned slot is
+    /// dropped. Returns `None` when either limit is reached.
+    pub fn acquire(limits: &Arc<Self>, client: IpAddr) -> Option<TransferSlot> {
+        let mut active = limits.active.lock().ok()?;
+        if active.total >= limits.max_total {
+            warn!(
+                "TFTP transfer for {client} refused, maximum of {} concurrent transfers reached.",
+                limits.max_total
+            );
+            return None;
+        }
+        if !active.per_client.acquire(&client, Instant::now()).is_counted() {
+            warn!(
+                "TFTP transfer for {client} refused, maximum of {} concurrent transfers per client reached.",
+                limits.max_per_client
+            );
+            return None;
+        }
+
+        active.total += 1;
+
+        Some(TransferSlot {
+            limits: Arc::clone(limits),
+            client,
+        })
+    }
+
+    pub fn active_transfers(&self) -> u64 {
+        self.active.lock().map(|active| active.total).unwrap_or(0)
+    }
+}
+
+/// A transfer counted by [`TransferLimits`].
+pub struct TransferSlot {
+    limits: Arc<TransferLimits>,
+    client: IpAddr,
+}
+
+impl Drop for TransferSlot {
+    fn drop(&mut self) {
+        let Ok(mut active) = self.limits.active.lock() else {
+            return;
+        };
+
+        active.total = active.total.saturating_sub(1);
+        active.per_client.release(&self.client);
+    }
+}
+
+/// Windows boot clients (WDS, WinPE) request files as `\boot\bcd`, map
+/// those onto the forward slash paths of the served directory.
+fn translate_backslashes(path: &Path) -> PathBuf {
+    match path.to_str() {
+        Some(path_str) if path_str.contains('\\') => PathBuf::from(path_str.replace('\\', "/")),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Finds `path` ignoring the case of its components, for clients assuming a
+/// case insensitive server, like Windows' boot manager requesting `\Boot\BCD`.
+fn find_case_insensitive(restricted_dir: &Path, path: &Path) -> Option<PathBuf> {
+    let relative = path.strip_prefix(restricted_dir).ok()?;
+    relative
+        .components()
+        .try_fold(restricted_dir.to_path_buf(), |dir, component| {
+            let name = component.as_os_str();
+            let exact = dir.join(name);
+            if exact.exists() {
+                return Some(exact);
+            }
+
+            let name = name.to_str()?;
+            std::fs::read_dir(&dir)
+                .ok()?
+                .filter_map(|entry| entry.ok())
+                .find(|entry| {
+                    entry
+                        .file_name()
+                        .to_str()
+                        .is_some_and(|entry_name| entry_name.eq_ignore_ascii_case(name))
+                })
+                .map(|entry| entry.path())
+        })
+}
+
+fn secure_path(
+    restricted_dir: &Path,
+    path: &Path,
+    symlinks: SymlinkPolicy,
+) -> TftpResult<PathBuf, packet::Error> {
     // Strip `/` and `./` prefixes
     let path = path
         .strip_prefix("/")
@@ -194,17 +1353,143 @@ fn secure_path(restricted_dir: &Path, path: &Path) -> TftpResult<PathBuf, packet
         _ => return Err(packet::Error::PermissionDenied),
     }
 
-    Ok(restricted_dir.join(path))
+    check_symlinks(restricted_dir, &restricted_dir.join(path), symlinks)
+}
+
+/// Enforces the symlink policy on a path already joined to the restricted
+/// directory, returning the path to open. `restricted_dir` is expected to be
+/// canonical. With [`SymlinkPolicy::DenyEscaping`] the returned path is
+/// resolved, so that it can be opened without following links.
+fn check_symlinks(
+    restricted_dir: &Path,
+    path: &Path,
+    symlinks: SymlinkPolicy,
+) -> TftpResult<PathBuf, packet::Error> {
+    match symlinks {
+        SymlinkPolicy::Allow => Ok(path.to_path_buf()),
+        SymlinkPolicy::DenyEscaping => {
+            // Files about to be written don't exist yet, resolve their deepest
+            // existing ancestor, a dangling link included, instead
+            let ancestor = path
+                .ancestors()
+                .find(|ancestor| std::fs::symlink_metadata(ancestor).is_ok())
+                .ok_or(packet::Error::PermissionDenied)?;
+            let resolved = std::fs::canonicalize(ancestor)
+                .ok()
+                .zip(path.strip_prefix(ancestor).ok())
+                .map(|(real_ancestor, rest)| match rest.as_os_str().is_empty() {
+                    // Joining an empty path would add a trailing `/`
+                    true => real_ancestor,
+                    false => real_ancestor.join(rest),
+                });
+
+            match resolved {
+                Some(real_path) if real_path.starts_with(restricted_dir) => Ok(real_path),
+                Some(real_path) => {
+                    warn!(
+                        "TFTP request for {} denied, it resolves outside of the TFTP directory to {}",
+                        path.display(),
+                        real_path.display()
+                    );
+                    Err(packet::Error::PermissionDenied)
+                }
+                None => {
+                    warn!(
+                        "TFTP request for {} denied, it can't be resolved",
+                        path.display()
+                    );
+                    Err(packet::Error::PermissionDenied)
+                }
+            }
+        }
+        SymlinkPolicy::Deny => {
+            let relative = path.strip_prefix(restricted_dir).unwrap_or(path);
+            let mut current = restricted_dir.to_path_buf();
+            for component in relative.components() {
+                current.push(component);
+                let is_symlink = std::fs::symlink_metadata(&current)
+                    .map(|meta| meta.file_type().is_symlink())
+                    .unwrap_or(false);
+                if is_symlink {
+                    warn!(
+                        "TFTP request for {} denied, symbolic links are not allowed: {}",
+                        path.display(),
+                        current.display()
+                    );
+                    return Err(packet::Error::PermissionDenied);
+                }
+            }
+
+            Ok(path.to_path_buf())
+        }
+    }
 }
 
-async fn open_file_ro(path: PathBuf) -> io::Result<(File, Option<u64>)> {
-    let file = async_std::fs::File::open(path).await?;
-    let len = file.metadata().await.ok().map(|m| m.len());
-    Ok((file, len))
+/// Opens `path` for reading, refusing a symbolic link swapped in for it
+/// after it was checked unless `follow_symlinks` is set.
+async fn open_disk_file(path: &Path, follow_symlinks: bool) -> io::Result<File> {
+    let mut options = async_std::fs::OpenOptions::new();
+    options.read(true);
+    #[cfg(unix)]
+    if !follow_symlinks {
+        use async_std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NOFOLLOW);
+    }
+    #[cfg(not(unix))]
+    let _ = follow_symlinks;
+
+    options.open(path).await
+}
+
+/// Opens the content of `source` from byte `offset` on, returning the length
+/// left to read when known.
+async fn open_file_ro(
+    source: FileSource,
+    offset: u64,
+    follow_symlinks: bool,
+) -> io::Result<(FileReader, Option<u64>)> {
+    match source {
+        FileSource::Disk(path) => {
+            let mut file = open_disk_file(&path, follow_symlinks).await?;
+            let len = file
+                .metadata()
+                .await
+                .ok()
+                .map(|m| m.len().saturating_sub(offset));
+            if offset > 0 {
+                file.seek(io::SeekFrom::Start(offset)).await?;
+            }
+            let reader = file.take(len.unwrap_or(u64::MAX));
+            Ok((Box::new(ReadAhead::new(reader, &READ_AHEAD_POOL)), len))
+        }
+        FileSource::Iso(image, iso_file) => {
+            let mut file = open_disk_file(&image, follow_symlinks).await?;
+            let offset = offset.min(iso_file.size);
+            file.seek(io::SeekFrom::Start(iso_file.offset + offset)).await?;
+            let len = iso_file.size - offset;
+            let reader = file.take(len);
+            Ok((Box::new(ReadAhead::new(reader, &READ_AHEAD_POOL)), Some(len)))
+        }
+        FileSource::Upstream { reader, len, .. } if offset == 0 => Ok((Box::new(reader), len)),
+        FileSource::Upstream { .. } => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Upstream transfers can only be read from the start",
+        )),
+        FileSource::Generated(content) => {
+            let content = content.get(offset as usize..).unwrap_or_default().to_vec();
+            let len = content.len() as u64;
+            Ok((Box::new(futures::io::Cursor::new(content)), Some(len)))
+        }
+    }
 }
 
 async fn open_file_wo(path: PathBuf, size: Option<u64>) -> io::Result<File> {
-    let file = async_std::fs::File::create(path).await?;
+    // The temporary file has a random name, never reuse one found in its place
+    let file = async_std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await?;
 
     if let Some(size) = size {
         file.set_len(size).await?;
@@ -0,0 +1,116 @@
+//! `tftp-get` subcommand: downloads a file from a TFTP server, the running
+//! one by default, timing it, to check the files the clients are served by
+//! the TFTP root, its fallbacks and its access rules.
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_std::{fs::File, io::WriteExt};
+use futures::AsyncReadExt;
+use sha2::{Digest, Sha256};
+
+use crate::{upstream, util::to_hex, Result};
+
+const DEFAULT_SERVER: &str = "127.0.0.1";
+
+/// A file downloaded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Download {
+    pub server: SocketAddr,
+    pub path: PathBuf,
+    pub size: u64,
+    /// Size the server told before sending the file.
+    pub announced_size: Option<u64>,
+    pub sha256: String,
+    /// Time the server took to answer the request.
+    pub first_answer: Duration,
+    pub elapsed: Duration,
+}
+
+/// The address of the TFTP server `server`, an IP address with an optional
+/// port, or the local one when not given.
+pub fn server_addr(server: Option<&str>) -> Result<SocketAddr> {
+    let server = server.unwrap_or(DEFAULT_SERVER);
+    if let Ok(addr) = server.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    let ip = server
+        .parse()
+        .map_err(|_| anyhow!("Invalid TFTP server {server}, expected <ip> or <ip>:<port>"))?;
+
+    Ok(SocketAddr::new(ip, upstream::DEFAULT_TFTP_PORT))
+}
+
+/// Downloads `path` from `server`, writing it to `output` when given.
+pub async fn download(server: SocketAddr, path: &Path, output: Option<&Path>) -> Result<Download> {
+    let start = Instant::now();
+    let (mut reader, announced_size) = upstream::get(server, path).await?;
+    let first_answer = start.elapsed();
+    let mut file = match output {
+        Some(output) => Some(
+            File::create(output)
+                .await
+                .with_context(|| format!("Creating {}", output.display()))?,
+        ),
+        None => None,
+    };
+
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        size += read as u64;
+        if let Some(file) = &mut file {
+            file.write_all(&buf[..read]).await?;
+        }
+    }
+    if let Some(file) = &mut file {
+        file.flush().await?;
+    }
+
+    Ok(Download {
+        server,
+        path: path.to_path_buf(),
+        size,
+        announced_size,
+        sha256: to_hex(&hasher.finalize()),
+        first_answer,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// What was downloaded, how fast, and its checksum to compare it with.
+pub fn report(download: &Download) -> String {
+    let secs = download.elapsed.as_secs_f64();
+    let rate = match secs > 0.0 {
+        true => format!("{:.1} KiB/s", download.size as f64 / 1024.0 / secs),
+        false => "-".to_string(),
+    };
+    let mut lines = vec![
+        format!(
+            "Downloaded {} from {}",
+            download.path.display(),
+            download.server
+        ),
+        format!("  Size: {} bytes", download.size),
+        format!("  Answered in: {} ms", download.first_answer.as_millis()),
+        format!("  Time: {secs:.3} s, {rate}"),
+        format!("  SHA-256: {}", download.sha256),
+    ];
+    match download.announced_size {
+        Some(announced) if announced != download.size => {
+            lines.push(format!("Warning: the server announced {announced} bytes."))
+        }
+        _ => {}
+    }
+
+    lines.join("\n") + "\n"
+}
@@ -0,0 +1,247 @@
+//! Timeline of the clients, `event_log`: a line of JSON for each step of
+//! their boots, the DISCOVER and REQUEST received, the OFFER and ACK sent,
+//! the transfers and the DHCP sessions timing out, for the support engineers
+//! chasing one flaky machine to tell what it did and when. The `timeline`
+//! command and the control API assemble the steps of a MAC address from the
+//! file and those it was rotated to, the oldest first. The file is appended
+//! to across restarts and rotated once it reaches `event_log_max_size`,
+//! `event_log_files` of them being kept.
+use std::{
+    fmt,
+    fs,
+    io,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::Context;
+use log::{info, warn};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{conf::Conf, template::parse_mac, util::LineLog, Result};
+
+static EVENT_LOG: OnceLock<Mutex<LineLog>> = OnceLock::new();
+
+/// A step of the boot of a client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// A DISCOVER asking for a boot file was received.
+    Discover,
+    /// A REQUEST was received, of the DHCP handshake or for the PXE boot
+    /// server.
+    Request,
+    /// The client declined the address it was offered.
+    Decline,
+    /// An OFFER was sent.
+    Offer,
+    /// An ACK was sent.
+    Ack,
+    /// A TFTP or HTTP transfer ended, whole or not.
+    Transfer,
+    /// The DHCP session wasn't acknowledged within 2 minutes.
+    Timeout,
+}
+
+impl Step {
+    pub const ALL: [Step; 7] = [
+        Step::Discover,
+        Step::Request,
+        Step::Decline,
+        Step::Offer,
+        Step::Ack,
+        Step::Transfer,
+        Step::Timeout,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Step::Discover => "discover",
+            Step::Request => "request",
+            Step::Decline => "decline",
+            Step::Offer => "offer",
+            Step::Ack => "ack",
+            Step::Transfer => "transfer",
+            Step::Timeout => "timeout",
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Step {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Step::ALL
+            .into_iter()
+            .find(|step| step.name() == s)
+            .ok_or(anyhow!("Unknown step {s}"))
+    }
+}
+
+/// A step of a client, as much as is known of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub time: OffsetDateTime,
+    pub step: Step,
+    pub mac_address: String,
+    pub xid: Option<u32>,
+    pub iface: Option<String>,
+    pub ip: Option<Ipv4Addr>,
+    /// What the step was about: the boot file answered, the file
+    /// transferred, or the stage the session timed out at.
+    pub detail: Option<String>,
+}
+
+impl Entry {
+    /// `step` of `mac_address`, now.
+    pub fn new(step: Step, mac_address: &str) -> Self {
+        Self {
+            time: OffsetDateTime::now_utc(),
+            step,
+            mac_address: mac_address.to_string(),
+            xid: None,
+            iface: None,
+            ip: None,
+            detail: None,
+        }
+    }
+
+    /// Its line, those unknown being `null`.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "time": self.time.format(&Rfc3339).unwrap_or_default(),
+            "event": self.step.name(),
+            "mac_address": self.mac_address,
+            "xid": self.xid.map(|xid| format!("{xid:#010x}")),
+            "iface": self.iface,
+            "ip": self.ip,
+            "detail": self.detail,
+        })
+    }
+
+    /// The entry of a line, none when it isn't one.
+    pub fn from_json(doc: &serde_json::Value) -> Option<Self> {
+        let text = |name: &str| doc[name].as_str().map(str::to_string);
+        Some(Self {
+            time: OffsetDateTime::parse(doc["time"].as_str()?, &Rfc3339).ok()?,
+            step: doc["event"].as_str()?.parse().ok()?,
+            mac_address: text("mac_address")?,
+            xid: doc["xid"]
+                .as_str()
+                .and_then(|xid| u32::from_str_radix(xid.trim_start_matches("0x"), 16).ok()),
+            iface: text("iface"),
+            ip: doc["ip"].as_str().and_then(|ip| ip.parse().ok()),
+            detail: text("detail"),
+        })
+    }
+}
+
+/// Starts appending to the `event_log` of `conf`, when set. Only the first
+/// call applies, the event log being read on start only.
+pub fn start(conf: &Conf) -> Result<()> {
+    let Some(path) = conf.get_event_log() else {
+        return Ok(());
+    };
+    if EVENT_LOG.get().is_some() {
+        return Ok(());
+    }
+    let event_log = LineLog::open(path, conf.get_event_log_max_size(), conf.get_event_log_files())
+        .context(format!("Opening the event log {}", path.display()))?;
+    let _ = EVENT_LOG.set(Mutex::new(event_log));
+    info!("Recording the events of the clients to {}.", path.display());
+    Ok(())
+}
+
+/// Appends `entry` to the event log, when recording.
+pub fn record(entry: Entry) {
+    let Some(event_log) = EVENT_LOG.get() else {
+        return;
+    };
+    let mut event_log = event_log.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(e) = event_log.write(&entry.to_json().to_string()) {
+        warn!(
+            "Failed recording the {} of {} to {}: {e}",
+            entry.step,
+            entry.mac_address,
+            event_log.path().display()
+        );
+    }
+}
+
+/// The steps of `mac_address` in the event log being recorded, none when
+/// there's none.
+pub fn recorded(mac_address: &str) -> Option<Result<Vec<Entry>>> {
+    let event_log = EVENT_LOG.get()?.lock().unwrap_or_else(|e| e.into_inner());
+    Some(read(event_log.path(), event_log.files(), mac_address))
+}
+
+/// The steps of `mac_address` in the event log `path` and the `files` it's
+/// rotated to, the oldest first. The lines that aren't steps are skipped.
+pub fn read(path: &Path, files: u64, mac_address: &str) -> Result<Vec<Entry>> {
+    let mac = parse_mac(mac_address).ok_or(anyhow!("Invalid MAC address {mac_address}"))?;
+    let numbered = |n: u64| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    };
+    let paths = (1..files).rev().map(numbered).chain([path.to_path_buf()]);
+
+    let mut entries = Vec::new();
+    for path in paths {
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).context(format!("Reading {}", path.display())),
+        };
+        entries.extend(
+            content
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .filter_map(|doc| Entry::from_json(&doc))
+                .filter(|entry| parse_mac(&entry.mac_address) == Some(mac)),
+        );
+    }
+    // In the order they happened, those of the same time as written
+    entries.sort_by_key(|entry| entry.time);
+    Ok(entries)
+}
+
+/// The steps of `entries` as the `timeline` command prints them, a line
+/// each.
+pub fn report(mac_address: &str, entries: &[Entry]) -> String {
+    if entries.is_empty() {
+        return format!("No events recorded for {mac_address}.\n");
+    }
+    let rows: Vec<[String; 6]> = entries
+        .iter()
+        .map(|entry| {
+            let or_none = |value: Option<String>| value.unwrap_or("-".to_string());
+            [
+                entry.time.format(&Rfc3339).unwrap_or_default(),
+                entry.step.name().to_uppercase(),
+                or_none(entry.xid.map(|xid| format!("{xid:#010x}"))),
+                or_none(entry.iface.clone()),
+                or_none(entry.ip.map(|ip| ip.to_string())),
+                or_none(entry.detail.clone()),
+            ]
+        })
+        .collect();
+    let widths: Vec<usize> = (0..6)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or_default())
+        .collect();
+    rows.iter()
+        .map(|row| {
+            let cells: Vec<String> = (0..6)
+                .map(|column| format!("{:width$}", row[column], width = widths[column]))
+                .collect();
+            cells.join("  ").trim_end().to_string() + "\n"
+        })
+        .collect()
+}
@@ -0,0 +1,149 @@
+//! TLS setup for the HTTPS endpoint, from configured PEM files or from a
+//! self-signed certificate generated on first use.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use futures_rustls::{
+    rustls::{
+        crypto::ring::default_provider,
+        pki_types::{CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+use log::{info, warn};
+
+use crate::{conf::CONFIG_FOLDER, Result};
+
+const SELF_SIGNED_CERT_FILENAME: &str = "https-cert.pem";
+const SELF_SIGNED_KEY_FILENAME: &str = "https-key.pem";
+
+/// Builds the TLS acceptor for the HTTPS endpoint. Without a configured
+/// certificate and key, a self-signed certificate for `names` is used. With
+/// `client_ca`, clients are asked for a certificate issued by it, but can
+/// still connect without one.
+pub fn acceptor(
+    cert: Option<&Path>,
+    key: Option<&Path>,
+    client_ca: Option<&Path>,
+    names: &[String],
+) -> Result<TlsAcceptor> {
+    let (certs, key) = match (cert, key) {
+        (Some(cert), Some(key)) => load_pem(cert, key)?,
+        (None, None) => self_signed(names)?,
+        _ => return Err(anyhow!("Both https_cert and https_key need to be configured.")),
+    };
+
+    let provider = Arc::new(default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()?;
+    let builder = match client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for ca_cert in rustls_pemfile::certs(&mut fs::read(client_ca)?.as_slice()) {
+                roots
+                    .add(ca_cert?)
+                    .with_context(|| format!("Reading certificates from {}", client_ca.display()))?;
+            }
+            if roots.is_empty() {
+                return Err(anyhow!("No certificate found in {}", client_ca.display()));
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .allow_unauthenticated()
+                .build()
+                .context("Setting up the HTTPS client certificate verification")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .context("Setting up the HTTPS certificate")?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+pub(crate) fn load_pem(
+    cert: &Path,
+    key: &Path,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let certs = rustls_pemfile::certs(&mut fs::read(cert)?.as_slice())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .with_context(|| format!("Reading certificates from {}", cert.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificate found in {}", cert.display()));
+    }
+    let key = rustls_pemfile::private_key(&mut fs::read(key)?.as_slice())
+        .with_context(|| format!("Reading private key from {}", key.display()))?
+        .ok_or_else(|| anyhow!("No private key found in {}", key.display()))?;
+
+    Ok((certs, key))
+}
+
+/// Reuses the self-signed certificate saved by a previous run, so clients
+/// that enrolled it keep trusting the server, or generates and saves one.
+fn self_signed(names: &[String]) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let paths = self_signed_paths();
+    if let Some((cert, key)) = &paths {
+        if cert.is_file() && key.is_file() {
+            return load_pem(cert, key);
+        }
+    }
+
+    let generated = rcgen::generate_simple_self_signed(names.to_vec())
+        .context("Generating a self-signed HTTPS certificate")?;
+    let (cert_pem, key_pem) = (generated.cert.pem(), generated.signing_key.serialize_pem());
+    let saved = paths.and_then(|(cert, key)| {
+        let dir = cert.parent()?;
+        fs::create_dir_all(dir)
+            .and_then(|_| fs::write(&cert, &cert_pem))
+            .and_then(|_| write_private(&key, &key_pem))
+            .inspect_err(|e| warn!("Failed saving the self-signed HTTPS certificate: {e}"))
+            .ok()
+            .map(|_| cert)
+    });
+    match saved {
+        Some(cert) => info!(
+            "Generated a self-signed HTTPS certificate for {}, saved to {}",
+            names.join(", "),
+            cert.display()
+        ),
+        None => info!(
+            "Generated a self-signed HTTPS certificate for {}",
+            names.join(", ")
+        ),
+    }
+
+    let key = PrivateKeyDer::try_from(generated.signing_key.serialize_der())
+        .map_err(|e| anyhow!("Invalid generated private key: {e}"))?;
+    Ok((vec![generated.cert.der().clone()], key))
+}
+
+/// Writes a file only readable by its owner.
+fn write_private(path: &Path, content: &str) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    std::io::Write::write_all(&mut options.open(path)?, content.as_bytes())
+}
+
+/// Directory the self-signed certificate is saved in.
+pub fn self_signed_dir() -> Option<PathBuf> {
+    Some(dirs::config_local_dir()?.join(CONFIG_FOLDER))
+}
+
+fn self_signed_paths() -> Option<(PathBuf, PathBuf)> {
+    let dir = self_signed_dir()?;
+    Some((
+        dir.join(SELF_SIGNED_CERT_FILENAME),
+        dir.join(SELF_SIGNED_KEY_FILENAME),
+    ))
+}
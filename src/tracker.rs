@@ -0,0 +1,484 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, SystemTime},
+};
+
+use log::{debug, info, trace};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analytics::{Protocol, TransferAnalytics, TransferRecord},
+    assets::AssetDb,
+    oneshot::Oneshot,
+    stats::BootStats,
+    timeline::{self, Entry, Step},
+};
+
+const MAX_TRACKED_AGE: Duration = Duration::from_secs(60 * 60);
+/// Sessions in a row expiring without an offer of the DHCP server of the
+/// network before its interface is told to have none.
+pub const UPSTREAM_MISSING_AFTER: u64 = 3;
+
+/// A client that was handed boot information by the DHCP service.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrackedClient {
+    pub mac_address: String,
+    pub xid: u32,
+    pub boot_file: String,
+    pub boot_file_delivered: bool,
+    pub updated: SystemTime,
+    /// Interface its DHCP messages came in on.
+    #[serde(default)]
+    pub iface: Option<String>,
+}
+
+/// Answers and downloads since the server started, told by the `status`
+/// command.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Counts {
+    pub offers: u64,
+    pub acks: u64,
+    /// Files read whole, from the start, over TFTP or HTTP.
+    pub transfers: u64,
+}
+
+/// DHCP messages and transfers of an interface since the server started,
+/// for the problems of a single interface to show.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct IfaceCounts {
+    pub iface: String,
+    /// DHCP messages received, ignored and dropped ones included.
+    pub received: u64,
+    /// DHCP messages not for the server: regular DHCP traffic, clients not
+    /// asking for a boot file and messages that can't be decoded.
+    pub ignored: u64,
+    /// DHCP answers sent, on the PXE boot server too.
+    pub answered: u64,
+    /// DHCP messages dropped unanswered, too many being answered already.
+    #[serde(default)]
+    pub dropped: u64,
+    /// Files read whole by the clients handed boot information on it.
+    pub transfers: u64,
+    /// DHCP sessions in a row that expired without the DHCP server of the
+    /// network offering the client an address.
+    #[serde(default)]
+    pub unoffered: u64,
+    /// Whether the DHCP server of the network is missing, `unoffered`
+    /// having reached `UPSTREAM_MISSING_AFTER`, until it offers again.
+    #[serde(default)]
+    pub upstream_missing: bool,
+}
+
+/// A TFTP or HTTP transfer in progress, as the dashboard lists it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransferInfo {
+    pub client: IpAddr,
+    /// The file sent, or the upload received.
+    pub path: String,
+    /// Size of the file, when known.
+    pub size: Option<u64>,
+    /// Bytes sent, or received, so far.
+    pub bytes: u64,
+    pub age_secs: u64,
+}
+
+struct Transfer {
+    client: IpAddr,
+    path: String,
+    size: Option<u64>,
+    started: SystemTime,
+    bytes: Arc<AtomicU64>,
+}
+
+/// Ties DHCP sessions to the TFTP transfers that follow them. The DHCP side
+/// records which IP address was given which boot file, the TFTP side reports
+/// completed downloads back by client IP.
+pub struct BootTracker {
+    clients: RwLock<HashMap<Ipv4Addr, TrackedClient>>,
+    max_clients: usize,
+    offers: AtomicU64,
+    acks: AtomicU64,
+    transfers: AtomicU64,
+    ifaces: Mutex<BTreeMap<String, IfaceCounts>>,
+    /// Transfers in progress by their ID, waited for before handing over.
+    active: Mutex<HashMap<u64, Transfer>>,
+    next_transfer: AtomicU64,
+    /// Told of the clients served, with `--oneshot`.
+    oneshot: Option<Arc<Oneshot>>,
+    stats: BootStats,
+    assets: AssetDb,
+    analytics: TransferAnalytics,
+}
+
+impl BootTracker {
+    pub fn new(max_clients: u64) -> Self {
+        Self {
+            clients: Default::default(),
+            max_clients: usize::try_from(max_clients).unwrap_or(usize::MAX),
+            offers: AtomicU64::new(0),
+            acks: AtomicU64::new(0),
+            transfers: AtomicU64::new(0),
+            ifaces: Default::default(),
+            active: Default::default(),
+            next_transfer: AtomicU64::new(0),
+            oneshot: None,
+            stats: BootStats::default(),
+            assets: AssetDb::default(),
+            analytics: TransferAnalytics::default(),
+        }
+    }
+
+    /// Tells `oneshot` of the clients downloading their boot file.
+    pub fn with_oneshot(mut self, oneshot: Arc<Oneshot>) -> Self {
+        self.oneshot = Some(oneshot);
+        self
+    }
+
+    /// Carries on the boot statistics `stats`, saved by a previous run.
+    pub fn with_stats(mut self, stats: BootStats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn stats(&self) -> &BootStats {
+        &self.stats
+    }
+
+    /// Carries on the clients `assets`, saved by a previous run.
+    pub fn with_assets(mut self, assets: AssetDb) -> Self {
+        self.assets = assets;
+        self
+    }
+
+    pub fn assets(&self) -> &AssetDb {
+        &self.assets
+    }
+
+    /// The transfers ended since the start, added up.
+    pub fn analytics(&self) -> &TransferAnalytics {
+        &self.analytics
+    }
+
+    /// Counts a DHCP OFFER of `boot_file` sent to `mac_address`.
+    pub fn offer_sent(&self, mac_address: &str, boot_file: Option<&str>) {
+        self.offers.fetch_add(1, Ordering::Relaxed);
+        self.stats.offered(mac_address, boot_file, SystemTime::now());
+        if let Some(boot_file) = boot_file {
+            self.assets.boot_file_sent(mac_address, boot_file, SystemTime::now());
+        }
+    }
+
+    /// Counts a DHCP ACK of `boot_file` sent to `mac_address`, by the PXE
+    /// boot server too.
+    pub fn ack_sent(&self, mac_address: &str, boot_file: Option<&str>) {
+        self.acks.fetch_add(1, Ordering::Relaxed);
+        self.stats.acked(mac_address, boot_file, SystemTime::now());
+        if let Some(boot_file) = boot_file {
+            self.assets.boot_file_sent(mac_address, boot_file, SystemTime::now());
+        }
+    }
+
+    pub fn counts(&self) -> Counts {
+        Counts {
+            offers: self.offers.load(Ordering::Relaxed),
+            acks: self.acks.load(Ordering::Relaxed),
+            transfers: self.transfers.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Counts a DHCP message received on `iface`.
+    pub fn dhcp_received(&self, iface: &str) {
+        self.count_iface(iface, |counts| counts.received += 1);
+    }
+
+    /// Counts a DHCP message received on `iface` that isn't for the server.
+    pub fn dhcp_ignored(&self, iface: &str) {
+        self.count_iface(iface, |counts| counts.ignored += 1);
+    }
+
+    /// Counts a DHCP message received on `iface` dropped unanswered.
+    pub fn dhcp_dropped(&self, iface: &str) {
+        self.count_iface(iface, |counts| counts.dropped += 1);
+    }
+
+    /// Counts a DHCP answer sent on `iface`.
+    pub fn dhcp_answered(&self, iface: &str) {
+        self.count_iface(iface, |counts| counts.answered += 1);
+    }
+
+    /// Counts a DHCP session of `iface` that expired without an offer of
+    /// the DHCP server of the network, true when it's the one raising the
+    /// alert of a missing DHCP server.
+    pub fn upstream_timed_out(&self, iface: &str) -> bool {
+        let mut raised = false;
+        self.count_iface(iface, |counts| {
+            counts.unoffered += 1;
+            raised = !counts.upstream_missing && counts.unoffered >= UPSTREAM_MISSING_AFTER;
+            counts.upstream_missing |= raised;
+        });
+        raised
+    }
+
+    /// Records an offer of the DHCP server of the network on `iface`, true
+    /// when it clears the alert of a missing DHCP server.
+    pub fn upstream_offered(&self, iface: &str) -> bool {
+        let mut cleared = false;
+        self.count_iface(iface, |counts| {
+            cleared = counts.upstream_missing;
+            counts.unoffered = 0;
+            counts.upstream_missing = false;
+        });
+        cleared
+    }
+
+    fn count_iface(&self, iface: &str, count: impl FnOnce(&mut IfaceCounts)) {
+        let mut ifaces = self.ifaces.lock().unwrap_or_else(|e| e.into_inner());
+        let counts = ifaces.entry(iface.to_string()).or_insert_with(|| IfaceCounts {
+            iface: iface.to_string(),
+            ..Default::default()
+        });
+        count(counts);
+    }
+
+    /// The counts of the interfaces messages were received on, by name.
+    pub fn iface_counts(&self) -> Vec<IfaceCounts> {
+        let ifaces = self.ifaces.lock().unwrap_or_else(|e| e.into_inner());
+        ifaces.values().cloned().collect()
+    }
+
+    /// Records that `ip` was acknowledged with `boot_file` in the DHCP
+    /// session `xid`, on `iface`.
+    pub fn boot_info_sent(&self, ip: Ipv4Addr, mac_address: &str, xid: u32, boot_file: &str, iface: &str) {
+        let Ok(mut clients) = self.clients.write() else {
+            return;
+        };
+
+        let now = SystemTime::now();
+        clients.retain(|_, client| {
+            now.duration_since(client.updated)
+                .map(|age| age < MAX_TRACKED_AGE)
+                .unwrap_or(true)
+        });
+        if clients.len() >= self.max_clients && !clients.contains_key(&ip) {
+            debug!("Boot tracker is full, not tracking client {mac_address} at {ip}.");
+            return;
+        }
+
+        clients.insert(
+            ip,
+            TrackedClient {
+                mac_address: mac_address.to_string(),
+                xid,
+                boot_file: boot_file.to_string(),
+                boot_file_delivered: false,
+                updated: now,
+                iface: Some(iface.to_string()),
+            },
+        );
+    }
+
+    /// Records a completed TFTP download of `path` by `ip`. Returns the client
+    /// when the file was the boot file it was given over DHCP.
+    pub fn file_delivered(&self, ip: Ipv4Addr, path: &Path) -> Option<TrackedClient> {
+        self.transfers.fetch_add(1, Ordering::Relaxed);
+        let mut clients = self.clients.write().ok()?;
+        let client = clients.get_mut(&ip)?;
+        if let Some(iface) = &client.iface {
+            self.count_iface(iface, |counts| counts.transfers += 1);
+        }
+        if !is_same_file(&client.boot_file, path) {
+            trace!(
+                "File {} delivered to client {} at {ip}, boot file was {}.",
+                path.display(),
+                client.mac_address,
+                client.boot_file
+            );
+            return None;
+        }
+
+        client.boot_file_delivered = true;
+        client.updated = SystemTime::now();
+        self.stats.booted(&client.mac_address, client.updated);
+        info!(
+            "Boot file {} delivered to client {} at {ip} (XID: {}), network boot handed over.",
+            client.boot_file, client.mac_address, client.xid
+        );
+        if let Some(oneshot) = &self.oneshot {
+            oneshot.served(&client.mac_address);
+        }
+
+        Some(client.clone())
+    }
+
+    pub fn get(&self, ip: &Ipv4Addr) -> Option<TrackedClient> {
+        self.clients.read().ok()?.get(ip).cloned()
+    }
+
+    /// Counts a TFTP or HTTP transfer of `path` with `client` in progress,
+    /// until the returned guard is dropped.
+    pub fn transfer_started(tracker: &Arc<Self>, client: IpAddr, path: &Path, size: Option<u64>) -> ActiveTransfer {
+        let id = tracker.next_transfer.fetch_add(1, Ordering::Relaxed);
+        let bytes = Arc::new(AtomicU64::new(0));
+        let transfer = Transfer {
+            client,
+            path: path.display().to_string(),
+            size,
+            started: SystemTime::now(),
+            bytes: Arc::clone(&bytes),
+        };
+        if let Ok(mut active) = tracker.active.lock() {
+            active.insert(id, transfer);
+        }
+        ActiveTransfer {
+            tracker: Arc::clone(tracker),
+            id,
+            bytes,
+            protocol: Protocol::Http,
+            completed: false,
+            retransmissions: 0,
+        }
+    }
+
+    pub fn active_transfers(&self) -> u64 {
+        self.active.lock().map_or(0, |active| active.len() as u64)
+    }
+
+    /// The transfers in progress, the oldest first.
+    pub fn transfers(&self) -> Vec<TransferInfo> {
+        let Ok(active) = self.active.lock() else {
+            return Vec::new();
+        };
+        let now = SystemTime::now();
+        let mut transfers: Vec<TransferInfo> = active
+            .values()
+            .map(|transfer| TransferInfo {
+                client: transfer.client,
+                path: transfer.path.clone(),
+                size: transfer.size,
+                bytes: transfer.bytes.load(Ordering::Relaxed),
+                age_secs: now.duration_since(transfer.started).unwrap_or_default().as_secs(),
+            })
+            .collect();
+        transfers.sort_by_key(|transfer| std::cmp::Reverse(transfer.age_secs));
+        transfers
+    }
+
+    /// The clients tracked, by IP address, for the server taking over.
+    pub fn clients(&self) -> Vec<(Ipv4Addr, TrackedClient)> {
+        self.clients
+            .read()
+            .map(|clients| clients.iter().map(|(ip, client)| (*ip, client.clone())).collect())
+            .unwrap_or_default()
+    }
+
+    /// Tracks the clients of the server taken over, as far as there's room.
+    pub fn inherit(&self, inherited: Vec<(Ipv4Addr, TrackedClient)>) {
+        let Ok(mut clients) = self.clients.write() else {
+            return;
+        };
+        for (ip, client) in inherited {
+            if clients.len() >= self.max_clients {
+                break;
+            }
+            clients.insert(ip, client);
+        }
+    }
+}
+
+/// A transfer counted by [`BootTracker::active_transfers`], added up by
+/// the analytics of the tracker once it ends.
+pub struct ActiveTransfer {
+    tracker: Arc<BootTracker>,
+    id: u64,
+    bytes: Arc<AtomicU64>,
+    /// HTTP unless told otherwise.
+    protocol: Protocol,
+    completed: bool,
+    retransmissions: u64,
+}
+
+impl ActiveTransfer {
+    /// Counts `bytes` more sent, or received.
+    pub fn transferred(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Tells the transfer is over `protocol`.
+    pub fn over(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    /// Tells the transfer was read, or written, to its end.
+    pub fn completed(&mut self) {
+        self.completed = true;
+    }
+
+    /// Counts `count` more blocks sent again.
+    pub fn retransmitted(&mut self, count: u64) {
+        self.retransmissions += count;
+    }
+}
+
+impl Drop for ActiveTransfer {
+    fn drop(&mut self) {
+        let Some(transfer) = self
+            .tracker
+            .active
+            .lock()
+            .ok()
+            .and_then(|mut active| active.remove(&self.id))
+        else {
+            return;
+        };
+        let record = TransferRecord {
+            client: transfer.client,
+            path: transfer.path,
+            protocol: self.protocol,
+            bytes: self.bytes.load(Ordering::Relaxed),
+            duration: transfer.started.elapsed().unwrap_or_default(),
+            completed: self.completed,
+            retransmissions: self.retransmissions,
+        };
+        // Of the client handed boot information at its address, if any
+        let tracked = match record.client {
+            IpAddr::V4(ip) => self.tracker.get(&ip).map(|client| (ip, client)),
+            IpAddr::V6(_) => None,
+        };
+        if let Some((ip, client)) = tracked {
+            let protocol = match record.protocol {
+                Protocol::Tftp => "TFTP",
+                Protocol::Http => "HTTP",
+            };
+            let outcome = match record.completed {
+                true => "",
+                false => ", not to its end",
+            };
+            timeline::record(Entry {
+                xid: Some(client.xid),
+                iface: client.iface,
+                ip: Some(ip),
+                detail: Some(format!(
+                    "{protocol} {}, {} bytes in {:.1}s{outcome}",
+                    record.path,
+                    record.bytes,
+                    record.duration.as_secs_f64()
+                )),
+                ..Entry::new(Step::Transfer, &client.mac_address)
+            });
+        }
+        self.tracker.analytics.record(&record);
+    }
+}
+
+fn is_same_file(boot_file: &str, path: &Path) -> bool {
+    let boot_file = boot_file.trim_start_matches('/');
+    let path = path.strip_prefix("/").unwrap_or(path);
+
+    Path::new(boot_file) == path
+}
@@ -0,0 +1,104 @@
+//! Log of the DHCP traffic, `traffic_log`: a line of JSON for each DHCP
+//! message received and sent, decoded as the `match` rules see it, for the
+//! rules to be written from what the clients of the site really send. The
+//! file is appended to and rotated once it reaches `traffic_log_max_size`,
+//! `traffic_log_files` of them being kept. Unlike `pcap_file`, the
+//! messages that can't be decoded are left out.
+use std::{
+    net::SocketAddr,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::Context;
+use dhcproto::v4::Message;
+use log::{info, warn};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{conf::Conf, util::LineLog, Result};
+
+static TRAFFIC_LOG: OnceLock<Mutex<LineLog>> = OnceLock::new();
+
+/// Whether a message was received or sent by the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Received => "received",
+            Self::Sent => "sent",
+        }
+    }
+}
+
+/// The line of `message`, from `src` to `dst` on the interface `iface` at
+/// `time`, the message being the document the `match` rules are matched to.
+pub fn to_json(
+    direction: Direction,
+    iface: &str,
+    src: SocketAddr,
+    dst: SocketAddr,
+    message: &Message,
+    time: OffsetDateTime,
+) -> serde_json::Value {
+    serde_json::json!({
+        "time": time.format(&Rfc3339).unwrap_or_default(),
+        "direction": direction.as_str(),
+        "iface": iface,
+        "src": src.to_string(),
+        "dst": dst.to_string(),
+        "message": serde_json::to_value(message).unwrap_or_default(),
+    })
+}
+
+/// Starts appending to the `traffic_log` of `conf`, when set. Only the
+/// first call applies, the traffic log being read on start only.
+pub fn start(conf: &Conf) -> Result<()> {
+    let Some(path) = conf.get_traffic_log() else {
+        return Ok(());
+    };
+    if TRAFFIC_LOG.get().is_some() {
+        return Ok(());
+    }
+    let traffic_log = LineLog::open(
+        path,
+        conf.get_traffic_log_max_size(),
+        conf.get_traffic_log_files(),
+    )
+    .context(format!("Opening the traffic log {}", path.display()))?;
+    let _ = TRAFFIC_LOG.set(Mutex::new(traffic_log));
+    info!("Recording the DHCP messages to {}.", path.display());
+    Ok(())
+}
+
+/// Appends `message`, from `src` to `dst` on the interface `iface`, to the
+/// traffic log, when recording.
+pub fn record(
+    direction: Direction,
+    iface: &str,
+    src: SocketAddr,
+    dst: SocketAddr,
+    message: &Message,
+) {
+    let Some(traffic_log) = TRAFFIC_LOG.get() else {
+        return;
+    };
+    let line = to_json(
+        direction,
+        iface,
+        src,
+        dst,
+        message,
+        OffsetDateTime::now_utc(),
+    );
+    let mut traffic_log = traffic_log.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(e) = traffic_log.write(&line.to_string()) {
+        warn!(
+            "Failed recording a DHCP message to {}: {e}",
+            traffic_log.path().display()
+        );
+    }
+}
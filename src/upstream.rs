@@ -0,0 +1,248 @@
+//! Client side of relaying TFTP read requests to an upstream TFTP server, used
+//! for files not available locally, and by the `tftp-get` command.
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    time::Duration,
+};
+
+use async_std::{io, net::UdpSocket, task};
+use futures::{
+    channel::mpsc::{self, Receiver, Sender},
+    stream::IntoAsyncRead,
+    SinkExt, TryStreamExt,
+};
+use log::{debug, trace, warn};
+
+pub const DEFAULT_TFTP_PORT: u16 = 69;
+
+const BLOCK_SIZE: usize = 512;
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(2);
+const UPSTREAM_RETRIES: u32 = 3;
+/// Blocks received from upstream but not yet sent to the client.
+const RELAY_BUFFER_BLOCKS: usize = 64;
+
+const OPCODE_RRQ: u16 = 1;
+const OPCODE_DATA: u16 = 3;
+const OPCODE_ACK: u16 = 4;
+const OPCODE_ERROR: u16 = 5;
+const OPCODE_OACK: u16 = 6;
+
+pub type UpstreamReader = IntoAsyncRead<Receiver<io::Result<Vec<u8>>>>;
+
+/// Requests `path` from the `upstream` TFTP server. Returns the file content
+/// as it is received and its size when the server reports it, or `None` when
+/// the server refuses the request, e.g. because it doesn't have the file.
+pub async fn open(
+    upstream: SocketAddr,
+    path: &Path,
+) -> io::Result<Option<(UpstreamReader, Option<u64>)>> {
+    match request(upstream, path).await? {
+        Ok(opened) => Ok(Some(opened)),
+        Err(message) => {
+            debug!(
+                "Upstream TFTP server {upstream} refused {}: {message}",
+                path.display()
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Requests `path` from the TFTP server `server` as `open` does, failing with
+/// the error it answers when it refuses the request.
+pub async fn get(server: SocketAddr, path: &Path) -> io::Result<(UpstreamReader, Option<u64>)> {
+    request(server, path).await?.map_err(|message| {
+        io::Error::other(format!("{server} refused {}: {message}", path.display()))
+    })
+}
+
+/// Sends the read request of `path`, answering the error message of the
+/// server when it refuses it.
+async fn request(
+    upstream: SocketAddr,
+    path: &Path,
+) -> io::Result<Result<(UpstreamReader, Option<u64>), String>> {
+    let bind_addr: SocketAddr = match upstream {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    let request = read_request(path);
+    let mut buf = [0u8; 4 + BLOCK_SIZE];
+
+    let mut response = None;
+    for _ in 0..UPSTREAM_RETRIES {
+        socket.send_to(&request, upstream).await?;
+        match io::timeout(UPSTREAM_TIMEOUT, socket.recv_from(&mut buf)).await {
+            Ok((len, peer)) if peer.ip() == upstream.ip() => {
+                response = Some((len, peer));
+                break;
+            }
+            Ok(_) => continue,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    let (len, peer) = response.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("No response from upstream TFTP server {upstream}"),
+        )
+    })?;
+
+    let mut transfer = Transfer {
+        socket,
+        peer,
+        acked: None,
+    };
+    let (first_block, size) = match Packet::parse(&buf[..len]) {
+        Packet::Oack(options) => {
+            transfer.ack(0).await?;
+            (None, tsize_option(options))
+        }
+        Packet::Data(1, data) => (Some(data.to_vec()), None),
+        Packet::Error(message) => return Ok(Err(message)),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unexpected response from upstream TFTP server {upstream}"),
+            ))
+        }
+    };
+
+    let (sender, receiver) = mpsc::channel(RELAY_BUFFER_BLOCKS);
+    task::spawn(transfer.relay(first_block, sender));
+
+    Ok(Ok((receiver.into_async_read(), size)))
+}
+
+struct Transfer {
+    socket: UdpSocket,
+    /// Address the upstream server answers from, its transfer ID.
+    peer: SocketAddr,
+    acked: Option<u16>,
+}
+
+impl Transfer {
+    async fn relay(
+        mut self,
+        first_block: Option<Vec<u8>>,
+        mut sender: Sender<io::Result<Vec<u8>>>,
+    ) {
+        let result = async {
+            let mut pending = first_block;
+            let mut block: u16 = 1;
+            loop {
+                let data = match pending.take() {
+                    Some(data) => data,
+                    None => self.receive(block).await?,
+                };
+                self.ack(block).await?;
+
+                let is_last = data.len() < BLOCK_SIZE;
+                if sender.send(Ok(data)).await.is_err() {
+                    trace!("Client transfer ended, stopped relaying from {}", self.peer);
+                    return Ok(());
+                }
+                if is_last {
+                    return Ok(());
+                }
+                block = block.wrapping_add(1);
+            }
+        };
+
+        if let Err(e) = result.await {
+            warn!(
+                "Relaying from upstream TFTP server {} failed: {e}",
+                self.peer
+            );
+            let _ = sender.send(Err(e)).await;
+        }
+    }
+
+    async fn receive(&mut self, block: u16) -> io::Result<Vec<u8>> {
+        let mut buf = [0u8; 4 + BLOCK_SIZE];
+        let mut retries = 0;
+        loop {
+            match io::timeout(UPSTREAM_TIMEOUT, self.socket.recv_from(&mut buf)).await {
+                Ok((len, peer)) if peer == self.peer => match Packet::parse(&buf[..len]) {
+                    Packet::Data(received, data) if received == block => return Ok(data.to_vec()),
+                    // Retransmission of a block already relayed
+                    Packet::Data(..) => continue,
+                    Packet::Error(message) => return Err(io::Error::other(message)),
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Unexpected packet during transfer",
+                        ))
+                    }
+                },
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut && retries < UPSTREAM_RETRIES => {
+                    retries += 1;
+                    if let Some(acked) = self.acked {
+                        self.ack(acked).await?;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn ack(&mut self, block: u16) -> io::Result<()> {
+        let mut packet = OPCODE_ACK.to_be_bytes().to_vec();
+        packet.extend_from_slice(&block.to_be_bytes());
+        self.socket.send_to(&packet, self.peer).await?;
+        self.acked = Some(block);
+
+        Ok(())
+    }
+}
+
+enum Packet<'a> {
+    Data(u16, &'a [u8]),
+    Oack(&'a [u8]),
+    Error(String),
+    Other,
+}
+
+impl<'a> Packet<'a> {
+    fn parse(packet: &'a [u8]) -> Self {
+        if packet.len() < 4 {
+            return Packet::Other;
+        }
+
+        let opcode = u16::from_be_bytes([packet[0], packet[1]]);
+        match opcode {
+            OPCODE_DATA => Packet::Data(u16::from_be_bytes([packet[2], packet[3]]), &packet[4..]),
+            OPCODE_OACK => Packet::Oack(&packet[2..]),
+            OPCODE_ERROR => {
+                let message = packet[4..].split(|b| *b == 0).next().unwrap_or_default();
+                Packet::Error(String::from_utf8_lossy(message).to_string())
+            }
+            _ => Packet::Other,
+        }
+    }
+}
+
+fn read_request(path: &Path) -> Vec<u8> {
+    let mut packet = OPCODE_RRQ.to_be_bytes().to_vec();
+    for field in [path.to_string_lossy().as_ref(), "octet", "tsize", "0"] {
+        packet.extend_from_slice(field.as_bytes());
+        packet.push(0);
+    }
+
+    packet
+}
+
+fn tsize_option(options: &[u8]) -> Option<u64> {
+    let mut fields = options.split(|b| *b == 0);
+    while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+        if name.eq_ignore_ascii_case(b"tsize") {
+            return std::str::from_utf8(value).ok()?.parse().ok();
+        }
+    }
+
+    None
+}
@@ -1,7 +1,178 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use network_interface::{Addr, NetworkInterface, NetworkInterfaceConfig};
+
+use crate::{
+    conf::{Conf, MacAddress},
+    Result,
+};
+
 pub fn bytes_to_mac_address(bytes: &[u8]) -> String {
     let str_parts: Vec<String> = bytes
-        .into_iter()
+        .iter()
         .map(|byte| format!("{:0>2X}", byte))
         .collect();
     str_parts.join(":")
 }
+
+/// MAC address of the client of `doc`, from its `chaddr`.
+pub fn mac_from_doc(doc: &serde_json::Value) -> Option<MacAddress> {
+    let bytes = doc["chaddr"]
+        .as_array()?
+        .iter()
+        .take(6)
+        .map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+        .collect::<Option<Vec<u8>>>()?;
+
+    bytes.try_into().ok()
+}
+
+/// Lowercase hex encoding, as checksums are written.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Hidden file in the directory of `path` to write its new content to, then
+/// renamed over it atomically.
+pub fn part_path(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let suffix: u32 = rand::random();
+
+    path.with_file_name(format!(".{file_name}.{suffix:08x}.part"))
+}
+
+/// IPv4 addresses of the configured network interfaces, or of all when none
+/// are configured.
+pub fn listen_ips(conf: &Conf) -> Result<Vec<Ipv4Addr>> {
+    let network_interfaces = NetworkInterface::show().context("Listing network interfaces")?;
+    let listen_ips = network_interfaces
+        .iter()
+        .filter(|iface| {
+            // only listen on the configured network interfaces
+            conf.get_ifaces()
+                .map(|ifaces| ifaces.contains(&iface.name))
+                .unwrap_or(true) // or on all if no interfaces are configured
+        })
+        .flat_map(|iface| {
+            iface
+                .addr
+                .iter()
+                .filter_map(|ip| match ip {
+                    Addr::V4(v4) => Some(v4.ip),
+                    Addr::V6(_) => None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    Ok(listen_ips)
+}
+
+/// `url` with the password of its user information redacted, for logs.
+pub fn redact_url(url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_string();
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    match authority.rsplit_once('@') {
+        Some((user_info, host)) => {
+            let user = user_info.split(':').next().unwrap_or_default();
+            let path = &rest[authority.len()..];
+            format!("{scheme}://{user}:<redacted>@{host}{path}")
+        }
+        None => url.to_string(),
+    }
+}
+
+/// A file lines are appended to, rotated by size, for the logs kept apart
+/// from the messages of the server.
+pub struct LineLog {
+    path: PathBuf,
+    max_size: u64,
+    files: u64,
+    file: File,
+    size: u64,
+}
+
+impl LineLog {
+    /// Appends to `path`, created when missing. The file is rotated once
+    /// writing a line would make it larger than `max_size` bytes, keeping
+    /// `files` of them, `path.1` being the most recent before `path`.
+    pub fn open(path: &Path, max_size: u64, files: u64) -> io::Result<Self> {
+        let file = append(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_size,
+            files,
+            file,
+            size,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Files kept, the one written included.
+    pub fn files(&self) -> u64 {
+        self.files
+    }
+
+    /// Appends `line`, a line feed ending it.
+    pub fn write(&mut self, line: &str) -> io::Result<()> {
+        let line = format!("{line}\n");
+        if self.size + line.len() as u64 > self.max_size && self.size > 0 {
+            rotate(&self.path, self.files)?;
+            self.file = append(&self.path)?;
+            self.size = 0;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Opens `path` to append to, created readable by the user and group of
+/// the server only.
+fn append(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o640);
+    options.open(path)
+}
+
+/// Moves `path` to `path.1`, `path.1` to `path.2` and so on, the oldest
+/// beyond `files` being removed.
+pub fn rotate(path: &Path, files: u64) -> io::Result<()> {
+    let numbered = |n: u64| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    };
+    if files <= 1 {
+        return fs::remove_file(path).or_else(ignore_not_found);
+    }
+    fs::remove_file(numbered(files - 1)).or_else(ignore_not_found)?;
+    for n in (1..files - 1).rev() {
+        fs::rename(numbered(n), numbered(n + 1)).or_else(ignore_not_found)?;
+    }
+    fs::rename(path, numbered(1)).or_else(ignore_not_found)
+}
+
+fn ignore_not_found(e: io::Error) -> io::Result<()> {
+    match e.kind() {
+        io::ErrorKind::NotFound => Ok(()),
+        _ => Err(e),
+    }
+}
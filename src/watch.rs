@@ -0,0 +1,108 @@
+//! `watch` subcommand: the events of the clients of the running server as
+//! they happen, streamed on its control socket, a line each colored by
+//! client, for an operator to follow a machine booting live. The same
+//! events are streamed by the control API as server-sent events.
+use std::path::Path;
+#[cfg(unix)]
+use std::{
+    io::{BufRead, BufReader, IsTerminal, Write},
+    os::unix::net::UnixStream,
+};
+
+#[cfg(unix)]
+use anyhow::Context;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::Result;
+
+/// Colors of the clients, as ANSI codes, picked by MAC address.
+const CLIENT_COLORS: [u8; 10] = [32, 33, 34, 35, 36, 92, 93, 94, 95, 96];
+/// Events of clients not booting, told in bold.
+const FAILURES: [&str; 3] = [
+    "unknown_client",
+    "session_timed_out",
+    "upstream_dhcp_missing",
+];
+const DETAILS: [&str; 6] = ["xid", "ip", "boot_file", "iface", "stage", "rule"];
+
+/// Prints the events of the clients of `mac_address`, or all of them, told
+/// by the server listening on the control socket at `path`, until it stops,
+/// as their JSON documents with `json`.
+#[cfg(unix)]
+pub fn run(path: &Path, mac_address: Option<&str>, json: bool) -> Result<()> {
+    let mut stream = UnixStream::connect(path).with_context(|| {
+        format!(
+            "Connecting to {}, is preboot-oxide running?",
+            path.display()
+        )
+    })?;
+    let command = match mac_address {
+        Some(mac_address) => format!("watch {mac_address}\n"),
+        None => "watch\n".to_string(),
+    };
+    stream.write_all(command.as_bytes())?;
+
+    let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let mut stdout = std::io::stdout();
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        // The keep-alives
+        if line.is_empty() {
+            continue;
+        }
+        let event: serde_json::Value =
+            serde_json::from_str(&line).context("Reading the events of the server")?;
+        if let Some(error) = event.get("error").and_then(|error| error.as_str()) {
+            bail!("{error}");
+        }
+        match json {
+            true => writeln!(stdout, "{line}")?,
+            false => writeln!(stdout, "{}", format_event(&event, color))?,
+        }
+        stdout.flush()?;
+    }
+
+    bail!("The server closed the event stream")
+}
+
+#[cfg(not(unix))]
+pub fn run(_path: &Path, _mac_address: Option<&str>, _json: bool) -> Result<()> {
+    bail!("The control socket of the running server needs Unix sockets")
+}
+
+/// The line of `event`, its time to the second, the MAC address of the
+/// client, the event and what's known of it, colored by client with
+/// `color`.
+pub fn format_event(event: &serde_json::Value, color: bool) -> String {
+    let field = |name: &str| event[name].as_str().unwrap_or_default();
+    let time = OffsetDateTime::parse(field("time"), &Rfc3339)
+        .ok()
+        .and_then(|time| time.replace_nanosecond(0).ok())
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or(field("time").to_string());
+    let details: Vec<String> = DETAILS
+        .into_iter()
+        .filter(|name| !field(name).is_empty())
+        .map(|name| format!("{name}={}", field(name)))
+        .collect();
+    let line = format!(
+        "{time}  {}  {:<19}  {}",
+        field("mac_address"),
+        field("event"),
+        details.join(" ")
+    );
+    let line = line.trim_end();
+    if !color {
+        return line.to_string();
+    }
+
+    let mac_address = field("mac_address");
+    let hash = mac_address.bytes().fold(0usize, |hash, byte| {
+        hash.wrapping_mul(31).wrapping_add(byte as usize)
+    });
+    let code = CLIENT_COLORS[hash % CLIENT_COLORS.len()];
+    match FAILURES.contains(&field("event")) {
+        true => format!("\x1b[1;{code}m{line}\x1b[0m"),
+        false => format!("\x1b[{code}m{line}\x1b[0m"),
+    }
+}
@@ -0,0 +1,66 @@
+//! Lookup of the configuration of DHCP clients from an HTTP endpoint, for
+//! sites keeping their provisioning logic in a CMDB rather than in `match`:
+//! the decoded DHCP message is posted to `webhook_url` as JSON, answered
+//! with the fields of a `default` section.
+use std::time::Duration;
+
+use anyhow::Context;
+use async_std::task;
+use log::{debug, warn};
+
+use crate::{
+    conf::{Conf, ConfEntry},
+    Result,
+};
+
+/// Configuration of the client of `doc` from the webhook, `None` when none
+/// is configured, it has none for the client or it failed, for `match` to
+/// be used.
+pub async fn client_conf(conf: &Conf, doc: &serde_json::Value) -> Option<ConfEntry> {
+    let url = conf.get_webhook_url()?;
+    match lookup(url, conf.get_webhook_timeout(), doc).await {
+        Ok(entry) => entry,
+        Err(e) => {
+            warn!("Webhook lookup failed, using the match rules: {e:#}");
+            None
+        }
+    }
+}
+
+/// Posts `doc` to `url`, returning the configuration answered. `None` when
+/// answered with 204 No Content or 404 Not Found.
+pub async fn lookup(
+    url: &str,
+    timeout: Duration,
+    doc: &serde_json::Value,
+) -> Result<Option<ConfEntry>> {
+    let (url, body) = (url.to_string(), doc.to_string());
+    let response = task::spawn_blocking(move || -> Result<Option<String>> {
+        let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+        let response = agent
+            .post(&url)
+            .set("Content-Type", "application/json")
+            .send_string(&body);
+        match response {
+            Ok(response) if response.status() == 204 => Ok(None),
+            Ok(response) => response
+                .into_string()
+                .map(Some)
+                .with_context(|| format!("Reading the answer of {url}")),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(anyhow::Error::from(e)).with_context(|| format!("Posting to {url}")),
+        }
+    })
+    .await?;
+
+    let Some(response) = response else {
+        debug!("Webhook has no configuration for the client.");
+        return Ok(None);
+    };
+    let entry = serde_json::from_str(&response)
+        .map_err(anyhow::Error::from)
+        .and_then(ConfEntry::from_json)
+        .context("Parsing the answer of the webhook")?;
+
+    Ok(Some(entry))
+}
@@ -0,0 +1,102 @@
+//! Windows service wrapper: `preboot-oxide service` is the command the
+//! service control manager starts, the server being reported running, and
+//! stopped on request or when it fails.
+use std::{
+    ffi::OsString,
+    sync::{mpsc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Context;
+use log::{error, info};
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+        ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult},
+    service_dispatcher,
+};
+
+use crate::Result;
+
+/// Name the service is installed under.
+pub const SERVICE_NAME: &str = "preboot-oxide";
+
+type Serve = Box<dyn FnOnce() -> Result<()> + Send>;
+
+/// What the service runs, handed over to the thread of the dispatcher.
+static SERVE: Mutex<Option<Serve>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Runs `serve` as the service, returning once it's stopped.
+pub fn run(serve: impl FnOnce() -> Result<()> + Send + 'static) -> Result<()> {
+    *SERVE.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Box::new(serve));
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .context("Connecting to the service control manager, the service command is for it only")?;
+
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("The service failed: {e:#}");
+    }
+}
+
+fn run_service() -> Result<()> {
+    let serve = SERVE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .take()
+        .ok_or(anyhow!("The service was already started"))?;
+    // Told the result of the server, `None` when it's stopped
+    let (done, finished) = mpsc::channel::<Option<Result<()>>>();
+    let stop = done.clone();
+    let status_handle = service_control_handler::register(SERVICE_NAME, move |control| {
+        match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                let _ = stop.send(None);
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        }
+    })?;
+    status_handle.set_service_status(status(ServiceState::Running, 0))?;
+
+    thread::spawn(move || {
+        let _ = done.send(Some(serve()));
+    });
+    let exit_code = match finished.recv() {
+        Ok(Some(Err(e))) => {
+            error!("{e:#}");
+            1
+        }
+        _ => {
+            info!("Service stopped.");
+            0
+        }
+    };
+    status_handle.set_service_status(status(ServiceState::Stopped, exit_code))?;
+
+    Ok(())
+}
+
+fn status(state: ServiceState, exit_code: u32) -> ServiceStatus {
+    ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted: match state {
+            ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            _ => ServiceControlAccept::empty(),
+        },
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    }
+}
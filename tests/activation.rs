@@ -0,0 +1,46 @@
+#![cfg(target_os = "linux")]
+extern crate preboot_oxide;
+
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    os::fd::IntoRawFd,
+};
+
+use preboot_oxide::activation;
+
+#[test]
+fn test_listen_fds() {
+    assert_eq!(activation::listen_fds(Some("42"), Some("3"), 42), vec![3, 4, 5]);
+    // Meant for the parent, started without socket activation
+    assert!(activation::listen_fds(Some("41"), Some("3"), 42).is_empty());
+    assert!(activation::listen_fds(None, Some("3"), 42).is_empty());
+    assert!(activation::listen_fds(Some("42"), None, 42).is_empty());
+    assert!(activation::listen_fds(Some("42"), Some("-1"), 42).is_empty());
+}
+
+#[test]
+fn test_activated_sockets() {
+    let passed = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let SocketAddr::V4(addr) = passed.local_addr().unwrap() else {
+        unreachable!("Bound to an IPv4 address");
+    };
+    // SAFETY: the descriptor is released by the socket
+    unsafe { activation::add_fds(&[passed.into_raw_fd()]) }.unwrap();
+    assert!(activation::has(addr));
+    assert!(!activation::has(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1)));
+
+    // Used again, e.g. when the TFTP servers restart
+    for _ in 0..2 {
+        let socket = activation::socket(addr, None).unwrap().unwrap();
+        assert_eq!(socket.local_addr().unwrap(), addr.into());
+    }
+    let other = SocketAddrV4::new(Ipv4Addr::LOCALHOST, addr.port().wrapping_add(1));
+    assert!(activation::socket(other, None).unwrap().is_none());
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.send_to(b"hello", addr).unwrap();
+    let mut buf = [0u8; 5];
+    let socket = activation::socket(addr, None).unwrap().unwrap();
+    socket.recv_from(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+}
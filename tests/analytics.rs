@@ -0,0 +1,125 @@
+extern crate preboot_oxide;
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use preboot_oxide::{
+    analytics::{self, AnalyticsState, Protocol, TransferAnalytics, TransferRecord},
+    tracker::BootTracker,
+};
+
+fn record(client: [u8; 4], path: &str, bytes: u64, millis: u64, completed: bool) -> TransferRecord {
+    TransferRecord {
+        client: IpAddr::from(client),
+        path: path.to_string(),
+        protocol: Protocol::Tftp,
+        bytes,
+        duration: Duration::from_millis(millis),
+        completed,
+        retransmissions: 0,
+    }
+}
+
+fn analytics() -> AnalyticsState {
+    let analytics = TransferAnalytics::new(AnalyticsState {
+        since: 1714521600,
+        ..Default::default()
+    });
+    for client in [10, 11, 12] {
+        analytics.record(&record(
+            [10, 0, 0, client],
+            "ipxe.efi",
+            1_000_000,
+            500,
+            true,
+        ));
+    }
+    analytics.record(&TransferRecord {
+        retransmissions: 4,
+        ..record([10, 0, 1, 10], "ipxe.efi", 200_000, 20_000, false)
+    });
+    analytics.record(&TransferRecord {
+        protocol: Protocol::Http,
+        ..record([10, 0, 1, 11], "images/vmlinuz", 50_000_000, 2_000, true)
+    });
+    analytics.snapshot()
+}
+
+#[test]
+fn test_record() {
+    let state = analytics();
+    assert_eq!(state.totals.transfers, 5);
+    assert_eq!(state.totals.failed, 1);
+    assert_eq!(state.totals.retransmissions, 4);
+    assert_eq!(state.tftp.transfers, 4);
+    assert_eq!(state.http.bytes, 50_000_000);
+    assert_eq!(state.files["ipxe.efi"].transfers, 4);
+    assert_eq!(state.subnets["10.0.1.0/24"].failed, 1);
+    assert_eq!(state.subnets["10.0.0.0/24"].throughput(), Some(2_000_000));
+    // The failed transfer left out
+    assert_eq!(state.throughput, vec![0, 0, 3, 1, 0]);
+
+    assert_eq!(
+        analytics::subnet("2001:db8:1:2:3:4:5:6".parse().unwrap()),
+        "2001:db8:1:2::/64"
+    );
+}
+
+#[test]
+fn test_report() {
+    assert_eq!(
+        analytics::report(&analytics(), 1),
+        "\
+Transfers since 2024-05-01T00:00:00Z: 5, failed: 1 (20.0%), retransmissions: 4
+  TFTP: 4, failed: 1, 3.2 MB at 148.8 kB/s
+  HTTP: 1, failed: 0, 50.0 MB at 25.0 MB/s
+Top files, of 2:
+  FILE      TRANSFERS  FAILED     BYTES   THROUGHPUT  RETRANSMISSIONS
+  ipxe.efi  4          1 (25.0%)  3.2 MB  148.8 kB/s  4
+Throughput of the transfers read whole:
+  0 B/s - 100 kB/s             0
+  100 kB/s - 1 MB/s            0
+  1 MB/s - 10 MB/s             3  ########################################
+  10 MB/s - 100 MB/s           1  ##############
+  100 MB/s and more            0
+Subnets, failing the most first:
+  SUBNET       TRANSFERS  FAILED     BYTES    THROUGHPUT  RETRANSMISSIONS
+  10.0.1.0/24  2          1 (50.0%)  50.2 MB  2.3 MB/s    4
+  10.0.0.0/24  3          0 (0.0%)   3.0 MB   2.0 MB/s    0
+"
+    );
+    assert_eq!(
+        analytics::report(&AnalyticsState::default(), 10),
+        "Transfers since 1970-01-01T00:00:00Z: 0, failed: 0, retransmissions: 0\n"
+    );
+}
+
+#[test]
+fn test_tracked_transfers() {
+    let tracker = Arc::new(BootTracker::new(10));
+    let client = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5));
+    let mut transfer =
+        BootTracker::transfer_started(&tracker, client, Path::new("ipxe.efi"), Some(1024));
+    transfer.over(Protocol::Tftp);
+    transfer.transferred(1024);
+    transfer.retransmitted(2);
+    transfer.completed();
+    drop(transfer);
+    // Dropped before its end
+    let transfer = BootTracker::transfer_started(&tracker, client, Path::new("boot.cfg"), None);
+    transfer.transferred(10);
+    drop(transfer);
+
+    let state = tracker.analytics().snapshot();
+    assert_eq!(tracker.active_transfers(), 0);
+    assert_eq!(state.tftp.transfers, 1);
+    assert_eq!(state.tftp.bytes, 1024);
+    assert_eq!(state.tftp.retransmissions, 2);
+    assert_eq!(state.http.failed, 1);
+    assert_eq!(state.files["boot.cfg"].bytes, 10);
+    assert_eq!(state.subnets["10.0.0.0/24"].transfers, 2);
+}
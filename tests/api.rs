@@ -0,0 +1,120 @@
+extern crate preboot_oxide;
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use async_std::task;
+use futures::StreamExt;
+use preboot_oxide::{
+    api,
+    conf::Conf,
+    control::{Control, ControlledService},
+    dhcp::{self, SessionMap},
+    reload,
+    tracker::BootTracker,
+};
+
+mod utils;
+
+/// Sends `method` on `path` with the token `token`, returns the status and
+/// the body of the response.
+fn request(port: u16, method: &str, path: &str, token: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let request = format!(
+        "{method} {path} HTTP/1.1\r\nAuthorization: Bearer {token}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head[9..12].parse().unwrap(), body.to_string())
+}
+
+#[test]
+fn test_api() {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let yaml = format!(
+        "tftp_server_dir: /tftpdir\napi_addr: 127.0.0.1:{port}\napi_token: s3cret\ndefault:\n    boot_file: ipxe.efi\n"
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_ok());
+
+    let tracker = Arc::new(BootTracker::new(10));
+    tracker.boot_info_sent("10.0.0.5".parse().unwrap(), "52:54:00:12:34:56", 42, "ipxe.efi", "eth0");
+    tracker.boot_info_sent("10.0.0.6".parse().unwrap(), "52:54:00:ab:cd:ef", 43, "ipxe.efi", "eth0");
+    let (reloader, mut reload_requests) = reload::reloader();
+    let control = Arc::new(Control {
+        started: SystemTime::now(),
+        origin: "--set".to_string(),
+        tracker: Arc::clone(&tracker),
+        services: vec![ControlledService {
+            tenant: None,
+            shared_conf: Arc::new(std::sync::RwLock::new(Arc::new(conf.clone()))),
+            sessions: Some(Arc::new(SessionMap::new(&conf))),
+        }],
+        reloader,
+        handover: Default::default(),
+    });
+    api::spawn_api_async(&conf, control).unwrap();
+    task::spawn(async move {
+        while let Some(answer) = reload_requests.next().await {
+            let _ = answer.send(Ok(()));
+        }
+    });
+
+    assert_eq!(request(port, "GET", "/api/v1/sessions", "wrong", "").0, 401);
+    assert_eq!(request(port, "GET", "/api/v1/sessions", "s3cret", ""), (200, "[]".to_string()));
+    assert_eq!(request(port, "DELETE", "/api/v1/sessions/0x2a", "s3cret", "").0, 404);
+    assert_eq!(request(port, "DELETE", "/api/v1/sessions/xyz", "s3cret", "").0, 400);
+
+    let (status, body) = request(port, "GET", "/api/v1/clients?mac=52:54:00:AB:CD:EF", "s3cret", "");
+    assert_eq!(status, 200);
+    let clients: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(clients.as_array().unwrap().len(), 1);
+    assert_eq!(clients[0]["ip"], "10.0.0.6");
+
+    // Without event_log
+    assert_eq!(request(port, "GET", "/api/v1/timeline?mac=52:54:00:12:34:56", "s3cret", "").0, 404);
+    assert_eq!(request(port, "GET", "/api/v1/timeline?mac=xyz", "s3cret", "").0, 400);
+
+    let (status, body) = request(port, "POST", "/api/v1/reload", "s3cret", "");
+    assert_eq!((status, body.as_str()), (200, "{\"reloaded\":true}"));
+    assert_eq!(request(port, "GET", "/api/v1/reload", "s3cret", "").0, 405);
+
+    let (status, body) = request(port, "PUT", "/api/v1/dry_run", "s3cret", "{\"dry_run\": true}");
+    assert_eq!((status, body.as_str()), (200, "{\"dry_run\":true}"));
+    assert!(dhcp::is_dry_run());
+    assert_eq!(request(port, "PUT", "/api/v1/dry_run", "s3cret", "yes").0, 400);
+    // Bodies over the limit are refused before they're sent
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let head = "PUT /api/v1/dry_run HTTP/1.1\r\nAuthorization: Bearer s3cret\r\nContent-Length: 1000000\r\n\r\n";
+    stream.write_all(head.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 413 "), "{response}");
+    let (_, body) = request(port, "GET", "/api/v1/stats", "s3cret", "");
+    let stats: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(stats["dry_run"], true);
+    assert_eq!(stats["sessions"], 0);
+    request(port, "PUT", "/api/v1/dry_run", "s3cret", "{\"dry_run\": false}");
+    assert!(!dhcp::is_dry_run());
+
+    assert_eq!(request(port, "GET", "/api/v2/stats", "s3cret", "").0, 404);
+}
+
+#[test]
+fn test_api_conf() {
+    let yaml = "tftp_server_dir: /tftpdir\napi_addr: 127.0.0.1:8068\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+    let yaml = "tftp_server_dir: /tftpdir\napi_addr: localhost:8068\napi_token: s3cret\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    assert!(Conf::from_config_file(Some(&yaml_mock.path)).is_err());
+}
@@ -0,0 +1,110 @@
+extern crate preboot_oxide;
+
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use dhcproto::v4::{Architecture, DhcpOption, Message, MessageType};
+use preboot_oxide::{
+    assets::{self, Asset, AssetDb},
+    conf::Conf,
+    tracker::BootTracker,
+};
+
+mod utils;
+
+const MAC: &str = "52:54:00:12:34:56";
+
+fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1714521600 + secs)
+}
+
+fn discover() -> Message {
+    let mut message = Message::default();
+    message.set_chaddr(&[0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+    let opts = message.opts_mut();
+    opts.insert(DhcpOption::MessageType(MessageType::Discover));
+    opts.insert(DhcpOption::ClientSystemArchitecture(Architecture::from(7)));
+    opts.insert(DhcpOption::ClassIdentifier(
+        b"PXEClient:Arch:00007:UNDI:003016".to_vec(),
+    ));
+    let mut uuid = vec![0];
+    uuid.extend(0x00..0x10u8);
+    opts.insert(DhcpOption::ClientMachineIdentifier(uuid));
+    message
+}
+
+#[test]
+fn test_seen() {
+    let assets = AssetDb::default();
+    assets.seen(&discover(), at(0));
+    assets.boot_file_sent(MAC, "ipxe.efi", at(1));
+    // Its options left out by the chainload, what was known is kept
+    let mut request = Message::default();
+    request.set_chaddr(&[0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+    assets.seen(&request, at(60));
+
+    assert_eq!(
+        assets.list(),
+        vec![Asset {
+            mac_address: MAC.to_string(),
+            uuid: Some("00010203-0405-0607-0809-0a0b0c0d0e0f".to_string()),
+            arch: Some("x64-uefi".to_string()),
+            vendor: Some("PXEClient:Arch:00007:UNDI:003016".to_string()),
+            first_seen: 1714521600,
+            last_seen: 1714521660,
+            last_boot_file: Some("ipxe.efi".to_string()),
+        }]
+    );
+}
+
+#[test]
+fn test_report() {
+    let assets = AssetDb::default();
+    assets.seen(&discover(), at(0));
+    assets.boot_file_sent("52:54:00:12:34:57", "undionly.kpxe", at(5));
+
+    assert_eq!(
+        assets::report(&assets.list(), false),
+        "\
+MAC                UUID                                  ARCH      VENDOR                            FIRST SEEN            LAST SEEN             LAST BOOT FILE
+52:54:00:12:34:56  00010203-0405-0607-0809-0a0b0c0d0e0f  x64-uefi  PXEClient:Arch:00007:UNDI:003016  2024-05-01T00:00:00Z  2024-05-01T00:00:00Z  -
+52:54:00:12:34:57  -                                     -         -                                 2024-05-01T00:00:05Z  2024-05-01T00:00:05Z  undionly.kpxe
+"
+    );
+    assert_eq!(
+        assets::report(&assets.list()[1..], true),
+        "mac,uuid,arch,vendor,first_seen,last_seen,last_boot_file
+52:54:00:12:34:57,,,,2024-05-01T00:00:05Z,2024-05-01T00:00:05Z,undionly.kpxe
+"
+    );
+    assert_eq!(assets::report(&[], false), "No clients seen yet.\n");
+}
+
+#[test]
+fn test_saved_assets() {
+    let dir = std::env::temp_dir().join(format!("po-assets-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("assets.json");
+
+    let tracker = BootTracker::new(10).with_assets(AssetDb::load(&path).unwrap());
+    assert!(tracker.assets().list().is_empty());
+    tracker.offer_sent(MAC, Some("ipxe.efi"));
+    tracker.assets().save(&path).unwrap();
+    let loaded = AssetDb::load(&path).unwrap();
+    assert_eq!(loaded.list(), tracker.assets().list());
+    assert_eq!(loaded.list()[0].last_boot_file.as_deref(), Some("ipxe.efi"));
+
+    std::fs::write(&path, "[").unwrap();
+    assert!(AssetDb::load(&path).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let yaml = "tftp_server_dir: /tftpdir\nassets_file: /var/lib/preboot-oxide/assets.json\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert_eq!(
+        conf.get_assets_file().unwrap(),
+        Path::new("/var/lib/preboot-oxide/assets.json")
+    );
+}
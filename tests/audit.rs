@@ -0,0 +1,94 @@
+extern crate preboot_oxide;
+
+use std::net::Ipv4Addr;
+
+use preboot_oxide::{
+    audit::{AuditLog, Decision},
+    conf::Conf,
+};
+use time::OffsetDateTime;
+
+mod utils;
+
+fn decision() -> Decision {
+    Decision {
+        mac_address: "aa:bb:cc:dd:ee:ff".to_string(),
+        xid: 0x1234,
+        client_ip: Some(Ipv4Addr::new(10, 0, 0, 5)),
+        relay_ip: None,
+        iface: "eth0".to_string(),
+        reply: "offer".to_string(),
+        rule: "match[2]".to_string(),
+        boot_file: Some("ipxe.efi".to_string()),
+        boot_server: Some(Ipv4Addr::new(10, 0, 0, 1)),
+    }
+}
+
+#[test]
+fn test_decision_json() {
+    let time = OffsetDateTime::from_unix_timestamp(1714566600).unwrap();
+    assert_eq!(
+        decision().to_json(time),
+        serde_json::json!({
+            "time": "2024-05-01T12:30:00Z",
+            "mac_address": "aa:bb:cc:dd:ee:ff",
+            "xid": "0x00001234",
+            "session": "14f31c04",
+            "client_ip": "10.0.0.5",
+            "relay_ip": null,
+            "iface": "eth0",
+            "reply": "offer",
+            "rule": "match[2]",
+            "boot_file": "ipxe.efi",
+            "boot_server": "10.0.0.1",
+        })
+    );
+}
+
+#[test]
+fn test_audit_log() {
+    let dir = std::env::temp_dir().join(format!("po-audit-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("audit.log");
+    let time = OffsetDateTime::now_utc();
+
+    let mut audit_log = AuditLog::open(&path, 1024, 3).unwrap();
+    audit_log.write(&decision(), time).unwrap();
+    drop(audit_log);
+    // Appended to rather than overwritten on start
+    let mut audit_log = AuditLog::open(&path, 1024, 3).unwrap();
+    audit_log.write(&decision(), time).unwrap();
+    let content = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<serde_json::Value> = content
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1]["boot_file"], "ipxe.efi");
+
+    // Rotated once full, the oldest beyond the files kept being removed
+    for _ in 0..30 {
+        audit_log.write(&decision(), time).unwrap();
+    }
+    assert!(std::fs::metadata(&path).unwrap().len() <= 1024);
+    assert!(path.with_extension("log.1").exists());
+    assert!(path.with_extension("log.2").exists());
+    assert!(!path.with_extension("log.3").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_audit_log_conf() {
+    let yaml = "tftp_server_dir: /tftpdir\naudit_log: /var/log/preboot-oxide/audit.log\naudit_log_files: 10\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_ok());
+    assert_eq!(conf.get_audit_log_max_size(), 10 * 1024 * 1024);
+    assert_eq!(conf.get_audit_log_files(), 10);
+
+    let yaml = "tftp_server_dir: /tftpdir\naudit_log: audit.log\naudit_log_max_size: 0\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+}
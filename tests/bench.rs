@@ -0,0 +1,118 @@
+extern crate preboot_oxide;
+
+use std::{
+    net::{Ipv4Addr, UdpSocket},
+    path::PathBuf,
+    time::Duration,
+};
+
+use async_std::task;
+use async_tftp::server::TftpServerBuilder;
+use dhcproto::v4::{DhcpOption, MessageType, OptionCode};
+use preboot_oxide::{
+    bench::{self, BenchSettings, ClientTimings},
+    probe::ProbeAnswer,
+    tftp::{DirHandler, DirHandlerMode},
+};
+
+#[test]
+fn test_bench_downloads() {
+    let root = std::env::temp_dir().join(format!("po-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("ipxe.efi"), vec![7u8; 5000]).unwrap();
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let server_addr = socket.local_addr().unwrap();
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let server = task::block_on(
+        TftpServerBuilder::with_handler(handler)
+            .std_socket(socket)
+            .unwrap()
+            .build(),
+    )
+    .unwrap();
+    let server = task::spawn(server.serve());
+
+    let mut settings = BenchSettings {
+        clients: 4,
+        iface: None,
+        file: Some(PathBuf::from("ipxe.efi")),
+        server: Some(server_addr),
+        timeout: Duration::from_secs(1),
+    };
+    let results = task::block_on(bench::run(&settings)).unwrap();
+    assert_eq!(results.len(), 4);
+    for result in &results {
+        let timings = result.as_ref().unwrap();
+        assert_eq!((timings.dhcp, timings.bytes), (None, 5000));
+    }
+
+    settings.file = Some(PathBuf::from("missing.efi"));
+    let results = task::block_on(bench::run(&settings)).unwrap();
+    let report = bench::report(&results, Duration::from_secs(1));
+    assert!(
+        report.starts_with("Clients: 4, succeeded: 0, failed: 4\n"),
+        "{report}"
+    );
+    assert!(report.contains("\n  4x "), "{report}");
+
+    settings.file = None;
+    assert!(task::block_on(bench::run(&settings)).is_err());
+
+    task::block_on(server.cancel());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_bench_report() {
+    let sorted = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0];
+    assert_eq!(bench::percentile(&sorted, 50.0), 5.0);
+    assert_eq!(bench::percentile(&sorted, 90.0), 9.0);
+    assert_eq!(bench::percentile(&sorted, 99.0), 10.0);
+    assert_eq!(bench::percentile(&[], 50.0), 0.0);
+
+    let client = |dhcp_ms: u64, tftp_ms: u64| {
+        Ok(ClientTimings {
+            dhcp: Some(Duration::from_millis(dhcp_ms)),
+            tftp: Duration::from_millis(tftp_ms),
+            bytes: 1024 * 1024,
+        })
+    };
+    let results = vec![
+        client(100, 1000),
+        client(200, 2000),
+        Err(anyhow::anyhow!("No Offer with a boot file within 5s")),
+    ];
+    assert_eq!(
+        bench::report(&results, Duration::from_secs(2)),
+        "Clients: 3, succeeded: 2, failed: 1\n\
+         DHCP handshake: p50 100.0 ms, p90 200.0 ms, p99 200.0 ms, max 200.0 ms\n\
+         TFTP download: p50 1000.0 ms, p90 2000.0 ms, p99 2000.0 ms, max 2000.0 ms\n\
+         Client throughput: p50 512.0 KiB/s, p90 1024.0 KiB/s, p99 1024.0 KiB/s, max 1024.0 KiB/s\n\
+         Total: 2097152 bytes in 2.0 s, 1024.0 KiB/s\n\
+         Failures:\n  \
+           1x No Offer with a boot file within 5s\n"
+    );
+}
+
+#[test]
+fn test_request_message() {
+    let offer = ProbeAnswer {
+        server: Ipv4Addr::new(10, 0, 0, 2),
+        offered_ip: Some(Ipv4Addr::new(10, 0, 0, 57)),
+        boot_file: Some("ipxe.efi".to_string()),
+        boot_server: None,
+        is_pxe: false,
+        options: vec![],
+    };
+    let request = bench::request_message(&[0x02, 0, 0, 0, 0, 1], 7, &offer);
+    assert_eq!(request.xid(), 7);
+    assert_eq!(request.opts().msg_type(), Some(MessageType::Request));
+    assert_eq!(
+        request.opts().get(OptionCode::RequestedIpAddress),
+        Some(&DhcpOption::RequestedIpAddress(Ipv4Addr::new(10, 0, 0, 57)))
+    );
+    assert_eq!(
+        request.opts().get(OptionCode::ServerIdentifier),
+        Some(&DhcpOption::ServerIdentifier(Ipv4Addr::new(10, 0, 0, 2)))
+    );
+}
@@ -0,0 +1,41 @@
+extern crate preboot_oxide;
+
+use preboot_oxide::{chainload, conf::Conf};
+
+mod utils;
+
+#[test]
+fn test_ipxe_chainload_preset() {
+    let root = std::env::temp_dir().join(format!("po-chainload-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+
+    let yaml = format!(
+        r#"
+tftp_server_dir: {}
+http_port: 8080
+ipxe_chainload: true
+    "#,
+        root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.get_ipxe_chainload());
+    conf.validate().unwrap();
+
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml.replace("http_port: 8080", ""));
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+
+    assert_eq!(chainload::boot_file(None, false), "images/ipxe/undionly.kpxe");
+    assert_eq!(chainload::boot_file(Some(7), false), "images/ipxe/ipxe.efi");
+    assert_eq!(chainload::boot_file(Some(0x0b), false), "images/ipxe/ipxe-arm64.efi");
+    assert_eq!(chainload::boot_file(Some(7), true), chainload::SCRIPT);
+    assert_eq!(chainload::images().len(), 3);
+
+    chainload::write_script(&root, 8080).unwrap();
+    let script = std::fs::read_to_string(root.join(chainload::SCRIPT)).unwrap();
+    assert!(script.starts_with("#!ipxe\n"));
+    assert!(script.contains("chain http://${next-server}:8080/boot.ipxe?mac=${net0/mac}\n"));
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,242 @@
+extern crate preboot_oxide;
+
+use std::time::Duration;
+
+use clap::Parser;
+use preboot_oxide::{
+    cli::{self, Cli, Command, Generated},
+    conf::Conf,
+    daemon::{self, DaemonSettings},
+    status::{self, Health, ServiceHealth},
+    tracker::{Counts, IfaceCounts},
+};
+
+mod utils;
+
+#[test]
+fn test_subcommands() {
+    let parse = |args: &[&str]| Cli::try_parse_from([&["preboot-oxide"], args].concat());
+    let cli = parse(&["-vv", "--set", "ifaces=[eth0]"]).unwrap();
+    assert_eq!(cli.command(), Command::Serve);
+    assert_eq!(cli.log_level().as_deref(), Some("info"));
+    assert_eq!(cli.set, vec!["ifaces=[eth0]".to_string()]);
+
+    // The global options may come after the subcommand
+    let cli = parse(&["test", "-v", "--set", "default.boot_file=ipxe.efi"]).unwrap();
+    assert_eq!(cli.command(), Command::Test);
+    assert_eq!(cli.log_level().as_deref(), Some("warn"));
+    assert_eq!(parse(&["status"]).unwrap().log_level(), None);
+    assert_eq!(parse(&["-vvvvvv"]).unwrap().log_level().as_deref(), Some("trace"));
+    assert_eq!(parse(&["status"]).unwrap().command(), Command::Status);
+    assert_eq!(parse(&["serve"]).unwrap().command(), Command::Serve);
+    assert!(parse(&["restart"]).is_err());
+}
+
+#[test]
+fn test_stats_since() {
+    let parse = |args: &[&str]| Cli::try_parse_from([&["preboot-oxide"], args].concat());
+    let since = |args: &[&str]| match parse(args).unwrap().command() {
+        Command::Stats { since } => since,
+        command => panic!("{command:?}"),
+    };
+    assert_eq!(since(&["stats"]), None);
+    assert_eq!(since(&["stats", "--since", "12h"]), Some(Duration::from_secs(12 * 3600)));
+    assert_eq!(since(&["stats", "--since", "90"]), Some(Duration::from_secs(90)));
+    assert!(parse(&["stats", "--since", "2w"]).is_err());
+    assert!(parse(&["stats", "--since", "h"]).is_err());
+}
+
+#[test]
+fn test_assets_csv() {
+    let parse = |args: &[&str]| Cli::try_parse_from([&["preboot-oxide"], args].concat());
+    let csv = |args: &[&str]| match parse(args).unwrap().command() {
+        Command::Assets { csv } => csv,
+        command => panic!("{command:?}"),
+    };
+    assert!(!csv(&["assets"]));
+    assert!(csv(&["assets", "--csv"]));
+}
+
+#[test]
+fn test_instance_name() {
+    let parse = |args: &[&str]| Cli::try_parse_from([&["preboot-oxide"], args].concat());
+    let cli = parse(&["status", "--instance-name", "lab-2"]).unwrap();
+    assert_eq!(cli.instance_name.as_deref(), Some("lab-2"));
+    assert_eq!(parse(&[]).unwrap().instance_name, None);
+    // Used in paths
+    for invalid in ["../lab", "", "lab.2", "lab 2", "lab/2", "läb", "lab\n"] {
+        assert!(parse(&["--instance-name", invalid]).is_err(), "{invalid:?} accepted");
+    }
+    assert!(parse(&["--instance-name", "Lab_2-b"]).is_ok());
+
+    let yaml_mock = utils::YamlMockFile::from_yaml("default:\n    boot_file: ipxe.efi\n");
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert_eq!(conf.get_control_socket(), std::path::Path::new("/run/preboot-oxide.sock"));
+    let invalid = conf.clone().with_instance_name(Some("../lab".to_string()));
+    assert!(invalid.validate().unwrap_err().to_string().contains("instance name"));
+    let conf = conf.with_instance_name(cli.instance_name);
+    assert_eq!(conf.get_control_socket(), std::path::Path::new("/run/preboot-oxide-lab-2.sock"));
+    let report = status::report(&conf, "test", false);
+    assert!(report.starts_with("preboot-oxide instance lab-2 is not running.\n"));
+}
+
+#[test]
+fn test_container() {
+    let parse = |args: &[&str]| Cli::try_parse_from([&["preboot-oxide"], args].concat());
+    assert!(!parse(&[]).unwrap().container);
+    let cli = parse(&["serve", "--container"]).unwrap();
+    assert!(cli.container);
+    assert_eq!(cli.command(), Command::Serve);
+    // Supervised by the container runtime
+    assert!(parse(&["--container", "--daemon"]).is_err());
+}
+
+#[test]
+fn test_generate() {
+    let parse = |args: &[&str]| Cli::try_parse_from([&["preboot-oxide"], args].concat());
+    assert_eq!(
+        parse(&["generate", "fish"]).unwrap().command(),
+        Command::Generate { what: Generated::Fish }
+    );
+    assert!(parse(&["generate", "powershell"]).is_err());
+
+    let generated = |what| {
+        let mut out = Vec::new();
+        cli::generate(what, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    let bash = generated(Generated::Bash);
+    assert!(bash.contains("complete -F _preboot-oxide"));
+    // The subcommands and their options
+    assert!(bash.contains("which-config"));
+    assert!(bash.contains("--instance-name"));
+    assert!(generated(Generated::Zsh).starts_with("#compdef preboot-oxide"));
+    assert!(generated(Generated::Fish).contains("complete -c preboot-oxide"));
+    let man = generated(Generated::Man);
+    assert!(man.contains(".TH preboot-oxide 1"));
+    assert!(man.contains("self\\-test"));
+}
+
+#[test]
+fn test_daemon_options() {
+    let parse = |args: &[&str]| Cli::try_parse_from([&["preboot-oxide"], args].concat());
+    let cli = parse(&["--daemon", "--pid-file", "/run/preboot-oxide.pid"]).unwrap();
+    assert_eq!(cli.command(), Command::Serve);
+    assert_eq!(
+        cli.daemon_settings(),
+        DaemonSettings {
+            detach: true,
+            pid_file: Some("/run/preboot-oxide.pid".into()),
+            log_file: None,
+        }
+    );
+    let cli = parse(&["serve", "--daemon", "--log-file", "/var/log/preboot-oxide.log"]).unwrap();
+    assert_eq!(cli.daemon_settings().log_file, Some("/var/log/preboot-oxide.log".into()));
+    // The output is only redirected once detached, or by the Windows service
+    #[cfg(unix)]
+    assert!(parse(&["--log-file", "/var/log/preboot-oxide.log"]).is_err());
+    assert_eq!(parse(&[]).unwrap().daemon_settings(), DaemonSettings::default());
+
+    let pid_file = utils::MockFile::from_bytes(b"1\n", "pid");
+    daemon::write_pid_file(&pid_file.path).unwrap();
+    let pid = std::fs::read_to_string(&pid_file.path).unwrap();
+    assert_eq!(pid, format!("{}\n", std::process::id()));
+}
+
+#[test]
+fn test_status_report() {
+    let yaml = r#"
+ifaces: [eth0]
+tftp_server_dir: /srv/tftp
+http_port: 8080
+pxe_boot_server: true
+default:
+    boot_file: ipxe.efi
+tenants:
+    lab:
+        ifaces: [eth1]
+        tftp_server_dir: /srv/lab
+    "#;
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert_eq!(
+        status::report(&conf, "/etc/preboot-oxide.yaml", false),
+        "preboot-oxide is not running.\n\
+         Configuration: /etc/preboot-oxide.yaml\n\
+         DHCP: on eth0\n\
+         PXE boot server: port 4011\n\
+         TFTP: /srv/tftp, port 69\n\
+         HTTP: port 8080\n\
+         Tenant lab:\n  \
+           DHCP: on eth1\n  \
+           PXE boot server: port 4011\n  \
+           TFTP: /srv/lab, port 69\n  \
+           HTTP: port 8080\n"
+    );
+
+    let yaml = "dhcp:\n    enabled: false\ntftp:\n    enabled: false\ntftp_server_dir: /srv/tftp\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    let report = status::report(&conf, "--set", true);
+    assert!(report.starts_with("preboot-oxide is running.\nConfiguration: --set\n"), "{report}");
+    assert!(report.ends_with("DHCP: disabled\nTFTP: disabled\n"), "{report}");
+}
+
+#[test]
+fn test_health_report() {
+    let service = ServiceHealth {
+        tenant: None,
+        dhcp_ifaces: Some(vec!["eth0".to_string(), "eth1".to_string()]),
+        tftp_dir: Some("/srv/tftp".to_string()),
+    };
+    let tenant = ServiceHealth {
+        tenant: Some("lab".to_string()),
+        dhcp_ifaces: None,
+        tftp_dir: None,
+    };
+    let health = Health {
+        version: "1.5.12".to_string(),
+        uptime_secs: 2 * 3600 + 5 * 60,
+        configuration: "/etc/preboot-oxide.yaml".to_string(),
+        counts: Counts { offers: 12, acks: 10, transfers: 9 },
+        ifaces: vec![
+            IfaceCounts {
+                iface: "eth0".to_string(),
+                received: 43,
+                ignored: 18,
+                answered: 22,
+                dropped: 3,
+                transfers: 9,
+                ..Default::default()
+            },
+            IfaceCounts {
+                iface: "eth1".to_string(),
+                received: 4,
+                ignored: 0,
+                answered: 0,
+                transfers: 0,
+                unoffered: 4,
+                upstream_missing: true,
+                ..Default::default()
+            },
+        ],
+        services: vec![service, tenant],
+    };
+    assert_eq!(
+        status::health_report(&health),
+        "Running server:\n  \
+           Version: 1.5.12\n  \
+           Uptime: 2h 5m 0s\n  \
+           Configuration: /etc/preboot-oxide.yaml\n  \
+           Offers: 12, acknowledgements: 10, transfers: 9\n  \
+           Interface eth0: received 43, ignored 18, answered 22, transfers 9\n    \
+             Dropped 3 DHCP messages, too many being answered at once\n  \
+           Interface eth1: received 4, ignored 0, answered 0, transfers 0\n    \
+             No DHCP server offering, the last 4 sessions expired waiting for it\n  \
+           DHCP: on eth0, eth1\n  \
+           TFTP: /srv/tftp\n  \
+           Tenant lab:\n    \
+             DHCP: disabled\n    \
+             TFTP: not started\n"
+    );
+}
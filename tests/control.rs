@@ -0,0 +1,136 @@
+extern crate preboot_oxide;
+
+use std::{path::Path, sync::Arc, time::SystemTime};
+
+use async_std::task;
+use futures::StreamExt;
+use preboot_oxide::{
+    conf::Conf,
+    control::{self, Control, ControlledService},
+    dhcp::{SessionInfo, SessionMap},
+    reload,
+    assets,
+    stats::{self, Outcome},
+    status::{self, ServiceHealth},
+    tracker::{BootTracker, Counts},
+};
+
+mod utils;
+
+#[test]
+fn test_control_socket() {
+    let socket = std::env::temp_dir().join(format!("preboot-oxide-{}.sock", std::process::id()));
+    let yaml = format!(
+        "control_socket: {}\ndefault:\n    boot_file: ipxe.efi\n",
+        socket.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert_eq!(conf.get_control_socket(), socket);
+
+    let tracker = Arc::new(BootTracker::new(10));
+    let (reloader, mut reload_requests) = reload::reloader();
+    let control = Control {
+        started: SystemTime::now(),
+        origin: "--set".to_string(),
+        tracker: Arc::clone(&tracker),
+        services: vec![ControlledService {
+            tenant: None,
+            shared_conf: Arc::new(std::sync::RwLock::new(Arc::new(conf.clone()))),
+            sessions: Some(Arc::new(SessionMap::new(&conf))),
+        }],
+        reloader,
+        handover: Default::default(),
+    };
+    control::spawn_control_service_async(&conf, Arc::new(control)).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let private_dir = socket.with_file_name(format!(
+            ".{}.{}",
+            socket.file_name().unwrap().to_string_lossy(),
+            std::process::id()
+        ));
+        assert!(!private_dir.exists());
+    }
+
+    let answer = control::request(&socket, "sessions").unwrap();
+    assert_eq!(answer, serde_json::json!([]));
+
+    tracker.offer_sent("52:54:00:12:34:56", Some("ipxe.efi"));
+    tracker.file_delivered("10.0.0.5".parse().unwrap(), Path::new("ipxe.efi"));
+    let health = status::ask(&socket).unwrap();
+    assert_eq!(health.configuration, "--set");
+    assert_eq!(health.counts, Counts { offers: 1, acks: 0, transfers: 1 });
+    let stats = stats::ask(&socket).unwrap();
+    assert_eq!(stats.clients["52:54:00:12:34:56"].last.outcome, Outcome::Offered);
+    let assets = assets::ask(&socket).unwrap();
+    assert_eq!(assets[0].last_boot_file.as_deref(), Some("ipxe.efi"));
+    // Not listening yet, without its server loop
+    let service = ServiceHealth { tenant: None, dhcp_ifaces: Some(vec![]), tftp_dir: None };
+    assert_eq!(health.services, vec![service]);
+
+    let error = control::request(&socket, "restart").unwrap_err();
+    assert_eq!(error.to_string(), "Unknown command restart");
+
+    // Answered with the outcome of the reload, the first one rejected
+    task::spawn(async move {
+        let mut rejected = true;
+        while let Some(answer) = reload_requests.next().await {
+            let _ = answer.send(match rejected {
+                true => Err(anyhow::anyhow!("Invalid configuration: Unknown key bogus")),
+                false => Ok(()),
+            });
+            rejected = false;
+        }
+    });
+    let error = control::request(&socket, "reload").unwrap_err();
+    assert_eq!(error.to_string(), "Invalid configuration: Unknown key bogus");
+    let answer = control::request(&socket, "reload").unwrap();
+    assert_eq!(answer, serde_json::json!({ "reloaded": true }));
+
+    std::fs::remove_file(&socket).unwrap();
+    assert!(control::request(&socket, "sessions").is_err());
+}
+
+#[test]
+fn test_sessions_report() {
+    let session = SessionInfo {
+        tenant: None,
+        xid: 0x1a2b3c,
+        mac_address: "52:54:00:12:34:56".to_string(),
+        stage: "offer".to_string(),
+        age_secs: 12,
+        rule: Some("match[0]".to_string()),
+        iface: "eth0".to_string(),
+        client_ip: Some("10.0.0.5".parse().unwrap()),
+    };
+    let waiting = SessionInfo {
+        xid: 0xff,
+        mac_address: "52:54:00:12:34:57".to_string(),
+        stage: "discover".to_string(),
+        age_secs: 3,
+        rule: None,
+        client_ip: None,
+        ..session.clone()
+    };
+    assert_eq!(
+        control::sessions_report(&[session.clone(), waiting]),
+        "XID         MAC                STAGE     AGE  RULE      INTERFACE  IP\n\
+         0x001a2b3c  52:54:00:12:34:56  offer     12s  match[0]  eth0       10.0.0.5\n\
+         0x000000ff  52:54:00:12:34:57  discover  3s   -         eth0       -\n"
+    );
+
+    // The tenants of the sessions, when there are some
+    let session = SessionInfo {
+        tenant: Some("lab".to_string()),
+        ..session
+    };
+    assert!(control::sessions_report(&[session]).ends_with("eth0       10.0.0.5  lab\n"));
+    assert_eq!(
+        control::sessions_report(&[]),
+        "No DHCP sessions in progress.\n"
+    );
+}
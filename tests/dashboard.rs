@@ -0,0 +1,90 @@
+extern crate preboot_oxide;
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    sync::Arc,
+    time::SystemTime,
+};
+
+use async_std::task;
+use preboot_oxide::{
+    conf::Conf,
+    control::{Control, ControlledService},
+    dashboard,
+    dhcp::SessionMap,
+    reload,
+    tracker::BootTracker,
+};
+
+mod utils;
+
+fn get(port: u16, request: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_dashboard() {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let yaml = format!("dashboard_addr: 127.0.0.1:{port}\ndefault:\n    boot_file: ipxe.efi\n");
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+
+    let tracker = Arc::new(BootTracker::new(10));
+    tracker.boot_info_sent("10.0.0.5".parse().unwrap(), "52:54:00:12:34:56", 42, "<ipxe>.efi", "eth0");
+    let transfer =
+        BootTracker::transfer_started(&tracker, "10.0.0.5".parse().unwrap(), Path::new("ipxe.efi"), Some(1024));
+    transfer.transferred(512);
+    let control = Arc::new(Control {
+        started: SystemTime::now(),
+        origin: "--set".to_string(),
+        tracker: Arc::clone(&tracker),
+        services: vec![ControlledService {
+            tenant: None,
+            shared_conf: Arc::new(std::sync::RwLock::new(Arc::new(conf.clone()))),
+            sessions: Some(Arc::new(SessionMap::new(&conf))),
+        }],
+        reloader: reload::reloader().0,
+        handover: Default::default(),
+    });
+
+    let state = task::block_on(dashboard::state(&control));
+    assert_eq!(state.recent_boots.len(), 1);
+    assert_eq!(state.transfers[0].bytes, 512);
+    assert_eq!(state.services[0].lines[0], "DHCP: on all interfaces");
+    dashboard::spawn_dashboard_async(&conf, control).unwrap();
+
+    let page = get(port, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(page.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(page.contains("<td>52:54:00:12:34:56</td>"));
+    assert!(page.contains("<td>&lt;ipxe&gt;.efi</td>"));
+    assert!(page.contains("<td>512 of 1024 bytes</td>"));
+
+    let json = get(port, "GET /state.json HTTP/1.1\r\nConnection: close\r\n\r\n");
+    let body = json.split_once("\r\n\r\n").unwrap().1;
+    let state: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(state["recent_boots"][0]["xid"], 42);
+    assert_eq!(state["transfers"][0]["path"], "ipxe.efi");
+
+    drop(transfer);
+    let json = get(port, "GET /state.json HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(json.contains("\"transfers\": []"));
+    let post = get(port, "POST / HTTP/1.1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    assert!(post.starts_with("HTTP/1.1 405 "));
+    assert!(get(port, "GET /x HTTP/1.1\r\nConnection: close\r\n\r\n").starts_with("HTTP/1.1 404 "));
+}
+
+#[test]
+fn test_dashboard_addr_conf() {
+    let yaml = "dashboard_addr: 8067\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    assert!(Conf::from_config_file(Some(&yaml_mock.path)).is_err());
+    let yaml = "tftp_server_dir: /tftpdir\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    assert_eq!(Conf::from_config_file(Some(&yaml_mock.path)).unwrap().get_dashboard_addr(), None);
+}
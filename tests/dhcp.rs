@@ -0,0 +1,285 @@
+extern crate preboot_oxide;
+
+use std::{
+    net::Ipv4Addr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use async_std::task;
+use network_interface::NetworkInterface;
+use preboot_oxide::{
+    conf::{Conf, ConfFormat},
+    dhcp::{self, ConfigProvider, DhcpService, Handlers, Session, SessionKey, SessionMap},
+    status::{self, Health},
+    tracker::BootTracker,
+};
+
+mod utils;
+
+/// Held by the tests binding the sockets of `lo`, the messages sent to
+/// them being spread among all those of a port.
+#[cfg(target_os = "linux")]
+static LO: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[test]
+fn test_bind_retry_delay() {
+    assert_eq!(dhcp::bind_retry_delay(0), Duration::from_secs(1));
+    assert_eq!(dhcp::bind_retry_delay(1), Duration::from_secs(2));
+    assert_eq!(dhcp::bind_retry_delay(4), Duration::from_secs(16));
+    assert_eq!(dhcp::bind_retry_delay(5), Duration::from_secs(30));
+    assert_eq!(dhcp::bind_retry_delay(u32::MAX), Duration::from_secs(30));
+}
+
+#[test]
+fn test_interfaces_coming_up() {
+    let yaml_mock = utils::YamlMockFile::from_yaml(
+        "ifaces: [po-missing0]\ndefault:\n    boot_file: ipxe.efi\n",
+    );
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    // Waited for rather than failing
+    let interfaces = dhcp::bind_interfaces(&conf).unwrap();
+    assert!(interfaces.names().is_empty());
+    assert!(interfaces.get("po-missing0").is_none());
+
+    let no_address = NetworkInterface {
+        name: "po-missing0".to_string(),
+        addr: Vec::new(),
+        mac_addr: None,
+        index: 7,
+    };
+    let changes = dhcp::interface_changes(&conf, &interfaces, vec![no_address]);
+    assert!(changes.is_empty());
+
+    // Bound once up, the other interfaces not being served
+    let up = NetworkInterface::new_afinet("po-missing0", [10, 0, 0, 2].into(), None, None, 7);
+    let other = NetworkInterface::new_afinet("po-other0", [10, 0, 1, 2].into(), None, None, 8);
+    let changes = dhcp::interface_changes(&conf, &interfaces, vec![up.clone(), other]);
+    assert_eq!(changes.added, vec![up]);
+    assert!(changes.removed.is_empty());
+}
+
+#[test]
+fn test_config_providers() {
+    let conf = |boot_file: &str| {
+        let yaml = format!("default:\n    boot_file: {boot_file}\n");
+        let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+        Arc::new(Conf::from_config_file(Some(&yaml_mock.path)).unwrap())
+    };
+    let boot_file = |provider: &dyn ConfigProvider| {
+        let conf = provider.conf();
+        let entry = conf.get_from_doc(serde_json::Value::default()).unwrap().unwrap();
+        entry.boot_file.cloned()
+    };
+
+    let fixed = conf("ipxe.efi");
+    assert_eq!(boot_file(&fixed).as_deref(), Some("ipxe.efi"));
+
+    // The configuration reloaded applies to the messages received next
+    let reloaded = RwLock::new(conf("ipxe.efi"));
+    *reloaded.write().unwrap() = conf("snponly.efi");
+    assert_eq!(boot_file(&reloaded).as_deref(), Some("snponly.efi"));
+}
+
+#[test]
+fn test_dhcp_service_embedded() {
+    let yaml = "ifaces: [po-missing0]\ndefault:\n    boot_file: ipxe.efi\n    boot_server_ipv4: 10.0.0.2\n";
+    let conf = Conf::from_text(yaml, ConfFormat::Yaml).unwrap();
+    conf.validate().unwrap();
+    let tracker = Arc::new(BootTracker::new(conf.get_max_sessions()));
+    let sessions = Arc::new(SessionMap::new(&conf));
+    let interfaces = dhcp::bind_interfaces(&conf).unwrap();
+    task::block_on(async {
+        let service = DhcpService::start(Arc::new(Arc::new(conf)), interfaces, tracker, sessions);
+        assert!(service.interfaces().is_empty());
+        assert!(service.sessions().list(SystemTime::now()).is_empty());
+        service.stop().await;
+    });
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_boot_server_workers_share_port_4011() {
+    let yaml = "ifaces: [po-missing0]\npxe_boot_server: true\npxe_boot_server_workers: 0\n";
+    let conf = Conf::from_text(yaml, ConfFormat::Yaml).unwrap();
+    assert!(conf.validate().is_err());
+    // Binding to a device takes CAP_NET_RAW
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+
+    let _lo = LO.lock().unwrap_or_else(|e| e.into_inner());
+    let yaml = "ifaces: [lo]\npxe_boot_server: true\npxe_boot_server_workers: 4\n";
+    let conf = Conf::from_text(yaml, ConfFormat::Yaml).unwrap();
+    let interfaces = dhcp::bind_interfaces(&conf).unwrap();
+    // Indexed by name as bound
+    assert_eq!(interfaces.names(), vec!["lo".to_string()]);
+    assert_eq!(interfaces.bound().count(), 1);
+    let lo = interfaces.get("lo").unwrap();
+    assert_eq!(lo.boot_servers.len(), 4);
+
+    // The requests of the clients are spread among the workers
+    for _ in 0..64 {
+        let client = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        client.send_to(b"request", (Ipv4Addr::LOCALHOST, dhcp::PXE_BOOT_SERVER_PORT)).unwrap();
+    }
+    let received = |socket: &async_std::net::UdpSocket| {
+        let mut count = 0;
+        let mut buf = [0; 16];
+        while task::block_on(async_std::io::timeout(Duration::from_millis(100), socket.recv_from(&mut buf))).is_ok() {
+            count += 1;
+        }
+        count
+    };
+    let counts: Vec<usize> = lo.boot_servers.iter().map(received).collect();
+    assert_eq!(counts.iter().sum::<usize>(), 64);
+    assert!(counts.iter().all(|count| *count > 0), "{counts:?}");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_queued_messages_are_all_received() {
+    // Binding to a device takes CAP_NET_RAW
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+    let _lo = LO.lock().unwrap_or_else(|e| e.into_inner());
+    let yaml = "ifaces: [lo]\npxe_boot_server: true\ndefault:\n    boot_file: ipxe.efi\n";
+    let conf = Conf::from_text(yaml, ConfFormat::Yaml).unwrap();
+    let tracker = Arc::new(BootTracker::new(conf.get_max_sessions()));
+    let sessions = Arc::new(SessionMap::new(&conf));
+    let interfaces = dhcp::bind_interfaces(&conf).unwrap();
+
+    // Queued before the receivers start, read several batches at a time
+    let client = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    for _ in 0..100 {
+        client.send_to(b"not dhcp", (Ipv4Addr::LOCALHOST, dhcp::PXE_BOOT_SERVER_PORT)).unwrap();
+    }
+    let received = |tracker: &BootTracker| {
+        let counts = tracker.iface_counts();
+        counts.iter().find(|counts| counts.iface == "lo").map_or(0, |counts| counts.received)
+    };
+    task::block_on(async {
+        let provider = Arc::new(Arc::new(conf));
+        let service = DhcpService::start(provider, interfaces, Arc::clone(&tracker), sessions);
+        for _ in 0..50 {
+            if received(&tracker) >= 100 {
+                break;
+            }
+            task::sleep(Duration::from_millis(100)).await;
+        }
+        service.stop().await;
+    });
+    assert_eq!(received(&tracker), 100);
+}
+
+fn session(xid: u32) -> Session {
+    let mac = [0x52, 0x54, 0, 0, (xid >> 8) as u8, xid as u8];
+    Session::new(mac, "eth0".to_string(), Ipv4Addr::new(10, 0, 0, 1))
+}
+
+fn key(xid: u32) -> SessionKey {
+    SessionKey::of(xid, &session(xid))
+}
+
+#[test]
+fn test_sessions_from_many_tasks() {
+    let conf = Conf::from_text("max_sessions: 10000\n", ConfFormat::Yaml).unwrap();
+    let sessions = Arc::new(SessionMap::new(&conf));
+
+    // Consecutive XIDs, spread over all the shards
+    let tasks: Vec<_> = (0..16u32)
+        .map(|task| {
+            let sessions = Arc::clone(&sessions);
+            std::thread::spawn(move || {
+                for xid in task * 100..(task + 1) * 100 {
+                    sessions.insert(key(xid), session(xid)).unwrap();
+                }
+                for xid in (task * 100..(task + 1) * 100).step_by(2) {
+                    assert!(sessions.remove(&key(xid)).is_some());
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.join().unwrap();
+    }
+
+    let listed = sessions.list(SystemTime::now());
+    assert_eq!(listed.len(), 800);
+    assert!(listed.iter().all(|session| session.xid % 2 == 1));
+}
+
+#[test]
+fn test_max_sessions_across_shards() {
+    let conf = Conf::from_text("max_sessions: 5\n", ConfFormat::Yaml).unwrap();
+    let sessions = SessionMap::new(&conf);
+
+    // More sessions than the maximum, on different shards, the oldest evicted
+    for xid in 0..20 {
+        sessions.insert(key(xid), session(xid)).unwrap();
+    }
+    let mut listed: Vec<u32> = sessions
+        .list(SystemTime::now())
+        .into_iter()
+        .map(|session| session.xid)
+        .collect();
+    listed.sort();
+    assert_eq!(listed, vec![15, 16, 17, 18, 19]);
+
+    let conf = Conf::from_text("max_sessions: 0\n", ConfFormat::Yaml).unwrap();
+    let sessions = SessionMap::new(&conf);
+    assert!(sessions.insert(key(1), session(1)).is_err());
+}
+
+#[test]
+fn test_sessions_of_one_xid_on_different_interfaces() {
+    let conf = Conf::from_text("max_sessions: 10\n", ConfFormat::Yaml).unwrap();
+    let sessions = SessionMap::new(&conf);
+    let mac = [0x52, 0x54, 0, 0, 0, 1];
+    let on = |iface: &str| Session::new(mac, iface.to_string(), Ipv4Addr::new(10, 0, 0, 1));
+
+    // Kept apart rather than the last one replacing the first
+    sessions.insert(SessionKey::of(7, &on("eth0")), on("eth0")).unwrap();
+    sessions.insert(SessionKey::of(7, &on("eth1")), on("eth1")).unwrap();
+    let mut ifaces: Vec<String> = sessions
+        .list(SystemTime::now())
+        .into_iter()
+        .map(|session| session.iface)
+        .collect();
+    ifaces.sort();
+    assert_eq!(ifaces, ["eth0", "eth1"]);
+
+    assert!(sessions.remove(&SessionKey::of(7, &on("eth1"))).is_some());
+    assert_eq!(sessions.list(SystemTime::now()).len(), 1);
+    assert!(sessions.expire(7));
+    assert!(sessions.list(SystemTime::now()).is_empty());
+}
+
+#[test]
+fn test_messages_over_max_handlers_are_dropped() {
+    let conf = Conf::from_text("dhcp_max_handlers: 2\n", ConfFormat::Yaml).unwrap();
+    let max = conf.get_dhcp_max_handlers();
+    let tracker = BootTracker::new(10);
+    let handlers = Handlers::default();
+
+    let slots: Vec<_> = (0..2).map(|_| handlers.admit(max, "eth0", &tracker).unwrap()).collect();
+    assert!(handlers.admit(max, "eth0", &tracker).is_none());
+    let health = Health {
+        version: "1.5.12".to_string(),
+        uptime_secs: 0,
+        configuration: "--set".to_string(),
+        counts: tracker.counts(),
+        ifaces: tracker.iface_counts(),
+        services: Vec::new(),
+    };
+    let report = status::health_report(&health);
+    assert!(report.contains("  Interface eth0: received 1, ignored 0, answered 0, transfers 0\n"), "{report}");
+    assert!(report.contains("    Dropped 1 DHCP messages, too many being answered at once\n"), "{report}");
+
+    // Answered messages give their slot back
+    drop(slots);
+    assert_eq!(handlers.active(), 0);
+    assert!(handlers.admit(max, "eth0", &tracker).is_some());
+    assert_eq!(tracker.iface_counts()[0].dropped, 1);
+}
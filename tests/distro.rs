@@ -0,0 +1,199 @@
+extern crate preboot_oxide;
+
+use async_std::task;
+use preboot_oxide::{
+    conf::Conf,
+    distro::BootEntry,
+    http::HttpService,
+    iso::SECTOR_SIZE,
+    tftp::{DirHandler, DirHandlerMode, SharedDirHandler},
+};
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    path::Path,
+    time::Duration,
+};
+
+mod utils;
+
+const SECTOR: usize = SECTOR_SIZE as usize;
+
+fn dir_record(extent: u32, size: u32, is_dir: bool, name: &[u8]) -> Vec<u8> {
+    let padding = 1 - name.len() % 2;
+    let mut record = vec![0u8; 33];
+    record[0] = (33 + name.len() + padding) as u8;
+    record[2..6].copy_from_slice(&extent.to_le_bytes());
+    record[10..14].copy_from_slice(&size.to_le_bytes());
+    record[25] = if is_dir { 0x02 } else { 0 };
+    record[32] = name.len() as u8;
+    record.extend_from_slice(name);
+    record.extend(std::iter::repeat_n(0, padding));
+    record
+}
+
+/// Builds an image laid out like the Ubuntu ISOs, `CASPER/VMLINUZ.;1` and
+/// `CASPER/INITRD.;1`.
+fn build_ubuntu_image() -> Vec<u8> {
+    let mut image = vec![0u8; SECTOR * 22];
+    let root = dir_record(18, SECTOR as u32, true, &[0]);
+    image[16 * SECTOR] = 1;
+    image[16 * SECTOR + 1..16 * SECTOR + 6].copy_from_slice(b"CD001");
+    image[16 * SECTOR + 156..16 * SECTOR + 156 + root.len()].copy_from_slice(&root);
+    image[17 * SECTOR] = 255;
+    image[17 * SECTOR + 1..17 * SECTOR + 6].copy_from_slice(b"CD001");
+
+    let root_entries = [
+        dir_record(18, SECTOR as u32, true, &[0]),
+        dir_record(18, SECTOR as u32, true, &[1]),
+        dir_record(19, SECTOR as u32, true, b"CASPER"),
+    ]
+    .concat();
+    image[18 * SECTOR..18 * SECTOR + root_entries.len()].copy_from_slice(&root_entries);
+
+    let casper_entries = [
+        dir_record(19, SECTOR as u32, true, &[0]),
+        dir_record(18, SECTOR as u32, true, &[1]),
+        dir_record(20, 6, false, b"VMLINUZ.;1"),
+        dir_record(21, 6, false, b"INITRD.;1"),
+    ]
+    .concat();
+    image[19 * SECTOR..19 * SECTOR + casper_entries.len()].copy_from_slice(&casper_entries);
+    image[20 * SECTOR..20 * SECTOR + 6].copy_from_slice(b"kernel");
+    image[21 * SECTOR..21 * SECTOR + 6].copy_from_slice(b"initrd");
+
+    image
+}
+
+fn get(port: u16, request: &str) -> String {
+    let mut stream = (0..50)
+        .find_map(|_| {
+            TcpStream::connect(("127.0.0.1", port))
+                .inspect_err(|_| std::thread::sleep(Duration::from_millis(50)))
+                .ok()
+        })
+        .unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_iso_boot_entries_are_generated() {
+    let root = std::env::temp_dir().join(format!("po-distro-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("images/ubuntu")).unwrap();
+    std::fs::write(root.join("images/ubuntu/noble.iso"), build_ubuntu_image()).unwrap();
+
+    let entry = BootEntry::detect(&root, Path::new("ubuntu/noble.iso"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(entry.distro, "Ubuntu");
+    assert_eq!(entry.kernel, "casper/vmlinuz");
+    assert_eq!(entry.initrd, "casper/initrd");
+
+    let port = 60000 + (std::process::id() % 5000) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: {}
+ifaces: [lo]
+http_port: {port}
+default:
+    boot_file: /ipxe.efi
+match:
+    - select:
+        ClientMacAddress: 52:54:00:12:34:56
+      conf:
+        boot_iso: ubuntu/noble.iso
+        vars:
+          kernel_args: autoinstall
+    "#,
+        root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+
+    let iso_url = format!("http://127.0.0.1:{port}/images/ubuntu/noble.iso");
+    let response = get(port, "GET /boot.ipxe?mac=52:54:00:12:34:56 HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(&format!(
+        "\r\n\r\n#!ipxe\n\
+         echo Booting Ubuntu from ubuntu/noble.iso\n\
+         kernel {iso_url}/casper/vmlinuz initrd=initrd ip=dhcp url={iso_url} cloud-config-url=/dev/null autoinstall\n\
+         initrd {iso_url}/casper/initrd\n\
+         boot\n"
+    )));
+
+    let response = get(port, "GET /grub.cfg?mac=52:54:00:12:34:56 HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(&format!(
+        "\r\n\r\nset timeout=0\n\
+         menuentry 'Boot Ubuntu from ubuntu/noble.iso' {{\n\
+         \x20   linux (http,127.0.0.1:{port})/images/ubuntu/noble.iso/casper/vmlinuz ip=dhcp url={iso_url} cloud-config-url=/dev/null autoinstall\n\
+         \x20   initrd (http,127.0.0.1:{port})/images/ubuntu/noble.iso/casper/initrd\n\
+         }}\n"
+    )));
+
+    // The kernel and initrd are read from within the image
+    let response = get(
+        port,
+        "GET /images/ubuntu/noble.iso/casper/vmlinuz HTTP/1.0\r\n\r\n",
+    );
+    assert!(response.ends_with("\r\n\r\nkernel"));
+
+    task::block_on(service.stop());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_windows_media_is_booted_with_wimboot() {
+    let root = std::env::temp_dir().join(format!("po-windows-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("win11/Boot")).unwrap();
+    std::fs::write(root.join("win11/Boot/BCD"), b"bcd").unwrap();
+
+    let port = 60001 + (std::process::id() % 5000) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: {}
+ifaces: [lo]
+http_port: {port}
+default:
+    boot_file: /ipxe.efi
+    windows: win11
+    "#,
+        root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.has_windows_profile());
+
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+
+    let base_url = format!("http://127.0.0.1:{port}");
+    let response = get(port, "GET /boot.ipxe?mac=52:54:00:12:34:56 HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(&format!(
+        "\r\n\r\n#!ipxe\n\
+         echo Booting Windows from win11\n\
+         kernel {base_url}/images/wimboot \n\
+         iseq ${{platform}} efi && set bcd efi/microsoft/boot/bcd || set bcd boot/bcd\n\
+         initrd {base_url}/win11/${{bcd}} BCD\n\
+         initrd {base_url}/win11/boot/boot.sdi boot.sdi\n\
+         initrd {base_url}/win11/sources/boot.wim boot.wim\n\
+         boot\n"
+    )));
+
+    // Windows paths are looked up ignoring their case
+    let response = get(port, "GET /win11/boot/bcd HTTP/1.0\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nbcd"));
+
+    task::block_on(service.stop());
+    std::fs::remove_dir_all(&root).unwrap();
+}
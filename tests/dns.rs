@@ -0,0 +1,168 @@
+extern crate preboot_oxide;
+
+use async_std::task;
+use preboot_oxide::{
+    conf::Conf,
+    dns::{self, DnsService},
+    template,
+};
+use std::{net::UdpSocket, time::Duration};
+
+mod utils;
+
+fn query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    let mut query = id.to_be_bytes().to_vec();
+    // Recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+    query
+}
+
+fn resolve(port: u16, query: &[u8]) -> Vec<u8> {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    let mut buf = [0u8; 512];
+    for _ in 0..25 {
+        socket.send_to(query, ("127.0.0.1", port)).unwrap();
+        if let Ok(len) = socket.recv(&mut buf) {
+            return buf[..len].to_vec();
+        }
+    }
+    panic!("No DNS response");
+}
+
+#[test]
+fn test_boot_hostname_is_resolved() {
+    let port = 30001 + (std::process::id() % 10000) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: /tmp
+ifaces: [lo]
+boot_hostname: boot.lab
+dns_port: {port}
+default:
+    boot_file: /bootfile
+    "#
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    conf.validate().unwrap();
+    let mut service = DnsService::default();
+    task::block_on(service.reload(&conf)).unwrap();
+
+    let request = query(0x1234, "Boot.LAB", 1);
+    let response = resolve(port, &request);
+    // Authoritative answer, recursion desired copied, no error
+    assert_eq!(&response[..4], &[0x12, 0x34, 0x85, 0x00]);
+    assert_eq!(&response[4..12], &[0, 1, 0, 1, 0, 0, 0, 0]);
+    assert_eq!(&response[12..request.len()], &request[12..]);
+    assert_eq!(
+        &response[request.len()..],
+        &[0xc0, 12, 0, 1, 0, 1, 0, 0, 0x01, 0x2c, 0, 4, 127, 0, 0, 1]
+    );
+
+    // AAAA, the name exists without such records
+    let response = resolve(port, &query(2, "boot.lab", 28));
+    assert_eq!(&response[2..8], &[0x85, 0x00, 0, 1, 0, 0]);
+
+    let response = resolve(port, &query(3, "example.com", 1));
+    assert_eq!(&response[2..8], &[0x85, 0x05, 0, 1, 0, 0]);
+
+    task::block_on(service.stop());
+}
+
+/// Answers one query on `socket` with the `PTR` record `name`, or with the
+/// error `rcode` when there's none.
+fn answer_ptr(socket: &UdpSocket, name: Option<&str>, rcode: u8) {
+    let mut buf = [0u8; 512];
+    let (len, peer) = socket.recv_from(&mut buf).unwrap();
+    let mut response = buf[..len].to_vec();
+    response[2] = 0x81;
+    response[3] = 0x80 | rcode;
+    if let Some(name) = name {
+        response[7] = 1;
+        // The name of the question, then the type, class, TTL and length
+        response.extend_from_slice(&[0xc0, 12, 0, 12, 0, 1, 0, 0, 0x0e, 0x10]);
+        let mut data = Vec::new();
+        for label in name.split('.') {
+            data.push(label.len() as u8);
+            data.extend_from_slice(label.as_bytes());
+        }
+        data.push(0);
+        response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        response.extend_from_slice(&data);
+    }
+    socket.send_to(&response, peer).unwrap();
+}
+
+#[test]
+fn test_reverse_lookup() {
+    let query = dns::ptr_query(7, "10.0.1.23".parse().unwrap());
+    assert_eq!(&query[12..], &b"\x0223\x011\x010\x0210\x07in-addr\x04arpa\x00\x00\x0c\x00\x01"[..]);
+
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let lookup = std::thread::spawn(move || {
+        let ip = "10.0.1.23".parse().unwrap();
+        let found = task::block_on(dns::reverse_lookup(addr, ip)).unwrap();
+        let missing = task::block_on(dns::reverse_lookup(addr, ip)).unwrap();
+        let failed = task::block_on(dns::reverse_lookup(addr, ip));
+        (found, missing, failed)
+    });
+    answer_ptr(&server, Some("Node7-LAB.example.com"), 0);
+    answer_ptr(&server, None, 3);
+    answer_ptr(&server, None, 2);
+    let (found, missing, failed) = lookup.join().unwrap();
+    assert_eq!(found.as_deref(), Some("node7-lab.example.com"));
+    assert_eq!(missing, None);
+    assert!(failed.is_err());
+
+    // Looping compression pointers
+    let mut response = dns::ptr_query(7, "10.0.1.23".parse().unwrap());
+    response[2] = 0x81;
+    response[7] = 1;
+    let data_offset = response.len() as u8 + 12;
+    response.extend_from_slice(&[0xc0, 12, 0, 12, 0, 1, 0, 0, 0, 0, 0, 2, 0xc0, data_offset]);
+    assert!(dns::parse_ptr_response(&response).is_err());
+}
+
+#[test]
+fn test_reverse_hostname_match() {
+    let yaml = r#"
+tftp_server_dir: /tmp
+reverse_dns: true
+reverse_dns_server: 127.0.0.1
+default:
+    boot_file: /default
+match:
+    - select:
+        ReverseHostname: "*-lab.example.com"
+      glob: true
+      conf:
+        boot_file: /lab
+    "#;
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.get_reverse_dns());
+    assert_eq!(conf.get_reverse_dns_server(), Some("127.0.0.1:53".parse().unwrap()));
+    let boot_file = |hostname: Option<&str>| {
+        let mac = template::parse_mac("52:54:00:00:00:01");
+        let mut doc = template::client_doc(mac.as_ref(), None, None);
+        if let Some(hostname) = hostname {
+            doc["ReverseHostname"] = hostname.into();
+        }
+        conf.get_from_doc(doc).unwrap().unwrap().boot_file.cloned()
+    };
+    assert_eq!(boot_file(Some("node7-lab.example.com")).as_deref(), Some("/lab"));
+    assert_eq!(boot_file(Some("node7.example.com")).as_deref(), Some("/default"));
+    assert_eq!(boot_file(None).as_deref(), Some("/default"));
+
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml.replace("127.0.0.1", "dns.lab"));
+    assert!(Conf::from_config_file(Some(&yaml_mock.path)).is_err());
+}
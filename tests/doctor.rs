@@ -0,0 +1,120 @@
+extern crate preboot_oxide;
+
+use network_interface::NetworkInterface;
+use preboot_oxide::{
+    conf::Conf,
+    doctor::{self, Check, Outcome},
+};
+
+mod utils;
+
+#[test]
+fn test_doctor_ports() {
+    let yaml = "ifaces: [eth0]\ntftp_server_dir: /srv/tftp\npxe_boot_server: true\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert_eq!(doctor::ports(&conf), vec![67, 68, 69, 4011]);
+
+    let yaml = "dhcp:\n    enabled: false\ntftp_server_dir: /srv/tftp\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert_eq!(doctor::ports(&conf), vec![69]);
+
+    let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n\
+        \x20 412: 00000000:0043 00000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 23154 2 0000000000000000 0\n\
+        \x20 413: 00000000:0045 00000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 23160 2 0000000000000000 0\n";
+    assert_eq!(doctor::udp_inodes(table, 67), vec![23154]);
+    assert_eq!(doctor::udp_inodes(table, 69), vec![23160]);
+    assert!(doctor::udp_inodes(table, 68).is_empty());
+}
+
+#[test]
+fn test_doctor_capabilities() {
+    let status = "Name:\tpreboot-oxide\nCapInh:\t0000000000000000\nCapEff:\t0000000000000400\n";
+    assert_eq!(doctor::effective_capabilities(status), Some(0x400));
+    let checks = doctor::capability_checks(Some(status));
+    assert_eq!(checks.len(), 2);
+    assert_eq!(checks[0].outcome, Outcome::Ok("granted".to_string()));
+    assert!(checks[1].is_problem(), "{checks:?}");
+
+    let root = "CapEff:\t000001ffffffffff\n";
+    assert!(!doctor::capability_checks(Some(root)).iter().any(Check::is_problem));
+    let checks = doctor::capability_checks(None);
+    assert!(matches!(checks[0].outcome, Outcome::Skipped(_)));
+}
+
+#[test]
+fn test_doctor_firewall() {
+    let ports = [67, 68, 69];
+    let open = "-P INPUT ACCEPT\n";
+    assert!(doctor::firewall_drops(open, &ports).is_empty());
+
+    let closed = "-P INPUT DROP\n\
+        -A INPUT -m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT\n\
+        -A INPUT -s 10.0.0.9/32 -j ACCEPT\n\
+        -A INPUT -p tcp -m tcp --dport 22 -j ACCEPT\n\
+        -A INPUT -p udp -m udp --dport 67:68 -j ACCEPT\n";
+    assert_eq!(doctor::firewall_drops(closed, &ports), vec![69]);
+
+    let dropping = "-P INPUT ACCEPT\n\
+        -A INPUT -p udp -m multiport --dports 53,69 -j REJECT --reject-with icmp-port-unreachable\n\
+        -A INPUT -j DROP\n";
+    assert_eq!(doctor::firewall_drops(dropping, &ports), vec![67, 68, 69]);
+    let accepting = format!("-A INPUT -p udp -m multiport --dports 67,68,69 -j ACCEPT\n{dropping}");
+    assert!(doctor::firewall_drops(&accepting, &ports).is_empty());
+}
+
+#[test]
+fn test_doctor_ifaces() {
+    let found = vec![
+        NetworkInterface::new_afinet("lo", [127, 0, 0, 1].into(), None, None, 1),
+        NetworkInterface::new_afinet("eth0", [10, 0, 0, 2].into(), None, None, 2),
+        NetworkInterface {
+            name: "eth1".to_string(),
+            addr: Vec::new(),
+            mac_addr: None,
+            index: 3,
+        },
+    ];
+    // Without `ifaces`, those having an address are served
+    let checks = doctor::iface_checks(&None, &found);
+    assert_eq!(checks.len(), 1);
+    assert_eq!(checks[0].name, "Interface eth0");
+    assert_eq!(checks[0].outcome, Outcome::Ok("10.0.0.2".to_string()));
+    assert!(doctor::iface_checks(&None, &found[2..])[0].is_problem());
+
+    let ifaces = Some(vec!["eth1".to_string(), "eth2".to_string()]);
+    let checks = doctor::iface_checks(&ifaces, &found);
+    assert!(matches!(&checks[0].outcome, Outcome::Problem { what, .. } if what.starts_with("has no IPv4")));
+    assert!(matches!(&checks[1].outcome, Outcome::Problem { what, .. } if what == "not found"));
+}
+
+#[test]
+fn test_doctor_report() {
+    let checks = vec![
+        Check {
+            name: "Port 67".to_string(),
+            outcome: Outcome::Problem {
+                what: "in use by dnsmasq (pid 812)".to_string(),
+                fix: "Stop it.".to_string(),
+            },
+        },
+        Check {
+            name: "Port 69".to_string(),
+            outcome: Outcome::Ok("free".to_string()),
+        },
+        Check {
+            name: "Firewall".to_string(),
+            outcome: Outcome::Skipped("iptables isn't installed".to_string()),
+        },
+    ];
+    assert_eq!(
+        doctor::report(&checks),
+        "[fail] Port 67: in use by dnsmasq (pid 812)\n       \
+                Fix: Stop it.\n\
+         [ok]   Port 69: free\n\
+         [skip] Firewall: not checked, iptables isn't installed\n\
+         1 problem found.\n"
+    );
+    assert_eq!(doctor::report(&checks[1..]).lines().last(), Some("No problems found."));
+}
@@ -0,0 +1,180 @@
+#![cfg(feature = "grpc")]
+extern crate preboot_oxide;
+
+use std::{net::TcpListener, sync::Arc, time::SystemTime};
+
+use preboot_oxide::{
+    conf::Conf,
+    control::{Control, ControlledService},
+    dhcp::SessionMap,
+    events::{self, Event},
+    grpc::{
+        self, ClientEvent, DryRun, ExpireSessionRequest, GetStatsRequest, ListClientsRequest,
+        ListClientsResponse, Stats, WatchEventsRequest,
+    },
+    reload,
+    tracker::BootTracker,
+};
+use tonic::{
+    client::Grpc,
+    codec::ProstCodec,
+    codegen::http::uri::PathAndQuery,
+    transport::{Channel, Endpoint},
+    Code,
+};
+
+mod utils;
+
+fn request<T>(message: T, token: &str) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request
+        .metadata_mut()
+        .insert("authorization", format!("Bearer {token}").parse().unwrap());
+    request
+}
+
+async fn call<Req, Resp>(
+    client: &mut Grpc<Channel>,
+    method: &'static str,
+    message: Req,
+    token: &str,
+) -> Result<Resp, tonic::Status>
+where
+    Req: prost::Message + Send + Sync + 'static,
+    Resp: prost::Message + Default + Send + Sync + 'static,
+{
+    client.ready().await.unwrap();
+    let path = PathAndQuery::from_static(method);
+    let response = client
+        .unary(request(message, token), path, ProstCodec::default())
+        .await?;
+    Ok(response.into_inner())
+}
+
+#[test]
+fn test_grpc() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let yaml = format!(
+        "tftp_server_dir: /tftpdir\ngrpc_addr: 127.0.0.1:{port}\napi_token: s3cret\ndefault:\n    boot_file: ipxe.efi\n"
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_ok());
+
+    let tracker = Arc::new(BootTracker::new(10));
+    tracker.boot_info_sent(
+        "10.0.0.5".parse().unwrap(),
+        "52:54:00:12:34:56",
+        42,
+        "ipxe.efi",
+        "eth0",
+    );
+    let control = Arc::new(Control {
+        started: SystemTime::now(),
+        origin: "--set".to_string(),
+        tracker,
+        services: vec![ControlledService {
+            tenant: None,
+            shared_conf: Arc::new(std::sync::RwLock::new(Arc::new(conf.clone()))),
+            sessions: Some(Arc::new(SessionMap::new(&conf))),
+        }],
+        reloader: reload::reloader().0,
+        handover: Default::default(),
+    });
+    grpc::spawn_grpc_async(&conf, control).unwrap();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let channel = Endpoint::from_shared(format!("http://127.0.0.1:{port}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = Grpc::new(channel);
+
+        let stats: Result<Stats, _> = call(
+            &mut client,
+            "/preboot_oxide.v1.Control/GetStats",
+            GetStatsRequest {},
+            "wrong",
+        )
+        .await;
+        assert_eq!(stats.unwrap_err().code(), Code::Unauthenticated);
+        let stats: Stats = call(
+            &mut client,
+            "/preboot_oxide.v1.Control/GetStats",
+            GetStatsRequest {},
+            "s3cret",
+        )
+        .await
+        .unwrap();
+        assert_eq!((stats.sessions, stats.dry_run), (0, false));
+
+        let clients: ListClientsResponse = call(
+            &mut client,
+            "/preboot_oxide.v1.Control/ListClients",
+            ListClientsRequest {
+                mac_address: Some("52:54:00:12:34:56".to_string()),
+            },
+            "s3cret",
+        )
+        .await
+        .unwrap();
+        assert_eq!(clients.clients[0].ip, "10.0.0.5");
+        let expired: Result<(), _> = call(
+            &mut client,
+            "/preboot_oxide.v1.Control/ExpireSession",
+            ExpireSessionRequest { xid: 42 },
+            "s3cret",
+        )
+        .await
+        .map(|_: grpc::ExpireSessionResponse| ());
+        assert_eq!(expired.unwrap_err().code(), Code::NotFound);
+        let dry_run: DryRun = call(
+            &mut client,
+            "/preboot_oxide.v1.Control/SetDryRun",
+            DryRun { dry_run: true },
+            "s3cret",
+        )
+        .await
+        .unwrap();
+        assert!(dry_run.dry_run && preboot_oxide::dhcp::is_dry_run());
+        preboot_oxide::dhcp::set_dry_run(false);
+
+        client.ready().await.unwrap();
+        let path = PathAndQuery::from_static("/preboot_oxide.v1.Control/WatchEvents");
+        let mut events = client
+            .server_streaming(
+                request(WatchEventsRequest {}, "s3cret"),
+                path,
+                ProstCodec::<WatchEventsRequest, ClientEvent>::default(),
+            )
+            .await
+            .unwrap()
+            .into_inner();
+        let client_event = events::ClientEvent {
+            mac_address: "52:54:00:12:34:56".to_string(),
+            xid: 0x1234,
+            boot_file: Some("ipxe.efi".to_string()),
+            ..Default::default()
+        };
+        events::fire(&conf, Event::BootFileDelivered, client_event);
+        let event = events.message().await.unwrap().unwrap();
+        assert_eq!(event.event, "boot_file_delivered");
+        assert_eq!(event.session, "940d5785");
+        assert_eq!(event.boot_file.as_deref(), Some("ipxe.efi"));
+    });
+}
+
+#[test]
+fn test_grpc_conf() {
+    let yaml =
+        "tftp_server_dir: /tftpdir\ngrpc_addr: 127.0.0.1:8069\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+}
@@ -0,0 +1,88 @@
+#![cfg(target_os = "linux")]
+extern crate preboot_oxide;
+
+use std::{
+    net::{Ipv4Addr, SocketAddrV4, UdpSocket},
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use async_std::{future::timeout, task};
+use preboot_oxide::{
+    activation,
+    conf::Conf,
+    control::{self, Control, ControlledService},
+    dhcp::SessionMap,
+    handover::{self, Handover},
+    reload,
+    tracker::BootTracker,
+};
+
+mod utils;
+
+#[test]
+fn test_handover() {
+    let socket = std::env::temp_dir().join(format!("preboot-oxide-handover-{}.sock", std::process::id()));
+    // The DNS responder's socket, on a port of its own
+    let dns = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let dns_port = dns.local_addr().unwrap().port();
+    let yaml = format!(
+        "control_socket: {}\ndns_port: {dns_port}\ndefault:\n    boot_file: ipxe.efi\n",
+        socket.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+
+    let tracker = Arc::new(BootTracker::new(10));
+    tracker.boot_info_sent("10.0.0.5".parse().unwrap(), "52:54:00:12:34:56", 42, "ipxe.efi", "eth0");
+    let handover = Handover::default();
+    let control = Control {
+        started: SystemTime::now(),
+        origin: "--set".to_string(),
+        tracker,
+        services: vec![ControlledService {
+            tenant: None,
+            shared_conf: Arc::new(std::sync::RwLock::new(Arc::new(conf.clone()))),
+            sessions: Some(Arc::new(SessionMap::new(&conf))),
+        }],
+        reloader: reload::reloader().0,
+        handover: handover.clone(),
+    };
+    control::spawn_control_service_async(&conf, Arc::new(control)).unwrap();
+
+    let mut state = handover::take_over(&socket).unwrap();
+    assert_eq!(state.summary(), "0 DHCP sessions and 1 tracked clients");
+    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, dns_port);
+    assert!(activation::has(addr));
+    task::block_on(timeout(Duration::from_secs(2), handover.requested())).unwrap();
+
+    // The socket taken over receives what's sent to the one handed over
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.send_to(b"hello", addr).unwrap();
+    let mut buf = [0u8; 5];
+    let taken_over = activation::socket(addr, None).unwrap().unwrap();
+    taken_over.recv_from(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+
+    let tracker = BootTracker::new(10);
+    state.inherit_clients(&tracker);
+    let client = tracker.get(&"10.0.0.5".parse().unwrap()).unwrap();
+    assert_eq!((client.mac_address.as_str(), client.xid), ("52:54:00:12:34:56", 42));
+
+    std::fs::remove_file(&socket).unwrap();
+    assert!(handover::take_over(Path::new(&socket)).is_err());
+}
+
+#[test]
+fn test_drain() {
+    let tracker = Arc::new(BootTracker::new(10));
+    let transfer = BootTracker::transfer_started(&tracker, "10.0.0.5".parse().unwrap(), Path::new("ipxe.efi"), None);
+    assert_eq!(tracker.active_transfers(), 1);
+    // Given up on after the timeout
+    task::block_on(handover::drain(&tracker, Duration::from_millis(100)));
+
+    drop(transfer);
+    assert_eq!(tracker.active_transfers(), 0);
+    task::block_on(timeout(Duration::from_secs(1), handover::drain(&tracker, handover::DRAIN_TIMEOUT))).unwrap();
+}
@@ -0,0 +1,127 @@
+extern crate preboot_oxide;
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use preboot_oxide::{
+    conf::Conf,
+    control::{Control, ControlledService},
+    dhcp::SessionMap,
+    health,
+    notify::{LoopsState, Notifier},
+    reload,
+    tracker::BootTracker,
+};
+
+mod utils;
+
+fn get(port: u16, path: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let request = format!("GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn control(conf: &Conf) -> Arc<Control> {
+    Arc::new(Control {
+        started: SystemTime::now(),
+        origin: "--set".to_string(),
+        tracker: Arc::new(BootTracker::new(10)),
+        services: vec![ControlledService {
+            tenant: None,
+            shared_conf: Arc::new(std::sync::RwLock::new(Arc::new(conf.clone()))),
+            sessions: Some(Arc::new(SessionMap::new(conf))),
+        }],
+        reloader: reload::reloader().0,
+        handover: Default::default(),
+    })
+}
+
+#[test]
+fn test_health_checks() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let yaml = format!(
+        "health_addr: 127.0.0.1:{port}\ntftp_server_dir: /nonexistent/tftpdir\ndefault:\n    boot_file: ipxe.efi\n"
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    let control = control(&conf);
+    let notifier = Arc::new(Notifier::new(None, None));
+    let heartbeat = notifier.register();
+
+    // Alive while the loop waits to bind, not ready
+    let liveness = health::liveness(&notifier);
+    assert!(liveness.ok);
+    let readiness = health::readiness(&control, &notifier);
+    assert!(!readiness.ok);
+    let failed: Vec<&str> = readiness
+        .checks
+        .iter()
+        .filter(|check| !check.ok)
+        .map(|check| check.name)
+        .collect();
+    assert_eq!(failed, ["dhcp_sockets", "tftp_roots"]);
+    assert!(readiness.checks[2]
+        .error
+        .as_ref()
+        .unwrap()
+        .starts_with("/nonexistent/tftpdir: "));
+
+    heartbeat.bound();
+    health::spawn_health_async(&conf, Arc::clone(&control), Arc::clone(&notifier)).unwrap();
+    let response = get(port, "/healthz");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    let body = response.split_once("\r\n\r\n").unwrap().1;
+    let report: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(report["checks"][0]["name"], "dhcp_loops");
+    assert!(get(port, "/readyz").starts_with("HTTP/1.1 503 "));
+    assert!(get(port, "/metrics").starts_with("HTTP/1.1 404 "));
+}
+
+#[test]
+fn test_loops_state() {
+    let notifier =
+        Arc::new(Notifier::new(None, None).with_liveness_interval(Duration::from_secs(10)));
+    let (first, second) = (notifier.register(), notifier.register());
+    assert_eq!(first.interval(), Some(Duration::from_secs(10)));
+    assert_eq!(
+        notifier.loops_state(Duration::from_secs(30)),
+        LoopsState {
+            loops: 2,
+            bound: 0,
+            stalled: 0
+        }
+    );
+    first.bound();
+    second.bound();
+    second.beat();
+    assert_eq!(notifier.loops_state(Duration::from_secs(30)).stalled, 0);
+    std::thread::sleep(Duration::from_millis(20));
+    second.beat();
+    let state = notifier.loops_state(Duration::from_millis(10));
+    assert_eq!((state.bound, state.stalled), (2, 1));
+}
+
+#[test]
+fn test_health_addr_conf() {
+    let yaml = "health_addr: 8068\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    assert!(Conf::from_config_file(Some(&yaml_mock.path)).is_err());
+    let yaml = "tftp_server_dir: /tftpdir\nhealth_addr: 0.0.0.0:8068\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert_eq!(
+        conf.get_health_addr(),
+        Some("0.0.0.0:8068".parse().unwrap())
+    );
+}
@@ -0,0 +1,99 @@
+extern crate preboot_oxide;
+
+use async_std::task;
+use preboot_oxide::{conf::Conf, hook, template};
+use std::time::{Duration, SystemTime};
+
+mod utils;
+
+fn script(source: &str) -> utils::MockFile {
+    utils::MockFile::from_bytes(source.as_bytes(), "rhai")
+}
+
+#[test]
+fn test_boot_hook_decides_per_client() {
+    // Canary clients get the new image, the others are left to match
+    let script = script(
+        r#"
+if client.Serial.starts_with("canary") {
+    #{ boot_file: "/canary", vars: #{ track: "canary" } }
+}
+"#,
+    );
+    let doc = |serial: &str| template::client_doc(None, None, Some(serial));
+
+    let entry = task::block_on(hook::run(&script.path, &doc("canary-1")))
+        .unwrap()
+        .unwrap();
+    assert_eq!(entry.boot_file, Some("/canary".to_string()));
+    assert_eq!(entry.vars.get("track").map(String::as_str), Some("canary"));
+    let entry = task::block_on(hook::run(&script.path, &doc("stable-1"))).unwrap();
+    assert!(entry.is_none());
+
+    let yaml = format!(
+        "tftp_server_dir: /tftpdir\nboot_hook: {}\ndefault:\n    boot_file: /default\n",
+        script.path.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_ok());
+    assert!(task::block_on(hook::client_conf(&conf, &doc("canary-2"))).is_some());
+}
+
+#[test]
+fn test_boot_hook_failures_are_errors() {
+    let invalid_syntax = script("if {");
+    let endless = script("loop {}");
+    let invalid = script("[1, 2]");
+    let doc = template::client_doc(None, None, None);
+
+    let run = |path| task::block_on(hook::run(path, &doc));
+    assert!(hook::compile(&invalid_syntax.path).is_err());
+    assert!(run(&invalid_syntax.path).is_err());
+    let err = run(&endless.path).unwrap_err();
+    assert!(err.to_string().contains("operations"), "{err:#}");
+    assert!(run(&invalid.path).is_err());
+
+    let yaml = format!(
+        "tftp_server_dir: /tftpdir\nboot_hook: {}\n",
+        invalid_syntax.path.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+}
+
+#[test]
+fn test_boot_hook_is_recompiled_when_changed() {
+    let script = script(r#"#{ boot_file: "/old" }"#);
+    let doc = template::client_doc(None, None, None);
+    let boot_file = || {
+        task::block_on(hook::run(&script.path, &doc))
+            .unwrap()
+            .and_then(|entry| entry.boot_file)
+    };
+    assert_eq!(boot_file(), Some("/old".to_string()));
+
+    std::fs::write(&script.path, r#"#{ boot_file: "/new" }"#).unwrap();
+    let file = std::fs::File::options().write(true).open(&script.path).unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(boot_file(), Some("/new".to_string()));
+}
+
+#[test]
+fn test_boot_hook_runs_beyond_the_bound_wait_their_turn() {
+    let busy = script("let n = 0; while n < 20000 { n += 1; } #{ boot_file: \"/busy\" }");
+    let doc = template::client_doc(None, None, None);
+
+    let runs: Vec<_> = (0..3 * hook::MAX_RUNNING)
+        .map(|_| {
+            let (path, doc) = (busy.path.clone(), doc.clone());
+            task::spawn(async move { hook::run(&path, &doc).await })
+        })
+        .collect();
+    for run in runs {
+        let entry = task::block_on(run).unwrap().unwrap();
+        assert_eq!(entry.boot_file, Some("/busy".to_string()));
+    }
+}
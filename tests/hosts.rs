@@ -0,0 +1,97 @@
+extern crate preboot_oxide;
+
+use preboot_oxide::{conf::Conf, hosts, template};
+
+mod utils;
+
+#[test]
+fn test_parse_hosts() {
+    let csv = "# exported from the lab sheet\n\
+               52:54:00:00:00:01,debian/bootx64.efi,\n\
+               \n\
+               52-54-00-00-00-02,,\"ubuntu\"\n";
+    let parsed = hosts::parse(csv, ',').unwrap();
+    assert_eq!(parsed.len(), 2);
+    let host = &parsed[&[0x52, 0x54, 0, 0, 0, 1]];
+    assert_eq!(host.boot_file.as_deref(), Some("debian/bootx64.efi"));
+    assert_eq!(host.profile, None);
+    assert_eq!(parsed[&[0x52, 0x54, 0, 0, 0, 2]].profile.as_deref(), Some("ubuntu"));
+
+    // Columns by the header, the others ignored
+    let tsv = "Owner\tprofile\tMAC\nops\tubuntu\t52:54:00:00:00:03\n";
+    let parsed = hosts::parse(tsv, '\t').unwrap();
+    assert_eq!(parsed[&[0x52, 0x54, 0, 0, 0, 3]].profile.as_deref(), Some("ubuntu"));
+
+    for (invalid, error) in [
+        ("52:54:00:00:00,a.efi", "Invalid MAC address 52:54:00:00:00 on line 1"),
+        ("mac,boot_file\n52:54:00:00:00:01", "No boot_file or profile on line 2"),
+        ("52:54:00:00:00:01,a\n52:54:00:00:00:01,b", "listed again on line 2"),
+    ] {
+        let message = hosts::parse(invalid, ',').unwrap_err().to_string();
+        assert!(message.contains(error), "{message}");
+    }
+}
+
+#[test]
+fn test_match_hosts() {
+    let csv = utils::MockFile::from_bytes(
+        b"mac,boot_file,profile\n\
+          52:54:00:00:00:01,debian/bootx64.efi,\n\
+          52:54:00:00:00:02,,ubuntu\n",
+        "csv",
+    );
+    let yaml = format!(
+        r#"
+tftp_server_dir: /tftpdir
+default:
+    boot_file: /default
+profiles:
+    ubuntu:
+        boot_file: ubuntu/bootx64.efi
+        menu_label: Ubuntu
+match:
+    - hosts: {}
+      conf:
+        menu_label: Lab
+    - select:
+        ClientMacAddress: 52:54:00:00:00:02
+      conf:
+        boot_file: /shadowed
+"#,
+        csv.path.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.lint_match_rules().is_empty());
+    assert_eq!(conf.get_host_files(), vec![csv.path.as_path()]);
+    let entry = |mac: &str| {
+        let doc = template::client_doc(template::parse_mac(mac).as_ref(), None, None);
+        let entry = conf.get_from_doc(doc).unwrap().unwrap();
+        (entry.boot_file.cloned(), entry.menu_label.cloned())
+    };
+    let owned = |boot_file: &str, label: Option<&str>| {
+        (Some(boot_file.to_string()), label.map(str::to_string))
+    };
+    assert_eq!(entry("52:54:00:00:00:01"), owned("debian/bootx64.efi", Some("Lab")));
+    assert_eq!(entry("52:54:00:00:00:02"), owned("ubuntu/bootx64.efi", Some("Lab")));
+    assert_eq!(entry("52:54:00:00:00:03"), owned("/default", None));
+    assert!(conf.to_yaml().unwrap().contains(&format!("hosts: {}", csv.path.display())));
+
+    // Narrowed by select
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml.replace(
+        "      conf:\n        menu_label: Lab",
+        "      select:\n        Interface: eth1\n      conf:\n        menu_label: Lab",
+    ));
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    let mac = template::parse_mac("52:54:00:00:00:01");
+    let mut doc = template::client_doc(mac.as_ref(), None, None);
+    assert_eq!(conf.get_from_doc(doc.clone()).unwrap().unwrap().boot_file.unwrap(), "/default");
+    doc["Interface"] = "eth1".into();
+    let entry = conf.get_from_doc(doc).unwrap().unwrap();
+    assert_eq!(entry.boot_file.unwrap(), "debian/bootx64.efi");
+
+    let missing = yaml.replace(&csv.path.display().to_string(), "/nonexistent/hosts.csv");
+    let yaml_mock = utils::YamlMockFile::from_yaml(&missing);
+    let error = format!("{:#}", Conf::from_config_file(Some(&yaml_mock.path)).unwrap_err());
+    assert!(error.contains("Reading the hosts file /nonexistent/hosts.csv"), "{error}");
+}
@@ -0,0 +1,763 @@
+extern crate preboot_oxide;
+
+use async_std::{net::TcpStream as AsyncTcpStream, task};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use futures_rustls::{
+    rustls::{
+        crypto::ring::default_provider,
+        pki_types::{PrivateKeyDer, ServerName},
+        ClientConfig, RootCertStore,
+    },
+    TlsConnector,
+};
+use preboot_oxide::{
+    conf::Conf,
+    http::HttpService,
+    tftp::{DirHandler, DirHandlerMode, SharedDirHandler},
+};
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+mod utils;
+
+fn get(port: u16, request: &str) -> String {
+    String::from_utf8(get_bytes(port, request)).unwrap()
+}
+
+fn get_bytes(port: u16, request: &str) -> Vec<u8> {
+    let mut stream = (0..50)
+        .find_map(|_| {
+            TcpStream::connect(("127.0.0.1", port))
+                .inspect_err(|_| std::thread::sleep(Duration::from_millis(50)))
+                .ok()
+        })
+        .unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_files_are_served_over_http() {
+    let root = std::env::temp_dir().join(format!("po-http-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("boot")).unwrap();
+    std::fs::write(root.join("boot/vmlinuz"), b"kernel").unwrap();
+    let port = 20000 + (std::process::id() % 10000) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: {}
+ifaces: [lo]
+http_port: {port}
+default:
+    boot_file: /bootfile
+    "#,
+        root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+
+    let response = get(port, "GET /boot/vmlinuz HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Length: 6\r\n"));
+    assert!(response.ends_with("\r\n\r\nkernel"));
+
+    let response = get(port, "HEAD /boot/vmlinuz HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\n"));
+
+    let etag = response
+        .lines()
+        .find_map(|line| line.strip_prefix("ETag: "))
+        .unwrap()
+        .to_string();
+    let response = get(
+        port,
+        &format!("GET /boot/vmlinuz HTTP/1.0\r\nIf-None-Match: {etag}\r\n\r\n"),
+    );
+    assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+
+    let response = get(port, "GET /boot/vmlinuz HTTP/1.0\r\nRange: bytes=2-\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+    assert!(response.contains("Content-Range: bytes 2-5/6\r\n"));
+    assert!(response.ends_with("\r\n\r\nrnel"));
+
+    let response = get(port, "GET /boot/vmlinuz HTTP/1.0\r\nRange: bytes=-3\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nnel"));
+
+    let response = get(port, "GET /boot/vmlinuz HTTP/1.0\r\nRange: bytes=6-\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+    assert!(response.contains("Content-Range: bytes */6\r\n"));
+
+    let response = get(
+        port,
+        "GET /boot/vmlinuz HTTP/1.0\r\nRange: bytes=2-\r\nIf-Range: \"stale\"\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+    let response = get(port, "GET /boot/missing HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    let response = get(port, "GET /../etc/passwd HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+
+    task::block_on(service.stop());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_files_are_served_over_https() {
+    let root = std::env::temp_dir().join(format!("po-https-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("boot.efi"), b"network boot program").unwrap();
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    std::fs::write(root.join("cert.pem"), certified.cert.pem()).unwrap();
+    std::fs::write(root.join("key.pem"), certified.signing_key.serialize_pem()).unwrap();
+    let port = 30000 + (std::process::id() % 10000) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: {root}
+ifaces: [lo]
+https_port: {port}
+https_cert: {root}/cert.pem
+https_key: {root}/key.pem
+default:
+    boot_file: /bootfile
+    "#,
+        root = root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(certified.cert.der().clone()).unwrap();
+    let client_config = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(client_config));
+
+    let response = task::block_on(async {
+        let mut stream = None;
+        for _ in 0..50 {
+            match AsyncTcpStream::connect(("127.0.0.1", port)).await {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    break;
+                }
+                Err(_) => task::sleep(Duration::from_millis(50)).await,
+            }
+        }
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(server_name, stream.unwrap()).await.unwrap();
+        stream
+            .write_all(b"GET /boot.efi HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    });
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nnetwork boot program"));
+
+    task::block_on(service.stop());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_ipxe_scripts_are_rendered_per_client() {
+    let root = std::env::temp_dir().join(format!("po-ipxe-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(
+        root.join("lab.ipxe.tmpl"),
+        "#!ipxe\nkernel {{base_url}}/{{boot_file}} BOOTIF={{mac}}\nboot\n",
+    )
+    .unwrap();
+    let port = 40000 + (std::process::id() % 10000) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: {}
+ifaces: [lo]
+http_port: {port}
+default:
+    boot_file: /ipxe.efi
+match:
+    - select:
+        ClientMacAddress: 52:54:00:12:34:56
+      conf:
+        boot_file: /lab/vmlinuz
+        ipxe_script: lab.ipxe.tmpl
+    "#,
+        root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+
+    let response = get(port, "GET /boot.ipxe?mac=52%3a54%3a00%3a12%3a34%3a56 HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(&format!(
+        "\r\n\r\n#!ipxe\nkernel http://127.0.0.1:{port}/lab/vmlinuz BOOTIF=52:54:00:12:34:56\nboot\n"
+    )));
+
+    let response = get(port, "GET /boot.ipxe?mac=52:54:00:ab:cd:ef HTTP/1.0\r\n\r\n");
+    assert!(response.ends_with(&format!(
+        "\r\n\r\n#!ipxe\nchain http://127.0.0.1:{port}/ipxe.efi\n"
+    )));
+
+    let response = get(port, "GET /boot.ipxe HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+    task::block_on(service.stop());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_ipxe_menus_list_the_boot_entries() {
+    let root = std::env::temp_dir().join(format!("po-menu-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("lab.ipxe.tmpl"), "#!ipxe\necho {{mac}}\n").unwrap();
+    let port = 65000 + (std::process::id() % 500) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: {}
+ifaces: [lo]
+http_port: {port}
+default:
+    boot_file: /ipxe.efi
+match:
+    - select:
+        ClientMacAddress: 52:54:00:12:34:56
+      conf:
+        ipxe_script: lab.ipxe.tmpl
+        menu_label: Lab tools
+    - select:
+        ClientMacAddress: 52:54:00:ab:cd:ef
+      conf:
+        ipxe_script: lab.ipxe.tmpl
+        menu_label: Lab tools
+    "#,
+        root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+
+    let response = get(port, "GET /menu.ipxe?mac=52:54:00:12:34:56 HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    let body = response.split_once("\r\n\r\n").unwrap().1;
+    assert!(body.starts_with("#!ipxe\n"));
+    assert_eq!(body.matches("item entry").count(), 1);
+    assert!(body.contains("item entry0 Lab tools\n"));
+    assert!(body.contains("choose --default entry0 "));
+    assert!(body.contains(&format!(
+        "chain http://127.0.0.1:{port}/boot.ipxe?entry=0&mac=${{net0/mac}} || goto failed\n"
+    )));
+
+    let response = get(port, "GET /menu.ipxe?mac=52:54:00:00:00:01 HTTP/1.0\r\n\r\n");
+    assert!(response.contains("choose --default local "));
+
+    let response = get(port, "GET /boot.ipxe?entry=0&mac=52:54:00:00:00:01 HTTP/1.0\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n#!ipxe\necho 52:54:00:00:00:01\n"));
+    let response = get(port, "GET /boot.ipxe?entry=1&mac=52:54:00:00:00:01 HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    task::block_on(service.stop());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_autoinstall_files_are_rendered_per_client() {
+    let root = std::env::temp_dir().join(format!("po-autoinstall-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("templates")).unwrap();
+    std::fs::write(
+        root.join("templates/user-data"),
+        "#cloud-config\nhostname: {{hostname}}\nfqdn: {{hostname}}.{{domain}}\n",
+    )
+    .unwrap();
+    let port = 50000 + (std::process::id() % 10000) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: {root}
+ifaces: [lo]
+http_port: {port}
+autoinstall_dir: {root}/templates
+default:
+    boot_file: /ipxe.efi
+    vars:
+        hostname: unnamed
+        domain: lab.example
+match:
+    - select:
+        ClientMacAddress: 52:54:00:12:34:56
+      conf:
+        vars:
+            hostname: web1
+    "#,
+        root = root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+
+    let response = get(
+        port,
+        "GET /autoinstall/user-data?mac=52:54:00:12:34:56 HTTP/1.0\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\n#cloud-config\nhostname: web1\nfqdn: web1.lab.example\n"));
+
+    let response = get(port, "GET /autoinstall/user-data HTTP/1.0\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\n#cloud-config\nhostname: unnamed\nfqdn: unnamed.lab.example\n"));
+
+    let response = get(port, "GET /autoinstall/meta-data HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    let response = get(port, "GET /autoinstall/%2e%2e/templates/user-data HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+
+    task::block_on(service.stop());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_files_are_uploaded_over_http() {
+    let root = std::env::temp_dir().join(format!("po-upload-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let port = 10000 + (std::process::id() % 10000) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: {}
+ifaces: [lo]
+http_port: {port}
+upload_token: s3cret
+upload_users:
+    ci: hunter2
+upload_max_size: 1
+default:
+    boot_file: /bootfile
+    "#,
+        root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+
+    let upload = |path: &str, authorization: &str, checksum: Option<&str>, body: &str| {
+        let checksum = checksum
+            .map(|checksum| format!("X-Checksum-Sha256: {checksum}\r\n"))
+            .unwrap_or_default();
+        get(
+            port,
+            &format!(
+                "PUT {path} HTTP/1.1\r\nAuthorization: {authorization}\r\n{checksum}Content-Length: {}\r\n\r\n{body}",
+                body.len()
+            ),
+        )
+    };
+    // SHA-256 of "kernel v1"
+    let v1 = "c7a340515fad4c4926a7141c8c14c087fc15fa9f8b823e86ecab6cc89684bbeb";
+
+    let response = upload("/boot/vmlinuz", "Bearer wrong", None, "kernel v1");
+    assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+
+    let response = upload("/boot/vmlinuz", "Bearer s3cret", Some(v1), "kernel v2");
+    assert!(response.starts_with("HTTP/1.1 422 Unprocessable Content\r\n"));
+    assert!(!root.join("boot/vmlinuz").exists());
+
+    let response = upload("/boot/vmlinuz", "Bearer s3cret", Some(v1), "kernel v1");
+    assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+    assert!(response.ends_with(&format!("\r\n\r\n{v1}\n")));
+    assert_eq!(std::fs::read(root.join("boot/vmlinuz")).unwrap(), b"kernel v1");
+
+    let response = upload("/boot/vmlinuz", "Bearer s3cret", None, "kernel v2");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    let response = get(port, "GET /boot/vmlinuz HTTP/1.0\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nkernel v2"));
+
+    // "ci:hunter2", basic authentication sending it in clear is refused
+    let response = upload("/boot/initrd", "Basic Y2k6aHVudGVyMg==", None, "initrd");
+    assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    assert!(!response.contains("WWW-Authenticate: Basic"));
+    assert!(!root.join("boot/initrd").exists());
+
+    let response = get(
+        port,
+        "PUT /boot/initrd HTTP/1.1\r\nAuthorization: Bearer s3cret\r\nContent-Length: 1048577\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+    assert!(!root.join("boot/initrd").exists());
+
+    let response = upload("/../escaped", "Bearer s3cret", None, "kernel");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+
+    task::block_on(service.stop());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_concurrent_uploads_to_the_same_file() {
+    let root = std::env::temp_dir().join(format!("po-upload-concurrent-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let port = 10001 + (std::process::id() % 10000) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: {}
+ifaces: [lo]
+http_port: {port}
+upload_token: s3cret
+default:
+    boot_file: /bootfile
+    "#,
+        root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+
+    let bodies: Vec<String> = (0..8).map(|i| format!("kernel {i}").repeat(10000)).collect();
+    let uploads: Vec<_> = bodies
+        .iter()
+        .cloned()
+        .map(|body| {
+            std::thread::spawn(move || {
+                get(
+                    port,
+                    &format!(
+                        "PUT /new/dir/vmlinuz HTTP/1.1\r\nAuthorization: Bearer s3cret\r\nContent-Length: {}\r\n\r\n{body}",
+                        body.len()
+                    ),
+                )
+            })
+        })
+        .collect();
+    for upload in uploads {
+        let response = upload.join().unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 201 Created\r\n")
+                || response.starts_with("HTTP/1.1 200 OK\r\n"),
+            "{response}"
+        );
+    }
+
+    // One of the uploads whole, without the temporary files of the others
+    let content = String::from_utf8(std::fs::read(root.join("new/dir/vmlinuz")).unwrap()).unwrap();
+    assert!(bodies.contains(&content));
+    assert_eq!(std::fs::read_dir(root.join("new/dir")).unwrap().count(), 1);
+
+    task::block_on(service.stop());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_ignition_configs_are_served_per_client() {
+    let root = std::env::temp_dir().join(format!("po-ignition-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("templates")).unwrap();
+    std::fs::write(
+        root.join("templates/worker.ign"),
+        r#"{"ignition":{"version":"3.4.0"},"storage":{"files":[{"path":"/etc/hostname","contents":{"source":"data:,{{hostname}}"}}]}}"#,
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("templates/default.ign"),
+        r#"{"ignition":{"version":"3.4.0"}}"#,
+    )
+    .unwrap();
+    let port = 60000 + (std::process::id() % 5000) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: {0}
+ifaces: [lo]
+http_port: {port}
+autoinstall_dir: {0}/templates
+default:
+    boot_file: /bootfile
+    ignition: default.ign
+match:
+    - select:
+        Uuid: 4C4C4544-0042-3510-8052-B2C04F4B4E32
+        Serial: SN123
+      conf:
+        ignition: worker.ign
+        vars:
+          hostname: worker1
+    "#,
+        root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+
+    let response = get(
+        port,
+        "GET /ignition?uuid=4c4c4544-0042-3510-8052-b2c04f4b4e32&serial=SN123 HTTP/1.0\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: application/vnd.coreos.ignition+json\r\n"));
+    assert!(response.ends_with(r#""source":"data:,worker1"}}]}}"#));
+
+    let response = get(port, "GET /ignition?uuid=unknown HTTP/1.0\r\n\r\n");
+    assert!(response.ends_with(r#"{"ignition":{"version":"3.4.0"}}"#));
+
+    task::block_on(service.stop());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_checksums_and_signatures_are_served() {
+    let root = std::env::temp_dir().join(format!("po-signing-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("boot")).unwrap();
+    std::fs::write(root.join("boot/vmlinuz"), b"kernel image\n").unwrap();
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let port = 5000 + (std::process::id() % 5000) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: {}
+ifaces: [lo]
+http_port: {port}
+signing_cert: {1}/signing.crt
+signing_key: {1}/signing.key
+default:
+    boot_file: /bootfile
+    "#,
+        root.display(),
+        fixtures.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+
+    let response = get(port, "GET /boot/vmlinuz.sha256 HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(
+        "\r\n\r\n6f64c2d2f55490a1a5291b436f012572301ec40c9c7165001ce9721cbcb9d415  vmlinuz\n"
+    ));
+
+    // Same signature as `openssl cms -sign -binary -noattr -md sha256 -outform DER`
+    let response = get_bytes(port, "GET /boot/vmlinuz.sig HTTP/1.0\r\n\r\n");
+    let expected = std::fs::read(fixtures.join("vmlinuz.sig")).unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(&expected));
+
+    // Files on disk take precedence
+    std::fs::write(root.join("boot/vmlinuz.sha256"), b"published").unwrap();
+    let response = get(port, "GET /boot/vmlinuz.sha256 HTTP/1.0\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\npublished"));
+
+    let response = get(port, "GET /boot/missing.sig HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    task::block_on(service.stop());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_uploads_are_authorized_by_client_certificates() {
+    let root = std::env::temp_dir().join(format!("po-mtls-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("boot.efi"), b"network boot program").unwrap();
+    let server = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    std::fs::write(root.join("cert.pem"), server.cert.pem()).unwrap();
+    std::fs::write(root.join("key.pem"), server.signing_key.serialize_pem()).unwrap();
+
+    let ca_key = rcgen::KeyPair::generate().unwrap();
+    let mut ca_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+    ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+    std::fs::write(root.join("client-ca.pem"), ca_cert.pem()).unwrap();
+    let client_key = rcgen::KeyPair::generate().unwrap();
+    let client_cert = rcgen::CertificateParams::new(vec!["ci".to_string()])
+        .unwrap()
+        .signed_by(&client_key, &rcgen::Issuer::new(ca_params, &ca_key))
+        .unwrap();
+
+    let port = 1024 + (std::process::id() % 3976) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: {root}
+ifaces: [lo]
+https_port: {port}
+https_cert: {root}/cert.pem
+https_key: {root}/key.pem
+https_client_ca: {root}/client-ca.pem
+upload_users:
+    ci: hunter2
+default:
+    boot_file: /bootfile
+    "#,
+        root = root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(server.cert.der().clone()).unwrap();
+    let builder = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+    let anonymous = builder.clone().with_no_client_auth();
+    let authenticated = builder
+        .with_client_auth_cert(
+            vec![client_cert.der().clone()],
+            PrivateKeyDer::try_from(client_key.serialize_der()).unwrap(),
+        )
+        .unwrap();
+    let request = |client_config: ClientConfig, request: &str| {
+        let connector = TlsConnector::from(Arc::new(client_config));
+        task::block_on(async {
+            let mut stream = None;
+            for _ in 0..50 {
+                match AsyncTcpStream::connect(("127.0.0.1", port)).await {
+                    Ok(connected) => {
+                        stream = Some(connected);
+                        break;
+                    }
+                    Err(_) => task::sleep(Duration::from_millis(50)).await,
+                }
+            }
+            let server_name = ServerName::try_from("localhost").unwrap();
+            let mut stream = connector.connect(server_name, stream.unwrap()).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        })
+    };
+    let upload = "PUT /boot/vmlinuz HTTP/1.1\r\nContent-Length: 6\r\n\r\nkernel";
+
+    // Boot files stay open to clients without a certificate
+    let response = request(
+        anonymous.clone(),
+        "GET /boot.efi HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.ends_with("\r\n\r\nnetwork boot program"));
+
+    let response = request(anonymous.clone(), upload);
+    assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    assert!(response.contains("WWW-Authenticate: Basic realm=\"preboot-oxide\"\r\n"));
+    assert!(!root.join("boot/vmlinuz").exists());
+
+    // "ci:hunter2" and "ci:wrong", accepted over TLS
+    let basic = |credentials: &str| {
+        format!("PUT /boot/initrd HTTP/1.1\r\nAuthorization: Basic {credentials}\r\nContent-Length: 6\r\n\r\ninitrd")
+    };
+    let response = request(anonymous.clone(), &basic("Y2k6d3Jvbmc="));
+    assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    let response = request(anonymous, &basic("Y2k6aHVudGVyMg=="));
+    assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+
+    let response = request(authenticated, upload);
+    assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+    assert_eq!(std::fs::read(root.join("boot/vmlinuz")).unwrap(), b"kernel");
+
+    task::block_on(service.stop());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_mirrored_files_are_fetched_and_cached() {
+    let upstream_root = std::env::temp_dir().join(format!("po-upstream-{}", std::process::id()));
+    let root = std::env::temp_dir().join(format!("po-mirror-{}", std::process::id()));
+    std::fs::create_dir_all(upstream_root.join("casper")).unwrap();
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(upstream_root.join("casper/vmlinuz"), b"kernel").unwrap();
+    let upstream_port = 50001 + (std::process::id() % 10000) as u16;
+    let port = 50002 + (std::process::id() % 10000) as u16;
+
+    let upstream_yaml = format!(
+        r#"
+tftp_server_dir: {}
+ifaces: [lo]
+http_port: {upstream_port}
+default:
+    boot_file: /bootfile
+    "#,
+        upstream_root.display()
+    );
+    let upstream_yaml_mock = utils::YamlMockFile::from_yaml(&upstream_yaml);
+    let upstream_conf = Conf::from_config_file(Some(&upstream_yaml_mock.path)).unwrap();
+    let upstream_handler = DirHandler::new(&upstream_root, DirHandlerMode::ReadOnly).unwrap();
+    let mut upstream = HttpService::new(SharedDirHandler::from(upstream_handler));
+    task::block_on(upstream.reload(&upstream_conf)).unwrap();
+
+    let yaml = format!(
+        r#"
+tftp_server_dir: {}
+ifaces: [lo]
+http_port: {port}
+mirrors:
+    ubuntu/casper: http://127.0.0.1:{upstream_port}/casper/
+default:
+    boot_file: /bootfile
+    "#,
+        root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+
+    let response = get(port, "GET /ubuntu/casper/vmlinuz HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nkernel"));
+    assert_eq!(std::fs::read(root.join("ubuntu/casper/vmlinuz")).unwrap(), b"kernel");
+
+    let response = get(port, "GET /ubuntu/casper/initrd HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    // Served from the cache once the mirror is gone
+    task::block_on(upstream.stop());
+    let response = get(port, "GET /ubuntu/casper/vmlinuz HTTP/1.0\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nkernel"));
+    let response = get(port, "GET /ubuntu/casper/initrd HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+
+    task::block_on(service.stop());
+    std::fs::remove_dir_all(&upstream_root).unwrap();
+    std::fs::remove_dir_all(&root).unwrap();
+}
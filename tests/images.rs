@@ -0,0 +1,184 @@
+extern crate preboot_oxide;
+
+use async_std::task;
+use async_tftp::server::Handler;
+use futures::AsyncReadExt;
+use preboot_oxide::{
+    conf::{Conf, ImageSource},
+    http::HttpService,
+    images::{sync_image, IMAGES_DIR},
+    tftp::{DirHandler, DirHandlerMode, SharedDirHandler},
+    tracker::BootTracker,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+mod utils;
+
+fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[test]
+fn test_images_are_fetched_verified_and_versioned() {
+    let dir = std::env::temp_dir().join(format!("po-images-{}", std::process::id()));
+    let (source, store) = (dir.join("source"), dir.join("store"));
+    fs::create_dir_all(&source).unwrap();
+    fs::create_dir_all(&store).unwrap();
+    fs::write(source.join("vmlinuz"), b"kernel v1").unwrap();
+    fs::write(
+        source.join("SHA256SUMS"),
+        format!("{}  ./vmlinuz\n", sha256(b"kernel v1")),
+    )
+    .unwrap();
+
+    let port = 10000 + (std::process::id() % 10000) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: {}
+ifaces: [lo]
+http_port: {port}
+default:
+    boot_file: /bootfile
+    "#,
+        source.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    let handler = DirHandler::new(&source, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+
+    let url = format!("http://127.0.0.1:{port}");
+    let mut image = ImageSource {
+        path: PathBuf::from("linux/vmlinuz"),
+        url: format!("{url}/vmlinuz"),
+        sha256: None,
+        sha256sums_url: Some(format!("{url}/SHA256SUMS")),
+        minisign_key: None,
+    };
+    let served = store.join(IMAGES_DIR).join("linux/vmlinuz");
+    let versions = store.join(IMAGES_DIR).join(".versions/linux/vmlinuz");
+
+    assert!(task::block_on(sync_image(&store, &image, 2, &[])).unwrap());
+    assert_eq!(fs::read(&served).unwrap(), b"kernel v1");
+    assert!(!task::block_on(sync_image(&store, &image, 2, &[])).unwrap());
+
+    // Newer remote content failing verification is not served
+    std::thread::sleep(Duration::from_millis(1100));
+    fs::write(source.join("vmlinuz"), b"kernel v2").unwrap();
+    assert!(task::block_on(sync_image(&store, &image, 2, &[])).is_err());
+    assert_eq!(fs::read(&served).unwrap(), b"kernel v1");
+
+    image.sha256 = Some(sha256(b"kernel v2"));
+    assert!(task::block_on(sync_image(&store, &image, 2, &[])).unwrap());
+    assert_eq!(fs::read(&served).unwrap(), b"kernel v2");
+
+    std::thread::sleep(Duration::from_millis(1100));
+    fs::write(source.join("vmlinuz"), b"kernel v3").unwrap();
+    image.sha256 = Some(sha256(b"kernel v3"));
+    assert!(task::block_on(sync_image(&store, &image, 2, &[])).unwrap());
+    assert_eq!(fs::read(&served).unwrap(), b"kernel v3");
+
+    let mut kept: Vec<String> = fs::read_dir(&versions)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    kept.sort();
+    let mut expected = vec![sha256(b"kernel v2"), sha256(b"kernel v3")];
+    expected.sort();
+    assert_eq!(kept, expected);
+
+    task::block_on(service.stop());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_pinned_versions_are_kept_and_served() {
+    let dir = std::env::temp_dir().join(format!("po-images-pinned-{}", std::process::id()));
+    let (source, store) = (dir.join("source"), dir.join("store"));
+    fs::create_dir_all(&source).unwrap();
+    fs::create_dir_all(&store).unwrap();
+    fs::write(source.join("vmlinuz"), b"kernel v1").unwrap();
+
+    let port = 20000 + (std::process::id() % 10000) as u16;
+    let yaml = format!(
+        r#"
+tftp_server_dir: {}
+ifaces: [lo]
+http_port: {port}
+default:
+    boot_file: /bootfile
+match:
+    - select:
+        ClientMacAddress: 52:54:00:12:34:56
+      conf:
+        pinned_images:
+          linux/vmlinuz: {}
+    "#,
+        store.display(),
+        sha256(b"kernel v1")
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    let handler = DirHandler::new(&source, DirHandlerMode::ReadOnly).unwrap();
+    let mut service = HttpService::new(SharedDirHandler::from(handler));
+    task::block_on(service.reload(&conf)).unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+
+    let image = ImageSource {
+        path: PathBuf::from("linux/vmlinuz"),
+        url: format!("http://127.0.0.1:{port}/vmlinuz"),
+        sha256: None,
+        sha256sums_url: None,
+        minisign_key: None,
+    };
+    let pinned = conf.get_pinned_versions(&image.path);
+    assert_eq!(pinned, vec![sha256(b"kernel v1")]);
+    let versions = store.join(IMAGES_DIR).join(".versions/linux/vmlinuz");
+
+    assert!(task::block_on(sync_image(&store, &image, 1, &pinned)).unwrap());
+    // The ETag of the served version is sent along when checking for updates
+    assert!(versions.join(".etag").is_file());
+    assert!(!task::block_on(sync_image(&store, &image, 1, &pinned)).unwrap());
+
+    std::thread::sleep(Duration::from_millis(1100));
+    fs::write(source.join("vmlinuz"), b"kernel v2").unwrap();
+    assert!(task::block_on(sync_image(&store, &image, 1, &pinned)).unwrap());
+    assert!(versions.join(sha256(b"kernel v1")).is_file());
+    assert!(versions.join(sha256(b"kernel v2")).is_file());
+
+    let tracker = Arc::new(BootTracker::new(10));
+    tracker.boot_info_sent(Ipv4Addr::LOCALHOST, "52:54:00:12:34:56", 1, "/bootfile", "eth0");
+    let mut handler = DirHandler::new(&store, DirHandlerMode::ReadOnly)
+        .unwrap()
+        .with_tracker(tracker)
+        .with_conf(Arc::new(conf));
+    let mut read = |client: SocketAddr| {
+        task::block_on(async {
+            let (mut reader, _) = handler
+                .read_req_open(&client, Path::new("images/linux/vmlinuz"))
+                .await
+                .unwrap();
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content).await.unwrap();
+            content
+        })
+    };
+    assert_eq!(read((Ipv4Addr::LOCALHOST, 4000).into()), b"kernel v1");
+    assert_eq!(read((Ipv4Addr::new(127, 0, 0, 2), 4000).into()), b"kernel v2");
+
+    task::block_on(service.stop());
+    let _ = fs::remove_dir_all(&dir);
+}
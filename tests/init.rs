@@ -0,0 +1,66 @@
+extern crate preboot_oxide;
+
+use preboot_oxide::{
+    conf::Conf,
+    init::{self, Interface},
+};
+use std::io::Cursor;
+
+#[test]
+fn test_init_wizard() {
+    let root = std::env::temp_dir().join(format!("po-init-{}", std::process::id()));
+    let path = root.join("preboot-oxide.yaml");
+    let tftp_dir = root.join("tftp root");
+    let interfaces = [Interface {
+        name: "eth1".into(),
+        ips: vec!["10.0.0.2".parse().unwrap()],
+    }];
+
+    // Default interface, no DHCP server, the TFTP directory created
+    let answers = format!("\nn\n{}\ny\nbootx64.efi\n", tftp_dir.display());
+    let mut output = Vec::new();
+    init::run(&mut Cursor::new(answers), &mut output, &interfaces, &path).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("  eth1: 10.0.0.2"));
+    assert!(output.contains("Warning: clients won't boot until a DHCP server"));
+    assert!(output.contains("Note: copy bootx64.efi"));
+    assert!(tftp_dir.is_dir());
+
+    let conf = Conf::from_config_file(Some(&path)).unwrap();
+    assert!(conf.validate().is_ok());
+    assert_eq!(conf.get_ifaces(), Some(&vec!["eth1".to_string()]));
+    assert_eq!(conf.get_tftp_serve_path(), Some(tftp_dir.display().to_string()));
+    let entry = conf.get_from_doc(serde_json::json!({})).unwrap().unwrap();
+    assert_eq!(entry.boot_file, Some(&"bootx64.efi".to_string()));
+
+    // Left as it is unless told to overwrite it
+    let written = std::fs::read_to_string(&path).unwrap();
+    init::run(&mut Cursor::new("\n"), &mut Vec::new(), &interfaces, &path).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), written);
+
+    let answers = format!("y\nall\ny\n{}\nnetboot.xyz\n", tftp_dir.display());
+    init::run(&mut Cursor::new(answers), &mut Vec::new(), &interfaces, &path).unwrap();
+    let conf = Conf::from_config_file(Some(&path)).unwrap();
+    assert!(conf.validate().is_ok());
+    assert!(conf.get_ifaces().is_none());
+    assert!(conf.get_netbootxyz());
+
+    // Running out of answers
+    let answers = "y\neth1\n";
+    assert!(init::run(&mut Cursor::new(answers), &mut Vec::new(), &interfaces, &path).is_err());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_starter_yaml() {
+    let yaml = init::starter_yaml(&["eth0".into()], "/srv/tftp", "1.0");
+    assert_eq!(
+        yaml,
+        "# Written by preboot-oxide init\n\
+         version: 1\n\
+         ifaces: [eth0]\n\
+         tftp_server_dir: /srv/tftp\n\
+         default:\n  boot_file: \"1.0\"\n"
+    );
+}
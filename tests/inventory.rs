@@ -0,0 +1,133 @@
+extern crate preboot_oxide;
+
+use preboot_oxide::{
+    conf::Conf,
+    inventory::{self, NetBox},
+    template,
+};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+mod utils;
+
+/// NetBox answering the device lookups with `answer` for the request line
+/// received, sending the request lines to the returned channel.
+fn netbox(answer: fn(&str) -> &'static str) -> (String, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut reader = BufReader::new(stream.unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut authorized = false;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                authorized |= line.trim() == "Authorization: Token s3cret";
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+
+            let body = if authorized {
+                answer(&request_line)
+            } else {
+                ""
+            };
+            let response = format!(
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: application/json\r\n\
+                 Content-Length: {}\r\n\
+                 Connection: close\r\n\r\n{body}",
+                body.len()
+            );
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            let _ = sender.send(request_line);
+        }
+    });
+
+    (url, receiver)
+}
+
+fn devices(request_line: &str) -> &'static str {
+    if request_line.contains("mac_address=52%3A54%3A00%3A00%3A00%3A01") {
+        r#"{"count": 1, "results": [{"name": "db-01", "config_context": {
+            "preboot_oxide": {"boot_file": "/db", "vars": {"role": "db"}}}}]}"#
+    } else if request_line.contains("serial=SN-42") {
+        r#"{"count": 1, "results": [{"name": "web-01", "config_context": {
+            "preboot_oxide": {"boot_file": "/web", "vars": {"hostname": "www"}}}}]}"#
+    } else {
+        r#"{"count": 0, "results": []}"#
+    }
+}
+
+#[test]
+fn test_netbox_lookup() {
+    let (url, requests) = netbox(devices);
+    let token = "s3cret".to_string();
+    let netbox = NetBox::new(&url, Some(&token), Duration::from_secs(2));
+    let ttl = Duration::from_secs(60);
+
+    let doc = template::client_doc(template::parse_mac("52:54:00:00:00:01").as_ref(), None, None);
+    let entry = inventory::lookup(&netbox, ttl, &doc).unwrap().unwrap();
+    assert_eq!(entry.boot_file, Some("/db".to_string()));
+    assert_eq!(entry.vars.get("role").map(String::as_str), Some("db"));
+    assert_eq!(entry.vars.get("hostname").map(String::as_str), Some("db-01"));
+    assert!(requests.recv().unwrap().starts_with("GET /api/dcim/devices/?mac_address="));
+
+    // Answered from the cache
+    let entry = inventory::lookup(&netbox, ttl, &doc).unwrap().unwrap();
+    assert_eq!(entry.boot_file, Some("/db".to_string()));
+    assert!(requests.try_recv().is_err());
+
+    let doc = template::client_doc(
+        template::parse_mac("52:54:00:00:00:02").as_ref(),
+        None,
+        Some("SN-42"),
+    );
+    let entry = inventory::lookup(&netbox, ttl, &doc).unwrap().unwrap();
+    assert_eq!(entry.boot_file, Some("/web".to_string()));
+    assert_eq!(entry.vars.get("hostname").map(String::as_str), Some("www"));
+    assert_eq!(requests.iter().take(2).count(), 2);
+
+    let doc = template::client_doc(template::parse_mac("52:54:00:00:00:03").as_ref(), None, None);
+    assert!(inventory::lookup(&netbox, ttl, &doc).unwrap().is_none());
+
+    // Unauthorized lookups are answered with an empty body
+    inventory::clear_cache();
+    let netbox = NetBox::new(&url, None, Duration::from_secs(2));
+    assert!(inventory::lookup(&netbox, ttl, &doc).is_err());
+}
+
+#[test]
+fn test_netbox_conf() {
+    let yaml = r#"
+tftp_server_dir: /tftpdir
+netbox_url: netbox.lab
+    "#;
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+
+    let yaml = r#"
+tftp_server_dir: /tftpdir
+netbox_url: https://netbox.lab
+netbox_token: s3cret
+inventory_cache_ttl: 30
+default:
+    boot_file: /default
+    "#;
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_ok());
+    assert_eq!(conf.get_netbox_token(), Some(&"s3cret".to_string()));
+    assert_eq!(conf.get_inventory_cache_ttl(), Duration::from_secs(30));
+    assert_eq!(inventory::from_conf(&conf).unwrap().name(), "https://netbox.lab");
+}
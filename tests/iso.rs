@@ -0,0 +1,88 @@
+extern crate preboot_oxide;
+
+use preboot_oxide::iso::{IsoFile, IsoImage, SECTOR_SIZE};
+use std::path::Path;
+
+mod utils;
+
+const SECTOR: usize = SECTOR_SIZE as usize;
+
+fn dir_record(extent: u32, size: u32, is_dir: bool, name: &[u8], system_use: &[u8]) -> Vec<u8> {
+    let padding = 1 - name.len() % 2;
+    let mut record = vec![0u8; 33];
+    record[0] = (33 + name.len() + padding + system_use.len()) as u8;
+    record[2..6].copy_from_slice(&extent.to_le_bytes());
+    record[6..10].copy_from_slice(&extent.to_be_bytes());
+    record[10..14].copy_from_slice(&size.to_le_bytes());
+    record[14..18].copy_from_slice(&size.to_be_bytes());
+    record[25] = if is_dir { 0x02 } else { 0 };
+    record[32] = name.len() as u8;
+    record.extend_from_slice(name);
+    record.extend(std::iter::repeat_n(0, padding));
+    record.extend_from_slice(system_use);
+    record
+}
+
+fn rock_ridge_name(name: &str) -> Vec<u8> {
+    let mut entry = vec![b'N', b'M', (5 + name.len()) as u8, 1, 0];
+    entry.extend_from_slice(name.as_bytes());
+    entry
+}
+
+/// Builds an image holding `BOOT/VMLINUZ.;1`, named `boot/vmlinuz` with Rock Ridge.
+fn build_image(rock_ridge: bool) -> Vec<u8> {
+    let content = b"kernel image content";
+    let mut image = vec![0u8; SECTOR * 21];
+    let su = |name: &str| if rock_ridge { rock_ridge_name(name) } else { vec![] };
+    let sharing_protocol = if rock_ridge {
+        vec![b'S', b'P', 7, 1, 0xBE, 0xEF, 0]
+    } else {
+        vec![]
+    };
+
+    let root = dir_record(18, SECTOR as u32, true, &[0], &[]);
+    image[16 * SECTOR] = 1;
+    image[16 * SECTOR + 1..16 * SECTOR + 6].copy_from_slice(b"CD001");
+    image[16 * SECTOR + 156..16 * SECTOR + 156 + root.len()].copy_from_slice(&root);
+    image[17 * SECTOR] = 255;
+    image[17 * SECTOR + 1..17 * SECTOR + 6].copy_from_slice(b"CD001");
+
+    let root_entries = [
+        dir_record(18, SECTOR as u32, true, &[0], &sharing_protocol),
+        dir_record(18, SECTOR as u32, true, &[1], &[]),
+        dir_record(19, SECTOR as u32, true, b"BOOT", &su("boot")),
+    ]
+    .concat();
+    image[18 * SECTOR..18 * SECTOR + root_entries.len()].copy_from_slice(&root_entries);
+
+    let boot_entries = [
+        dir_record(19, SECTOR as u32, true, &[0], &[]),
+        dir_record(18, SECTOR as u32, true, &[1], &[]),
+        dir_record(20, content.len() as u32, false, b"VMLINUZ.;1", &su("vmlinuz")),
+    ]
+    .concat();
+    image[19 * SECTOR..19 * SECTOR + boot_entries.len()].copy_from_slice(&boot_entries);
+    image[20 * SECTOR..20 * SECTOR + content.len()].copy_from_slice(content);
+
+    image
+}
+
+#[test]
+fn test_find_files_in_iso_image() {
+    for rock_ridge in [false, true] {
+        let image = utils::MockFile::from_bytes(&build_image(rock_ridge), "iso");
+        let mut iso = IsoImage::open(&image.path).unwrap();
+
+        let file = iso.find(Path::new("boot/vmlinuz")).unwrap();
+        assert_eq!(
+            file,
+            Some(IsoFile {
+                offset: 20 * SECTOR_SIZE,
+                size: 20
+            })
+        );
+        assert_eq!(iso.find(Path::new("/BOOT/VMLINUZ")).unwrap(), file);
+        assert_eq!(iso.find(Path::new("boot")).unwrap(), None);
+        assert_eq!(iso.find(Path::new("boot/initrd")).unwrap(), None);
+    }
+}
@@ -0,0 +1,63 @@
+extern crate preboot_oxide;
+
+use preboot_oxide::{locate, overrides::Overrides};
+
+mod utils;
+
+fn places(search: &locate::Search) -> Vec<(String, Option<String>)> {
+    search
+        .probes
+        .iter()
+        .map(|probe| (probe.place.clone(), probe.rejected.clone()))
+        .collect()
+}
+
+#[test]
+fn test_config_flag() {
+    let yaml_mock = utils::YamlMockFile::from_yaml("default:\n    boot_file: ipxe.efi\n");
+    let path = yaml_mock.path.display().to_string();
+    let search = locate::search(Some(&path), Some("/etc/other.yaml"), false, &Overrides::default());
+    let (conf, origin) = search.loaded.as_ref().unwrap();
+    assert!(conf.to_yaml().unwrap().contains("ipxe.efi"));
+    assert_eq!(origin, &path);
+    let probes = places(&search);
+    assert_eq!(probes[0], (format!("--config {path}"), None));
+    assert_eq!(
+        probes[1],
+        ("PO_CONF_PATH=/etc/other.yaml".to_string(), Some("overridden by --config".to_string()))
+    );
+    assert_eq!(probes[2].1.as_deref(), Some("not looked in, a configuration is given"));
+    assert!(locate::report(&search).ends_with(&format!("Loaded from {path}.\n")));
+}
+
+#[test]
+fn test_config_fallbacks() {
+    // A missing file leaves the values of --set
+    let sets = Overrides::parse(&["default.boot_file=ipxe.efi".to_string()]).unwrap();
+    let search = locate::search(None, Some("/nonexistent/preboot-oxide.yaml"), false, &sets);
+    assert_eq!(search.loaded.as_ref().unwrap().1, "--set");
+    let probes = places(&search);
+    assert_eq!(probes[0], ("--config".to_string(), Some("not given".to_string())));
+    assert!(probes[1].1.as_deref().unwrap().contains("No such file"));
+    assert_eq!(probes.last().unwrap(), &("--set".to_string(), None));
+
+    // An invalid one the environment variables
+    let yaml_mock = utils::YamlMockFile::from_yaml("default: [\n");
+    let path = yaml_mock.path.display().to_string();
+    let search = locate::search(Some(&path), None, false, &sets);
+    assert_eq!(search.loaded.as_ref().unwrap().1, "environment variables");
+    let probes = places(&search);
+    assert!(probes[0].1.is_some());
+    assert_eq!(probes[1], ("PO_CONF_PATH".to_string(), Some("not set".to_string())));
+    assert_eq!(
+        probes[3],
+        ("--set".to_string(), Some("only used without a configuration file".to_string()))
+    );
+    assert_eq!(probes[4], ("environment variables".to_string(), None));
+
+    // The default files aren't looked for in a container
+    let search = locate::search(None, None, true, &Overrides::default());
+    assert_eq!(places(&search)[2].1.as_deref(), Some("not looked in, in container mode"));
+    assert!(locate::report(&search).contains("  --set: no values given\n"));
+}
+
@@ -0,0 +1,141 @@
+extern crate preboot_oxide;
+
+use std::time::{Duration, Instant};
+
+use async_std::task;
+use log::{log_enabled, Level, LevelFilter, Record};
+use preboot_oxide::logging::{self, Chatter, LogFormat, TaskClient, CHATTER_INTERVAL};
+
+#[test]
+fn test_client_level() {
+    logging::init("warn", LogFormat::Text).unwrap();
+    let mac = [0x52, 0x54, 0, 0, 0, 1];
+    let enabled = || log_enabled!(target: "preboot_oxide::dhcp", Level::Trace);
+    assert!(!enabled());
+
+    task::block_on(async {
+        logging::set_client_level(mac, Some("10.0.0.5".parse().unwrap()), Some(LevelFilter::Trace));
+        assert!(enabled());
+        // Only the messages of the service are raised
+        assert!(!log_enabled!(target: "async_io::reactor", Level::Trace));
+    });
+
+    // The next tasks handling the client, by its MAC or IP address
+    task::block_on(async {
+        assert!(!enabled());
+        logging::enter_mac(mac);
+        assert_eq!(logging::task_level(), LevelFilter::Trace);
+    });
+    task::block_on(async {
+        logging::enter_ip("10.0.0.5".parse().unwrap());
+        assert!(enabled());
+        logging::enter_ip("10.0.0.6".parse().unwrap());
+        assert!(enabled());
+    });
+    task::block_on(async {
+        logging::enter_ip("10.0.0.6".parse().unwrap());
+        assert!(!enabled());
+    });
+
+    // Cleared once the client isn't matched anymore
+    task::block_on(async {
+        logging::set_client_level(mac, Some("10.0.0.5".parse().unwrap()), None);
+        assert!(!enabled());
+    });
+    task::block_on(async {
+        logging::enter_mac(mac);
+        assert!(!enabled());
+    });
+}
+
+#[test]
+fn test_json_line() {
+    let time = time::OffsetDateTime::from_unix_timestamp(1_714_566_600).unwrap();
+    let line = logging::json_line(
+        time,
+        &Record::builder()
+            .level(Level::Warn)
+            .target("preboot_oxide::tftp")
+            .args(format_args!("File \"{}\" not found", "bootx64.efi"))
+            .build(),
+        &TaskClient::default(),
+    );
+    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(
+        value,
+        serde_json::json!({
+            "time": "2024-05-01T12:30:00Z",
+            "level": "WARN",
+            "target": "preboot_oxide::tftp",
+            "subsystem": "tftp",
+            "message": "File \"bootx64.efi\" not found",
+        })
+    );
+    assert!(!line.contains('\n'));
+}
+
+#[test]
+fn test_json_line_client() {
+    let time = time::OffsetDateTime::from_unix_timestamp(1_714_566_600).unwrap();
+    let client = task::block_on(async {
+        logging::enter_mac([0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+        logging::enter_xid(0x1234);
+        logging::task_client()
+    });
+    let line = logging::json_line(
+        time,
+        &Record::builder()
+            .level(Level::Info)
+            .target("async_tftp::server")
+            .args(format_args!("Offer sent"))
+            .build(),
+        &client,
+    );
+    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(value["subsystem"], "async_tftp");
+    assert_eq!(value["mac"], "52:54:00:12:34:56");
+    assert_eq!(value["xid"], "0x00001234");
+    assert_eq!(value["session"], "940d5785");
+    assert!(value.get("client_ip").is_none());
+
+    // Each task handles its own client
+    assert_eq!(task::block_on(async { logging::task_client() }), TaskClient::default());
+}
+
+#[test]
+fn test_session_id() {
+    let mac = [0x52, 0x54, 0, 0x12, 0x34, 0x56];
+    assert_eq!(logging::session_id(&mac, 0x1234), "940d5785");
+    assert_ne!(logging::session_id(&mac, 0x1235), logging::session_id(&mac, 0x1234));
+    assert_eq!(TaskClient { mac: Some(mac), ..Default::default() }.session_id(), None);
+
+    // Futures polled outside of the tasks of async-std
+    let client = TaskClient { mac: Some(mac), xid: Some(0x1234), ip: None };
+    let polled = futures::executor::block_on(logging::with_client(client, async { logging::task_client() }));
+    assert_eq!(polled.session_id().as_deref(), Some("940d5785"));
+    assert_eq!(logging::task_client(), TaskClient::default());
+}
+
+#[test]
+fn test_chatter() {
+    let mut chatter = Chatter::default();
+    let start = Instant::now();
+    let second = |secs: u64| start + Duration::from_secs(secs);
+    assert_eq!(chatter.ignored(Some([0x52, 0x54, 0, 0, 0, 1]), start), None);
+    assert_eq!(chatter.ignored(Some([0x52, 0x54, 0, 0, 0, 2]), second(10)), None);
+    assert_eq!(chatter.ignored(Some([0x52, 0x54, 0, 0, 0, 1]), second(20)), None);
+    assert_eq!(chatter.ignored(None, second(30)), None);
+    assert_eq!(chatter.flush(second(59)), None);
+    assert_eq!(
+        chatter.ignored(None, second(61)).as_deref(),
+        Some("Ignored 5 non-PXE DHCP messages from 2 clients in the last 61s.")
+    );
+
+    // Counting over
+    assert_eq!(chatter.flush(second(200)), None);
+    assert_eq!(chatter.ignored(Some([0x52, 0x54, 0, 0, 0, 1]), second(200)), None);
+    assert_eq!(
+        chatter.flush(second(200) + CHATTER_INTERVAL).as_deref(),
+        Some("Ignored 1 non-PXE DHCP message from 1 client in the last 60s.")
+    );
+}
@@ -0,0 +1,87 @@
+extern crate preboot_oxide;
+
+use preboot_oxide::{
+    conf::Conf,
+    migrate::{self, Migration, CONF_VERSION},
+};
+use yaml_rust2::{Yaml, YamlLoader};
+
+mod utils;
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 2,
+        moves: &[
+            ("boot_file", "default.boot_file"),
+            ("match.*.conf.label", "match.*.conf.menu_label"),
+        ],
+    },
+    Migration {
+        version: 3,
+        moves: &[("default.boot_file", "default.file")],
+    },
+];
+
+fn load(yaml: &str) -> Yaml {
+    YamlLoader::load_from_str(yaml).unwrap().remove(0)
+}
+
+#[test]
+fn test_migrate() {
+    let mut document = load(
+        r#"
+boot_file: ipxe.efi
+match:
+  - select: {Serial: lab}
+    conf: {label: Lab}
+  - select: {Serial: ci}
+"#,
+    );
+    let changes = migrate::migrate(&mut document, MIGRATIONS, 2).unwrap();
+    assert_eq!(
+        changes,
+        [
+            "Moved boot_file to default.boot_file (version 2)",
+            "Moved match.*.conf.label to match.*.conf.menu_label (version 2)"
+        ]
+    );
+    assert_eq!(document["default"]["boot_file"].as_str(), Some("ipxe.efi"));
+    assert!(document["boot_file"].is_badvalue());
+    assert_eq!(document["match"][0]["conf"]["menu_label"].as_str(), Some("Lab"));
+    assert!(document["match"][1]["conf"].is_badvalue());
+
+    // Only the migrations after the version of the document
+    let mut document = load("version: 2\nboot_file: ipxe.efi\n");
+    assert!(migrate::migrate(&mut document, MIGRATIONS, 2).unwrap().is_empty());
+    assert_eq!(document["boot_file"].as_str(), Some("ipxe.efi"));
+
+    // Set on both sides, nothing silently dropped
+    let mut document = load("boot_file: a.efi\ndefault:\n  boot_file: b.efi\n");
+    assert!(migrate::migrate(&mut document, MIGRATIONS, 2).is_err());
+
+    for invalid in ["version: 4", "version: 0", "version: latest"] {
+        assert!(migrate::migrate(&mut load(invalid), MIGRATIONS, 3).is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn test_migrate_file() {
+    let yaml = "tftp_server_dir: /tftpdir\ndefault:\n  boot_file: ipxe.efi\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let changes = migrate::migrate_file(&yaml_mock.path).unwrap();
+    assert_eq!(changes, [format!("Set version {CONF_VERSION}")]);
+    let migrated = std::fs::read_to_string(&yaml_mock.path).unwrap();
+    assert!(migrated.starts_with(&format!("---\nversion: {CONF_VERSION}\n")));
+    let backup = migrate::backup_path(&yaml_mock.path);
+    assert_eq!(std::fs::read_to_string(&backup).unwrap(), yaml);
+    std::fs::remove_file(&backup).unwrap();
+
+    // Up to date
+    assert!(migrate::migrate_file(&yaml_mock.path).unwrap().is_empty());
+    assert!(!backup.exists());
+
+    // Configurations newer than the release aren't loaded
+    let yaml_mock = utils::YamlMockFile::from_yaml(&format!("version: {}", CONF_VERSION + 1));
+    assert!(Conf::from_config_file(Some(&yaml_mock.path)).is_err());
+    assert!(migrate::migrate_file(&yaml_mock.path).is_err());
+}
@@ -0,0 +1,141 @@
+extern crate preboot_oxide;
+
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+};
+
+use preboot_oxide::{
+    conf::Conf,
+    events::{ClientEvent, Event},
+    mqtt::{self, Connection, MqttBroker, Settings},
+};
+
+mod utils;
+
+/// Reads an MQTT packet, returning its header byte and its body.
+fn read_packet(stream: &mut impl Read) -> (u8, Vec<u8>) {
+    let mut byte = [0u8];
+    stream.read_exact(&mut byte).unwrap();
+    let header = byte[0];
+    let (mut len, mut shift) = (0usize, 0);
+    loop {
+        stream.read_exact(&mut byte).unwrap();
+        len |= usize::from(byte[0] & 0x7F) << shift;
+        shift += 7;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).unwrap();
+    (header, body)
+}
+
+#[test]
+fn test_publish() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let broker = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let connect = read_packet(&mut stream);
+        stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+        let publish = read_packet(&mut stream);
+        (connect, publish)
+    });
+
+    let settings = Settings {
+        broker: format!("mqtt://127.0.0.1:{port}").parse().unwrap(),
+        client_id: "preboot-oxide".to_string(),
+        username: Some("ha".to_string()),
+        password: Some("s3cret".to_string()),
+    };
+    let mut connection = Connection::open(&settings).unwrap();
+    let payload = "x".repeat(200);
+    connection
+        .publish("preboot-oxide/offer_sent", &payload, true)
+        .unwrap();
+
+    let ((connect_header, connect), (publish_header, publish)) = broker.join().unwrap();
+    assert_eq!(connect_header, 0x10);
+    assert_eq!(&connect[..7], b"\0\x04MQTT\x04");
+    // User name, password and clean session
+    assert_eq!(connect[7], 0xC2);
+    assert_eq!(&connect[10..], b"\0\x0dpreboot-oxide\0\x02ha\0\x06s3cret");
+    // Retained, QoS 0
+    assert_eq!(publish_header, 0x31);
+    assert_eq!(&publish[..26], b"\0\x18preboot-oxide/offer_sent");
+    assert_eq!(&publish[26..], payload.as_bytes());
+}
+
+#[test]
+fn test_refused_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let broker = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        read_packet(&mut stream);
+        stream.write_all(&[0x20, 2, 0, 4]).unwrap();
+    });
+    let settings = Settings {
+        broker: format!("127.0.0.1:{port}").parse().unwrap(),
+        client_id: "preboot-oxide".to_string(),
+        username: None,
+        password: None,
+    };
+    let e = Connection::open(&settings).err().unwrap();
+    assert!(e.to_string().contains("bad user name or password"));
+    broker.join().unwrap();
+}
+
+#[test]
+fn test_topic() {
+    let client = ClientEvent {
+        mac_address: "aa:bb:cc:dd:ee:ff".to_string(),
+        xid: 0x1234,
+        ..Default::default()
+    };
+    assert_eq!(
+        mqtt::topic(mqtt::DEFAULT_MQTT_TOPIC, Event::BootFileDelivered, &client),
+        "preboot-oxide/boot_file_delivered"
+    );
+    assert_eq!(
+        mqtt::topic("pxe/{mac}/{session}/{event}", Event::OfferSent, &client),
+        "pxe/aa:bb:cc:dd:ee:ff/14f31c04/offer_sent"
+    );
+}
+
+#[test]
+fn test_broker() {
+    let broker: MqttBroker = "homeassistant.local".parse().unwrap();
+    assert_eq!(
+        (broker.host.as_str(), broker.port),
+        ("homeassistant.local", 1883)
+    );
+    assert_eq!(broker.to_string(), "mqtt://homeassistant.local:1883");
+    let broker: MqttBroker = "mqtt://[::1]:8883".parse().unwrap();
+    assert_eq!(broker.to_string(), "mqtt://[::1]:8883");
+    assert!("mqtts://broker.lab".parse::<MqttBroker>().is_err());
+    assert!("broker.lab:port".parse::<MqttBroker>().is_err());
+}
+
+#[test]
+fn test_mqtt_conf() {
+    let yaml = "tftp_server_dir: /tftpdir\nmqtt_broker: 10.0.0.2\nmqtt_topic: pxe/{mac}/{event}\nmqtt_username: ha\nmqtt_password: s3cret\nmqtt_retain: true\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_ok());
+    assert_eq!(conf.get_mqtt_topic(), "pxe/{mac}/{event}");
+    assert!(conf.get_mqtt_retain());
+    assert!(!conf.to_yaml().unwrap().contains("s3cret"));
+
+    let yaml =
+        "tftp_server_dir: /tftpdir\nmqtt_topic: pxe/{event}\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+    let yaml = "tftp_server_dir: /tftpdir\nmqtt_broker: 10.0.0.2\nmqtt_topic: pxe/#\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+}
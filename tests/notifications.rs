@@ -0,0 +1,191 @@
+extern crate preboot_oxide;
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener},
+};
+
+use preboot_oxide::{
+    conf::Conf,
+    events::{ClientEvent, Event},
+    notifications::{self, NotificationKind, SmtpServer},
+};
+
+mod utils;
+
+fn client() -> ClientEvent {
+    ClientEvent {
+        mac_address: "52:54:00:12:34:56".to_string(),
+        xid: 0x1234,
+        ip: Some(Ipv4Addr::new(10, 0, 0, 5)),
+        iface: Some("eth0".to_string()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_message() {
+    assert_eq!(
+        notifications::message(None, Event::UnknownClient, &client()),
+        "Unknown client 52:54:00:12:34:56 (10.0.0.5) requested PXE boot on eth0"
+    );
+    let client = ClientEvent {
+        ip: None,
+        boot_file: Some("ipxe.efi".to_string()),
+        ..client()
+    };
+    assert_eq!(
+        notifications::message(Some("lab2"), Event::BootFileDelivered, &client),
+        "[lab2] 52:54:00:12:34:56 downloaded ipxe.efi on eth0"
+    );
+    assert_eq!(
+        notifications::message(None, Event::UpstreamDhcpMissing, &client),
+        "No DHCP server offered an address to the last 3 clients on eth0, 52:54:00:12:34:56 the last"
+    );
+    assert_eq!(
+        notifications::details(Event::BootFileDelivered, &client),
+        "Event: boot_file_delivered\nMAC address: 52:54:00:12:34:56\nXID: 0x00001234\nSession: 940d5785\nBoot file: ipxe.efi\nInterface: eth0"
+    );
+    assert_eq!(
+        notifications::payload(NotificationKind::Slack, "hi"),
+        serde_json::json!({"text": "hi"})
+    );
+    assert_eq!(
+        notifications::payload(NotificationKind::Discord, "hi"),
+        serde_json::json!({"content": "hi"})
+    );
+}
+
+#[test]
+fn test_send_mail() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let relay = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut lines = Vec::new();
+        stream.write_all(b"220 mail.lab ESMTP\r\n").unwrap();
+        let mut in_data = false;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+            let line = line.trim_end().to_string();
+            let answer: &[u8] = match line.as_str() {
+                "." if in_data => {
+                    in_data = false;
+                    b"250 Queued\r\n"
+                }
+                _ if in_data => b"",
+                "DATA" => {
+                    in_data = true;
+                    b"354 Go ahead\r\n"
+                }
+                "QUIT" => b"221 Bye\r\n",
+                _ if line.starts_with("EHLO") => b"250-mail.lab\r\n250 8BITMIME\r\n",
+                _ => b"250 OK\r\n",
+            };
+            stream.write_all(answer).unwrap();
+            lines.push(line);
+        }
+        lines
+    });
+
+    let server: SmtpServer = format!("smtp://127.0.0.1:{port}").parse().unwrap();
+    let to = [
+        "ops@lab.example".to_string(),
+        "oncall@lab.example".to_string(),
+    ];
+    notifications::send_mail(
+        &server,
+        "pxe@lab.example",
+        &to,
+        "Unknown client",
+        "Unknown client\n.dotted\n\nEvent: unknown_client",
+    )
+    .unwrap();
+
+    let lines = relay.join().unwrap();
+    assert_eq!(lines[0], "EHLO [127.0.0.1]");
+    assert_eq!(lines[1], "MAIL FROM:<pxe@lab.example>");
+    assert_eq!(lines[2], "RCPT TO:<ops@lab.example>");
+    assert_eq!(lines[3], "RCPT TO:<oncall@lab.example>");
+    assert_eq!(lines[4], "DATA");
+    assert!(lines.contains(&"To: <ops@lab.example>, <oncall@lab.example>".to_string()));
+    assert!(lines.contains(&"Subject: Unknown client".to_string()));
+    // Dot-stuffed
+    assert!(lines.contains(&"..dotted".to_string()));
+    assert_eq!(lines[lines.len() - 2..], [".", "QUIT"]);
+}
+
+#[test]
+fn test_refused_mail() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let relay = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        stream.write_all(b"220 mail.lab ESMTP\r\n").unwrap();
+        for answer in ["250 mail.lab\r\n", "550 Sender rejected\r\n"] {
+            reader.read_line(&mut String::new()).unwrap();
+            stream.write_all(answer.as_bytes()).unwrap();
+        }
+    });
+    let server: SmtpServer = format!("smtp://127.0.0.1:{port}").parse().unwrap();
+    let e = notifications::send_mail(
+        &server,
+        "pxe@lab.example",
+        &["ops@lab.example".to_string()],
+        "Hi",
+        "Hi",
+    )
+    .err()
+    .unwrap();
+    assert!(format!("{e:#}").contains("550 Sender rejected"));
+    relay.join().unwrap();
+}
+
+#[test]
+fn test_smtp_server() {
+    let server: SmtpServer = "smtp://mail.lab".parse().unwrap();
+    assert_eq!((server.host.as_str(), server.port), ("mail.lab", 25));
+    let server: SmtpServer = "smtp://[::1]:2525".parse().unwrap();
+    assert_eq!((server.host.as_str(), server.port), ("::1", 2525));
+    assert!("mail.lab".parse::<SmtpServer>().is_err());
+    assert!("smtps://mail.lab".parse::<SmtpServer>().is_err());
+}
+
+#[test]
+fn test_notifications_conf() {
+    let yaml = "tftp_server_dir: /tftpdir\nnotifications:\n    - kind: slack\n      url: https://hooks.slack.com/services/T0/B0/s3cret\n      events: [unknown_client]\n    - kind: smtp\n      url: smtp://mail.lab\n      from: pxe@lab.example\n      to: [ops@lab.example]\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_ok());
+    let notifications = conf.get_notifications();
+    assert_eq!(notifications[0].kind, NotificationKind::Slack);
+    assert!(notifications[0].takes(Event::UnknownClient));
+    assert!(!notifications[0].takes(Event::OfferSent));
+    assert!(notifications[1].takes(Event::OfferSent));
+    assert_eq!(notifications[1].to, ["ops@lab.example"]);
+    assert!(conf.to_yaml().unwrap().contains("kind: smtp"));
+
+    // Mails need their sender and recipients, which only they take
+    let invalid = [
+        "notifications:\n    - kind: smtp\n      url: smtp://mail.lab\n      to: [ops@lab.example]\n",
+        "notifications:\n    - kind: smtp\n      url: https://mail.lab\n      from: pxe@lab.example\n      to: [ops@lab.example]\n",
+        "notifications:\n    - kind: smtp\n      url: smtp://mail.lab\n      from: pxe@lab.example\n      to: [\"ops@lab.example>\\r\\nBcc: <x@y\"]\n",
+        "notifications:\n    - kind: discord\n      url: https://discord.com/api/webhooks/1/x\n      to: [ops@lab.example]\n",
+        "notifications:\n    - kind: discord\n      url: discord.com/api/webhooks/1/x\n",
+    ];
+    for fields in invalid {
+        let yaml =
+            format!("tftp_server_dir: /tftpdir\n{fields}default:\n    boot_file: /default\n");
+        let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+        let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+        assert!(conf.validate().is_err(), "{fields}");
+    }
+    let yaml = "tftp_server_dir: /tftpdir\nnotifications:\n    - kind: teams\n      url: https://teams.lab\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    assert!(Conf::from_config_file(Some(&yaml_mock.path)).is_err());
+}
@@ -0,0 +1,61 @@
+#![cfg(target_os = "linux")]
+extern crate preboot_oxide;
+
+use std::{
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    process,
+    sync::Arc,
+    time::Duration,
+};
+
+use preboot_oxide::notify::{self, Notifier};
+
+fn receive(systemd: &UnixDatagram) -> Option<String> {
+    let mut buf = [0u8; 64];
+    let len = systemd.recv(&mut buf).ok()?;
+    Some(String::from_utf8_lossy(&buf[..len]).to_string())
+}
+
+#[test]
+fn test_watchdog_interval() {
+    let interval = notify::watchdog_interval(Some("30000000"), Some("42"), 42);
+    assert_eq!(interval, Some(Duration::from_secs(15)));
+    assert!(notify::watchdog_interval(Some("30000000"), None, 42).is_some());
+    // Meant for another process
+    assert!(notify::watchdog_interval(Some("30000000"), Some("41"), 42).is_none());
+    assert!(notify::watchdog_interval(Some("0"), None, 42).is_none());
+    assert!(notify::watchdog_interval(None, Some("42"), 42).is_none());
+}
+
+#[test]
+fn test_notifications() {
+    let name = format!("preboot-oxide-test-{}", process::id());
+    let systemd = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+    systemd.set_nonblocking(true).unwrap();
+    let notifier = Arc::new(Notifier::new(
+        Some(format!("@{name}").into()),
+        Some(Duration::from_secs(15)),
+    ));
+    let (first, second) = (notifier.register(), notifier.register());
+    notifier.start();
+    assert_eq!(first.interval(), Some(Duration::from_secs(15)));
+
+    // Ready once every DHCP loop has bound its sockets
+    assert!(!first.bound());
+    assert_eq!(receive(&systemd), None);
+    assert!(second.bound());
+    assert_eq!(receive(&systemd).as_deref(), Some("READY=1\nSTATUS=Serving"));
+    assert!(second.bound());
+    assert_eq!(receive(&systemd), None);
+
+    // A loop not going around keeps the watchdog from being pinged
+    assert!(!first.beat());
+    assert!(!first.beat());
+    assert_eq!(receive(&systemd), None);
+    assert!(second.beat());
+    assert_eq!(receive(&systemd).as_deref(), Some("WATCHDOG=1"));
+    assert!(!second.beat());
+}
@@ -0,0 +1,58 @@
+extern crate preboot_oxide;
+
+use std::{net::Ipv4Addr, path::Path, sync::Arc, time::Duration};
+
+use async_std::{future::timeout, task};
+use clap::Parser;
+use preboot_oxide::{
+    cli::Cli,
+    oneshot::{Oneshot, Target},
+    tracker::BootTracker,
+};
+
+#[test]
+fn test_target() {
+    assert_eq!("3".parse::<Target>().unwrap(), Target::Clients(3));
+    let target: Target = "52:54:00:12:34:56, 52-54-00-12-34-57".parse().unwrap();
+    assert_eq!(
+        target,
+        Target::Macs([[0x52, 0x54, 0, 0x12, 0x34, 0x56], [0x52, 0x54, 0, 0x12, 0x34, 0x57]].into())
+    );
+    assert_eq!(target.to_string(), "52:54:00:12:34:56, 52:54:00:12:34:57");
+    assert_eq!(Target::Clients(1).to_string(), "1 client");
+    assert!("0".parse::<Target>().is_err());
+    assert!("52:54:00:12:34".parse::<Target>().is_err());
+
+    let parse = |args: &[&str]| Cli::try_parse_from([&["preboot-oxide"], args].concat());
+    assert_eq!(parse(&[]).unwrap().oneshot, None);
+    assert_eq!(parse(&["--oneshot"]).unwrap().oneshot, Some(Target::Clients(1)));
+    assert_eq!(parse(&["serve", "--oneshot=2"]).unwrap().oneshot, Some(Target::Clients(2)));
+    assert!(parse(&["--oneshot=none"]).is_err());
+}
+
+#[test]
+fn test_oneshot_clients() {
+    let oneshot = Oneshot::new(Target::Clients(2));
+    assert!(!oneshot.served("52:54:00:12:34:56"));
+    // Counted once
+    assert!(!oneshot.served("52:54:00:12:34:56"));
+    assert!(oneshot.served("52:54:00:12:34:57"));
+
+    let oneshot = Oneshot::new("52:54:00:12:34:57".parse().unwrap());
+    assert!(!oneshot.served("52:54:00:12:34:56"));
+    assert!(oneshot.served("52:54:00:12:34:57"));
+}
+
+#[test]
+fn test_oneshot_tracker() {
+    let oneshot = Arc::new(Oneshot::new(Target::Clients(1)));
+    let tracker = BootTracker::new(10).with_oneshot(Arc::clone(&oneshot));
+    let ip = Ipv4Addr::new(10, 0, 0, 10);
+    tracker.boot_info_sent(ip, "52:54:00:12:34:56", 1234, "ipxe.efi", "eth0");
+
+    // Files other than the boot file don't count
+    tracker.file_delivered(ip, Path::new("grub.cfg"));
+    assert!(task::block_on(timeout(Duration::from_millis(100), oneshot.finished())).is_err());
+    tracker.file_delivered(ip, Path::new("ipxe.efi"));
+    assert!(task::block_on(timeout(Duration::from_secs(10), oneshot.finished())).is_ok());
+}
@@ -0,0 +1,68 @@
+extern crate preboot_oxide;
+
+use preboot_oxide::{conf::Conf, overrides::Overrides, remote::ConfSource, template};
+
+mod utils;
+
+fn overrides(args: &[&str]) -> Overrides {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    Overrides::parse(&args).unwrap()
+}
+
+#[test]
+fn test_overrides_parse() {
+    assert!(overrides(&[]).is_empty());
+    for invalid in ["boot_file", "default..boot_file=x", "=x", "ifaces=[eth0"] {
+        assert!(Overrides::parse(&[invalid.to_string()]).is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn test_conf_overrides() {
+    let yaml = r#"
+tftp_server_dir: /tftpdir
+default:
+    boot_file: /default
+match:
+    - select:
+        ClientMacAddress: 52:54:00:00:00:01
+      conf:
+        boot_file: /match
+    "#;
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let source = ConfSource::File(yaml_mock.path.clone());
+    let sets = overrides(&[
+        "default.boot_file=ipxe.efi",
+        "ifaces=[eth0, eth1]",
+        "match.0.conf.boot_file=/override",
+        "http_port=8080",
+    ]);
+    let conf = Conf::from_source_with(&source, &sets).unwrap();
+    assert!(conf.validate().is_ok());
+    assert_eq!(conf.get_ifaces(), Some(&vec!["eth0".to_string(), "eth1".to_string()]));
+    assert_eq!(conf.get_http_port(), Some(8080));
+    let boot_file = |conf: &Conf, mac: &str| {
+        let doc = template::client_doc(template::parse_mac(mac).as_ref(), None, None);
+        conf.get_from_doc(doc).unwrap().unwrap().boot_file.cloned()
+    };
+    assert_eq!(boot_file(&conf, "52:54:00:00:00:01"), Some("/override".to_string()));
+    assert_eq!(boot_file(&conf, "52:54:00:00:00:02"), Some("ipxe.efi".to_string()));
+
+    // Checked as the fields of the file are
+    let error = Conf::from_source_with(&source, &overrides(&["default.boot_flie=x"]))
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("Unknown key default.boot_flie, did you mean boot_file?"));
+    let error = Conf::from_source_with(&source, &overrides(&["match.1.conf.boot_file=x"]))
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("No entry 1 in a list of 1, applying --set match.1.conf.boot_file"));
+    let sets = overrides(&["tftp_server_dir.path=/srv"]);
+    assert!(Conf::from_source_with(&source, &sets).is_err());
+
+    // Without a configuration file
+    let sets = overrides(&["tftp_server_dir=/srv/tftp", "default.boot_file=ipxe.efi"]);
+    let conf = Conf::from_overrides(&sets).unwrap();
+    assert!(conf.validate().is_ok());
+    assert_eq!(conf.get_tftp_serve_path(), Some("/srv/tftp".to_string()));
+}
@@ -0,0 +1,124 @@
+extern crate preboot_oxide;
+
+use std::net::SocketAddrV4;
+
+use preboot_oxide::{conf::Conf, pcap::Capture};
+
+mod utils;
+
+/// The type and body of each block of the pcapng file `data`.
+fn blocks(data: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    let mut blocks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let block_type = u32::from_le_bytes(rest[0..4].try_into().unwrap());
+        let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+        assert_eq!(rest[len - 4..len], rest[4..8]);
+        blocks.push((block_type, rest[8..len - 4].to_vec()));
+        rest = &rest[len..];
+    }
+    blocks
+}
+
+#[test]
+fn test_capture() {
+    let dir = std::env::temp_dir().join(format!("po-pcap-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("boot.pcapng");
+    let client: SocketAddrV4 = "0.0.0.0:68".parse().unwrap();
+    let broadcast: SocketAddrV4 = "255.255.255.255:67".parse().unwrap();
+    let server: SocketAddrV4 = "10.0.0.1:67".parse().unwrap();
+
+    let mut capture = Capture::create(&path, 1024, 3).unwrap();
+    capture
+        .write("eth0", client, broadcast, b"discover")
+        .unwrap();
+    capture
+        .write("eth1", client, broadcast, b"discover")
+        .unwrap();
+    capture
+        .write(
+            "eth0",
+            server,
+            "255.255.255.255:68".parse().unwrap(),
+            b"offer",
+        )
+        .unwrap();
+
+    let first = blocks(&std::fs::read(&path).unwrap());
+    let types: Vec<u32> = first.iter().map(|(block_type, _)| *block_type).collect();
+    assert_eq!(types, [0x0A0D0D0A, 1, 6, 1, 6, 6]);
+    assert_eq!(first[0].1[0..4], 0x1A2B3C4Du32.to_le_bytes());
+    // LINKTYPE_RAW and the name of the interface
+    assert_eq!(first[1].1[0..2], 101u16.to_le_bytes());
+    assert_eq!(&first[1].1[12..16], b"eth0");
+    assert_eq!(&first[3].1[12..16], b"eth1");
+
+    let packet = |body: &[u8]| -> (u32, Vec<u8>) {
+        let iface = u32::from_le_bytes(body[0..4].try_into().unwrap());
+        let len = u32::from_le_bytes(body[12..16].try_into().unwrap()) as usize;
+        (iface, body[20..20 + len].to_vec())
+    };
+    let (iface, offer) = packet(&first[5].1);
+    assert_eq!(iface, 0);
+    assert_eq!(offer.len(), 20 + 8 + 5);
+    assert_eq!(offer[0], 0x45);
+    assert_eq!(offer[9], 17);
+    assert_eq!(offer[12..16], [10, 0, 0, 1]);
+    assert_eq!(offer[16..20], [255, 255, 255, 255]);
+    assert_eq!(offer[20..24], [0, 67, 0, 68]);
+    assert_eq!(&offer[28..], b"offer");
+    // The header sums up to 0xFFFF with its checksum
+    let sum: u32 = offer[..20]
+        .chunks(2)
+        .map(|w| u32::from(u16::from_be_bytes([w[0], w[1]])))
+        .sum();
+    assert_eq!((sum & 0xFFFF) + (sum >> 16), 0xFFFF);
+    assert_eq!(packet(&first[4].1).0, 1);
+
+    // Rotated once full, the interfaces described again
+    for _ in 0..20 {
+        capture.write("eth1", client, broadcast, &[0; 64]).unwrap();
+    }
+    let rotated = path.with_extension("pcapng.1");
+    assert!(rotated.exists());
+    assert!(std::fs::metadata(&path).unwrap().len() <= 1024);
+    let types: Vec<u32> = blocks(&std::fs::read(&path).unwrap())
+        .iter()
+        .map(|(block_type, _)| *block_type)
+        .collect();
+    assert_eq!(types[..3], [0x0A0D0D0A, 1, 6]);
+    for _ in 0..40 {
+        capture.write("eth1", client, broadcast, &[0; 64]).unwrap();
+    }
+    assert!(path.with_extension("pcapng.2").exists());
+    assert!(!path.with_extension("pcapng.3").exists());
+
+    // A capture found on start is rotated rather than overwritten
+    let previous = std::fs::read(&path).unwrap();
+    drop(capture);
+    Capture::create(&path, 1024, 3).unwrap();
+    assert_eq!(std::fs::read(&rotated).unwrap(), previous);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 28);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_pcap_conf() {
+    let yaml = "tftp_server_dir: /tftpdir\npcap_file: /var/log/preboot-oxide/boot.pcapng\npcap_max_size: 50\npcap_tftp: true\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert_eq!(conf.get_pcap_max_size(), 50 * 1024 * 1024);
+    assert_eq!(conf.get_pcap_files(), 5);
+    assert!(conf.get_pcap_tftp());
+
+    let yaml = "tftp_server_dir: /tftpdir\npcap_tftp: true\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+    let yaml = "tftp_server_dir: /tftpdir\npcap_file: boot.pcapng\npcap_files: 0\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+}
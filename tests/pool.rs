@@ -0,0 +1,26 @@
+extern crate preboot_oxide;
+
+use preboot_oxide::pool::BufferPool;
+
+#[test]
+fn test_buffer_pool() {
+    let pool = BufferPool::new(576, 2);
+    let mut buf = pool.take();
+    assert!(buf.is_empty());
+    assert!(buf.capacity() >= 576);
+    buf.extend_from_slice(&[1, 2, 3]);
+    drop(buf);
+    assert_eq!(pool.kept(), 1);
+
+    // Given back empty, its capacity kept
+    let buf = pool.take();
+    assert!(buf.is_empty());
+    assert!(buf.capacity() >= 576);
+    assert_eq!(pool.kept(), 0);
+
+    // No more than `max_kept` are kept
+    let taken: Vec<_> = (0..4).map(|_| pool.take()).collect();
+    drop(taken);
+    drop(buf);
+    assert_eq!(pool.kept(), 2);
+}
@@ -0,0 +1,39 @@
+#![cfg(unix)]
+extern crate preboot_oxide;
+
+use preboot_oxide::{
+    conf::Conf,
+    privileges::{self, Account},
+};
+
+mod utils;
+
+#[test]
+fn test_lookup() {
+    let root = Account { uid: 0, gid: 0 };
+    assert_eq!(privileges::lookup("root", None).unwrap(), root);
+    assert_eq!(privileges::lookup("0", None).unwrap(), root);
+    assert_eq!(privileges::lookup("root", Some("0")).unwrap(), root);
+    assert_eq!(privileges::lookup("root", Some("4242")).unwrap(), Account { uid: 0, gid: 4242 });
+    // IDs without an account, as in containers
+    assert_eq!(privileges::lookup("4242", Some("4242")).unwrap(), Account { uid: 4242, gid: 4242 });
+    assert!(privileges::lookup("4242", None).is_err());
+    assert!(privileges::lookup("preboot-oxide-nobody", None).is_err());
+    assert!(privileges::lookup("root", Some("preboot-oxide-nobody")).is_err());
+}
+
+#[test]
+fn test_user_config() {
+    let yaml = "user: nobody\ngroup: nogroup\ndefault:\n    boot_file: ipxe.efi\n    boot_server_ipv4: 10.0.0.1\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert_eq!(conf.get_user(), Some("nobody"));
+    assert_eq!(conf.get_group(), Some("nogroup"));
+    conf.validate().unwrap();
+
+    let yaml_mock = utils::YamlMockFile::from_yaml(
+        "group: nogroup\ndefault:\n    boot_file: ipxe.efi\n    boot_server_ipv4: 10.0.0.1\n",
+    );
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+}
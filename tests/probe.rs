@@ -0,0 +1,73 @@
+extern crate preboot_oxide;
+
+use std::net::Ipv4Addr;
+
+use dhcproto::v4::{DhcpOption, Message, MessageType, Opcode, OptionCode};
+use preboot_oxide::probe;
+
+fn offer(xid: u32, server: [u8; 4]) -> Message {
+    let mut offer = Message::default();
+    offer
+        .set_opcode(Opcode::BootReply)
+        .set_xid(xid)
+        .set_yiaddr([10, 0, 0, 57]);
+    offer
+        .opts_mut()
+        .insert(DhcpOption::MessageType(MessageType::Offer));
+    offer
+        .opts_mut()
+        .insert(DhcpOption::ServerIdentifier(server.into()));
+    offer
+}
+
+#[test]
+fn test_probe_answers() {
+    let mac = [0x52, 0x54, 0, 0x12, 0x34, 0x56];
+    let discover = probe::discover_message(&mac, 7);
+    assert_eq!(discover.opts().msg_type(), Some(MessageType::Discover));
+    // Answered by PXE servers, preboot-oxide included
+    let Some(DhcpOption::ParameterRequestList(params)) =
+        discover.opts().get(OptionCode::ParameterRequestList)
+    else {
+        panic!("No parameter request list");
+    };
+    assert!(params.contains(&OptionCode::BootfileName));
+    assert!(discover.opts().get(OptionCode::ClassIdentifier).is_some());
+
+    let dhcp = probe::parse_answer(&offer(7, [10, 0, 0, 1]), 7, [10, 0, 0, 1].into()).unwrap();
+    assert_eq!(dhcp.offered_ip, Some(Ipv4Addr::new(10, 0, 0, 57)));
+    assert!(!dhcp.has_boot_info());
+    assert!(probe::parse_answer(&offer(8, [10, 0, 0, 1]), 7, [10, 0, 0, 1].into()).is_none());
+
+    let mut pxe_offer = offer(7, [10, 0, 0, 2]);
+    pxe_offer.set_siaddr([10, 0, 0, 2]);
+    pxe_offer
+        .opts_mut()
+        .insert(DhcpOption::BootfileName(b"ipxe.efi".to_vec()));
+    pxe_offer
+        .opts_mut()
+        .insert(DhcpOption::ClassIdentifier(b"PXEClient".to_vec()));
+    let pxe = probe::parse_answer(&pxe_offer, 7, [10, 0, 0, 3].into()).unwrap();
+    assert_eq!(pxe.server, Ipv4Addr::new(10, 0, 0, 2));
+    assert_eq!(pxe.boot_file.as_deref(), Some("ipxe.efi"));
+    assert_eq!(pxe.boot_server.as_deref(), Some("10.0.0.2"));
+    assert!(pxe.is_pxe);
+
+    assert_eq!(
+        probe::report(&[dhcp, pxe.clone()]),
+        "Server 10.0.0.1\n  \
+           Offered address: 10.0.0.57\n  \
+           Boot information: none\n  \
+           Options: MessageType (53), ServerIdentifier (54)\n\
+         Server 10.0.0.2, PXE\n  \
+           Offered address: 10.0.0.57\n  \
+           Boot information: ipxe.efi from 10.0.0.2\n  \
+           Options: MessageType (53), ServerIdentifier (54), ClassIdentifier (60), \
+           BootfileName (67)\n"
+    );
+    let competing = probe::report(&[pxe.clone(), pxe]);
+    assert!(competing.ends_with(
+        "Warning: 2 servers give boot information, the clients may boot from either.\n"
+    ));
+    assert_eq!(probe::report(&[]), "No DHCP server answered.\n");
+}
@@ -0,0 +1,98 @@
+extern crate preboot_oxide;
+
+use std::time::{Duration, Instant};
+
+use preboot_oxide::quota::{Acquired, QuotaMap};
+
+#[test]
+fn test_quota_map() {
+    let now = Instant::now();
+    let mut quotas = QuotaMap::new([("eth1", 2), ("eth2", 0)], None);
+    assert!(quotas.acquire(&"eth1", now).is_counted());
+    assert!(quotas.acquire(&"eth1", now).is_counted());
+    assert!(!quotas.acquire(&"eth1", now).is_counted());
+    assert!(!quotas.acquire(&"eth2", now).is_counted());
+    assert_eq!(quotas.used(&"eth1"), 2);
+
+    // Released uses can be acquired again
+    quotas.release(&"eth1");
+    assert!(quotas.acquire(&"eth1", now).is_counted());
+
+    // Keys without a quota are unlimited, unless given a default
+    for _ in 0..10 {
+        assert!(quotas.acquire(&"eth3", now).is_counted());
+    }
+    assert_eq!(quotas.limit(&"eth3"), None);
+    let mut quotas = QuotaMap::new([("eth1", 3)], Some(1));
+    assert!(quotas.acquire(&"eth3", now).is_counted());
+    assert!(!quotas.acquire(&"eth3", now).is_counted());
+    assert_eq!(quotas.limit(&"eth1"), Some(3));
+
+    // Releasing what isn't used does nothing
+    quotas.release(&"eth4");
+    assert_eq!(quotas.used(&"eth4"), 0);
+}
+
+#[test]
+fn test_quota_map_expiry() {
+    let start = Instant::now();
+    let ttl = Duration::from_secs(120);
+    let mut quotas = QuotaMap::new([], None).with_ttl(ttl);
+    assert_eq!(quotas.acquire(&1, start), Acquired::Counted);
+    assert_eq!(quotas.acquire(&2, start), Acquired::Counted);
+    assert!(quotas.expire(start + Duration::from_secs(60)).is_empty());
+
+    // Using a key puts off its expiry
+    quotas.touch(&2, start + Duration::from_secs(60));
+    assert!(quotas.is_expired(&1, start + ttl));
+    assert!(!quotas.is_expired(&2, start + ttl));
+    assert_eq!(quotas.expire(start + ttl), vec![1]);
+    assert_eq!((quotas.len(), quotas.used(&1)), (1, 0));
+    assert_eq!(quotas.expire(start + ttl + Duration::from_secs(60)), vec![2]);
+    assert!(quotas.is_empty());
+
+    // Without a TTL nothing expires
+    let mut quotas = QuotaMap::new([], None);
+    quotas.acquire(&1, start);
+    assert!(quotas.expire(start + Duration::from_secs(3600)).is_empty());
+}
+
+#[test]
+fn test_quota_map_eviction() {
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+    let mut quotas = QuotaMap::new([], None).with_capacity(3);
+    for key in 1..=3 {
+        assert_eq!(quotas.acquire(&key, at(key)), Acquired::Counted);
+    }
+    // More uses of a key counted don't evict
+    assert_eq!(quotas.acquire(&3, at(4)), Acquired::Counted);
+
+    // The least recently used key makes room for a new one
+    quotas.touch(&1, at(5));
+    assert_eq!(quotas.acquire(&4, at(6)), Acquired::Evicted(2));
+    assert_eq!(quotas.least_recently_used(|_| true), Some(3));
+    assert_eq!(quotas.least_recently_used(|key| *key != 3), Some(1));
+
+    // The counts stay consistent, the evicted key starting over
+    assert_eq!(quotas.len(), 3);
+    assert_eq!((quotas.used(&1), quotas.used(&2), quotas.used(&3)), (1, 0, 2));
+    quotas.release(&3);
+    quotas.release(&3);
+    assert_eq!(quotas.len(), 2);
+    assert_eq!(quotas.acquire(&2, at(7)), Acquired::Counted);
+    assert_eq!(quotas.used(&2), 1);
+    quotas.release(&2);
+    assert_eq!(quotas.used(&2), 0);
+
+    // Keys used at the same instant are evicted in the order they're used
+    let mut quotas = QuotaMap::new([], None).with_capacity(2);
+    quotas.acquire(&1, start);
+    quotas.acquire(&2, start);
+    assert_eq!(quotas.acquire(&3, start), Acquired::Evicted(1));
+
+    // With no room at all, nothing is counted
+    let mut quotas = QuotaMap::new([], None).with_capacity(0);
+    assert_eq!(quotas.acquire(&1, start), Acquired::Refused);
+    assert!(quotas.is_empty());
+}
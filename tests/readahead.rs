@@ -0,0 +1,35 @@
+extern crate preboot_oxide;
+
+use async_std::task;
+use futures::{io::Cursor, AsyncReadExt};
+use preboot_oxide::readahead::{BufferPool, ReadAhead};
+
+#[test]
+fn test_read_ahead_preserves_content_and_recycles_buffers() {
+    let pool = BufferPool::new(1000, 1);
+    let content: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+
+    let received = task::block_on(async {
+        let mut reader = ReadAhead::new(Cursor::new(content.clone()), &pool);
+        let mut received = Vec::new();
+        let mut block = [0u8; 512];
+        loop {
+            let len = reader.read(&mut block).await.unwrap();
+            if len == 0 {
+                break;
+            }
+            received.extend_from_slice(&block[..len]);
+        }
+        received
+    });
+    assert_eq!(received, content);
+    assert_eq!(pool.idle_buffers(), 1);
+
+    // Only up to the idle limit is kept
+    let first = ReadAhead::new(Cursor::new(vec![]), &pool);
+    let second = ReadAhead::new(Cursor::new(vec![]), &pool);
+    assert_eq!(pool.idle_buffers(), 0);
+    drop(first);
+    drop(second);
+    assert_eq!(pool.idle_buffers(), 1);
+}
@@ -0,0 +1,91 @@
+extern crate preboot_oxide;
+
+use std::{net::SocketAddr, path::Path, sync::Arc};
+
+use async_std::task;
+use async_tftp::server::Handler;
+use preboot_oxide::{
+    conf::Conf,
+    dhcp,
+    dns::DnsService,
+    http::HttpService,
+    images::ImageService,
+    overrides::Overrides,
+    reload::{self, Services},
+    remote::ConfSource,
+    template,
+    tftp::TftpService,
+    tracker::BootTracker,
+};
+
+mod utils;
+
+#[test]
+fn test_reload_applies_the_changed_file() {
+    let dir = |name: &str| {
+        let dir = std::env::temp_dir().join(format!("po-reload-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(format!("{name}.efi")), name).unwrap();
+        dir
+    };
+    let (first, second) = (dir("first"), dir("second"));
+    // TFTP disabled, the directory is still served to HTTP without binding port 69
+    let yaml = |tftp_dir: &Path, boot_file: &str| {
+        format!(
+            "tftp_server_dir: {}\ntftp:\n    enabled: false\nmatch:\n    - select:\n        ClientMacAddress: 52:54:00:00:00:01\n      conf:\n        boot_file: {boot_file}\n        boot_server_ipv4: 10.0.0.2\n",
+            tftp_dir.display()
+        )
+    };
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml(&first, "/first.efi"));
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    conf.validate().unwrap();
+
+    let mut tftp = TftpService::new(Arc::new(BootTracker::new(10)));
+    task::block_on(tftp.reload(&conf)).unwrap();
+    let handler = tftp.handler();
+    let services = Services {
+        shared_conf: Arc::new(std::sync::RwLock::new(Arc::new(conf))),
+        http: HttpService::new(tftp.handler()),
+        images: ImageService::new(),
+        dns: DnsService::default(),
+        tftp,
+    };
+    let shared_conf = Arc::clone(&services.shared_conf);
+    let (reloader, requests) = reload::reloader();
+    task::spawn(reload::watch_config(
+        ConfSource::File(yaml_mock.path.clone()),
+        Overrides::default(),
+        vec![(None, services)],
+        requests,
+    ));
+
+    let boot_file = || {
+        let doc = template::client_doc(template::parse_mac("52:54:00:00:00:01").as_ref(), None, None);
+        let conf = dhcp::current_conf(&shared_conf);
+        conf.get_from_doc(doc).unwrap().and_then(|entry| entry.boot_file.cloned())
+    };
+    let serves = |path: &str| {
+        let client: SocketAddr = "10.0.0.5:1024".parse().unwrap();
+        task::block_on(handler.clone().read_req_open(&client, Path::new(path))).is_ok()
+    };
+    assert_eq!(boot_file().as_deref(), Some("/first.efi"));
+    assert!(serves("first.efi") && !serves("second.efi"));
+
+    std::fs::write(&yaml_mock.path, yaml(&second, "/second.efi")).unwrap();
+    task::block_on(reloader.reload()).unwrap();
+    assert_eq!(
+        dhcp::current_conf(&shared_conf).get_tftp_serve_path(),
+        Some(second.display().to_string())
+    );
+    assert_eq!(boot_file().as_deref(), Some("/second.efi"));
+    assert!(serves("second.efi") && !serves("first.efi"));
+
+    // Invalid, the previous configuration is kept
+    std::fs::write(&yaml_mock.path, "tftp_server_dir: [unclosed\n").unwrap();
+    assert!(task::block_on(reloader.reload()).is_err());
+    assert_eq!(boot_file().as_deref(), Some("/second.efi"));
+    assert!(serves("second.efi"));
+
+    std::fs::remove_dir_all(first).unwrap();
+    std::fs::remove_dir_all(second).unwrap();
+}
@@ -0,0 +1,119 @@
+extern crate preboot_oxide;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use preboot_oxide::{
+    conf::{Conf, ConfFormat},
+    remote::ConfSource,
+};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    thread,
+};
+
+/// Serves the configuration answered by `answer` for the request line and
+/// body received, on the returned address.
+fn server(answer: fn(&str, &str) -> (&'static str, String)) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut reader = BufReader::new(stream.unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(len) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = len.trim().parse().unwrap();
+                }
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let (status, content) = answer(&request_line, &String::from_utf8(body).unwrap());
+            let response = format!(
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{content}",
+                content.len()
+            );
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    addr
+}
+
+const YAML: &str = "tftp_server_dir: /tftpdir\ndefault:\n  boot_file: /remote\n";
+
+fn answer(request_line: &str, body: &str) -> (&'static str, String) {
+    match request_line.split(' ').nth(1).unwrap_or_default() {
+        "/fleet/conf.yaml" => ("200 OK", YAML.to_string()),
+        "/fleet/conf.json" => ("200 OK", r#"{"default": {"boot_file": "/json"}}"#.to_string()),
+        "/v1/kv/pxe/conf?raw=" => ("200 OK", YAML.to_string()),
+        "/v3/kv/range" if body.contains(&BASE64_STANDARD.encode("/pxe/conf")) => (
+            "200 OK",
+            serde_json::json!({"kvs": [{"value": BASE64_STANDARD.encode(YAML)}]}).to_string(),
+        ),
+        "/v3/kv/range" => ("200 OK", r#"{"count": "0"}"#.to_string()),
+        _ => ("404 Not Found", String::new()),
+    }
+}
+
+#[test]
+fn test_conf_source_parse() {
+    let source: ConfSource = "/etc/preboot-oxide.toml".parse().unwrap();
+    assert_eq!(source, ConfSource::File("/etc/preboot-oxide.toml".into()));
+    assert_eq!(source.format(), ConfFormat::Toml);
+    assert!(!source.is_remote());
+
+    let source: ConfSource = "https://conf.lab/pxe.json?ref=main".parse().unwrap();
+    assert_eq!(source.format(), ConfFormat::Json);
+    assert!(source.is_remote());
+
+    let source: ConfSource = "etcd://etcd.lab:2379/pxe/conf".parse().unwrap();
+    assert_eq!(
+        source,
+        ConfSource::Etcd {
+            endpoint: "http://etcd.lab:2379".into(),
+            key: "/pxe/conf".into()
+        }
+    );
+    assert_eq!(source.to_string(), "etcd://etcd.lab:2379/pxe/conf");
+    assert_eq!(source.format(), ConfFormat::Yaml);
+
+    let source: ConfSource = "consul://consul.lab:8500/pxe/conf.toml".parse().unwrap();
+    assert_eq!(source.to_string(), "consul://consul.lab:8500/pxe/conf.toml");
+    assert_eq!(source.format(), ConfFormat::Toml);
+
+    assert!("consul://consul.lab:8500".parse::<ConfSource>().is_err());
+    assert!("etcd:///pxe/conf".parse::<ConfSource>().is_err());
+}
+
+#[test]
+fn test_remote_conf() {
+    let addr = server(answer);
+    for (source, boot_file) in [
+        (format!("http://{addr}/fleet/conf.yaml"), "/remote"),
+        (format!("http://{addr}/fleet/conf.json"), "/json"),
+        (format!("etcd://{addr}/pxe/conf"), "/remote"),
+        (format!("consul://{addr}/pxe/conf"), "/remote"),
+    ] {
+        let source: ConfSource = source.parse().unwrap();
+        let conf = Conf::from_source(&source).unwrap();
+        let entry = conf.get_from_doc(serde_json::json!({})).unwrap().unwrap();
+        assert_eq!(entry.boot_file, Some(&boot_file.to_string()), "{source}");
+    }
+
+    for source in [
+        format!("http://{addr}/missing.yaml"),
+        format!("etcd://{addr}/missing"),
+        format!("consul://{addr}/missing"),
+    ] {
+        let source: ConfSource = source.parse().unwrap();
+        assert!(Conf::from_source(&source).is_err(), "{source}");
+    }
+}
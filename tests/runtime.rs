@@ -0,0 +1,65 @@
+extern crate preboot_oxide;
+
+use std::{
+    net::{Ipv4Addr, UdpSocket},
+    path::Path,
+    sync::Arc,
+};
+
+use async_std::task;
+use async_tftp::server::TftpServerBuilder;
+use preboot_oxide::{
+    conf::{Conf, ConfFormat},
+    dhcp::{self, DhcpService, SessionMap},
+    runtime,
+    tftp::{DirHandler, DirHandlerMode},
+    tftp_get,
+    tracker::BootTracker,
+};
+
+#[test]
+fn test_services_run_on_the_runtime() {
+    let tokio = cfg!(feature = "tokio-runtime");
+    assert_eq!(runtime::name(), if tokio { "Tokio" } else { "async-std" });
+    let on_worker = task::block_on(runtime::spawn(async {
+        std::thread::current().name() == Some("po-worker")
+    }));
+    assert_eq!(on_worker, tokio);
+    let pending = runtime::spawn(futures::future::pending::<()>());
+    assert_eq!(task::block_on(pending.cancel()), None);
+
+    // A TFTP transfer served by a task of the runtime
+    let root = std::env::temp_dir().join(format!("po-runtime-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let content: Vec<u8> = (0..1300).map(|i| (i % 251) as u8).collect();
+    std::fs::write(root.join("ipxe.efi"), &content).unwrap();
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let server_addr = socket.local_addr().unwrap();
+    let handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let server = task::block_on(
+        TftpServerBuilder::with_handler(handler)
+            .std_socket(socket)
+            .unwrap()
+            .build(),
+    )
+    .unwrap();
+    let server = runtime::spawn(server.serve());
+    let output = root.join("downloaded");
+    task::block_on(tftp_get::download(server_addr, Path::new("ipxe.efi"), Some(&output))).unwrap();
+    assert_eq!(std::fs::read(&output).unwrap(), content);
+    task::block_on(server.cancel());
+
+    // The DHCP service started and stopped with its tasks on the runtime
+    let yaml = "ifaces: [po-missing0]\ndefault:\n    boot_file: ipxe.efi\n    boot_server_ipv4: 10.0.0.2\n";
+    let conf = Conf::from_text(yaml, ConfFormat::Yaml).unwrap();
+    let tracker = Arc::new(BootTracker::new(conf.get_max_sessions()));
+    let sessions = Arc::new(SessionMap::new(&conf));
+    let interfaces = dhcp::bind_interfaces(&conf).unwrap();
+    task::block_on(async {
+        let service = DhcpService::start(Arc::new(Arc::new(conf)), interfaces, tracker, sessions);
+        assert!(service.interfaces().is_empty());
+        service.stop().await;
+    });
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,51 @@
+extern crate preboot_oxide;
+
+use std::path::{Path, PathBuf};
+
+use preboot_oxide::{conf::Conf, sandbox};
+
+mod utils;
+
+#[test]
+fn test_sandbox_access() {
+    let token = utils::MockFile::from_bytes(b"0123456789abcdef", "token");
+    let yaml = format!(
+        "sandbox: true\ntftp_server_dir: /srv/tftp\nhttps_cert: /etc/ssl/po.pem\nhttps_key: /etc/ssl/po.key\n\
+        control_socket: /run/po/control.sock\nnetbox_token: file:{}\ndefault:\n    boot_file: ipxe.efi\n",
+        token.path.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.get_sandbox());
+
+    let access = sandbox::access(&conf, Some(&yaml_mock.path), Some(Path::new("/run/po.pid")));
+    for path in ["/etc", "/etc/ssl/po.pem", "/etc/ssl/po.key"] {
+        assert!(access.read.contains(&PathBuf::from(path)), "{path} not readable");
+    }
+    assert!(access.read.contains(&token.path));
+    assert!(access.read.contains(&yaml_mock.path));
+    assert!(access.read.contains(&Conf::include_dir(&yaml_mock.path)));
+    assert_eq!(access.write, vec![PathBuf::from("/srv/tftp")]);
+    assert_eq!(access.remove, vec![PathBuf::from("/run"), PathBuf::from("/run/po")]);
+    assert!(!access.exec);
+}
+
+#[test]
+fn test_sandbox_hook_can_run() {
+    // The boot hook is a script run in the server, only read
+    let yaml = "tftp_server_dir: /srv/tftp\nboot_hook: /opt/hook.rhai\ndefault:\n    boot_file: ipxe.efi\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(!conf.get_sandbox());
+
+    let access = sandbox::access(&conf, None, None);
+    assert!(!access.exec);
+    assert!(access.read.contains(&PathBuf::from("/opt/hook.rhai")));
+
+    let yaml = "tftp_server_dir: /srv/tftp\nevent_hooks:\n    offer_sent: /opt/notify.sh\ndefault:\n    boot_file: ipxe.efi\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    let access = sandbox::access(&conf, None, None);
+    assert!(access.exec);
+    assert!(access.read.contains(&PathBuf::from("/opt/notify.sh")));
+}
@@ -0,0 +1,49 @@
+extern crate preboot_oxide;
+
+use preboot_oxide::{conf::Conf, secureboot};
+
+mod utils;
+
+#[test]
+fn test_secure_boot_preset() {
+    let root = std::env::temp_dir().join(format!("po-secureboot-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+
+    let yaml = format!(
+        r#"
+tftp_server_dir: {}
+http_port: 8080
+secure_boot: true
+    "#,
+        root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.get_secure_boot());
+    conf.validate().unwrap();
+
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml.replace("http_port: 8080", ""));
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+
+    assert_eq!(
+        secureboot::boot_file(Some(7)).unwrap(),
+        "images/secureboot/amd64/bootnetx64.efi"
+    );
+    assert_eq!(
+        secureboot::boot_file(Some(0x0b)).unwrap(),
+        "images/secureboot/arm64/bootnetaa64.efi"
+    );
+    assert_eq!(secureboot::boot_file(Some(0)), None);
+    assert_eq!(secureboot::images().len(), 4);
+
+    secureboot::write_bootstrap(&root, 8080).unwrap();
+    let bootstrap =
+        std::fs::read_to_string(root.join("debian-installer/amd64/grub/grub.cfg")).unwrap();
+    assert!(bootstrap.contains(
+        "configfile (http,${net_default_server}:8080)/grub.cfg?mac=${net_default_mac}\n"
+    ));
+    assert!(root.join("debian-installer/arm64/grub/grub.cfg").is_file());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,52 @@
+extern crate preboot_oxide;
+
+use dhcproto::v4::{DhcpOption, MessageType, OptionCode};
+use preboot_oxide::{
+    bench,
+    probe::{self, ProbeAnswer},
+    selftest,
+};
+
+#[test]
+fn test_network_offer() {
+    let mac = [0x02, 0x54, 0, 0x12, 0x34, 0x56];
+    let discover = probe::discover_message(&mac, 7);
+    let offer = selftest::network_offer(&discover).unwrap();
+    assert_eq!(offer.opts().msg_type(), Some(MessageType::Offer));
+    assert_eq!(offer.xid(), 7);
+    assert_eq!(offer.chaddr(), &mac);
+    assert_eq!(offer.yiaddr(), selftest::CLIENT_IP);
+    assert_eq!(
+        offer.opts().get(OptionCode::SubnetMask),
+        Some(&DhcpOption::SubnetMask([255, 255, 255, 252].into()))
+    );
+    // Without boot information, the proxy adding it
+    assert!(offer.opts().get(OptionCode::BootfileName).is_none());
+
+    // The REQUEST is the proxy's to answer
+    let answer = ProbeAnswer::from_message(&offer, selftest::CLIENT_IP);
+    let request = bench::request_message(&mac, 7, &answer);
+    assert_eq!(selftest::network_offer(&request), None);
+}
+
+#[test]
+fn test_tftp_server() {
+    let ack = |boot_file: &str, boot_server: Option<&str>| ProbeAnswer {
+        server: [198, 18, 247, 1].into(),
+        offered_ip: None,
+        boot_file: Some(boot_file.to_string()),
+        boot_server: boot_server.map(String::from),
+        is_pxe: true,
+        options: vec![],
+    };
+    assert_eq!(
+        selftest::tftp_server(&ack("ipxe.efi", None)),
+        Some("198.18.247.1:69".parse().unwrap())
+    );
+    assert_eq!(
+        selftest::tftp_server(&ack("ipxe.efi", Some("198.18.247.9"))),
+        Some("198.18.247.9:69".parse().unwrap())
+    );
+    // Fetched over HTTP by the client
+    assert_eq!(selftest::tftp_server(&ack("http://198.18.247.1/boot.ipxe", None)), None);
+}
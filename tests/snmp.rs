@@ -0,0 +1,284 @@
+extern crate preboot_oxide;
+
+use std::{
+    net::UdpSocket,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use async_std::task;
+use preboot_oxide::{
+    conf::Conf,
+    control::{Control, ControlledService},
+    dhcp::SessionMap,
+    reload,
+    snmp::{self, Message, Oid, Pdu, Value},
+    tracker::BootTracker,
+};
+
+mod utils;
+
+fn oid(s: &str) -> Oid {
+    snmp::parse_oid(s).unwrap()
+}
+
+fn request(version: i64, kind: u8, oids: &[&str]) -> Message {
+    Message {
+        version,
+        community: b"public".to_vec(),
+        pdu: Pdu {
+            kind,
+            request_id: 7,
+            error_status: 0,
+            error_index: 0,
+            varbinds: oids.iter().map(|s| (oid(s), Value::Null)).collect(),
+        },
+    }
+}
+
+fn objects() -> Vec<(Oid, Value)> {
+    vec![
+        (
+            oid("1.3.6.1.2.1.1.1.0"),
+            Value::OctetString(b"preboot-oxide".to_vec()),
+        ),
+        (oid("1.3.6.1.2.1.1.3.0"), Value::TimeTicks(4200)),
+        (
+            oid("1.3.6.1.4.1.8072.9999.9999.1.1.2.0"),
+            Value::Counter32(3),
+        ),
+    ]
+}
+
+#[test]
+fn test_codec() {
+    // snmpget -v2c -c public localhost sysDescr.0
+    let bytes = [
+        0x30, 0x29, 0x02, 0x01, 0x01, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', 0xa0, 0x1c,
+        0x02, 0x04, 0x12, 0x34, 0x56, 0x78, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x30,
+        0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05, 0x00,
+    ];
+    let message = Message::decode(&bytes).unwrap();
+    assert_eq!(message.version, snmp::VERSION_2C);
+    assert_eq!(message.pdu.kind, snmp::GET);
+    assert_eq!(message.pdu.request_id, 0x12345678);
+    assert_eq!(
+        message.pdu.varbinds,
+        vec![(oid("1.3.6.1.2.1.1.1.0"), Value::Null)]
+    );
+    assert_eq!(message.encode(), bytes);
+
+    let mut message = request(snmp::VERSION_1, snmp::RESPONSE, &[]);
+    message.pdu.request_id = -129;
+    message.pdu.varbinds = vec![
+        (
+            oid("1.3.6.1.4.1.8072.9999.9999.1.1.2.0"),
+            Value::Counter32(u32::MAX),
+        ),
+        (
+            oid("1.3.6.1.2.1.1.1.0"),
+            Value::OctetString(vec![b'x'; 300]),
+        ),
+        (
+            oid("1.3.6.1.2.1.1.2.0"),
+            Value::ObjectId(oid("1.3.6.1.4.1.8072.9999.9999.1")),
+        ),
+        (oid("1.3.6.1.2.1.1.7.0"), Value::Integer(-1)),
+        (oid("1.3.6.1.2.1.1.8.0"), Value::EndOfMibView),
+    ];
+    assert_eq!(Message::decode(&message.encode()).unwrap(), message);
+    assert!(Message::decode(&bytes[..20]).is_err());
+}
+
+#[test]
+fn test_answer() {
+    let objects = objects();
+    let get = request(
+        snmp::VERSION_2C,
+        snmp::GET,
+        &["1.3.6.1.2.1.1.3.0", "1.3.6.1.2.1.1.3.1", "1.3.6.1.2.1.9.0"],
+    );
+    let answer = snmp::answer(&get, &objects).unwrap();
+    assert_eq!(answer.pdu.kind, snmp::RESPONSE);
+    assert_eq!(answer.pdu.request_id, 7);
+    assert_eq!(answer.pdu.error_status, 0);
+    let values: Vec<&Value> = answer.pdu.varbinds.iter().map(|(_, value)| value).collect();
+    assert_eq!(
+        values,
+        [
+            &Value::TimeTicks(4200),
+            &Value::NoSuchInstance,
+            &Value::NoSuchObject
+        ]
+    );
+
+    // SNMPv1 fails the request for the first object missing
+    let get = request(
+        snmp::VERSION_1,
+        snmp::GET,
+        &["1.3.6.1.2.1.1.3.0", "1.3.6.1.2.1.9.0"],
+    );
+    let answer = snmp::answer(&get, &objects).unwrap();
+    assert_eq!(
+        (answer.pdu.error_status, answer.pdu.error_index),
+        (snmp::NO_SUCH_NAME, 2)
+    );
+    assert_eq!(answer.pdu.varbinds, get.pdu.varbinds);
+
+    // Walked to the end
+    let mut walked = Vec::new();
+    let mut next = oid("1.3.6.1");
+    loop {
+        let get_next = Message {
+            pdu: Pdu {
+                varbinds: vec![(next.clone(), Value::Null)],
+                ..request(snmp::VERSION_2C, snmp::GET_NEXT, &[]).pdu
+            },
+            ..request(snmp::VERSION_2C, snmp::GET_NEXT, &[])
+        };
+        let (oid, value) = snmp::answer(&get_next, &objects)
+            .unwrap()
+            .pdu
+            .varbinds
+            .remove(0);
+        if value == Value::EndOfMibView {
+            break;
+        }
+        walked.push((oid.clone(), value));
+        next = oid;
+    }
+    assert_eq!(walked, objects);
+
+    let mut bulk = request(
+        snmp::VERSION_2C,
+        snmp::GET_BULK,
+        &["1.3.6.1.2.1.1.1.0", "1.3.6.1.2.1.1"],
+    );
+    bulk.pdu.error_status = 1;
+    bulk.pdu.error_index = 5;
+    let answer = snmp::answer(&bulk, &objects).unwrap();
+    let oids: Vec<&Oid> = answer.pdu.varbinds.iter().map(|(oid, _)| oid).collect();
+    assert_eq!(
+        oids,
+        [
+            &oid("1.3.6.1.2.1.1.3.0"),
+            &oid("1.3.6.1.2.1.1.1.0"),
+            &oid("1.3.6.1.2.1.1.3.0"),
+            &oid("1.3.6.1.4.1.8072.9999.9999.1.1.2.0"),
+            &oid("1.3.6.1.4.1.8072.9999.9999.1.1.2.0"),
+        ]
+    );
+    assert_eq!(answer.pdu.varbinds[4].1, Value::EndOfMibView);
+
+    // As many objects as fit in one datagram
+    let walk = vec!["1.3.6.1"; 800];
+    let mut bulk = request(snmp::VERSION_2C, snmp::GET_BULK, &walk);
+    bulk.pdu.error_index = 64;
+    let answer = snmp::answer(&bulk, &objects).unwrap();
+    assert!(!answer.pdu.varbinds.is_empty());
+    assert!(answer.encode().len() <= 1472);
+
+    let set = request(snmp::VERSION_2C, snmp::SET, &["1.3.6.1.2.1.1.1.0"]);
+    assert_eq!(
+        snmp::answer(&set, &objects).unwrap().pdu.error_status,
+        snmp::NOT_WRITABLE
+    );
+    let set = request(snmp::VERSION_1, snmp::SET, &["1.3.6.1.2.1.1.1.0"]);
+    assert_eq!(
+        snmp::answer(&set, &objects).unwrap().pdu.error_status,
+        snmp::NO_SUCH_NAME
+    );
+    assert!(snmp::answer(
+        &request(snmp::VERSION_1, snmp::GET_BULK, &["1.3.6.1"]),
+        &objects
+    )
+    .is_none());
+    assert!(snmp::answer(&request(3, snmp::GET, &["1.3.6.1"]), &objects).is_none());
+}
+
+#[test]
+fn test_snmp_agent() {
+    let port = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let yaml = format!(
+        "snmp_addr: 127.0.0.1:{port}\nsnmp_community: monitoring\ndefault:\n    boot_file: ipxe.efi\n"
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path))
+        .unwrap()
+        .with_instance_name(Some("lab".to_string()));
+    assert_eq!(conf.get_snmp_community(), "monitoring");
+
+    let tracker = Arc::new(BootTracker::new(10));
+    tracker.boot_info_sent(
+        "10.0.0.5".parse().unwrap(),
+        "52:54:00:12:34:56",
+        42,
+        "ipxe.efi",
+        "eth0",
+    );
+    let control = Arc::new(Control {
+        started: SystemTime::now(),
+        origin: "--set".to_string(),
+        tracker,
+        services: vec![ControlledService {
+            tenant: None,
+            shared_conf: Arc::new(std::sync::RwLock::new(Arc::new(conf.clone()))),
+            sessions: Some(Arc::new(SessionMap::new(&conf))),
+        }],
+        reloader: reload::reloader().0,
+        handover: Default::default(),
+    });
+
+    let objects = task::block_on(snmp::objects(&control));
+    let value = |s: &str| {
+        objects
+            .iter()
+            .find(|(known, _)| *known == oid(s))
+            .map(|(_, value)| value.clone())
+    };
+    assert_eq!(
+        value("1.3.6.1.2.1.1.5.0"),
+        Some(Value::OctetString(b"lab".to_vec()))
+    );
+    assert_eq!(
+        value("1.3.6.1.4.1.8072.9999.9999.1.1.7.0"),
+        Some(Value::truth(true))
+    );
+    assert_eq!(
+        value("1.3.6.1.4.1.8072.9999.9999.1.1.9.0"),
+        Some(Value::truth(false))
+    );
+    snmp::spawn_snmp_async(&conf, control).unwrap();
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    socket.connect(("127.0.0.1", port)).unwrap();
+    let mut buffer = [0; 1500];
+
+    // Of another community, dropped
+    let get = request(snmp::VERSION_2C, snmp::GET, &["1.3.6.1.2.1.1.5.0"]);
+    socket.send(&get.encode()).unwrap();
+    assert!(socket.recv(&mut buffer).is_err());
+
+    let get = Message {
+        community: b"monitoring".to_vec(),
+        ..get
+    };
+    socket.send(&get.encode()).unwrap();
+    let len = socket.recv(&mut buffer).unwrap();
+    let answer = Message::decode(&buffer[..len]).unwrap();
+    assert_eq!(answer.community, b"monitoring");
+    assert_eq!(
+        answer.pdu.varbinds,
+        vec![(
+            oid("1.3.6.1.2.1.1.5.0"),
+            Value::OctetString(b"lab".to_vec())
+        )]
+    );
+}
@@ -0,0 +1,128 @@
+extern crate preboot_oxide;
+
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use preboot_oxide::{
+    conf::Conf,
+    stats::{self, BootStats, Outcome, StatsState, ATTEMPT_WINDOW},
+    tracker::BootTracker,
+};
+
+mod utils;
+
+const MAC: &str = "52:54:00:12:34:56";
+const OTHER_MAC: &str = "52:54:00:12:34:57";
+
+fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1714521600 + secs)
+}
+
+#[test]
+fn test_attempts() {
+    let stats = BootStats::new(StatsState {
+        since: 1714521600,
+        ..Default::default()
+    });
+    // The chainload of iPXE asking again is the same attempt
+    stats.offered(MAC, Some("ipxe.efi"), at(0));
+    stats.acked(MAC, Some("ipxe.efi"), at(1));
+    stats.booted(MAC, at(2));
+    stats.offered(MAC, Some("boot.ipxe"), at(60));
+    stats.acked(MAC, Some("boot.ipxe"), at(61));
+    // The other one retries after timing out, then never downloads
+    stats.offered(OTHER_MAC, Some("ipxe.efi"), at(0));
+    stats.timed_out(OTHER_MAC, at(120));
+    stats.offered(OTHER_MAC, Some("ipxe.efi"), at(180));
+    stats.acked(OTHER_MAC, Some("ipxe.efi"), at(181));
+
+    let state = stats.snapshot();
+    let client = &state.clients[MAC];
+    assert_eq!(client.last.outcome, Outcome::Booted);
+    assert_eq!(client.last.boot_file.as_deref(), Some("boot.ipxe"));
+    assert_eq!(state.clients[OTHER_MAC].last.outcome, Outcome::Acked);
+    assert_eq!(state.totals().attempts(), 2);
+
+    // A new attempt past the window, the last one being ended
+    let later = ATTEMPT_WINDOW.as_secs() + 10;
+    stats.timed_out(MAC, at(later));
+    stats.offered(MAC, Some("ipxe.efi"), at(later));
+    let state = stats.snapshot();
+    assert_eq!(state.clients[MAC].ended.booted, 1);
+    assert_eq!(state.clients[MAC].last.outcome, Outcome::Offered);
+    assert_eq!(state.ended.booted, 1);
+    assert_eq!(state.totals().attempts(), 3);
+}
+
+#[test]
+fn test_report() {
+    let stats = BootStats::new(StatsState {
+        since: 1714521600,
+        ..Default::default()
+    });
+    stats.offered(MAC, Some("ipxe.efi"), at(0));
+    stats.booted(MAC, at(5));
+    stats.offered(OTHER_MAC, Some("ipxe.efi"), at(0));
+    stats.acked(OTHER_MAC, Some("ipxe.efi"), at(1));
+
+    let report = stats::report(&stats.snapshot(), Some(at(0)), at(3600));
+    assert_eq!(
+        report,
+        "Clients seen since 2024-05-01T00:00:00Z: 2
+  Booted: 1 (50.0%)
+  Acknowledged, boot file not downloaded: 1
+  Timed out in the DHCP handshake: 0
+  Offered, not acknowledged: 0
+Not booted:
+  MAC                OUTCOME       LAST SEEN             BOOT FILE
+  52:54:00:12:34:57  acknowledged  2024-05-01T00:00:01Z  ipxe.efi
+Boot attempts since 2024-05-01T00:00:00Z: 2, booted: 1 (50.0%)
+"
+    );
+    // Still within its window, not failed yet
+    let report = stats::report(&stats.snapshot(), None, at(60));
+    assert!(report.starts_with("Clients seen: 2\n"));
+    assert!(!report.contains("Not booted:"));
+    // Those seen before left out
+    let report = stats::report(&stats.snapshot(), Some(at(3)), at(3600));
+    assert!(report.starts_with("Clients seen since 2024-05-01T00:00:03Z: 1\n"));
+}
+
+#[test]
+fn test_tracker_stats() {
+    let tracker = BootTracker::new(10);
+    tracker.offer_sent(MAC, Some("ipxe.efi"));
+    tracker.ack_sent(MAC, Some("ipxe.efi"));
+    tracker.boot_info_sent("10.0.0.5".parse().unwrap(), MAC, 42, "ipxe.efi", "eth0");
+    tracker.file_delivered("10.0.0.5".parse().unwrap(), Path::new("/ipxe.efi"));
+    let state = tracker.stats().snapshot();
+    assert_eq!(state.clients[MAC].last.outcome, Outcome::Booted);
+}
+
+#[test]
+fn test_saved_stats() {
+    let dir = std::env::temp_dir().join(format!("po-stats-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("stats.json");
+
+    let stats = BootStats::load(&path).unwrap();
+    assert!(stats.snapshot().since > 0);
+    stats.offered(MAC, Some("ipxe.efi"), SystemTime::now());
+    stats.save(&path).unwrap();
+    let loaded = BootStats::load(&path).unwrap();
+    assert_eq!(loaded.snapshot(), stats.snapshot());
+
+    std::fs::write(&path, "{").unwrap();
+    assert!(BootStats::load(&path).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let yaml = "tftp_server_dir: /tftpdir\nstats_file: /var/lib/preboot-oxide/stats.json\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert_eq!(
+        conf.get_stats_file().unwrap(),
+        Path::new("/var/lib/preboot-oxide/stats.json")
+    );
+}
@@ -0,0 +1,112 @@
+extern crate preboot_oxide;
+
+use std::net::UdpSocket;
+
+use log::{Level, Record};
+use preboot_oxide::{
+    conf::Conf,
+    logging::TaskClient,
+    syslog::{self, Emitter, LogOutput, SyslogServer},
+};
+
+mod utils;
+
+fn client() -> TaskClient {
+    TaskClient {
+        mac: Some([0x52, 0x54, 0, 0x12, 0x34, 0x56]),
+        xid: Some(0x1234),
+        ip: None,
+    }
+}
+
+#[test]
+fn test_syslog_server() {
+    assert_eq!(
+        "10.0.0.1".parse::<SyslogServer>().unwrap(),
+        SyslogServer::Udp("10.0.0.1:514".parse().unwrap())
+    );
+    assert_eq!(
+        "tcp://10.0.0.1:601".parse::<SyslogServer>().unwrap(),
+        SyslogServer::Tcp("10.0.0.1:601".parse().unwrap())
+    );
+    assert_eq!("udp://[::1]".parse::<SyslogServer>().unwrap().to_string(), "udp://[::1]:514");
+    assert!("http://10.0.0.1".parse::<SyslogServer>().is_err());
+    assert!("udp://syslog.lan".parse::<SyslogServer>().is_err());
+}
+
+#[test]
+fn test_syslog_lines() {
+    let time = time::OffsetDateTime::from_unix_timestamp(1_714_566_600).unwrap();
+    let record = Record::builder()
+        .level(Level::Warn)
+        .target("preboot_oxide::tftp")
+        .args(format_args!("File \"bootx64.efi\" not found"))
+        .build();
+    let pid = std::process::id();
+
+    assert_eq!(
+        syslog::rfc5424_line(time, "pxe1", &record, &client()),
+        format!(
+            "<28>1 2024-05-01T12:30:00Z pxe1 preboot-oxide {pid} tftp [client@32473 mac=\"52:54:00:12:34:56\" xid=\"0x00001234\" session=\"940d5785\"] File \"bootx64.efi\" not found"
+        )
+    );
+    assert!(syslog::rfc5424_line(time, "pxe1", &record, &TaskClient::default()).contains(" tftp - File"));
+    assert_eq!(
+        syslog::local_line(&record, &client()),
+        format!("<28>preboot-oxide[{pid}]: session=940d5785 File \"bootx64.efi\" not found")
+    );
+    assert!(syslog::local_line(&record, &TaskClient::default()).ends_with("]: File \"bootx64.efi\" not found"));
+}
+
+#[test]
+fn test_journald_fields() {
+    let record = Record::builder()
+        .level(Level::Info)
+        .target("preboot_oxide::dhcp")
+        .args(format_args!("two\nlines"))
+        .build();
+    let fields = syslog::journald_fields(&record, &client());
+    let mut expected = b"MESSAGE\n".to_vec();
+    expected.extend_from_slice(&9u64.to_le_bytes());
+    expected.extend_from_slice(b"two\nlines\n");
+    expected.extend_from_slice(
+        b"PRIORITY=6\nSYSLOG_IDENTIFIER=preboot-oxide\nPO_SUBSYSTEM=dhcp\nPO_TARGET=preboot_oxide::dhcp\n\
+          PO_CLIENT_MAC=52:54:00:12:34:56\nPO_XID=0x00001234\nPO_SESSION=940d5785\n",
+    );
+    assert_eq!(fields, expected);
+}
+
+#[test]
+fn test_syslog_udp() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let emitter = Emitter::open(LogOutput::Syslog, Some(SyslogServer::Udp(addr))).unwrap().unwrap();
+    let record = Record::builder().level(Level::Error).target("preboot_oxide::http").args(format_args!("Failed")).build();
+    emitter.emit(time::OffsetDateTime::now_utc(), &record, &TaskClient::default()).unwrap();
+
+    let mut buf = [0u8; 512];
+    let len = server.recv(&mut buf).unwrap();
+    let line = std::str::from_utf8(&buf[..len]).unwrap();
+    assert!(line.starts_with("<27>1 "));
+    assert!(line.ends_with(" http - Failed"));
+
+    assert!(Emitter::open(LogOutput::Stderr, None).unwrap().is_none());
+}
+
+#[test]
+fn test_log_output_conf() {
+    let yaml = "tftp_server_dir: /tftpdir\nlog_output: syslog\nsyslog_server: tcp://10.0.0.1\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_ok());
+    assert_eq!(conf.get_log_output(), LogOutput::Syslog);
+    assert_eq!(conf.get_syslog_server(), Some(SyslogServer::Tcp("10.0.0.1:514".parse().unwrap())));
+
+    let yaml = "tftp_server_dir: /tftpdir\nsyslog_server: 10.0.0.1\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+    let yaml = "tftp_server_dir: /tftpdir\nlog_output: kmsg\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    assert!(Conf::from_config_file(Some(&yaml_mock.path)).is_err());
+}
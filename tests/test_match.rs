@@ -0,0 +1,127 @@
+extern crate preboot_oxide;
+
+use std::time::SystemTime;
+
+use dhcproto::v4::{DhcpOption, Encodable, Encoder, Message, MessageType};
+use preboot_oxide::{conf::Conf, test_match, util};
+
+mod utils;
+
+const YAML: &str = r#"
+tftp_server_dir: /tftpdir
+default:
+    boot_file: default.efi
+match:
+    - select:
+        ClientMacAddress: 52:54:00:00:00:01
+      conf:
+        boot_file: lab.efi
+    - select:
+        Stage: offer
+        Interface: eth1
+      conf:
+        menu_label: Lab
+    "#;
+
+/// A pcap capture of the DISCOVER of `mac`, on a VLAN.
+fn discover_capture(mac: &[u8]) -> Vec<u8> {
+    let mut message = Message::default();
+    message.set_chaddr(mac);
+    message
+        .opts_mut()
+        .insert(DhcpOption::MessageType(MessageType::Discover));
+    let mut dhcp = Vec::new();
+    message.encode(&mut Encoder::new(&mut dhcp)).unwrap();
+
+    let mut udp = [68u16, 67, 8 + dhcp.len() as u16, 0]
+        .map(u16::to_be_bytes)
+        .concat();
+    udp.extend(dhcp);
+    let mut ip = vec![
+        0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0, 0, 0, 0, 0, 255, 255, 255, 255,
+    ];
+    ip.extend(udp);
+    let mut frame = [[0xff; 6].as_slice(), mac, &[0x81, 0x00, 0, 10, 0x08, 0x00]].concat();
+    frame.extend(ip);
+
+    let mut capture = [0xa1b2c3d4u32.to_le_bytes(), [2, 0, 4, 0]].concat();
+    capture.extend([0u32, 0, 65535, 1].map(u32::to_le_bytes).concat());
+    capture.extend(
+        [0u32, 0, frame.len() as u32, frame.len() as u32]
+            .map(u32::to_le_bytes)
+            .concat(),
+    );
+    capture.extend(frame);
+    capture
+}
+
+#[test]
+fn test_match_report() {
+    let yaml_mock = utils::YamlMockFile::from_yaml(YAML);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    let report = |client: &str, fields: &[&str]| {
+        let fields: Vec<String> = fields.iter().map(|field| field.to_string()).collect();
+        let doc = test_match::client_doc(client, &fields).unwrap();
+        test_match::report(&conf, &doc, SystemTime::now()).unwrap()
+    };
+
+    assert_eq!(
+        report("52:54:00:00:00:01", &[]),
+        "Client: 52:54:00:00:00:01\n\
+         Matching entries: match[0]\n\
+         Used: match[0], by the first policy\n\
+         Configuration:\n  \
+           boot_file: lab.efi\n"
+    );
+    assert_eq!(
+        report("52-54-00-00-00-02", &["Stage=offer", "Interface=eth1"]),
+        "Client: 52:54:00:00:00:02\n\
+         Matching entries: match[1]\n\
+         Used: match[1], by the first policy\n\
+         Configuration:\n  \
+           boot_file: default.efi\n  \
+           menu_label: Lab\n"
+    );
+
+    // The stage of the message captured
+    let capture = utils::MockFile::from_bytes(&discover_capture(&[0x52, 0x54, 0, 0, 0, 2]), "pcap");
+    let report = report(&capture.path.display().to_string(), &["Interface=eth1"]);
+    assert!(
+        report.starts_with("Client: 52:54:00:00:00:02\nMatching entries: match[1]\n"),
+        "{report}"
+    );
+
+    let json = utils::MockFile::from_bytes(br#"{"chaddr": [82, 84, 0, 0, 0, 1]}"#, "json");
+    let doc = test_match::client_doc(&json.path.display().to_string(), &[]).unwrap();
+    assert_eq!(conf.used_entry_at(&doc, SystemTime::now()), Some(0));
+
+    let yaml_mock = utils::YamlMockFile::from_yaml(&format!("require_match: true\n{YAML}"));
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    let doc = test_match::client_doc("52:54:00:00:00:02", &[]).unwrap();
+    let report = test_match::report(&conf, &doc, SystemTime::now()).unwrap();
+    assert!(report.ends_with("Used: none, ignored with require_match\nConfiguration: none\n"));
+}
+
+#[test]
+fn test_client_from_capture() {
+    let doc = test_match::doc_from_pcap(&discover_capture(&[0x52, 0x54, 0, 0, 0, 3])).unwrap();
+    assert_eq!(doc["Stage"], "offer");
+    assert_eq!(util::mac_from_doc(&doc), Some([0x52, 0x54, 0, 0, 0, 3]));
+
+    for (capture, error) in [
+        (vec![0x0a, 0x0d, 0x0d, 0x0a, 0, 0, 0, 0], "too short"),
+        (
+            [0x0a0d0d0au32.to_le_bytes().as_slice(), &[0; 20]].concat(),
+            "pcapng captures aren't supported",
+        ),
+        (
+            [0xa1b2c3d4u32.to_be_bytes().as_slice(), &[0; 20]].concat(),
+            "No DHCP message",
+        ),
+    ] {
+        let message = test_match::doc_from_pcap(&capture).unwrap_err().to_string();
+        assert!(message.contains(error), "{message}");
+    }
+
+    assert!(test_match::client_doc("52:54:00:00:00:01", &["Interface".into()]).is_err());
+}
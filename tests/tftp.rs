@@ -0,0 +1,472 @@
+extern crate preboot_oxide;
+
+use async_std::task;
+use async_tftp::packet;
+use async_tftp::server::{Handler, TftpServerBuilder};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use preboot_oxide::conf::{Conf, FileFilter, SymlinkPolicy, TftpFallback};
+use preboot_oxide::tftp::{DirHandler, DirHandlerMode, TransferLimits};
+use preboot_oxide::tftp_get;
+use preboot_oxide::tracker::BootTracker;
+use std::{
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    path::Path,
+    sync::Arc,
+};
+
+mod utils;
+
+#[test]
+fn test_transfer_limits() {
+    let limits = Arc::new(TransferLimits::new(3, 2));
+    let client_a = Ipv4Addr::new(10, 0, 0, 1).into();
+    let client_b = Ipv4Addr::new(10, 0, 0, 2).into();
+
+    let a1 = TransferLimits::acquire(&limits, client_a).unwrap();
+    let _a2 = TransferLimits::acquire(&limits, client_a).unwrap();
+    assert!(TransferLimits::acquire(&limits, client_a).is_none());
+
+    let _b1 = TransferLimits::acquire(&limits, client_b).unwrap();
+    assert!(TransferLimits::acquire(&limits, client_b).is_none());
+    assert_eq!(limits.active_transfers(), 3);
+
+    drop(a1);
+    assert_eq!(limits.active_transfers(), 2);
+    assert!(TransferLimits::acquire(&limits, client_b).is_some());
+}
+
+#[test]
+fn test_backslash_paths_are_translated() {
+    let root = std::env::temp_dir().join(format!("po-tftp-backslash-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("boot")).unwrap();
+    std::fs::write(root.join("boot/bcd"), b"bcd store").unwrap();
+    let client: SocketAddr = (Ipv4Addr::LOCALHOST, 4000).into();
+
+    let mut handler = DirHandler::new(&root, DirHandlerMode::ReadOnly).unwrap();
+    let content = task::block_on(async {
+        let (mut reader, len) = handler
+            .read_req_open(&client, Path::new("\\boot\\bcd"))
+            .await
+            .unwrap();
+        assert_eq!(len, Some(9));
+
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).await.unwrap();
+        content
+    });
+    assert_eq!(content, b"bcd store");
+
+    let traversal = task::block_on(handler.read_req_open(&client, Path::new("..\\etc\\passwd")));
+    assert!(traversal.is_err());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_file_filter_rejects_requests() {
+    let root = std::env::temp_dir().join(format!("po-tftp-filter-{}", std::process::id()));
+    std::fs::create_dir_all(root.join(".git")).unwrap();
+    for file in ["boot.efi", ".env", "server.key", ".git/boot.efi"] {
+        std::fs::write(root.join(file), b"content").unwrap();
+    }
+    let client: SocketAddr = (Ipv4Addr::LOCALHOST, 4000).into();
+    let mut handler = DirHandler::new(&root, DirHandlerMode::ReadOnly)
+        .unwrap()
+        .with_file_filter(FileFilter::default());
+    let mut open = |path: &str| task::block_on(handler.read_req_open(&client, Path::new(path)));
+
+    assert!(open("boot.efi").is_ok());
+    for rejected in [".env", "server.key", ".git/boot.efi", "/./server.key"] {
+        assert!(
+            matches!(open(rejected), Err(packet::Error::FileNotFound)),
+            "{rejected} served"
+        );
+    }
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_fallbacks() {
+    let root = std::env::temp_dir().join(format!("po-tftp-fallbacks-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("pxelinux.cfg")).unwrap();
+    std::fs::write(root.join("pxelinux.cfg/01-52-54-00-00-00-01"), b"host menu").unwrap();
+    let client: SocketAddr = (Ipv4Addr::LOCALHOST, 4000).into();
+    let fallbacks = vec!["pxelinux.cfg/=pxelinux.cfg/default".parse::<TftpFallback>().unwrap()];
+    let mut handler = DirHandler::new(&root, DirHandlerMode::ReadOnly)
+        .unwrap()
+        .with_fallbacks(fallbacks);
+    let mut read = |path: &str| {
+        task::block_on(async {
+            let (mut reader, _) = handler.read_req_open(&client, Path::new(path)).await?;
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content).await.unwrap();
+            Ok::<_, packet::Error>(content)
+        })
+    };
+
+    // Both missing
+    let missing = read("pxelinux.cfg/01-52-54-00-00-00-02");
+    assert!(matches!(missing, Err(packet::Error::FileNotFound)));
+
+    std::fs::write(root.join("pxelinux.cfg/default"), b"default menu").unwrap();
+    assert_eq!(read("pxelinux.cfg/01-52-54-00-00-00-01").unwrap(), b"host menu");
+    assert_eq!(read("pxelinux.cfg/01-52-54-00-00-00-02").unwrap(), b"default menu");
+    assert_eq!(read("/pxelinux.cfg/C0A80001").unwrap(), b"default menu");
+    // Not under the prefix
+    assert!(matches!(read("grub/grub.cfg"), Err(packet::Error::FileNotFound)));
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_policies() {
+    use std::os::unix::fs::symlink;
+
+    let base = std::env::temp_dir().join(format!("po-tftp-symlinks-{}", std::process::id()));
+    let (root, outside) = (base.join("root"), base.join("outside"));
+    std::fs::create_dir_all(root.join("boot")).unwrap();
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(root.join("boot/ipxe.efi"), b"ipxe").unwrap();
+    std::fs::write(outside.join("secret"), b"secret").unwrap();
+    symlink(root.join("boot/ipxe.efi"), root.join("inside")).unwrap();
+    symlink(outside.join("secret"), root.join("escaping")).unwrap();
+    symlink(&outside, root.join("escaping-dir")).unwrap();
+    let client: SocketAddr = (Ipv4Addr::LOCALHOST, 4000).into();
+
+    let read = |symlinks: SymlinkPolicy, path: &str| {
+        let mut handler = DirHandler::new(&root, DirHandlerMode::ReadWrite)
+            .unwrap()
+            .with_symlink_policy(symlinks);
+        task::block_on(async {
+            let (mut reader, _) = handler.read_req_open(&client, Path::new(path)).await?;
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content).await.unwrap();
+            Ok::<_, packet::Error>(content)
+        })
+    };
+    let write = |symlinks: SymlinkPolicy, path: &str| {
+        let mut handler = DirHandler::new(&root, DirHandlerMode::ReadWrite)
+            .unwrap()
+            .with_symlink_policy(symlinks);
+        task::block_on(async {
+            let mut writer = handler
+                .write_req_open(&client, Path::new(path), Some(6))
+                .await?;
+            writer.write_all(b"upload").await.unwrap();
+            Ok::<_, packet::Error>(())
+        })
+    };
+
+    assert_eq!(read(SymlinkPolicy::Allow, "inside").unwrap(), b"ipxe");
+    assert_eq!(read(SymlinkPolicy::Allow, "escaping").unwrap(), b"secret");
+    assert!(write(SymlinkPolicy::Allow, "escaping-dir/allowed").is_ok());
+    assert_eq!(std::fs::read(outside.join("allowed")).unwrap(), b"upload");
+
+    assert_eq!(read(SymlinkPolicy::DenyEscaping, "inside").unwrap(), b"ipxe");
+    assert!(matches!(read(SymlinkPolicy::DenyEscaping, "escaping"), Err(packet::Error::PermissionDenied)));
+    assert!(matches!(read(SymlinkPolicy::DenyEscaping, "escaping-dir/secret"), Err(packet::Error::PermissionDenied)));
+    assert!(matches!(write(SymlinkPolicy::DenyEscaping, "escaping-dir/denied"), Err(packet::Error::PermissionDenied)));
+    assert!(write(SymlinkPolicy::DenyEscaping, "boot/uploaded").is_ok());
+
+    assert_eq!(read(SymlinkPolicy::Deny, "boot/ipxe.efi").unwrap(), b"ipxe");
+    assert!(matches!(read(SymlinkPolicy::Deny, "inside"), Err(packet::Error::PermissionDenied)));
+    assert!(matches!(read(SymlinkPolicy::Deny, "escaping"), Err(packet::Error::PermissionDenied)));
+    assert!(matches!(write(SymlinkPolicy::Deny, "escaping-dir/denied"), Err(packet::Error::PermissionDenied)));
+    assert!(!outside.join("denied").exists());
+
+    std::fs::remove_dir_all(&base).unwrap();
+}
+
+fn start_server(root: &Path) -> (SocketAddr, task::JoinHandle<async_tftp::Result<()>>) {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = socket.local_addr().unwrap();
+    let handler = DirHandler::new(root, DirHandlerMode::ReadOnly).unwrap();
+    let server = task::block_on(
+        TftpServerBuilder::with_handler(handler)
+            .std_socket(socket)
+            .unwrap()
+            .build(),
+    )
+    .unwrap();
+
+    (addr, task::spawn(server.serve()))
+}
+
+#[test]
+fn test_block_number_rollover() {
+    // More than 65535 blocks of the default 512 bytes, block numbers wrap to 0
+    const BLOCK_SIZE: usize = 512;
+    let file_size = (u16::MAX as usize + 2) * BLOCK_SIZE + 100;
+    let content: Vec<u8> = (0..file_size).map(|i| (i % 251) as u8).collect();
+    let root = std::env::temp_dir().join(format!("po-tftp-rollover-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("large.img"), &content).unwrap();
+
+    let (server_addr, server) = start_server(&root);
+
+    let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    client
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    client
+        .send_to(b"\x00\x01large.img\x00octet\x00", server_addr)
+        .unwrap();
+
+    let mut received = Vec::with_capacity(file_size);
+    let mut expected_block: u16 = 1;
+    let mut wrapped = false;
+    let mut packet = [0u8; 4 + BLOCK_SIZE];
+    loop {
+        let (len, peer) = client.recv_from(&mut packet).unwrap();
+        assert_eq!(&packet[0..2], &[0, 3], "Expected a DATA packet");
+        let block = u16::from_be_bytes([packet[2], packet[3]]);
+        if block != expected_block {
+            // Duplicate of a block already acknowledged
+            continue;
+        }
+
+        received.extend_from_slice(&packet[4..len]);
+        let ack = [0, 4, packet[2], packet[3]];
+        client.send_to(&ack, peer).unwrap();
+
+        wrapped |= block == 0;
+        expected_block = expected_block.wrapping_add(1);
+        if len < 4 + BLOCK_SIZE {
+            break;
+        }
+    }
+
+    assert!(wrapped);
+    assert_eq!(received.len(), content.len());
+    assert!(received == content);
+
+    task::block_on(server.cancel());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_missing_files_are_relayed_from_upstream() {
+    let base = std::env::temp_dir().join(format!("po-tftp-upstream-{}", std::process::id()));
+    let (local_root, upstream_root) = (base.join("local"), base.join("upstream"));
+    std::fs::create_dir_all(&local_root).unwrap();
+    std::fs::create_dir_all(upstream_root.join("legacy")).unwrap();
+    let content: Vec<u8> = (0..1300).map(|i| (i % 251) as u8).collect();
+    std::fs::write(upstream_root.join("legacy/menu.cfg"), &content).unwrap();
+    let (upstream_addr, upstream) = start_server(&upstream_root);
+    let client: SocketAddr = (Ipv4Addr::LOCALHOST, 4000).into();
+
+    let mut handler = DirHandler::new(&local_root, DirHandlerMode::ReadOnly)
+        .unwrap()
+        .with_upstream(Some(upstream_addr));
+    let received = task::block_on(async {
+        let (mut reader, len) = handler
+            .read_req_open(&client, Path::new("/legacy/menu.cfg"))
+            .await
+            .unwrap();
+        assert_eq!(len, Some(content.len() as u64));
+
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).await.unwrap();
+        received
+    });
+    assert_eq!(received, content);
+
+    let missing = task::block_on(handler.read_req_open(&client, Path::new("legacy/missing.cfg")));
+    assert!(missing.is_err());
+
+    task::block_on(upstream.cancel());
+    std::fs::remove_dir_all(&base).unwrap();
+}
+
+#[test]
+fn test_tftp_get() {
+    let root = std::env::temp_dir().join(format!("po-tftp-get-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let content: Vec<u8> = (0..1300).map(|i| (i % 251) as u8).collect();
+    std::fs::write(root.join("ipxe.efi"), &content).unwrap();
+    let (server_addr, server) = start_server(&root);
+    assert_eq!(
+        tftp_get::server_addr(Some(&server_addr.to_string())).unwrap(),
+        server_addr
+    );
+    assert_eq!(
+        tftp_get::server_addr(Some("10.0.0.1")).unwrap(),
+        "10.0.0.1:69".parse().unwrap()
+    );
+    assert!(tftp_get::server_addr(Some("tftp.lab")).is_err());
+
+    let output = root.join("downloaded");
+    let download =
+        task::block_on(tftp_get::download(server_addr, Path::new("ipxe.efi"), Some(&output)))
+            .unwrap();
+    assert_eq!(std::fs::read(&output).unwrap(), content);
+    assert_eq!((download.size, download.announced_size), (1300, Some(1300)));
+    let report = tftp_get::report(&download);
+    assert!(report.starts_with(&format!(
+        "Downloaded ipxe.efi from {server_addr}\n  Size: 1300 bytes\n"
+    )));
+    assert!(report.ends_with(&format!("SHA-256: {}\n", download.sha256)));
+
+    let missing = task::block_on(tftp_get::download(server_addr, Path::new("missing"), None));
+    assert!(missing.unwrap_err().to_string().contains("refused missing"));
+
+    task::block_on(server.cancel());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_uploads_are_moved_into_place_only_when_complete() {
+    let root = std::env::temp_dir().join(format!("po-tftp-upload-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let client: SocketAddr = (Ipv4Addr::LOCALHOST, 4000).into();
+    let mut handler = DirHandler::new(&root, DirHandlerMode::ReadWrite).unwrap();
+
+    task::block_on(async {
+        let mut writer = handler
+            .write_req_open(&client, Path::new("complete.bin"), Some(10))
+            .await
+            .unwrap();
+        writer.write_all(b"0123456789").await.unwrap();
+
+        let mut partial = handler
+            .write_req_open(&client, Path::new("partial.bin"), Some(10))
+            .await
+            .unwrap();
+        partial.write_all(b"01234").await.unwrap();
+
+        // Nothing visible while uploads are in progress
+        assert!(!root.join("complete.bin").exists());
+        drop(writer);
+        drop(partial);
+    });
+
+    assert_eq!(std::fs::read(root.join("complete.bin")).unwrap(), b"0123456789");
+    let files: Vec<_> = std::fs::read_dir(&root).unwrap().collect();
+    assert_eq!(files.len(), 1);
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_grub_configs_are_generated_per_client() {
+    let root = std::env::temp_dir().join(format!("po-tftp-grub-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("templates")).unwrap();
+    std::fs::write(
+        root.join("templates/grub.cfg"),
+        "linux {{grub_root}}/{{boot_file}} hostname={{hostname}} {{kernel_args}}\n",
+    )
+    .unwrap();
+    let yaml = format!(
+        r#"
+tftp_server_dir: {0}
+ifaces: [lo]
+http_port: 8080
+default:
+    boot_file: /grubnetx64.efi
+match:
+    - select:
+        ClientMacAddress: 52:54:00:12:34:56
+      conf:
+        boot_file: /vmlinuz
+        grub_cfg: templates/grub.cfg
+        vars:
+          hostname: node1
+    "#,
+        root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    let client: SocketAddr = (Ipv4Addr::LOCALHOST, 4000).into();
+
+    let mut handler = DirHandler::new(&root, DirHandlerMode::ReadOnly)
+        .unwrap()
+        .with_conf(Arc::new(conf));
+    let content = task::block_on(async {
+        let (mut reader, len) = handler
+            .read_req_open(&client, Path::new("/grub/grub.cfg-01-52-54-00-12-34-56"))
+            .await
+            .unwrap();
+        let mut content = String::new();
+        reader.read_to_string(&mut content).await.unwrap();
+        assert_eq!(len, Some(content.len() as u64));
+        content
+    });
+    assert_eq!(
+        content,
+        "linux (http,${net_default_server}:8080)/vmlinuz hostname=node1 \n"
+    );
+
+    // Clients without a GRUB template get none
+    let unmatched = task::block_on(
+        handler.read_req_open(&client, Path::new("grub.cfg-01-52-54-00-65-43-21")),
+    );
+    assert!(unmatched.is_err());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_pinned_image_versions_are_served_from_their_file() {
+    use std::os::unix::fs::symlink;
+
+    let root = std::env::temp_dir().join(format!("po-tftp-pinned-{}", std::process::id()));
+    let versions = root.join("images/.versions/linux");
+    std::fs::create_dir_all(versions.join("vmlinuz")).unwrap();
+    std::fs::create_dir_all(versions.join("initrd")).unwrap();
+    std::fs::create_dir_all(root.join("images/linux")).unwrap();
+    std::fs::write(root.join("images/linux/vmlinuz"), b"kernel v2").unwrap();
+    std::fs::write(root.join("images/linux/initrd"), b"initrd v2").unwrap();
+    let (v1, linked) = ("a".repeat(64), "b".repeat(64));
+    std::fs::write(versions.join("vmlinuz").join(&v1), b"kernel v1").unwrap();
+    symlink(root.join("images/linux/initrd"), versions.join("initrd").join(&linked)).unwrap();
+
+    let yaml = format!(
+        r#"
+tftp_server_dir: {}
+ifaces: [lo]
+default:
+    boot_file: /bootfile
+match:
+    - select:
+        ClientMacAddress: 52:54:00:12:34:56
+      conf:
+        pinned_images:
+          linux/vmlinuz: {v1}
+          linux/initrd: {linked}
+    "#,
+        root.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Arc::new(Conf::from_config_file(Some(&yaml_mock.path)).unwrap());
+    let tracker = Arc::new(BootTracker::new(10));
+    tracker.boot_info_sent(Ipv4Addr::LOCALHOST, "52:54:00:12:34:56", 1, "/bootfile", "eth0");
+    let client: SocketAddr = (Ipv4Addr::LOCALHOST, 4000).into();
+
+    let read = |symlinks: SymlinkPolicy, path: &str| {
+        let mut handler = DirHandler::new(&root, DirHandlerMode::ReadOnly)
+            .unwrap()
+            .with_tracker(tracker.clone())
+            .with_conf(conf.clone())
+            .with_symlink_policy(symlinks);
+        task::block_on(async {
+            let (mut reader, _) = handler.read_req_open(&client, Path::new(path)).await?;
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content).await.unwrap();
+            Ok::<_, packet::Error>(content)
+        })
+    };
+
+    // The version pinned is the file itself, matched by the exact path
+    assert_eq!(read(SymlinkPolicy::Deny, "images/linux/vmlinuz").unwrap(), b"kernel v1");
+    assert_eq!(read(SymlinkPolicy::Deny, "images/Linux/VMLINUZ").unwrap(), b"kernel v2");
+    // Pinned versions are held to the symlink policy as well
+    assert!(matches!(
+        read(SymlinkPolicy::Deny, "images/linux/initrd"),
+        Err(packet::Error::PermissionDenied)
+    ));
+    assert_eq!(read(SymlinkPolicy::Allow, "images/linux/initrd").unwrap(), b"initrd v2");
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
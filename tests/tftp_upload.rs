@@ -0,0 +1,59 @@
+//! Uploads failing to be written, apart from the other TFTP tests as the
+//! limit of the size of the files written is the same for the whole process.
+#![cfg(target_os = "linux")]
+extern crate preboot_oxide;
+
+use async_std::task;
+use async_tftp::server::Handler;
+use futures::AsyncWriteExt;
+use preboot_oxide::tftp::{DirHandler, DirHandlerMode};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    path::Path,
+};
+
+#[test]
+fn test_uploads_failing_to_be_written_are_discarded() {
+    let root = std::env::temp_dir().join(format!("po-tftp-failed-upload-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let client: SocketAddr = (Ipv4Addr::LOCALHOST, 4000).into();
+    let mut handler = DirHandler::new(&root, DirHandlerMode::ReadWrite).unwrap();
+
+    // Writes past 64 KiB fail with EFBIG, as they would with ENOSPC
+    // SAFETY: setting the disposition of a signal and a limit of the process
+    unsafe {
+        libc::signal(libc::SIGXFSZ, libc::SIG_IGN);
+        let limit = libc::rlimit {
+            rlim_cur: 64 * 1024,
+            rlim_max: libc::RLIM_INFINITY,
+        };
+        assert_eq!(libc::setrlimit(libc::RLIMIT_FSIZE, &limit), 0);
+    }
+
+    // No size announced, the upload ends right after the failed write
+    task::block_on(async {
+        let mut writer = handler
+            .write_req_open(&client, Path::new("image.bin"), None)
+            .await
+            .unwrap();
+        let block = [0x5a; 512];
+        let mut result = Ok(());
+        for _ in 0..256 {
+            result = writer.write_all(&block).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        if result.is_ok() {
+            result = writer.flush().await;
+        }
+        assert!(result.is_err());
+        drop(writer);
+    });
+
+    assert!(!root.join("image.bin").exists());
+    let files: Vec<_> = std::fs::read_dir(&root).unwrap().collect();
+    assert!(files.is_empty());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,98 @@
+extern crate preboot_oxide;
+
+use preboot_oxide::{
+    conf::Conf,
+    timeline::{self, Entry, Step},
+    util::LineLog,
+};
+use time::OffsetDateTime;
+
+mod utils;
+
+fn at(secs: i64) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp(1714566600 + secs).unwrap()
+}
+
+#[test]
+fn test_entry_json() {
+    let entry = Entry {
+        time: at(0),
+        xid: Some(0x1234),
+        iface: Some("eth0".to_string()),
+        ip: Some("10.0.0.5".parse().unwrap()),
+        detail: Some("ipxe.efi".to_string()),
+        ..Entry::new(Step::Offer, "52:54:00:12:34:56")
+    };
+    let line = entry.to_json();
+    assert_eq!(line["time"], "2024-05-01T12:30:00Z");
+    assert_eq!(line["event"], "offer");
+    assert_eq!(line["xid"], "0x00001234");
+    assert_eq!(line["ip"], "10.0.0.5");
+    assert_eq!(Entry::from_json(&line), Some(entry));
+
+    let line = Entry::new(Step::Timeout, "52:54:00:12:34:56").to_json();
+    assert_eq!(line["xid"], serde_json::Value::Null);
+    assert_eq!(Entry::from_json(&line).unwrap().step, Step::Timeout);
+    assert_eq!(Entry::from_json(&serde_json::json!({ "event": "offer" })), None);
+}
+
+#[test]
+fn test_read() {
+    let dir = std::env::temp_dir().join(format!("po-timeline-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("events.jsonl");
+
+    let mut log = LineLog::open(&path, 400, 3).unwrap();
+    let steps = [
+        (Step::Discover, "52:54:00:12:34:56"),
+        (Step::Discover, "52:54:00:ab:cd:ef"),
+        (Step::Offer, "52:54:00:12:34:56"),
+        (Step::Request, "52:54:00:12:34:56"),
+        (Step::Ack, "52:54:00:12:34:56"),
+        (Step::Transfer, "52:54:00:12:34:56"),
+    ];
+    for (secs, (step, mac)) in steps.into_iter().enumerate() {
+        let entry = Entry {
+            time: at(secs as i64),
+            ..Entry::new(step, mac)
+        };
+        log.write(&entry.to_json().to_string()).unwrap();
+    }
+    log.write("not json").unwrap();
+    // Rotated, the first steps being in events.jsonl.1
+    assert!(path.with_extension("jsonl.1").exists());
+
+    let entries = timeline::read(&path, 3, "52-54-00-12-34-56").unwrap();
+    let found: Vec<Step> = entries.iter().map(|entry| entry.step).collect();
+    assert_eq!(
+        found,
+        [Step::Discover, Step::Offer, Step::Request, Step::Ack, Step::Transfer]
+    );
+    assert!(timeline::read(&path, 3, "52:54:00:00:00:00").unwrap().is_empty());
+    assert!(timeline::read(&path, 3, "xyz").is_err());
+
+    let report = timeline::report("52:54:00:12:34:56", &entries);
+    assert!(report.starts_with("2024-05-01T12:30:00Z  DISCOVER"));
+    assert_eq!(report.lines().count(), 5);
+    assert_eq!(
+        timeline::report("52:54:00:00:00:00", &[]),
+        "No events recorded for 52:54:00:00:00:00.\n"
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_event_log_conf() {
+    let yaml = "tftp_server_dir: /tftpdir\nevent_log: /var/log/preboot-oxide/events.jsonl\nevent_log_files: 10\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_ok());
+    assert_eq!(conf.get_event_log_max_size(), 10 * 1024 * 1024);
+    assert_eq!(conf.get_event_log_files(), 10);
+
+    let yaml = "tftp_server_dir: /tftpdir\nevent_log: events.jsonl\nevent_log_max_size: 0\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+}
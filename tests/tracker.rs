@@ -0,0 +1,83 @@
+extern crate preboot_oxide;
+
+use preboot_oxide::tracker::{BootTracker, IfaceCounts, UPSTREAM_MISSING_AFTER};
+use std::{net::Ipv4Addr, path::Path};
+
+#[test]
+fn test_boot_file_delivery_is_correlated() {
+    let tracker = BootTracker::new(10);
+    let ip = Ipv4Addr::new(10, 0, 0, 10);
+    tracker.boot_info_sent(ip, "08:00:27:E7:DE:FE", 1234, "/boot/bootx64.efi", "eth0");
+
+    assert!(tracker.file_delivered(ip, Path::new("boot/grub.cfg")).is_none());
+    assert!(!tracker.get(&ip).unwrap().boot_file_delivered);
+
+    let client = tracker
+        .file_delivered(ip, Path::new("boot/bootx64.efi"))
+        .unwrap();
+    assert_eq!(client.xid, 1234);
+    assert!(tracker.get(&ip).unwrap().boot_file_delivered);
+    assert!(tracker
+        .file_delivered(Ipv4Addr::new(10, 0, 0, 11), Path::new("boot/bootx64.efi"))
+        .is_none());
+}
+
+#[test]
+fn test_iface_counts() {
+    let tracker = BootTracker::new(10);
+    let ip = Ipv4Addr::new(10, 0, 0, 10);
+    for _ in 0..3 {
+        tracker.dhcp_received("eth1");
+        tracker.dhcp_ignored("eth1");
+    }
+    tracker.dhcp_received("eth0");
+    tracker.dhcp_answered("eth0");
+    tracker.boot_info_sent(ip, "08:00:27:E7:DE:FE", 1234, "/boot/bootx64.efi", "eth0");
+    tracker.file_delivered(ip, Path::new("boot/grub.cfg"));
+    // Not a client of the server
+    tracker.file_delivered(Ipv4Addr::new(10, 0, 0, 11), Path::new("boot/bootx64.efi"));
+
+    assert_eq!(
+        tracker.iface_counts(),
+        vec![
+            IfaceCounts {
+                iface: "eth0".to_string(),
+                received: 1,
+                ignored: 0,
+                answered: 1,
+                transfers: 1,
+                ..Default::default()
+            },
+            IfaceCounts {
+                iface: "eth1".to_string(),
+                received: 3,
+                ignored: 3,
+                answered: 0,
+                transfers: 0,
+                ..Default::default()
+            },
+        ]
+    );
+    assert_eq!(tracker.counts().transfers, 2);
+}
+
+#[test]
+fn test_upstream_missing() {
+    let tracker = BootTracker::new(10);
+    for _ in 1..UPSTREAM_MISSING_AFTER {
+        assert!(!tracker.upstream_timed_out("eth0"));
+    }
+    assert!(tracker.upstream_timed_out("eth0"));
+    // Raised once
+    assert!(!tracker.upstream_timed_out("eth0"));
+    assert!(!tracker.upstream_timed_out("eth1"));
+    let counts = tracker.iface_counts();
+    assert!(counts[0].upstream_missing);
+    assert_eq!(counts[0].unoffered, UPSTREAM_MISSING_AFTER + 1);
+    assert!(!counts[1].upstream_missing);
+
+    assert!(tracker.upstream_offered("eth0"));
+    assert!(!tracker.upstream_offered("eth0"));
+    assert!(!tracker.iface_counts()[0].upstream_missing);
+    assert_eq!(tracker.iface_counts()[0].unoffered, 0);
+}
@@ -0,0 +1,75 @@
+extern crate preboot_oxide;
+
+use dhcproto::v4::{DhcpOption, Message, MessageType};
+use preboot_oxide::{
+    conf::Conf,
+    traffic::{self, Direction},
+    util::LineLog,
+};
+use time::OffsetDateTime;
+
+mod utils;
+
+#[test]
+fn test_traffic_json() {
+    let mut message = Message::default();
+    message.set_xid(0x1234);
+    message.set_chaddr(&[0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+    message
+        .opts_mut()
+        .insert(DhcpOption::MessageType(MessageType::Discover));
+    let time = OffsetDateTime::from_unix_timestamp(1714566600).unwrap();
+    let line = traffic::to_json(
+        Direction::Received,
+        "eth0",
+        "0.0.0.0:68".parse().unwrap(),
+        "255.255.255.255:67".parse().unwrap(),
+        &message,
+        time,
+    );
+    assert_eq!(line["time"], "2024-05-01T12:30:00Z");
+    assert_eq!(line["direction"], "received");
+    assert_eq!(line["iface"], "eth0");
+    assert_eq!(line["src"], "0.0.0.0:68");
+    assert_eq!(line["dst"], "255.255.255.255:67");
+    // The document the match rules see
+    assert_eq!(line["message"], serde_json::to_value(&message).unwrap());
+}
+
+#[test]
+fn test_line_log() {
+    let dir = std::env::temp_dir().join(format!("po-traffic-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("traffic.jsonl");
+
+    let mut log = LineLog::open(&path, 64, 2).unwrap();
+    log.write("{\"n\":1}").unwrap();
+    log.write("{\"n\":2}").unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "{\"n\":1}\n{\"n\":2}\n"
+    );
+    for _ in 0..10 {
+        log.write("{\"n\":3}").unwrap();
+    }
+    assert!(std::fs::metadata(&path).unwrap().len() <= 64);
+    assert!(path.with_extension("jsonl.1").exists());
+    assert!(!path.with_extension("jsonl.2").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_traffic_log_conf() {
+    let yaml = "tftp_server_dir: /tftpdir\ntraffic_log: /var/log/preboot-oxide/traffic.jsonl\ntraffic_log_max_size: 100\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_ok());
+    assert_eq!(conf.get_traffic_log_max_size(), 100 * 1024 * 1024);
+    assert_eq!(conf.get_traffic_log_files(), 5);
+
+    let yaml = "tftp_server_dir: /tftpdir\ntraffic_log: traffic.jsonl\ntraffic_log_files: 0\ndefault:\n    boot_file: /default\n";
+    let yaml_mock = utils::YamlMockFile::from_yaml(yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_err());
+}
@@ -1,19 +1,16 @@
 use std::path::PathBuf;
 use rand::Rng;
 
+#[allow(dead_code)]
 pub struct YamlMockFile {
   pub path: PathBuf,
 }
 
 
+#[allow(dead_code)]
 impl YamlMockFile {
   pub fn from_yaml(yaml: &str) -> Self {
-    let random_string: String = rand::thread_rng()
-        .sample_iter(&rand::distributions::Alphanumeric)
-        .take(15)
-        .map(char::from)
-        .collect();
-    let path = PathBuf::from(format!("/tmp/{random_string}.yaml"));
+    let path = random_tmp_path("yaml");
     std::fs::write(&path, yaml).unwrap();
     Self { path }
   }
@@ -23,4 +20,33 @@ impl Drop for YamlMockFile {
   fn drop(&mut self) {
     std::fs::remove_file(&self.path).unwrap();
   }
-}
\ No newline at end of file
+}
+
+#[allow(dead_code)]
+pub struct MockFile {
+  pub path: PathBuf,
+}
+
+#[allow(dead_code)]
+impl MockFile {
+  pub fn from_bytes(content: &[u8], extension: &str) -> Self {
+    let path = random_tmp_path(extension);
+    std::fs::write(&path, content).unwrap();
+    Self { path }
+  }
+}
+
+impl Drop for MockFile {
+  fn drop(&mut self) {
+    std::fs::remove_file(&self.path).unwrap();
+  }
+}
+
+fn random_tmp_path(extension: &str) -> PathBuf {
+  let random_string: String = rand::thread_rng()
+      .sample_iter(&rand::distributions::Alphanumeric)
+      .take(15)
+      .map(char::from)
+      .collect();
+  PathBuf::from(format!("/tmp/{random_string}.{extension}"))
+}
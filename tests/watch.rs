@@ -0,0 +1,168 @@
+#![cfg(unix)]
+extern crate preboot_oxide;
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    os::unix::net::UnixStream,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use preboot_oxide::{
+    api,
+    conf::Conf,
+    control::{self, Control, ControlledService},
+    dhcp::SessionMap,
+    events::{self, ClientEvent, Event},
+    reload,
+    tracker::BootTracker,
+    watch,
+};
+
+mod utils;
+
+const MAC: &str = "52:54:00:12:34:56";
+
+fn control(conf: &Conf) -> Arc<Control> {
+    let (reloader, _) = reload::reloader();
+    Arc::new(Control {
+        started: SystemTime::now(),
+        origin: "--set".to_string(),
+        tracker: Arc::new(BootTracker::new(10)),
+        services: vec![ControlledService {
+            tenant: None,
+            shared_conf: Arc::new(std::sync::RwLock::new(Arc::new(conf.clone()))),
+            sessions: Some(Arc::new(SessionMap::new(conf))),
+        }],
+        reloader,
+        handover: Default::default(),
+    })
+}
+
+/// Fires the offers to another client then `MAC` until stopped, the
+/// watchers starting at some point.
+fn fire_offers(conf: Conf) -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = Arc::clone(&stop);
+    thread::spawn(move || {
+        while !stopped.load(Ordering::Relaxed) {
+            for mac_address in ["52:54:00:ab:cd:ef", MAC] {
+                let client = ClientEvent {
+                    mac_address: mac_address.to_string(),
+                    xid: 42,
+                    ip: Some(Ipv4Addr::new(10, 0, 0, 5)),
+                    boot_file: Some("ipxe.efi".to_string()),
+                    ..Default::default()
+                };
+                events::fire(&conf, Event::OfferSent, client);
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    });
+    stop
+}
+
+#[test]
+fn test_format_event() {
+    let event = serde_json::json!({
+        "event": "offer_sent",
+        "time": "2024-05-01T12:30:00.123456Z",
+        "mac_address": MAC,
+        "xid": "0x0000002a",
+        "ip": "10.0.0.5",
+        "boot_file": "ipxe.efi",
+        "iface": null,
+    });
+    assert_eq!(
+        watch::format_event(&event, false),
+        "2024-05-01T12:30:00Z  52:54:00:12:34:56  offer_sent           xid=0x0000002a ip=10.0.0.5 boot_file=ipxe.efi"
+    );
+    let colored = watch::format_event(&event, true);
+    assert!(colored.starts_with("\x1b[") && colored.ends_with("\x1b[0m"));
+    assert!(colored.contains(&watch::format_event(&event, false)));
+
+    // The same color for the same client, in bold when it's failing
+    let mut timed_out = event.clone();
+    timed_out["event"] = "session_timed_out".into();
+    let code = colored.split('m').next().unwrap().to_string();
+    assert!(watch::format_event(&timed_out, true).starts_with(&code.replace("\x1b[", "\x1b[1;")));
+}
+
+#[test]
+fn test_watch_socket() {
+    let socket = std::env::temp_dir().join(format!("po-watch-{}.sock", std::process::id()));
+    let yaml = format!(
+        "tftp_server_dir: /tftpdir\ncontrol_socket: {}\n",
+        socket.display()
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    control::spawn_control_service_async(&conf, control(&conf)).unwrap();
+
+    let mut stream = UnixStream::connect(&socket).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+        .write_all(format!("watch {}\n", MAC.to_uppercase()).as_bytes())
+        .unwrap();
+    let stop = fire_offers(conf);
+    let mut lines = BufReader::new(stream).lines();
+    let line = lines
+        .find(|line| !line.as_ref().unwrap().is_empty())
+        .unwrap()
+        .unwrap();
+    stop.store(true, Ordering::Relaxed);
+
+    let event: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(event["event"], "offer_sent");
+    assert_eq!(event["mac_address"], MAC);
+    assert_eq!(event["boot_file"], "ipxe.efi");
+    let _ = std::fs::remove_file(&socket);
+}
+
+#[test]
+fn test_event_stream() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let yaml =
+        format!("tftp_server_dir: /tftpdir\napi_addr: 127.0.0.1:{port}\napi_token: s3cret\n");
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    api::spawn_api_async(&conf, control(&conf)).unwrap();
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let request =
+        format!("GET /api/v1/events?mac={MAC} HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n");
+    stream.write_all(request.as_bytes()).unwrap();
+    let stop = fire_offers(conf);
+    let mut response = Vec::new();
+    let mut buffer = [0; 1024];
+    while !String::from_utf8_lossy(&response).contains("}\n\n") {
+        let read = stream.read(&mut buffer).unwrap();
+        assert!(read > 0);
+        response.extend_from_slice(&buffer[..read]);
+    }
+    stop.store(true, Ordering::Relaxed);
+
+    let response = String::from_utf8(response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"));
+    assert!(head.contains("Content-Type: text/event-stream"));
+    let (name, data) = body.split_once('\n').unwrap();
+    assert_eq!(name, "event: offer_sent");
+    let event: serde_json::Value =
+        serde_json::from_str(data.strip_prefix("data: ").unwrap().trim_end()).unwrap();
+    assert_eq!(event["mac_address"], MAC);
+}
@@ -0,0 +1,95 @@
+extern crate preboot_oxide;
+
+use async_std::task;
+use preboot_oxide::{conf::Conf, template, webhook};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+mod utils;
+
+/// Answers the requests posted to the returned URL with `status` and `body`,
+/// sending the posted bodies to the returned channel.
+fn endpoint(status: &'static str, body: &'static str) -> (String, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/boot", listener.local_addr().unwrap());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut reader = BufReader::new(stream.unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(len) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = len.trim().parse().unwrap();
+                }
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+            let mut posted = vec![0; content_length];
+            reader.read_exact(&mut posted).unwrap();
+            let _ = sender.send(String::from_utf8(posted).unwrap());
+
+            let response = format!(
+                "HTTP/1.1 {status}\r\n\
+                 Content-Type: application/json\r\n\
+                 Content-Length: {}\r\n\
+                 Connection: close\r\n\r\n{body}",
+                body.len()
+            );
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    (url, receiver)
+}
+
+#[test]
+fn test_webhook_lookup() {
+    let (url, posted) = endpoint("200 OK", r#"{"boot_file": "/cmdb", "vars": {"role": "db"}}"#);
+    let doc = template::client_doc(template::parse_mac("52:54:00:00:00:01").as_ref(), None, None);
+
+    let entry = task::block_on(webhook::lookup(&url, Duration::from_secs(2), &doc))
+        .unwrap()
+        .unwrap();
+    assert_eq!(entry.boot_file, Some("/cmdb".to_string()));
+    assert_eq!(entry.vars.get("role").map(String::as_str), Some("db"));
+    let posted: serde_json::Value = serde_json::from_str(&posted.recv().unwrap()).unwrap();
+    assert_eq!(posted, doc);
+
+    let (url, _posted) = endpoint("404 Not Found", "");
+    let entry = task::block_on(webhook::lookup(&url, Duration::from_secs(2), &doc)).unwrap();
+    assert!(entry.is_none());
+
+    let (url, _posted) = endpoint("200 OK", "[]");
+    assert!(task::block_on(webhook::lookup(&url, Duration::from_secs(2), &doc)).is_err());
+}
+
+#[test]
+fn test_webhook_falls_back_to_match() {
+    let (url, _posted) = endpoint("500 Internal Server Error", "");
+    let yaml = format!(
+        r#"
+tftp_server_dir: /tftpdir
+webhook_url: {url}
+webhook_timeout: 500
+default:
+    boot_file: /default
+    "#
+    );
+    let yaml_mock = utils::YamlMockFile::from_yaml(&yaml);
+    let conf = Conf::from_config_file(Some(&yaml_mock.path)).unwrap();
+    assert!(conf.validate().is_ok());
+    assert_eq!(conf.get_webhook_timeout(), Duration::from_millis(500));
+
+    let doc = template::client_doc(None, None, None);
+    assert!(task::block_on(webhook::client_conf(&conf, &doc)).is_none());
+    let fallback = conf.get_from_doc(doc).unwrap().unwrap();
+    assert_eq!(fallback.boot_file, Some(&"/default".to_string()));
+}